@@ -0,0 +1,247 @@
+//! Benchmarks of the import and drawing pipeline, run with `cargo bench`.
+//!
+//! Pass a name to run only some of them, e.g. `cargo bench -- insert`. The synthetic data
+//! comes from `src/test_support.rs`. Tessellation runs on one thread fewer than there are
+//! cores, set `GMC_TESSELLATION_THREADS` to compare thread counts.
+//!
+//! Baseline, median of 10 runs on a single core of an Intel Xeon, optimized build:
+//!
+//! ```text
+//! parse/nodes        100 000 nodes      176.6 ms      566 000 nodes/s
+//! insert/nodes        50 000 rows       147.6 ms      339 000 rows/s
+//! insert/way_nodes    50 000 rows        64.3 ms      778 000 rows/s
+//! tessellate/ways     10 000 ways        27.6 ms    9 577 000 vertices/s
+//! pick/brute_force     1 000 queries      1.90 s           526 queries/s
+//! pick/grid            1 000 queries      3.34 ms      299 500 queries/s
+//! ```
+//!
+//! Update the baseline along with changes that move it, so the difference shows in review.
+
+use std::env;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::SqlitePool;
+
+use google_maps_clone::app::tessellate_ways;
+use google_maps_clone::geo::distance_to_polyline;
+use google_maps_clone::database::{create_tables, insert_node_data, insert_way_data, InsertConfig};
+use google_maps_clone::open_street_map::read_nodes_from_bytes;
+use google_maps_clone::spatial::SpatialIndex;
+use google_maps_clone::style::StyleSheet;
+use google_maps_clone::test_support::{synthetic_nodes, synthetic_osm_xml, synthetic_renderable_ways, synthetic_ways, SyntheticRng, SYNTHETIC_BBOX};
+
+/// How many times every benchmark is timed after an untimed warm-up run.
+const SAMPLES: usize = 10;
+
+const PARSE_NODE_COUNT: usize = 100_000;
+const INSERT_NODE_COUNT: usize = 50_000;
+const NODES_PER_WAY: usize = 10;
+const TESSELLATE_WAY_COUNT: usize = 10_000;
+// The frame the ways are tessellated for, which decides the points of lines too close to draw
+const TESSELLATE_FRAME_PX: (u32, u32) = (1920, 1080);
+const PICK_WAY_COUNT: usize = 10_000;
+const PICK_QUERY_COUNT: usize = 1000;
+// About the distance a click is from the way it picks
+const PICK_RADIUS_M: f64 = 10.0;
+
+/// The timings of one benchmark.
+///
+/// # Fields
+/// * `name` - What was measured, as `group/benchmark`.
+/// * `elements` - How many elements one run processes.
+/// * `unit` - What the elements are, e.g. `nodes`.
+/// * `durations` - How long each run took.
+struct Samples {
+    name: &'static str,
+    elements: usize,
+    unit: &'static str,
+    durations: Vec<Duration>,
+}
+
+impl Samples {
+    fn new(name: &'static str, elements: usize, unit: &'static str) -> Self {
+        Samples { name, elements, unit, durations: Vec::with_capacity(SAMPLES) }
+    }
+
+    fn median(&self) -> Duration {
+        let mut durations = self.durations.clone();
+        durations.sort();
+        durations.get(durations.len() / 2).copied().unwrap_or_default()
+    }
+}
+
+// One line per benchmark, with the throughput of the median run
+impl fmt::Display for Samples {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let median = self.median();
+        let min = self.durations.iter().min().copied().unwrap_or_default();
+        let max = self.durations.iter().max().copied().unwrap_or_default();
+        let throughput = self.elements as f64 / median.as_secs_f64().max(f64::EPSILON);
+
+        write!(
+            f,
+            "{:<28} {:>10.2?} [{:.2?} .. {:.2?}]  {:>12.0} {}/s",
+            self.name, median, min, max, throughput, self.unit,
+        )
+    }
+}
+
+/// Runs the benchmarks on synthetic data and prints the median time and throughput of each.
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    // Cargo passes `--bench` itself, anything else not starting with `--` is a filter
+    let filter = env::args().skip(1).find(|argument| !argument.starts_with("--"));
+    let selected = |name: &str| filter.as_deref().is_none_or(|filter| name.contains(filter));
+
+    if selected("parse/nodes") {
+        println!("{}", bench_parse()?);
+    }
+    if selected("insert/nodes") {
+        println!("{}", bench_insert_nodes().await?);
+    }
+    if selected("insert/way_nodes") {
+        println!("{}", bench_insert_way_nodes().await?);
+    }
+    if selected("tessellate/ways") {
+        println!("{}", bench_tessellate());
+    }
+    for (name, use_index) in [("pick/brute_force", false), ("pick/grid", true)] {
+        if selected(name) {
+            println!("{}", bench_pick(name, use_index));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the nodes of a generated OSM XML file from memory.
+fn bench_parse() -> Result<Samples> {
+    let xml = synthetic_osm_xml(&synthetic_nodes(PARSE_NODE_COUNT));
+    let mut samples = Samples::new("parse/nodes", PARSE_NODE_COUNT, "nodes");
+
+    for sample in 0..=SAMPLES {
+        let start = Instant::now();
+        let outcome = read_nodes_from_bytes(xml.as_bytes()).map_err(|error| anyhow::anyhow!("{}", error))?;
+        let elapsed = start.elapsed();
+        anyhow::ensure!(outcome.items.len() == PARSE_NODE_COUNT, "read {} of {} nodes", outcome.items.len(), PARSE_NODE_COUNT);
+
+        if sample > 0 {
+            samples.durations.push(elapsed);
+        }
+    }
+
+    Ok(samples)
+}
+
+/// An empty in-memory database. An in-memory database belongs to its connection, so the
+/// pool keeps exactly one open.
+async fn memory_pool() -> Result<SqlitePool> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await?;
+    create_tables(&pool).await?;
+    Ok(pool)
+}
+
+/// Inserts nodes and their tags into a fresh in-memory database.
+async fn bench_insert_nodes() -> Result<Samples> {
+    let nodes = synthetic_nodes(INSERT_NODE_COUNT);
+    let mut samples = Samples::new("insert/nodes", INSERT_NODE_COUNT, "rows");
+
+    for sample in 0..=SAMPLES {
+        let pool = memory_pool().await?;
+        let config = InsertConfig::detect(&pool).await?;
+        let nodes = nodes.clone();
+
+        let start = Instant::now();
+        insert_node_data(&pool, nodes, None, &config).await?;
+        if sample > 0 {
+            samples.durations.push(start.elapsed());
+        }
+        pool.close().await;
+    }
+
+    Ok(samples)
+}
+
+/// Inserts ways into a fresh in-memory database holding their nodes, counting their node
+/// references, which make up most of the rows.
+async fn bench_insert_way_nodes() -> Result<Samples> {
+    let nodes = synthetic_nodes(INSERT_NODE_COUNT);
+    let ways = synthetic_ways(&nodes, NODES_PER_WAY);
+    let way_node_count = ways.iter().map(|way| way.node_refs.len()).sum();
+    let mut samples = Samples::new("insert/way_nodes", way_node_count, "rows");
+
+    for sample in 0..=SAMPLES {
+        let pool = memory_pool().await?;
+        let config = InsertConfig::detect(&pool).await?;
+        // The references need their nodes
+        insert_node_data(&pool, nodes.clone(), None, &config).await?;
+        let ways = ways.clone();
+
+        let start = Instant::now();
+        insert_way_data(&pool, ways, None, &config).await?;
+        if sample > 0 {
+            samples.durations.push(start.elapsed());
+        }
+        pool.close().await;
+    }
+
+    Ok(samples)
+}
+
+/// Tessellates a mix of ways covering the whole viewport with the default style sheet.
+fn bench_tessellate() -> Samples {
+    let renderable_ways = synthetic_renderable_ways(TESSELLATE_WAY_COUNT);
+    let style_sheet = StyleSheet::default();
+    let vertex_count = tessellate_ways(&renderable_ways, &style_sheet, &SYNTHETIC_BBOX, TESSELLATE_FRAME_PX);
+    let mut samples = Samples::new("tessellate/ways", vertex_count, "vertices");
+
+    for _ in 0..SAMPLES {
+        let start = Instant::now();
+        tessellate_ways(&renderable_ways, &style_sheet, &SYNTHETIC_BBOX, TESSELLATE_FRAME_PX);
+        samples.durations.push(start.elapsed());
+    }
+
+    samples
+}
+
+/// Picks the way closest to random points, measuring the distance to every way or only to
+/// the candidates of the grid the viewer picks with.
+fn bench_pick(name: &'static str, use_index: bool) -> Samples {
+    let renderable_ways = synthetic_renderable_ways(PICK_WAY_COUNT);
+    let index = SpatialIndex::build(&renderable_ways, &SYNTHETIC_BBOX);
+    let mut rng = SyntheticRng::new(3);
+    let points: Vec<(f64, f64)> = (0..PICK_QUERY_COUNT).map(|_| rng.next_point()).collect();
+    let mut samples = Samples::new(name, PICK_QUERY_COUNT, "queries");
+
+    let closest = |point: (f64, f64), candidates: &mut dyn Iterator<Item = usize>| {
+        candidates
+            .map(|way| (way, distance_to_polyline(point, &renderable_ways[way].coords)))
+            .filter(|&(_, distance_m)| distance_m <= PICK_RADIUS_M)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    };
+
+    for sample in 0..=SAMPLES {
+        let start = Instant::now();
+        for &point in &points {
+            let picked = if use_index {
+                closest(point, &mut index.query_point(point.0, point.1, PICK_RADIUS_M).into_iter())
+            } else {
+                closest(point, &mut (0..renderable_ways.len()))
+            };
+            std::hint::black_box(picked);
+        }
+        if sample > 0 {
+            samples.durations.push(start.elapsed());
+        }
+    }
+
+    samples
+}
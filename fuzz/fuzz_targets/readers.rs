@@ -0,0 +1,17 @@
+//! Feeds the OSM XML and JSON readers arbitrary input, which they must reject without
+//! panicking. Run with `cargo fuzz run readers`, starting from the inputs checked in to
+//! `fuzz/corpus/readers`. An input found to crash a reader belongs there once it is fixed,
+//! where `tests/readers_fuzz.rs` replays it on every `cargo test`.
+
+#![no_main]
+
+use google_maps_clone::open_street_map::{read_nodes_from_bytes, read_osc_from_bytes, read_osm_json, read_relations_from_bytes, read_ways_from_bytes};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    let _ = read_nodes_from_bytes(input);
+    let _ = read_ways_from_bytes(input);
+    let _ = read_relations_from_bytes(input);
+    let _ = read_osc_from_bytes(input);
+    let _ = read_osm_json(input);
+});
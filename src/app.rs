@@ -1,26 +1,107 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
 use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use wgpu::util::DeviceExt;
 use winit::{
+    dpi::PhysicalPosition,
     event::*,
-    event_loop::EventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowBuilder},
-};
-use sqlx::{
-    migrate::MigrateDatabase, Pool, Sqlite, SqlitePool
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy, EventLoopWindowTarget},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+    window::{Window, WindowBuilder, WindowId},
 };
+use sqlx::{Pool, Sqlite};
+use rayon::prelude::*;
+use tracing::{debug, error, info, warn};
 
-use crate::{database::{create_tables, fetch_all_nodes_and_tags, fetch_all_renderable_ways}, fetcher::read_openstreet_map_file, osm_entities::{Node, RenderableWay}, texture, utils::lat_lon_to_screen, DB_URL};
+use crate::events::{event_channel, AppEvent, EventQueue, EventSender, MAX_EVENTS_PER_FRAME};
+use crate::database::{
+    count_nodes_in_bbox, default_marker_label, fetch_all_renderable_ways, fetch_data_extent, fetch_gps_tracks_in_bbox,
+    fetch_land_water_grid, fetch_markers_in_bbox, fetch_node_by_id, fetch_nodes_in_bbox, fetch_relation_by_id,
+    fetch_relation_ways, fetch_saved_viewport, fetch_setting, fetch_source_filename, fetch_way_bboxes_in_viewport,
+    fetch_way_by_id, is_in_memory, reverse_geocode, Marker, WayBbox, DEFAULT_MARKER_COLOR,
+};
+use crate::fetcher::{download_and_import, process_map_file, refresh_land_water_grid, ImportOptions, MAPDATA_DIRECTORY};
+use crate::gpx::GpsTrack;
+use crate::osm_entities::{RenderableWay, SimpleNode};
+use crate::texture;
+use crate::utils::{MapsType, Projection};
+use crate::coastline::{viewport_surface, LandWaterGrid, Surface};
+use crate::geo::{bbox_of_points, clip_polygon_to_bbox, clip_polyline_to_bbox, dash_polyline, distance_to_polyline, fit_bbox_to_window, format_distance, graticule_lines, graticule_step, meters_per_ndc_unit, permalink_to_viewport, polyline_length, round_scale_length, sanitize_ring, simplify_polyline, triangulate_polygon, viewport_to_permalink, zoom_level, BBox, Permalink};
+use crate::style::{building_height_m, parse_hex_color, Style, StyleSheet, METERS_PER_LEVEL, STYLE_SHEET_PATH};
+use crate::history::{NavigationHistory, Viewport};
+use crate::debounce::FetchDebouncer;
+use crate::cancel::{OperationError, OperationKind, Operations};
+use crate::node_dots::{max_node_dots_from_env, node_dot_stride, MAX_NODE_DOTS};
+use crate::metrics;
+use crate::progressive::{WorkQueue, TESSELLATION_BATCH, TESSELLATION_BUDGET};
+use crate::filter::WayFilter;
+use crate::frame_rate::FrameRateMeter;
+use crate::profiler::{FrameProfiler, FRAME_HISTORY};
+use crate::hover::{describe_way, hover_radius_px, tooltip_anchor, HoverState, TOOLTIP_PADDING_PX};
+use crate::inspect::{Inspection, PanelSize, CHAR_WIDTH_PX, LINE_HEIGHT_PX};
+use crate::gpu::{request_device, select_adapter, select_headless_adapter, surface_config, surface_retry_backoff, GpuError, GpuOptions, GpuTimer, SURFACE_RECONFIGURE_ATTEMPTS};
+use crate::keybindings::{Action, KeyBindings, KEY_BINDINGS_SETTING};
+use crate::junctions::{merge_lines_at_junctions, merge_ways_if_enabled, shared_node_ids, WayOrigins};
+use crate::layers::{push_layer_range, visible_index_ranges, LayerRange, LayerVisibility, MapLayer, VerticalLayer, LAYER_VISIBILITY_SETTING};
+use crate::open_street_map::{OverpassConfig, OverpassError};
+use crate::routing::{isochrone_around, route_alternatives_between, snap_to_road, AlternativeOptions, ReachGrid, Route, RoutingProfile, DEFAULT_ISOCHRONE_CELL_M, DEFAULT_SNAP_DISTANCE_M};
+use crate::skeleton::{Skeleton, MAX_PLACEHOLDERS};
+use crate::snapshot::{load_snapshot, SnapshotError, SNAPSHOT_PATH};
+use crate::spatial::SpatialIndex;
+use crate::stats::compute_viewport_stats;
+use crate::status::{StatusLevel, StatusLine};
+use crate::theme::{Palette, Theme, THEME_SETTING};
+use crate::threads::{install_tessellation, spawn_db_task};
+use crate::watch::{MapFileWatcher, WatchMode};
+use crate::tiles::{tile_zoom_for_viewport, tiles_to_prefetch, TileCache, TileId, TilePrefetcher};
+use crate::writer::{DatabaseWrite, DatabaseWriter};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
     position: [f32; 3],
-    tex_coords: [f32; 2],
+    /// The index of the color in the `Palette`, resolved for the active theme in the shader.
+    palette_index: u32,
+    /// Multiplies the color, e.g. to darken the walls of buildings.
+    shade: f32,
 }
 
 impl Vertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Uint32, 2 => Float32];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+/// A corner of an icon, drawn with `icon.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct IconVertex {
+    position: [f32; 3],
+    tex_coords: [f32; 2],
+}
+
+// Implemented by hand, as the derives leave an unused `check` function behind. Both are sound
+// for a `repr(C)` struct of f32 arrays, it has no padding and every bit pattern is valid
+unsafe impl bytemuck::Zeroable for IconVertex {}
+unsafe impl bytemuck::Pod for IconVertex {}
+
+impl IconVertex {
     const ATTRIBS: [wgpu::VertexAttribute; 2] =
         wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2];
 
@@ -35,260 +116,865 @@ impl Vertex {
     }
 }
 
-struct State<'a> {
-    surface: wgpu::Surface<'a>,
+/// Maps the positions of a vertex buffer to clip space, see `map.wgsl` and `icon.wgsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct CameraUniform {
+    offset: [f32; 2],
+    scale: [f32; 2],
+    extrusion: [f32; 2],
+}
+
+// By hand for the same reason as `IconVertex`, it is f32 arrays only as well
+unsafe impl bytemuck::Zeroable for CameraUniform {}
+unsafe impl bytemuck::Pod for CameraUniform {}
+
+impl CameraUniform {
+    /// For positions already given in normalized device coordinates, like the scale bar.
+    const SCREEN: CameraUniform = CameraUniform {
+        offset: [0.0, 0.0],
+        scale: [1.0, 1.0],
+        extrusion: [0.0, 0.0],
+    };
+
+    /// Shows vertices generated with the `vertices` projection through the `camera` projection.
+    /// The origins are subtracted in f64, so the offset stays small and precise.
+    fn new(vertices: &Projection, camera: &Projection) -> Self {
+        CameraUniform {
+            offset: [
+                (camera.origin.0 - vertices.origin.0) as f32,
+                (camera.origin.1 - vertices.origin.1) as f32,
+            ],
+            scale: [camera.scale.0 as f32, camera.scale.1 as f32],
+            extrusion: [0.0, 0.0],
+        }
+    }
+
+    /// Tilts the view, so the heights of extruded buildings show.
+    fn tilted(self) -> Self {
+        CameraUniform {
+            extrusion: [BUILDING_TILT, BUILDING_DEPTH],
+            ..self
+        }
+    }
+}
+
+// How far a height rises on screen, as a fraction of the same distance on the ground, and
+// how much closer to the viewer it comes in the depth buffer. A rise below one keeps the
+// view close to looking straight down, as if tilted by about 30 degrees.
+const BUILDING_TILT: f32 = 0.6;
+const BUILDING_DEPTH: f32 = 0.25;
+
+/// A camera uniform buffer and the bind group exposing it to the shader.
+struct CameraBinding {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl CameraBinding {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, label: &str, uniform: CameraUniform) -> Self {
+        let buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Camera Buffer", label)),
+                contents: bytemuck::cast_slice(&[uniform]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some(&format!("{} Camera Bind Group", label)),
+        });
+
+        CameraBinding { buffer, bind_group }
+    }
+
+    fn write(&self, queue: &wgpu::Queue, uniform: CameraUniform) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+/// The palette uniform buffer and the bind group exposing it to the shader.
+struct PaletteBinding {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl PaletteBinding {
+    fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, colors: &[[f32; 4]]) -> Self {
+        let buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Palette Buffer"),
+                contents: bytemuck::cast_slice(colors),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("Palette Bind Group"),
+        });
+
+        PaletteBinding { buffer, bind_group }
+    }
+
+    fn write(&self, queue: &wgpu::Queue, colors: &[[f32; 4]]) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(colors));
+    }
+}
+
+/// Creates a pipeline drawing triangles with the `vs_main` and `fs_main` of a shader.
+///
+/// ## Arguments
+/// * `vertex_layout` - The layout of the vertices the shader reads.
+/// * `blend` - How fragments are combined with what is drawn already.
+/// * `depth_compare` - When a fragment passes the depth test. Pipelines passing always do not write depth.
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    vertex_layout: wgpu::VertexBufferLayout<'static>,
+    format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
+    depth_compare: wgpu::CompareFunction,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[
+                vertex_layout,
+            ],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        },
+        // Flat geometry all lies at the same depth, so with LessEqual later ways still draw over earlier ones
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: depth_compare != wgpu::CompareFunction::Always,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// The bind group layouts of the shaders. Both shaders read the camera from group 0.
+///
+/// # Fields
+/// * `texture` - Group 1 of `icon.wgsl`, the texture and its sampler.
+/// * `camera` - Group 0, a `CameraUniform`.
+/// * `palette` - Group 1 of `map.wgsl`, the colors of the palette.
+struct BindGroupLayouts {
+    texture: wgpu::BindGroupLayout,
+    camera: wgpu::BindGroupLayout,
+    palette: wgpu::BindGroupLayout,
+}
+
+impl BindGroupLayouts {
+    fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    // This should match the filterable field of the
+                    // corresponding Texture entry above.
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        });
+
+        BindGroupLayouts {
+            texture,
+            camera: uniform_bind_group_layout(device, "camera_bind_group_layout"),
+            palette: uniform_bind_group_layout(device, "palette_bind_group_layout"),
+        }
+    }
+}
+
+/// A layout of a single uniform buffer read by the vertex shader.
+fn uniform_bind_group_layout(device: &wgpu::Device, label: &str) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }
+        ],
+        label: Some(label),
+    })
+}
+
+/// Creates the pipelines of the map, of the overlays and of the translucent overlays,
+/// drawing into textures of `format`.
+///
+/// The map is depth tested, so walls of extruded buildings hide what is behind them.
+/// Overlays are drawn on top of everything, in the order they are drawn in. Translucent
+/// overlays are blended with what is below them by the alpha of their colors.
+fn create_map_pipelines(device: &wgpu::Device, layouts: &BindGroupLayouts, format: wgpu::TextureFormat) -> (wgpu::RenderPipeline, wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let shader = device.create_shader_module(wgpu::include_wgsl!("map.wgsl"));
+
+    let render_pipeline_layout =
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[&layouts.camera, &layouts.palette],
+            push_constant_ranges: &[],
+        });
+
+    let render_pipeline = create_render_pipeline(device, &render_pipeline_layout, &shader, Vertex::desc(), format, wgpu::BlendState::REPLACE, wgpu::CompareFunction::LessEqual, "Render Pipeline");
+    let overlay_pipeline = create_render_pipeline(device, &render_pipeline_layout, &shader, Vertex::desc(), format, wgpu::BlendState::REPLACE, wgpu::CompareFunction::Always, "Overlay Pipeline");
+    let translucent_pipeline = create_render_pipeline(device, &render_pipeline_layout, &shader, Vertex::desc(), format, wgpu::BlendState::ALPHA_BLENDING, wgpu::CompareFunction::Always, "Translucent Overlay Pipeline");
+    (render_pipeline, overlay_pipeline, translucent_pipeline)
+}
+
+/// Creates the pipeline of the icons of points of interest. The icons are drawn over the
+/// map, and blended with it where they are transparent.
+fn create_icon_pipeline(device: &wgpu::Device, layouts: &BindGroupLayouts, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::include_wgsl!("icon.wgsl"));
+
+    let icon_pipeline_layout =
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Icon Pipeline Layout"),
+            bind_group_layouts: &[&layouts.camera, &layouts.texture],
+            push_constant_ranges: &[],
+        });
+
+    create_render_pipeline(device, &icon_pipeline_layout, &shader, IconVertex::desc(), format, wgpu::BlendState::ALPHA_BLENDING, wgpu::CompareFunction::Always, "Icon Pipeline")
+}
+
+/// Loads the icon of points of interest, bound to group 1 of the icon pipeline.
+///
+/// The bind group keeps the texture it binds alive, so the texture itself is not returned.
+fn create_icon_bind_group(device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) -> wgpu::BindGroup {
+    let icon_texture = texture::Texture::from_bytes(device, queue, texture::NODE_TEXTURE, "node.png").unwrap();
+
+    device.create_bind_group(
+        &wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&icon_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&icon_texture.sampler),
+                }
+            ],
+            label: Some("icon_bind_group"),
+        }
+    )
+}
+
+/// Where an import running in the background gets its data, see `State::start_import`.
+enum ImportSource {
+    /// Downloaded from the Overpass API, the box of the viewport.
+    Viewport(BBox),
+    /// A map file, e.g. one the watcher reported.
+    File(PathBuf),
+}
+
+impl fmt::Display for ImportSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportSource::Viewport(_) => write!(f, "the viewport"),
+            ImportSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+struct State {
+    surface: wgpu::Surface<'static>,
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    window: &'a Window,
+    window: Arc<Window>,
+    surface_configured: bool,
     render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
-    diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
-    top_left_corner: (f64, f64),
-    bottom_right_corner: (f64, f64),
+    overlay_pipeline: wgpu::RenderPipeline,
+    translucent_pipeline: wgpu::RenderPipeline,
+    icon_pipeline: wgpu::RenderPipeline,
+    depth_texture: texture::Texture,
+    map_chunks: Vec<ChunkBuffers>,
+    map_chunk_count: usize,
+    pending_map: Option<PendingMap>,
+    layer_visibility: LayerVisibility,
+    icon_bind_group: wgpu::BindGroup,
+    poi_icons: OverlayBuffers,
+    markers: Vec<Marker>,
+    markers_area: Viewport,
+    markers_generation: u64,
+    selected_marker: Option<i64>,
+    marker_overlay: OverlayBuffers,
+    max_node_dots: usize,
+    node_dots: Vec<SimpleNode>,
+    node_dots_generation: u64,
+    node_dot_overlay: OverlayBuffers,
+    isochrone: Option<ReachGrid>,
+    isochrone_overlay: OverlayBuffers,
+    routes: Vec<Route>,
+    active_route: usize,
+    route_overlay: OverlayBuffers,
+    skeleton: Skeleton,
+    skeleton_overlay: OverlayBuffers,
+    vertex_projection: Projection,
+    map_camera: CameraBinding,
+    minimap_map_camera: CameraBinding,
+    screen_camera: CameraBinding,
+    view: BBox,
     renderable_ways : Vec<RenderableWay>,
+    relation_ways: Vec<RenderableWay>,
+    tile_cache: TileCache,
+    prefetcher: TilePrefetcher,
+    camera_center: (f64, f64),
+    style_sheet: StyleSheet,
+    theme: Theme,
+    palette: Palette,
+    palette_binding: PaletteBinding,
+    gps_tracks: Vec<GpsTrack>,
+    show_gps_tracks: bool,
+    show_buildings_3d: bool,
+    cursor_position: Option<PhysicalPosition<f64>>,
+    last_click: Option<(Instant, PhysicalPosition<f64>)>,
+    modifiers: ModifiersState,
+    key_bindings: KeyBindings,
+    history: NavigationHistory,
+    fetch_debouncer: FetchDebouncer,
+    fitted_viewport: Option<Viewport>,
+    continuous_redraw: bool,
+    frame_rate: FrameRateMeter,
+    profiler: FrameProfiler,
+    gpu_timer: Option<GpuTimer>,
+    measuring: bool,
+    measure_points: Vec<(f64, f64)>,
+    measure_overlay: OverlayBuffers,
+    show_graticule: bool,
+    graticule_overlay: OverlayBuffers,
+    scale_bar_overlay: OverlayBuffers,
+    status: StatusLine,
+    operations: Operations,
+    status_overlay: OverlayBuffers,
+    inspection: Option<Inspection>,
+    inspection_generation: u64,
+    inspection_overlay: OverlayBuffers,
+    typing_filter: bool,
+    filter_draft: String,
+    way_filter: Option<WayFilter>,
+    filter_chunks: Vec<ChunkBuffers>,
+    filter_chunk_count: usize,
+    cursor_readout: String,
+    importing: bool,
+    import_options: ImportOptions,
+    watch_mode: Option<WatchMode>,
+    map_file_watcher: Option<MapFileWatcher>,
+    changed_map_file: Option<PathBuf>,
+    events: EventQueue,
+    event_sender: EventSender,
+    writer: DatabaseWriter,
+    data_extent: Option<BBox>,
+    imported_extent: Option<BBox>,
+    outside_data: bool,
+    land_water_grid: Option<LandWaterGrid>,
+    background: Option<Surface>,
+    way_index: SpatialIndex,
+    way_origins: WayOrigins,
+    hover_pending: bool,
+    hover: HoverState<RenderableWay>,
+    hover_overlay: OverlayBuffers,
+    tooltip_overlay: OverlayBuffers,
+    minimap_background: OverlayBuffers,
+    minimap_map: OverlayBuffers,
+    minimap_camera: OverlayBuffers,
     pool: Pool<Sqlite>,
+    surface_failures: u32,
+    surface_retry_at: Option<Instant>,
 }
 
-impl<'a> State<'a> {
-    async fn new(window: &'a Window) -> State<'a> {
-        // We start by making sure there is a database to connect to
-        // Create a database instance with the full connection string.
-        if !Sqlite::database_exists(DB_URL).await.unwrap_or(false) {
-            println!("Creating database {}", DB_URL);
-            Sqlite::create_database(DB_URL).await;
-            println!("Database created successfully");
+impl State {
+    async fn new(window: Arc<Window>, waker: EventLoopProxy<()>, pool: Pool<Sqlite>, import_options: ImportOptions, watch_mode: Option<WatchMode>, goto: Option<Permalink>) -> Result<State, GpuError> {
+        // // Read and process the chosen map file
+        // read_openstreet_map_file(&pool).await;
+
+        // Failures while starting up are shown once the window is open
+        let mut status = StatusLine::default();
+
+        // Beyond the imported data the map is shaded, so it is clear why it is empty there
+        let imported_extent = match fetch_data_extent(&pool).await {
+            Ok(extent) => extent,
+            Err(error) => {
+                error!(%error, "could not fetch the extent of the data");
+                status.post(StatusLevel::Error, format!("Could not fetch the extent of the data: {}", error), Instant::now());
+                None
+            }
+        };
+
+        // The viewport shown when the viewer was last closed, or else all of the imported data
+        let saved_viewport = match fetch_saved_viewport(&pool).await {
+            Ok(viewport) => viewport,
+            Err(error) => {
+                error!(%error, "could not fetch the saved viewport");
+                status.post(StatusLevel::Error, format!("Could not fetch the saved viewport: {}", error), Instant::now());
+                None
+            }
+        };
+        // A permalink to go to takes the place of both
+        let window_size = window.inner_size();
+        let start_viewport = goto.map(|permalink| permalink_to_viewport(&permalink, (window_size.width, window_size.height))).or(saved_viewport);
+        // One beyond a pole is left for the data fitted to the window, and that for the world
+        // should the data reach too close to a pole to be fitted
+        let start_view = start_viewport.and_then(|viewport| viewport.validated()
+            .inspect_err(|error| warn!(%error, "not starting at the viewport"))
+            .ok());
+        let view = start_view
+            .or_else(|| fit_viewport(imported_extent, (window_size.width, window_size.height)).validated().ok())
+            .unwrap_or_else(|| {
+                fit_viewport(None, (window_size.width, window_size.height)).validated().expect("the world fitted to a window is a valid box")
+            });
+        let fitted_viewport = start_view.is_none().then_some(view);
+        info!(%view, aspect = view.aspect(), fitted = fitted_viewport.is_some(), "initial viewport");
+
+        // A snapshot of the ways opens much faster than querying them, the database is the fallback.
+        // The snapshot was taken of the database file, so a database in memory is always queried
+        let renderable_ways = if is_in_memory(&pool).await.unwrap_or(false) {
+            load_renderable_ways(&pool).await
         } else {
-            println!("Database already exists");
+            match load_snapshot(SNAPSHOT_PATH) {
+                Ok(renderable_ways) => {
+                    info!(path = SNAPSHOT_PATH, count = renderable_ways.len(), "loaded renderable ways from the snapshot");
+                    renderable_ways
+                }
+                Err(error) => {
+                    match error {
+                        SnapshotError::Io(ref io_error) if io_error.kind() == std::io::ErrorKind::NotFound => debug!(path = SNAPSHOT_PATH, "no snapshot"),
+                        error => warn!(path = SNAPSHOT_PATH, %error, "ignoring the snapshot"),
+                    }
+                    load_renderable_ways(&pool).await
+                }
+            }
+        };
+
+        if let Some(message) = report_incomplete_ways(&renderable_ways) {
+            status.post(StatusLevel::Info, message, Instant::now());
         }
-        let pool = SqlitePool::connect(DB_URL).await.unwrap();
-        create_tables(&pool).await;
-        println!("Tables created successfully");
+        let (renderable_ways, way_origins) = merge_ways_if_enabled(renderable_ways);
 
-        // // Read and process the chosen map file
-        // read_openstreet_map_file(&pool).await;
+        // Administrative boundaries and multipolygons are assembled from the ways of their relations
+        let relation_ways = match fetch_relation_ways(&pool).await {
+            Ok(relation_ways) => relation_ways,
+            Err(error) => {
+                error!(%error, "could not fetch the boundaries and multipolygons");
+                status.post(StatusLevel::Error, format!("Could not fetch the boundaries and multipolygons: {}", error), Instant::now());
+                Vec::new()
+            }
+        };
+        info!(count = relation_ways.len(), "loaded the ways of relations");
+
+        let style_sheet = StyleSheet::load_or_default(STYLE_SHEET_PATH);
+        let mut palette = build_palette(&style_sheet);
+
+        // Get the imported GPS tracks crossing the viewport
+        let gps_tracks = match fetch_gps_tracks_in_bbox(&pool, &view).await {
+            Ok(gps_tracks) => gps_tracks,
+            Err(error) => {
+                error!(%error, "could not fetch the GPS tracks");
+                status.post(StatusLevel::Error, format!("Could not fetch the GPS tracks: {}", error), Instant::now());
+                Vec::new()
+            }
+        };
+        info!(count = gps_tracks.len(), "loaded GPS tracks in view");
+
+        // Get the markers around the viewport, see `update_markers`
+        let markers_area = view.expand(MARKER_FETCH_MARGIN);
+        let markers = match fetch_markers_in_bbox(&pool, &markers_area).await {
+            Ok(markers) => markers,
+            Err(error) => {
+                error!(%error, "could not fetch the markers");
+                status.post(StatusLevel::Error, format!("Could not fetch the markers: {}", error), Instant::now());
+                Vec::new()
+            }
+        };
+        add_marker_colors(&mut palette, &markers);
+        let show_gps_tracks = true;
+        let show_buildings_3d = false;
+
+        // The layers shown when the viewer was last closed
+        let layer_visibility = match fetch_setting(&pool, LAYER_VISIBILITY_SETTING).await {
+            Ok(Some(value)) => value.parse().unwrap_or_else(|error| {
+                warn!(%error, "ignoring the saved layer visibility");
+                LayerVisibility::default()
+            }),
+            Ok(None) => LayerVisibility::default(),
+            Err(error) => {
+                error!(%error, "could not fetch the layer visibility");
+                status.post(StatusLevel::Error, format!("Could not fetch the layer visibility: {}", error), Instant::now());
+                LayerVisibility::default()
+            }
+        };
 
-        let top_left_corner: (f64, f64) = (55.0407000, 11.3377000);
-        let bottom_right_corner: (f64, f64) = (55.0210000, 11.3794000);
+        // The keys bound differently from the defaults. Bindings that cannot be used are
+        // ignored as a whole, so a typo does not leave a key doing something unexpected
+        let key_bindings = match fetch_setting(&pool, KEY_BINDINGS_SETTING).await {
+            Ok(Some(value)) => KeyBindings::from_json(&value).unwrap_or_else(|error| {
+                warn!(%error, "ignoring the saved key bindings");
+                status.post(StatusLevel::Error, format!("Using the default keys, {}", error), Instant::now());
+                KeyBindings::default()
+            }),
+            Ok(None) => KeyBindings::default(),
+            Err(error) => {
+                error!(%error, "could not fetch the key bindings");
+                status.post(StatusLevel::Error, format!("Could not fetch the key bindings: {}", error), Instant::now());
+                KeyBindings::default()
+            }
+        };
 
-        // Get the renderable ways from the database
-        let renderable_ways = match fetch_all_renderable_ways(&pool).await {
-            Ok(renderable_ways) => renderable_ways,
-            Err(error) => panic!("There was a problem fetching the renderable ways: {:?}", error),
+        // The theme chosen when the viewer was last closed
+        let theme = match fetch_setting(&pool, THEME_SETTING).await {
+            Ok(Some(value)) => value.parse().unwrap_or_else(|error| {
+                warn!(%error, "ignoring the saved theme");
+                Theme::default()
+            }),
+            Ok(None) => Theme::default(),
+            Err(error) => {
+                error!(%error, "could not fetch the theme");
+                status.post(StatusLevel::Error, format!("Could not fetch the theme: {}", error), Instant::now());
+                Theme::default()
+            }
         };
 
-        println!("There are {} renderable_ways", renderable_ways.len());
+        let outside_data = is_outside_data(imported_extent, &view);
+        if outside_data {
+            status.post(StatusLevel::Info, NO_DATA_MESSAGE, Instant::now());
+        }
+
+        // The grid telling land from water behind a viewport without coastlines, built now
+        // for data imported before there was one
+        let land_water_grid = match fetch_land_water_grid(&pool).await {
+            Ok(Some(grid)) => Some(grid),
+            Ok(None) => refresh_land_water_grid(&pool).await,
+            Err(error) => {
+                error!(%error, "could not fetch the land and water grid");
+                None
+            }
+        };
 
         let size = window.inner_size();
-        // The instance is a handle to our GPU
-        // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
+        // The instance is a handle to our GPU. The backends and the kind of GPU can be chosen
+        // through the environment, e.g. when a laptop picks the wrong one of its two GPUs
+        let gpu_options = GpuOptions::from_env();
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
+            backends: gpu_options.backends,
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window).unwrap();
+        let surface = instance.create_surface(window.clone()).map_err(|error| GpuError::Surface(error.to_string()))?;
+        let adapter = select_adapter(&instance, &surface, &gpu_options)?;
+        let (device, queue) = request_device(&adapter).await?;
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::empty(),
-                    // WebGL doesn't support all of wgpu's features, so if
-                    // we're building for the web we'll have to disable some.
-                    required_limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                    memory_hints: Default::default(),
-                },
-                // Some(&std::path::Path::new("trace")), // Trace path
-                None,
-            )
-            .await
-            .unwrap();
-
-        let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            desired_maximum_frame_latency: 2,
-            view_formats: vec![],
-        };
-
-        let building_texture_bytes = include_bytes!("../utils/textures/building.png");
-        let highway_texture_bytes = include_bytes!("../utils/textures/highway.png");
-        let coastline_texture_bytes = include_bytes!("../utils/textures/coastline.png");
-
-        let building_texture = texture::Texture::from_bytes(&device, &queue, building_texture_bytes, "building.png").unwrap();
-        let highway_texture = texture::Texture::from_bytes(&device, &queue, highway_texture_bytes, "highway.png").unwrap();
-        let coastline_texture = texture::Texture::from_bytes(&device, &queue, coastline_texture_bytes, "coastline.png").unwrap();
-
-        let diffuse_bytes = include_bytes!("../utils/textures/node.png");
-        let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
-
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        // Validation errors would otherwise panic, e.g. configuring a surface whose display was just unplugged
+        device.on_uncaptured_error(Box::new(|error| error!(%error, "uncaptured wgpu error")));
+        let gpu_timer = GpuTimer::new(&device, &queue);
 
-        let diffuse_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    }
-                ],
-                label: Some("diffuse_bind_group"),
+        let config = surface_config(&surface, &adapter, size)?;
+        info!(format = ?config.format, present_mode = ?config.present_mode, "configured surface");
+
+        // A window that is not shown yet has no size, its surface is configured on the first resize
+        let surface_configured = size.width > 0 && size.height > 0;
+        if surface_configured {
+            surface.configure(&device, &config);
+        }
+
+        let layouts = BindGroupLayouts::new(&device);
+        let icon_bind_group = create_icon_bind_group(&device, &queue, &layouts.texture);
+        let palette_binding = PaletteBinding::new(&device, &layouts.palette, &palette.resolve(theme));
+
+        // The map vertices are generated relative to the center of the viewport
+        let vertex_projection = Projection::for_viewport(&view);
+        let map_camera = CameraBinding::new(&device, &layouts.camera, "Map", CameraUniform::new(&vertex_projection, &vertex_projection));
+        let screen_camera = CameraBinding::new(&device, &layouts.camera, "Screen", CameraUniform::SCREEN);
+
+        let (render_pipeline, overlay_pipeline, translucent_pipeline) = create_map_pipelines(&device, &layouts, config.format);
+        let icon_pipeline = create_icon_pipeline(&device, &layouts, config.format);
+        let depth_texture = texture::Texture::create_depth_texture(&device, &config, "Depth Texture");
+
+        // Ways are split into tiles, only the tiles covering the viewport are tessellated
+        let mut tile_cache = TileCache::new();
+        let visible_ways = tile_cache.ways_in_viewport(&renderable_ways, &style_sheet, &view);
+        let background = viewport_surface(land_water_grid.as_ref(), &visible_ways, &view);
+
+        // The tiles around the viewport are built in the background, ready for the first pan
+        // Background tasks report to the event loop through events, never by touching the state
+        let (event_sender, events) = event_channel(Some(waker));
+        let mut prefetcher = TilePrefetcher::new(pool.clone(), event_sender.clone());
+        let writer = DatabaseWriter::start(pool.clone(), event_sender.clone());
+        let prefetch = tiles_to_prefetch(&view, (0.0, 0.0)).into_iter()
+            .filter(|id| !tile_cache.contains(id))
+            .collect();
+        prefetcher.request(prefetch, &style_sheet);
+
+        // The map still opens if the map data can not be watched
+        let map_file_watcher = watch_mode.and_then(|_| match MapFileWatcher::start(Path::new(MAPDATA_DIRECTORY), event_sender.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                error!(directory = MAPDATA_DIRECTORY, %error, "could not watch for map files");
+                None
             }
-        );
+        });
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+        let mut chunks = ChunkBuilder::default();
+        generate_data_extent_vertices_and_indices(imported_extent, &palette, &view, &mut chunks);
+        let scene = MapScene {
+            renderable_ways: &visible_ways,
+            relation_ways: &relation_ways,
+            style_sheet: &style_sheet,
+            view,
+            extrude_buildings: show_buildings_3d,
+        };
+        generate_vertices_and_indices_from_renderable_ways(&scene, &palette, line_lod_ndc((size.width, size.height)), &mut chunks);
+        if show_gps_tracks {
+            generate_gps_track_vertices_and_indices(&gps_tracks, &palette, &view, &mut chunks);
+        }
 
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&texture_bind_group_layout],
-                push_constant_ranges: &[],
-            });
+        let mut map_chunks = Vec::new();
+        let map_chunk_count = write_map_chunks(&device, &queue, &mut map_chunks, chunks.finish());
+        let (icon_vertices, icon_indices) = generate_poi_icon_vertices_and_indices(&visible_ways, &view, (size.width, size.height));
+        let poi_icons = OverlayBuffers::new(&device, "POI Icons", &icon_vertices, &icon_indices);
+        let (marker_vertices, marker_indices) = generate_marker_vertices_and_indices(&markers, None, &palette, &view, (size.width, size.height));
+        let marker_overlay = OverlayBuffers::new(&device, "Markers", &marker_vertices, &marker_indices);
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[
-                    Vertex::desc(),
-                ],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
+        // A file of nodes only shows them as dots, see `update_node_dots`
+        let max_node_dots = max_node_dots_from_env();
+        let node_dots = match visible_ways.is_empty() && max_node_dots > 0 {
+            true => match fetch_node_dots(&pool, &view, max_node_dots).await {
+                Ok((node_dots, total)) => {
+                    if !node_dots.is_empty() {
+                        status.post(StatusLevel::Info, node_dots_message(node_dots.len(), total), Instant::now());
+                    }
+                    node_dots
+                }
+                Err(error) => {
+                    error!(%error, "could not fetch the nodes in view");
+                    Vec::new()
+                }
             },
-            multiview: None,
-            cache: None,
-        });
+            false => Vec::new(),
+        };
+        let (node_dot_vertices, node_dot_indices) = generate_node_dot_vertices_and_indices(&node_dots, &palette, &view, (size.width, size.height));
+        let node_dot_overlay = OverlayBuffers::new(&device, "Node Dots", &node_dot_vertices, &node_dot_indices);
 
-        let (vertices, indices) = generate_vertices_and_indices_from_renderable_ways(&renderable_ways, top_left_corner, bottom_right_corner);
+        // The measurement starts out empty, its buffers are filled once points are added
+        let measure_points = Vec::new();
+        let (measure_vertices, measure_indices) = generate_measurement_vertices_and_indices(&measure_points, &palette, &view);
+        let measure_overlay = OverlayBuffers::new(&device, "Measurement", &measure_vertices, &measure_indices);
+        // The graticule is hidden until toggled
+        let graticule_overlay = OverlayBuffers::new::<Vertex>(&device, "Graticule", &[], &[]);
+        let isochrone_overlay = OverlayBuffers::new::<Vertex>(&device, "Isochrone", &[], &[]);
+        let route_overlay = OverlayBuffers::new::<Vertex>(&device, "Routes", &[], &[]);
+        let skeleton_overlay = OverlayBuffers::new::<Vertex>(&device, "Skeleton", &[], &[]);
+        let hover_overlay = OverlayBuffers::new::<Vertex>(&device, "Hover", &[], &[]);
+        let tooltip_overlay = OverlayBuffers::new::<Vertex>(&device, "Tooltip", &[], &[]);
 
-        let vertex_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex buffer"),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
-        );
+        let (scale_bar_vertices, scale_bar_indices, _) = generate_scale_bar_vertices_and_indices(&palette, &view, size);
+        let scale_bar_overlay = OverlayBuffers::new(&device, "Scale Bar", &scale_bar_vertices, &scale_bar_indices);
 
-        let index_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX,
-            }
-        );
+        let (status_vertices, status_indices) = generate_status_bar_vertices_and_indices(&palette, status.current().map(|message| message.level), size);
+        let status_overlay = OverlayBuffers::new(&device, "Status Bar", &status_vertices, &status_indices);
+        window.set_title(&status_title(&status));
+
+        // Nothing is inspected yet, the panel is generated once an element is selected
+        let (inspection_vertices, inspection_indices) = generate_inspection_panel_vertices_and_indices(&palette, None, size);
+        let inspection_overlay = OverlayBuffers::new(&device, "Inspection Panel", &inspection_vertices, &inspection_indices);
+
+        // The loaded ways do not change while running, so the minimap map is only built once
+        let data_extent = data_extent(&renderable_ways);
+        let (minimap_vertices, minimap_indices) = generate_minimap_vertices_and_indices(&renderable_ways, &palette, data_extent);
+        let minimap_map = OverlayBuffers::new(&device, "Minimap", &minimap_vertices, &minimap_indices);
+        let minimap_map_camera = CameraBinding::new(&device, &layouts.camera, "Minimap", minimap_camera_uniform(data_extent));
+        let (background_vertices, background_indices) = generate_minimap_background_vertices_and_indices(&palette);
+        let minimap_background = OverlayBuffers::new(&device, "Minimap Background", &background_vertices, &background_indices);
+        let (camera_vertices, camera_indices) = generate_minimap_camera_vertices_and_indices(&palette, &view, data_extent);
+        let minimap_camera = OverlayBuffers::new(&device, "Minimap Camera", &camera_vertices, &camera_indices);
 
-        let num_indices = indices.len() as u32;
+        // Picking and hovering look up the ways near the cursor in a grid over the loaded ways
+        let way_index = build_way_index(&renderable_ways, data_extent);
 
-        Self {
+        let mut history = NavigationHistory::default();
+        history.push(view);
+
+        Ok(Self {
             surface,
+            instance,
+            adapter,
             device,
             queue,
             config,
             size,
             window,
+            surface_configured,
             render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
-            diffuse_bind_group,
-            diffuse_texture,
+            overlay_pipeline,
+            translucent_pipeline,
+            icon_pipeline,
+            depth_texture,
+            map_chunks,
+            map_chunk_count,
+            pending_map: None,
+            layer_visibility,
+            icon_bind_group,
+            poi_icons,
+            markers,
+            markers_area,
+            markers_generation: 0,
+            selected_marker: None,
+            marker_overlay,
+            max_node_dots,
+            node_dots,
+            node_dots_generation: 0,
+            node_dot_overlay,
+            isochrone: None,
+            isochrone_overlay,
+            routes: Vec::new(),
+            active_route: 0,
+            route_overlay,
+            skeleton: Skeleton::new(),
+            skeleton_overlay,
+            vertex_projection,
+            map_camera,
+            minimap_map_camera,
+            screen_camera,
             renderable_ways,
+            relation_ways,
+            tile_cache,
+            prefetcher,
+            camera_center: view.center(),
+            style_sheet,
+            theme,
+            palette,
+            palette_binding,
+            gps_tracks,
+            show_gps_tracks,
+            show_buildings_3d,
+            cursor_position: None,
+            last_click: None,
+            modifiers: ModifiersState::empty(),
+            key_bindings,
+            history,
+            fetch_debouncer: FetchDebouncer::default(),
+            fitted_viewport,
+            continuous_redraw: false,
+            frame_rate: FrameRateMeter::default(),
+            profiler: FrameProfiler::default(),
+            gpu_timer,
+            measuring: false,
+            measure_points,
+            measure_overlay,
+            show_graticule: false,
+            graticule_overlay,
+            scale_bar_overlay,
+            status,
+            operations: Operations::default(),
+            status_overlay,
+            inspection: None,
+            inspection_generation: 0,
+            inspection_overlay,
+            typing_filter: false,
+            filter_draft: String::new(),
+            way_filter: None,
+            filter_chunks: Vec::new(),
+            filter_chunk_count: 0,
+            cursor_readout: String::new(),
+            importing: false,
+            import_options,
+            watch_mode,
+            map_file_watcher,
+            changed_map_file: None,
+            events,
+            event_sender,
+            writer,
+            data_extent,
+            imported_extent,
+            outside_data,
+            land_water_grid,
+            background,
+            way_index,
+            way_origins,
+            hover_pending: false,
+            hover: HoverState::default(),
+            hover_overlay,
+            tooltip_overlay,
+            minimap_background,
+            minimap_map,
+            minimap_camera,
             pool,
-            top_left_corner,
-            bottom_right_corner,
-        }
+            view,
+            surface_failures: 0,
+            surface_retry_at: None,
+        })
     }
 
     fn window(&self) -> &Window {
@@ -301,208 +987,3484 @@ impl<'a> State<'a> {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "Depth Texture");
+            self.surface_configured = true;
+            self.update_scale_bar();
+            self.update_status_overlay();
+            self.update_inspection_overlay();
+            self.update_hover_overlay();
         }
     }
 
-    #[allow(unused_variables)]
-    fn input(&mut self, event: &WindowEvent) -> bool {
-        false
-    }
+    /// Tries to get a lost or outdated surface back. The surface is reconfigured a few times,
+    /// then created anew for the window, e.g. after the display it was made for was unplugged.
+    /// Every failure doubles the wait before the next frame, up to a limit.
+    fn recover_surface(&mut self) -> Result<(), GpuError> {
+        self.surface_failures += 1;
+        let backoff = surface_retry_backoff(self.surface_failures);
+        self.surface_retry_at = Some(Instant::now() + backoff);
 
-    fn update(&mut self) {
-        // TODO
-    }
+        if self.surface_failures > SURFACE_RECONFIGURE_ATTEMPTS {
+            warn!(failures = self.surface_failures, ?backoff, "reconfiguring did not help, recreating the surface");
+            self.surface = self.instance.create_surface(self.window.clone()).map_err(|error| GpuError::Surface(error.to_string()))?;
+            self.config = surface_config(&self.surface, &self.adapter, self.size)?;
+        } else {
+            warn!(failures = self.surface_failures, ?backoff, "surface lost, reconfiguring");
+        }
 
-    fn update_buffers(&mut self) {
-        // Generate vertices and indices from renderable_ways
-        let (vertices, indices) = generate_vertices_and_indices_from_renderable_ways(&self.renderable_ways, self.top_left_corner, self.bottom_right_corner);
+        self.resize(self.window.inner_size());
+        Ok(())
+    }
 
-        // Update the vertex buffer with the node vertices
-        self.vertex_buffer = self.device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Node Vertex Buffer"),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            // While a filter is typed every key goes to it, so typing a `b` does not hide the buildings
+            WindowEvent::KeyboardInput { event, .. } if self.typing_filter => self.edit_filter(event),
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                false
             }
-        );
+            // Every other key does what it is bound to, see `KeyBindings`
+            WindowEvent::KeyboardInput { .. } => match self.key_action(event) {
+                Some(action) => self.perform(action),
+                None => false,
+            },
+            // Nothing drawn follows the cursor, so moving it only draws a frame to hide the
+            // hovered way. The way is looked up once the pending events are handled, however
+            // often the cursor moved
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some(*position);
+                self.update_cursor_readout();
+                self.hover_pending = true;
+                self.hover_moved()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                let hidden = self.hover.clear();
+                if hidden {
+                    self.update_hover_overlay();
+                }
+                hidden
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if self.measuring => {
+                if let Some(point) = self.cursor_lat_lon() {
+                    self.add_measure_point(point);
+                }
+                true
+            }
+            // Clicking the minimap jumps to the clicked spot, double-clicking the map zooms in
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                if let Some(point) = self.cursor_minimap_lat_lon() {
+                    self.navigate(|state| state.center_on(point));
+                } else if self.is_double_click(Instant::now()) {
+                    self.navigate(|state| state.zoom_at_cursor(DOUBLE_CLICK_ZOOM_LEVELS));
+                }
+                true
+            }
+            // Zooming continuously is recorded in the history once it stops
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.zoom_at_cursor(scroll_zoom_levels(delta));
+                self.history.moved(Instant::now());
+                true
+            }
+            // Pinching on a trackpad
+            WindowEvent::TouchpadMagnify { delta, .. } if *delta > -1.0 => {
+                self.zoom_at_cursor((1.0 + delta).log2());
+                self.history.moved(Instant::now());
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } if self.measuring => {
+                self.clear_measurement();
+                true
+            }
+            // Outside of measure mode, right click tells what is at the cursor and inspects it
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Right,
+                ..
+            } => {
+                if let Some(point) = self.cursor_lat_lon() {
+                    self.start_inspection(point);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
 
-        // Update the index buffer with the node indices
-        self.index_buffer = self.device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Node Index Buffer"),
-                contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX,
+    /// Does what a key is bound to.
+    ///
+    /// ## Returns
+    /// Whether the action applied. An action that does not apply right now, e.g. deleting a
+    /// marker while none is selected, leaves the key to the window.
+    fn perform(&mut self, action: Action) -> bool {
+        match action {
+            Action::ReloadStyle => {
+                info!(path = STYLE_SHEET_PATH, "reloading style sheet");
+                self.style_sheet = StyleSheet::load_or_default(STYLE_SHEET_PATH);
+                self.palette = build_palette(&self.style_sheet);
+                self.write_palette();
+                // The style decides which ways are clipped as areas
+                self.prefetcher.cancel();
+                self.tile_cache.clear();
+                self.update_buffers();
+                self.prefetch_around_viewport();
             }
-        );
+            // Import the map file the watcher reported
+            Action::ImportChangedFile if self.changed_map_file.is_some() => self.import_changed_map_file(),
+            // Drop a marker at the cursor
+            Action::AddMarker => {
+                if let Some(point) = self.cursor_lat_lon() {
+                    self.add_marker(point);
+                }
+            }
+            // Select the next marker in view, starting over after the last
+            Action::SelectNextMarker => self.select_next_marker(),
+            Action::DeleteMarker if self.selected_marker.is_some() => self.delete_selected_marker(),
+            // Show the area reachable from the cursor within `ISOCHRONE_MINUTES`
+            Action::ShowIsochrone => {
+                if let Some(point) = self.cursor_lat_lon() {
+                    self.start_isochrone(point);
+                }
+            }
+            Action::HideIsochrone => {
+                self.isochrone = None;
+                self.update_isochrone_overlay();
+            }
+            // Route from the selected marker to the cursor, with alternatives where there are any
+            Action::ShowRoute if self.selected_marker.is_some() => {
+                let from = self.markers.iter().find(|marker| Some(marker.id) == self.selected_marker).map(|marker| (marker.lat, marker.lon));
+                if let (Some(from), Some(to)) = (from, self.cursor_lat_lon()) {
+                    self.start_routes(from, to);
+                }
+            }
+            Action::HideRoute => {
+                self.routes.clear();
+                self.update_route_overlay();
+            }
+            Action::NextRoute if self.routes.len() > 1 => {
+                self.active_route = (self.active_route + 1) % self.routes.len();
+                self.show_active_route();
+                self.update_route_overlay();
+            }
+            // The lines of latitude and longitude
+            Action::ToggleGraticule => {
+                self.show_graticule = !self.show_graticule;
+                info!(shown = self.show_graticule, "toggled the graticule");
+                self.update_graticule();
+            }
+            Action::ToggleGpsTracks => {
+                self.show_gps_tracks = !self.show_gps_tracks;
+                self.update_buffers();
+            }
+            // Redraw continuously and log the frame rate, to measure how fast the map is drawn
+            Action::ToggleContinuousRedraw => {
+                self.continuous_redraw = !self.continuous_redraw;
+                self.frame_rate.reset();
+                info!(continuous = self.continuous_redraw, "toggled continuous redraw");
+                let text = if self.continuous_redraw { "Redrawing continuously, the frame rate is logged every second" } else { "Redrawing only on changes" };
+                self.post_status(StatusLevel::Info, text.to_string());
+            }
+            // Log where the time of the frames goes, best together with redrawing continuously
+            Action::ToggleProfiler => {
+                let enabled = !self.profiler.is_enabled();
+                self.profiler.set_enabled(enabled);
+                info!(enabled, gpu = self.gpu_timer.is_some(), "toggled the frame profiler");
+                let text = match (enabled, self.gpu_timer.is_some()) {
+                    (true, true) => format!("Profiling, a summary of the CPU and GPU time is logged every {} frames", FRAME_HISTORY),
+                    (true, false) => format!("Profiling the CPU time only, the GPU has no timestamps, a summary is logged every {} frames", FRAME_HISTORY),
+                    (false, _) => "Stopped profiling".to_string(),
+                };
+                self.post_status(StatusLevel::Info, text);
+            }
+            Action::CycleTheme => self.cycle_theme(),
+            // The 2.5D buildings
+            Action::ToggleBuildings3d => {
+                self.show_buildings_3d = !self.show_buildings_3d;
+                info!(enabled = self.show_buildings_3d, "3D buildings");
+                self.update_buffers();
+            }
+            Action::ToggleBuildings => self.toggle_layer(LayerVisibility::BUILDINGS, "buildings"),
+            Action::ToggleHighways => self.toggle_layer(LayerVisibility::HIGHWAYS, "highways"),
+            Action::ToggleWater => self.toggle_layer(LayerVisibility::WATER, "water"),
+            Action::TogglePois => self.toggle_layer(LayerVisibility::POIS, "points of interest"),
+            Action::ToggleLabels => self.toggle_layer(LayerVisibility::LABELS, "labels"),
+            Action::ToggleTransport => self.toggle_layer(LayerVisibility::TRANSPORT, "railways, ferries and aeroways"),
+            Action::ToggleMeasure => {
+                self.measuring = !self.measuring;
+                info!(enabled = self.measuring, "measure mode");
+            }
+            // Type a tag filter, the ways matching it are highlighted and the others dimmed
+            Action::TypeFilter => {
+                self.typing_filter = true;
+                self.show_filter_draft(None);
+            }
+            Action::SharePermalink => {
+                let permalink = viewport_to_permalink(&self.view);
+                info!(%permalink, "permalink of the viewport");
+                self.post_status(StatusLevel::Info, format!("Permalink {}", permalink));
+            }
+            // Download the viewport from Overpass and import it
+            Action::DownloadViewport => self.download_viewport(),
+            // Walk through the viewports navigated to
+            // Holding the key pans continuously, recorded in the history once it stops
+            Action::PanUp | Action::PanDown | Action::PanLeft | Action::PanRight => {
+                let step = match action {
+                    Action::PanUp => (0.0, PAN_STEP),
+                    Action::PanDown => (0.0, -PAN_STEP),
+                    Action::PanLeft => (-PAN_STEP, 0.0),
+                    _ => (PAN_STEP, 0.0),
+                };
+                self.pan_by(step);
+                self.history.moved(Instant::now());
+            }
+            Action::HistoryBack => self.walk_history(false),
+            Action::HistoryForward => self.walk_history(true),
+            // Cancel stops the operations shown in the status line and clears an ongoing
+            // measurement before it is allowed to close the window, then it closes the
+            // inspection panel, deselects the marker and clears the tag filter
+            Action::Cancel if self.operations.shown().next().is_some() => self.cancel_operations(),
+            Action::Cancel if !self.measure_points.is_empty() => self.clear_measurement(),
+            Action::Cancel if self.inspection.is_some() => self.close_inspection(),
+            Action::Cancel if self.selected_marker.is_some() => {
+                self.selected_marker = None;
+                self.update_marker_overlay();
+            }
+            Action::Cancel if self.way_filter.is_some() => self.clear_way_filter(),
+            // Turn the pages of the inspection panel
+            Action::PreviousInspectionPage if self.inspection.is_some() => return self.scroll_inspection(-1),
+            Action::NextInspectionPage if self.inspection.is_some() => return self.scroll_inspection(1),
+            Action::ImportChangedFile | Action::DeleteMarker | Action::ShowRoute | Action::NextRoute | Action::Cancel
+                | Action::PreviousInspectionPage | Action::NextInspectionPage => return false,
+        }
+        true
+    }
 
-        self.num_indices = indices.len() as u32;
+    /// The action of a key event, if it is a bound key being pressed.
+    fn key_action(&self, event: &WindowEvent) -> Option<Action> {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(code),
+                        ..
+                    },
+                ..
+            } => self.key_bindings.action_for(*code, self.modifiers.shift_key()),
+            _ => None,
+        }
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    /// Returns the cursor in normalized device coordinates, or `None` before the cursor has entered the window.
+    fn cursor_ndc(&self) -> Option<(f64, f64)> {
+        let position = self.cursor_position?;
+        if self.size.width == 0 || self.size.height == 0 {
+            return None;
+        }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
+        // Window coordinates grow downwards from the top left, NDC grows upwards from the center
+        let x = position.x / self.size.width as f64 * 2.0 - 1.0;
+        let y = 1.0 - position.y / self.size.height as f64 * 2.0;
+        Some((x, y))
+    }
+
+    /// Returns the `(lat, lon)` under the cursor, or `None` before the cursor has entered the window.
+    fn cursor_lat_lon(&self) -> Option<(f64, f64)> {
+        let (x, y) = self.cursor_ndc()?;
+        Some(Projection::for_viewport(&self.view).ndc_to_lat_lon(x as f32, y as f32))
+    }
+
+    /// Zooms about the cursor, so the spot under it stays in place, or about the center of the
+    /// window if the cursor is outside of it.
+    ///
+    /// ## Arguments
+    /// * `levels` - How many zoom levels to zoom in, negative to zoom out. Stops at
+    ///   `MIN_ZOOM_LEVEL` and `MAX_ZOOM_LEVEL`.
+    fn zoom_at_cursor(&mut self, levels: f64) {
+        let current = zoom_level(&self.view);
+        // Only the direction of the zoom is limited, so a viewport already beyond a limit does not jump
+        let levels = if levels > 0.0 {
+            levels.min((MAX_ZOOM_LEVEL - current).max(0.0))
+        } else {
+            levels.max((MIN_ZOOM_LEVEL - current).min(0.0))
+        };
+        if levels == 0.0 || !levels.is_finite() {
+            return;
+        }
+
+        let anchor = self.cursor_ndc().unwrap_or((0.0, 0.0));
+        let projection = Projection::for_viewport(&self.view).zoomed_about(anchor, levels.exp2());
+        self.show_viewport(projection.viewport());
+    }
+
+    /// Shows a viewport, e.g. one of the history. One reaching beyond a pole, e.g. after
+    /// panning north of the map, is not shown and the camera stays where it is.
+    fn show_viewport(&mut self, viewport: Viewport) {
+        match viewport.validated() {
+            Ok(view) => self.view = view,
+            Err(error) => {
+                debug!(%error, %viewport, "not showing the viewport");
+                return;
+            }
+        }
+        self.camera_moved();
+    }
+
+    /// Redraws what follows the camera after it moved, without fetching the map. The map
+    /// loaded is drawn moved by the camera uniform until `update` fetches the viewport once
+    /// the camera stays still, see `FetchDebouncer`, so panning never waits on the database.
+    fn camera_moved(&mut self) {
+        self.hover_moved();
+        self.update_cursor_readout();
+        self.update_scale_bar();
+        self.update_minimap_camera();
+        self.hover_pending = true;
+        // Far enough beyond the map loaded its edge would come into view, so it is not waited for
+        if self.fetch_debouncer.moved(self.view, Instant::now()) {
+            self.fetch_viewport();
+        }
+    }
+
+    /// Regenerates the map and the overlays for the viewport and prefetches the tiles around it.
+    fn fetch_viewport(&mut self) {
+        self.update_buffers();
+        self.prefetch_around_viewport();
+    }
+
+    /// Moves the camera by a share of the viewport, e.g. `(0.0, 1.0)` a whole screen up.
+    fn pan_by(&mut self, (right, up): (f64, f64)) {
+        let lat = (self.view.max_lat - self.view.min_lat) * up;
+        let lon = (self.view.max_lon - self.view.min_lon) * right;
+        let BBox { min_lat, max_lat, min_lon, max_lon } = self.view;
+        self.show_viewport(BBox { min_lat: min_lat + lat, max_lat: max_lat + lat, min_lon: min_lon + lon, max_lon: max_lon + lon });
+    }
+
+    /// Jumps somewhere else and records the jump in the history, after the viewport it
+    /// started from if that was not recorded yet.
+    fn navigate(&mut self, jump: impl FnOnce(&mut Self)) {
+        self.history.flush(self.view);
+        jump(self);
+        self.history.push(self.view);
+    }
+
+    /// Jumps to the view of a permalink, at the aspect ratio of the window.
+    fn go_to_permalink(&mut self, permalink: &Permalink) {
+        info!(%permalink, "going to a permalink");
+        let viewport = permalink_to_viewport(permalink, (self.size.width, self.size.height));
+        self.navigate(|state| state.show_viewport(viewport));
+        self.post_status(StatusLevel::Info, format!("Went to {}", permalink));
+    }
+
+    /// Shows the previous viewport of the history, or the next one if `forward`. The walk
+    /// itself is not recorded.
+    fn walk_history(&mut self, forward: bool) {
+        let viewport = self.view;
+        let walked_to = if forward { self.history.forward(viewport) } else { self.history.back(viewport) };
+
+        match walked_to {
+            Some(viewport) => self.show_viewport(viewport),
+            None => debug!(forward, "no further viewport in the history"),
+        }
+    }
+
+    /// Remembers a click on the map and tells whether it completes a double-click.
+    fn is_double_click(&mut self, now: Instant) -> bool {
+        let Some(position) = self.cursor_position else {
+            return false;
+        };
+
+        let double_click = self.last_click.is_some_and(|(time, last)| {
+            now.duration_since(time) <= DOUBLE_CLICK_INTERVAL
+                && (position.x - last.x).hypot(position.y - last.y) <= DOUBLE_CLICK_DISTANCE_PX
+        });
+        // A third click starts over instead of zooming again
+        self.last_click = if double_click { None } else { Some((now, position)) };
+        double_click
+    }
+
+    /// Returns the `(lat, lon)` under the cursor within the minimap, or `None` if the cursor is not on the minimap.
+    fn cursor_minimap_lat_lon(&self) -> Option<(f64, f64)> {
+        let position = self.cursor_position?;
+        let extent = self.data_extent?;
+        let (left, top, width, height) = minimap_rect(self.size)?;
+
+        let (px, py) = (position.x as f32, position.y as f32);
+        if !(left..=left + width).contains(&px) || !(top..=top + height).contains(&py) {
+            return None;
+        }
+
+        // Same conversion as for the main viewport, relative to the inset
+        let x = (px - left) / width * 2.0 - 1.0;
+        let y = 1.0 - (py - top) / height * 2.0;
+
+        Some(Projection::for_viewport(&extent).ndc_to_lat_lon(x, y))
+    }
+
+    /// Moves the camera so it is centered on a point, keeping the zoom level.
+    fn center_on(&mut self, (lat, lon): (f64, f64)) {
+        let span = (self.view.max_lat - self.view.min_lat, self.view.max_lon - self.view.min_lon);
+        match BBox::from_center_and_span((lat, lon), span) {
+            Ok(view) => self.show_viewport(view),
+            Err(error) => debug!(%error, lat, lon, "not centering the viewport"),
+        }
+    }
+
+    /// Asks the prefetcher for the tiles around the viewport that are not cached yet.
+    /// Call this once the camera has settled after moving.
+    fn prefetch_around_viewport(&mut self) {
+        let center = self.view.center();
+        let heading = (center.0 - self.camera_center.0, center.1 - self.camera_center.1);
+        self.camera_center = center;
+
+        let tiles: Vec<TileId> = tiles_to_prefetch(&self.view, heading).into_iter()
+            .filter(|id| !self.tile_cache.contains(id))
+            .collect();
+        self.prefetcher.request(tiles, &self.style_sheet);
+    }
+
+    /// Shows or hides a layer and saves the choice for the next run.
+    fn toggle_layer(&mut self, layer: LayerVisibility, name: &str) {
+        self.layer_visibility.toggle(layer);
+        info!(layer = name, shown = self.layer_visibility.contains(layer), "toggled layer");
+
+        let value = self.layer_visibility.to_string();
+        self.writer.write(DatabaseWrite::Setting { key: LAYER_VISIBILITY_SETTING, value, what: "the layer visibility" });
+    }
+
+    /// Saves the viewport shown, so the next run starts there instead of fitting the viewport
+    /// to the data.
+    fn save_viewport(&self) {
+        self.writer.write(DatabaseWrite::Viewport(self.view));
+    }
+
+    /// Switches to the next theme and saves the choice for the next run. The vertices refer
+    /// to their colors in the palette, so only the palette uniform is rewritten.
+    fn cycle_theme(&mut self) {
+        self.theme = self.theme.next();
+        self.write_palette();
+        info!(theme = %self.theme, "switched theme");
+
+        self.writer.write(DatabaseWrite::Setting { key: THEME_SETTING, value: self.theme.as_str().to_string(), what: "the theme" });
+    }
+
+    /// The loaded way closest to a point within a radius.
+    ///
+    /// ## Arguments
+    /// * `radius_px` - How close the way has to be, in pixels, so the radius is the same on
+    ///   screen whatever the zoom.
+    ///
+    /// ## Returns
+    /// * The way, the id of the imported way there, which differs from the id of the way where
+    ///   ways were merged, and its distance in meters, or `None` if no way is close enough.
+    fn way_at(&self, (lat, lon): (f64, f64), radius_px: f64) -> Option<(&RenderableWay, i64, f64)> {
+        let radius_m = radius_px / self.size.width.max(1) as f64 * self.view.width_m();
+
+        self.way_index.query_point(lat, lon, radius_m).into_iter()
+            .filter_map(|index| self.renderable_ways.get(index))
+            .map(|way| (way, distance_to_polyline((lat, lon), &way.coords)))
+            .filter(|&(_, distance_m)| distance_m <= radius_m)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(way, distance_m)| (way, self.way_origins.original_id(way, (lat, lon)), distance_m))
+    }
+
+    /// Looks up the way under the cursor if the cursor moved since the last frame. It is
+    /// shown once the cursor rested on it for `HOVER_DELAY`, see `update`.
+    fn update_hover(&mut self) {
+        if !self.hover_pending {
+            return;
+        }
+        self.hover_pending = false;
+
+        // Lines are thin when zoomed out, so the radius grows to keep them easy to hover
+        let radius_px = hover_radius_px(zoom_level(&self.view));
+        let hovered_way = self.cursor_lat_lon()
+            .and_then(|point| self.way_at(point, radius_px))
+            .map(|(way, id, _)| (id, way.clone()));
+        let way_id = hovered_way.as_ref().map(|&(id, _)| id);
+        if self.hover.set_target(hovered_way) {
+            debug!(?way_id, "hovered way changed");
+        }
+    }
+
+    /// Hides the hovered way, as the cursor or the map under it moved, until the cursor rests again.
+    ///
+    /// ## Returns
+    /// * Whether the hovered way was shown, so the frame has to be redrawn.
+    fn hover_moved(&mut self) -> bool {
+        let hidden = self.hover.moved(Instant::now());
+        if hidden {
+            self.update_hover_overlay();
+        }
+        hidden
+    }
+
+    /// Regenerates the highlight of the hovered way and its tooltip, which follow the viewport
+    /// and the window size.
+    fn update_hover_overlay(&mut self) {
+        let shown = self.hover.shown().map(|(_, way)| way);
+        let (vertices, indices) = generate_hover_vertices_and_indices(shown, &self.palette, &self.view);
+        self.hover_overlay = OverlayBuffers::new(&self.device, "Hover", &vertices, &indices);
+
+        let tooltip = shown.zip(self.cursor_position).map(|(way, cursor)| (describe_way(&way.tags), cursor));
+        let (vertices, indices) = generate_tooltip_vertices_and_indices(&self.palette, tooltip.as_ref().map(|(text, cursor)| (text.as_str(), *cursor)), self.size);
+        self.tooltip_overlay = OverlayBuffers::new(&self.device, "Tooltip", &vertices, &indices);
+    }
+
+    /// Tells what is at a point and shows it in the inspection panel. The way hit is found
+    /// among the loaded ways right here, the rest is looked up on a thread of its own, see
+    /// `print_place_at`, which reports the element with `AppEvent::InspectionLoaded`.
+    fn start_inspection(&mut self, point: (f64, f64)) {
+        let way_hit = self.way_at(point, PICK_RADIUS_PX).map(|(way, way_id, distance_m)| {
+            let name = way.tags.iter().find(|tag| tag.key == "name").map(|tag| tag.value.clone());
+            (way_id, name, distance_m)
+        });
+
+        // Only the element of the last click is shown, the lookups may finish out of order
+        self.inspection_generation += 1;
+        let generation = self.inspection_generation;
+        let task = spawn_db_task(self.pool.clone(), move |pool, events| async move {
+            let element = print_place_at(&pool, point, way_hit, &events).await;
+            let result = fetch_inspection(&pool, element.as_ref()).await;
+            AppEvent::InspectionLoaded { generation, element, result }
+        }, self.event_sender.clone());
+        if let Err(error) = task {
+            self.event_sender.send(AppEvent::InspectionLoaded { generation, element: None, result: Err(sqlx::Error::Io(error)) });
+        }
+    }
+
+    fn close_inspection(&mut self) {
+        if self.inspection.take().is_some() {
+            debug!("inspection panel closed");
+            self.update_inspection_overlay();
+        }
+    }
+
+    /// Turns the pages of the inspection panel.
+    ///
+    /// ## Returns
+    /// * Whether another page is shown, so the panel has to be drawn again.
+    fn scroll_inspection(&mut self, pages: isize) -> bool {
+        let size = inspection_panel_size(self.size);
+        let Some(inspection) = self.inspection.as_mut() else {
+            return false;
+        };
+        if !inspection.scroll(pages, size) {
+            return false;
+        }
+
+        self.update_inspection_overlay();
+        self.log_inspection();
+        true
+    }
+
+    /// Prints the page of the inspection panel shown, which is how its text is read until
+    /// there is text rendering to show it in the panel itself.
+    fn log_inspection(&self) {
+        let Some(inspection) = &self.inspection else {
+            return;
+        };
+
+        let size = inspection_panel_size(self.size);
+        info!(
+            maps_type = inspection.maps_type.as_str(), id = inspection.id,
+            page = inspection.page + 1, pages = inspection.page_count(size),
+            "inspecting\n{}", inspection.visible_lines(size).join("\n"),
+        );
+    }
+
+    /// Regenerates the inspection panel, which depends on the page shown and the window size.
+    fn update_inspection_overlay(&mut self) {
+        // A larger window fits more lines on a page, so the page shown may no longer exist
+        let size = inspection_panel_size(self.size);
+        let page = self.inspection.as_mut().map(|inspection| {
+            let page_count = inspection.page_count(size);
+            inspection.page = inspection.page.min(page_count - 1);
+            (inspection.page, page_count)
+        });
+        let (vertices, indices) = generate_inspection_panel_vertices_and_indices(&self.palette, page, self.size);
+        self.inspection_overlay = OverlayBuffers::new(&self.device, "Inspection Panel", &vertices, &indices);
+    }
+
+    fn add_measure_point(&mut self, point: (f64, f64)) {
+        // Clicking the same spot again would only add a zero length segment
+        if self.measure_points.last() == Some(&point) {
+            return;
+        }
+
+        self.measure_points.push(point);
+        info!(distance = %format_distance(polyline_length(&self.measure_points)), "measured");
+        self.update_measurement_buffers();
+    }
+
+    fn clear_measurement(&mut self) {
+        self.measure_points.clear();
+        info!("measurement cleared");
+        self.update_measurement_buffers();
+    }
+
+    /// Regenerates the measurement overlay. The points are kept as `(lat, lon)`, so this
+    /// also has to run whenever the viewport changes.
+    fn update_measurement_buffers(&mut self) {
+        let (vertices, indices) = generate_measurement_vertices_and_indices(&self.measure_points, &self.palette, &self.view);
+        self.measure_overlay = OverlayBuffers::new(&self.device, "Measurement", &vertices, &indices);
+    }
+
+    /// Fetches the markers again if the viewport left the area they were fetched for, and
+    /// regenerates them.
+    fn update_markers(&mut self) {
+        if !self.markers_area.contains_bbox(&self.view) {
+            self.fetch_markers();
+        }
+        self.update_marker_overlay();
+    }
+
+    /// Fetches the markers around the viewport, a margin beyond it so panning a little
+    /// does not query the database again.
+    ///
+    /// They are fetched on a thread of their own, which reports them with
+    /// `AppEvent::MarkersLoaded`; until then the markers fetched before stay. The area counts
+    /// as fetched right away, so the viewport moving on within it asks for no more fetches.
+    fn fetch_markers(&mut self) {
+        let area = self.view.expand(MARKER_FETCH_MARGIN);
+        self.markers_area = area;
+
+        // A newer fetch, e.g. after a marker was added, makes the markers still being fetched stale
+        self.markers_generation += 1;
+        let generation = self.markers_generation;
+        let task = spawn_db_task(self.pool.clone(), move |pool, _| async move {
+            AppEvent::MarkersLoaded { generation, result: fetch_markers_in_bbox(&pool, &area).await }
+        }, self.event_sender.clone());
+        if let Err(error) = task {
+            self.event_sender.send(AppEvent::MarkersLoaded { generation, result: Err(sqlx::Error::Io(error)) });
+        }
+    }
+
+    fn update_marker_overlay(&mut self) {
+        let (vertices, indices) = generate_marker_vertices_and_indices(&self.markers, self.selected_marker, &self.palette, &self.view, (self.size.width, self.size.height));
+        self.marker_overlay = OverlayBuffers::new(&self.device, "Markers", &vertices, &indices);
+    }
+
+    /// Stores a marker at a point, labeled with its position. It is selected once stored,
+    /// see `AppEvent::MarkerAdded`.
+    fn add_marker(&mut self, (lat, lon): (f64, f64)) {
+        let label = default_marker_label(lat, lon);
+        self.writer.write(DatabaseWrite::AddMarker { lat, lon, label, color: DEFAULT_MARKER_COLOR });
+    }
+
+    /// Selects the marker in view after the selected one, in the order they were added.
+    fn select_next_marker(&mut self) {
+        let in_view: Vec<&Marker> = self.markers.iter()
+            .filter(|marker| self.view.contains(marker.lat, marker.lon))
+            .collect();
+        let next = match self.selected_marker {
+            Some(selected) => in_view.iter().find(|marker| marker.id > selected).or(in_view.first()),
+            None => in_view.first(),
+        };
+
+        let Some(next) = next else {
+            self.post_status(StatusLevel::Info, "No markers in view".to_string());
+            return;
+        };
+        let text = format!("Selected marker {}, Delete removes it, N finds routes from it to the cursor", next.label);
+        self.selected_marker = Some(next.id);
+        self.post_status(StatusLevel::Info, text);
+        self.update_marker_overlay();
+    }
+
+    fn delete_selected_marker(&mut self) {
+        let Some(id) = self.selected_marker.take() else {
+            return;
+        };
+        self.writer.write(DatabaseWrite::DeleteMarker(id));
+        self.update_marker_overlay();
+    }
+
+    fn update_isochrone_overlay(&mut self) {
+        let (vertices, indices) = match &self.isochrone {
+            Some(grid) => generate_isochrone_vertices_and_indices(grid, &self.palette, &self.view),
+            None => (Vec::new(), Vec::new()),
+        };
+        self.isochrone_overlay = OverlayBuffers::new(&self.device, "Isochrone", &vertices, &indices);
+    }
+
+    fn update_route_overlay(&mut self) {
+        let (vertices, indices) = generate_route_vertices_and_indices(&self.routes, self.active_route, &self.palette, &self.view);
+        self.route_overlay = OverlayBuffers::new(&self.device, "Routes", &vertices, &indices);
+    }
+
+    /// Tells the length and travel time of the active route, and logs its instructions as
+    /// there is no text rendering to list them.
+    fn show_active_route(&mut self) {
+        let Some(route) = self.routes.get(self.active_route) else {
+            return;
+        };
+
+        let instructions: Vec<String> = route.instructions.iter().map(ToString::to_string).collect();
+        info!(route = self.active_route + 1, routes = self.routes.len(), "route\n{}{}", route.summary, instructions.join("\n"));
+        let mut text = format!(
+            "Route {} of {}: {} in {:.0} min",
+            self.active_route + 1, self.routes.len(), format_distance(route.summary.distance_m), route.summary.time_s / 60.0,
+        );
+        if self.routes.len() > 1 {
+            text.push_str(", Tab shows the next");
+        }
+        self.post_status(StatusLevel::Info, text);
+    }
+
+    fn update_skeleton_overlay(&mut self) {
+        let (vertices, indices) = generate_skeleton_vertices_and_indices(self.skeleton.placeholders(), &self.palette, &self.view);
+        self.skeleton_overlay = OverlayBuffers::new(&self.device, "Skeleton", &vertices, &indices);
+    }
+
+    /// Replaces the placeholders of the ways now drawn, see `Skeleton`.
+    fn replace_placeholders(&mut self, drawn_way_ids: impl IntoIterator<Item = i64>) {
+        let replaced = self.skeleton.replace(drawn_way_ids);
+        if replaced > 0 {
+            debug!(replaced, left = self.skeleton.len(), "replaced placeholders with their ways");
+            self.update_skeleton_overlay();
+        }
+    }
+
+    /// Regenerates the graticule for the viewport, or empties it while it is hidden.
+    fn update_graticule(&mut self) {
+        let (vertices, indices) = if self.show_graticule {
+            generate_graticule_vertices_and_indices(&self.palette, &self.view)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        self.graticule_overlay = OverlayBuffers::new(&self.device, "Graticule", &vertices, &indices);
+    }
+
+    /// Regenerates the scale bar, which depends on both the viewport and the window size.
+    fn update_scale_bar(&mut self) {
+        let (vertices, indices, length_m) = generate_scale_bar_vertices_and_indices(&self.palette, &self.view, self.size);
+        self.scale_bar_overlay = OverlayBuffers::new(&self.device, "Scale Bar", &vertices, &indices);
+        debug!(length = %format_distance(length_m), "scale bar");
+    }
+
+    /// Writes the colors of the theme into the palette uniform. While a filter highlights
+    /// ways the map is dimmed, so the highlight stands out.
+    fn write_palette(&mut self) {
+        let colors = match self.way_filter {
+            Some(_) => {
+                let background = self.theme.clear_color();
+                self.palette.resolve_dimmed(self.theme, [background.r as f32, background.g as f32, background.b as f32, 1.0], FILTER_DIM_AMOUNT)
+            }
+            None => self.palette.resolve(self.theme),
+        };
+        self.palette_binding.write(&self.queue, &colors);
+    }
+
+    /// Shows the filter being typed in the status line, with why it could not be applied.
+    fn show_filter_draft(&mut self, error: Option<String>) {
+        let text = match error {
+            Some(error) => format!("Filter: {}_ ({}, Escape cancels)", self.filter_draft, error),
+            None => format!("Filter: {}_ (Enter applies, Escape cancels)", self.filter_draft),
+        };
+        self.post_status(StatusLevel::Info, text);
+    }
+
+    /// Edits the filter being typed. Enter applies it, or clears the filter if nothing was
+    /// typed, or jumps to it if it is a permalink, and Escape stops typing. A filter that cannot be parsed stays open to be fixed,
+    /// and the text typed is kept for the next time.
+    ///
+    /// ## Returns
+    /// * Whether anything shown changed.
+    fn edit_filter(&mut self, event: &KeyEvent) -> bool {
+        if event.state != ElementState::Pressed {
+            return false;
+        }
+
+        match event.physical_key {
+            PhysicalKey::Code(KeyCode::Escape) => {
+                self.typing_filter = false;
+                if self.status.clear(StatusLevel::Info) {
+                    self.update_status_overlay();
+                }
+                return true;
+            }
+            PhysicalKey::Code(KeyCode::Enter | KeyCode::NumpadEnter) => {
+                if self.filter_draft.trim().is_empty() {
+                    self.typing_filter = false;
+                    self.clear_way_filter();
+                    return true;
+                }
+                // A filter never has `map=` in it, so the draft is a permalink to jump to
+                if self.filter_draft.contains("map=") {
+                    match self.filter_draft.parse::<Permalink>() {
+                        Ok(permalink) => {
+                            self.typing_filter = false;
+                            self.go_to_permalink(&permalink);
+                        }
+                        Err(error) => {
+                            debug!(permalink = %self.filter_draft, %error, "invalid permalink");
+                            self.show_filter_draft(Some(error.to_string()));
+                        }
+                    }
+                    return true;
+                }
+                match self.filter_draft.parse::<WayFilter>() {
+                    Ok(filter) => {
+                        self.typing_filter = false;
+                        self.apply_way_filter(filter);
+                    }
+                    Err(error) => {
+                        debug!(filter = %self.filter_draft, %error, "invalid filter");
+                        self.show_filter_draft(Some(error.to_string()));
+                    }
+                }
+                return true;
+            }
+            PhysicalKey::Code(KeyCode::Backspace) => {
+                self.filter_draft.pop();
+            }
+            _ => match &event.text {
+                Some(text) => self.filter_draft.extend(text.chars().filter(|c| !c.is_control())),
+                None => return false,
+            },
+        }
+
+        self.show_filter_draft(None);
+        true
+    }
+
+    /// Highlights the ways matching a filter and dims the others, and shows how many of the
+    /// loaded ways match in the status line.
+    fn apply_way_filter(&mut self, filter: WayFilter) {
+        let matches = self.renderable_ways.iter().filter(|way| filter.matches(&way.tags)).count();
+        info!(%filter, matches, "applied a way filter");
+        self.post_status(StatusLevel::Info, format!("{} of {} ways match {}", matches, self.renderable_ways.len(), filter));
+
+        self.way_filter = Some(filter);
+        self.write_palette();
+        let visible_ways = self.tile_cache.ways_in_viewport(&self.renderable_ways, &self.style_sheet, &self.view);
+        self.update_filter_highlight(&visible_ways);
+    }
+
+    fn clear_way_filter(&mut self) {
+        if self.way_filter.take().is_some() {
+            info!("cleared the way filter");
+            self.post_status(StatusLevel::Info, "Cleared the filter".to_string());
+        }
+        self.filter_chunk_count = 0;
+        self.write_palette();
+    }
+
+    /// Tessellates the ways in view matching the filter once more, in the highlight color,
+    /// to be drawn over the dimmed map. The map itself is left as it is.
+    fn update_filter_highlight(&mut self, visible_ways: &[RenderableWay]) {
+        let Some(filter) = &self.way_filter else {
+            self.filter_chunk_count = 0;
+            return;
+        };
+
+        let matching_ways: Vec<RenderableWay> = visible_ways.iter().filter(|way| filter.matches(&way.tags)).cloned().collect();
+        let matching_relation_ways: Vec<RenderableWay> = self.relation_ways.iter().filter(|way| filter.matches(&way.tags)).cloned().collect();
+        let mut chunks = ChunkBuilder::default();
+        generate_vertices_and_indices_from_renderable_ways(&self.map_scene(&matching_ways, &matching_relation_ways), &self.palette, line_lod_ndc((self.size.width, self.size.height)), &mut chunks);
+
+        let mut chunks = chunks.finish();
+        let highlight = overlay_color(&self.palette, FILTER_MATCH_COLOR);
+        for vertex in chunks.iter_mut().flat_map(|chunk| chunk.vertices.iter_mut()) {
+            vertex.palette_index = highlight;
+        }
+        self.filter_chunk_count = write_map_chunks(&self.device, &self.queue, &mut self.filter_chunks, chunks);
+    }
+
+    /// Shows a message in the status line, unless a more severe one is shown.
+    fn post_status(&mut self, level: StatusLevel, text: String) {
+        if self.status.post(level, text, Instant::now()) {
+            self.update_status_overlay();
+        }
+    }
+
+    /// Regenerates the status bar, whose color shows the level of the message, and shows
+    /// the message in the title of the window.
+    fn update_status_overlay(&mut self) {
+        let (vertices, indices) = generate_status_bar_vertices_and_indices(&self.palette, self.status.current().map(|message| message.level), self.size);
+        self.status_overlay = OverlayBuffers::new(&self.device, "Status Bar", &vertices, &indices);
+        self.window.set_title(&status_title(&self.status));
+    }
+
+    /// Prints the coordinates under the cursor whenever they change at the printed precision.
+    fn update_cursor_readout(&mut self) {
+        let Some((lat, lon)) = self.cursor_lat_lon() else {
+            return;
+        };
+
+        let readout = format!("{:.5}, {:.5}", lat, lon);
+        if readout != self.cursor_readout {
+            debug!(position = %readout, "cursor");
+            self.cursor_readout = readout;
+        }
+    }
+
+    /// Downloads and imports the data within the viewport, see `start_import`.
+    fn download_viewport(&mut self) {
+        let view = self.view;
+        let config = OverpassConfig::from_env();
+        let area_deg2 = (view.max_lat - view.min_lat) * (view.max_lon - view.min_lon);
+        if area_deg2 > config.max_area_deg2 {
+            let error = OverpassError::AreaTooLarge { area_deg2, max_area_deg2: config.max_area_deg2 };
+            warn!("{}", error);
+            self.post_status(StatusLevel::Error, error.to_string());
+            return;
+        }
+
+        self.start_import(ImportSource::Viewport(view));
+    }
+
+    /// Stops watching for map files, waiting for the watcher to finish a scan it is in.
+    fn stop_watching(&mut self) {
+        if self.map_file_watcher.take().is_some() {
+            info!("stopped watching for map files");
+        }
+    }
+
+    /// Imports the map file that changed last, see `MapFileWatcher`.
+    fn import_changed_map_file(&mut self) {
+        if let Some(path) = self.changed_map_file.take() {
+            self.start_import(ImportSource::File(path));
+        }
+    }
+
+    /// Imports data on a separate thread, which sends the reloaded ways to the event loop
+    /// once it is done. Only one import runs at a time.
+    fn start_import(&mut self, source: ImportSource) {
+        let what = source.to_string();
+        if self.importing {
+            warn!(what, "an import is already running");
+            self.post_status(StatusLevel::Error, format!("Could not import {}, an import is already running", what));
+            return;
+        }
+
+        let options = self.import_options.clone();
+        let view = self.view;
+        let task_what = what.clone();
+        let task = spawn_db_task(self.pool.clone(), move |pool, events| async move {
+            let what = task_what;
+            let result = match &source {
+                ImportSource::Viewport(bbox) => download_and_import(&pool, bbox, &options).await,
+                ImportSource::File(path) => process_map_file(&pool, &path.to_string_lossy(), &options).await,
+            };
+            let stats = match result {
+                Ok(stats) => stats,
+                Err(error) => return AppEvent::ImportFinished { what, result: Err(error.to_string()) },
+            };
+            events.send(AppEvent::ImportFinished { what, result: Ok(stats) });
+
+            match fetch_land_water_grid(&pool).await {
+                Ok(grid) => {
+                    events.send(AppEvent::LandWaterLoaded(grid));
+                }
+                Err(error) => warn!(%error, "could not fetch the land and water grid"),
+            }
+
+            match fetch_data_extent(&pool).await {
+                Ok(extent) => events.send(AppEvent::DataExtentLoaded(extent)),
+                Err(error) => events.send(AppEvent::Status(StatusLevel::Error, format!("Could not fetch the extent of the data: {}", error))),
+            };
+
+            // The boxes of the ways are fetched first, so they show while the ways are loaded
+            match fetch_way_bboxes_in_viewport(&pool, &view, MAX_PLACEHOLDERS).await {
+                Ok(bboxes) => {
+                    events.send(AppEvent::SkeletonLoaded(bboxes));
+                }
+                // Only the placeholders are missing, the ways are still loaded
+                Err(error) => warn!(%error, "could not fetch the bounding boxes of the ways"),
+            }
+
+            match fetch_all_renderable_ways(&pool).await {
+                Ok(renderable_ways) => events.send(AppEvent::WaysLoaded(renderable_ways)),
+                Err(error) => events.send(AppEvent::Status(StatusLevel::Error, format!("Could not reload the ways: {}", error))),
+            };
+
+            match fetch_relation_ways(&pool).await {
+                Ok(relation_ways) => AppEvent::RelationWaysLoaded(relation_ways),
+                Err(error) => AppEvent::Status(StatusLevel::Error, format!("Could not reload the boundaries and multipolygons: {}", error)),
+            }
+        }, self.event_sender.clone());
+        if let Err(error) = task {
+            self.event_sender.send(AppEvent::ImportFinished { what: what.clone(), result: Err(error.to_string()) });
+        }
+
+        info!(what, "importing");
+        self.importing = true;
+        self.post_status(StatusLevel::Busy, format!("Importing {}", what));
+    }
+
+    /// Applies what a background task sent to the event loop.
+    fn handle_event(&mut self, event: AppEvent) {
+        match event {
+            AppEvent::WaysLoaded(renderable_ways) => {
+                info!(count = renderable_ways.len(), "reloaded renderable ways");
+                report_incomplete_ways(&renderable_ways);
+                (self.renderable_ways, self.way_origins) = merge_ways_if_enabled(renderable_ways);
+                // Ways missing from the reloaded ones, e.g. merged into others, are never drawn
+                let known_way_ids: HashSet<i64> = self.renderable_ways.iter().map(|way| way.id).collect();
+                let removed = self.skeleton.remove_unknown(&known_way_ids);
+                if removed > 0 {
+                    debug!(removed, "removed placeholders of ways not loaded");
+                }
+                self.prefetcher.cancel();
+                self.tile_cache.clear();
+
+                self.data_extent = data_extent(&self.renderable_ways);
+                self.way_index = build_way_index(&self.renderable_ways, self.data_extent);
+                self.hover.clear();
+                self.update_hover_overlay();
+                let (vertices, indices) = generate_minimap_vertices_and_indices(&self.renderable_ways, &self.palette, self.data_extent);
+                self.minimap_map = OverlayBuffers::new(&self.device, "Minimap", &vertices, &indices);
+                self.minimap_map_camera.write(&self.queue, minimap_camera_uniform(self.data_extent));
+
+                self.update_buffers_progressively();
+                self.prefetch_around_viewport();
+            }
+            // The ways drawn already need no placeholder, the overlay is drawn over the map as it is
+            AppEvent::SkeletonLoaded(bboxes) => {
+                let drawn_way_ids: HashSet<i64> = self.renderable_ways.iter().map(|way| way.id).collect();
+                let added = self.skeleton.add(bboxes, |way_id| drawn_way_ids.contains(&way_id));
+                debug!(added, "added placeholders for the ways being loaded");
+                if added > 0 {
+                    self.update_skeleton_overlay();
+                }
+            }
+            AppEvent::RelationWaysLoaded(relation_ways) => {
+                info!(count = relation_ways.len(), "reloaded the ways of relations");
+                self.relation_ways = relation_ways;
+                self.update_buffers_progressively();
+            }
+            // Tiles of a cancelled request are dropped, a tile coming into view is drawn right away
+            AppEvent::TileReady { generation, tile } => {
+                if !self.prefetcher.is_current(generation) {
+                    return;
+                }
+
+                let zoom = tile_zoom_for_viewport(&self.view);
+                let needs_redraw = !self.tile_cache.contains(&tile.id)
+                    && TileId::covering(&self.view, zoom).contains(&tile.id);
+                self.tile_cache.insert(tile);
+                if needs_redraw {
+                    self.update_buffers_progressively();
+                }
+            }
+            // The viewport may have moved on, or its ways come in, while the nodes were fetched
+            AppEvent::NodeDotsLoaded { generation, result } => {
+                if generation != self.node_dots_generation {
+                    debug!(generation, "dropped the nodes of a viewport no longer shown");
+                    return;
+                }
+
+                match result {
+                    Ok((node_dots, total)) => {
+                        if !node_dots.is_empty() && self.node_dots.is_empty() {
+                            self.post_status(StatusLevel::Info, node_dots_message(node_dots.len(), total));
+                        }
+                        self.node_dots = node_dots;
+                    }
+                    Err(error) => {
+                        error!(%error, "could not fetch the nodes in view");
+                        self.post_status(StatusLevel::Error, format!("Could not fetch the nodes in view: {}", error));
+                        self.node_dots.clear();
+                    }
+                }
+                self.update_node_dot_overlay();
+            }
+            AppEvent::MarkersLoaded { generation, result } => {
+                if generation != self.markers_generation {
+                    debug!(generation, "dropped the markers of an outdated fetch");
+                    return;
+                }
+
+                match result {
+                    Ok(markers) => {
+                        self.markers = markers;
+                        if add_marker_colors(&mut self.palette, &self.markers) {
+                            self.write_palette();
+                        }
+                    }
+                    Err(error) => {
+                        error!(%error, "could not fetch the markers");
+                        self.post_status(StatusLevel::Error, format!("Could not fetch the markers: {}", error));
+                        // Fetched again once the viewport moves
+                        self.markers_area = Viewport::default();
+                    }
+                }
+                self.update_marker_overlay();
+            }
+            AppEvent::MarkerAdded { lat, lon, label, result } => {
+                match result {
+                    Ok(id) => {
+                        info!(id, lat, lon, "added a marker");
+                        self.selected_marker = Some(id);
+                        self.post_status(StatusLevel::Info, format!("Added marker {}", label));
+                    }
+                    Err(error) => {
+                        error!(%error, "could not add the marker");
+                        self.post_status(StatusLevel::Error, format!("Could not add the marker: {}", error));
+                    }
+                }
+                self.fetch_markers();
+            }
+            AppEvent::MarkerDeleted { id, result } => {
+                match result {
+                    Ok(true) => {
+                        info!(id, "deleted a marker");
+                        self.post_status(StatusLevel::Info, "Deleted the marker".to_string());
+                    }
+                    Ok(false) => debug!(id, "the marker was deleted already"),
+                    Err(error) => {
+                        error!(%error, "could not delete the marker");
+                        self.post_status(StatusLevel::Error, format!("Could not delete the marker: {}", error));
+                    }
+                }
+                self.fetch_markers();
+            }
+            // Only the element of the last click is shown
+            AppEvent::InspectionLoaded { generation, element, result } => {
+                if generation != self.inspection_generation {
+                    debug!(generation, "dropped the element of an earlier click");
+                    return;
+                }
+
+                match (result, element) {
+                    (Ok(Some(inspection)), _) => {
+                        self.inspection = Some(inspection);
+                        self.update_inspection_overlay();
+                        self.log_inspection();
+                    }
+                    (Ok(None), element) => {
+                        if let Some((maps_type, id)) = element {
+                            debug!(maps_type = maps_type.as_str(), id, "the element at the cursor is not stored");
+                        }
+                        self.close_inspection();
+                    }
+                    (Err(error), Some((maps_type, id))) => {
+                        error!(maps_type = maps_type.as_str(), id, %error, "could not fetch the element to inspect");
+                        self.post_status(StatusLevel::Error, format!("Could not fetch {} {}: {}", maps_type.as_str(), id, error));
+                    }
+                    (Err(error), None) => {
+                        error!(%error, "could not look up what is at the cursor");
+                        self.post_status(StatusLevel::Error, format!("Could not look up what is at the cursor: {}", error));
+                    }
+                }
+            }
+            // The ways are loaded after, which draws the background anew
+            AppEvent::LandWaterLoaded(grid) => self.land_water_grid = grid,
+            AppEvent::DataExtentLoaded(extent) => {
+                self.imported_extent = extent;
+
+                // Until the map is moved, the viewport fitted to the data follows what is imported
+                if self.fitted_viewport == Some(self.view) {
+                    let viewport = fit_viewport(extent, (self.size.width, self.size.height));
+                    info!(?viewport, "fitted the viewport to the imported data");
+                    self.fitted_viewport = Some(viewport);
+                    self.navigate(|state| state.show_viewport(viewport));
+                } else {
+                    self.update_buffers_progressively();
+                }
+            }
+            AppEvent::Status(level, text) => self.post_status(level, text),
+            AppEvent::ImportFinished { what, result } => {
+                self.importing = false;
+                if self.status.clear(StatusLevel::Busy) {
+                    self.update_status_overlay();
+                }
+
+                match result {
+                    Ok(stats) => {
+                        info!(%stats, what, "imported");
+                        self.post_status(StatusLevel::Info, format!("Imported {}, {}", what, stats));
+                    }
+                    Err(error) => {
+                        error!(%error, what, "could not import");
+                        self.post_status(StatusLevel::Error, format!("Could not import {}: {}", what, error));
+                    }
+                }
+            }
+            // The camera may have moved on while the statistics were computed
+            AppEvent::ViewportStatsReady { operation, viewport, stats } => {
+                if !self.operations.finish(OperationKind::ViewportStats, operation) {
+                    debug!(?viewport, "dropped the statistics of a cancelled computation");
+                } else if viewport == self.view {
+                    info!(%stats, "viewport statistics");
+                } else {
+                    debug!(?viewport, "dropped the statistics of a viewport no longer shown");
+                }
+            }
+            AppEvent::IsochroneReady { operation, result } => {
+                if !self.operations.finish(OperationKind::Isochrone, operation) {
+                    debug!(operation, "dropped the reachable area of a cancelled computation");
+                    return;
+                }
+                self.update_operations_status();
+
+                match result {
+                    Ok(Some(grid)) => {
+                        self.isochrone = Some(grid);
+                        self.post_status(StatusLevel::Info, format!("Showing the area reachable by car within {} min, Shift+O hides it", ISOCHRONE_MINUTES));
+                    }
+                    Ok(None) => self.post_status(StatusLevel::Info, format!("No road within {} m to start from", DEFAULT_SNAP_DISTANCE_M)),
+                    Err(OperationError::Cancelled) => debug!(operation, "the reachable area was cancelled"),
+                    Err(error) => {
+                        error!(%error, "could not compute the reachable area");
+                        self.post_status(StatusLevel::Error, format!("Could not compute the reachable area: {}", error));
+                    }
+                }
+                self.update_isochrone_overlay();
+            }
+            AppEvent::RoutesReady { operation, result } => {
+                if !self.operations.finish(OperationKind::Routes, operation) {
+                    debug!(operation, "dropped the routes of a cancelled search");
+                    return;
+                }
+                self.update_operations_status();
+
+                match result {
+                    Ok(routes) if routes.is_empty() => self.post_status(StatusLevel::Info, format!("No route, or no road within {} m of either end", DEFAULT_SNAP_DISTANCE_M)),
+                    Ok(routes) => {
+                        self.routes = routes;
+                        self.active_route = 0;
+                        self.show_active_route();
+                    }
+                    Err(OperationError::Cancelled) => debug!(operation, "the route search was cancelled"),
+                    Err(error) => {
+                        error!(%error, "could not find a route");
+                        self.post_status(StatusLevel::Error, format!("Could not find a route: {}", error));
+                    }
+                }
+                self.update_route_overlay();
+            }
+            AppEvent::MapFileChanged(path) => match self.watch_mode {
+                Some(WatchMode::Auto) => self.start_import(ImportSource::File(path)),
+                _ => {
+                    self.post_status(StatusLevel::Info, format!("{} changed, press I to import it", path.display()));
+                    self.changed_map_file = Some(path);
+                }
+            },
+        }
+    }
+
+    /// Applies the events of the background tasks and the expired status messages, and
+    /// tessellates the next slice of the map queued by `update_buffers_progressively`.
+    ///
+    /// ## Returns
+    /// * Whether events or ways to tessellate are left over for the next frame.
+    fn update(&mut self) -> bool {
+        let events = self.events.drain(MAX_EVENTS_PER_FRAME);
+        let events_left = events.len() == MAX_EVENTS_PER_FRAME;
+        for event in events {
+            self.handle_event(event);
+        }
+
+        let now = Instant::now();
+        if self.status.expire(now) {
+            self.update_status_overlay();
+        }
+        if self.fetch_debouncer.is_due(now) {
+            debug!(viewport = ?self.view, "the camera stopped, fetching the viewport");
+            self.fetch_viewport();
+        }
+        if self.history.settle(self.view, now) {
+            debug!(viewport = ?self.view, "recorded the viewport in the history");
+            self.start_viewport_stats();
+        }
+        if self.hover.show_if_rested(now) {
+            self.update_hover_overlay();
+            // There is no text rendering, so the text of the tooltip is logged like the inspection panel's
+            if let Some((way_id, way)) = self.hover.shown() {
+                info!(way_id, "hovering {}", describe_way(&way.tags));
+            }
+        }
+        let tessellating = self.tessellate_pending();
+        events_left || tessellating
+    }
+
+    /// Computes the area reachable from a point by car on a thread of its own, which reports
+    /// it with `AppEvent::IsochroneReady`. The computation started before is cancelled.
+    fn start_isochrone(&mut self, point: (f64, f64)) {
+        let (operation, cancel) = self.operations.start(OperationKind::Isochrone, Instant::now());
+        let task = spawn_db_task(self.pool.clone(), move |pool, _| async move {
+            let result = isochrone_around(&pool, point, ISOCHRONE_MINUTES * 60.0, RoutingProfile::Car, DEFAULT_ISOCHRONE_CELL_M, &cancel).await;
+            AppEvent::IsochroneReady { operation, result }
+        }, self.event_sender.clone());
+        if let Err(error) = task {
+            self.event_sender.send(AppEvent::IsochroneReady { operation, result: Err(sqlx::Error::Io(error).into()) });
+        }
+
+        info!(?point, minutes = ISOCHRONE_MINUTES, operation, "computing the reachable area");
+        self.update_operations_status();
+    }
+
+    /// Finds the fastest route between two points and its alternatives on a thread of its
+    /// own, which reports them with `AppEvent::RoutesReady`. The search started before is
+    /// cancelled.
+    fn start_routes(&mut self, from: (f64, f64), to: (f64, f64)) {
+        let (operation, cancel) = self.operations.start(OperationKind::Routes, Instant::now());
+        let task = spawn_db_task(self.pool.clone(), move |pool, _| async move {
+            let result = route_alternatives_between(&pool, from, to, RoutingProfile::Car, &AlternativeOptions::default(), &cancel).await;
+            AppEvent::RoutesReady { operation, result }
+        }, self.event_sender.clone());
+        if let Err(error) = task {
+            self.event_sender.send(AppEvent::RoutesReady { operation, result: Err(sqlx::Error::Io(error).into()) });
+        }
+
+        info!(?from, ?to, operation, "finding a route");
+        self.update_operations_status();
+    }
+
+    /// Computes the statistics of the viewport on a thread of its own, which reports them
+    /// with `AppEvent::ViewportStatsReady`. Only the ways in view are handed over, and the
+    /// statistics of the viewport the camera stopped at before are cancelled.
+    fn start_viewport_stats(&mut self) {
+        let viewport = self.view;
+        let ways: Vec<RenderableWay> = self.renderable_ways.iter()
+            .filter(|way| way_bbox(way).is_some_and(|way_bbox| way_bbox.intersects(&viewport)))
+            .cloned()
+            .collect();
+
+        let (operation, cancel) = self.operations.start(OperationKind::ViewportStats, Instant::now());
+        let events = self.event_sender.clone();
+        thread::spawn(move || {
+            // Nothing waits for statistics that were cancelled, they were replaced or are not wanted
+            if let Ok(stats) = compute_viewport_stats(&ways, &viewport, &cancel) {
+                events.send(AppEvent::ViewportStatsReady { operation, viewport, stats });
+            }
+        });
+    }
+
+    /// Shows the operations running in the status line with the key that cancels them, or
+    /// clears the busy message once none is left.
+    fn update_operations_status(&mut self) {
+        let running: Vec<String> = self.operations.shown().map(|operation| operation.kind.to_string()).collect();
+        if running.is_empty() {
+            if self.status.clear(StatusLevel::Busy) {
+                self.update_status_overlay();
+            }
+            return;
+        }
+
+        let running = running.join(" and ");
+        let mut text = running[..1].to_uppercase();
+        text.push_str(&running[1..]);
+        text.push_str(" (Escape cancels)");
+        self.post_status(StatusLevel::Busy, text);
+    }
+
+    /// Cancels every operation running in the background. Their results are dropped once
+    /// they report them.
+    fn cancel_operations(&mut self) {
+        let cancelled = self.operations.cancel_all();
+        for operation in &cancelled {
+            info!(operation = %operation.kind, id = operation.id, elapsed = ?operation.started_at.elapsed(), "cancelled");
+        }
+        self.update_operations_status();
+
+        let shown: Vec<String> = cancelled.iter().filter(|operation| operation.kind.is_shown()).map(|operation| operation.kind.to_string()).collect();
+        if !shown.is_empty() {
+            self.post_status(StatusLevel::Info, format!("Stopped {}", shown.join(" and ")));
+        }
+    }
+
+    fn update_buffers(&mut self) {
+        // The map is tessellated whole for the new viewport, what was queued for the old one is dropped
+        if let Some(pending) = self.pending_map.take() {
+            debug!(items = pending.items.len(), "dropped the tessellation queued for the previous viewport");
+        }
+
+        // The generators place the vertices relative to the center of the viewport
+        self.vertex_projection = Projection::for_viewport(&self.view);
+        self.fetch_debouncer.loaded(self.view);
+        let started = Instant::now();
+
+        // Generate vertices and indices from the ways of the tiles in view
+        self.profiler.begin("tessellate", started);
+        let visible_ways = self.tile_cache.ways_in_viewport(&self.renderable_ways, &self.style_sheet, &self.view);
+        let mut chunks = ChunkBuilder::default();
+        // The shading beyond the data comes first, so the map is drawn over it
+        generate_data_extent_vertices_and_indices(self.imported_extent, &self.palette, &self.view, &mut chunks);
+        generate_vertices_and_indices_from_renderable_ways(&self.map_scene(&visible_ways, &self.relation_ways), &self.palette, line_lod_ndc((self.size.width, self.size.height)), &mut chunks);
+
+        // GPS tracks are appended last, so they are drawn on top of the map
+        if self.show_gps_tracks {
+            generate_gps_track_vertices_and_indices(&self.gps_tracks, &self.palette, &self.view, &mut chunks);
+        }
+
+        let chunks = chunks.finish();
+        self.profiler.end("tessellate", Instant::now());
+
+        // The buffers of the previous chunks are written over where they are large enough
+        self.profiler.begin("upload", Instant::now());
+        self.map_chunk_count = write_map_chunks(&self.device, &self.queue, &mut self.map_chunks, chunks);
+        self.profiler.end("upload", Instant::now());
+        metrics::TESSELLATION_SECONDS.observe_since(started);
+        self.skeleton.replace(visible_ways.iter().map(|way| way.id));
+        self.update_view_overlays(&visible_ways);
+    }
+
+    /// The map in view as drawn from the given ways, with the style and the viewport shown.
+    fn map_scene<'a>(&'a self, renderable_ways: &'a [RenderableWay], relation_ways: &'a [RenderableWay]) -> MapScene<'a> {
+        MapScene {
+            renderable_ways,
+            relation_ways,
+            style_sheet: &self.style_sheet,
+            view: self.view,
+            extrude_buildings: self.show_buildings_3d,
+        }
+    }
+
+    /// Regenerates the map like `update_buffers`, but leaves tessellating the ways to
+    /// `tessellate_pending`, a slice per frame, so a large batch of data arriving in the
+    /// background does not freeze the window. The map fills in from its lowest layer up.
+    fn update_buffers_progressively(&mut self) {
+        self.vertex_projection = Projection::for_viewport(&self.view);
+        self.fetch_debouncer.loaded(self.view);
+
+        let visible_ways = self.tile_cache.ways_in_viewport(&self.renderable_ways, &self.style_sheet, &self.view);
+        let mut chunks = ChunkBuilder::default();
+        generate_data_extent_vertices_and_indices(self.imported_extent, &self.palette, &self.view, &mut chunks);
+        let items = prepare_draw_items(&self.map_scene(&visible_ways, &self.relation_ways));
+        let tessellation = Tessellation::new(&self.view, line_lod_ndc((self.size.width, self.size.height)), chunks.vertex_limit);
+        debug!(items = items.len(), "queued the map for tessellation");
+
+        // Chunks are written as they fill, starting over in the buffers of the previous map
+        let way_ids = visible_ways.iter().map(|way| way.id).collect();
+        self.pending_map = Some(PendingMap { items: WorkQueue::new(items), tessellation, chunks, written: Vec::new(), way_ids });
+        self.update_view_overlays(&visible_ways);
+    }
+
+    /// Tessellates the ways queued by `update_buffers_progressively` for at most
+    /// `TESSELLATION_BUDGET`, and copies the geometry appended to the chunks into their buffers.
+    /// The GPS tracks are appended once the last ways are done, so they stay on top.
+    ///
+    /// ## Returns
+    /// * Whether ways are left over for the next frame.
+    fn tessellate_pending(&mut self) -> bool {
+        let Some(PendingMap { items, tessellation, chunks, written, .. }) = &mut self.pending_map else {
+            return false;
+        };
+
+        let started = Instant::now();
+        self.profiler.begin("tessellate", started);
+        let palette = &self.palette;
+        let done = items.run(TESSELLATION_BUDGET, TESSELLATION_BATCH, Instant::now, |batch| {
+            for geometry in tessellate_draw_items(batch, tessellation, palette) {
+                chunks.push(geometry);
+            }
+        });
+        let left = items.len();
+        if items.is_empty() && self.show_gps_tracks {
+            generate_gps_track_vertices_and_indices(&self.gps_tracks, palette, &self.view, chunks);
+        }
+        self.profiler.end("tessellate", Instant::now());
+        self.profiler.begin("upload", Instant::now());
+        self.map_chunk_count = write_appended_chunks(&self.device, &self.queue, &mut self.map_chunks, chunks.chunks(), written);
+        self.profiler.end("upload", Instant::now());
+        metrics::TESSELLATION_SLICE_SECONDS.observe_since(started);
+        debug!(done, left, "tessellated a slice of the map");
+
+        if left == 0 {
+            if let Some(pending) = self.pending_map.take() {
+                self.replace_placeholders(pending.way_ids);
+            }
+        }
+        left > 0
+    }
+
+    /// Regenerates what is drawn of the viewport besides the map, after the map was.
+    fn update_view_overlays(&mut self, visible_ways: &[RenderableWay]) {
+        let (icon_vertices, icon_indices) = generate_poi_icon_vertices_and_indices(visible_ways, &self.view, (self.size.width, self.size.height));
+        self.poi_icons = OverlayBuffers::new(&self.device, "POI Icons", &icon_vertices, &icon_indices);
+        self.update_filter_highlight(visible_ways);
+        self.update_markers();
+        self.update_node_dots(visible_ways);
+        self.update_isochrone_overlay();
+        self.update_route_overlay();
+        self.update_skeleton_overlay();
+        self.update_hover_overlay();
+
+        self.update_measurement_buffers();
+        self.update_graticule();
+        self.update_scale_bar();
+        self.update_minimap_camera();
+        self.update_outside_data();
+        self.background = viewport_surface(self.land_water_grid.as_ref(), visible_ways, &self.view);
+    }
+
+    /// Draws the nodes in view as dots while no way is in view, e.g. of a survey imported as
+    /// nodes only, so the data shows at all. They are fetched on a thread of their own, which
+    /// reports them with `AppEvent::NodeDotsLoaded`; until then the dots fetched before stay.
+    /// At most `max_node_dots` are drawn, see `node_dot_stride`.
+    fn update_node_dots(&mut self, visible_ways: &[RenderableWay]) {
+        // A newer fetch, or ways coming into view, makes the nodes still being fetched stale
+        self.node_dots_generation += 1;
+        if visible_ways.is_empty() && self.max_node_dots > 0 {
+            let generation = self.node_dots_generation;
+            let view = self.view;
+            let max = self.max_node_dots;
+            let task = spawn_db_task(self.pool.clone(), move |pool, _| async move {
+                AppEvent::NodeDotsLoaded { generation, result: fetch_node_dots(&pool, &view, max).await }
+            }, self.event_sender.clone());
+            if let Err(error) = task {
+                self.event_sender.send(AppEvent::NodeDotsLoaded { generation, result: Err(sqlx::Error::Io(error)) });
+            }
+        } else {
+            self.node_dots.clear();
+        }
+        self.update_node_dot_overlay();
+    }
+
+    /// Regenerates the dots of `node_dots` for the viewport.
+    fn update_node_dot_overlay(&mut self) {
+        let (vertices, indices) = generate_node_dot_vertices_and_indices(&self.node_dots, &self.palette, &self.view, (self.size.width, self.size.height));
+        self.node_dot_overlay = OverlayBuffers::new(&self.device, "Node Dots", &vertices, &indices);
+    }
+
+    /// Tells once the viewport has left the imported data, as the map is empty beyond it.
+    fn update_outside_data(&mut self) {
+        let outside_data = is_outside_data(self.imported_extent, &self.view);
+        if outside_data && !self.outside_data {
+            self.post_status(StatusLevel::Info, NO_DATA_MESSAGE.to_string());
+        }
+        self.outside_data = outside_data;
+    }
+
+    /// Regenerates the outline of the viewport on the minimap.
+    fn update_minimap_camera(&mut self) {
+        let (vertices, indices) = generate_minimap_camera_vertices_and_indices(&self.palette, &self.view, self.data_extent);
+        self.minimap_camera = OverlayBuffers::new(&self.device, "Minimap Camera", &vertices, &indices);
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // The camera is applied per frame, relative to the origin the vertices were generated around
+        let camera = Projection::for_viewport(&self.view);
+        let map_camera = CameraUniform::new(&self.vertex_projection, &camera);
+        self.map_camera.write(&self.queue, if self.show_buildings_3d { map_camera.tilted() } else { map_camera });
+
+        // The GPU time of an earlier frame, as the GPU finishes a frame after it was submitted
+        if let Some(gpu_time) = self.gpu_timer.as_mut().and_then(|gpu_timer| gpu_timer.poll(&self.device)) {
+            self.profiler.record("gpu", gpu_time);
+        }
+
+        // Waiting for the next image of the surface shows how long the frame waited for the display
+        self.profiler.begin("acquire", Instant::now());
+        let output = self.surface.get_current_texture()?;
+        self.profiler.end("acquire", Instant::now());
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.profiler.begin("encode", Instant::now());
+        let timestamp_writes = self.gpu_timer.as_ref()
+            .filter(|_| self.profiler.is_enabled())
+            .and_then(GpuTimer::timestamp_writes);
+        let timing_gpu = timestamp_writes.is_some();
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.theme.background_color(self.background)),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes,
+            });
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, &self.map_camera.bind_group, &[]);
+            render_pass.set_bind_group(1, &self.palette_binding.bind_group, &[]);
+            for chunk in &self.map_chunks[..self.map_chunk_count] {
+                chunk.draw(&mut render_pass, self.layer_visibility);
+            }
+            // The ways matching the filter are drawn again over the dimmed map, at the same depth
+            for chunk in &self.filter_chunks[..self.filter_chunk_count] {
+                chunk.draw(&mut render_pass, self.layer_visibility);
+            }
+
+            // The icons of points of interest are textured, so they have a pipeline of their own
+            if self.layer_visibility.contains(LayerVisibility::POIS) {
+                render_pass.set_pipeline(&self.icon_pipeline);
+                render_pass.set_bind_group(1, &self.icon_bind_group, &[]);
+                self.poi_icons.draw(&mut render_pass);
+                render_pass.set_bind_group(1, &self.palette_binding.bind_group, &[]);
+            }
+
+            // The overlays are drawn last, on top of the map, the translucent ones first
+            render_pass.set_pipeline(&self.translucent_pipeline);
+            self.skeleton_overlay.draw(&mut render_pass);
+            self.isochrone_overlay.draw(&mut render_pass);
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            self.graticule_overlay.draw(&mut render_pass);
+            self.node_dot_overlay.draw(&mut render_pass);
+            self.route_overlay.draw(&mut render_pass);
+            if self.layer_visibility.contains(LayerVisibility::POIS) {
+                self.marker_overlay.draw(&mut render_pass);
+            }
+            self.measure_overlay.draw(&mut render_pass);
+            self.hover_overlay.draw(&mut render_pass);
+            render_pass.set_bind_group(0, &self.screen_camera.bind_group, &[]);
+            self.scale_bar_overlay.draw(&mut render_pass);
+            self.status_overlay.draw(&mut render_pass);
+            self.inspection_overlay.draw(&mut render_pass);
+            self.tooltip_overlay.draw(&mut render_pass);
+
+            // The minimap geometry is in NDC of its own inset, so it is drawn through a smaller viewport
+            if let Some((left, top, width, height)) = minimap_rect(self.size) {
+                render_pass.set_viewport(left, top, width, height, 0.0, 1.0);
+                self.minimap_background.draw(&mut render_pass);
+                render_pass.set_bind_group(0, &self.minimap_map_camera.bind_group, &[]);
+                self.minimap_map.draw(&mut render_pass);
+                render_pass.set_bind_group(0, &self.screen_camera.bind_group, &[]);
+                self.minimap_camera.draw(&mut render_pass);
+            }
+        }
+
+        if timing_gpu {
+            if let Some(gpu_timer) = &mut self.gpu_timer {
+                gpu_timer.resolve(&mut encoder);
+            }
+        }
+        self.queue.submit(iter::once(encoder.finish()));
+        self.profiler.end("encode", Instant::now());
+        if timing_gpu {
+            if let Some(gpu_timer) = &mut self.gpu_timer {
+                gpu_timer.read_back();
+            }
+        }
+        output.present();
+
+        Ok(())
+    }
+}
+
+/// Loads the renderable ways from the database.
+async fn load_renderable_ways(pool: &Pool<Sqlite>) -> Vec<RenderableWay> {
+    let renderable_ways = match fetch_all_renderable_ways(pool).await {
+        Ok(renderable_ways) => renderable_ways,
+        Err(error) => panic!("There was a problem fetching the renderable ways: {:?}", error),
+    };
+
+    info!(count = renderable_ways.len(), "loaded renderable ways");
+    renderable_ways
+}
+
+/// Logs how many of the ways miss some of their nodes, e.g. because they cross the edge of
+/// the extract. They are drawn as open lines through the nodes they have.
+///
+/// ## Returns
+/// * A message for the status line, or `None` if every way is complete.
+fn report_incomplete_ways(renderable_ways: &[RenderableWay]) -> Option<String> {
+    let incomplete: Vec<&RenderableWay> = renderable_ways.iter().filter(|way| !way.is_complete()).collect();
+    if incomplete.is_empty() {
+        return None;
+    }
+
+    let missing_nodes: u64 = incomplete.iter().map(|way| way.missing_nodes as u64).sum();
+    warn!(ways = incomplete.len(), missing_nodes, "drawing ways with missing nodes as open lines");
+    Some(format!("{} ways miss {} of their nodes and are drawn as open lines", incomplete.len(), missing_nodes))
+}
+
+// Ways within this many pixels of the cursor can be picked and hovered.
+const PICK_RADIUS_PX: f64 = 8.0;
+
+// The zoom levels the mouse wheel, trackpad and double-click zoom between, see `zoom_level`.
+const MIN_ZOOM_LEVEL: f64 = 2.0;
+const MAX_ZOOM_LEVEL: f64 = 20.0;
+// One notch of a mouse wheel zooms half a level, a trackpad zooms a level per this many pixels
+const ZOOM_LEVELS_PER_LINE: f64 = 0.5;
+const PIXELS_PER_ZOOM_LEVEL: f64 = 200.0;
+const DOUBLE_CLICK_ZOOM_LEVELS: f64 = 1.0;
+// A press of an arrow key pans a tenth of the viewport, the repeats of a held key pan on
+const PAN_STEP: f64 = 0.1;
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+const DOUBLE_CLICK_DISTANCE_PX: f64 = 4.0;
+
+/// How many zoom levels a scroll zooms in, negative to zoom out.
+///
+/// Mouse wheels scroll in lines (notches), trackpads in pixels and much more often, so both
+/// are scaled to a similar zoom for a similar movement of the hand.
+fn scroll_zoom_levels(delta: &MouseScrollDelta) -> f64 {
+    match *delta {
+        MouseScrollDelta::LineDelta(_, lines) => lines as f64 * ZOOM_LEVELS_PER_LINE,
+        MouseScrollDelta::PixelDelta(position) => position.y / PIXELS_PER_ZOOM_LEVEL,
+    }
+}
+
+/// Builds the grid for picking over the extent of the ways.
+fn build_way_index(renderable_ways: &[RenderableWay], extent: Option<BBox>) -> SpatialIndex {
+    let Some(extent) = extent else {
+        return SpatialIndex::default();
+    };
+
+    let started = Instant::now();
+    let way_index = SpatialIndex::build(renderable_ways, &extent);
+    debug!(ways = renderable_ways.len(), elapsed_ms = started.elapsed().as_millis() as u64, "built the way index");
+    way_index
+}
+
+// Ways whose bounding box reaches further than this fraction of the viewport span
+// beyond the viewport are clipped before tessellation.
+const CLIP_MARGIN: f64 = 0.1;
+
+// The colors of the overlays, which keep them in every theme. The style sheet colors are
+// added by `build_palette`.
+const OVERLAY_COLORS: [&str; 25] = [
+    GPS_TRACK_COLOR, MEASURE_COLOR, SCALE_BAR_COLOR, STATUS_IDLE_COLOR, STATUS_BUSY_COLOR, STATUS_ERROR_COLOR,
+    MINIMAP_BACKGROUND_COLOR, MINIMAP_COASTLINE_COLOR, MINIMAP_MOTORWAY_COLOR, MINIMAP_CAMERA_COLOR,
+    OUTSIDE_DATA_COLOR, DATA_EDGE_COLOR, INCOMPLETE_WAY_COLOR, INSPECTION_PANEL_COLOR, INSPECTION_THUMB_COLOR,
+    FILTER_MATCH_COLOR, GRATICULE_COLOR, MARKER_OUTLINE_COLOR, SELECTED_MARKER_OUTLINE_COLOR,
+    HOVER_COLOR, TOOLTIP_COLOR, TOOLTIP_BORDER_COLOR, ROUTE_COLOR, ROUTE_ALTERNATIVE_COLOR, NODE_DOT_COLOR,
+];
+
+// Ways matching the tag filter are drawn in this color, over the map dimmed this much of
+// the way towards the background
+const FILTER_MATCH_COLOR: &str = "#ff8c00";
+const FILTER_DIM_AMOUNT: f32 = 0.7;
+
+// The dashes and gaps in meters of lines through tunnels, unless their style dashes them already
+const TUNNEL_DASH_M: (f64, f64) = (6.0, 4.0);
+
+// Set `GMC_TINT_INCOMPLETE_WAYS=1` to draw the ways missing some of their nodes in a color of
+// their own, to find them while debugging an extract.
+const TINT_INCOMPLETE_WAYS_ENV: &str = "GMC_TINT_INCOMPLETE_WAYS";
+const INCOMPLETE_WAY_COLOR: &str = "#ff00ff";
+
+// Points of a way closer than this many pixels on screen to the last point drawn are skipped
+// while tessellating, set with `GMC_LINE_LOD_PX`. `0` keeps every point.
+const LINE_LOD_PX_ENV: &str = "GMC_LINE_LOD_PX";
+const DEFAULT_LINE_LOD_PX: f32 = 1.0;
+// Keeps every point of a line
+const NO_LINE_LOD: (f32, f32) = (0.0, 0.0);
+
+/// The pixels between points of a way, `DEFAULT_LINE_LOD_PX` unless overridden by
+/// `GMC_LINE_LOD_PX`. It is read once, a value that cannot be parsed is ignored with a warning.
+fn line_lod_px() -> f32 {
+    static LINE_LOD_PX: OnceLock<f32> = OnceLock::new();
+
+    *LINE_LOD_PX.get_or_init(|| {
+        let Ok(value) = env::var(LINE_LOD_PX_ENV) else {
+            return DEFAULT_LINE_LOD_PX;
+        };
+        match value.trim().parse::<f32>() {
+            Ok(px) if px.is_finite() && px >= 0.0 => px,
+            Ok(_) => {
+                warn!(variable = LINE_LOD_PX_ENV, value, "ignoring a negative pixel count");
+                DEFAULT_LINE_LOD_PX
+            }
+            Err(error) => {
+                warn!(variable = LINE_LOD_PX_ENV, %error, "ignoring");
+                DEFAULT_LINE_LOD_PX
+            }
+        }
+    })
+}
+
+/// The step between points of a way below which they are skipped, in normalized device units
+/// along x and y, for a viewport of `size_px` pixels.
+fn line_lod_ndc(size_px: (u32, u32)) -> (f32, f32) {
+    let px = line_lod_px();
+    (px * 2.0 / size_px.0.max(1) as f32, px * 2.0 / size_px.1.max(1) as f32)
+}
+
+/// Collects every color the map and the overlays are drawn in. The colors of the style
+/// sheet are remapped by the theme, the overlays keep theirs.
+fn build_palette(style_sheet: &StyleSheet) -> Palette {
+    let mut palette = Palette::new(Style::default().color, true);
+    for rule in &style_sheet.rules {
+        palette.add(rule.style.color, true);
+        if let Some(dash_overlay) = &rule.style.dash_overlay {
+            palette.add(dash_overlay.color, true);
+        }
+    }
+    for color in OVERLAY_COLORS {
+        palette.add(parse_hex_color(color).unwrap_or(Style::default().color), false);
+    }
+    palette.add(isochrone_color(), false);
+    palette.add(skeleton_color(), false);
+    palette
+}
+
+/// The index of an overlay color written as `#rrggbb` in the palette.
+fn overlay_color(palette: &Palette, color: &str) -> u32 {
+    palette.index_of(parse_hex_color(color).unwrap_or(Style::default().color), false)
+}
+
+/// A way, or a line joined from several ways, tessellated on its own. It owns what it is
+/// drawn from, so it can wait in a `WorkQueue` for a later frame.
+enum DrawItem {
+    /// A line, with whether its first and last point reach into an intersection, whether it
+    /// is a way missing some of its nodes, and whether it runs through a tunnel.
+    Line { points: Vec<(f64, f64)>, style: Style, layer: MapLayer, extend_ends: (bool, bool), incomplete: bool, tunnel: bool },
+    /// A filled area with its outline and the outlines of its holes, raised to a building of
+    /// `height_m` in the 2.5D view.
+    Area { coords: Vec<(f64, f64)>, holes: Vec<Vec<(f64, f64)>>, style: Style, layer: MapLayer, height_m: Option<f64> },
+}
+
+/// Tessellates ways like a frame of the map does, with buildings extruded, but without a
+/// GPU, e.g. to benchmark it.
+///
+/// ## Arguments
+/// * `size_px` - The size of the frame in pixels, which decides the points of lines skipped.
+///
+/// ## Returns
+/// * The number of vertices generated.
+pub fn tessellate_ways(renderable_ways: &[RenderableWay], style_sheet: &StyleSheet, view: &BBox, size_px: (u32, u32)) -> usize {
+    let palette = build_palette(style_sheet);
+    let mut chunks = ChunkBuilder::default();
+    let scene = MapScene { renderable_ways, relation_ways: &[], style_sheet, view: *view, extrude_buildings: true };
+    generate_vertices_and_indices_from_renderable_ways(&scene, &palette, line_lod_ndc(size_px), &mut chunks);
+    chunks.finish().iter().map(|chunk| chunk.vertices.len()).sum()
+}
+
+/// What `render_to_image` draws.
+///
+/// # Fields
+/// * `renderable_ways` - The ways of the map.
+/// * `relation_ways` - The administrative boundaries and multipolygons, see `fetch_relation_ways`.
+/// * `style_sheet` - How the ways are drawn.
+/// * `theme` - The colors the map is drawn in.
+/// * `viewport` - The part of the map drawn.
+/// * `buildings_3d` - Whether buildings are extruded and the view is tilted to show them.
+pub struct OffscreenView<'a> {
+    pub renderable_ways: &'a [RenderableWay],
+    pub relation_ways: &'a [RenderableWay],
+    pub style_sheet: &'a StyleSheet,
+    pub theme: Theme,
+    pub viewport: BBox,
+    pub buildings_3d: bool,
+}
+
+// Frames drawn without a window are sRGB encoded, like the surfaces of windows
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Draws the map into an image without opening a window, e.g. to compare the drawing with
+/// an earlier one. The overlays are left out. The adapter is picked like for the window,
+/// so `GMC_WGPU_BACKEND=gl` draws on a software renderer where there is one.
+///
+/// ## Returns
+/// * The image of `width` × `height` pixels, or `GpuError::NoAdapter` if there is no adapter to draw with.
+pub async fn render_to_image(view: &OffscreenView<'_>, width: u32, height: u32) -> Result<image::RgbaImage, GpuError> {
+    let gpu_options = GpuOptions::from_env();
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: gpu_options.backends,
+        ..Default::default()
+    });
+    let adapter = select_headless_adapter(&instance, &gpu_options)?;
+    let (device, queue) = request_device(&adapter).await?;
+
+    let layouts = BindGroupLayouts::new(&device);
+    let icon_bind_group = create_icon_bind_group(&device, &queue, &layouts.texture);
+    let palette = build_palette(view.style_sheet);
+    let palette_binding = PaletteBinding::new(&device, &layouts.palette, &palette.resolve(view.theme));
+    let projection = Projection::for_viewport(&view.viewport);
+    let camera = CameraUniform::new(&projection, &projection);
+    let map_camera = CameraBinding::new(&device, &layouts.camera, "Offscreen", if view.buildings_3d { camera.tilted() } else { camera });
+    let (render_pipeline, _, _) = create_map_pipelines(&device, &layouts, OFFSCREEN_FORMAT);
+    let icon_pipeline = create_icon_pipeline(&device, &layouts, OFFSCREEN_FORMAT);
+
+    let mut chunks = ChunkBuilder::default();
+    let scene = MapScene {
+        renderable_ways: view.renderable_ways,
+        relation_ways: view.relation_ways,
+        style_sheet: view.style_sheet,
+        view: view.viewport,
+        extrude_buildings: view.buildings_3d,
+    };
+    generate_vertices_and_indices_from_renderable_ways(&scene, &palette, line_lod_ndc((width, height)), &mut chunks);
+    let mut map_chunks = Vec::new();
+    let chunk_count = write_map_chunks(&device, &queue, &mut map_chunks, chunks.finish());
+    let (icon_vertices, icon_indices) = generate_poi_icon_vertices_and_indices(view.renderable_ways, &view.viewport, (width, height));
+    let poi_icons = OverlayBuffers::new(&device, "Offscreen POI Icons", &icon_vertices, &icon_indices);
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Texture"),
+        size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: OFFSCREEN_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_texture = texture::Texture::create_depth_texture_of_size(&device, width, height, "Offscreen Depth Texture");
+
+    // The rows of a copy from a texture are padded to a multiple of 256 bytes
+    let bytes_per_row = width * 4;
+    let padded_bytes_per_row = bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Offscreen Readback Buffer"),
+        size: padded_bytes_per_row as u64 * height as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Offscreen Encoder"),
+    });
+    {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Offscreen Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(view.theme.clear_color()),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(&render_pipeline);
+        render_pass.set_bind_group(0, &map_camera.bind_group, &[]);
+        render_pass.set_bind_group(1, &palette_binding.bind_group, &[]);
+        for chunk in &map_chunks[..chunk_count] {
+            chunk.draw(&mut render_pass, LayerVisibility::ALL);
+        }
+
+        render_pass.set_pipeline(&icon_pipeline);
+        render_pass.set_bind_group(1, &icon_bind_group, &[]);
+        poi_icons.draw(&mut render_pass);
+    }
+
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &readback_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+    );
+    queue.submit(iter::once(encoder.finish()));
+
+    // Wait for the copy, then strip the padding off the rows
+    let (sender, receiver) = std::sync::mpsc::channel();
+    readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv()
+        .map_err(|error| GpuError::Readback(error.to_string()))?
+        .map_err(|error| GpuError::Readback(error.to_string()))?;
+
+    let pixels: Vec<u8> = readback_buffer.slice(..).get_mapped_range()
+        .chunks(padded_bytes_per_row as usize)
+        .flat_map(|row| row[..bytes_per_row as usize].iter().copied())
+        .collect();
+    image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| GpuError::Readback("the frame has the wrong size".to_string()))
+}
+
+/// What the map is drawn from, see `generate_vertices_and_indices_from_renderable_ways`.
+///
+/// # Fields
+/// * `renderable_ways` - The ways in view.
+/// * `relation_ways` - The lines of the administrative boundaries and the areas of the multipolygons.
+/// * `style_sheet` - How the ways look.
+/// * `view` - The viewport.
+/// * `extrude_buildings` - Whether buildings are drawn as blocks rather than flat.
+#[derive(Clone, Copy)]
+struct MapScene<'a> {
+    renderable_ways: &'a [RenderableWay],
+    relation_ways: &'a [RenderableWay],
+    style_sheet: &'a StyleSheet,
+    view: BBox,
+    extrude_buildings: bool,
+}
+
+/// Tessellates the ways in view into the chunks of the map.
+///
+/// The ways are tessellated in parallel, each into geometry of its own, and then packed into
+/// the chunks in draw order. Every way, and every run of `MapLayer`, is recorded in its chunk,
+/// so layers can be hidden when drawing.
+///
+/// The administrative boundaries are drawn from `relation_ways` alone, so ways tagged as
+/// part of one are left out of `renderable_ways`. The multipolygons among `relation_ways`
+/// are filled around their holes, so the areas below show through them.
+fn generate_vertices_and_indices_from_renderable_ways(scene: &MapScene, palette: &Palette, min_step_ndc: (f32, f32), chunks: &mut ChunkBuilder) {
+    let items = prepare_draw_items(scene);
+    let tessellation = Tessellation::new(&scene.view, min_step_ndc, chunks.vertex_limit);
+    for geometry in tessellate_draw_items(items, &tessellation, palette) {
+        chunks.push(geometry);
+    }
+}
+
+/// Picks the ways in view that are drawn at its zoom level, and turns them into items in
+/// draw order, see `generate_vertices_and_indices_from_renderable_ways`.
+fn prepare_draw_items(scene: &MapScene) -> Vec<DrawItem> {
+    let MapScene { renderable_ways, relation_ways, style_sheet, view, extrude_buildings } = *scene;
+    // Clip a little outside the viewport, so line caps at the screen edges are not visible
+    let clip_bbox = view.expand(CLIP_MARGIN);
+    let zoom = zoom_level(&view);
+
+    // Determine how to visualize each way based on its tags, and draw lower layers first.
+    // Within a layer, tunnels come first and bridges last, so they cross over the ways below them.
+    // Ways entirely outside the viewport contribute nothing
+    let default_style = Style::default();
+    let is_boundary = |way: &RenderableWay| way.tags.iter().any(|tag| tag.key == "boundary" && tag.value == "administrative");
+    let mut styled_ways: Vec<(&RenderableWay, &Style)> = renderable_ways.iter()
+        .filter(|way| !is_boundary(way))
+        .chain(relation_ways)
+        .map(|way| (way, style_sheet.style_for(&way.tags).unwrap_or(&default_style)))
+        .filter(|(_, style)| style.visible_at(zoom))
+        .filter(|(way, _)| way_bbox(way).is_some_and(|way_bbox| way_bbox.intersects(&clip_bbox)))
+        .collect();
+    styled_ways.sort_by_cached_key(|(way, style)| (style.layer, VerticalLayer::of_tags(&way.tags)));
+
+    // Lines of one style, map layer and vertical layer are joined where they continue each
+    // other, and all drawn once the first of them comes up. Every style has a single layer and
+    // a bridge is not joined to the road leading onto it, so the draw order is kept
+    let is_joined_line = |way: &RenderableWay, style: &Style| !style.fill && way.is_complete();
+    let junctions = shared_node_ids(styled_ways.iter().filter(|(way, style)| is_joined_line(way, style)).map(|(way, _)| way.node_ids.as_slice()));
+    let mut lines_by_style: HashMap<(*const Style, MapLayer, VerticalLayer), Vec<Vec<SimpleNode>>> = HashMap::new();
+    for (way, style) in styled_ways.iter().filter(|(way, style)| is_joined_line(way, style)) {
+        lines_by_style.entry((*style as *const Style, MapLayer::of_tags(&way.tags), VerticalLayer::of_tags(&way.tags))).or_default().push(way.nodes());
+    }
+
+    let mut items = Vec::new();
+    for (way, style) in styled_ways {
+        let map_layer = MapLayer::of_tags(&way.tags);
+        let vertical_layer = VerticalLayer::of_tags(&way.tags);
+
+        // A way missing some of its nodes is an open line through the nodes it has. It is
+        // never closed or filled, as the missing nodes could lie anywhere
+        if !way.is_complete() {
+            items.push(DrawItem::Line { points: way.coords.clone(), style: style.clone(), layer: map_layer, extend_ends: (false, false), incomplete: true, tunnel: vertical_layer.tunnel });
+            continue;
+        }
+
+        if !style.fill {
+            let Some(lines) = lines_by_style.remove(&(style as *const Style, map_layer, vertical_layer)) else {
+                continue;
+            };
+
+            for line in merge_lines_at_junctions(lines) {
+                let points: Vec<(f64, f64)> = line.iter().map(|node| (node.lat, node.lon)).collect();
+
+                // Closed lines have no ends to extend
+                let is_junction = |node: Option<&SimpleNode>| node.and_then(|node| node.id).is_some_and(|id| junctions.contains(&id));
+                let is_closed = matches!((line.first(), line.last()), (Some(first), Some(last)) if first.id.is_some() && first.id == last.id);
+                let extend_ends = if is_closed { (false, false) } else { (is_junction(line.first()), is_junction(line.last())) };
+
+                items.push(DrawItem::Line { points, style: style.clone(), layer: map_layer, extend_ends, incomplete: false, tunnel: vertical_layer.tunnel });
+            }
+            continue;
+        }
+
+        // Buildings and their parts are raised to their height in the 2.5D view
+        let height_m = (extrude_buildings && way.tags.iter().any(|tag| tag.key == "building" || tag.key == "building:part"))
+            .then(|| building_height_m(&way.tags).unwrap_or(DEFAULT_BUILDING_HEIGHT_M));
+        items.push(DrawItem::Area { coords: way.coords.clone(), holes: way.inner_rings.clone(), style: style.clone(), layer: map_layer, height_m });
+    }
+    items
+}
+
+/// How draw items are tessellated for a viewport, kept so items of it can be tessellated
+/// in later frames, see `PendingMap`.
+///
+/// # Fields
+/// * `projection` - The projection the vertices are placed with.
+/// * `clip_bbox` - The box geometry is clipped to, a little outside the viewport.
+/// * `meters_per_ndc` - How many meters a normalized device unit spans, to size the lines.
+/// * `min_step_ndc` - The step between points of a line below which they are skipped, see `line_lod_ndc`.
+/// * `tint_incomplete` - Whether ways missing some of their nodes get `INCOMPLETE_WAY_COLOR`.
+/// * `vertex_limit` - The most vertices of a geometry, see `ChunkBuilder`.
+struct Tessellation {
+    projection: Projection,
+    clip_bbox: BBox,
+    meters_per_ndc: f64,
+    min_step_ndc: (f32, f32),
+    tint_incomplete: bool,
+    vertex_limit: usize,
+}
+
+impl Tessellation {
+    fn new(view: &BBox, min_step_ndc: (f32, f32), vertex_limit: usize) -> Self {
+        Tessellation {
+            projection: Projection::for_viewport(view),
+            clip_bbox: view.expand(CLIP_MARGIN),
+            meters_per_ndc: meters_per_ndc_unit(view),
+            min_step_ndc,
+            tint_incomplete: env::var(TINT_INCOMPLETE_WAYS_ENV).is_ok_and(|value| value == "1"),
+            vertex_limit,
+        }
+    }
+}
+
+/// Tessellates draw items into geometry for the chunks, in the order of the items.
+fn tessellate_draw_items(items: Vec<DrawItem>, tessellation: &Tessellation, palette: &Palette) -> Vec<WayGeometry> {
+    let Tessellation { projection, clip_bbox, meters_per_ndc, min_step_ndc, tint_incomplete, vertex_limit } = *tessellation;
+
+    // The items are independent of each other, so they are tessellated on the tessellation
+    // threads. Collecting keeps them in draw order, so the chunks are the same on every run
+    let outlined_areas = AtomicUsize::new(0);
+    let geometries: Vec<Vec<WayGeometry>> = install_tessellation(|| items.into_par_iter()
+        .map(|item| match item {
+            DrawItem::Line { mut points, style, layer, extend_ends: (extend_start, extend_end), incomplete, tunnel } => {
+                // Handle line rendering (e.g., highways and coastlines as thick lines)
+                let thickness = (style.width_m / meters_per_ndc) as f32;
+                let palette_index = if incomplete && tint_incomplete { overlay_color(palette, INCOMPLETE_WAY_COLOR) } else { palette.index_of(style.color, true) };
+
+                // Ends at intersections reach half a width into the crossing line, so the
+                // quads butt together without a gap
+                extend_line_ends(&mut points, &projection, thickness / 2.0, extend_start, extend_end);
+
+                // Dashes are cut along the whole line before clipping, so they stay in place
+                // on the ground while the map moves. Tunnels without dashes of their own are
+                // dashed, so the ways above them show through the gaps
+                let pieces = match style.dash.or(tunnel.then_some(TUNNEL_DASH_M)) {
+                    Some((dash_m, gap_m)) => dash_polyline(&points, dash_m, gap_m),
+                    None => vec![points],
+                };
+
+                let clip = |piece: Vec<(f64, f64)>| {
+                    let needs_clipping = bbox_of_points(&piece).is_some_and(|piece_bbox| !clip_bbox.contains_bbox(&piece_bbox));
+                    if needs_clipping { clip_polyline_to_bbox(&piece, &clip_bbox) } else { vec![piece] }
+                };
+                let overlay_dashes: Vec<Vec<(f64, f64)>> = style.dash_overlay.iter()
+                    .flat_map(|dash_overlay| pieces.iter().flat_map(|piece| dash_polyline(piece, dash_overlay.dash.0, dash_overlay.dash.1)))
+                    .collect();
+
+                let mut geometries: Vec<WayGeometry> = pieces.into_iter()
+                    .flat_map(clip)
+                    .flat_map(|part| line_geometries(&part, &projection, thickness, min_step_ndc, palette_index, layer, vertex_limit))
+                    .collect();
+
+                // The dashes on top come after the line, so they win the depth test against it
+                if let Some(dash_overlay) = &style.dash_overlay {
+                    let thickness = (dash_overlay.width_m / meters_per_ndc) as f32;
+                    let palette_index = palette.index_of(dash_overlay.color, true);
+                    geometries.extend(overlay_dashes.into_iter()
+                        .flat_map(clip)
+                        .flat_map(|part| line_geometries(&part, &projection, thickness, min_step_ndc, palette_index, layer, vertex_limit)));
+                }
+                geometries
+            }
+            DrawItem::Area { coords, holes, style, layer, height_m } => {
+                // An outline that cannot be filled, e.g. one crossing itself, is drawn as a line
+                let Some(points) = sanitize_ring(&coords) else {
+                    outlined_areas.fetch_add(1, Ordering::Relaxed);
+                    let thickness = (style.width_m / meters_per_ndc) as f32;
+                    return clip_polyline_to_bbox(&coords, &clip_bbox).iter()
+                        .flat_map(|part| line_geometries(part, &projection, thickness, min_step_ndc, palette.index_of(style.color, true), layer, vertex_limit))
+                        .collect();
+                };
+                let needs_clipping = bbox_of_points(&coords).is_some_and(|area_bbox| !clip_bbox.contains_bbox(&area_bbox));
+                let clip = |ring: Vec<(f64, f64)>| if needs_clipping { clip_polygon_to_bbox(&ring, &clip_bbox) } else { ring };
+                let ring = clip(points);
+                // A hole that cannot be cut out is filled over, what lies below it is still drawn
+                let holes: Vec<Vec<(f64, f64)>> = holes.iter()
+                    .filter_map(|hole| sanitize_ring(hole))
+                    .map(clip)
+                    .filter(|hole| hole.len() >= 3)
+                    .collect();
+
+                // Handle area rendering (e.g., buildings as polygons)
+                let mut geometry = WayGeometry::new(layer);
+                let palette_index = palette.index_of(style.color, true);
+                match height_m {
+                    Some(height_m) => generate_building_vertices_and_indices(&ring, &holes, &projection, height_m, palette_index, &mut geometry.vertices, &mut geometry.indices),
+                    None if holes.is_empty() => generate_polygon_vertices_and_indices(&ring, &projection, palette_index, &mut geometry.vertices, &mut geometry.indices),
+                    None => generate_polygon_with_holes_vertices_and_indices(&ring, &holes, &projection, 0.0, palette_index, &mut geometry.vertices, &mut geometry.indices),
+                }
+                vec![geometry]
+            }
+        })
+        .collect());
+
+    let outlined_areas = outlined_areas.into_inner();
+    if outlined_areas > 0 {
+        debug!(count = outlined_areas, "drew areas with invalid outlines as lines");
+    }
+    geometries.into_iter().flatten().collect()
+}
+
+/// Tessellates a polyline into pieces of at most `vertex_limit` vertices, so that every
+/// piece fits into a chunk. The segments are separate quads, so the pieces join without a seam.
+fn line_geometries(points: &[(f64, f64)], projection: &Projection, thickness: f32, min_step_ndc: (f32, f32), palette_index: u32, layer: MapLayer, vertex_limit: usize) -> Vec<WayGeometry> {
+    // Every segment takes the four corners of its quad
+    let max_segments = (vertex_limit / 4).max(1);
+
+    (0..points.len().saturating_sub(1))
+        .step_by(max_segments)
+        .map(|start| {
+            let end = (start + max_segments + 1).min(points.len());
+            let mut geometry = WayGeometry::new(layer);
+            generate_line_vertices_and_indices(&points[start..end], projection, thickness, min_step_ndc, palette_index, &mut geometry.vertices, &mut geometry.indices);
+            geometry
+        })
+        .collect()
+}
+
+fn way_bbox(way: &RenderableWay) -> Option<BBox> {
+    bbox_of_points(&way.coords)
+}
+
+/// Moves the ends of a polyline outwards along its end segments.
+///
+/// ## Arguments
+/// * `points` - The `(lat, lon)` points of the line.
+/// * `projection` - The projection the line is drawn with.
+/// * `distance` - How far to move the ends, in normalized device units.
+/// * `extend_start` - Whether to move the first point.
+/// * `extend_end` - Whether to move the last point.
+fn extend_line_ends(points: &mut [(f64, f64)], projection: &Projection, distance: f32, extend_start: bool, extend_end: bool) {
+    let extend = |points: &[(f64, f64)]| -> Option<(f64, f64)> {
+        // The end segment starts at the first point differing from the end
+        let (end_x, end_y) = projection.to_ndc(points[0].0, points[0].1);
+        let (x, y) = points[1..].iter()
+            .map(|&(lat, lon)| projection.to_ndc(lat, lon))
+            .find(|&(x, y)| x != end_x || y != end_y)?;
+
+        let length = ((end_x - x).powi(2) + (end_y - y).powi(2)).sqrt();
+        let scale = distance / length;
+        Some(projection.ndc_to_lat_lon(end_x + (end_x - x) * scale, end_y + (end_y - y) * scale))
+    };
+
+    if points.len() < 2 {
+        return;
+    }
+    if extend_start {
+        if let Some(start) = extend(points) {
+            points[0] = start;
+        }
+    }
+    if extend_end {
+        let reversed: Vec<(f64, f64)> = points.iter().rev().copied().collect();
+        if let Some(end) = extend(&reversed) {
+            points[points.len() - 1] = end;
+        }
+    }
+}
+
+// Points of interest get an icon of this many pixels once zoomed in this far. The icons
+// have a chunk of their own, so there are at most as many as fit into one.
+const POI_ICON_SIZE_PX: f32 = 24.0;
+const POI_ICON_MIN_ZOOM: f64 = 16.0;
+const MAX_POI_ICONS: usize = MAX_CHUNK_VERTICES / 4;
+
+/// Generates an icon at the center of every point of interest in view, see `MapLayer::Pois`.
+/// The icons keep their size in pixels at every zoom level, like the lines keep their width.
+///
+/// ## Arguments
+/// * `size_px` - The size of the window, which the icons are sized for.
+fn generate_poi_icon_vertices_and_indices(renderable_ways: &[RenderableWay], view: &BBox, size_px: (u32, u32)) -> (Vec<IconVertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    if zoom_level(view) < POI_ICON_MIN_ZOOM {
+        return (vertices, indices);
+    }
+
+    let projection = Projection::for_viewport(view);
+    let half_size_ndc = (POI_ICON_SIZE_PX / size_px.0.max(1) as f32, POI_ICON_SIZE_PX / size_px.1.max(1) as f32);
+
+    let centers = renderable_ways.iter()
+        .filter(|way| MapLayer::of_tags(&way.tags) == MapLayer::Pois)
+        .filter_map(way_bbox)
+        .map(|way_bbox| way_bbox.center())
+        .filter(|&(lat, lon)| {
+            let (x, y) = projection.to_ndc(lat, lon);
+            x.abs() <= 1.0 && y.abs() <= 1.0
+        });
+
+    for (lat, lon) in centers.take(MAX_POI_ICONS) {
+        let (x, y) = projection.to_local(lat, lon);
+        let base_index = vertices.len() as u16;
+
+        // Counter clockwise from the bottom left corner, with the top of the image on top of the screen
+        for (corner_x, corner_y, u, v) in [(-1.0, -1.0, 0.0, 1.0), (1.0, -1.0, 1.0, 1.0), (1.0, 1.0, 1.0, 0.0), (-1.0, 1.0, 0.0, 0.0)] {
+            let (offset_x, offset_y) = projection.ndc_offset_to_local((corner_x * half_size_ndc.0, corner_y * half_size_ndc.1));
+            vertices.push(IconVertex {
+                position: [x + offset_x, y + offset_y, 0.0],
+                tex_coords: [u, v],
             });
+        }
+        indices.extend_from_slice(&[
+            base_index, base_index + 1, base_index + 2,
+            base_index, base_index + 2, base_index + 3,
+        ]);
+    }
+
+    (vertices, indices)
+}
+
+// Markers are diamonds of this many pixels in their own color, with an outline setting them
+// apart from the icons of points of interest. They are fetched for the viewport grown by
+// this fraction on every side, see `expand_bbox`.
+const MARKER_SIZE_PX: f32 = 16.0;
+const MARKER_OUTLINE_PX: f32 = 2.0;
+const SELECTED_MARKER_OUTLINE_PX: f32 = 4.0;
+const MARKER_OUTLINE_COLOR: &str = "#ffffff";
+const SELECTED_MARKER_OUTLINE_COLOR: &str = "#101010";
+const MARKER_FETCH_MARGIN: f64 = 0.5;
+
+/// Adds the colors of markers to the palette, as overlay colors the theme keeps.
+///
+/// ## Returns
+/// * Whether a color was new, so the palette uniform has to be written again.
+fn add_marker_colors(palette: &mut Palette, markers: &[Marker]) -> bool {
+    let mut added = false;
+    for color in markers.iter().filter_map(|marker| parse_hex_color(&marker.color)) {
+        if !palette.contains(color, false) {
+            palette.add(color, false);
+            added = true;
+        }
+    }
+    added
+}
+
+/// Generates a diamond at every marker in view, keeping its size in pixels at every zoom
+/// level like the icons of points of interest. The selected marker gets a thicker outline.
+///
+/// ## Arguments
+/// * `size_px` - The size of the window, which the markers are sized for.
+fn generate_marker_vertices_and_indices(markers: &[Marker], selected: Option<i64>, palette: &Palette, view: &BBox, size_px: (u32, u32)) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let projection = Projection::for_viewport(view);
+    let px = (2.0 / size_px.0.max(1) as f32, 2.0 / size_px.1.max(1) as f32);
+
+    for marker in markers.iter().take(MAX_POI_ICONS / 2) {
+        let (x, y) = projection.to_ndc(marker.lat, marker.lon);
+        if x.abs() > 1.0 || y.abs() > 1.0 {
+            continue;
+        }
+
+        let (outline_px, outline_color) = if selected == Some(marker.id) {
+            (SELECTED_MARKER_OUTLINE_PX, SELECTED_MARKER_OUTLINE_COLOR)
+        } else {
+            (MARKER_OUTLINE_PX, MARKER_OUTLINE_COLOR)
+        };
+        let color = parse_hex_color(&marker.color).map_or(0, |color| palette.index_of(color, false));
+
+        // The outline is a larger diamond below the marker
+        let (center_x, center_y) = projection.to_local(marker.lat, marker.lon);
+        for (half_size_px, color) in [(MARKER_SIZE_PX / 2.0 + outline_px, overlay_color(palette, outline_color)), (MARKER_SIZE_PX / 2.0, color)] {
+            let base_index = vertices.len() as u16;
+
+            // Counter clockwise from the bottom corner
+            for (corner_x, corner_y) in [(0.0, -1.0), (1.0, 0.0), (0.0, 1.0), (-1.0, 0.0)] {
+                let (offset_x, offset_y) = projection.ndc_offset_to_local((corner_x * half_size_px * px.0, corner_y * half_size_px * px.1));
+                vertices.push(Vertex { position: [center_x + offset_x, center_y + offset_y, 0.0], palette_index: color, shade: 1.0 });
+            }
+            indices.extend_from_slice(&[
+                base_index, base_index + 1, base_index + 2,
+                base_index, base_index + 2, base_index + 3,
+            ]);
+        }
+    }
+
+    (vertices, indices)
+}
+
+// Where no way is in view its nodes are drawn as squares of this color and size, which
+// stays the same in pixels at every zoom level like the markers
+const NODE_DOT_COLOR: &str = "#2f5d9e";
+const NODE_DOT_SIZE_PX: f32 = 4.0;
+
+/// The file an element was imported from, for telling what is at the cursor.
+async fn source_of(pool: &Pool<Sqlite>, maps_type: &MapsType, id: i64) -> String {
+    match fetch_source_filename(pool, maps_type, id).await {
+        Ok(Some(filename)) => filename,
+        Ok(None) => "unknown".to_string(),
+        Err(error) => {
+            warn!(maps_type = maps_type.as_str(), id, %error, "could not look up the source file");
+            "unknown".to_string()
+        }
+    }
+}
+
+/// Prints the way and the place at a point, and the road nearest to it. Lookups that fail
+/// are reported to the status line through `events`.
+///
+/// ## Arguments
+/// * `way_hit` - The id, name and distance in meters of the loaded way at the point, if any.
+///
+/// ## Returns
+/// * The element at the point: the way hit, else the place found, or `None` if there is neither.
+async fn print_place_at(pool: &Pool<Sqlite>, (lat, lon): (f64, f64), way_hit: Option<(i64, Option<String>, f64)>, events: &EventSender) -> Option<(MapsType, i64)> {
+    let mut element = None;
+    if let Some((way_id, name, distance_m)) = way_hit {
+        let name = name.as_deref().unwrap_or("unnamed");
+        let source = source_of(pool, &MapsType::Way, way_id).await;
+        info!(way_id, name, distance_m = distance_m.round(), source, "way at cursor");
+        element = Some((MapsType::Way, way_id));
+    }
+
+    match reverse_geocode(pool, lat, lon).await {
+        Ok(Some(place)) => {
+            let name = place.name.as_deref().unwrap_or("unnamed");
+            let address: Vec<String> = place.address.iter().map(|tag| format!("{}={}", tag.key, tag.value)).collect();
+            let source = source_of(pool, &place.maps_type, place.id).await;
+            info!(
+                lat, lon, maps_type = place.maps_type.as_str(), id = place.id, name,
+                distance_m = place.distance_m.round(), address = %address.join(", "), source,
+                "place at cursor",
+            );
+            element.get_or_insert((place.maps_type, place.id));
+        }
+        Ok(None) => info!(lat, lon, "nothing found at cursor"),
+        Err(error) => {
+            error!(lat, lon, %error, "could not look up the place at the cursor");
+            events.send(AppEvent::Status(StatusLevel::Error, format!("Could not look up the place at the cursor: {}", error)));
+        }
+    }
+
+    match snap_to_road(pool, lat, lon, DEFAULT_SNAP_DISTANCE_M).await {
+        Ok(Some(snap)) => info!(way_id = snap.way_id, segment = snap.segment_index, lat = snap.lat, lon = snap.lon, distance_m = snap.distance_m, "nearest road"),
+        Ok(None) => info!(max_distance_m = DEFAULT_SNAP_DISTANCE_M, "no road near cursor"),
+        Err(error) => {
+            error!(lat, lon, %error, "could not snap the cursor to a road");
+            events.send(AppEvent::Status(StatusLevel::Error, format!("Could not snap the cursor to a road: {}", error)));
+        }
+    }
+
+    element
+}
+
+/// Fetches the tags and metadata of an element for the inspection panel.
+///
+/// ## Returns
+/// * What to show, or `None` if there is no element or it is not stored.
+async fn fetch_inspection(pool: &Pool<Sqlite>, element: Option<&(MapsType, i64)>) -> Result<Option<Inspection>, sqlx::Error> {
+    match element {
+        Some((MapsType::Node, id)) => Ok(fetch_node_by_id(pool, *id).await?.as_ref().map(Inspection::of_node)),
+        Some((MapsType::Way, id)) => Ok(fetch_way_by_id(pool, *id).await?.as_ref().map(Inspection::of_way)),
+        Some((MapsType::Relation, id)) => Ok(fetch_relation_by_id(pool, *id).await?.as_ref().map(Inspection::of_relation)),
+        Some((MapsType::Other(_), _)) | None => Ok(None),
+    }
+}
+
+/// Fetches the nodes in the viewport to draw as dots, at most `max` of them evenly spread
+/// over the ids, see `node_dot_stride`.
+///
+/// ## Returns
+/// * The nodes picked and how many nodes are in the viewport.
+async fn fetch_node_dots(pool: &Pool<Sqlite>, view: &BBox, max: usize) -> Result<(Vec<SimpleNode>, usize), sqlx::Error> {
+    let total = count_nodes_in_bbox(pool, view).await?;
+    if total == 0 {
+        return Ok((Vec::new(), 0));
+    }
+
+    let nodes = fetch_nodes_in_bbox(pool, view, node_dot_stride(total, max), max).await?;
+    Ok((nodes, total))
+}
+
+/// The status message telling that the nodes in view are drawn as there are no ways.
+fn node_dots_message(shown: usize, total: usize) -> String {
+    if shown < total {
+        format!("No ways in view, showing {} of its {} nodes as dots", shown, total)
+    } else {
+        format!("No ways in view, showing its {} nodes as dots", total)
+    }
+}
+
+/// Generates a square at every node, keeping its size in pixels at every zoom level.
+///
+/// ## Arguments
+/// * `size_px` - The size of the window, which the dots are sized for.
+fn generate_node_dot_vertices_and_indices(nodes: &[SimpleNode], palette: &Palette, view: &BBox, size_px: (u32, u32)) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::with_capacity(nodes.len() * 4);
+    let mut indices = Vec::with_capacity(nodes.len() * 6);
+
+    let projection = Projection::for_viewport(view);
+    let color = overlay_color(palette, NODE_DOT_COLOR);
+    let (half_x, half_y) = projection.ndc_offset_to_local((NODE_DOT_SIZE_PX / size_px.0.max(1) as f32, NODE_DOT_SIZE_PX / size_px.1.max(1) as f32));
+
+    // Beyond that many the 16 bit indices would wrap
+    for node in nodes.iter().take(MAX_NODE_DOTS) {
+        let (center_x, center_y) = projection.to_local(node.lat, node.lon);
+        let base_index = vertices.len() as u16;
+
+        // Counter clockwise from the bottom left corner
+        for (corner_x, corner_y) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            vertices.push(Vertex { position: [center_x + corner_x * half_x, center_y + corner_y * half_y, 0.0], palette_index: color, shade: 1.0 });
+        }
+        indices.extend_from_slice(&[
+            base_index, base_index + 1, base_index + 2,
+            base_index, base_index + 2, base_index + 3,
+        ]);
+    }
+
+    (vertices, indices)
+}
+
+// GPS tracks are drawn as lines of this color and width on top of every way.
+const GPS_TRACK_COLOR: &str = "#e8178a";
+const GPS_TRACK_WIDTH_M: f64 = 4.0;
+
+fn generate_gps_track_vertices_and_indices(gps_tracks: &[GpsTrack], palette: &Palette, view: &BBox, chunks: &mut ChunkBuilder) {
+    let clip_bbox = view.expand(CLIP_MARGIN);
+    let color = overlay_color(palette, GPS_TRACK_COLOR);
+    let thickness = (GPS_TRACK_WIDTH_M / meters_per_ndc_unit(view)) as f32;
+    let projection = Projection::for_viewport(view);
+
+    for segment in gps_tracks.iter().flat_map(|track| &track.segments) {
+        let points: Vec<(f64, f64)> = segment.iter().map(|point| (point.lat, point.lon)).collect();
+
+        for part in clip_polyline_to_bbox(&points, &clip_bbox) {
+            for geometry in line_geometries(&part, &projection, thickness, NO_LINE_LOD, color, MapLayer::Other, chunks.vertex_limit) {
+                chunks.push(geometry);
+            }
+        }
+    }
+}
+
+// Beyond the imported data the map is shaded in a neutral gray, which stands apart from the
+// background of every theme, with a thin line along the edge of the data.
+const OUTSIDE_DATA_COLOR: &str = "#3b3f45";
+const DATA_EDGE_COLOR: &str = "#9aa0a6";
+const DATA_EDGE_WIDTH_NDC: f32 = 0.004;
+const NO_DATA_MESSAGE: &str = "No data loaded for this area — import an extract covering it";
+
+/// How much the viewport fitted to the data reaches beyond it on every side, as a fraction
+/// of its height and width.
+const VIEWPORT_FIT_MARGIN: f64 = 0.05;
+/// The viewport fitted when there is no data, all of the world Web Mercator can show.
+const WORLD_VIEWPORT: Viewport = BBox { min_lat: -85.0, max_lat: 85.0, min_lon: -180.0, max_lon: 180.0 };
+
+/// The viewport showing all of the imported data with a margin, or the world without any
+/// data, at the aspect ratio of the window.
+///
+/// ## Arguments
+/// * `extent` - The box around the imported data, if any.
+/// * `size` - The `(width, height)` of the window in pixels.
+fn fit_viewport(extent: Option<BBox>, size: (u32, u32)) -> Viewport {
+    let bbox = match extent {
+        Some(extent) => extent.expand(VIEWPORT_FIT_MARGIN),
+        None => WORLD_VIEWPORT,
+    };
+    fit_bbox_to_window(&bbox, size)
+}
+
+/// Returns true if the viewport lies completely outside the imported data. Without any data
+/// there is no edge to be outside of.
+fn is_outside_data(extent: Option<BBox>, view: &BBox) -> bool {
+    extent.is_some_and(|extent| !extent.intersects(view))
+}
+
+/// Generates the shading of the viewport beyond the imported data, as up to four rectangles
+/// around the extent, and the edge of the extent.
+fn generate_data_extent_vertices_and_indices(extent: Option<BBox>, palette: &Palette, view: &BBox, chunks: &mut ChunkBuilder) {
+    let Some(extent) = extent else {
+        return;
+    };
+
+    let clip_bbox = view.expand(CLIP_MARGIN);
+    let BBox { min_lat, max_lat, min_lon, max_lon } = clip_bbox;
+    let BBox { min_lat: data_min_lat, max_lat: data_max_lat, min_lon: data_min_lon, max_lon: data_max_lon } = extent;
+    let projection = Projection::for_viewport(view);
+    let shade = overlay_color(palette, OUTSIDE_DATA_COLOR);
+
+    // North and south of the data across the whole width, west and east of it in between
+    let band_bottom = data_min_lat.clamp(min_lat, max_lat);
+    let band_top = data_max_lat.clamp(min_lat, max_lat);
+    let rectangles = [
+        (band_top, min_lon, max_lat, max_lon),
+        (min_lat, min_lon, band_bottom, max_lon),
+        (band_bottom, min_lon, band_top, data_min_lon.clamp(min_lon, max_lon)),
+        (band_bottom, data_max_lon.clamp(min_lon, max_lon), band_top, max_lon),
+    ];
+
+    for (bottom, left, top, right) in rectangles {
+        if bottom >= top || left >= right {
+            continue;
+        }
+
+        // Clockwise on the ground, which the north-down projection turns counter clockwise on screen
+        let mut geometry = WayGeometry::new(MapLayer::Other);
+        generate_polygon_vertices_and_indices(&[(bottom, left), (top, left), (top, right), (bottom, right)], &projection, shade, &mut geometry.vertices, &mut geometry.indices);
+        chunks.push(geometry);
+    }
+
+    let edge = [
+        (data_min_lat, data_min_lon), (data_min_lat, data_max_lon), (data_max_lat, data_max_lon),
+        (data_max_lat, data_min_lon), (data_min_lat, data_min_lon),
+    ];
+    let color = overlay_color(palette, DATA_EDGE_COLOR);
+    for part in clip_polyline_to_bbox(&edge, &clip_bbox) {
+        for geometry in line_geometries(&part, &projection, DATA_EDGE_WIDTH_NDC, NO_LINE_LOD, color, MapLayer::Other, chunks.vertex_limit) {
+            chunks.push(geometry);
+        }
+    }
+}
+
+// The area reachable within this many minutes is shown in this color, with the map
+// showing through it
+// The active route is drawn boldly, its alternatives thinner and muted beneath it
+const ROUTE_COLOR: &str = "#1d4ed8";
+const ROUTE_WIDTH_NDC: f32 = 0.016;
+const ROUTE_ALTERNATIVE_COLOR: &str = "#8da2c0";
+const ROUTE_ALTERNATIVE_WIDTH_NDC: f32 = 0.01;
+
+/// Generates the routes found, the active one over the others.
+fn generate_route_vertices_and_indices(routes: &[Route], active: usize, palette: &Palette, view: &BBox) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let projection = Projection::for_viewport(view);
+
+    // Overlays are drawn in order, so the active route goes last to stay on top
+    let order = (0..routes.len()).filter(|&index| index != active).chain((active < routes.len()).then_some(active));
+    for index in order {
+        let points: Vec<(f64, f64)> = routes[index].nodes.iter().map(|node| (node.lat, node.lon)).collect();
+        let (color, width) = if index == active { (ROUTE_COLOR, ROUTE_WIDTH_NDC) } else { (ROUTE_ALTERNATIVE_COLOR, ROUTE_ALTERNATIVE_WIDTH_NDC) };
+        generate_line_vertices_and_indices(&points, &projection, width, NO_LINE_LOD, overlay_color(palette, color), &mut vertices, &mut indices);
+    }
+
+    (vertices, indices)
+}
+
+const ISOCHRONE_MINUTES: f64 = 10.0;
+const ISOCHRONE_COLOR: &str = "#3a86ff";
+const ISOCHRONE_OPACITY: f32 = 0.35;
+
+/// The translucent color of the reachable area, drawn with the translucent overlay pipeline.
+fn isochrone_color() -> [f32; 4] {
+    let [r, g, b, _] = parse_hex_color(ISOCHRONE_COLOR).unwrap_or(Style::default().color);
+    [r, g, b, ISOCHRONE_OPACITY]
+}
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+/// Generates the area reachable from a point as a rectangle for every run of its cells in
+/// view, see `ReachGrid::filled_runs`. The rectangles do not overlap, so the area is
+/// equally translucent everywhere.
+fn generate_isochrone_vertices_and_indices(grid: &ReachGrid, palette: &Palette, view: &BBox) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+    let BBox { min_lat, max_lat, min_lon, max_lon } = view.expand(CLIP_MARGIN);
+    let projection = Projection::for_viewport(view);
+    let color = palette.index_of(isochrone_color(), false);
 
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    for BBox { min_lat: bottom, max_lat: top, min_lon: left, max_lon: right } in grid.filled_runs() {
+        // Clipped to the viewport, so the vertices stay close to its center
+        let (bottom, left, top, right) = (bottom.max(min_lat), left.max(min_lon), top.min(max_lat), right.min(max_lon));
+        if bottom >= top || left >= right {
+            continue;
+        }
+        if vertices.len() + 4 > u16::MAX as usize {
+            warn!("the reachable area has too many rectangles to draw them all");
+            break;
         }
 
-        self.queue.submit(iter::once(encoder.finish()));
-        output.present();
+        // Clockwise on the ground, which the north-down projection turns counter clockwise on screen
+        generate_polygon_vertices_and_indices(&[(bottom, left), (top, left), (top, right), (bottom, right)], &projection, color, &mut vertices, &mut indices);
+    }
 
-        Ok(())
+    (vertices, indices)
+}
+
+// The placeholders are faint, so the ways drawn over them as they arrive stand out
+const SKELETON_COLOR: &str = "#8d99ae";
+const SKELETON_OPACITY: f32 = 0.18;
+
+/// The translucent color of the placeholders, drawn with the translucent overlay pipeline.
+fn skeleton_color() -> [f32; 4] {
+    let [r, g, b, _] = parse_hex_color(SKELETON_COLOR).unwrap_or(Style::default().color);
+    [r, g, b, SKELETON_OPACITY]
+}
+
+/// Generates a rectangle for the bounding box of every way waiting to be drawn, see
+/// `Skeleton`. Overlapping rectangles add up, so where many ways wait the map looks denser.
+fn generate_skeleton_vertices_and_indices<'a>(placeholders: impl Iterator<Item = &'a WayBbox>, palette: &Palette, view: &BBox) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let BBox { min_lat, max_lat, min_lon, max_lon } = view.expand(CLIP_MARGIN);
+    let projection = Projection::for_viewport(view);
+    let color = palette.index_of(skeleton_color(), false);
+
+    for placeholder in placeholders {
+        let BBox { min_lat: bottom, max_lat: top, min_lon: left, max_lon: right } = placeholder.bbox;
+        // Clipped to the viewport like the reachable area. A point or a straight line north to
+        // south has an empty box, which is left out
+        let (bottom, left, top, right) = (bottom.max(min_lat), left.max(min_lon), top.min(max_lat), right.min(max_lon));
+        if bottom >= top || left >= right {
+            continue;
+        }
+        if vertices.len() + 4 > u16::MAX as usize {
+            warn!("too many placeholders to draw them all");
+            break;
+        }
+
+        generate_polygon_vertices_and_indices(&[(bottom, left), (top, left), (top, right), (bottom, right)], &projection, color, &mut vertices, &mut indices);
+    }
+
+    (vertices, indices)
+}
+
+// The lines of the graticule are thin, so they do not hide the map below them
+const GRATICULE_COLOR: &str = "#5a6f8c";
+const GRATICULE_WIDTH_NDC: f32 = 0.003;
+
+/// Generates the lines of latitude and longitude across the viewport, spaced by the span of
+/// the viewport, see `graticule_step`.
+fn generate_graticule_vertices_and_indices(palette: &Palette, view: &BBox) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let BBox { min_lat, max_lat, min_lon, max_lon } = *view;
+    let step = graticule_step((max_lat - min_lat).max(max_lon - min_lon));
+    let projection = Projection::for_viewport(view);
+    let color = overlay_color(palette, GRATICULE_COLOR);
+
+    let lines = graticule_lines(view, step);
+    debug!(step, lines = lines.len(), "graticule");
+    for line in lines {
+        generate_line_vertices_and_indices(&[line.from, line.to], &projection, GRATICULE_WIDTH_NDC, NO_LINE_LOD, color, &mut vertices, &mut indices);
+    }
+
+    (vertices, indices)
+}
+
+// The measurement line is drawn dashed, with dash and gap lengths given as a fraction
+// of the viewport width so the pattern looks the same at every zoom level.
+const MEASURE_COLOR: &str = "#d62828";
+const MEASURE_WIDTH_NDC: f32 = 0.008;
+const MEASURE_DASH_NDC: f64 = 0.03;
+const MEASURE_GAP_NDC: f64 = 0.015;
+
+fn generate_measurement_vertices_and_indices(points: &[(f64, f64)], palette: &Palette, view: &BBox) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let color = overlay_color(palette, MEASURE_COLOR);
+    let meters_per_ndc = meters_per_ndc_unit(view);
+    let projection = Projection::for_viewport(view);
+
+    for dash in dash_polyline(points, MEASURE_DASH_NDC * meters_per_ndc, MEASURE_GAP_NDC * meters_per_ndc) {
+        generate_line_vertices_and_indices(&dash, &projection, MEASURE_WIDTH_NDC, NO_LINE_LOD, color, &mut vertices, &mut indices);
+    }
+
+    (vertices, indices)
+}
+
+// The scale bar sits in the bottom left corner and is at most this fraction of the viewport wide.
+const SCALE_BAR_MAX_FRACTION: f64 = 0.3;
+const SCALE_BAR_MARGIN_PX: f32 = 20.0;
+const SCALE_BAR_THICKNESS_PX: f32 = 4.0;
+const SCALE_BAR_TICK_HEIGHT_PX: f32 = 12.0;
+const SCALE_BAR_COLOR: &str = "#202020";
+
+/// Generates the scale bar: a horizontal bar with a tick at either end, in screen space.
+///
+/// ## Returns
+/// * The vertices, the indices and the length in meters the bar stands for.
+fn generate_scale_bar_vertices_and_indices(palette: &Palette, view: &BBox, size: winit::dpi::PhysicalSize<u32>) -> (Vec<Vertex>, Vec<u16>, f64) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let meters_per_ndc = meters_per_ndc_unit(view);
+    // NDC spans two units across the viewport
+    let length_m = round_scale_length(2.0 * meters_per_ndc * SCALE_BAR_MAX_FRACTION);
+    if length_m <= 0.0 || size.width == 0 || size.height == 0 {
+        return (vertices, indices, length_m);
+    }
+
+    // Sizes are given in pixels, so the bar looks the same in every window size
+    let px_x = 2.0 / size.width as f32;
+    let px_y = 2.0 / size.height as f32;
+    let color = overlay_color(palette, SCALE_BAR_COLOR);
+
+    let left = -1.0 + SCALE_BAR_MARGIN_PX * px_x;
+    let right = left + (length_m / meters_per_ndc) as f32;
+    let bottom = -1.0 + SCALE_BAR_MARGIN_PX * px_y;
+    let half_tick = SCALE_BAR_THICKNESS_PX * px_x / 2.0;
+
+    generate_rectangle_vertices_and_indices(left, bottom, right, bottom + SCALE_BAR_THICKNESS_PX * px_y, color, &mut vertices, &mut indices);
+    for x in [left, right] {
+        generate_rectangle_vertices_and_indices(x - half_tick, bottom, x + half_tick, bottom + SCALE_BAR_TICK_HEIGHT_PX * px_y, color, &mut vertices, &mut indices);
+    }
+
+    (vertices, indices, length_m)
+}
+
+// The status bar spans the top edge of the window, its color tells whether all is well.
+const STATUS_BAR_HEIGHT_PX: f32 = 4.0;
+const STATUS_IDLE_COLOR: &str = "#2a9d3f";
+const STATUS_BUSY_COLOR: &str = "#e9b824";
+const STATUS_ERROR_COLOR: &str = "#d62828";
+const WINDOW_TITLE: &str = "GoogleMapsClone";
+
+/// Generates the status bar in screen space: green while idle or after a finished task,
+/// yellow while a task is running and red after an error.
+fn generate_status_bar_vertices_and_indices(palette: &Palette, level: Option<StatusLevel>, size: winit::dpi::PhysicalSize<u32>) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    if size.width == 0 || size.height == 0 {
+        return (vertices, indices);
+    }
+
+    let color = match level {
+        None | Some(StatusLevel::Info) => STATUS_IDLE_COLOR,
+        Some(StatusLevel::Busy) => STATUS_BUSY_COLOR,
+        Some(StatusLevel::Error) => STATUS_ERROR_COLOR,
+    };
+    let color = overlay_color(palette, color);
+
+    let bottom = 1.0 - STATUS_BAR_HEIGHT_PX * 2.0 / size.height as f32;
+    generate_rectangle_vertices_and_indices(-1.0, bottom, 1.0, 1.0, color, &mut vertices, &mut indices);
+
+    (vertices, indices)
+}
+
+/// The title of the window, which carries the message of the status line until there is
+/// text rendering to show it in the window itself.
+fn status_title(status: &StatusLine) -> String {
+    match status.current() {
+        Some(message) => format!("{} - {}", WINDOW_TITLE, message.text),
+        None => WINDOW_TITLE.to_string(),
+    }
+}
+
+// The inspection panel runs down the left edge, between the status bar and the scale bar.
+// A thumb on its right edge shows which of the pages of a long tag list is shown.
+const INSPECTION_PANEL_WIDTH_PX: f32 = 320.0;
+const INSPECTION_PANEL_MARGIN_PX: f32 = 20.0;
+const INSPECTION_PANEL_BOTTOM_PX: f32 = SCALE_BAR_MARGIN_PX + SCALE_BAR_TICK_HEIGHT_PX + INSPECTION_PANEL_MARGIN_PX;
+const INSPECTION_THUMB_WIDTH_PX: f32 = 4.0;
+const INSPECTION_PANEL_COLOR: &str = "#fafaf7";
+const INSPECTION_THUMB_COLOR: &str = "#6c6c6c";
+
+/// Returns the inspection panel as `(left, top, width, height)` in pixels, or `None` if the
+/// window is too small to fit a line of it.
+fn inspection_panel_rect(size: winit::dpi::PhysicalSize<u32>) -> Option<(f32, f32, f32, f32)> {
+    let top = STATUS_BAR_HEIGHT_PX + INSPECTION_PANEL_MARGIN_PX;
+    let height = size.height as f32 - top - INSPECTION_PANEL_BOTTOM_PX;
+    if (size.width as f32) < INSPECTION_PANEL_WIDTH_PX + 2.0 * INSPECTION_PANEL_MARGIN_PX || height < LINE_HEIGHT_PX as f32 {
+        return None;
+    }
+
+    Some((INSPECTION_PANEL_MARGIN_PX, top, INSPECTION_PANEL_WIDTH_PX, height))
+}
+
+/// The text fitting into the inspection panel, a single character if the window is too small for it.
+fn inspection_panel_size(size: winit::dpi::PhysicalSize<u32>) -> PanelSize {
+    let (width, height) = inspection_panel_rect(size).map_or((0, 0), |(_, _, width, height)| (width as u32, height as u32));
+    PanelSize::from_pixels(width, height)
+}
+
+/// Generates the inspection panel in screen space, with a thumb on its right edge showing
+/// the page shown if there are several.
+///
+/// ## Arguments
+/// * `page` - The page shown and the number of pages, or `None` to generate no panel.
+fn generate_inspection_panel_vertices_and_indices(palette: &Palette, page: Option<(usize, usize)>, size: winit::dpi::PhysicalSize<u32>) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let (Some((page, page_count)), Some((left, top, width, height))) = (page, inspection_panel_rect(size)) else {
+        return (vertices, indices);
+    };
+
+    // Pixels grow downwards from the top left, NDC grows upwards from the center
+    let px_x = 2.0 / size.width as f32;
+    let px_y = 2.0 / size.height as f32;
+    let ndc_left = -1.0 + left * px_x;
+    let ndc_right = -1.0 + (left + width) * px_x;
+    let ndc_top = 1.0 - top * px_y;
+    let ndc_bottom = 1.0 - (top + height) * px_y;
+    generate_rectangle_vertices_and_indices(ndc_left, ndc_bottom, ndc_right, ndc_top, overlay_color(palette, INSPECTION_PANEL_COLOR), &mut vertices, &mut indices);
+
+    if page_count > 1 {
+        let thumb_height = height / page_count as f32;
+        let thumb_top = ndc_top - page as f32 * thumb_height * px_y;
+        let thumb_left = ndc_right - INSPECTION_THUMB_WIDTH_PX * px_x;
+        generate_rectangle_vertices_and_indices(thumb_left, thumb_top - thumb_height * px_y, ndc_right, thumb_top, overlay_color(palette, INSPECTION_THUMB_COLOR), &mut vertices, &mut indices);
+    }
+
+    (vertices, indices)
+}
+
+// The hovered way is drawn over the map in this color, with a tooltip describing it next to the cursor
+const HOVER_COLOR: &str = "#00b4d8";
+const HOVER_WIDTH_NDC: f32 = 0.01;
+const TOOLTIP_COLOR: &str = "#fafaf7";
+const TOOLTIP_BORDER_COLOR: &str = "#202020";
+const TOOLTIP_BORDER_PX: f32 = 1.0;
+
+/// Generates the highlight of the hovered way, a line along it, areas along their outline.
+///
+/// ## Arguments
+/// * `way` - The hovered way, or `None` to generate no highlight.
+fn generate_hover_vertices_and_indices(way: Option<&RenderableWay>, palette: &Palette, view: &BBox) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    if let Some(way) = way {
+        let projection = Projection::for_viewport(view);
+        generate_line_vertices_and_indices(&way.coords, &projection, HOVER_WIDTH_NDC, NO_LINE_LOD, overlay_color(palette, HOVER_COLOR), &mut vertices, &mut indices);
+    }
+    (vertices, indices)
+}
+
+/// Generates the tooltip in screen space, a box sized for its text next to the cursor.
+///
+/// ## Arguments
+/// * `tooltip` - The text and the cursor in pixels, or `None` to generate no tooltip.
+fn generate_tooltip_vertices_and_indices(palette: &Palette, tooltip: Option<(&str, PhysicalPosition<f64>)>, size: winit::dpi::PhysicalSize<u32>) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let Some((text, cursor)) = tooltip else {
+        return (vertices, indices);
+    };
+    if size.width == 0 || size.height == 0 {
+        return (vertices, indices);
+    }
+
+    let width = (text.chars().count() as u32 * CHAR_WIDTH_PX) as f32 + 2.0 * TOOLTIP_PADDING_PX;
+    let height = LINE_HEIGHT_PX as f32 + 2.0 * TOOLTIP_PADDING_PX;
+    let (left, top) = tooltip_anchor((cursor.x as f32, cursor.y as f32), (width, height), (size.width as f32, size.height as f32));
+
+    // Pixels grow downwards from the top left, NDC grows upwards from the center
+    let px_x = 2.0 / size.width as f32;
+    let px_y = 2.0 / size.height as f32;
+    let ndc_left = -1.0 + left * px_x;
+    let ndc_right = -1.0 + (left + width) * px_x;
+    let ndc_top = 1.0 - top * px_y;
+    let ndc_bottom = 1.0 - (top + height) * px_y;
+    // The border is a box behind the tooltip, peeking out around it
+    generate_rectangle_vertices_and_indices(ndc_left, ndc_bottom, ndc_right, ndc_top, overlay_color(palette, TOOLTIP_BORDER_COLOR), &mut vertices, &mut indices);
+    generate_rectangle_vertices_and_indices(
+        ndc_left + TOOLTIP_BORDER_PX * px_x, ndc_bottom + TOOLTIP_BORDER_PX * px_y, ndc_right - TOOLTIP_BORDER_PX * px_x, ndc_top - TOOLTIP_BORDER_PX * px_y,
+        overlay_color(palette, TOOLTIP_COLOR), &mut vertices, &mut indices,
+    );
+
+    (vertices, indices)
+}
+
+// The minimap is a square inset in the top right corner showing all loaded data.
+// It only shows the ways giving a rough orientation, simplified to this fraction of the extent.
+const MINIMAP_SIZE_PX: f32 = 200.0;
+const MINIMAP_MARGIN_PX: f32 = 20.0;
+const MINIMAP_SIMPLIFY_FRACTION: f64 = 0.005;
+const MINIMAP_LINE_WIDTH_NDC: f32 = 0.02;
+const MINIMAP_OUTLINE_WIDTH_NDC: f32 = 0.02;
+const MINIMAP_BACKGROUND_COLOR: &str = "#f2efe9";
+const MINIMAP_COASTLINE_COLOR: &str = "#4a80b4";
+const MINIMAP_MOTORWAY_COLOR: &str = "#e07a3f";
+const MINIMAP_CAMERA_COLOR: &str = "#d62828";
+
+/// Computes the extent of the loaded data.
+///
+/// ## Returns
+/// * The box around every way, or `None` if there are no ways or they all lie on a single
+///   line of latitude or longitude.
+fn data_extent(renderable_ways: &[RenderableWay]) -> Option<BBox> {
+    let points: Vec<(f64, f64)> = renderable_ways.iter()
+        .flat_map(|way| way.coords.iter().copied())
+        .collect();
+
+    // An extent without area can not be mapped onto the minimap
+    let extent = bbox_of_points(&points)?;
+    if extent.min_lat == extent.max_lat || extent.min_lon == extent.max_lon {
+        return None;
+    }
+
+    Some(extent)
+}
+
+/// The camera showing the minimap geometry generated by `generate_minimap_vertices_and_indices`.
+fn minimap_camera_uniform(extent: Option<BBox>) -> CameraUniform {
+    match extent {
+        Some(extent) => {
+            let projection = Projection::for_viewport(&extent);
+            CameraUniform::new(&projection, &projection)
+        }
+        None => CameraUniform::SCREEN,
+    }
+}
+
+/// Returns the minimap inset as `(left, top, width, height)` in pixels, or `None` if the
+/// window is too small to fit it.
+fn minimap_rect(size: winit::dpi::PhysicalSize<u32>) -> Option<(f32, f32, f32, f32)> {
+    let needed = MINIMAP_SIZE_PX + 2.0 * MINIMAP_MARGIN_PX;
+    if (size.width as f32) < needed || (size.height as f32) < needed {
+        return None;
+    }
+
+    Some((size.width as f32 - MINIMAP_MARGIN_PX - MINIMAP_SIZE_PX, MINIMAP_MARGIN_PX, MINIMAP_SIZE_PX, MINIMAP_SIZE_PX))
+}
+
+/// Maps the viewport onto the minimap.
+///
+/// ## Arguments
+/// * `view` - The viewport.
+/// * `extent` - The box around the data shown on the minimap.
+///
+/// ## Returns
+/// * The `(left, bottom, right, top)` edges of the viewport in the NDC of the minimap,
+///   clamped to the minimap so a viewport beyond the data still shows at its edge.
+fn viewport_to_minimap(view: &BBox, extent: &BBox) -> (f32, f32, f32, f32) {
+    let projection = Projection::for_viewport(extent);
+    let (x0, y0) = projection.to_ndc(view.max_lat, view.min_lon);
+    let (x1, y1) = projection.to_ndc(view.min_lat, view.max_lon);
+
+    (
+        x0.min(x1).clamp(-1.0, 1.0),
+        y0.min(y1).clamp(-1.0, 1.0),
+        x0.max(x1).clamp(-1.0, 1.0),
+        y0.max(y1).clamp(-1.0, 1.0),
+    )
+}
+
+/// Generates the minimap background, covering the whole inset.
+fn generate_minimap_background_vertices_and_indices(palette: &Palette) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let background = overlay_color(palette, MINIMAP_BACKGROUND_COLOR);
+    generate_rectangle_vertices_and_indices(-1.0, -1.0, 1.0, 1.0, background, &mut vertices, &mut indices);
+
+    (vertices, indices)
+}
+
+/// Generates the coastline and motorways of the minimap, simplified to a few points each.
+/// They are projected to fit the extent into the minimap inset.
+fn generate_minimap_vertices_and_indices(renderable_ways: &[RenderableWay], palette: &Palette, extent: Option<BBox>) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let Some(extent) = extent else {
+        return (vertices, indices);
+    };
+
+    let projection = Projection::for_viewport(&extent);
+    let coastline = overlay_color(palette, MINIMAP_COASTLINE_COLOR);
+    let motorway = overlay_color(palette, MINIMAP_MOTORWAY_COLOR);
+    let tolerance = (extent.max_lat - extent.min_lat).max(extent.max_lon - extent.min_lon) * MINIMAP_SIMPLIFY_FRACTION;
+
+    for way in renderable_ways {
+        let color = if way.tags.iter().any(|tag| tag.key == "natural" && tag.value == "coastline") {
+            coastline
+        } else if way.tags.iter().any(|tag| tag.key == "highway" && tag.value == "motorway") {
+            motorway
+        } else {
+            continue;
+        };
+
+        let simplified = simplify_polyline(&way.coords, tolerance);
+        generate_line_vertices_and_indices(&simplified, &projection, MINIMAP_LINE_WIDTH_NDC, NO_LINE_LOD, color, &mut vertices, &mut indices);
+    }
+
+    (vertices, indices)
+}
+
+/// Generates the outline of the viewport on the minimap, as four thin rectangles.
+fn generate_minimap_camera_vertices_and_indices(palette: &Palette, view: &BBox, extent: Option<BBox>) -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+
+    let Some(extent) = extent else {
+        return (vertices, indices);
+    };
+
+    let color = overlay_color(palette, MINIMAP_CAMERA_COLOR);
+    let (left, bottom, right, top) = viewport_to_minimap(view, &extent);
+    let half = MINIMAP_OUTLINE_WIDTH_NDC / 2.0;
+
+    generate_rectangle_vertices_and_indices(left - half, bottom - half, right + half, bottom + half, color, &mut vertices, &mut indices);
+    generate_rectangle_vertices_and_indices(left - half, top - half, right + half, top + half, color, &mut vertices, &mut indices);
+    generate_rectangle_vertices_and_indices(left - half, bottom - half, left + half, top + half, color, &mut vertices, &mut indices);
+    generate_rectangle_vertices_and_indices(right - half, bottom - half, right + half, top + half, color, &mut vertices, &mut indices);
+
+    (vertices, indices)
+}
+
+/// Adds an axis aligned rectangle given in normalized device coordinates.
+fn generate_rectangle_vertices_and_indices(left: f32, bottom: f32, right: f32, top: f32, palette_index: u32, vertices: &mut Vec<Vertex>, indices: &mut Vec<u16>) {
+    let base_index = vertices.len() as u16;
+
+    for (x, y) in [(left, bottom), (right, bottom), (right, top), (left, top)] {
+        vertices.push(Vertex {
+            position: [x, y, 0.0],
+            palette_index,
+            shade: 1.0,
+        });
+    }
+
+    // Counter clockwise, so the rectangle is not culled
+    indices.extend_from_slice(&[
+        base_index, base_index + 1, base_index + 2,
+        base_index, base_index + 2, base_index + 3,
+    ]);
+}
+
+/// The vertex and index buffer of a small overlay drawn on top of the map, or of the icons.
+struct OverlayBuffers {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+impl OverlayBuffers {
+    fn new<V: bytemuck::Pod>(device: &wgpu::Device, label: &str, vertices: &[V], indices: &[u16]) -> Self {
+        let vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Vertex Buffer", label)),
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        );
+
+        let index_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} Index Buffer", label)),
+                contents: bytemuck::cast_slice(indices),
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+
+        OverlayBuffers {
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+        }
+    }
+
+    fn draw<'pass>(&'pass self, render_pass: &mut wgpu::RenderPass<'pass>) {
+        if self.num_indices == 0 {
+            return;
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+}
+
+// The indices are `u16`, so a chunk of the map can address no more vertices than this.
+const MAX_CHUNK_VERTICES: usize = u16::MAX as usize + 1;
+
+/// The geometry of a single way, or of a piece of a long line, with indices starting at 0.
+struct WayGeometry {
+    layer: MapLayer,
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+}
+
+impl WayGeometry {
+    fn new(layer: MapLayer) -> Self {
+        WayGeometry { layer, vertices: Vec::new(), indices: Vec::new() }
+    }
+}
+
+/// A part of the map small enough to be drawn from one vertex and index buffer.
+///
+/// # Fields
+/// * `vertices` - The vertices of the ways in the chunk.
+/// * `indices` - The indices into `vertices`.
+/// * `layer_ranges` - Which runs of `indices` belong to which `MapLayer`.
+#[derive(Default)]
+struct GeometryChunk {
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+    layer_ranges: Vec<LayerRange>,
+}
+
+/// Packs the geometry of ways into chunks of at most `vertex_limit` vertices, in the order
+/// it is pushed. A way always goes into a single chunk, a new chunk is started when it does
+/// not fit into the current one.
+struct ChunkBuilder {
+    vertex_limit: usize,
+    chunks: Vec<GeometryChunk>,
+}
+
+impl Default for ChunkBuilder {
+    fn default() -> Self {
+        ChunkBuilder::new(MAX_CHUNK_VERTICES)
+    }
+}
+
+impl ChunkBuilder {
+    /// Starts without chunks. The limit is kept between a single quad and `MAX_CHUNK_VERTICES`.
+    fn new(vertex_limit: usize) -> Self {
+        ChunkBuilder { vertex_limit: vertex_limit.clamp(4, MAX_CHUNK_VERTICES), chunks: Vec::new() }
+    }
+
+    /// Appends the geometry of a way to the last chunk, or to a new one if it does not fit.
+    /// Geometry larger than a whole chunk is skipped with a warning.
+    fn push(&mut self, geometry: WayGeometry) {
+        if geometry.vertices.is_empty() {
+            return;
+        }
+        if geometry.vertices.len() > self.vertex_limit {
+            warn!(vertices = geometry.vertices.len(), limit = self.vertex_limit, "skipping a way too large for a chunk");
+            return;
+        }
+
+        let fits = self.chunks.last().is_some_and(|chunk| chunk.vertices.len() + geometry.vertices.len() <= self.vertex_limit);
+        if !fits {
+            self.chunks.push(GeometryChunk::default());
+        }
+        let Some(chunk) = self.chunks.last_mut() else {
+            return;
+        };
+
+        let base_index = chunk.vertices.len() as u32;
+        chunk.vertices.extend(geometry.vertices);
+        chunk.indices.extend(geometry.indices.iter().map(|&index| (base_index + index as u32) as u16));
+        push_layer_range(&mut chunk.layer_ranges, geometry.layer, chunk.indices.len() as u32);
+    }
+
+    /// The chunks packed so far, e.g. to write them before all geometry is pushed.
+    fn chunks(&self) -> &[GeometryChunk] {
+        &self.chunks
+    }
+
+    fn finish(self) -> Vec<GeometryChunk> {
+        self.chunks
+    }
+}
+
+/// The map while `State::tessellate_pending` tessellates it a slice per frame.
+///
+/// # Fields
+/// * `items` - The draw items left, in draw order.
+/// * `tessellation` - How the items are tessellated, for the viewport they were queued for.
+/// * `chunks` - The chunks of the geometry tessellated so far.
+/// * `written` - How many vertices and indices of every chunk are in its buffers already.
+/// * `way_ids` - The ways queued, whose placeholders are replaced once all are drawn.
+struct PendingMap {
+    items: WorkQueue<DrawItem>,
+    tessellation: Tessellation,
+    chunks: ChunkBuilder,
+    written: Vec<(usize, usize)>,
+    way_ids: Vec<i64>,
+}
+
+/// The GPU buffers of one chunk of the map. They are kept when the map is regenerated, and
+/// only replaced when a chunk outgrows them.
+struct ChunkBuffers {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    layer_ranges: Vec<LayerRange>,
+}
+
+/// Creates a buffer to copy into of at least `size` bytes, rounded up to a power of two so
+/// a growing chunk seldom needs a new buffer.
+fn create_chunk_buffer(device: &wgpu::Device, label: &str, size: usize, usage: wgpu::BufferUsages) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (size as u64).max(wgpu::COPY_BUFFER_ALIGNMENT).next_power_of_two(),
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+impl ChunkBuffers {
+    fn new(device: &wgpu::Device, chunk: &GeometryChunk) -> Self {
+        ChunkBuffers {
+            vertex_buffer: create_chunk_buffer(device, "Map Chunk Vertex Buffer", std::mem::size_of_val(chunk.vertices.as_slice()), wgpu::BufferUsages::VERTEX),
+            index_buffer: create_chunk_buffer(device, "Map Chunk Index Buffer", std::mem::size_of_val(chunk.indices.as_slice()), wgpu::BufferUsages::INDEX),
+            layer_ranges: Vec::new(),
+        }
+    }
+
+    /// Copies a chunk into the buffers, replacing a buffer first if the chunk does not fit.
+    fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, chunk: GeometryChunk) {
+        // Copies into a buffer have to be a multiple of four bytes long, the padding is never drawn
+        let mut indices = chunk.indices;
+        if indices.len() % 2 == 1 {
+            indices.push(0);
+        }
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(&chunk.vertices);
+        let index_bytes: &[u8] = bytemuck::cast_slice(&indices);
+
+        if vertex_bytes.len() as u64 > self.vertex_buffer.size() {
+            self.vertex_buffer = create_chunk_buffer(device, "Map Chunk Vertex Buffer", vertex_bytes.len(), wgpu::BufferUsages::VERTEX);
+        }
+        if index_bytes.len() as u64 > self.index_buffer.size() {
+            self.index_buffer = create_chunk_buffer(device, "Map Chunk Index Buffer", index_bytes.len(), wgpu::BufferUsages::INDEX);
+        }
+
+        queue.write_buffer(&self.vertex_buffer, 0, vertex_bytes);
+        queue.write_buffer(&self.index_buffer, 0, index_bytes);
+        self.layer_ranges = chunk.layer_ranges;
+    }
+
+    /// Copies what was appended to a chunk into the buffers, the vertices and indices before
+    /// `written` are there already. A buffer the chunk outgrew is replaced and written whole.
+    ///
+    /// ## Arguments
+    /// * `written` - How many vertices and indices of the chunk were copied before.
+    fn write_appended(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, chunk: &GeometryChunk, written: (usize, usize)) {
+        // Copies have to start at a multiple of four bytes, so after an odd number of indices
+        // the last one is copied again, over the padding that followed it
+        let (mut vertex_start, mut index_start) = (written.0, written.1 & !1);
+        let padded_indices = chunk.indices.len().next_multiple_of(2);
+
+        if std::mem::size_of_val(chunk.vertices.as_slice()) as u64 > self.vertex_buffer.size() {
+            self.vertex_buffer = create_chunk_buffer(device, "Map Chunk Vertex Buffer", std::mem::size_of_val(chunk.vertices.as_slice()), wgpu::BufferUsages::VERTEX);
+            vertex_start = 0;
+        }
+        if (padded_indices * std::mem::size_of::<u16>()) as u64 > self.index_buffer.size() {
+            self.index_buffer = create_chunk_buffer(device, "Map Chunk Index Buffer", padded_indices * std::mem::size_of::<u16>(), wgpu::BufferUsages::INDEX);
+            index_start = 0;
+        }
+
+        let mut indices = chunk.indices[index_start..].to_vec();
+        if indices.len() % 2 == 1 {
+            indices.push(0);
+        }
+        if vertex_start < chunk.vertices.len() {
+            queue.write_buffer(&self.vertex_buffer, std::mem::size_of_val(&chunk.vertices[..vertex_start]) as u64, bytemuck::cast_slice(&chunk.vertices[vertex_start..]));
+        }
+        if !indices.is_empty() {
+            queue.write_buffer(&self.index_buffer, (index_start * std::mem::size_of::<u16>()) as u64, bytemuck::cast_slice(&indices));
+        }
+        self.layer_ranges = chunk.layer_ranges.clone();
+    }
+
+    /// Draws the runs of the shown layers. Hidden layers are skipped here, so toggling them
+    /// needs no new geometry.
+    fn draw<'pass>(&'pass self, render_pass: &mut wgpu::RenderPass<'pass>, visibility: LayerVisibility) {
+        let ranges = visible_index_ranges(&self.layer_ranges, visibility);
+        if ranges.is_empty() {
+            return;
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        for indices in ranges {
+            render_pass.draw_indexed(indices, 0, 0..1);
+        }
     }
 }
 
-fn generate_vertices_and_indices_from_renderable_ways(renderable_ways: &Vec<RenderableWay>, top_left: (f64, f64), bottom_right: (f64, f64)) -> (Vec<Vertex>, Vec<u16>) {
-    let mut vertices = Vec::new();
-    let mut indices = Vec::new();
+/// Copies the chunks of the map into the chunk buffers, reusing the buffers of the previous
+/// chunks and only creating buffers for chunks beyond them. Unused buffers are kept for later.
+///
+/// ## Returns
+/// * The number of buffers in use, the first of `buffers`.
+fn write_map_chunks(device: &wgpu::Device, queue: &wgpu::Queue, buffers: &mut Vec<ChunkBuffers>, chunks: Vec<GeometryChunk>) -> usize {
+    let count = chunks.len();
 
-    for way in renderable_ways {
-        // Determine how to visualize this way based on its tags
-        let is_coastline = way.tags.iter().any(|tag| tag.key == "natural" && tag.value == "coastline");
-        let is_highway = way.tags.iter().any(|tag| tag.key == "highway" && tag.value == "track");
-        let is_building = way.tags.iter().any(|tag| tag.key == "building");
-
-        if is_coastline {
-            // Handle coastline rendering (e.g., as lines)
-            generate_line_vertices_and_indices(way, top_left, bottom_right, 0.002, &mut vertices, &mut indices);
-        } else if is_highway {
-            // Handle highway rendering (e.g., as thick lines or strips)
-            generate_line_vertices_and_indices(way, top_left, bottom_right, 0.005, &mut vertices, &mut indices);
-        } else if is_building {
-            // Handle building rendering (e.g., as polygons)
-            generate_polygon_vertices_and_indices(way, top_left, bottom_right, &mut vertices, &mut indices);
-        } else {
-            // Handle other types of ways or default rendering (e.g., as lines)
-            generate_line_vertices_and_indices(way, top_left, bottom_right, 0.002, &mut vertices, &mut indices);
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        if index == buffers.len() {
+            buffers.push(ChunkBuffers::new(device, &chunk));
         }
+        buffers[index].write(device, queue, chunk);
     }
-    // println!("{:#?}", vertices);
-    (vertices, indices)
+
+    debug!(chunks = count, buffers = buffers.len(), "wrote map chunks");
+    count
+}
+
+/// Copies what was appended to chunks still being filled into the chunk buffers, see
+/// `ChunkBuffers::write_appended`. Like `write_map_chunks`, the buffers of the previous
+/// chunks are reused.
+///
+/// ## Arguments
+/// * `written` - How many vertices and indices of every chunk are in its buffers, updated to all of them.
+///
+/// ## Returns
+/// * The number of buffers in use, the first of `buffers`.
+fn write_appended_chunks(device: &wgpu::Device, queue: &wgpu::Queue, buffers: &mut Vec<ChunkBuffers>, chunks: &[GeometryChunk], written: &mut Vec<(usize, usize)>) -> usize {
+    written.resize(chunks.len(), (0, 0));
+    for (index, chunk) in chunks.iter().enumerate() {
+        let appended = (chunk.vertices.len(), chunk.indices.len());
+        if written[index] == appended {
+            continue;
+        }
+        if index == buffers.len() {
+            buffers.push(ChunkBuffers::new(device, chunk));
+        }
+        buffers[index].write_appended(device, queue, chunk, written[index]);
+        written[index] = appended;
+    }
+    chunks.len()
 }
 
+/// Tessellates a polyline into one quad per segment. Closed ways repeat their first
+/// point at the end, so they are closed without any extra segment.
+///
+/// Points closer to the last point drawn than `min_step_ndc`, scaled along x and y, are
+/// skipped as they would not show on screen, which turns dense surveyed lines into a few
+/// quads. The first and last point are always drawn. `NO_LINE_LOD` draws every point.
 fn generate_line_vertices_and_indices(
-    way: &RenderableWay,
-    top_left: (f64, f64),
-    bottom_right: (f64, f64),
-    thickness: f32, // Parameter to control the thickness
+    points: &[(f64, f64)],
+    projection: &Projection,
+    thickness: f32, // Parameter to control the thickness, in normalized device units
+    min_step_ndc: (f32, f32),
+    palette_index: u32,
     vertices: &mut Vec<Vertex>,
     indices: &mut Vec<u16>,
 ) {
-    let base_index = vertices.len() as u16;
-
-    // Loop through the nodes in the way
-    for (i, node) in way.nodes.iter().enumerate() {
-        let (x, y) = lat_lon_to_screen(node.lat, node.lon, top_left, bottom_right);
-
-        // If this isn't the first node, calculate direction to the previous node
-        if i > 0 {
-            let (prev_x, prev_y) = lat_lon_to_screen(
-                way.nodes[i - 1].lat,
-                way.nodes[i - 1].lon,
-                top_left,
-                bottom_right,
-            );
-
-            // Calculate the direction vector from the previous point to the current point
-            let direction = (
-                x - prev_x,
-                y - prev_y,
-            );
+    let Some(&first) = points.first() else {
+        return;
+    };
+    let skips_points = min_step_ndc.0 > 0.0 && min_step_ndc.1 > 0.0;
 
-            // Normalize the direction vector
-            let length = (direction.0.powi(2) + direction.1.powi(2)).sqrt();
-            let direction = (
-                direction.0 / length,
-                direction.1 / length,
-            );
-
-            // Calculate the perpendicular vector to the line direction
-            let perpendicular = (
-                -direction.1 * thickness / 2.0,
-                direction.0 * thickness / 2.0,
-            );
+    // The point the last quad ended at, where the next one starts
+    let mut from = first;
+    let mut from_ndc = projection.to_ndc(first.0, first.1);
 
-            // Define the vertices for the thick line
-            vertices.push(Vertex {
-                position: [prev_x + perpendicular.0, prev_y + perpendicular.1, 0.0],
-                tex_coords: [0.0, 0.0],
-            });
-            vertices.push(Vertex {
-                position: [prev_x - perpendicular.0, prev_y - perpendicular.1, 0.0],
-                tex_coords: [1.0, 0.0],
-            });
-            vertices.push(Vertex {
-                position: [x + perpendicular.0, y + perpendicular.1, 0.0],
-                tex_coords: [0.0, 1.0],
-            });
-            vertices.push(Vertex {
-                position: [x - perpendicular.0, y - perpendicular.1, 0.0],
-                tex_coords: [1.0, 1.0],
-            });
+    for (index, &to) in points.iter().enumerate().skip(1) {
+        // The line is extruded in normalized device coordinates, so it keeps its width on screen
+        let to_ndc = projection.to_ndc(to.0, to.1);
 
-            // Add the indices to create two triangles forming a quad
-            indices.extend_from_slice(&[
-                base_index + (i as u16 - 1) * 4,
-                base_index + (i as u16 - 1) * 4 + 1,
-                base_index + i as u16 * 4,
+        // Calculate the direction vector from the previous point to the current point
+        let direction = (
+            to_ndc.0 - from_ndc.0,
+            to_ndc.1 - from_ndc.1,
+        );
 
-                base_index + i as u16 * 4,
-                base_index + (i as u16 - 1) * 4 + 1,
-                base_index + i as u16 * 4 + 1,
-            ]);
+        // Skip repeated points, they have no direction to extrude along
+        let length = (direction.0.powi(2) + direction.1.powi(2)).sqrt();
+        if length == 0.0 {
+            continue;
         }
-    }
-
-    // Connect the last node to the first node to close the loop
-    if way.nodes.len() > 1 {
-        let first_node = &way.nodes[0];
-        let last_node = &way.nodes[way.nodes.len() - 1];
 
-        let (x1, y1) = lat_lon_to_screen(first_node.lat, first_node.lon, top_left, bottom_right);
-        let (x2, y2) = lat_lon_to_screen(last_node.lat, last_node.lon, top_left, bottom_right);
-
-        // Calculate the direction vector from the last point to the first point
-        let direction = (
-            x1 - x2,
-            y1 - y2,
-        );
+        // Points too close to the last point drawn are skipped, unless they end the line
+        let is_last = index == points.len() - 1;
+        if skips_points && !is_last && (direction.0 / min_step_ndc.0).powi(2) + (direction.1 / min_step_ndc.1).powi(2) < 1.0 {
+            continue;
+        }
 
         // Normalize the direction vector
-        let length = (direction.0.powi(2) + direction.1.powi(2)).sqrt();
         let direction = (
             direction.0 / length,
             direction.1 / length,
@@ -514,122 +4476,949 @@ fn generate_line_vertices_and_indices(
             direction.0 * thickness / 2.0,
         );
 
-        // Define the vertices for the thick line from last node to first node
+        // The vertices themselves are stored relative to the projection origin, only the
+        // small extrusion offset goes through the conversion from normalized device units
+        let (prev_x, prev_y) = projection.to_local(from.0, from.1);
+        let (x, y) = projection.to_local(to.0, to.1);
+        let perpendicular = projection.ndc_offset_to_local(perpendicular);
+        (from, from_ndc) = (to, to_ndc);
+
+        let base_index = vertices.len() as u16;
+
+        // Define the vertices for the thick line
         vertices.push(Vertex {
-            position: [x2 + perpendicular.0, y2 + perpendicular.1, 0.0],
-            tex_coords: [0.0, 0.0],
+            position: [prev_x + perpendicular.0, prev_y + perpendicular.1, 0.0],
+            palette_index,
+            shade: 1.0,
         });
         vertices.push(Vertex {
-            position: [x2 - perpendicular.0, y2 - perpendicular.1, 0.0],
-            tex_coords: [1.0, 0.0],
+            position: [prev_x - perpendicular.0, prev_y - perpendicular.1, 0.0],
+            palette_index,
+            shade: 1.0,
         });
         vertices.push(Vertex {
-            position: [x1 + perpendicular.0, y1 + perpendicular.1, 0.0],
-            tex_coords: [0.0, 1.0],
+            position: [x + perpendicular.0, y + perpendicular.1, 0.0],
+            palette_index,
+            shade: 1.0,
         });
         vertices.push(Vertex {
-            position: [x1 - perpendicular.0, y1 - perpendicular.1, 0.0],
-            tex_coords: [1.0, 1.0],
+            position: [x - perpendicular.0, y - perpendicular.1, 0.0],
+            palette_index,
+            shade: 1.0,
         });
 
-        // Add the indices to create two triangles forming a quad to close the loop
+        // Add the indices to create two triangles forming a quad
         indices.extend_from_slice(&[
-            base_index + (way.nodes.len() as u16 - 1) * 4,
-            base_index + (way.nodes.len() as u16 - 1) * 4 + 1,
             base_index,
+            base_index + 1,
+            base_index + 2,
 
-            base_index,
-            base_index + (way.nodes.len() as u16 - 1) * 4 + 1,
+            base_index + 2,
             base_index + 1,
+            base_index + 3,
         ]);
     }
 }
 
-fn generate_polygon_vertices_and_indices(way: &RenderableWay, top_left: (f64, f64), bottom_right: (f64, f64), vertices: &mut Vec<Vertex>, indices: &mut Vec<u16>) {
+fn generate_polygon_vertices_and_indices(points: &[(f64, f64)], projection: &Projection, palette_index: u32, vertices: &mut Vec<Vertex>, indices: &mut Vec<u16>) {
+    if points.len() < 3 {
+        return;
+    }
+
     let base_index = vertices.len() as u16;
 
-    for node in &way.nodes {
-        let (x, y) = lat_lon_to_screen(node.lat, node.lon, top_left, bottom_right);
+    for &(lat, lon) in points {
+        let (x, y) = projection.to_local(lat, lon);
         vertices.push(Vertex {
             position: [x, y, 0.0],
-            tex_coords: [0.0, 0.0], // Placeholder texture coordinates
+            palette_index,
+            shade: 1.0,
         });
     }
 
     // Triangulation: For a simple polygon, assume that nodes are ordered and define a fan from the first vertex
-    for i in 1..way.nodes.len() as u16 - 1 {
+    for i in 1..points.len() as u16 - 1 {
         indices.extend_from_slice(&[
             base_index, base_index + i, base_index + i + 1,
         ]);
     }
 }
 
-pub async fn run() {
-    let event_loop = EventLoop::new().unwrap();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+// Buildings without a usable height are extruded by a single level.
+const DEFAULT_BUILDING_HEIGHT_M: f64 = METERS_PER_LEVEL;
+// Walls are darker than roofs, the more so the more they face sideways, so the shape reads.
+const WALL_SHADE_FRONT: f32 = 0.8;
+const WALL_SHADE_SIDE: f32 = 0.6;
 
-    // State::new uses async code, so we're going to wait for it to finish
-    let mut state = State::new(&window).await;
-    let mut surface_configured = false;
+/// Twice the signed area of a ring, positive if it runs counter clockwise.
+fn signed_area(points: &[(f32, f32)]) -> f32 {
+    points.iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum()
+}
 
-    event_loop
-        .run(move |event, control_flow| {
-            match event {
-                Event::WindowEvent {
-                    ref event,
-                    window_id,
-                } if window_id == state.window().id() => {
-                    if !state.input(event) {
-                        // UPDATED!
-                        match event {
-                            WindowEvent::CloseRequested
-                            | WindowEvent::KeyboardInput {
-                                event:
-                                    KeyEvent {
-                                        state: ElementState::Pressed,
-                                        physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                        ..
-                                    },
-                                ..
-                            } => control_flow.exit(),
-                            WindowEvent::Resized(physical_size) => {
-                                log::info!("physical_size: {physical_size:?}");
-                                surface_configured = true;
-                                state.resize(*physical_size);
-                            }
-                            WindowEvent::RedrawRequested => {
-                                // This tells winit that we want another frame after this one
-                                state.window().request_redraw();
-
-                                if !surface_configured {
-                                    return;
-                                }
-
-                                state.update();
-                                match state.render() {
-                                    Ok(_) => {}
-                                    // Reconfigure the surface if it's lost or outdated
-                                    Err(
-                                        wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
-                                    ) => state.resize(state.size),
-                                    // The system is out of memory, we should probably quit
-                                    Err(wgpu::SurfaceError::OutOfMemory) => {
-                                        log::error!("OutOfMemory");
-                                        control_flow.exit();
-                                    }
-
-                                    // This happens when the a frame takes too long to present
-                                    Err(wgpu::SurfaceError::Timeout) => {
-                                        log::warn!("Surface timeout")
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
+/// Fills a polygon with holes, e.g. a multipolygon around a courtyard, at `height` above
+/// the ground. The outline and the holes are triangulated together by
+/// `triangulate_polygon`, so what lies below shows through the holes.
+fn generate_polygon_with_holes_vertices_and_indices(points: &[(f64, f64)], holes: &[Vec<(f64, f64)>], projection: &Projection, height: f32, palette_index: u32, vertices: &mut Vec<Vertex>, indices: &mut Vec<u16>) {
+    let rings: Vec<Vec<(f64, f64)>> = std::iter::once(points).chain(holes.iter().map(Vec::as_slice)).map(open_ring).collect();
+
+    // The triangles are wound on screen, as the north-down projection mirrors the rings
+    let on_screen: Vec<Vec<(f64, f64)>> = rings.iter()
+        .map(|ring| ring.iter().map(|&(lat, lon)| projection.to_ndc(lat, lon)).map(|(x, y)| (x as f64, y as f64)).collect())
+        .collect();
+    let triangles = triangulate_polygon(&on_screen[0], &on_screen[1..]);
+
+    let base_index = vertices.len() as u16;
+    for &(lat, lon) in rings.iter().flatten() {
+        let (x, y) = projection.to_local(lat, lon);
+        vertices.push(Vertex {
+            position: [x, y, height],
+            palette_index,
+            shade: 1.0,
+        });
+    }
+    indices.extend(triangles.into_iter().map(|index| base_index + index as u16));
+}
+
+/// Drops the last point of a closed ring, which repeats the first.
+fn open_ring(points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let mut ring: Vec<(f64, f64)> = points.to_vec();
+    if ring.len() > 1 && ring.first() == ring.last() {
+        ring.pop();
+    }
+    ring
+}
+
+/// Extrudes a building footprint into a prism: a wall quad along every edge and the roof
+/// at `height_m` above the ground. The roof is triangulated as a fan, like flat polygons,
+/// unless the building has holes, e.g. courtyards, which get walls of their own.
+///
+/// The walls are wound counter clockwise on screen when seen from the outside, so the walls
+/// facing away from the viewer are culled.
+fn generate_building_vertices_and_indices(points: &[(f64, f64)], holes: &[Vec<(f64, f64)>], projection: &Projection, height_m: f64, palette_index: u32, vertices: &mut Vec<Vertex>, indices: &mut Vec<u16>) {
+    // Closed ways repeat their first point, which would add an empty wall
+    let ring = open_ring(points);
+    if ring.len() < 3 {
+        return;
+    }
+
+    // Mercator stretches distances by 1 / cos(lat), so heights are stretched alike
+    let center_lat = ring.iter().map(|&(lat, _)| lat).sum::<f64>() / ring.len() as f64;
+    let height = (height_m / center_lat.to_radians().cos()) as f32;
+
+    // The walls of a courtyard face into it, so its ring runs the other way around
+    generate_wall_vertices_and_indices(&ring, false, projection, height, palette_index, vertices, indices);
+    for hole in holes {
+        generate_wall_vertices_and_indices(&open_ring(hole), true, projection, height, palette_index, vertices, indices);
+    }
+
+    if !holes.is_empty() {
+        generate_polygon_with_holes_vertices_and_indices(&ring, holes, projection, height, palette_index, vertices, indices);
+        return;
+    }
+
+    // The north-down projection mirrors the ring, so its winding is decided on screen
+    let mut corners: Vec<(f32, f32)> = ring.iter().map(|&(lat, lon)| projection.to_local(lat, lon)).collect();
+    let on_screen: Vec<(f32, f32)> = ring.iter().map(|&(lat, lon)| projection.to_ndc(lat, lon)).collect();
+    if signed_area(&on_screen) < 0.0 {
+        corners.reverse();
+    }
+
+    let base_index = vertices.len() as u16;
+    for &(x, y) in &corners {
+        vertices.push(Vertex {
+            position: [x, y, height],
+            palette_index,
+            shade: 1.0,
+        });
+    }
+    for i in 1..corners.len() as u16 - 1 {
+        indices.extend_from_slice(&[
+            base_index, base_index + i, base_index + i + 1,
+        ]);
+    }
+}
+
+/// Raises a wall of `height` along every edge of a ring, wound counter clockwise on screen,
+/// or clockwise for the ring of a hole, so the walls face away from the inside of the building.
+fn generate_wall_vertices_and_indices(ring: &[(f64, f64)], hole: bool, projection: &Projection, height: f32, palette_index: u32, vertices: &mut Vec<Vertex>, indices: &mut Vec<u16>) {
+    // The north-down projection mirrors the ring, so its winding is decided on screen
+    let mut corners: Vec<(f32, f32)> = ring.iter().map(|&(lat, lon)| projection.to_local(lat, lon)).collect();
+    let on_screen: Vec<(f32, f32)> = ring.iter().map(|&(lat, lon)| projection.to_ndc(lat, lon)).collect();
+    if (signed_area(&on_screen) < 0.0) != hole {
+        corners.reverse();
+    }
+
+    for (&a, &b) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+        let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+        if length == 0.0 {
+            continue;
+        }
+
+        // Walls running along the screen face the viewer, walls running up it face sideways
+        let facing = (b.0 - a.0).abs() / length;
+        let shade = WALL_SHADE_SIDE + (WALL_SHADE_FRONT - WALL_SHADE_SIDE) * facing;
+
+        let base_index = vertices.len() as u16;
+        for (x, y, z) in [(a.0, a.1, 0.0), (b.0, b.1, 0.0), (b.0, b.1, height), (a.0, a.1, height)] {
+            vertices.push(Vertex {
+                position: [x, y, z],
+                palette_index,
+                shade,
+            });
+        }
+
+        // Seen from the outside the ground edge runs from a to b, then the wall goes up
+        indices.extend_from_slice(&[
+            base_index, base_index + 1, base_index + 2,
+            base_index, base_index + 2, base_index + 3,
+        ]);
+    }
+}
+
+/// Drives the event loop, shaped like winit's `ApplicationHandler` so the event loop
+/// closure only has to dispatch to it.
+///
+/// # Fields
+/// * `state` - The map shown in the window, which owns the window.
+struct App {
+    state: State,
+}
+
+impl App {
+    fn resumed(&mut self, _event_loop: &EventLoopWindowTarget<()>) {
+        // The surface may have been lost while the application was suspended
+        self.state.resize(self.state.window().inner_size());
+        self.state.window().request_redraw();
+    }
+
+    fn resume_time_reached(&mut self, event_loop: &EventLoopWindowTarget<()>) {
+        // The wait for a lost surface, an expiring status message, a settling viewport, a
+        // camera to stop or a resting cursor is over
+        event_loop.set_control_flow(ControlFlow::Wait);
+        self.state.window().request_redraw();
+    }
+
+    fn user_event(&mut self) {
+        // A background task sent an event, it is applied in the next frame
+        self.state.window().request_redraw();
+    }
+
+    fn about_to_wait(&mut self, event_loop: &EventLoopWindowTarget<()>) {
+        // The events waiting were handled, look up the way the cursor ended up on
+        self.state.update_hover();
+
+        // Wake up to show it once the cursor rested on it, unless something else wakes up earlier
+        if let Some(due_at) = self.state.hover.due_at() {
+            let wake_at = match event_loop.control_flow() {
+                ControlFlow::WaitUntil(wake_at) => wake_at.min(due_at),
+                _ => due_at,
+            };
+            event_loop.set_control_flow(ControlFlow::WaitUntil(wake_at));
+        }
+    }
+
+    fn window_event(&mut self, event_loop: &EventLoopWindowTarget<()>, window_id: WindowId, event: WindowEvent) {
+        if window_id != self.state.window().id() {
+            return;
+        }
+        if self.state.input(&event) {
+            self.state.window().request_redraw();
+            return;
+        }
+
+        // The key bound to cancel closes the window once there is nothing left to cancel
+        if matches!(event, WindowEvent::CloseRequested) || self.state.key_action(&event) == Some(Action::Cancel) {
+            self.state.operations.cancel_all();
+            self.state.save_viewport();
+            self.state.writer.finish();
+            self.state.stop_watching();
+            event_loop.exit();
+            return;
+        }
+
+        match event {
+            WindowEvent::Resized(physical_size) => {
+                debug!(?physical_size, "resized");
+                self.state.resize(physical_size);
+                self.state.window().request_redraw();
+            }
+            WindowEvent::RedrawRequested => self.redraw(event_loop),
+            _ => {}
+        }
+    }
+
+    fn redraw(&mut self, event_loop: &EventLoopWindowTarget<()>) {
+        // A lost surface is retried once its backoff is over, without spinning until then
+        if let Some(retry_at) = self.state.surface_retry_at {
+            if Instant::now() < retry_at {
+                event_loop.set_control_flow(ControlFlow::WaitUntil(retry_at));
+                return;
+            }
+        }
+
+        if !self.state.surface_configured {
+            return;
+        }
+
+        // Frames are only drawn when something changed, so the loop sleeps until the next
+        // input, event of a background task or expiring status message
+        self.state.profiler.begin("update", Instant::now());
+        if self.state.update() {
+            self.state.window().request_redraw();
+        }
+        self.state.profiler.end("update", Instant::now());
+        match self.state.render() {
+            Ok(_) => {
+                self.state.surface_failures = 0;
+                self.state.surface_retry_at = None;
+                if self.state.profiler.end_frame() {
+                    info!("frame profile: {}", self.state.profiler.summary());
+                }
+                // Redrawing continuously draws the next frame right away instead
+                if self.state.continuous_redraw {
+                    if let Some(frames_per_second) = self.state.frame_rate.frame(Instant::now()) {
+                        info!(frames_per_second = format!("{:.1}", frames_per_second), "frame rate");
                     }
+                    self.state.window().request_redraw();
+                }
+
+                // Wake up for the next expiring status message, viewport to fetch or viewport to
+                // record in the history
+                let expires_at = self.state.status.current().and_then(|message| message.expires_at);
+                match expires_at.into_iter().chain(self.state.fetch_debouncer.due_at()).chain(self.state.history.settles_at()).min() {
+                    Some(wake_at) => event_loop.set_control_flow(ControlFlow::WaitUntil(wake_at)),
+                    None => event_loop.set_control_flow(ControlFlow::Wait),
                 }
-                _ => {}
             }
+            // Reconfigure or recreate the surface if it's lost or outdated
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                if let Err(error) = self.state.recover_surface() {
+                    error!(%error, "could not recover the surface");
+                }
+                self.state.window().request_redraw();
+            }
+            // The system is out of memory, we should probably quit
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                error!("out of memory");
+                event_loop.exit();
+            }
+            // This happens when the a frame takes too long to present
+            Err(wgpu::SurfaceError::Timeout) => {
+                warn!("surface timeout");
+                self.state.window().request_redraw();
+            }
+        }
+    }
+}
+
+/// Opens the map of the database behind `pool` in a window and shows it until the window
+/// is closed.
+///
+/// ## Arguments
+/// * `import_options` - How the files and downloads imported from the map are imported.
+/// * `watch_mode` - What to do with map files appearing or changing in `MAPDATA_DIRECTORY`,
+///   or `None` not to watch for them.
+/// * `goto` - The view to open on, or `None` for the one the map was last closed on.
+///
+/// ## Returns
+/// * An error if no GPU could be set up to draw into the window.
+pub async fn run(pool: Pool<Sqlite>, import_options: ImportOptions, watch_mode: Option<WatchMode>, goto: Option<Permalink>) -> Result<(), GpuError> {
+    let event_loop = EventLoop::new().unwrap();
+    let window = Arc::new(WindowBuilder::new().build(&event_loop).unwrap());
+
+    // State::new uses async code, so we're going to wait for it to finish
+    let mut app = App {
+        state: State::new(window, event_loop.create_proxy(), pool, import_options, watch_mode, goto).await?,
+    };
+
+    event_loop
+        .run(move |event, event_loop| match event {
+            Event::Resumed => app.resumed(event_loop),
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) => app.resume_time_reached(event_loop),
+            Event::UserEvent(()) => app.user_event(),
+            Event::AboutToWait => app.about_to_wait(event_loop),
+            Event::WindowEvent { window_id, event } => app.window_event(event_loop, window_id, event),
+            _ => {}
         })
         .unwrap();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    const HOUSE_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.0010" lon="12.0000" version="1"/>
+ <node id="2" lat="55.0010" lon="12.0010" version="1"/>
+ <node id="3" lat="55.0000" lon="12.0010" version="1"/>
+ <node id="4" lat="55.0000" lon="12.0000" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><nd ref="1"/><tag k="building" v="house"/></way>
+</osm>
+"#;
+
+    #[tokio::test]
+    async fn the_element_at_a_point_is_looked_up_for_the_inspection_panel() {
+        let pool = memory_pool("inspect_at_point").await;
+        import_osm_xml(&pool, "inspect_at_point", HOUSE_OSM).await;
+        let (events, queue) = event_channel(None);
+
+        // Without a loaded way hit, the building containing the point is found
+        let element = print_place_at(&pool, (55.0005, 12.0005), None, &events).await;
+        assert_eq!(element, Some((MapsType::Way, 10)));
+        let inspection = fetch_inspection(&pool, element.as_ref()).await.unwrap().unwrap();
+        assert_eq!((inspection.maps_type, inspection.id), (MapsType::Way, 10));
+
+        // The loaded way hit comes first, even if it is not stored
+        let element = print_place_at(&pool, (55.0005, 12.0005), Some((99, None, 1.0)), &events).await;
+        assert_eq!(element, Some((MapsType::Way, 99)));
+        assert_eq!(fetch_inspection(&pool, element.as_ref()).await.unwrap(), None);
+        assert_eq!(fetch_inspection(&pool, None).await.unwrap(), None);
+
+        // Nothing failed, so nothing was posted to the status line
+        assert!(queue.drain(usize::MAX).is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_way_missing_a_node_is_drawn_as_an_open_line_through_the_others() {
+        let pool = memory_pool("incomplete_way").await;
+        import_osm_xml(&pool, "incomplete_way", r#"<osm version="0.6">
+ <node id="1" lat="55.0010" lon="12.0000" version="1"/>
+ <node id="2" lat="55.0010" lon="12.0010" version="1"/>
+ <node id="3" lat="55.0000" lon="12.0010" version="1"/>
+ <node id="9" lat="55.0000" lon="12.0000" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="9"/><tag k="building" v="house"/></way>
+</osm>"#).await;
+        // The foreign keys keep out references to missing nodes, so node 9 is removed behind
+        // their back, as if the building crossed the edge of the extract
+        let mut connection = pool.acquire().await.unwrap();
+        for statement in ["PRAGMA foreign_keys = OFF", "DELETE FROM node WHERE id = 9", "PRAGMA foreign_keys = ON"] {
+            sqlx::query(statement).execute(&mut *connection).await.unwrap();
+        }
+        drop(connection);
+
+        let ways = fetch_all_renderable_ways(&pool).await.unwrap();
+        assert_eq!(ways.len(), 1);
+        assert_eq!((ways[0].coords.len(), ways[0].missing_nodes, ways[0].is_complete()), (3, 1, false));
+        assert_eq!(report_incomplete_ways(&ways).as_deref(), Some("1 ways miss 1 of their nodes and are drawn as open lines"));
+
+        let style_sheet = StyleSheet::default();
+        let view = BBox { min_lat: 54.999, max_lat: 55.002, min_lon: 11.999, max_lon: 12.002 };
+        let scene = MapScene { renderable_ways: &ways, relation_ways: &[], style_sheet: &style_sheet, view, extrude_buildings: true };
+        let items = prepare_draw_items(&scene);
+        assert_eq!(items.len(), 1);
+        let DrawItem::Line { points, incomplete: true, extend_ends: (false, false), .. } = &items[0] else {
+            panic!("the building was not drawn as an open line");
+        };
+        // Through the nodes it has, not closed back to the first one
+        assert_eq!(points, &ways[0].coords);
+        assert_ne!(points.first(), points.last());
+
+        // Its two segments are drawn, as a quad each
+        let palette = build_palette(&style_sheet);
+        let geometries = tessellate_draw_items(items, &Tessellation::new(&view, NO_LINE_LOD, MAX_CHUNK_VERTICES), &palette);
+        let vertices: usize = geometries.iter().map(|geometry| geometry.vertices.len()).sum();
+        assert_eq!(vertices, 2 * 4);
+    }
+
+    #[tokio::test]
+    async fn bridges_are_drawn_after_the_road_they_cross_and_tunnels_before_it() {
+        let pool = memory_pool("bridge_order").await;
+        import_osm_xml(&pool, "bridge_order", r#"<osm version="0.6">
+ <node id="1" lat="55.000" lon="12.005" version="1"/>
+ <node id="2" lat="55.010" lon="12.005" version="1"/>
+ <node id="3" lat="55.005" lon="12.000" version="1"/>
+ <node id="4" lat="55.005" lon="12.010" version="1"/>
+ <node id="5" lat="55.000" lon="12.008" version="1"/>
+ <node id="6" lat="55.010" lon="12.008" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/><tag k="bridge" v="yes"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="4"/><tag k="highway" v="primary"/></way>
+ <way id="12" version="1"><nd ref="5"/><nd ref="6"/><tag k="highway" v="service"/><tag k="tunnel" v="yes"/></way>
+</osm>"#).await;
+        let mut ways = fetch_all_renderable_ways(&pool).await.unwrap();
+        let style_sheet = StyleSheet::default();
+        let view = BBox { min_lat: 54.998, max_lat: 55.012, min_lon: 11.998, max_lon: 12.012 };
+
+        // Named by the longitude they run along, or the road running east
+        let draw_order = |ways: &[RenderableWay]| -> Vec<(&'static str, bool)> {
+            let scene = MapScene { renderable_ways: ways, relation_ways: &[], style_sheet: &style_sheet, view, extrude_buildings: true };
+            prepare_draw_items(&scene).iter()
+                .map(|item| match item {
+                    DrawItem::Line { points, tunnel, .. } if points.iter().all(|point| point.1 == 12.005) => ("bridge", *tunnel),
+                    DrawItem::Line { points, tunnel, .. } if points.iter().all(|point| point.1 == 12.008) => ("tunnel", *tunnel),
+                    DrawItem::Line { tunnel, .. } => ("road", *tunnel),
+                    DrawItem::Area { .. } => ("area", false),
+                })
+                .collect()
+        };
+
+        let expected = [("tunnel", true), ("road", false), ("bridge", false)];
+        assert_eq!(draw_order(&ways), expected);
+        ways.reverse();
+        assert_eq!(draw_order(&ways), expected);
+
+        // The tunnel is dashed, so more than the one quad of a plain segment
+        let palette = build_palette(&style_sheet);
+        let scene = MapScene { renderable_ways: &ways, relation_ways: &[], style_sheet: &style_sheet, view, extrude_buildings: true };
+        let items: Vec<DrawItem> = prepare_draw_items(&scene).into_iter().filter(|item| matches!(item, DrawItem::Line { tunnel: true, .. })).collect();
+        let geometries = tessellate_draw_items(items, &Tessellation::new(&view, NO_LINE_LOD, MAX_CHUNK_VERTICES), &palette);
+        let vertices: usize = geometries.iter().map(|geometry| geometry.vertices.len()).sum();
+        assert!(vertices > 4, "{vertices}");
+    }
+
+    #[tokio::test]
+    async fn railways_ferries_and_runways_are_all_drawn() {
+        let pool = memory_pool("line_kinds").await;
+        import_osm_xml(&pool, "line_kinds", r#"<osm version="0.6">
+ <node id="1" lat="55.000" lon="12.000" version="1"/>
+ <node id="2" lat="55.000" lon="12.010" version="1"/>
+ <node id="3" lat="55.003" lon="12.000" version="1"/>
+ <node id="4" lat="55.003" lon="12.010" version="1"/>
+ <node id="5" lat="55.006" lon="12.000" version="1"/>
+ <node id="6" lat="55.006" lon="12.010" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><tag k="railway" v="rail"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="4"/><tag k="route" v="ferry"/></way>
+ <way id="12" version="1"><nd ref="5"/><nd ref="6"/><tag k="aeroway" v="runway"/></way>
+</osm>"#).await;
+        let ways = fetch_all_renderable_ways(&pool).await.unwrap();
+        let style_sheet = StyleSheet::default();
+        let palette = build_palette(&style_sheet);
+        let view = BBox { min_lat: 54.998, max_lat: 55.008, min_lon: 11.998, max_lon: 12.012 };
+        let scene = MapScene { renderable_ways: &ways, relation_ways: &[], style_sheet: &style_sheet, view, extrude_buildings: true };
+        let mut chunks = ChunkBuilder::default();
+        generate_vertices_and_indices_from_renderable_ways(&scene, &palette, NO_LINE_LOD, &mut chunks);
+        let chunks = chunks.finish();
+
+        let vertices_in = |color: [f32; 4]| {
+            let index = palette.index_of(color, true);
+            chunks.iter().flat_map(|chunk| &chunk.vertices).filter(|vertex| vertex.palette_index == index).count()
+        };
+        let [rail, ferry, runway] = [10, 11, 12].map(|id| style_sheet.style_for(&ways.iter().find(|way| way.id == id).unwrap().tags).unwrap());
+        // The rail and the runway are one quad each, the ferry is dashed into several
+        assert_eq!(vertices_in(rail.color), 4);
+        assert_eq!(vertices_in(runway.color), 4);
+        assert!(vertices_in(ferry.color) > 4);
+
+        // The light dashes on the rail are drawn over it
+        assert!(vertices_in(rail.dash_overlay.as_ref().unwrap().color) > 4);
+    }
+
+    #[test]
+    fn the_scale_bar_is_as_long_as_the_distance_it_stands_for() {
+        let palette = Palette::new(Style::default().color, true);
+        // 1.7 km wide at the equator
+        let view = BBox { min_lat: -0.005, max_lat: 0.005, min_lon: 0.0, max_lon: 1700.0 / crate::geo::METERS_PER_DEGREE };
+        let size = winit::dpi::PhysicalSize::new(1000, 800);
+
+        let (vertices, indices, length_m) = generate_scale_bar_vertices_and_indices(&palette, &view, size);
+        assert_eq!(length_m, 500.0);
+        // The bar and a tick at either end
+        assert_eq!((vertices.len(), indices.len()), (12, 18));
+        let bar_ndc = vertices[1].position[0] - vertices[0].position[0];
+        assert!((bar_ndc - 500.0 / 850.0).abs() < 1e-4, "{}", bar_ndc);
+        assert_eq!(vertices[0].position[0], -1.0 + 20.0 * 2.0 / 1000.0);
+
+        // Nothing is drawn into a minimized window
+        let (vertices, _, _) = generate_scale_bar_vertices_and_indices(&palette, &view, winit::dpi::PhysicalSize::new(0, 0));
+        assert!(vertices.is_empty());
+    }
+
+    #[test]
+    fn placeholders_are_drawn_as_rectangles_clipped_to_the_viewport() {
+        let palette = Palette::new(Style::default().color, true);
+        let view = BBox { min_lat: 55.0, max_lat: 55.01, min_lon: 12.0, max_lon: 12.01 };
+        let placeholder = |way_id: i64, min_lat: f64, max_lat: f64, min_lon: f64, max_lon: f64| WayBbox { way_id, bbox: BBox { min_lat, max_lat, min_lon, max_lon } };
+        let placeholders = [
+            placeholder(1, 55.002, 55.004, 12.002, 12.004),
+            // Reaching far beyond the viewport
+            placeholder(2, 54.0, 56.0, 12.005, 12.006),
+            // A straight line north to south has no area
+            placeholder(3, 55.002, 55.004, 12.003, 12.003),
+            placeholder(4, 57.0, 57.1, 12.0, 12.01),
+        ];
+
+        let (vertices, indices) = generate_skeleton_vertices_and_indices(placeholders.iter(), &palette, &view);
+        assert_eq!((vertices.len(), indices.len()), (8, 12));
+        // The second rectangle ends at the margin around the viewport
+        let projection = Projection::for_viewport(&view);
+        let clipped = view.expand(CLIP_MARGIN);
+        let mut expected = [projection.to_local(clipped.min_lat, 12.005).1, projection.to_local(clipped.max_lat, 12.005).1];
+        expected.sort_by(f32::total_cmp);
+        let ys: Vec<f32> = vertices[4..].iter().map(|vertex| vertex.position[1]).collect();
+        let extent = [ys.iter().copied().fold(f32::MAX, f32::min), ys.iter().copied().fold(f32::MIN, f32::max)];
+        assert_eq!(extent, expected);
+    }
+
+    // Whether every wall quad of a ring has `inside` to the left of its ground edge on screen
+    fn walls_around(ring: &[(f64, f64)], hole: bool, inside: (f64, f64)) -> Vec<bool> {
+        let view = BBox { min_lat: 54.99, max_lat: 55.01, min_lon: 11.99, max_lon: 12.01 };
+        let projection = Projection::for_viewport(&view);
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        generate_wall_vertices_and_indices(ring, hole, &projection, 0.5, 0, &mut vertices, &mut indices);
+
+        let to_screen = |vertex: &Vertex| (vertex.position[0] as f64 * projection.scale.0, vertex.position[1] as f64 * projection.scale.1);
+        let inside = projection.to_ndc(inside.0, inside.1);
+        vertices.chunks(4).zip(indices.chunks(6))
+            .map(|(quad, quad_indices)| {
+                let base = quad_indices[0] as usize;
+                let (a, b) = (to_screen(&vertices[base]), to_screen(&vertices[base + 1]));
+                // Both triangles go along the ground edge first, then up
+                assert_eq!(quad_indices.iter().map(|&index| index as usize - base).collect::<Vec<_>>(), vec![0, 1, 2, 0, 2, 3]);
+                assert_eq!((quad[0].position[2], quad[2].position[2]), (0.0, 0.5));
+                (b.0 - a.0) * (inside.1 as f64 - a.1) - (b.1 - a.1) * (inside.0 as f64 - a.0) > 0.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn walls_face_away_from_the_building_whichever_way_its_ring_runs() {
+        let square = [(55.0, 12.0), (55.0, 12.001), (55.001, 12.001), (55.001, 12.0), (55.0, 12.0)];
+        let reversed: Vec<(f64, f64)> = square.iter().rev().copied().collect();
+        let center = (55.0005, 12.0005);
+
+        // The closing edge has no length, so the closed ring gets four walls
+        for ring in [&square[..], &reversed[..]] {
+            assert_eq!(walls_around(ring, false, center), vec![true; 4]);
+            // The walls of a courtyard face into it, away from the building around it
+            assert_eq!(walls_around(ring, true, center), vec![false; 4]);
+        }
+    }
+
+    fn way_through(id: i64, coords: &[(f64, f64)]) -> RenderableWay {
+        let nodes: Vec<SimpleNode> = coords.iter().map(|&(lat, lon)| SimpleNode { id: None, lat, lon }).collect();
+        RenderableWay::from_nodes(id, &nodes, Vec::new(), 0)
+    }
+
+    #[test]
+    fn the_data_extent_surrounds_every_way() {
+        let ways = [
+            way_through(1, &[(0.0, 0.0), (0.5, 0.2)]),
+            way_through(2, &[(-0.3, 0.1), (0.2, 1.0)]),
+        ];
+        assert_eq!(data_extent(&ways), Some(BBox { min_lat: -0.3, max_lat: 0.5, min_lon: 0.0, max_lon: 1.0 }));
+
+        // Without area there is nothing to fit into the minimap
+        assert_eq!(data_extent(&[]), None);
+        assert_eq!(data_extent(&[way_through(1, &[(0.0, 0.0), (0.0, 1.0)])]), None);
+    }
+
+    #[test]
+    fn only_a_viewport_clear_of_the_data_is_outside_it() {
+        let extent = BBox { min_lat: 55.0, max_lat: 56.0, min_lon: 11.0, max_lon: 12.0 };
+        let inside = BBox { min_lat: 55.2, max_lat: 55.4, min_lon: 11.2, max_lon: 11.4 };
+        let across_the_edge = BBox { min_lat: 55.9, max_lat: 56.1, min_lon: 11.5, max_lon: 11.7 };
+        let beyond = BBox { min_lat: 57.0, max_lat: 57.2, min_lon: 11.5, max_lon: 11.7 };
+
+        assert!(!is_outside_data(Some(extent), &inside));
+        assert!(!is_outside_data(Some(extent), &across_the_edge));
+        assert!(is_outside_data(Some(extent), &beyond));
+        assert!(!is_outside_data(None, &beyond));
+    }
+
+    /// The width over the height of a viewport in Web Mercator meters, as the window shows it.
+    fn mercator_aspect(view: &Viewport) -> f64 {
+        let (left, top) = crate::geo::lat_lon_to_mercator(view.max_lat, view.min_lon);
+        let (right, bottom) = crate::geo::lat_lon_to_mercator(view.min_lat, view.max_lon);
+        (right - left) / (top - bottom)
+    }
+
+    fn contains(outer: &BBox, inner: &BBox) -> bool {
+        outer.min_lat <= inner.min_lat && outer.max_lat >= inner.max_lat && outer.min_lon <= inner.min_lon && outer.max_lon >= inner.max_lon
+    }
+
+    #[test]
+    fn the_fitted_viewport_has_the_aspect_of_the_window_and_holds_the_data() {
+        let tall = BBox { min_lat: 55.0, max_lat: 56.0, min_lon: 12.0, max_lon: 12.1 };
+        let wide = BBox { min_lat: 55.0, max_lat: 55.1, min_lon: 10.0, max_lon: 13.0 };
+
+        for (extent, size) in [(tall, (1600, 900)), (wide, (600, 1000)), (tall, (600, 1000)), (wide, (1600, 900))] {
+            let view = fit_viewport(Some(extent), size);
+            assert!((mercator_aspect(&view) - size.0 as f64 / size.1 as f64).abs() < 1e-6, "{view:?} in {size:?}");
+            assert!(contains(&view, &extent.expand(VIEWPORT_FIT_MARGIN)), "{view:?} misses {extent:?}");
+        }
+
+        // The short side grows, the long one keeps the margin
+        let view = fit_viewport(Some(tall), (1600, 900));
+        let margin = tall.expand(VIEWPORT_FIT_MARGIN);
+        assert!((view.max_lat - margin.max_lat).abs() < 1e-9 && (view.min_lat - margin.min_lat).abs() < 1e-9);
+        assert!(view.max_lon - view.min_lon > 1.0);
+    }
+
+    #[test]
+    fn a_single_point_or_no_data_still_gives_a_viewport() {
+        let point = BBox { min_lat: 55.0, max_lat: 55.0, min_lon: 12.0, max_lon: 12.0 };
+        let view = fit_viewport(Some(point), (800, 800));
+        assert!(view.max_lat > view.min_lat && view.max_lon > view.min_lon);
+        assert!(contains(&view, &point));
+        assert!((mercator_aspect(&view) - 1.0).abs() < 1e-6);
+
+        // Without data all of the world shows, at the window's aspect
+        let view = fit_viewport(None, (1600, 900));
+        assert!(contains(&view, &WORLD_VIEWPORT));
+        assert!((mercator_aspect(&view) - 16.0 / 9.0).abs() < 1e-6);
+
+        // A window minimized to nothing does not divide by zero
+        let view = fit_viewport(Some(point), (0, 0));
+        assert!(view.min_lat.is_finite() && view.max_lon.is_finite());
+    }
+
+    /// The quads of a straight line of `count` points along the equator spanning `span_px`
+    /// pixels of a viewport 1000 pixels wide, with and without skipping points.
+    fn straight_line_quads(count: usize, span_px: f64, min_step_ndc: (f32, f32)) -> (usize, Vec<Vertex>) {
+        let view = BBox { min_lat: -0.005, max_lat: 0.005, min_lon: 0.0, max_lon: 0.01 };
+        let span = 0.01 * span_px / 1000.0;
+        let points: Vec<(f64, f64)> = (0..count).map(|index| (0.0, span * index as f64 / (count - 1) as f64)).collect();
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        generate_line_vertices_and_indices(&points, &Projection::for_viewport(&view), 0.01, min_step_ndc, 0, &mut vertices, &mut indices);
+        assert_eq!(indices.len(), vertices.len() / 4 * 6);
+        (vertices.len() / 4, vertices)
+    }
+
+    #[test]
+    fn a_dense_straight_line_collapses_to_its_ends() {
+        // One pixel of a viewport 1000 pixels across, in either direction
+        let one_px = (2.0 / 1000.0, 2.0 / 1000.0);
+
+        // Under a pixel long, only the first and the last point are left
+        let (quads, vertices) = straight_line_quads(1000, 0.9, one_px);
+        assert_eq!(quads, 1);
+        let (_, every_vertex) = straight_line_quads(1000, 0.9, NO_LINE_LOD);
+        assert_eq!(every_vertex.len(), 999 * 4);
+        // The quad runs from the first point to the last
+        assert_eq!(vertices[0].position, every_vertex[0].position);
+        assert_eq!(vertices[3].position, every_vertex[every_vertex.len() - 1].position);
+
+        // A hundred pixels long, about a point per pixel is kept, and the last point
+        let (quads, _) = straight_line_quads(1000, 100.0, one_px);
+        assert_eq!(quads, 100);
+        assert_eq!(straight_line_quads(1000, 100.0, NO_LINE_LOD).0, 999);
+    }
+
+    #[test]
+    fn the_point_threshold_is_a_pixel_of_the_window() {
+        let (x, y) = line_lod_ndc((1000, 500));
+        assert!((x * 1000.0 - 2.0 * line_lod_px()).abs() < 1e-6 && (y * 500.0 - 2.0 * line_lod_px()).abs() < 1e-6);
+        // A window without a size does not divide by zero
+        assert!(line_lod_ndc((0, 0)).0.is_finite());
+    }
+
+    #[test]
+    fn the_viewport_is_shaded_beyond_the_data() {
+        let mut palette = Palette::new(Style::default().color, true);
+        let shade = palette.add(parse_hex_color(OUTSIDE_DATA_COLOR).unwrap(), false);
+        let edge = palette.add(parse_hex_color(DATA_EDGE_COLOR).unwrap(), false);
+        let view = BBox { min_lat: 55.0, max_lat: 56.0, min_lon: 11.0, max_lon: 12.0 };
+        let vertices_of = |extent: Option<BBox>, index: u32| {
+            let mut chunks = ChunkBuilder::default();
+            generate_data_extent_vertices_and_indices(extent, &palette, &view, &mut chunks);
+            chunks.finish().iter().flat_map(|chunk| &chunk.vertices).filter(|vertex| vertex.palette_index == index).count()
+        };
+
+        // Data in the middle of the viewport is framed by a rectangle on every side, and its edge
+        let middle = BBox { min_lat: 55.4, max_lat: 55.6, min_lon: 11.4, max_lon: 11.6 };
+        assert_eq!(vertices_of(Some(middle), shade), 4 * 4);
+        assert_eq!(vertices_of(Some(middle), edge), 4 * 4);
+
+        // Data to the north leaves a single rectangle covering the viewport
+        let north = BBox { min_lat: 58.0, max_lat: 59.0, min_lon: 11.0, max_lon: 12.0 };
+        assert_eq!(vertices_of(Some(north), shade), 4);
+        assert_eq!(vertices_of(Some(north), edge), 0);
+
+        // Nothing is shaded within the data, or without any
+        let around = view.expand(1.0);
+        assert_eq!(vertices_of(Some(around), shade) + vertices_of(Some(around), edge), 0);
+        assert_eq!(vertices_of(None, shade) + vertices_of(None, edge), 0);
+    }
+
+    fn assert_edges_eq(actual: (f32, f32, f32, f32), expected: (f32, f32, f32, f32)) {
+        let close = [(actual.0, expected.0), (actual.1, expected.1), (actual.2, expected.2), (actual.3, expected.3)]
+            .iter()
+            .all(|(a, e)| (a - e).abs() < 1e-3);
+        assert!(close, "{:?} is not {:?}", actual, expected);
+    }
+
+    #[test]
+    fn the_viewport_is_mapped_onto_the_minimap() {
+        // Near the equator, where Mercator is close to linear
+        let extent = BBox { min_lat: -1.0, max_lat: 1.0, min_lon: 10.0, max_lon: 14.0 };
+        assert_edges_eq(viewport_to_minimap(&extent, &extent), (-1.0, -1.0, 1.0, 1.0));
+
+        // The north east quarter of the extent, the projection puts the north at -1
+        let quarter = BBox { min_lat: 0.0, max_lat: 1.0, min_lon: 12.0, max_lon: 14.0 };
+        assert_edges_eq(viewport_to_minimap(&quarter, &extent), (0.0, -1.0, 1.0, 0.0));
+
+        // A viewport reaching beyond the data stays on the minimap
+        let beyond = BBox { min_lat: -0.5, max_lat: 0.5, min_lon: 13.0, max_lon: 20.0 };
+        let (_, _, right, _) = viewport_to_minimap(&beyond, &extent);
+        assert_eq!(right, 1.0);
+        let far = BBox { min_lat: 10.0, max_lat: 11.0, min_lon: 30.0, max_lon: 31.0 };
+        let (left, bottom, right, top) = viewport_to_minimap(&far, &extent);
+        assert_eq!((left, right), (1.0, 1.0));
+        assert_eq!(bottom, top);
+
+        // A point on the minimap reads back as the coordinate it was mapped from
+        let (x, y) = Projection::for_viewport(&extent).to_ndc(0.5, 13.0);
+        let (lat, lon) = Projection::for_viewport(&extent).ndc_to_lat_lon(x, y);
+        assert!((lat - 0.5).abs() < 1e-6 && (lon - 13.0).abs() < 1e-6, "{} {}", lat, lon);
+    }
+
+    // Geometry of `count` vertices and as many indices, its vertices marked with `mark`
+    fn geometry(layer: MapLayer, count: usize, mark: u32) -> WayGeometry {
+        let vertex = Vertex { position: [0.0; 3], palette_index: mark, shade: 1.0 };
+        WayGeometry { layer, vertices: vec![vertex; count], indices: (0..count as u16).rev().collect() }
+    }
+
+    #[test]
+    fn more_than_two_million_vertices_are_packed_into_chunks_within_the_limit() {
+        let layers = [MapLayer::Highways, MapLayer::Buildings, MapLayer::Other];
+        let sizes: Vec<usize> = (0..4000).map(|index| 4 + index * 7919 % 1000).collect();
+        let total: usize = sizes.iter().sum();
+        assert!(total > 2_000_000, "{}", total);
+
+        let mut chunks = ChunkBuilder::default();
+        for (index, &size) in sizes.iter().enumerate() {
+            chunks.push(geometry(layers[index % 3], size, index as u32));
+        }
+        let chunks = chunks.finish();
+
+        assert!(chunks.len() >= total.div_ceil(MAX_CHUNK_VERTICES));
+        assert_eq!(chunks.iter().map(|chunk| chunk.vertices.len()).sum::<usize>(), total);
+        assert_eq!(chunks.iter().map(|chunk| chunk.indices.len()).sum::<usize>(), total);
+
+        // Every way is whole in one chunk, in the order pushed
+        let mut next_way = 0;
+        for chunk in &chunks {
+            assert!(chunk.vertices.len() <= MAX_CHUNK_VERTICES);
+            assert!(chunk.indices.iter().all(|&index| (index as usize) < chunk.vertices.len()));
+            assert_eq!(chunk.layer_ranges.first().map(|range| range.indices.start), Some(0));
+            assert_eq!(chunk.layer_ranges.last().map(|range| range.indices.end), Some(chunk.indices.len() as u32));
+
+            let mut start = 0;
+            while start < chunk.vertices.len() {
+                let size = sizes[next_way];
+                assert!(chunk.vertices[start..start + size].iter().all(|vertex| vertex.palette_index == next_way as u32));
+                start += size;
+                next_way += 1;
+            }
+        }
+        assert_eq!(next_way, sizes.len());
+    }
+
+    #[test]
+    fn a_way_too_large_for_a_chunk_is_skipped() {
+        let mut chunks = ChunkBuilder::new(100);
+        chunks.push(geometry(MapLayer::Highways, 60, 0));
+        chunks.push(geometry(MapLayer::Highways, 101, 1));
+        chunks.push(geometry(MapLayer::Highways, 0, 2));
+        chunks.push(geometry(MapLayer::Highways, 40, 3));
+        chunks.push(geometry(MapLayer::Highways, 1, 4));
+
+        let sizes: Vec<usize> = chunks.finish().iter().map(|chunk| chunk.vertices.len()).collect();
+        assert_eq!(sizes, [100, 1]);
+    }
+
+    #[test]
+    fn tessellating_twice_gives_the_same_bytes() {
+        let ways = crate::test_support::synthetic_renderable_ways(3000);
+        let style_sheet = StyleSheet::default();
+        let palette = build_palette(&style_sheet);
+        let scene = MapScene { renderable_ways: &ways, relation_ways: &[], style_sheet: &style_sheet, view: crate::test_support::SYNTHETIC_BBOX, extrude_buildings: true };
+        let buffers = || {
+            let mut chunks = ChunkBuilder::default();
+            generate_vertices_and_indices_from_renderable_ways(&scene, &palette, line_lod_ndc((1920, 1080)), &mut chunks);
+            chunks.finish().iter()
+                .map(|chunk| (bytemuck::cast_slice::<Vertex, u8>(&chunk.vertices).to_vec(), bytemuck::cast_slice::<u16, u8>(&chunk.indices).to_vec()))
+                .collect::<Vec<_>>()
+        };
+
+        let first = buffers();
+        assert!(first.iter().any(|(vertices, _)| !vertices.is_empty()));
+        for _ in 0..3 {
+            assert!(buffers() == first, "the tessellated map changed between runs");
+        }
+    }
+
+    #[test]
+    fn smaller_chunks_hold_the_same_tessellated_map() {
+        let ways = crate::test_support::synthetic_renderable_ways(2000);
+        let style_sheet = StyleSheet::default();
+        let palette = build_palette(&style_sheet);
+        let scene = MapScene { renderable_ways: &ways, relation_ways: &[], style_sheet: &style_sheet, view: crate::test_support::SYNTHETIC_BBOX, extrude_buildings: true };
+        let totals = |vertex_limit: usize| {
+            let mut chunks = ChunkBuilder::new(vertex_limit);
+            generate_vertices_and_indices_from_renderable_ways(&scene, &palette, line_lod_ndc((1920, 1080)), &mut chunks);
+            let chunks = chunks.finish();
+            assert!(chunks.iter().all(|chunk| chunk.vertices.len() <= vertex_limit));
+            (chunks.len(), chunks.iter().map(|chunk| chunk.vertices.len()).sum::<usize>(), chunks.iter().map(|chunk| chunk.indices.len()).sum::<usize>())
+        };
+
+        let (whole_chunks, vertices, indices) = totals(MAX_CHUNK_VERTICES);
+        let (small_chunks, small_vertices, small_indices) = totals(1000);
+        assert!(vertices > 0 && small_chunks > whole_chunks);
+        assert_eq!((small_vertices, small_indices), (vertices, indices));
+    }
+
+    #[test]
+    fn a_wheel_notch_and_a_trackpad_swipe_zoom_alike() {
+        assert_eq!(scroll_zoom_levels(&MouseScrollDelta::LineDelta(0.0, 1.0)), ZOOM_LEVELS_PER_LINE);
+        assert_eq!(scroll_zoom_levels(&MouseScrollDelta::LineDelta(3.0, -2.0)), -2.0 * ZOOM_LEVELS_PER_LINE);
+        let swipe = |y: f64| MouseScrollDelta::PixelDelta(winit::dpi::PhysicalPosition::new(50.0, y));
+        assert_eq!(scroll_zoom_levels(&swipe(PIXELS_PER_ZOOM_LEVEL)), 1.0);
+        assert_eq!(scroll_zoom_levels(&swipe(-PIXELS_PER_ZOOM_LEVEL / 4.0)), -0.25);
+
+        // Zooming by a number of levels changes the zoom level by as many
+        let view = BBox { min_lat: 55.67, max_lat: 55.68, min_lon: 12.56, max_lon: 12.58 };
+        let levels = scroll_zoom_levels(&swipe(300.0));
+        let zoomed = Projection::for_viewport(&view).zoomed_about((0.3, -0.6), levels.exp2()).viewport();
+        assert!((zoom_level(&zoomed) - zoom_level(&view) - levels).abs() < 1e-9);
+    }
+
+    #[test]
+    fn only_the_asked_for_line_ends_are_moved_outwards() {
+        let view = BBox { min_lat: 54.99, max_lat: 55.01, min_lon: 11.99, max_lon: 12.01 };
+        let projection = Projection::for_viewport(&view);
+        let line = [(55.0, 12.0), (55.0, 12.001), (55.001, 12.001)];
+        let ndc = |point: (f64, f64)| projection.to_ndc(point.0, point.1);
+
+        let mut points = line;
+        extend_line_ends(&mut points, &projection, 0.01, true, false);
+        assert_eq!(points[1..], line[1..]);
+        // The start moves west, away from the second point, by the distance in NDC
+        let ((x, y), (start_x, start_y)) = (ndc(points[0]), ndc(line[0]));
+        assert!((start_x - x - 0.01).abs() < 1e-4, "{} {}", x, start_x);
+        assert!((y - start_y).abs() < 1e-4);
+
+        let mut points = line;
+        extend_line_ends(&mut points, &projection, 0.01, false, true);
+        assert_eq!(points[..2], line[..2]);
+        // The end moves north, which is down in NDC
+        let ((x, y), (end_x, end_y)) = (ndc(points[2]), ndc(line[2]));
+        assert!((x - end_x).abs() < 1e-4);
+        assert!((end_y - y - 0.01).abs() < 1e-4, "{} {}", y, end_y);
+    }
+
+    #[test]
+    fn a_repeated_end_point_is_extended_along_the_segment_before_it() {
+        let view = BBox { min_lat: 54.99, max_lat: 55.01, min_lon: 11.99, max_lon: 12.01 };
+        let projection = Projection::for_viewport(&view);
+
+        let mut points = [(55.0, 12.0), (55.0, 12.001), (55.0, 12.001)];
+        extend_line_ends(&mut points, &projection, 0.01, false, true);
+        assert!(points[2].1 > 12.001 && (points[2].0 - 55.0).abs() < 1e-9, "{:?}", points[2]);
+        assert_eq!(points[1], (55.0, 12.001));
+
+        // A line of one point, or of one point repeated, has no direction to be extended in
+        let mut point = [(55.0, 12.0)];
+        extend_line_ends(&mut point, &projection, 0.01, true, true);
+        assert_eq!(point, [(55.0, 12.0)]);
+        let mut repeated = [(55.0, 12.0), (55.0, 12.0)];
+        extend_line_ends(&mut repeated, &projection, 0.01, true, true);
+        assert_eq!(repeated, [(55.0, 12.0), (55.0, 12.0)]);
+    }
 }
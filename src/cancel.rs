@@ -0,0 +1,291 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::{Stream, StreamExt};
+
+/// How many rows or ways an operation that can be cancelled goes through between looking at
+/// its token.
+pub const CANCEL_CHECK_BATCH: usize = 1024;
+
+/// Tells a background operation to stop, e.g. once the camera moved on from the viewport it
+/// is for. Clones share the flag, so the event loop keeps one and hands the other to the task.
+///
+/// The operation stops at the next point it looks at the token, e.g. between the batches of
+/// a query read by `try_collect_cancellable`, and fails with `OperationError::Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails with `OperationError::Cancelled` once the token is cancelled, to return early with `?`.
+    pub fn check(&self) -> Result<(), OperationError> {
+        if self.is_cancelled() {
+            return Err(OperationError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// An error of an operation that can be cancelled.
+#[derive(Debug)]
+pub enum OperationError {
+    Sqlx(sqlx::Error),
+    /// The token of the operation was cancelled. This is not a failure to report, whoever
+    /// cancelled it knows.
+    Cancelled,
+}
+
+impl From<sqlx::Error> for OperationError {
+    fn from(err: sqlx::Error) -> Self {
+        OperationError::Sqlx(err)
+    }
+}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationError::Sqlx(e) => write!(f, "Database error: {}", e),
+            OperationError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl StdError for OperationError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            OperationError::Sqlx(e) => Some(e),
+            OperationError::Cancelled => None,
+        }
+    }
+}
+
+/// Collects the rows of a query like `TryStreamExt::try_collect`, looking at the token every
+/// `CANCEL_CHECK_BATCH` rows. Dropping the stream once cancelled stops the query.
+///
+/// ## Returns
+/// * The rows, or `OperationError::Cancelled` once the token is cancelled.
+pub async fn try_collect_cancellable<T, E>(stream: impl Stream<Item = Result<T, E>>, cancel: &CancellationToken) -> Result<Vec<T>, OperationError>
+where
+    OperationError: From<E>,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut rows = Vec::new();
+    cancel.check()?;
+    while let Some(row) = stream.next().await {
+        rows.push(row?);
+        if rows.len() % CANCEL_CHECK_BATCH == 0 {
+            cancel.check()?;
+        }
+    }
+    Ok(rows)
+}
+
+/// The kinds of background operations started from the viewer. Of every kind only the
+/// operation started last runs, see `Operations`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    ViewportStats,
+    Isochrone,
+    Routes,
+}
+
+impl OperationKind {
+    /// Whether the operation is shown in the status line while it runs, so the key bound to
+    /// cancel stops it. The statistics of the viewport are computed whenever the camera stops,
+    /// unasked for.
+    pub fn is_shown(self) -> bool {
+        !matches!(self, OperationKind::ViewportStats)
+    }
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OperationKind::ViewportStats => "computing the statistics of the viewport",
+            OperationKind::Isochrone => "computing the reachable area",
+            OperationKind::Routes => "finding a route",
+        })
+    }
+}
+
+/// A background operation that was started and has not reported back yet.
+///
+/// # Fields
+/// * `id` - Tells the operation from the ones of its kind it replaced. Its result is sent
+///   with it, see `Operations::finish`.
+/// * `started_at` - When the operation was started.
+/// * `cancel` - The token the operation looks at.
+#[derive(Debug, Clone)]
+pub struct RunningOperation {
+    pub kind: OperationKind,
+    pub id: u64,
+    pub started_at: Instant,
+    pub cancel: CancellationToken,
+}
+
+/// The background operations the viewer started, at most one of every kind.
+///
+/// # Fields
+/// * `next_id` - The id of the next operation started.
+/// * `running` - The operations running, in the order they were started.
+#[derive(Debug, Default)]
+pub struct Operations {
+    next_id: u64,
+    running: Vec<RunningOperation>,
+}
+
+impl Operations {
+    /// Starts an operation, cancelling the one of the same kind if it is still running.
+    ///
+    /// ## Returns
+    /// * The id to report the result with and the token for the operation to look at.
+    pub fn start(&mut self, kind: OperationKind, now: Instant) -> (u64, CancellationToken) {
+        self.cancel(kind);
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancel = CancellationToken::default();
+        self.running.push(RunningOperation { kind, id, started_at: now, cancel: cancel.clone() });
+        (id, cancel)
+    }
+
+    /// Notes that an operation reported its result.
+    ///
+    /// ## Returns
+    /// * Whether it is the operation of its kind still running, false for one that was
+    ///   cancelled or replaced, whose result is to be dropped.
+    pub fn finish(&mut self, kind: OperationKind, id: u64) -> bool {
+        let Some(index) = self.running.iter().position(|operation| operation.kind == kind && operation.id == id) else {
+            return false;
+        };
+        self.running.remove(index);
+        true
+    }
+
+    /// Cancels the running operation of a kind.
+    ///
+    /// ## Returns
+    /// * Whether one was running.
+    pub fn cancel(&mut self, kind: OperationKind) -> bool {
+        let Some(index) = self.running.iter().position(|operation| operation.kind == kind) else {
+            return false;
+        };
+        self.running.remove(index).cancel.cancel();
+        true
+    }
+
+    /// Cancels every running operation.
+    ///
+    /// ## Returns
+    /// * The operations cancelled.
+    pub fn cancel_all(&mut self) -> Vec<RunningOperation> {
+        for operation in &self.running {
+            operation.cancel.cancel();
+        }
+        std::mem::take(&mut self.running)
+    }
+
+    /// The operations shown in the status line, in the order they were started.
+    pub fn shown(&self) -> impl Iterator<Item = &RunningOperation> {
+        self.running.iter().filter(|operation| operation.kind.is_shown())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    /// An endless query, counting the rows it produced and yielding to other tasks for each.
+    fn endless_rows(produced: Arc<AtomicUsize>) -> impl Stream<Item = Result<usize, sqlx::Error>> {
+        futures::stream::unfold(0, move |row| {
+            let produced = produced.clone();
+            async move {
+                tokio::task::yield_now().await;
+                produced.fetch_add(1, Ordering::Relaxed);
+                Some((Ok(row), row + 1))
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_operation_stops_within_a_batch_and_reports_cancelled() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let cancel = CancellationToken::default();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let task_cancel = cancel.clone();
+        let task_produced = produced.clone();
+        tokio::spawn(async move {
+            let result = try_collect_cancellable(endless_rows(task_produced), &task_cancel).await;
+            let _ = sender.send(result);
+        });
+
+        // Some of the work is done, then the camera moves on
+        while produced.load(Ordering::Relaxed) < 3 * CANCEL_CHECK_BATCH {
+            tokio::task::yield_now().await;
+        }
+        let at_cancel = produced.load(Ordering::Relaxed);
+        cancel.cancel();
+
+        let result = tokio::time::timeout(Duration::from_secs(5), receiver).await.expect("the operation did not stop").unwrap();
+        assert!(matches!(result, Err(OperationError::Cancelled)), "{:?}", result.map(|rows| rows.len()));
+        let stopped_at = produced.load(Ordering::Relaxed);
+        assert!(stopped_at <= at_cancel + CANCEL_CHECK_BATCH, "{} rows after cancelling at {}", stopped_at, at_cancel);
+        assert_eq!(OperationError::Cancelled.to_string(), "Cancelled");
+    }
+
+    #[tokio::test]
+    async fn an_operation_cancelled_before_it_starts_reads_nothing() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let cancel = CancellationToken::default();
+        cancel.cancel();
+
+        assert!(matches!(try_collect_cancellable(endless_rows(produced.clone()), &cancel).await, Err(OperationError::Cancelled)));
+        assert_eq!(produced.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn an_operation_left_alone_collects_every_row_and_passes_errors_on() {
+        let cancel = CancellationToken::default();
+        let rows = futures::stream::iter((0..3 * CANCEL_CHECK_BATCH + 1).map(Ok::<_, sqlx::Error>));
+        assert_eq!(try_collect_cancellable(rows, &cancel).await.unwrap().len(), 3 * CANCEL_CHECK_BATCH + 1);
+
+        let failing = futures::stream::iter([Ok(1), Err(sqlx::Error::RowNotFound), Ok(2)]);
+        assert!(matches!(try_collect_cancellable(failing, &cancel).await, Err(OperationError::Sqlx(sqlx::Error::RowNotFound))));
+    }
+
+    #[test]
+    fn starting_an_operation_cancels_the_one_of_its_kind() {
+        let mut operations = Operations::default();
+        let now = Instant::now();
+        let (first, first_cancel) = operations.start(OperationKind::Routes, now);
+        let (_, stats_cancel) = operations.start(OperationKind::ViewportStats, now);
+        let (second, second_cancel) = operations.start(OperationKind::Routes, now);
+
+        assert!(first_cancel.is_cancelled() && !second_cancel.is_cancelled() && !stats_cancel.is_cancelled());
+        // The result of the replaced operation is dropped
+        assert!(!operations.finish(OperationKind::Routes, first));
+        // Only the routes are shown, the statistics run unasked for
+        assert_eq!(operations.shown().map(|operation| operation.id).collect::<Vec<_>>(), [second]);
+        assert!(operations.finish(OperationKind::Routes, second));
+        assert!(!operations.cancel(OperationKind::Routes));
+
+        let (_, isochrone_cancel) = operations.start(OperationKind::Isochrone, now);
+        let cancelled = operations.cancel_all();
+        assert_eq!(cancelled.iter().map(|operation| operation.kind).collect::<Vec<_>>(), [OperationKind::ViewportStats, OperationKind::Isochrone]);
+        assert!(stats_cancel.is_cancelled() && isochrone_cancel.is_cancelled());
+        assert_eq!(operations.shown().count(), 0);
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroI64;
+
+use crate::geo::{bbox_of_points, clip_polyline_to_bbox, format_distance, haversine_distance, point_in_polygon, BBox};
+use crate::junctions::{chain_lines, join_chain};
+use crate::osm_entities::RenderableWay;
+
+// Coastlines are drawn with the land on their left and the sea on their right. A single way
+// rarely encloses anything, so the ways are chained into rings around islands and into
+// strands crossing the viewport. The sea is what lies right of the strands, closed along the
+// edge of the viewport, minus the islands.
+
+// Points closer than this in degrees to an edge of the viewport lie on it
+const EDGE_EPSILON_DEG: f64 = 1e-9;
+
+/// A water area assembled from coastlines, see `assemble_coastline`.
+///
+/// # Fields
+/// * `outer` - The closed outline of the water, clockwise on the ground, which the north-down
+///   projection turns counter clockwise on screen like the other areas.
+/// * `holes` - The closed outlines of the islands within it, counter clockwise on the ground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub outer: Vec<(f64, f64)>,
+    pub holes: Vec<Vec<(f64, f64)>>,
+}
+
+/// The outcome of `assemble_coastline`.
+///
+/// # Fields
+/// * `polygons` - The water within the viewport.
+/// * `warnings` - A message for every gap in the coastline closed with a straight segment.
+#[derive(Debug, Clone, Default)]
+pub struct AssembledCoastline {
+    pub polygons: Vec<Polygon>,
+    pub warnings: Vec<String>,
+}
+
+/// Whether a way is a coastline, `natural=coastline`.
+pub fn is_coastline(way: &RenderableWay) -> bool {
+    way.tags.iter().any(|tag| tag.key == "natural" && tag.value == "coastline")
+}
+
+/// Assembles the coastlines among `ways` into the water within a viewport.
+///
+/// The coastlines are chained end to end at their shared nodes without turning any around,
+/// as their direction tells the land from the sea. A chain ending inside the viewport is a
+/// gap in the data, it is closed with a straight segment to the nearest chain starting inside
+/// the viewport, or straight on to the edge if there is none. The strands crossing the viewport
+/// are closed along its edge, walking it clockwise so the sea on their right is enclosed.
+///
+/// Without a strand crossing the viewport, the viewport is sea if the largest ring around an
+/// island lies within it, and land otherwise. A viewport without any coastline is land.
+///
+/// ## Arguments
+/// * `ways` - The ways around the viewport, the ways that are not coastlines are left out.
+/// * `bounds` - The viewport.
+///
+/// ## Returns
+/// * The water polygons with the islands as holes, and the gaps that were closed.
+pub fn assemble_coastline(ways: &[RenderableWay], bounds: &BBox) -> AssembledCoastline {
+    let mut warnings = Vec::new();
+
+    let coastlines: Vec<&RenderableWay> = ways.iter().filter(|way| is_coastline(way) && way.coords.len() >= 2).collect();
+    let node_ids: Vec<Vec<Option<i64>>> = coastlines.iter()
+        .map(|way| way.node_ids.iter().map(|id| id.map(NonZeroI64::get)).collect())
+        .collect();
+    let coords: Vec<&[(f64, f64)]> = coastlines.iter().map(|way| way.coords.as_slice()).collect();
+
+    let mut rings = Vec::new();
+    let mut strands = Vec::new();
+    for chain in chain_lines(&node_ids, false) {
+        let points = join_chain(&chain, &coords);
+        if points.len() >= 4 && points.first() == points.last() {
+            rings.push(points);
+        } else {
+            strands.push(points);
+        }
+    }
+    close_gaps(&mut strands, &mut rings, bounds, &mut warnings);
+
+    // Rings crossing an edge of the viewport are cut into strands like the open coastlines
+    let mut pieces: Vec<Vec<(f64, f64)>> = strands.iter().flat_map(|strand| clip_polyline_to_bbox(strand, bounds)).collect();
+    let mut inner_rings = Vec::new();
+    let mut outer_rings = Vec::new();
+    for ring in rings {
+        if ring.iter().all(|&point| is_within(point, bounds)) {
+            inner_rings.push(ring);
+            continue;
+        }
+
+        // Started outside, so no piece is cut in two where the ring closes
+        let outside = ring.iter().position(|&point| !is_within(point, bounds)).unwrap_or(0);
+        let rotated: Vec<(f64, f64)> = ring[outside..ring.len() - 1].iter().chain(&ring[..=outside]).copied().collect();
+        let ring_pieces = clip_polyline_to_bbox(&rotated, bounds);
+        if ring_pieces.is_empty() {
+            outer_rings.push(ring);
+        }
+        pieces.extend(ring_pieces);
+    }
+    pieces.retain(|piece| piece.len() >= 2 && piece.first() != piece.last());
+
+    let mut outers = if pieces.is_empty() {
+        if viewport_is_water(&inner_rings, &outer_rings, bounds) {
+            let BBox { min_lat, max_lat, min_lon, max_lon } = *bounds;
+            vec![vec![(max_lat, min_lon), (max_lat, max_lon), (min_lat, max_lon), (min_lat, min_lon), (max_lat, min_lon)]]
+        } else {
+            Vec::new()
+        }
+    } else {
+        close_along_edge(pieces, bounds)
+    };
+
+    // Rings with the sea inside are water of their own, the islands are holes in the water around them
+    let (islands, water): (Vec<_>, Vec<_>) = inner_rings.into_iter().partition(|ring| signed_area(ring) > 0.0);
+    outers.extend(water);
+
+    let mut polygons: Vec<Polygon> = outers.into_iter().map(|outer| Polygon { outer, holes: Vec::new() }).collect();
+    for island in islands {
+        let around = polygons.iter_mut()
+            .filter(|polygon| point_in_polygon(island[0], &polygon.outer))
+            .min_by(|a, b| signed_area(&a.outer).abs().total_cmp(&signed_area(&b.outer).abs()));
+        // An island within land is left out, there is no water to cut it from
+        if let Some(polygon) = around {
+            polygon.holes.push(island);
+        }
+    }
+
+    AssembledCoastline { polygons, warnings }
+}
+
+/// Closes the strands ending inside the viewport, see `assemble_coastline`. Strands closed on
+/// themselves are moved to `rings`.
+fn close_gaps(strands: &mut Vec<Vec<(f64, f64)>>, rings: &mut Vec<Vec<(f64, f64)>>, bounds: &BBox, warnings: &mut Vec<String>) {
+    while let Some(index) = strands.iter().position(|strand| is_inside(strand[strand.len() - 1], bounds)) {
+        let end = strands[index][strands[index].len() - 1];
+        let next = strands.iter()
+            .enumerate()
+            .filter(|(_, strand)| is_inside(strand[0], bounds))
+            .min_by(|(_, a), (_, b)| haversine_distance(end, a[0]).total_cmp(&haversine_distance(end, b[0])))
+            .map(|(next, _)| next);
+
+        match next {
+            Some(next) => {
+                let start = strands[next][0];
+                warnings.push(format!("closed a gap of {} in the coastline from {:.6},{:.6} to {:.6},{:.6}", format_distance(haversine_distance(end, start)), end.0, end.1, start.0, start.1));
+
+                if next == index {
+                    let mut ring = strands.swap_remove(index);
+                    ring.push(start);
+                    rings.push(ring);
+                } else {
+                    let following = strands.remove(next);
+                    let index = if next < index { index - 1 } else { index };
+                    strands[index].extend(following);
+                }
+            }
+            None => {
+                let strand = &mut strands[index];
+                let edge = edge_ahead(strand[strand.len() - 2], end, bounds);
+                warnings.push(format!("closed the coastline ending at {:.6},{:.6} straight to the edge of the viewport", end.0, end.1));
+                strand.push(edge);
+            }
+        }
+    }
+
+    // No strand ends inside anymore, so the strands starting inside are not continued by any
+    for strand in strands.iter_mut().filter(|strand| is_inside(strand[0], bounds)) {
+        let start = strand[0];
+        warnings.push(format!("closed the coastline starting at {:.6},{:.6} straight to the edge of the viewport", start.0, start.1));
+        strand.insert(0, edge_ahead(strand[1], start, bounds));
+    }
+}
+
+/// Closes the pieces of coastline crossing the viewport into water polygons. From where a
+/// piece leaves the viewport, the edge is followed clockwise to where the next piece enters,
+/// turning at the corners on the way, until the polygon is back at the piece it started with.
+fn close_along_edge(mut pieces: Vec<Vec<(f64, f64)>>, bounds: &BBox) -> Vec<Vec<(f64, f64)>> {
+    let BBox { min_lat, max_lat, min_lon, max_lon } = *bounds;
+    let corners = [(max_lat, min_lon), (max_lat, max_lon), (min_lat, max_lon), (min_lat, min_lon)];
+    let mut polygons = Vec::new();
+
+    while let Some(first) = pieces.pop() {
+        let first_start = edge_position(first[0], bounds);
+        let mut polygon = first;
+
+        loop {
+            let exit = edge_position(polygon[polygon.len() - 1], bounds);
+            let clockwise = |position: f64| (position - exit).rem_euclid(4.0);
+
+            // The piece entering next along the edge, or the first piece if it comes before any
+            let next = pieces.iter()
+                .enumerate()
+                .map(|(index, piece)| (Some(index), clockwise(edge_position(piece[0], bounds))))
+                .chain([(None, clockwise(first_start))])
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let Some((next, distance)) = next else {
+                break;
+            };
+
+            let mut passed: Vec<(f64, (f64, f64))> = corners.iter()
+                .enumerate()
+                .map(|(index, &corner)| (clockwise(index as f64), corner))
+                .filter(|&(corner_distance, _)| corner_distance > 0.0 && corner_distance < distance)
+                .collect();
+            passed.sort_by(|a, b| a.0.total_cmp(&b.0));
+            polygon.extend(passed.into_iter().map(|(_, corner)| corner));
+
+            match next {
+                Some(index) => polygon.extend(pieces.swap_remove(index)),
+                None => {
+                    polygon.push(polygon[0]);
+                    break;
+                }
+            }
+        }
+
+        polygons.push(polygon);
+    }
+
+    polygons
+}
+
+/// Where on the edge of the viewport a point lies, from 0 at the top left corner growing
+/// clockwise by 1 along every edge. A point off the edge is placed at the nearest point on it.
+fn edge_position(point: (f64, f64), bounds: &BBox) -> f64 {
+    let BBox { min_lat, max_lat, min_lon, max_lon } = *bounds;
+    let (lat, lon) = nearest_edge_point(point, bounds);
+    let along = |value: f64, from: f64, to: f64| if to == from { 0.0 } else { ((value - from) / (to - from)).clamp(0.0, 1.0) };
+
+    if (lat - max_lat).abs() <= EDGE_EPSILON_DEG && lon < max_lon {
+        along(lon, min_lon, max_lon)
+    } else if (lon - max_lon).abs() <= EDGE_EPSILON_DEG && lat > min_lat {
+        1.0 + along(lat, max_lat, min_lat)
+    } else if (lat - min_lat).abs() <= EDGE_EPSILON_DEG && lon > min_lon {
+        2.0 + along(lon, max_lon, min_lon)
+    } else {
+        3.0 + along(lat, min_lat, max_lat)
+    }
+}
+
+/// Where the line from `from` through `to` leaves the viewport past `to`, which lies within it.
+/// The nearest point on the edge if the points are the same.
+fn edge_ahead(from: (f64, f64), to: (f64, f64), bounds: &BBox) -> (f64, f64) {
+    let BBox { min_lat, max_lat, min_lon, max_lon } = *bounds;
+    let (d_lat, d_lon) = (to.0 - from.0, to.1 - from.1);
+
+    // How far along the direction every edge lies it heads to
+    let mut steps = Vec::new();
+    if d_lat > 0.0 {
+        steps.push((max_lat - to.0) / d_lat);
+    } else if d_lat < 0.0 {
+        steps.push((min_lat - to.0) / d_lat);
+    }
+    if d_lon > 0.0 {
+        steps.push((max_lon - to.1) / d_lon);
+    } else if d_lon < 0.0 {
+        steps.push((min_lon - to.1) / d_lon);
+    }
+
+    match steps.into_iter().min_by(f64::total_cmp) {
+        Some(step) => {
+            let (lat, lon) = (to.0 + d_lat * step, to.1 + d_lon * step);
+            (lat.clamp(min_lat, max_lat), lon.clamp(min_lon, max_lon))
+        }
+        None => nearest_edge_point(to, bounds),
+    }
+}
+
+/// The point on the edge of the viewport nearest to a point within it.
+fn nearest_edge_point((lat, lon): (f64, f64), &BBox { min_lat, max_lat, min_lon, max_lon }: &BBox) -> (f64, f64) {
+    let (lat, lon) = (lat.clamp(min_lat, max_lat), lon.clamp(min_lon, max_lon));
+    let distances = [lat - min_lat, max_lat - lat, lon - min_lon, max_lon - lon];
+    let nearest = (0..4).min_by(|&a, &b| distances[a].total_cmp(&distances[b])).unwrap_or(0);
+
+    match nearest {
+        0 => (min_lat, lon),
+        1 => (max_lat, lon),
+        2 => (lat, min_lon),
+        _ => (lat, max_lon),
+    }
+}
+
+/// Whether a point lies within the viewport or on its edge.
+fn is_within((lat, lon): (f64, f64), &BBox { min_lat, max_lat, min_lon, max_lon }: &BBox) -> bool {
+    lat >= min_lat && lat <= max_lat && lon >= min_lon && lon <= max_lon
+}
+
+/// Whether a point lies within the viewport and not on its edge.
+fn is_inside((lat, lon): (f64, f64), &BBox { min_lat, max_lat, min_lon, max_lon }: &BBox) -> bool {
+    lat > min_lat + EDGE_EPSILON_DEG && lat < max_lat - EDGE_EPSILON_DEG && lon > min_lon + EDGE_EPSILON_DEG && lon < max_lon - EDGE_EPSILON_DEG
+}
+
+/// Whether a viewport no strand crosses is sea, see `assemble_coastline`.
+///
+/// ## Arguments
+/// * `inner_rings` - The rings within the viewport.
+/// * `outer_rings` - The rings around the viewport, or around nothing of it.
+fn viewport_is_water(inner_rings: &[Vec<(f64, f64)>], outer_rings: &[Vec<(f64, f64)>], bounds: &BBox) -> bool {
+    let BBox { min_lat, max_lat, min_lon, max_lon } = *bounds;
+    let center = ((min_lat + max_lat) / 2.0, (min_lon + max_lon) / 2.0);
+
+    // The innermost ring around the viewport tells what it lies in, land within an island
+    let around = outer_rings.iter()
+        .filter(|ring| point_in_polygon(center, ring))
+        .min_by(|a, b| signed_area(a).abs().total_cmp(&signed_area(b).abs()));
+    if let Some(ring) = around {
+        return signed_area(ring) < 0.0;
+    }
+
+    inner_rings.iter()
+        .max_by(|a, b| signed_area(a).abs().total_cmp(&signed_area(b).abs()))
+        .is_some_and(|ring| signed_area(ring) > 0.0)
+}
+
+/// Twice the signed area of a ring in square degrees, positive if it runs counter clockwise
+/// on the ground, with the longitude to the right and the latitude up.
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(&(a_lat, a_lon), &(b_lat, b_lon))| a_lon * b_lat - b_lon * a_lat)
+        .sum()
+}
+
+/// The side of a cell of `LandWaterGrid` in degrees, about 2 km north to south.
+pub const LAND_WATER_CELL_DEG: f64 = 0.02;
+/// The most cells a `LandWaterGrid` is built with, so an extract spanning a continent is not
+/// classified cell by cell.
+pub const MAX_LAND_WATER_CELLS: usize = 1_000_000;
+
+/// Whether the ground is land or water, as far as the coastlines tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Surface {
+    Land,
+    Water,
+}
+
+/// A coarse grid telling land from water, for choosing the background of a viewport no
+/// coastline crosses, which `assemble_coastline` would call land even far out at sea.
+///
+/// The cells are `LAND_WATER_CELL_DEG` degrees square, aligned to 0° and numbered by
+/// `(row, column)` northwards and eastwards. A cell a coastline passes through is both, and is
+/// left out like the cells beyond the data, so a viewport within it is not classified.
+///
+/// # Fields
+/// * `cells` - The surface of every cell classified.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LandWaterGrid {
+    pub cells: HashMap<(i32, i32), Surface>,
+}
+
+impl LandWaterGrid {
+    /// The `(row, column)` of the cell a point lies in.
+    pub fn cell_of((lat, lon): (f64, f64)) -> (i32, i32) {
+        ((lat / LAND_WATER_CELL_DEG).floor() as i32, (lon / LAND_WATER_CELL_DEG).floor() as i32)
+    }
+
+    /// The box of a cell.
+    pub fn cell_bbox((row, column): (i32, i32)) -> BBox {
+        BBox {
+            min_lat: row as f64 * LAND_WATER_CELL_DEG,
+            max_lat: (row + 1) as f64 * LAND_WATER_CELL_DEG,
+            min_lon: column as f64 * LAND_WATER_CELL_DEG,
+            max_lon: (column + 1) as f64 * LAND_WATER_CELL_DEG,
+        }
+    }
+
+    /// Classifies the cells covering the imported data from its coastlines, assembled once
+    /// for all of them with `assemble_coastline`. The water is found along the middle of every
+    /// row of cells, so the grid is built in one pass over the outlines per row.
+    ///
+    /// ## Arguments
+    /// * `ways` - The ways of the data, the ways that are not coastlines are left out.
+    /// * `extent` - The extent of the data.
+    ///
+    /// ## Returns
+    /// * The grid, or `None` without any coastline, as then nothing tells the land from the
+    ///   sea, or if the data spans more than `MAX_LAND_WATER_CELLS` cells.
+    pub fn build(ways: &[RenderableWay], extent: &BBox) -> Option<LandWaterGrid> {
+        let coastlines: Vec<&RenderableWay> = ways.iter().filter(|way| is_coastline(way) && way.coords.len() >= 2).collect();
+        if coastlines.is_empty() {
+            return None;
+        }
+
+        let (min_row, min_column) = LandWaterGrid::cell_of((extent.min_lat, extent.min_lon));
+        let (max_row, max_column) = LandWaterGrid::cell_of((extent.max_lat, extent.max_lon));
+        let count = (max_row - min_row + 1) as usize * (max_column - min_column + 1) as usize;
+        if count > MAX_LAND_WATER_CELLS {
+            return None;
+        }
+
+        // The cells every segment of a coastline may pass through, those in its bounding box
+        let mut coastal: HashSet<(i32, i32)> = HashSet::new();
+        for way in &coastlines {
+            for segment in way.coords.windows(2) {
+                let (top, left) = LandWaterGrid::cell_of((segment[0].0.max(segment[1].0), segment[0].1.min(segment[1].1)));
+                let (bottom, right) = LandWaterGrid::cell_of((segment[0].0.min(segment[1].0), segment[0].1.max(segment[1].1)));
+                coastal.extend((bottom..=top).flat_map(|row| (left..=right).map(move |column| (row, column))));
+            }
+        }
+
+        let cells_bbox = LandWaterGrid::cell_bbox((min_row, min_column)).union(&LandWaterGrid::cell_bbox((max_row, max_column)));
+        let assembled = assemble_coastline(ways, &cells_bbox);
+        let outlines: Vec<&[(f64, f64)]> = assembled.polygons.iter()
+            .flat_map(|polygon| std::iter::once(&polygon.outer).chain(&polygon.holes))
+            .map(Vec::as_slice)
+            .collect();
+
+        let mut cells = HashMap::new();
+        for row in min_row..=max_row {
+            // Where the outlines cross the middle of the row, a cell is water with an odd
+            // number of crossings west of its middle
+            let lat = (row as f64 + 0.5) * LAND_WATER_CELL_DEG;
+            let mut crossings: Vec<f64> = outlines.iter()
+                .flat_map(|outline| outline.iter().zip(outline.iter().cycle().skip(1)))
+                .filter(|(a, b)| (a.0 > lat) != (b.0 > lat))
+                .map(|(a, b)| a.1 + (lat - a.0) / (b.0 - a.0) * (b.1 - a.1))
+                .collect();
+            crossings.sort_by(f64::total_cmp);
+
+            for column in min_column..=max_column {
+                if coastal.contains(&(row, column)) {
+                    continue;
+                }
+                let lon = (column as f64 + 0.5) * LAND_WATER_CELL_DEG;
+                let west = crossings.partition_point(|&crossing| crossing < lon);
+                cells.insert((row, column), if west % 2 == 1 { Surface::Water } else { Surface::Land });
+            }
+        }
+
+        Some(LandWaterGrid { cells })
+    }
+
+    /// The surface of a viewport, if every cell it covers has the same one.
+    ///
+    /// ## Returns
+    /// * The surface, or `None` if the viewport covers a cell not classified or both land and water.
+    pub fn classify(&self, view: &BBox) -> Option<Surface> {
+        let (min_row, min_column) = LandWaterGrid::cell_of((view.min_lat, view.min_lon));
+        let (max_row, max_column) = LandWaterGrid::cell_of((view.max_lat, view.max_lon));
+
+        // A viewport covering more cells than there are classified can not have them all classified
+        let count = (max_row as i64 - min_row as i64 + 1) * (max_column as i64 - min_column as i64 + 1);
+        if count > self.cells.len() as i64 {
+            return None;
+        }
+
+        let mut surface = None;
+        for cell in (min_row..=max_row).flat_map(|row| (min_column..=max_column).map(move |column| (row, column))) {
+            let cell_surface = *self.cells.get(&cell)?;
+            if surface.is_some_and(|surface| surface != cell_surface) {
+                return None;
+            }
+            surface = Some(cell_surface);
+        }
+        surface
+    }
+}
+
+/// The surface to draw behind a viewport: what the grid tells, unless a coastline crosses
+/// the viewport, as then its ways are drawn and the viewport is both land and water.
+///
+/// ## Arguments
+/// * `grid` - The grid of the data, if it has one.
+/// * `ways` - The ways around the viewport.
+pub fn viewport_surface(grid: Option<&LandWaterGrid>, ways: &[RenderableWay], view: &BBox) -> Option<Surface> {
+    let crosses_coastline = ways.iter()
+        .filter(|way| is_coastline(way))
+        .filter_map(|way| bbox_of_points(&way.coords))
+        .any(|bbox| bbox.intersects(view));
+    if crosses_coastline {
+        return None;
+    }
+    grid?.classify(view)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::{SimpleNode, Tag};
+
+    const VIEW: BBox = BBox { min_lat: 0.0, max_lat: 1.0, min_lon: 0.0, max_lon: 1.0 };
+
+    /// A coastline through `nodes`, given as `(id, lat, lon)`.
+    fn coast(id: i64, nodes: &[(i64, f64, f64)]) -> RenderableWay {
+        let nodes: Vec<SimpleNode> = nodes.iter().map(|&(id, lat, lon)| SimpleNode { id: Some(id), lat, lon }).collect();
+        RenderableWay::from_nodes(id, &nodes, vec![Tag::new("natural".to_string(), "coastline".to_string())], 0)
+    }
+
+    fn is_water(assembled: &AssembledCoastline, point: (f64, f64)) -> bool {
+        assembled.polygons.iter().any(|polygon| point_in_polygon(point, &polygon.outer) && !polygon.holes.iter().any(|hole| point_in_polygon(point, hole)))
+    }
+
+    /// The area of the water in square degrees.
+    fn water_area(assembled: &AssembledCoastline) -> f64 {
+        assembled.polygons.iter()
+            .map(|polygon| (signed_area(&polygon.outer).abs() - polygon.holes.iter().map(|hole| signed_area(hole).abs()).sum::<f64>()) / 2.0)
+            .sum()
+    }
+
+    #[test]
+    fn an_island_is_a_hole_in_the_sea_around_it() {
+        // Counter clockwise, with the land on the left
+        let island = coast(1, &[(1, 0.4, 0.4), (2, 0.4, 0.6), (3, 0.6, 0.6), (4, 0.6, 0.4), (1, 0.4, 0.4)]);
+
+        let assembled = assemble_coastline(&[island], &VIEW);
+
+        assert!(assembled.warnings.is_empty(), "{:?}", assembled.warnings);
+        assert_eq!(assembled.polygons.len(), 1);
+        assert_eq!(assembled.polygons[0].holes.len(), 1);
+        assert!(is_water(&assembled, (0.1, 0.1)));
+        assert!(!is_water(&assembled, (0.5, 0.5)));
+        assert!((water_area(&assembled) - 0.96).abs() < 1e-9, "{}", water_area(&assembled));
+    }
+
+    #[test]
+    fn a_strand_across_the_viewport_has_the_sea_on_its_right() {
+        // Running east, the land is to the north
+        let eastwards = coast(1, &[(1, 0.5, -0.5), (2, 0.5, 0.5), (3, 0.5, 1.5)]);
+        let assembled = assemble_coastline(std::slice::from_ref(&eastwards), &VIEW);
+
+        assert!(assembled.warnings.is_empty());
+        assert_eq!(assembled.polygons.len(), 1);
+        assert!(is_water(&assembled, (0.25, 0.5)) && !is_water(&assembled, (0.75, 0.5)));
+        assert!((water_area(&assembled) - 0.5).abs() < 1e-9);
+        // Clockwise on the ground, like the outlines of the other water areas
+        assert!(signed_area(&assembled.polygons[0].outer) < 0.0);
+
+        // Running west, the sea is to the north
+        let westwards = coast(1, &[(3, 0.5, 1.5), (2, 0.5, 0.5), (1, 0.5, -0.5)]);
+        let assembled = assemble_coastline(&[westwards], &VIEW);
+        assert!(is_water(&assembled, (0.75, 0.5)) && !is_water(&assembled, (0.25, 0.5)));
+        assert!((water_area(&assembled) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn two_ways_sharing_a_node_form_a_bay() {
+        // From the east down to node 2 and back up to the west, with the land to the south.
+        // The ways come in out of order, they are chained at the node they share
+        let ways = [
+            coast(2, &[(2, 0.3, 0.5), (3, 0.7, -0.1)]),
+            coast(1, &[(1, 0.7, 1.1), (2, 0.3, 0.5)]),
+        ];
+
+        let assembled = assemble_coastline(&ways, &VIEW);
+
+        assert!(assembled.warnings.is_empty(), "{:?}", assembled.warnings);
+        assert_eq!(assembled.polygons.len(), 1);
+        // In the bay, and the open sea beyond it
+        assert!(is_water(&assembled, (0.5, 0.5)));
+        assert!(is_water(&assembled, (0.9, 0.1)));
+        // The land around it
+        assert!(!is_water(&assembled, (0.2, 0.5)));
+        assert!(!is_water(&assembled, (0.35, 0.1)));
+        assert!(!is_water(&assembled, (0.35, 0.9)));
+    }
+
+    #[test]
+    fn gaps_in_the_coastline_are_closed_and_reported() {
+        // The coastline breaks off between 0.4 and 0.6 east
+        let ways = [
+            coast(1, &[(1, 0.5, -0.5), (2, 0.5, 0.4)]),
+            coast(2, &[(3, 0.5, 0.6), (4, 0.5, 1.5)]),
+        ];
+        let assembled = assemble_coastline(&ways, &VIEW);
+        assert_eq!(assembled.warnings.len(), 1);
+        assert!(assembled.warnings[0].starts_with("closed a gap of "), "{}", assembled.warnings[0]);
+        assert!((water_area(&assembled) - 0.5).abs() < 1e-9);
+        assert!(is_water(&assembled, (0.25, 0.5)));
+
+        // With nothing to continue it, the coastline is carried on straight to the edge
+        let assembled = assemble_coastline(&ways[..1], &VIEW);
+        assert_eq!(assembled.warnings.len(), 1);
+        assert!(assembled.warnings[0].ends_with("straight to the edge of the viewport"), "{}", assembled.warnings[0]);
+        assert!((water_area(&assembled) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn without_a_crossing_coastline_the_viewport_is_land_or_sea() {
+        // No coastline at all is land
+        let mut road = coast(1, &[(1, 0.5, -0.5), (2, 0.5, 1.5)]);
+        road.tags = vec![Tag::new("highway".to_string(), "primary".to_string())];
+        assert!(assemble_coastline(&[road], &VIEW).polygons.is_empty());
+
+        // Within a large island all around the viewport
+        let around = coast(1, &[(1, -1.0, -1.0), (2, -1.0, 2.0), (3, 2.0, 2.0), (4, 2.0, -1.0), (1, -1.0, -1.0)]);
+        assert!(assemble_coastline(&[around], &VIEW).polygons.is_empty());
+
+        // A coastline running clockwise within the viewport has the sea inside it
+        let inland_sea = coast(1, &[(1, 0.2, 0.2), (2, 0.8, 0.2), (3, 0.8, 0.8), (4, 0.2, 0.8), (1, 0.2, 0.2)]);
+        let assembled = assemble_coastline(&[inland_sea], &VIEW);
+        assert!(is_water(&assembled, (0.5, 0.5)) && !is_water(&assembled, (0.1, 0.1)));
+    }
+
+    /// An island a degree across in the middle of two degrees of sea.
+    fn island() -> RenderableWay {
+        coast(1, &[(1, 0.5, 0.5), (2, 0.5, 1.5), (3, 1.5, 1.5), (4, 1.5, 0.5), (1, 0.5, 0.5)])
+    }
+
+    const EXTENT: BBox = BBox { min_lat: 0.0, max_lat: 2.0, min_lon: 0.0, max_lon: 2.0 };
+
+    #[test]
+    fn cells_are_numbered_northwards_and_eastwards_from_zero() {
+        assert_eq!(LandWaterGrid::cell_of((0.01, 0.01)), (0, 0));
+        assert_eq!(LandWaterGrid::cell_of((0.05, 0.11)), (2, 5));
+        assert_eq!(LandWaterGrid::cell_of((-0.01, -0.03)), (-1, -2));
+
+        let cell = LandWaterGrid::cell_bbox((2, 5));
+        assert!((cell.min_lat - 0.04).abs() < 1e-12 && (cell.max_lat - 0.06).abs() < 1e-12);
+        assert!((cell.min_lon - 0.10).abs() < 1e-12 && (cell.max_lon - 0.12).abs() < 1e-12);
+    }
+
+    #[test]
+    fn the_grid_tells_the_island_from_the_sea_around_it() {
+        let grid = LandWaterGrid::build(&[island()], &EXTENT).unwrap();
+
+        assert_eq!(grid.cells.get(&LandWaterGrid::cell_of((1.0, 1.0))), Some(&Surface::Land));
+        assert_eq!(grid.cells.get(&LandWaterGrid::cell_of((0.1, 0.1))), Some(&Surface::Water));
+        assert_eq!(grid.cells.get(&LandWaterGrid::cell_of((1.9, 1.0))), Some(&Surface::Water));
+        // The cells the coastline passes through are left out
+        assert_eq!(grid.cells.get(&LandWaterGrid::cell_of((0.5, 1.0))), None);
+        assert_eq!(grid.cells.get(&LandWaterGrid::cell_of((1.0, 1.5))), None);
+
+        let land = BBox { min_lat: 0.9, max_lat: 1.1, min_lon: 0.9, max_lon: 1.1 };
+        let sea = BBox { min_lat: 0.05, max_lat: 0.3, min_lon: 0.05, max_lon: 1.9 };
+        let shore = BBox { min_lat: 0.3, max_lat: 0.7, min_lon: 0.9, max_lon: 1.1 };
+        assert_eq!(grid.classify(&land), Some(Surface::Land));
+        assert_eq!(grid.classify(&sea), Some(Surface::Water));
+        assert_eq!(grid.classify(&shore), None);
+    }
+
+    #[test]
+    fn a_view_is_classified_only_if_all_its_cells_agree() {
+        let grid = LandWaterGrid { cells: HashMap::from([((0, 0), Surface::Land), ((0, 1), Surface::Water), ((0, 2), Surface::Water)]) };
+        let view = |min_lon: f64, max_lon: f64| BBox { min_lat: 0.005, max_lat: 0.015, min_lon, max_lon };
+
+        assert_eq!(grid.classify(&view(0.005, 0.015)), Some(Surface::Land));
+        assert_eq!(grid.classify(&view(0.025, 0.055)), Some(Surface::Water));
+        // Land and water both
+        assert_eq!(grid.classify(&view(0.005, 0.035)), None);
+        // Beyond the data, and around all of it
+        assert_eq!(grid.classify(&view(0.045, 0.065)), None);
+        assert_eq!(grid.classify(&BBox { min_lat: -90.0, max_lat: 90.0, min_lon: -180.0, max_lon: 180.0 }), None);
+    }
+
+    #[test]
+    fn there_is_no_grid_without_a_coastline_or_for_a_continent() {
+        let mut road = island();
+        road.tags = vec![Tag::new("highway".to_string(), "primary".to_string())];
+        assert_eq!(LandWaterGrid::build(&[road], &EXTENT), None);
+
+        let continent = BBox { min_lat: -40.0, max_lat: 40.0, min_lon: -40.0, max_lon: 40.0 };
+        assert_eq!(LandWaterGrid::build(&[island()], &continent), None);
+    }
+
+    #[test]
+    fn a_view_a_coastline_crosses_is_not_classified() {
+        let grid = LandWaterGrid::build(&[island()], &EXTENT).unwrap();
+        let land = BBox { min_lat: 0.9, max_lat: 1.1, min_lon: 0.9, max_lon: 1.1 };
+
+        // Within the island, its coastline's box covers the view and the grid is not asked
+        assert_eq!(viewport_surface(Some(&grid), &[island()], &land), None);
+        // Without the coastline around the view, the grid decides
+        assert_eq!(viewport_surface(Some(&grid), &[], &land), Some(Surface::Land));
+        let sea = BBox { min_lat: 1.7, max_lat: 1.9, min_lon: 0.2, max_lon: 1.8 };
+        assert_eq!(viewport_surface(Some(&grid), &[island()], &sea), Some(Surface::Water));
+        // Without a grid nothing is known
+        assert_eq!(viewport_surface(None, &[], &land), None);
+    }
+}
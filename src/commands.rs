@@ -0,0 +1,645 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::cancel::CancellationToken;
+use crate::coastline::assemble_coastline;
+use crate::database::{self, DedupeOptions, MaintenanceOptions, TagFilter, TagMergePolicy, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use crate::export::{self, RouteFormat};
+use crate::fetcher::{self, ImportOptions, MAPDATA_DIRECTORY};
+use crate::geo::BBox;
+use crate::keybindings::{KeyBindings, KEY_BINDINGS_SETTING};
+use crate::metrics;
+use crate::osm_entities::Node;
+use crate::open_street_map::parse_bbox_argument;
+use crate::routing::{self, RoutingProfile, DEFAULT_ISOCHRONE_CELL_M, DEFAULT_SNAP_DISTANCE_M};
+use crate::utils::MapsType;
+use crate::watch::WatchMode;
+
+/// How many points of interest `--nearest` lists unless told otherwise.
+const DEFAULT_NEAREST_LIMIT: usize = 5;
+
+/// The most elements `--entities-by-user` lists, a prolific mapper changed far more.
+const ENTITIES_BY_USER_LIMIT: usize = 100;
+
+/// The largest box in square degrees `--nodes` pages through, about a region of a country.
+const MAX_NODE_PAGE_AREA_DEG2: f64 = 1.0;
+
+/// What the binary was asked to do, picked by the first of the flags below that is given.
+/// Without any of them the map is opened.
+#[derive(Debug)]
+pub enum Command {
+    /// `--doctor`: checks what the map needs before opening it, without changing the database.
+    Doctor,
+    /// `--download minLon,minLat,maxLon,maxLat`: downloads and imports a box.
+    Download(BBox),
+    /// `--validate`: checks the imported data.
+    Validate,
+    /// `--dump-keybindings`: prints the keys the viewer would use, the saved bindings over the defaults.
+    DumpKeyBindings,
+    /// `--sources`: lists the imported files and downloads.
+    Sources,
+    /// `--summary [--json]`: prints what the database holds.
+    Summary { json: bool },
+    /// `--stats-contributors [minLon,minLat,maxLon,maxLat]`: prints who last changed the most
+    /// elements, optionally within a box.
+    StatsContributors(Option<BBox>),
+    /// `--entities-by-user name`: prints the elements a user last changed, newest first.
+    EntitiesByUser(String),
+    /// `--changeset id`: prints the elements whose stored version was uploaded in a changeset.
+    Changeset(i64),
+    /// `--maintain [--vacuum]`: checks the database for damage and refreshes the statistics
+    /// of the query planner.
+    Maintain(MaintenanceOptions),
+    /// `--delete-source id`: removes what one import added.
+    DeleteSource(i64),
+    /// `--show node|way|relation id [--nested]`: prints one element with everything resolved.
+    Show { maps_type: MapsType, id: i64, nested: bool },
+    /// `--snap lat,lon [max_dist_m]`: snaps a coordinate to the nearest road.
+    Snap { point: (f64, f64), max_dist_m: f64 },
+    /// `--nearest lat,lon key=value|key [limit]`: lists the points of interest nearest to a coordinate.
+    Nearest { point: (f64, f64), tag_filter: TagFilter, limit: usize },
+    /// `--nodes minLon,minLat,maxLon,maxLat [limit] [cursor]`: prints a page of the nodes within
+    /// a box as JSON, with the cursor of the next page if there is one.
+    Nodes { bbox: BBox, limit: usize, after_id: Option<i64> },
+    /// `--route fromLat,fromLon toLat,toLon [gpx|geojson] [car|bicycle|foot]`: writes a route to stdout.
+    Route { from: (f64, f64), to: (f64, f64), format: RouteFormat, profile: RoutingProfile },
+    /// `--coastline minLon,minLat,maxLon,maxLat`: writes the sea within a box to stdout.
+    Coastline(BBox),
+    /// `--isochrone lat,lon minutes [car|bicycle|foot]`: writes the area reachable within some
+    /// minutes to stdout.
+    Isochrone { point: (f64, f64), minutes: f64, profile: RoutingProfile },
+    /// `--apply-diff file.osc`: applies a diff of the OpenStreetMap replication.
+    ApplyDiff(String),
+    /// `--import-all [directory]`: imports every map file of a directory.
+    ImportAll(String),
+    /// `--markers ...`: lists, adds and removes markers, see `MarkerCommand`.
+    Markers(MarkerCommand),
+    /// `--dedupe [--tag-merge newer|union]`: resolves the elements imported more than once.
+    Dedupe(DedupeOptions),
+    /// Opens the map, after importing `--import file.osm`, or what is piped in for `-`.
+    ///
+    /// # Fields
+    /// * `watch_mode` - How new extracts in the map data directory are imported, if they are watched.
+    Map { import: Option<String>, watch_mode: Option<WatchMode> },
+}
+
+/// What `--markers` does.
+#[derive(Debug, PartialEq)]
+pub enum MarkerCommand {
+    /// `list`
+    List,
+    /// `add lat,lon [label] [#rrggbb]`
+    Add { lat: f64, lon: f64, label: String, color: String },
+    /// `remove id`
+    Remove(i64),
+    /// `import file.csv`
+    Import(String),
+}
+
+impl Command {
+    /// Whether the tables are created, or migrated, before the command runs. The commands
+    /// only reading leave the database as it is, and nothing is written to a damaged database
+    /// `--maintain` is checking.
+    pub fn migrates(&self) -> bool {
+        !matches!(
+            self,
+            Command::Doctor | Command::Maintain(_) | Command::Show { .. } | Command::Snap { .. } | Command::Nearest { .. } | Command::Nodes { .. }
+                | Command::Route { .. } | Command::Coastline(_) | Command::Isochrone { .. }
+        )
+    }
+}
+
+/// Reads the command from the arguments, see `Command`.
+///
+/// ## Arguments
+/// * `db_url` - The database, a database in memory needs a file to import for the map.
+///
+/// ## Returns
+/// * The command, or the usage if its arguments are missing or invalid.
+pub fn parse_command(args: &[String], db_url: &str) -> Result<Command, String> {
+    let flag = |name: &str| args.iter().position(|arg| arg == name);
+    let has = |name: &str| args.iter().any(|arg| arg == name);
+    // The argument at an offset after a flag, unless it is the next flag
+    let value = |index: usize, offset: usize| args.get(index + offset).filter(|argument| !argument.starts_with("--"));
+
+    if has("--doctor") {
+        return Ok(Command::Doctor);
+    }
+
+    if let Some(index) = flag("--download") {
+        return match args.get(index + 1).map(|argument| parse_bbox_argument(argument)) {
+            Some(Ok(bbox)) => Ok(Command::Download(bbox)),
+            Some(Err(error)) => Err(format!("Invalid box: {}", error)),
+            None => Err("Usage: --download minLon,minLat,maxLon,maxLat".to_string()),
+        };
+    }
+
+    if has("--validate") {
+        return Ok(Command::Validate);
+    }
+    if has("--dump-keybindings") {
+        return Ok(Command::DumpKeyBindings);
+    }
+    if has("--sources") {
+        return Ok(Command::Sources);
+    }
+    if has("--summary") {
+        return Ok(Command::Summary { json: has("--json") });
+    }
+
+    if let Some(index) = flag("--stats-contributors") {
+        return match value(index, 1).map(|argument| parse_bbox_argument(argument)) {
+            Some(Ok(bbox)) => Ok(Command::StatsContributors(Some(bbox))),
+            Some(Err(error)) => Err(format!("Invalid box: {}, usage: --stats-contributors [minLon,minLat,maxLon,maxLat]", error)),
+            None => Ok(Command::StatsContributors(None)),
+        };
+    }
+
+    if let Some(index) = flag("--entities-by-user") {
+        return value(index, 1).map(|user| Command::EntitiesByUser(user.clone())).ok_or_else(|| "Usage: --entities-by-user name".to_string());
+    }
+
+    if let Some(index) = flag("--changeset") {
+        return args.get(index + 1)
+            .and_then(|argument| argument.parse::<i64>().ok())
+            .map(Command::Changeset)
+            .ok_or_else(|| "Usage: --changeset id".to_string());
+    }
+
+    if has("--maintain") {
+        return Ok(Command::Maintain(MaintenanceOptions { vacuum: has("--vacuum") }));
+    }
+
+    if let Some(index) = flag("--delete-source") {
+        return args.get(index + 1)
+            .and_then(|argument| argument.parse::<i64>().ok())
+            .map(Command::DeleteSource)
+            .ok_or_else(|| "Usage: --delete-source id, see --sources for the ids".to_string());
+    }
+
+    if let Some(index) = flag("--show") {
+        let maps_type = args.get(index + 1)
+            .and_then(|argument| argument.parse::<MapsType>().ok())
+            .filter(|maps_type| !matches!(maps_type, MapsType::Other(_)));
+        let id = args.get(index + 2).and_then(|argument| argument.parse::<i64>().ok());
+        let (Some(maps_type), Some(id)) = (maps_type, id) else {
+            return Err("Usage: --show node|way|relation id [--nested]".to_string());
+        };
+        return Ok(Command::Show { maps_type, id, nested: has("--nested") });
+    }
+
+    if let Some(index) = flag("--snap") {
+        let point = args.get(index + 1).and_then(|argument| parse_lat_lon(argument));
+        let max_dist_m = match value(index, 2) {
+            Some(argument) => argument.parse::<f64>().ok().filter(|max_dist_m| *max_dist_m > 0.0),
+            None => Some(DEFAULT_SNAP_DISTANCE_M),
+        };
+        let (Some(point), Some(max_dist_m)) = (point, max_dist_m) else {
+            return Err("Usage: --snap lat,lon [max_dist_m]".to_string());
+        };
+        return Ok(Command::Snap { point, max_dist_m });
+    }
+
+    if let Some(index) = flag("--nearest") {
+        let point = args.get(index + 1).and_then(|argument| parse_lat_lon(argument));
+        let tag_filter = args.get(index + 2).and_then(|argument| argument.parse::<TagFilter>().ok());
+        let limit = match value(index, 3) {
+            Some(argument) => argument.parse::<usize>().ok().filter(|limit| *limit > 0),
+            None => Some(DEFAULT_NEAREST_LIMIT),
+        };
+        let (Some(point), Some(tag_filter), Some(limit)) = (point, tag_filter, limit) else {
+            return Err("Usage: --nearest lat,lon key=value|key [limit]".to_string());
+        };
+        return Ok(Command::Nearest { point, tag_filter, limit });
+    }
+
+    if let Some(index) = flag("--nodes") {
+        let usage = || format!("Usage: --nodes minLon,minLat,maxLon,maxLat [limit] [cursor], a limit up to {}", MAX_PAGE_LIMIT);
+        let bbox = match args.get(index + 1).map(|argument| parse_bbox_argument(argument)) {
+            Some(Ok(bbox)) => bbox,
+            Some(Err(error)) => return Err(format!("Invalid box: {}", error)),
+            None => return Err(usage()),
+        };
+        let area_deg2 = (bbox.max_lat - bbox.min_lat) * (bbox.max_lon - bbox.min_lon);
+        if area_deg2 > MAX_NODE_PAGE_AREA_DEG2 {
+            return Err(format!(
+                "The area of {:.4} square degrees is larger than the allowed {:.4}, split the box",
+                area_deg2, MAX_NODE_PAGE_AREA_DEG2,
+            ));
+        }
+        let limit = match value(index, 2) {
+            Some(argument) => argument.parse::<usize>().ok().filter(|limit| (1..=MAX_PAGE_LIMIT).contains(limit)).ok_or_else(usage)?,
+            None => DEFAULT_PAGE_LIMIT,
+        };
+        let after_id = match value(index, 3) {
+            Some(cursor) => Some(decode_cursor(cursor).ok_or_else(|| format!("Invalid cursor {}, pass the next_cursor of the page before", cursor))?),
+            None => None,
+        };
+        return Ok(Command::Nodes { bbox, limit, after_id });
+    }
+
+    if let Some(index) = flag("--route") {
+        let from = args.get(index + 1).and_then(|argument| parse_lat_lon(argument));
+        let to = args.get(index + 2).and_then(|argument| parse_lat_lon(argument));
+        let options: Vec<&String> = args.iter().skip(index + 3).take_while(|argument| !argument.starts_with("--")).collect();
+        let format = match options.first() {
+            Some(argument) => argument.parse::<RouteFormat>().ok(),
+            None => Some(RouteFormat::default()),
+        };
+        let profile = match options.get(1) {
+            Some(argument) => argument.parse::<RoutingProfile>().ok(),
+            None => Some(RoutingProfile::default()),
+        };
+        let (Some(from), Some(to), Some(format), Some(profile), true) = (from, to, format, profile, options.len() <= 2) else {
+            return Err("Usage: --route fromLat,fromLon toLat,toLon [gpx|geojson] [car|bicycle|foot]".to_string());
+        };
+        return Ok(Command::Route { from, to, format, profile });
+    }
+
+    if let Some(index) = flag("--coastline") {
+        return match args.get(index + 1).map(|argument| parse_bbox_argument(argument)) {
+            Some(Ok(bbox)) => Ok(Command::Coastline(bbox)),
+            Some(Err(error)) => Err(format!("Invalid box: {}", error)),
+            None => Err("Usage: --coastline minLon,minLat,maxLon,maxLat".to_string()),
+        };
+    }
+
+    if let Some(index) = flag("--isochrone") {
+        let point = args.get(index + 1).and_then(|argument| parse_lat_lon(argument));
+        let minutes = args.get(index + 2).and_then(|argument| argument.parse::<f64>().ok()).filter(|minutes| *minutes > 0.0);
+        let profile = match value(index, 3) {
+            Some(argument) => argument.parse::<RoutingProfile>().ok(),
+            None => Some(RoutingProfile::default()),
+        };
+        let (Some(point), Some(minutes), Some(profile)) = (point, minutes, profile) else {
+            return Err("Usage: --isochrone lat,lon minutes [car|bicycle|foot]".to_string());
+        };
+        return Ok(Command::Isochrone { point, minutes, profile });
+    }
+
+    if let Some(index) = flag("--apply-diff") {
+        return value(index, 1).map(|path| Command::ApplyDiff(path.clone())).ok_or_else(|| "Usage: --apply-diff file.osc".to_string());
+    }
+
+    if let Some(index) = flag("--import-all") {
+        let directory = value(index, 1).map_or(MAPDATA_DIRECTORY, String::as_str);
+        return Ok(Command::ImportAll(directory.to_string()));
+    }
+
+    if let Some(index) = flag("--markers") {
+        let options: Vec<&str> = args.iter().skip(index + 1).take_while(|argument| !argument.starts_with("--")).map(String::as_str).collect();
+        return parse_marker_command(&options).map(Command::Markers);
+    }
+
+    if has("--dedupe") {
+        let mut options = DedupeOptions::default();
+        if let Some(index) = flag("--tag-merge") {
+            options.tag_policy = match args.get(index + 1).map(|policy| policy.parse::<TagMergePolicy>()) {
+                Some(Ok(policy)) => policy,
+                Some(Err(error)) => return Err(format!("Invalid tag merge policy: {}", error)),
+                None => return Err("Usage: --dedupe [--tag-merge newer|union]".to_string()),
+            };
+        }
+        return Ok(Command::Dedupe(options));
+    }
+
+    // A database in memory holds nothing else to show than the file imported
+    let import = match flag("--import").map(|index| value(index, 1)) {
+        Some(Some(path)) => Some(path.clone()),
+        None if !database::is_memory_url(db_url) => None,
+        _ => return Err("Usage: [--ephemeral | --db url] --import file.osm, or - to read standard input, a database in memory needs a file to show".to_string()),
+    };
+    // New extracts in the map data directory are imported on a key press, or right away
+    let watch_mode = if has("--watch-auto") {
+        Some(WatchMode::Auto)
+    } else if has("--watch") {
+        Some(WatchMode::Prompt)
+    } else {
+        None
+    };
+    Ok(Command::Map { import, watch_mode })
+}
+
+/// Reads the options following `--markers`, see `MarkerCommand`.
+fn parse_marker_command(options: &[&str]) -> Result<MarkerCommand, String> {
+    let usage = || "Usage: --markers list | add lat,lon [label] [#rrggbb] | remove id | import file.csv".to_string();
+    match *options {
+        ["list"] => Ok(MarkerCommand::List),
+        ["add", point, ref rest @ ..] if rest.len() <= 2 => {
+            let (lat, lon) = parse_lat_lon(point).ok_or_else(usage)?;
+            let label = rest.first().map_or_else(|| database::default_marker_label(lat, lon), |label| label.to_string());
+            let color = rest.get(1).copied().unwrap_or(database::DEFAULT_MARKER_COLOR);
+            database::validate_marker(lat, lon, color).map_err(|reason| format!("Not adding the marker, {}", reason))?;
+            Ok(MarkerCommand::Add { lat, lon, label, color: color.to_string() })
+        }
+        ["remove", id] => id.parse::<i64>().map(MarkerCommand::Remove).map_err(|_| usage()),
+        ["import", path] => Ok(MarkerCommand::Import(path.to_string())),
+        _ => Err(usage()),
+    }
+}
+
+/// The cursor of `--nodes` to pass for the page after the node with an id. It is opaque to
+/// its users, so how pages are cut may change.
+fn encode_cursor(after_id: i64) -> String {
+    format!("n{:x}", after_id)
+}
+
+/// Reads a cursor of `encode_cursor`.
+fn decode_cursor(cursor: &str) -> Option<i64> {
+    let hex = cursor.strip_prefix('n')?;
+    u64::from_str_radix(hex, 16).ok().map(|after_id| after_id as i64)
+}
+
+/// A page of `--nodes`, see `database::Page`.
+#[derive(Serialize)]
+struct NodePage {
+    nodes: Vec<Node>,
+    next_cursor: Option<String>,
+}
+
+/// Parses a `lat,lon` argument.
+fn parse_lat_lon(argument: &str) -> Option<(f64, f64)> {
+    let (lat, lon) = argument.split_once(',')?;
+    Some((lat.trim().parse::<f64>().ok()?, lon.trim().parse::<f64>().ok()?))
+}
+
+/// Runs a command other than `Command::Doctor` and `Command::Map`, which need more than a
+/// pool, printing what it found.
+///
+/// ## Arguments
+/// * `pool` - The database, with its tables created if the command `migrates`.
+/// * `import_options` - How the commands importing data import it.
+/// * `metrics_file` - Where the commands importing data write the metrics, if anywhere.
+///
+/// ## Returns
+/// * Whether the command succeeded, false e.g. if what it looked for does not exist.
+pub async fn run_command(command: Command, pool: &SqlitePool, import_options: &ImportOptions, metrics_file: Option<&Path>) -> Result<bool> {
+    match command {
+        Command::Doctor | Command::Map { .. } => unreachable!("{:?} is run by main", command),
+        Command::Download(bbox) => {
+            let stats = fetcher::download_and_import(pool, &bbox, import_options).await?;
+            println!("Imported {}", stats);
+        }
+        Command::Validate => {
+            let report = database::validate_database(pool).await?;
+            print!("{}", report);
+            return Ok(!report.has_critical_errors());
+        }
+        Command::DumpKeyBindings => {
+            let key_bindings = match database::fetch_setting(pool, KEY_BINDINGS_SETTING).await? {
+                Some(value) => KeyBindings::from_json(&value).unwrap_or_else(|error| {
+                    eprintln!("Using the default keys, {}", error);
+                    KeyBindings::default()
+                }),
+                None => KeyBindings::default(),
+            };
+            println!("{}", key_bindings.to_json());
+        }
+        Command::Sources => {
+            for source in database::fetch_source_files(pool).await? {
+                println!("{}", source);
+            }
+        }
+        Command::Summary { json } => {
+            let summary = database::DatabaseSummary::gather(pool).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&summary)?);
+            } else {
+                print!("{}", summary);
+            }
+        }
+        Command::StatsContributors(bbox) => {
+            for (rank, contributor) in database::top_contributors(pool, bbox.as_ref(), 10).await?.iter().enumerate() {
+                println!("{:>2}. {}", rank + 1, contributor);
+            }
+        }
+        Command::EntitiesByUser(user) => {
+            let entities = database::fetch_entities_by_user(pool, &user, ENTITIES_BY_USER_LIMIT).await?;
+            if entities.is_empty() {
+                println!("No element was last changed by {}", user);
+            }
+            for entity in &entities {
+                println!("{}", entity);
+            }
+            if entities.len() == ENTITIES_BY_USER_LIMIT {
+                println!("Only the newest {} elements are shown", ENTITIES_BY_USER_LIMIT);
+            }
+        }
+        Command::Changeset(changeset_id) => {
+            let entities = database::fetch_entities_in_changeset(pool, changeset_id).await?;
+            if entities.is_empty() {
+                println!("No stored element is in changeset {}", changeset_id);
+            }
+            for entity in &entities {
+                println!("{}", entity);
+            }
+        }
+        Command::Maintain(options) => match database::maintain_database(pool, &options).await {
+            Ok(report) => print!("{}", report),
+            Err(error) => {
+                println!("{}", error);
+                return Ok(false);
+            }
+        },
+        Command::DeleteSource(source_id) => {
+            let deleted = fetcher::delete_source(pool, source_id).await?;
+            println!("Deleted {}", deleted);
+
+            // The rows deleted leave their space in the file and the statistics out of date
+            let unused = database::unused_file_space(pool).await?;
+            if unused > 0 {
+                println!("{} of the database file is unused now, --maintain --vacuum gives it back", database::format_size(unused));
+            }
+        }
+        Command::Show { maps_type, id, nested } => {
+            // With `--nested` a relation is printed with the nodes and ways of its member relations
+            let json = match maps_type {
+                MapsType::Node => database::fetch_node_by_id(pool, id).await?.map(|node| serde_json::to_string_pretty(&node)),
+                MapsType::Way => database::fetch_way_by_id(pool, id).await?.map(|way| serde_json::to_string_pretty(&way)),
+                MapsType::Relation if nested => database::resolve_relation_tree(pool, id, database::MAX_RELATION_DEPTH).await?.map(|relation| serde_json::to_string_pretty(&relation)),
+                MapsType::Relation => database::fetch_relation_by_id(pool, id).await?.map(|relation| serde_json::to_string_pretty(&relation)),
+                MapsType::Other(_) => None,
+            };
+            match json {
+                Some(json) => println!("{}", json?),
+                None => {
+                    println!("No {} {}", maps_type.as_str(), id);
+                    return Ok(false);
+                }
+            }
+        }
+        Command::Snap { point: (lat, lon), max_dist_m } => match routing::snap_to_road(pool, lat, lon, max_dist_m).await? {
+            Some(snap) => print!("{}", snap),
+            None => {
+                println!("No road within {} m", max_dist_m);
+                return Ok(false);
+            }
+        },
+        Command::Nearest { point: (lat, lon), tag_filter, limit } => {
+            let pois = database::find_nearest_poi(pool, lat, lon, &tag_filter, limit).await?;
+            if pois.is_empty() {
+                println!("No {} within {} m", tag_filter, database::NEAREST_POI_MAX_RADIUS_M);
+                return Ok(false);
+            }
+            for poi in pois {
+                println!("{}", poi);
+            }
+        }
+        Command::Nodes { bbox, limit, after_id } => {
+            let page = database::fetch_nodes_in_bbox_page(pool, &bbox, after_id, limit).await?;
+            let page = NodePage { nodes: page.items, next_cursor: page.next_after_id.map(encode_cursor) };
+            println!("{}", serde_json::to_string_pretty(&page)?);
+        }
+        Command::Route { from, to, format, profile } => match routing::route_between(pool, from, to, profile).await? {
+            Some(route) => {
+                let name = format!("Route from {:.5},{:.5} to {:.5},{:.5}", from.0, from.1, to.0, to.1);
+                export::export_route(format, &name, &route.nodes, Some(&route.summary), &route.instructions, std::io::stdout().lock())?;
+
+                // The route itself goes to stdout, so it can be redirected to a file
+                for instruction in &route.instructions {
+                    eprintln!("{}", instruction);
+                }
+            }
+            None => {
+                println!("No route between {:?} and {:?}", from, to);
+                return Ok(false);
+            }
+        },
+        Command::Coastline(bbox) => {
+            let ways = database::fetch_coastline_shapes_in_bbox(pool, &bbox).await?;
+            let coastline = assemble_coastline(&ways, &bbox);
+            for warning in &coastline.warnings {
+                tracing::warn!("{}", warning);
+            }
+            export::export_water_geojson("Sea", &coastline.polygons, std::io::stdout().lock())?;
+        }
+        Command::Isochrone { point, minutes, profile } => {
+            match routing::isochrone_around(pool, point, minutes * 60.0, profile, DEFAULT_ISOCHRONE_CELL_M, &CancellationToken::default()).await? {
+                Some(grid) => {
+                    let name = format!("Reachable within {} min from {:.5},{:.5}", minutes, point.0, point.1);
+                    export::export_isochrone_geojson(&name, minutes * 60.0, &grid.outlines(), std::io::stdout().lock())?;
+                }
+                None => {
+                    println!("No road within {} m of {:?}", DEFAULT_SNAP_DISTANCE_M, point);
+                    return Ok(false);
+                }
+            }
+        }
+        Command::ApplyDiff(path) => {
+            let stats = fetcher::apply_diff_file(pool, &path).await?;
+            print!("{}", stats);
+            if let Some(metrics_file) = metrics_file {
+                metrics::write_metrics_file(pool, metrics_file).await?;
+            }
+        }
+        Command::ImportAll(directory) => {
+            let report = fetcher::import_map_directory(pool, &directory, import_options).await?;
+            print!("{}", report);
+            if let Some(metrics_file) = metrics_file {
+                metrics::write_metrics_file(pool, metrics_file).await?;
+            }
+            return Ok(report.failed.is_none());
+        }
+        Command::Markers(marker_command) => return run_marker_command(pool, marker_command).await,
+        Command::Dedupe(options) => {
+            let report = database::deduplicate(pool, &options).await?;
+            print!("{}", report);
+        }
+    }
+    Ok(true)
+}
+
+/// Runs `--markers`, e.g. to script a set of markers.
+async fn run_marker_command(pool: &SqlitePool, marker_command: MarkerCommand) -> Result<bool> {
+    match marker_command {
+        MarkerCommand::List => {
+            for marker in database::fetch_markers(pool).await? {
+                println!("{}", marker);
+            }
+        }
+        MarkerCommand::Add { lat, lon, label, color } => {
+            let id = database::insert_marker(pool, lat, lon, &label, &color).await?;
+            println!("Added marker {}", id);
+        }
+        MarkerCommand::Remove(id) => {
+            if !database::delete_marker(pool, id).await? {
+                println!("No marker {}", id);
+                return Ok(false);
+            }
+            println!("Removed marker {}", id);
+        }
+        MarkerCommand::Import(path) => {
+            let text = std::fs::read_to_string(path)?;
+            let report = database::import_marker_csv(pool, &text).await?;
+            print!("{}", report);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        std::iter::once("GoogleMapsClone").chain(line.split_whitespace()).map(String::from).collect()
+    }
+
+    const FILE_DB: &str = "sqlite://database/sqlite.db";
+
+    #[test]
+    fn the_first_command_given_is_picked_with_its_arguments() {
+        assert!(matches!(parse_command(&args("--summary --json"), FILE_DB), Ok(Command::Summary { json: true })));
+        assert!(matches!(parse_command(&args("--validate --summary"), FILE_DB), Ok(Command::Validate)));
+        assert!(matches!(
+            parse_command(&args("--snap 55.1,12.5"), FILE_DB),
+            Ok(Command::Snap { point: (55.1, 12.5), max_dist_m }) if max_dist_m == DEFAULT_SNAP_DISTANCE_M
+        ));
+        assert!(matches!(parse_command(&args("--stats-contributors --json"), FILE_DB), Ok(Command::StatsContributors(None))));
+        assert!(matches!(
+            parse_command(&args("--markers add 55.1,12.5 home"), FILE_DB),
+            Ok(Command::Markers(MarkerCommand::Add { label, .. })) if label == "home"
+        ));
+    }
+
+    #[test]
+    fn a_cursor_reads_back_as_the_id_it_was_made_from() {
+        for after_id in [1, 0, -42, i64::MAX, i64::MIN] {
+            assert_eq!(decode_cursor(&encode_cursor(after_id)), Some(after_id));
+        }
+        assert_eq!(decode_cursor("42"), None);
+        assert!(matches!(
+            parse_command(&args(&format!("--nodes 12.5,55.6,12.6,55.7 10 {}", encode_cursor(7))), FILE_DB),
+            Ok(Command::Nodes { limit: 10, after_id: Some(7), .. })
+        ));
+    }
+
+    #[test]
+    fn missing_or_invalid_arguments_give_the_usage() {
+        for line in ["--nodes 10,50,12,52", "--nodes 12.5,55.6,12.6,55.7 0", "--nodes 12.5,55.6,12.6,55.7 10 7", "--download", "--changeset x", "--snap 55.1", "--markers remove x", "--route 1,2 3,4 gpx car extra"] {
+            let usage = parse_command(&args(line), FILE_DB).unwrap_err();
+            assert!(["Usage", "Invalid", "The area"].iter().any(|start| usage.starts_with(start)), "{}: {}", line, usage);
+        }
+    }
+
+    #[test]
+    fn the_map_opens_without_a_command_but_not_on_an_empty_database_in_memory() {
+        assert!(matches!(parse_command(&args("--watch"), FILE_DB), Ok(Command::Map { import: None, watch_mode: Some(WatchMode::Prompt) })));
+        assert!(parse_command(&args(""), database::MEMORY_DB_URL).is_err());
+        assert!(matches!(
+            parse_command(&args("--import -"), database::MEMORY_DB_URL),
+            Ok(Command::Map { import: Some(path), .. }) if path == "-"
+        ));
+    }
+
+    #[test]
+    fn only_the_commands_writing_migrate_the_tables() {
+        assert!(parse_command(&args("--sources"), FILE_DB).unwrap().migrates());
+        assert!(parse_command(&args(""), FILE_DB).unwrap().migrates());
+        assert!(!parse_command(&args("--maintain --vacuum"), FILE_DB).unwrap().migrates());
+        assert!(!parse_command(&args("--show way 1"), FILE_DB).unwrap().migrates());
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+
+use crate::geo::BBox;
+use crate::utils::{to_e7, MapsType};
+
+/// Who last changed an element, and when.
+///
+/// # Fields
+/// * `maps_type` - Whether the element is a node, way or relation.
+/// * `changeset` - The changeset the last version of the element was uploaded in.
+/// * `timestamp` - When that version was uploaded, as OSM writes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityAttribution {
+    pub maps_type: MapsType,
+    pub id: i64,
+    pub version: i32,
+    pub changeset: i64,
+    pub user: String,
+    pub timestamp: String,
+}
+
+impl fmt::Display for EntityAttribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} v{} by {} in changeset {} at {}", self.maps_type.as_str(), self.id, self.version, self.user, self.changeset, self.timestamp)
+    }
+}
+
+/// How many of the stored elements one user last changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Contributor {
+    pub user: String,
+    pub nodes: u64,
+    pub ways: u64,
+    pub relations: u64,
+}
+
+impl Contributor {
+    pub fn total(&self) -> u64 {
+        self.nodes + self.ways + self.relations
+    }
+}
+
+impl fmt::Display for Contributor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} elements ({} nodes, {} ways, {} relations)", self.user, self.total(), self.nodes, self.ways, self.relations)
+    }
+}
+
+// The attribution of every element, the three tables in one, for filtering with `WHERE`
+const ATTRIBUTION_QUERY: &str = "
+    SELECT * FROM (
+        SELECT 'node' AS type, id, version, changeset, [user], timestamp FROM node
+        UNION ALL
+        SELECT 'way' AS type, id, version, changeset, [user], timestamp FROM way
+        UNION ALL
+        SELECT 'relation' AS type, id, version, changeset, [user], timestamp FROM relation
+    )
+";
+
+/// Reads the rows of `ATTRIBUTION_QUERY`.
+fn attributions_from_rows(rows: &[SqliteRow]) -> Result<Vec<EntityAttribution>, sqlx::Error> {
+    rows.iter()
+        .map(|row| {
+            let maps_type = match row.try_get::<&str, _>("type")? {
+                "node" => MapsType::Node,
+                "way" => MapsType::Way,
+                _ => MapsType::Relation,
+            };
+            Ok(EntityAttribution {
+                maps_type,
+                id: row.try_get("id")?,
+                version: row.try_get("version")?,
+                changeset: row.try_get("changeset")?,
+                user: row.try_get("user")?,
+                timestamp: row.try_get("timestamp")?,
+            })
+        })
+        .collect()
+}
+
+/// Fetches the elements a user last changed, newest first, e.g. to review the edits of a
+/// new or suspicious mapper.
+///
+/// ## Arguments
+/// * `user` - The display name of the user, matched exactly.
+/// * `limit` - The most elements fetched.
+pub async fn fetch_entities_by_user(sqlite_pool: &SqlitePool, user: &str, limit: usize) -> Result<Vec<EntityAttribution>, sqlx::Error> {
+    // The newest changes first, as that is what a reviewer looks at
+    let query = format!("{} WHERE [user] = ? ORDER BY timestamp DESC, type, id LIMIT ?", ATTRIBUTION_QUERY);
+    let rows = sqlx::query(&query)
+        .bind(user)
+        .bind(limit as i64)
+        .fetch_all(sqlite_pool)
+        .await?;
+    attributions_from_rows(&rows)
+}
+
+/// Fetches the elements whose stored version was uploaded in a changeset, newest first.
+///
+/// Only the last version of an element is stored, so an element changed again in a later
+/// changeset is not found by the earlier one.
+pub async fn fetch_entities_in_changeset(sqlite_pool: &SqlitePool, changeset_id: i64) -> Result<Vec<EntityAttribution>, sqlx::Error> {
+    let query = format!("{} WHERE changeset = ? ORDER BY timestamp DESC, type, id", ATTRIBUTION_QUERY);
+    let rows = sqlx::query(&query)
+        .bind(changeset_id)
+        .fetch_all(sqlite_pool)
+        .await?;
+    attributions_from_rows(&rows)
+}
+
+// How many elements of a table every user last changed, as `(table, query)`. `?1` to `?4` are
+// the south, north, west and east edges of the box, in degrees for `way_geom` and scaled by
+// 1e7 as `?5` to `?8` for the nodes. `?9` is 0 to count everything regardless of the box.
+const CONTRIBUTOR_QUERIES: [(&str, &str); 3] = [
+    ("node", "
+        SELECT n.[user], COUNT(*) AS count FROM node n
+        WHERE ?9 = 0 OR (n.lat_e7 BETWEEN ?5 AND ?6 AND n.lon_e7 BETWEEN ?7 AND ?8)
+        GROUP BY n.[user]
+    "),
+    ("way", "
+        SELECT w.[user], COUNT(*) AS count FROM way w
+        WHERE ?9 = 0 OR EXISTS (
+            SELECT 1 FROM way_geom g
+            WHERE g.way_id = w.id AND g.max_lat >= ?1 AND g.min_lat <= ?2 AND g.max_lon >= ?3 AND g.min_lon <= ?4
+        )
+        GROUP BY w.[user]
+    "),
+    // A relation counts if any of its node or way members is in the box
+    ("relation", "
+        SELECT r.[user], COUNT(*) AS count FROM relation r
+        WHERE ?9 = 0 OR EXISTS (
+            SELECT 1 FROM member m JOIN node n ON n.id = m.ref_id
+            WHERE m.relation_id = r.id AND m.member_type = 'node' AND n.lat_e7 BETWEEN ?5 AND ?6 AND n.lon_e7 BETWEEN ?7 AND ?8
+        ) OR EXISTS (
+            SELECT 1 FROM member m JOIN way_geom g ON g.way_id = m.ref_id
+            WHERE m.relation_id = r.id AND m.member_type = 'way' AND g.max_lat >= ?1 AND g.min_lat <= ?2 AND g.max_lon >= ?3 AND g.min_lon <= ?4
+        )
+        GROUP BY r.[user]
+    "),
+];
+
+/// Finds the users who last changed the most elements, e.g. to see who maintains an area.
+///
+/// The elements are counted per table with one query each and the counts added up per user.
+///
+/// ## Arguments
+/// * `bbox` - The area to count in, or `None` for all of the data. A way counts if its
+///   bounding box intersects the area, a relation if any of its node or way members is in it.
+/// * `n` - The most contributors returned.
+///
+/// ## Returns
+/// * The contributors with the most elements first, those with as many by name.
+pub async fn top_contributors(sqlite_pool: &SqlitePool, bbox: Option<&BBox>, n: usize) -> Result<Vec<Contributor>, sqlx::Error> {
+    let BBox { min_lat: south, max_lat: north, min_lon: west, max_lon: east } = bbox.copied().unwrap_or_default();
+
+    let mut contributors: HashMap<String, Contributor> = HashMap::new();
+    for (table, query) in CONTRIBUTOR_QUERIES {
+        let rows = sqlx::query(query)
+            .bind(south)
+            .bind(north)
+            .bind(west)
+            .bind(east)
+            .bind(to_e7(south))
+            .bind(to_e7(north))
+            .bind(to_e7(west))
+            .bind(to_e7(east))
+            .bind(bbox.is_some())
+            .fetch_all(sqlite_pool)
+            .await?;
+
+        for row in rows {
+            let user: String = row.try_get("user")?;
+            let count = row.try_get::<i64, _>("count")? as u64;
+            let contributor = contributors.entry(user.clone()).or_insert_with(|| Contributor { user, ..Default::default() });
+            match table {
+                "node" => contributor.nodes += count,
+                "way" => contributor.ways += count,
+                _ => contributor.relations += count,
+            }
+        }
+    }
+
+    let mut contributors: Vec<Contributor> = contributors.into_values().collect();
+    contributors.sort_by(|a, b| b.total().cmp(&a.total()).then_with(|| a.user.cmp(&b.user)));
+    contributors.truncate(n);
+    Ok(contributors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    // Alice mapped the village near 55° N 12° E in changeset 100 and a road far to the north
+    // in changeset 102. Bob moved a node and drew a way and a relation in the village in
+    // changeset 101, after her
+    const TWO_MAPPERS_OSM: &str = r#"<osm version="0.6">
+ <node id="1" lat="55.000" lon="12.000" version="1" changeset="100" user="alice" uid="1" timestamp="2024-01-01T10:00:00Z"/>
+ <node id="2" lat="55.001" lon="12.001" version="1" changeset="100" user="alice" uid="1" timestamp="2024-01-01T10:00:00Z"/>
+ <node id="3" lat="55.002" lon="12.000" version="2" changeset="101" user="bob" uid="2" timestamp="2024-02-01T10:00:00Z"/>
+ <node id="4" lat="56.000" lon="12.000" version="1" changeset="102" user="alice" uid="1" timestamp="2024-03-01T10:00:00Z"/>
+ <node id="5" lat="56.001" lon="12.001" version="1" changeset="102" user="alice" uid="1" timestamp="2024-03-01T10:00:00Z"/>
+ <way id="10" version="1" changeset="100" user="alice" uid="1" timestamp="2024-01-01T10:00:00Z"><nd ref="1"/><nd ref="2"/></way>
+ <way id="11" version="3" changeset="101" user="bob" uid="2" timestamp="2024-02-01T10:00:00Z"><nd ref="2"/><nd ref="3"/></way>
+ <way id="12" version="1" changeset="102" user="alice" uid="1" timestamp="2024-03-01T10:00:00Z"><nd ref="4"/><nd ref="5"/></way>
+ <relation id="20" version="1" changeset="101" user="bob" uid="2" timestamp="2024-02-01T10:00:00Z">
+  <member type="way" ref="11" role=""/><tag k="type" v="route"/>
+ </relation>
+</osm>"#;
+
+    fn ids(entities: &[EntityAttribution]) -> Vec<(MapsType, i64)> {
+        entities.iter().map(|entity| (entity.maps_type.clone(), entity.id)).collect()
+    }
+
+    #[tokio::test]
+    async fn contributors_are_counted_over_every_element_type() {
+        let pool = memory_pool("top_contributors").await;
+        import_osm_xml(&pool, "top_contributors", TWO_MAPPERS_OSM).await;
+
+        let contributors = top_contributors(&pool, None, 10).await.unwrap();
+        assert_eq!(contributors, [
+            Contributor { user: "alice".to_string(), nodes: 4, ways: 2, relations: 0 },
+            Contributor { user: "bob".to_string(), nodes: 1, ways: 1, relations: 1 },
+        ]);
+        assert_eq!(contributors[1].to_string(), "bob: 3 elements (1 nodes, 1 ways, 1 relations)");
+        assert_eq!(top_contributors(&pool, None, 1).await.unwrap().len(), 1);
+
+        // Around the village only, alice's road to the north is left out. Both have three
+        // elements there, so they come by name
+        let village = BBox { min_lat: 54.99, max_lat: 55.01, min_lon: 11.99, max_lon: 12.01 };
+        let contributors = top_contributors(&pool, Some(&village), 10).await.unwrap();
+        assert_eq!(contributors, [
+            Contributor { user: "alice".to_string(), nodes: 2, ways: 1, relations: 0 },
+            Contributor { user: "bob".to_string(), nodes: 1, ways: 1, relations: 1 },
+        ]);
+
+        // North of the village only alice mapped
+        let north = BBox { min_lat: 55.5, max_lat: 56.5, min_lon: 11.5, max_lon: 12.5 };
+        assert_eq!(top_contributors(&pool, Some(&north), 10).await.unwrap(), [Contributor { user: "alice".to_string(), nodes: 2, ways: 1, relations: 0 }]);
+
+        let elsewhere = BBox { min_lat: 10.0, max_lat: 11.0, min_lon: 10.0, max_lon: 11.0 };
+        assert!(top_contributors(&pool, Some(&elsewhere), 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_elements_of_a_user_or_changeset_come_newest_first() {
+        let pool = memory_pool("entities_by_user").await;
+        import_osm_xml(&pool, "entities_by_user", TWO_MAPPERS_OSM).await;
+
+        let alice = fetch_entities_by_user(&pool, "alice", 10).await.unwrap();
+        assert_eq!(ids(&alice), [
+            (MapsType::Node, 4), (MapsType::Node, 5), (MapsType::Way, 12),
+            (MapsType::Node, 1), (MapsType::Node, 2), (MapsType::Way, 10),
+        ]);
+        assert_eq!(ids(&fetch_entities_by_user(&pool, "alice", 2).await.unwrap()), [(MapsType::Node, 4), (MapsType::Node, 5)]);
+        assert!(fetch_entities_by_user(&pool, "carol", 10).await.unwrap().is_empty());
+
+        let changeset = fetch_entities_in_changeset(&pool, 101).await.unwrap();
+        assert_eq!(ids(&changeset), [(MapsType::Node, 3), (MapsType::Relation, 20), (MapsType::Way, 11)]);
+        assert_eq!(changeset[2].to_string(), "way 11 v3 by bob in changeset 101 at 2024-02-01T10:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn users_and_changesets_are_looked_up_by_index() {
+        let pool = memory_pool("attribution_indexes").await;
+        for (table, column) in [("node", "[user]"), ("way", "changeset"), ("relation", "[user]")] {
+            let plan: Vec<String> = sqlx::query(&format!("EXPLAIN QUERY PLAN SELECT id FROM {} WHERE {} = 1", table, column))
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+                .iter()
+                .map(|row| row.get("detail"))
+                .collect();
+            assert!(plan.iter().any(|step| step.contains("USING INDEX")), "{} {}: {:?}", table, column, plan);
+        }
+    }
+}
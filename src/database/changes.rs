@@ -0,0 +1,473 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::open_street_map::{Change, ChangeSet, OsmAction};
+use crate::osm_entities::{Member, Node, Relation, Tag, Way};
+use crate::utils::{to_e7, MapsType};
+
+use super::{InsertError, TagPolicy, TagPolicyStats};
+
+/// What `apply_changeset` did with the changes of a diff.
+///
+/// # Fields
+/// * `created` - Elements inserted because they were not stored yet.
+/// * `modified` - Stored elements replaced by a newer version.
+/// * `deleted` - Elements deleted together with their tags, node references and members.
+/// * `stale` - Changes skipped because the stored element is as new or newer.
+/// * `missing` - Deletions of elements that were not stored.
+/// * `kept` - Deletions skipped because a stored way or relation still refers to the element.
+/// * `tags` - How many tags were cut off or left out on the way in, see `TagPolicy`.
+/// * `warnings` - What was skipped and why, for the changes that need a closer look.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeStats {
+    pub created: u64,
+    pub modified: u64,
+    pub deleted: u64,
+    pub stale: u64,
+    pub missing: u64,
+    pub kept: u64,
+    pub tags: TagPolicyStats,
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for ChangeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} elements created", self.created)?;
+        writeln!(f, "{} elements modified", self.modified)?;
+        writeln!(f, "{} elements deleted", self.deleted)?;
+        writeln!(f, "{} changes older than the stored elements", self.stale)?;
+        writeln!(f, "{} deleted elements were not stored", self.missing)?;
+        writeln!(f, "{} deleted elements kept because they are still referenced", self.kept)?;
+        if !self.tags.is_empty() {
+            writeln!(f, "{}", self.tags)?;
+        }
+
+        for warning in &self.warnings {
+            writeln!(f, "  {}", warning)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The tables an element type is stored in
+struct ElementTables {
+    maps_type: MapsType,
+    table: &'static str,
+    tag_table: &'static str,
+    id_column: &'static str,
+}
+
+const NODE_TABLES: ElementTables = ElementTables { maps_type: MapsType::Node, table: "node", tag_table: "node_tags", id_column: "node_id" };
+const WAY_TABLES: ElementTables = ElementTables { maps_type: MapsType::Way, table: "way", tag_table: "way_tags", id_column: "way_id" };
+const RELATION_TABLES: ElementTables = ElementTables { maps_type: MapsType::Relation, table: "relation", tag_table: "relation_tags", id_column: "relation_id" };
+
+// What to do with a created or modified element
+enum Upsert {
+    Insert,
+    Update,
+    Skip,
+}
+
+/// Applies the changes of an OsmChange file to the database, in a single transaction.
+///
+/// Created and modified elements are inserted, or replace the stored element if their
+/// version is newer, together with their tags, node references and members. Changes that
+/// are not newer than the stored element are skipped, so applying a diff twice changes nothing.
+///
+/// Deleted elements are removed with their tags, node references, members and bounding box.
+/// Deletions run relations first, so a way deleted together with the relation it belongs to
+/// is no longer referenced. An element a stored way or relation still refers to is kept and
+/// reported instead, as deleting it would break the element referring to it.
+///
+/// ## Arguments
+/// * `pool` - The database to change.
+/// * `changes` - The changes read by `read_osc_file`.
+///
+/// ## Returns
+/// * What was changed and skipped.
+pub async fn apply_changeset(pool: &SqlitePool, changes: ChangeSet) -> Result<ChangeStats, InsertError> {
+    let mut stats = ChangeStats::default();
+    let mut tx = pool.begin().await?;
+    // The ways whose bounding box changes
+    let mut moved_ways: BTreeSet<i64> = BTreeSet::new();
+
+    let (node_deletes, node_upserts): (Vec<Change<Node>>, Vec<Change<Node>>) = changes.nodes.into_iter().partition(|change| change.action == OsmAction::Delete);
+    let (way_deletes, way_upserts): (Vec<Change<Way>>, Vec<Change<Way>>) = changes.ways.into_iter().partition(|change| change.action == OsmAction::Delete);
+    let (relation_deletes, relation_upserts): (Vec<Change<Relation>>, Vec<Change<Relation>>) = changes.relations.into_iter().partition(|change| change.action == OsmAction::Delete);
+
+    // Nodes first, so the ways created after them can refer to them
+    for Change { element: node, .. } in node_upserts {
+        let upsert = check_version(&mut tx, &NODE_TABLES, node.id, node.version, &mut stats).await?;
+        match upsert {
+            Upsert::Insert => {
+                sqlx::query("INSERT INTO node (id, lat_e7, lon_e7, version, timestamp, changeset, uid, [user]) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+                    .bind(node.id).bind(to_e7(node.lat)).bind(to_e7(node.lon)).bind(node.version).bind(&node.timestamp).bind(node.changeset).bind(node.uid).bind(&node.user)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Upsert::Update => {
+                sqlx::query("UPDATE node SET lat_e7 = ?, lon_e7 = ?, version = ?, timestamp = ?, changeset = ?, uid = ?, [user] = ? WHERE id = ?")
+                    .bind(to_e7(node.lat)).bind(to_e7(node.lon)).bind(node.version).bind(&node.timestamp).bind(node.changeset).bind(node.uid).bind(&node.user).bind(node.id)
+                    .execute(&mut *tx)
+                    .await?;
+
+                let ways: Vec<i64> = sqlx::query_scalar("SELECT DISTINCT way_id FROM way_nodes WHERE ref_id = ?")
+                    .bind(node.id)
+                    .fetch_all(&mut *tx)
+                    .await?;
+                moved_ways.extend(ways);
+            }
+            Upsert::Skip => continue,
+        }
+        replace_tags(&mut tx, &NODE_TABLES, node.id, &node.tags, &mut stats).await?;
+    }
+
+    for Change { element: way, .. } in way_upserts {
+        let upsert = check_version(&mut tx, &WAY_TABLES, way.id, way.version, &mut stats).await?;
+        match upsert {
+            Upsert::Insert => {
+                sqlx::query("INSERT INTO way (id, version, timestamp, changeset, uid, [user]) VALUES (?, ?, ?, ?, ?, ?)")
+                    .bind(way.id).bind(way.version).bind(&way.timestamp).bind(way.changeset).bind(way.uid).bind(&way.user)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Upsert::Update => {
+                sqlx::query("UPDATE way SET version = ?, timestamp = ?, changeset = ?, uid = ?, [user] = ? WHERE id = ?")
+                    .bind(way.version).bind(&way.timestamp).bind(way.changeset).bind(way.uid).bind(&way.user).bind(way.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Upsert::Skip => continue,
+        }
+        replace_tags(&mut tx, &WAY_TABLES, way.id, &way.tags, &mut stats).await?;
+        replace_way_nodes(&mut tx, way.id, &way.node_refs, &mut stats).await?;
+        moved_ways.insert(way.id);
+    }
+
+    for Change { element: relation, .. } in relation_upserts {
+        let upsert = check_version(&mut tx, &RELATION_TABLES, relation.id, relation.version, &mut stats).await?;
+        match upsert {
+            Upsert::Insert => {
+                sqlx::query("INSERT INTO relation (id, version, timestamp, changeset, uid, [user]) VALUES (?, ?, ?, ?, ?, ?)")
+                    .bind(relation.id).bind(relation.version).bind(&relation.timestamp).bind(relation.changeset).bind(relation.uid).bind(&relation.user)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Upsert::Update => {
+                sqlx::query("UPDATE relation SET version = ?, timestamp = ?, changeset = ?, uid = ?, [user] = ? WHERE id = ?")
+                    .bind(relation.version).bind(&relation.timestamp).bind(relation.changeset).bind(relation.uid).bind(&relation.user).bind(relation.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Upsert::Skip => continue,
+        }
+        replace_tags(&mut tx, &RELATION_TABLES, relation.id, &relation.tags, &mut stats).await?;
+        replace_members(&mut tx, relation.id, &relation.members).await?;
+    }
+
+    // Deletions in reverse, so the references of deleted relations and ways are gone first
+    for Change { element: relation, .. } in relation_deletes {
+        if check_delete(&mut tx, &RELATION_TABLES, relation.id, relation.version, &mut stats).await? {
+            sqlx::query("DELETE FROM member WHERE relation_id = ?").bind(relation.id).execute(&mut *tx).await?;
+            delete_element(&mut tx, &RELATION_TABLES, relation.id).await?;
+        }
+    }
+
+    for Change { element: way, .. } in way_deletes {
+        if check_delete(&mut tx, &WAY_TABLES, way.id, way.version, &mut stats).await? {
+            sqlx::query("DELETE FROM way_geom WHERE way_id = ?").bind(way.id).execute(&mut *tx).await?;
+            sqlx::query("DELETE FROM way_nodes WHERE way_id = ?").bind(way.id).execute(&mut *tx).await?;
+            delete_element(&mut tx, &WAY_TABLES, way.id).await?;
+            moved_ways.remove(&way.id);
+        }
+    }
+
+    for Change { element: node, .. } in node_deletes {
+        if check_delete(&mut tx, &NODE_TABLES, node.id, node.version, &mut stats).await? {
+            delete_element(&mut tx, &NODE_TABLES, node.id).await?;
+        }
+    }
+
+    for way_id in moved_ways {
+        update_way_bbox(&mut tx, way_id).await?;
+    }
+
+    tx.commit().await?;
+    Ok(stats)
+}
+
+/// Decides whether a created or modified element is inserted, replaces the stored one, or is
+/// skipped as stale, and counts it.
+async fn check_version(tx: &mut Transaction<'_, Sqlite>, tables: &ElementTables, id: i64, version: i32, stats: &mut ChangeStats) -> Result<Upsert, sqlx::Error> {
+    let stored_version: Option<i32> = sqlx::query_scalar(&format!("SELECT version FROM {} WHERE id = ?", tables.table))
+        .bind(id)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    Ok(match stored_version {
+        None => {
+            stats.created += 1;
+            Upsert::Insert
+        }
+        Some(stored_version) if stored_version < version => {
+            stats.modified += 1;
+            Upsert::Update
+        }
+        Some(_) => {
+            stats.stale += 1;
+            Upsert::Skip
+        }
+    })
+}
+
+/// Decides whether a deleted element can be deleted, and counts it.
+///
+/// ## Returns
+/// * True if the element is stored, not newer than the deletion and no longer referenced.
+async fn check_delete(tx: &mut Transaction<'_, Sqlite>, tables: &ElementTables, id: i64, version: i32, stats: &mut ChangeStats) -> Result<bool, sqlx::Error> {
+    let stored_version: Option<i32> = sqlx::query_scalar(&format!("SELECT version FROM {} WHERE id = ?", tables.table))
+        .bind(id)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    match stored_version {
+        None => {
+            stats.missing += 1;
+            return Ok(false);
+        }
+        Some(stored_version) if stored_version > version => {
+            stats.stale += 1;
+            return Ok(false);
+        }
+        Some(_) => (),
+    }
+
+    let referenced_by: Option<String> = match tables.maps_type {
+        MapsType::Node => sqlx::query_scalar("
+            SELECT 'way ' || way_id FROM way_nodes WHERE ref_id = ?1
+            UNION ALL
+            SELECT 'relation ' || relation_id FROM member WHERE ref_id = ?1 AND member_type = 'node'
+            LIMIT 1
+        "),
+        MapsType::Way => sqlx::query_scalar("SELECT 'relation ' || relation_id FROM member WHERE ref_id = ? AND member_type = 'way' LIMIT 1"),
+        _ => sqlx::query_scalar("SELECT 'relation ' || relation_id FROM member WHERE ref_id = ? AND member_type = 'relation' LIMIT 1"),
+    }
+        .bind(id)
+        .fetch_optional(&mut **tx)
+        .await?;
+
+    if let Some(referenced_by) = referenced_by {
+        stats.kept += 1;
+        stats.warnings.push(format!("kept {} {}, it is still referenced by {}", tables.maps_type.as_str(), id, referenced_by));
+        return Ok(false);
+    }
+
+    stats.deleted += 1;
+    Ok(true)
+}
+
+/// Replaces the stored tags of an element, cleaned up by the default `TagPolicy` as on import.
+async fn replace_tags(tx: &mut Transaction<'_, Sqlite>, tables: &ElementTables, id: i64, tags: &[Tag], stats: &mut ChangeStats) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("DELETE FROM {} WHERE {} = ?", tables.tag_table, tables.id_column))
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+
+    let insert_query = format!("
+        INSERT OR IGNORE INTO {} ({}, key_id, value_id)
+        SELECT ?, k.id, v.id FROM tag_key k, tag_value v WHERE k.text = ? AND v.text = ?
+    ", tables.tag_table, tables.id_column);
+
+    let policy = TagPolicy::default();
+    for tag in tags {
+        let Some((key, value)) = policy.apply(&tag.key, &tag.value, &mut stats.tags) else {
+            continue;
+        };
+        sqlx::query("INSERT OR IGNORE INTO tag_key (text) VALUES (?)").bind(key.as_ref()).execute(&mut **tx).await?;
+        sqlx::query("INSERT OR IGNORE INTO tag_value (text) VALUES (?)").bind(value.as_ref()).execute(&mut **tx).await?;
+        sqlx::query(&insert_query).bind(id).bind(key.as_ref()).bind(value.as_ref()).execute(&mut **tx).await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the node references of a way. References to nodes that are not stored, e.g.
+/// because they lie outside of the imported area, are left out with a warning.
+async fn replace_way_nodes(tx: &mut Transaction<'_, Sqlite>, way_id: i64, node_refs: &[i64], stats: &mut ChangeStats) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM way_nodes WHERE way_id = ?").bind(way_id).execute(&mut **tx).await?;
+
+    for (seq, &ref_id) in node_refs.iter().enumerate() {
+        let stored: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM node WHERE id = ?)")
+            .bind(ref_id)
+            .fetch_one(&mut **tx)
+            .await?;
+        if !stored {
+            stats.warnings.push(format!("way {} refers to node {}, which is not stored", way_id, ref_id));
+            continue;
+        }
+
+        // The position is that within the way as given, so one left out leaves a gap in them
+        sqlx::query("INSERT INTO way_nodes (way_id, seq, ref_id) VALUES (?, ?, ?)")
+            .bind(way_id)
+            .bind(seq as i64)
+            .bind(ref_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the members of a relation.
+async fn replace_members(tx: &mut Transaction<'_, Sqlite>, relation_id: i64, members: &[Member]) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM member WHERE relation_id = ?").bind(relation_id).execute(&mut **tx).await?;
+
+    for (seq, member) in members.iter().enumerate() {
+        sqlx::query("INSERT INTO member (relation_id, seq, ref_id, member_type, role) VALUES (?, ?, ?, ?, ?)")
+            .bind(relation_id)
+            .bind(seq as i64)
+            .bind(member.ref_id)
+            .bind(member.maps_type.as_str())
+            .bind(&member.role)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes an element and its tags. Its other dependent rows must be deleted before.
+async fn delete_element(tx: &mut Transaction<'_, Sqlite>, tables: &ElementTables, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("DELETE FROM {} WHERE {} = ?", tables.tag_table, tables.id_column))
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(&format!("DELETE FROM {} WHERE id = ?", tables.table))
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Recomputes the bounding box of a way like `update_way_geometry`, or removes it if none of
+/// the nodes of the way are stored.
+async fn update_way_bbox(tx: &mut Transaction<'_, Sqlite>, way_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM way_geom WHERE way_id = ?").bind(way_id).execute(&mut **tx).await?;
+    sqlx::query("
+        INSERT INTO way_geom (way_id, min_lat, min_lon, max_lat, max_lon)
+        SELECT
+            wn.way_id, MIN(n.lat_e7) / 1e7, MIN(n.lon_e7) / 1e7, MAX(n.lat_e7) / 1e7, MAX(n.lon_e7) / 1e7
+        FROM
+            way_nodes wn
+        JOIN node n ON n.id = wn.ref_id
+        WHERE
+            wn.way_id = ?
+        GROUP BY
+            wn.way_id
+    ")
+        .bind(way_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::fetch_way_by_id;
+    use crate::open_street_map::read_osc_from_bytes;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    const SQUARE_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.00080" lon="11.00030" version="1"/>
+ <node id="2" lat="55.00080" lon="11.00130" version="1"/>
+ <node id="3" lat="55.00020" lon="11.00130" version="1"/>
+ <node id="4" lat="55.00020" lon="11.00030" version="1"/>
+ <way id="50" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/></way>
+</osm>
+"#;
+
+    #[tokio::test]
+    async fn a_way_closed_by_a_diff_keeps_its_order_and_closing_node() {
+        let pool = memory_pool("diff_closed_way").await;
+        import_osm_xml(&pool, "diff_closed_way", SQUARE_OSM).await;
+
+        let diff = br#"<osmChange version="0.6">
+ <modify>
+  <way id="50" version="2" timestamp="2024-01-01T00:00:00Z" changeset="1" uid="1" user="a">
+   <nd ref="4"/><nd ref="3"/><nd ref="2"/><nd ref="1"/><nd ref="4"/>
+  </way>
+ </modify>
+</osmChange>"#;
+        let stats = apply_changeset(&pool, read_osc_from_bytes(diff).unwrap()).await.unwrap();
+        assert!(stats.warnings.is_empty(), "{:?}", stats.warnings);
+
+        let way = fetch_way_by_id(&pool, 50).await.unwrap().unwrap();
+        assert_eq!(way.nodes.iter().map(|node| node.id).collect::<Vec<i64>>(), [4, 3, 2, 1, 4]);
+    }
+
+    // Creates node 5, moves node 2, deletes way 50 and tries to delete node 4, which way 51
+    // still refers to. The change of node 1 is no newer than the stored node
+    const DIFF: &[u8] = br#"<osmChange version="0.6">
+ <create>
+  <node id="5" lat="55.001" lon="11.002" version="1" timestamp="2024-01-01T00:00:00Z" changeset="2" uid="1" user="a"><tag k="amenity" v="bench"/></node>
+ </create>
+ <modify>
+  <node id="2" lat="55.0009" lon="11.0014" version="2" timestamp="2024-01-01T00:00:00Z" changeset="2" uid="1" user="a"/>
+  <node id="1" lat="56.0" lon="12.0" version="1" timestamp="2024-01-01T00:00:00Z" changeset="2" uid="1" user="a"/>
+ </modify>
+ <delete>
+  <way id="50" version="2" timestamp="2024-01-01T00:00:00Z" changeset="2" uid="1" user="a"/>
+  <node id="4" version="2" timestamp="2024-01-01T00:00:00Z" changeset="2" uid="1" user="a"/>
+ </delete>
+</osmChange>"#;
+
+    async fn count(pool: &SqlitePool, query: &str) -> i64 {
+        sqlx::query_scalar(query).fetch_one(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_diff_creates_moves_and_deletes_and_keeps_what_is_still_referenced() {
+        let pool = memory_pool("diff_end_state").await;
+        import_osm_xml(&pool, "diff_end_state", &SQUARE_OSM.replace("</osm>", r#" <way id="51" version="1"><nd ref="3"/><nd ref="4"/><tag k="highway" v="path"/></way>
+</osm>"#)).await;
+        sqlx::query("INSERT INTO way_tags (way_id, key_id, value_id) SELECT 50, k.id, v.id FROM tag_key k, tag_value v WHERE k.text = 'highway' AND v.text = 'path'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let stats = apply_changeset(&pool, read_osc_from_bytes(DIFF).unwrap()).await.unwrap();
+        assert_eq!((stats.created, stats.modified, stats.deleted, stats.stale, stats.kept, stats.missing), (1, 1, 1, 1, 1, 0), "{}", stats);
+        assert_eq!(stats.warnings.len(), 1, "{:?}", stats.warnings);
+        assert!(stats.warnings[0].contains("node 4"), "{:?}", stats.warnings);
+
+        let nodes: Vec<(i64, i64, i64, i64)> = sqlx::query_as("SELECT id, lat_e7, lon_e7, version FROM node ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(nodes, [
+            (1, 550_008_000, 110_003_000, 1),
+            (2, 550_009_000, 110_014_000, 2),
+            (3, 550_002_000, 110_013_000, 1),
+            (4, 550_002_000, 110_003_000, 1),
+            (5, 550_010_000, 110_020_000, 1),
+        ]);
+        let bench = count(&pool, "SELECT COUNT(*) FROM node_tags t JOIN tag_key k ON k.id = t.key_id JOIN tag_value v ON v.id = t.value_id WHERE t.node_id = 5 AND k.text = 'amenity' AND v.text = 'bench'").await;
+        assert_eq!(bench, 1);
+
+        // The deleted way leaves nothing behind, the other keeps its nodes
+        assert!(fetch_way_by_id(&pool, 50).await.unwrap().is_none());
+        for table in ["way", "way_nodes", "way_tags", "way_geom"] {
+            let column = if table == "way" { "id" } else { "way_id" };
+            assert_eq!(count(&pool, &format!("SELECT COUNT(*) FROM {} WHERE {} = 50", table, column)).await, 0, "{}", table);
+        }
+        assert_eq!(count(&pool, "SELECT COUNT(*) FROM way_nodes WHERE way_id = 51").await, 2);
+
+        // Applied again, the diff changes nothing
+        let again = apply_changeset(&pool, read_osc_from_bytes(DIFF).unwrap()).await.unwrap();
+        assert_eq!((again.created, again.modified, again.deleted, again.stale, again.missing), (0, 0, 0, 3, 1), "{}", again);
+    }
+}
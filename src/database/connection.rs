@@ -0,0 +1,150 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use sqlx::migrate::MigrateDatabase;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Sqlite, SqlitePool};
+use tracing::{debug, info};
+
+/// How long a connection waits on a locked database before SQLite reports `SQLITE_BUSY`.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The URL of a database held in memory, gone once the program exits.
+pub const MEMORY_DB_URL: &str = "sqlite::memory:";
+
+/// Returns true if `url` names a database held in memory rather than a file, e.g.
+/// `sqlite::memory:` or `sqlite://file:name?mode=memory`.
+pub fn is_memory_url(url: &str) -> bool {
+    url.contains(":memory:") || url.contains("mode=memory")
+}
+
+/// Creates the database file at `url` if it does not exist yet. A database in memory is
+/// created by connecting to it, so nothing is done for one.
+pub async fn prepare_database(url: &str) -> Result<(), sqlx::Error> {
+    if is_memory_url(url) {
+        debug!(url, "database is held in memory");
+    } else if !Sqlite::database_exists(url).await? {
+        info!(url, "creating database");
+        Sqlite::create_database(url).await?;
+    } else {
+        debug!(url, "database already exists");
+    }
+
+    Ok(())
+}
+
+/// Connects to the SQLite database at `url` with a busy timeout, so concurrent
+/// readers and writers (e.g. the GUI and an import) wait for each other instead of failing.
+///
+/// Every connection to a database in memory opens a database of its own, unless they share
+/// a cache, and the shared database is dropped once its last connection closes. So the
+/// connections to one share their cache, and one of them is kept open for as long as the pool.
+pub async fn connect_pool(url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = SqliteConnectOptions::from_str(url)?
+        .busy_timeout(BUSY_TIMEOUT);
+
+    if !is_memory_url(url) {
+        return SqlitePool::connect_with(options).await;
+    }
+
+    SqlitePoolOptions::new()
+        .min_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options.shared_cache(true))
+        .await
+}
+
+/// Returns true if the database of `pool` is held in memory, which has no file name.
+pub async fn is_in_memory(pool: &SqlitePool) -> Result<bool, sqlx::Error> {
+    let file: String = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(file.is_empty())
+}
+
+/// The variable limit of SQLite builds older than 3.32.0 without a custom limit.
+pub const LEGACY_MAX_VARIABLE_NUMBER: usize = 999;
+/// The variable limit of SQLite 3.32.0 and newer without a custom limit.
+pub const DEFAULT_MAX_VARIABLE_NUMBER: usize = 32766;
+
+/// Finds how many bound variables a single statement may use on the linked SQLite.
+///
+/// A limit set at compile time is listed by `PRAGMA compile_options`, otherwise the
+/// built-in default of the SQLite version applies.
+pub async fn max_variable_number(pool: &SqlitePool) -> Result<usize, sqlx::Error> {
+    let options: Vec<String> = sqlx::query_scalar("PRAGMA compile_options")
+        .fetch_all(pool)
+        .await?;
+
+    let compiled_limit = options.iter()
+        .find_map(|option| option.strip_prefix("MAX_VARIABLE_NUMBER="))
+        .and_then(|limit| limit.parse().ok());
+    if let Some(limit) = compiled_limit {
+        return Ok(limit);
+    }
+
+    let version: String = sqlx::query_scalar("SELECT sqlite_version()")
+        .fetch_one(pool)
+        .await?;
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().unwrap_or(0));
+    let (major, minor) = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+
+    if (major, minor) >= (3, 32) {
+        Ok(DEFAULT_MAX_VARIABLE_NUMBER)
+    } else {
+        Ok(LEGACY_MAX_VARIABLE_NUMBER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{create_tables, fetch_all_renderable_ways, fetch_data_extent};
+    use crate::test_support::import_osm_xml;
+
+    #[test]
+    fn memory_urls_are_told_from_files() {
+        assert!(is_memory_url(MEMORY_DB_URL));
+        assert!(is_memory_url("sqlite://file:scratch?mode=memory&cache=shared"));
+        assert!(!is_memory_url("sqlite://database/sqlite.db"));
+        assert!(!is_memory_url("sqlite:///tmp/memory.db?mode=rwc"));
+    }
+
+    // The only test connecting to `MEMORY_DB_URL`, as every shared cache pool on it in this
+    // process opens the same database
+    #[tokio::test]
+    async fn an_import_is_fetched_back_from_a_database_held_in_memory() {
+        prepare_database(MEMORY_DB_URL).await.unwrap();
+        let pool = connect_pool(MEMORY_DB_URL).await.unwrap();
+        assert!(is_in_memory(&pool).await.unwrap());
+        create_tables(&pool).await.unwrap();
+
+        // The tables created on one connection are seen by every other one of the pool
+        let mut connections = Vec::new();
+        for _ in 0..3 {
+            connections.push(pool.acquire().await.unwrap());
+        }
+        for connection in &mut connections {
+            let tables: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'way_nodes'")
+                .fetch_one(&mut **connection)
+                .await
+                .unwrap();
+            assert_eq!(tables, 1);
+        }
+        drop(connections);
+
+        let stats = import_osm_xml(&pool, "ephemeral", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="11.0" version="1"/>
+ <node id="2" lat="55.1" lon="11.1" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/></way>
+</osm>"#).await;
+        assert_eq!((stats.nodes, stats.ways), (2, 1));
+
+        let ways = fetch_all_renderable_ways(&pool).await.unwrap();
+        assert_eq!(ways.len(), 1);
+        assert_eq!(ways[0].coords, [(55.0, 11.0), (55.1, 11.1)]);
+        assert!(fetch_data_extent(&pool).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use sqlx::{Row, Sqlite, SqlitePool, Transaction};
+
+use crate::geo::haversine_distance;
+use crate::utils::MapsType;
+
+use super::{update_way_geometry, InsertConfig, InsertError};
+
+/// How many moved nodes the report lists.
+pub const DEDUPE_SAMPLE_SIZE: usize = 10;
+
+/// How tags of an element imported more than once are merged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMergePolicy {
+    /// Conflicting tags take the value of the newest version of the element.
+    KeepNewer,
+    /// Conflicting tags keep every value, separated by `;` as is usual in OSM.
+    Union,
+}
+
+impl FromStr for TagMergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newer" => Ok(TagMergePolicy::KeepNewer),
+            "union" => Ok(TagMergePolicy::Union),
+            other => Err(format!("unknown tag merge policy '{}', expected 'newer' or 'union'", other)),
+        }
+    }
+}
+
+/// Options for `deduplicate`.
+///
+/// # Fields
+/// * `tag_policy` - How conflicting tags are merged.
+/// * `moved_threshold_m` - Nodes whose imports lie further apart than this are reported.
+#[derive(Debug, Clone)]
+pub struct DedupeOptions {
+    pub tag_policy: TagMergePolicy,
+    pub moved_threshold_m: f64,
+}
+
+impl Default for DedupeOptions {
+    fn default() -> Self {
+        DedupeOptions {
+            tag_policy: TagMergePolicy::KeepNewer,
+            moved_threshold_m: 1.0,
+        }
+    }
+}
+
+/// What `deduplicate` changed.
+///
+/// # Fields
+/// * `updated` - How many elements of each type were replaced by a newer imported version.
+/// * `moved_nodes` - The nodes whose imports lie further apart than the threshold, with the distance in meters, furthest first.
+/// * `tags_merged` - How many tags, counting every spelling of a key once per element, were merged.
+#[derive(Debug, Clone, Default)]
+pub struct DedupeReport {
+    pub updated: Vec<(MapsType, u64)>,
+    pub moved_nodes: Vec<(i64, f64)>,
+    pub tags_merged: u64,
+}
+
+impl fmt::Display for DedupeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (maps_type, count) in &self.updated {
+            writeln!(f, "{} {}s replaced by a newer version", count, maps_type.as_str())?;
+        }
+        writeln!(f, "{} tags merged", self.tags_merged)?;
+        writeln!(f, "{} nodes moved between imports", self.moved_nodes.len())?;
+
+        for (id, distance_m) in self.moved_nodes.iter().take(DEDUPE_SAMPLE_SIZE) {
+            writeln!(f, "  node {} moved {:.1} m", id, distance_m)?;
+        }
+        if self.moved_nodes.len() > DEDUPE_SAMPLE_SIZE {
+            writeln!(f, "  ...")?;
+        }
+
+        Ok(())
+    }
+}
+
+// The newest recorded duplicate of every element of one type
+const BEST_DUPLICATES: &str = "
+    WITH best AS (
+        SELECT * FROM (
+            SELECT d.*, ROW_NUMBER() OVER (PARTITION BY d.id ORDER BY d.version DESC, d.timestamp DESC) AS rank
+            FROM import_duplicate d
+            WHERE d.maps_type = ?
+        )
+        WHERE rank = 1
+    )
+";
+
+/// Resolves the elements and tags recorded while importing overlapping extracts, so every
+/// element is left with a single version and a single value per tag.
+///
+/// Elements keep the higher version, ties are broken by the newer timestamp. Node references
+/// and members are not recorded per version, so those of ways and relations are left as imported.
+///
+/// ## Arguments
+/// * `pool` - The database to clean up.
+/// * `options` - The tag merge policy and the threshold for reporting moved nodes.
+///
+/// ## Returns
+/// * A summary of the changes.
+pub async fn deduplicate(pool: &SqlitePool, options: &DedupeOptions) -> Result<DedupeReport, InsertError> {
+    let mut report = DedupeReport::default();
+    let mut tx = pool.begin().await?;
+
+    report.moved_nodes = find_moved_nodes(&mut tx, options.moved_threshold_m).await?;
+    // The nodes given the position of another import, which moves their ways
+    let mut replaced_nodes = Vec::new();
+
+    let tables = [
+        (MapsType::Node, "node", "lat_e7 = ROUND(best.lat * 1e7), lon_e7 = ROUND(best.lon * 1e7),", "node_tags", "node_id"),
+        (MapsType::Way, "way", "", "way_tags", "way_id"),
+        (MapsType::Relation, "relation", "", "relation_tags", "relation_id"),
+    ];
+
+    for (maps_type, table, extra_columns, tag_table, id_column) in tables {
+        // Which elements get replaced decides which tags count as newer
+        let newer_query = format!("
+            {}
+            SELECT best.id
+            FROM best
+            JOIN {table} e ON e.id = best.id
+            WHERE best.version > e.version OR (best.version = e.version AND best.timestamp > e.timestamp)
+        ", BEST_DUPLICATES, table = table);
+        let newer: HashSet<i64> = sqlx::query_scalar(&newer_query)
+            .bind(maps_type.as_str())
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .collect();
+        if maps_type == MapsType::Node {
+            replaced_nodes.extend(newer.iter().copied());
+        }
+
+        let update_query = format!("
+            {}
+            UPDATE {table}
+            SET {extra_columns} version = best.version, timestamp = best.timestamp, changeset = best.changeset, uid = best.uid, [user] = best.[user]
+            FROM best
+            WHERE {table}.id = best.id
+                AND (best.version > {table}.version OR (best.version = {table}.version AND best.timestamp > {table}.timestamp))
+        ", BEST_DUPLICATES, table = table, extra_columns = extra_columns);
+        let updated = sqlx::query(&update_query)
+            .bind(maps_type.as_str())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        report.tags_merged += merge_tags(&mut tx, maps_type.as_str(), tag_table, id_column, &newer, options.tag_policy).await?;
+        report.updated.push((maps_type, updated));
+    }
+
+    sqlx::query("DELETE FROM import_duplicate").execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM import_duplicate_tag").execute(&mut *tx).await?;
+    tx.commit().await?;
+
+    // Moved nodes change the bounding boxes of their ways
+    if !replaced_nodes.is_empty() {
+        update_way_geometry(pool, &[], &replaced_nodes, &InsertConfig::default()).await?;
+    }
+
+    Ok(report)
+}
+
+/// Records the stored versions of elements an import is about to replace, like the triggers
+/// record the versions an insert leaves out, so `deduplicate` still picks the higher version
+/// of elements imported from overlapping extracts in any order. Their tags are recorded as
+/// well, `forget_unchanged_tags` drops those the new version does not conflict with.
+///
+/// ## Arguments
+/// * `table` - The table of the elements, `node`, `way` or `relation`.
+/// * `ids` - The elements to be replaced.
+pub(super) async fn record_replaced_versions(sqlite_pool: &SqlitePool, table: &str, ids: &[i64], config: &InsertConfig) -> Result<(), InsertError> {
+    let position = if table == "node" { "lat_e7 / 1e7, lon_e7 / 1e7" } else { "NULL, NULL" };
+    let element_sql = format!("
+        INSERT OR IGNORE INTO import_duplicate
+        SELECT ?, id, version, timestamp, changeset, uid, [user], {position}
+        FROM {table}
+        WHERE id IN ({{ids}})
+    ");
+    let tag_sql = format!("
+        INSERT OR IGNORE INTO import_duplicate_tag
+        SELECT ?, t.{table}_id, k.text, v.text
+        FROM {table}_tags t
+        JOIN tag_key k ON k.id = t.key_id
+        JOIN tag_value v ON v.id = t.value_id
+        WHERE t.{table}_id IN ({{ids}})
+    ");
+
+    run_for_ids(sqlite_pool, &element_sql, table, ids, config).await?;
+    run_for_ids(sqlite_pool, &tag_sql, table, ids, config).await
+}
+
+/// Drops the tags `record_replaced_versions` recorded that do not conflict with the tags of
+/// the new version: those it has with the same value, and those of keys it no longer has,
+/// unless the version replaced is the higher one and `deduplicate` is to bring it back.
+pub(super) async fn forget_unchanged_tags(sqlite_pool: &SqlitePool, table: &str, ids: &[i64], config: &InsertConfig) -> Result<(), InsertError> {
+    let sql = format!("
+        DELETE FROM import_duplicate_tag
+        WHERE maps_type = ? AND id IN ({{ids}}) AND (
+            (
+                NOT EXISTS (
+                    SELECT 1 FROM {table}_tags t JOIN tag_key k ON k.id = t.key_id
+                    WHERE t.{table}_id = import_duplicate_tag.id AND k.text = import_duplicate_tag.[key] COLLATE NOCASE
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM import_duplicate d JOIN {table} e ON e.id = d.id
+                    WHERE d.maps_type = import_duplicate_tag.maps_type AND d.id = import_duplicate_tag.id
+                        AND (d.version > e.version OR (d.version = e.version AND d.timestamp > e.timestamp))
+                )
+            )
+            OR EXISTS (
+                SELECT 1 FROM {table}_tags t JOIN tag_key k ON k.id = t.key_id JOIN tag_value v ON v.id = t.value_id
+                WHERE t.{table}_id = import_duplicate_tag.id AND k.text = import_duplicate_tag.[key] AND v.text = import_duplicate_tag.value
+            )
+        )
+    ");
+
+    run_for_ids(sqlite_pool, &sql, table, ids, config).await
+}
+
+/// Runs a statement binding the type of element and then the ids in place of `{ids}`, as
+/// many at a time as the variable limit allows. Every batch is retried while the database
+/// is busy, see `RetryPolicy`.
+async fn run_for_ids(sqlite_pool: &SqlitePool, sql: &str, maps_type: &str, ids: &[i64], config: &InsertConfig) -> Result<(), InsertError> {
+    for chunk in ids.chunks(config.max_variable_number.saturating_sub(1).max(1)) {
+        let sql = sql.replace("{ids}", &vec!["?"; chunk.len()].join(", "));
+        config.retry_policy.run(|| {
+            let mut query = sqlx::query(&sql).bind(maps_type);
+            for id in chunk {
+                query = query.bind(*id);
+            }
+            query.execute(sqlite_pool)
+        }).await?;
+    }
+
+    Ok(())
+}
+
+/// Compares the stored position of every node imported more than once with its other imports.
+async fn find_moved_nodes(tx: &mut Transaction<'_, Sqlite>, threshold_m: f64) -> Result<Vec<(i64, f64)>, sqlx::Error> {
+    let rows = sqlx::query("
+        SELECT d.id, n.lat_e7 / 1e7 AS lat, n.lon_e7 / 1e7 AS lon, d.lat AS duplicate_lat, d.lon AS duplicate_lon
+        FROM import_duplicate d
+        JOIN node n ON n.id = d.id
+        WHERE d.maps_type = 'node' AND d.lat IS NOT NULL AND d.lon IS NOT NULL
+    ")
+        .fetch_all(&mut **tx)
+        .await?;
+
+    let mut moved: HashMap<i64, f64> = HashMap::new();
+    for row in rows {
+        let stored = (row.try_get("lat")?, row.try_get("lon")?);
+        let duplicate = (row.try_get("duplicate_lat")?, row.try_get("duplicate_lon")?);
+        let distance_m = haversine_distance(stored, duplicate);
+
+        if distance_m > threshold_m {
+            let furthest = moved.entry(row.try_get("id")?).or_insert(0.0);
+            *furthest = furthest.max(distance_m);
+        }
+    }
+
+    let mut moved: Vec<(i64, f64)> = moved.into_iter().collect();
+    moved.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    Ok(moved)
+}
+
+// The stored and the recorded `(key, value)` pairs of one key of one element
+type TagGroup = (Vec<(String, String)>, Vec<(String, String)>);
+
+/// Merges the recorded conflicting tags of one element type into its tag table.
+///
+/// Keys differing only in case are treated as the same key, the spelling already stored wins.
+///
+/// ## Returns
+/// * The number of merged keys.
+async fn merge_tags(
+    tx: &mut Transaction<'_, Sqlite>,
+    maps_type: &str,
+    tag_table: &str,
+    id_column: &str,
+    newer: &HashSet<i64>,
+    policy: TagMergePolicy,
+) -> Result<u64, sqlx::Error> {
+    let duplicates = sqlx::query("SELECT id, [key], value FROM import_duplicate_tag WHERE maps_type = ? ORDER BY rowid")
+        .bind(maps_type)
+        .fetch_all(&mut **tx)
+        .await?;
+
+    // Group by element and key regardless of its case
+    let mut groups: HashMap<(i64, String), TagGroup> = HashMap::new();
+    for row in duplicates {
+        let (id, key, value): (i64, String, String) = (row.try_get("id")?, row.try_get("key")?, row.try_get("value")?);
+        groups.entry((id, key.to_lowercase())).or_default().1.push((key, value));
+    }
+    if groups.is_empty() {
+        return Ok(0);
+    }
+
+    let stored_query = format!("
+        SELECT DISTINCT t.{id_column} AS id, k.text AS [key], v.text AS value
+        FROM {tag_table} t
+        JOIN tag_key k ON k.id = t.key_id
+        JOIN tag_value v ON v.id = t.value_id
+        JOIN (SELECT DISTINCT id, [key] FROM import_duplicate_tag WHERE maps_type = ?) d
+            ON t.{id_column} = d.id AND k.text = d.[key] COLLATE NOCASE
+    ", id_column = id_column, tag_table = tag_table);
+    let stored = sqlx::query(&stored_query)
+        .bind(maps_type)
+        .fetch_all(&mut **tx)
+        .await?;
+
+    for row in stored {
+        let (id, key, value): (i64, String, String) = (row.try_get("id")?, row.try_get("key")?, row.try_get("value")?);
+        if let Some(group) = groups.get_mut(&(id, key.to_lowercase())) {
+            if !group.0.contains(&(key.clone(), value.clone())) {
+                group.0.push((key, value));
+            }
+        }
+    }
+
+    let delete_query = format!("DELETE FROM {} WHERE {} = ? AND key_id IN (SELECT id FROM tag_key WHERE text = ? COLLATE NOCASE)", tag_table, id_column);
+    let insert_query = format!("
+        INSERT OR REPLACE INTO {} ({}, key_id, value_id)
+        SELECT ?, k.id, v.id FROM tag_key k, tag_value v WHERE k.text = ? AND v.text = ?
+    ", tag_table, id_column);
+    let mut merged = 0;
+
+    for ((id, lowercase_key), (stored, duplicates)) in groups {
+        let Some(merged_tag) = merge_tag_group(&stored, &duplicates, newer.contains(&id), policy) else {
+            continue;
+        };
+        // Nothing to do if the stored tag already is the merged one
+        if stored.len() == 1 && stored[0] == merged_tag {
+            continue;
+        }
+
+        sqlx::query(&delete_query).bind(id).bind(&lowercase_key).execute(&mut **tx).await?;
+        // A merged value joined with `;` is usually new to the dictionary
+        sqlx::query("INSERT OR IGNORE INTO tag_key (text) VALUES (?)").bind(&merged_tag.0).execute(&mut **tx).await?;
+        sqlx::query("INSERT OR IGNORE INTO tag_value (text) VALUES (?)").bind(&merged_tag.1).execute(&mut **tx).await?;
+        sqlx::query(&insert_query).bind(id).bind(&merged_tag.0).bind(&merged_tag.1).execute(&mut **tx).await?;
+        merged += 1;
+    }
+
+    Ok(merged)
+}
+
+/// Picks the single `(key, value)` an element keeps for one key.
+///
+/// ## Arguments
+/// * `stored` - The spellings of the key in the tag table, with their values.
+/// * `duplicates` - The conflicting tags recorded while importing, oldest first.
+/// * `duplicate_is_newer` - Whether the duplicates came with a newer version of the element.
+/// * `policy` - How the values are merged.
+fn merge_tag_group(stored: &[(String, String)], duplicates: &[(String, String)], duplicate_is_newer: bool, policy: TagMergePolicy) -> Option<(String, String)> {
+    // Case variants of a key are inserted next to the stored one, so the stored spelling is
+    // the one no duplicate brought in
+    let original = stored.iter()
+        .find(|(key, _)| duplicates.iter().all(|(duplicate_key, _)| duplicate_key != key))
+        .or_else(|| stored.first())
+        .or_else(|| duplicates.first())?;
+
+    match policy {
+        TagMergePolicy::KeepNewer if duplicate_is_newer => {
+            let (_, value) = duplicates.last()?;
+            Some((original.0.clone(), value.clone()))
+        }
+        TagMergePolicy::KeepNewer => Some(original.clone()),
+        TagMergePolicy::Union => {
+            let mut values: Vec<&str> = Vec::new();
+            let all_values = std::iter::once(&original.1)
+                .chain(stored.iter().map(|(_, value)| value))
+                .chain(duplicates.iter().map(|(_, value)| value));
+
+            for value in all_values.flat_map(|value| value.split(';')).map(str::trim) {
+                if !value.is_empty() && !values.contains(&value) {
+                    values.push(value);
+                }
+            }
+
+            Some((original.0.clone(), values.join(";")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    // The newer of two overlapping extracts
+    const NEWER_EXTRACT: &str = r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="3" timestamp="2024-03-01T00:00:00Z"><tag k="name" v="Harbour Café"/><tag k="amenity" v="cafe"/></node>
+ <node id="2" lat="55.001" lon="12.001" version="1" timestamp="2020-01-01T00:00:00Z"/>
+ <way id="10" version="2" timestamp="2024-03-01T00:00:00Z"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/><tag k="name" v="Harbour Street"/></way>
+</osm>"#;
+
+    // The older one, imported last: node 1 is about 11 m off and its name is spelled
+    // differently, way 10 has no name yet and node 3 is only in this one
+    const OLDER_EXTRACT: &str = r#"<osm version="0.6">
+ <node id="1" lat="55.0001" lon="12.0" version="2" timestamp="2023-01-01T00:00:00Z"><tag k="Name" v="Harbour Cafe"/><tag k="amenity" v="cafe"/></node>
+ <node id="2" lat="55.001" lon="12.001" version="1" timestamp="2020-01-01T00:00:00Z"/>
+ <node id="3" lat="55.002" lon="12.002" version="1" timestamp="2020-01-01T00:00:00Z"/>
+ <way id="10" version="1" timestamp="2023-01-01T00:00:00Z"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/></way>
+</osm>"#;
+
+    async fn overlapping_imports(name: &str) -> SqlitePool {
+        let pool = memory_pool(name).await;
+        import_osm_xml(&pool, &format!("{}_newer", name), NEWER_EXTRACT).await;
+        import_osm_xml(&pool, &format!("{}_older", name), OLDER_EXTRACT).await;
+        pool
+    }
+
+    async fn tags(pool: &SqlitePool, table: &str, id: i64) -> Vec<(String, String)> {
+        let query = format!("
+            SELECT k.text, v.text FROM {table}_tags t
+            JOIN tag_key k ON k.id = t.key_id JOIN tag_value v ON v.id = t.value_id
+            WHERE t.{table}_id = ? ORDER BY k.text
+        ");
+        sqlx::query_as(&query).bind(id).fetch_all(pool).await.unwrap()
+    }
+
+    fn tag(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[tokio::test]
+    async fn the_higher_version_of_overlapping_imports_is_kept() {
+        let pool = overlapping_imports("dedupe_newer").await;
+        // The import taken last replaced the higher versions
+        let version: i64 = sqlx::query_scalar("SELECT version FROM node WHERE id = 1").fetch_one(&pool).await.unwrap();
+        assert_eq!(version, 2);
+
+        let report = deduplicate(&pool, &DedupeOptions::default()).await.unwrap();
+        assert_eq!(report.updated, vec![(MapsType::Node, 1), (MapsType::Way, 1), (MapsType::Relation, 0)]);
+        assert_eq!(report.moved_nodes.len(), 1);
+        assert_eq!(report.moved_nodes[0].0, 1);
+        assert!((report.moved_nodes[0].1 - 11.1).abs() < 0.5, "{:?}", report.moved_nodes);
+
+        let node: (i64, i64, String) = sqlx::query_as("SELECT version, lat_e7, timestamp FROM node WHERE id = 1").fetch_one(&pool).await.unwrap();
+        assert_eq!(node, (3, 550000000, "2024-03-01T00:00:00Z".to_string()));
+        // The key keeps the spelling stored, the value is the one of the newer version
+        assert_eq!(tags(&pool, "node", 1).await, vec![tag("Name", "Harbour Café"), tag("amenity", "cafe")]);
+        let way_version: i64 = sqlx::query_scalar("SELECT version FROM way WHERE id = 10").fetch_one(&pool).await.unwrap();
+        assert_eq!(way_version, 2);
+        assert_eq!(tags(&pool, "way", 10).await, vec![tag("highway", "residential"), tag("name", "Harbour Street")]);
+        // The box of the way follows node 1 back
+        let min_lat: f64 = sqlx::query_scalar("SELECT min_lat FROM way_geom WHERE way_id = 10").fetch_one(&pool).await.unwrap();
+        assert_eq!(min_lat, 55.0);
+
+        // Everything recorded is resolved, so running it again changes nothing
+        let again = deduplicate(&pool, &DedupeOptions::default()).await.unwrap();
+        assert_eq!(again.updated, vec![(MapsType::Node, 0), (MapsType::Way, 0), (MapsType::Relation, 0)]);
+        assert_eq!((again.moved_nodes.len(), again.tags_merged), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn conflicting_tags_can_keep_every_value() {
+        let pool = overlapping_imports("dedupe_union").await;
+        let options = DedupeOptions { tag_policy: TagMergePolicy::Union, ..Default::default() };
+        let report = deduplicate(&pool, &options).await.unwrap();
+
+        assert_eq!(tags(&pool, "node", 1).await, vec![tag("Name", "Harbour Cafe;Harbour Café"), tag("amenity", "cafe")]);
+        assert!(report.tags_merged >= 1);
+        // Identical elements were never recorded
+        assert!(tags(&pool, "node", 2).await.is_empty());
+    }
+
+    #[test]
+    fn a_newer_import_loses_tags_it_does_not_have_but_keeps_its_values() {
+        let stored = [tag("name", "New")];
+        let duplicates = [tag("NAME", "Old")];
+        assert_eq!(merge_tag_group(&stored, &duplicates, false, TagMergePolicy::KeepNewer), Some(tag("name", "New")));
+        assert_eq!(merge_tag_group(&stored, &duplicates, true, TagMergePolicy::KeepNewer), Some(tag("name", "Old")));
+        assert_eq!(merge_tag_group(&stored, &[tag("name", "Old; New")], false, TagMergePolicy::Union), Some(tag("name", "New;Old")));
+    }
+}
@@ -1,12 +1,194 @@
-use sqlx::{FromRow, SqlitePool};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-use crate::osm_entities::{Node, Relation, RenderableWay, Way};
+use std::pin::Pin;
 
-pub async fn fetch_all_renderable_ways(sqlite_pool: &SqlitePool) -> Result<Vec<RenderableWay>, sqlx::Error> {
-    let query = "
+use futures::future::{self, BoxFuture};
+use futures::{stream, FutureExt, Stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{FromRow, Row, SqlitePool};
+use tracing::{debug, trace, warn};
+
+use crate::cancel::{try_collect_cancellable, CancellationToken, OperationError};
+use crate::geo::{bbox_around, distance_to_polyline, haversine_distance, point_in_polygon, BBox};
+use crate::gpx::{GpsPoint, GpsTrack};
+use crate::junctions::merge_lines_at_junctions;
+use crate::osm_entities::{Member, Node, Relation, RenderableWay, SimpleNode, Tag, Way};
+use crate::utils::{from_e7, to_e7, MapsType};
+
+// The queries are shared by the fetchers of some of the elements, which select from them and
+// stream the rows. Tags are listed in the order of their keys, as they were when the tag
+// tables held the texts themselves.
+//
+// The node references of ways and the members of relations are not concatenated into the
+// row of their parent, as a coastline or boundary can have tens of thousands of them. They
+// are fetched by a second query ordered by the parent, and attached by `attach_children`.
+// Fetchers of some of the elements select from these queries and keep them in order.
+//
+// Every query orders its outer select, as SQLite returns rows in whatever order its plan
+// visits them, which changes between versions and with the indexes there are. The elements
+// are ordered by id, and a key is on an element only once, so ordering tags by key is total.
+const WAYS_AND_TAGS_QUERY: &str = "
+    SELECT
+        w.id, w.version, w.timestamp, w.changeset, w.uid, w.[user],
+        way_tags.tags
+    FROM
+        way w
+    LEFT JOIN (
         SELECT
+            wt.way_id,
+            GROUP_CONCAT(k.text || '=' || v.text, ',' ORDER BY k.text) as tags
+        FROM
+            way_tags wt
+        JOIN tag_key k ON k.id = wt.key_id
+        JOIN tag_value v ON v.id = wt.value_id
+        GROUP BY
+            wt.way_id
+    ) as way_tags ON w.id = way_tags.way_id
+    ORDER BY
+        w.id
+";
+
+// The node references in the order of the way, a closed way ends at its first node again
+const WAY_NODE_REFS_QUERY: &str = "
+    SELECT
+        wn.way_id AS parent_id, wn.seq AS position, wn.ref_id
+    FROM
+        way_nodes wn
+    ORDER BY
+        wn.way_id, wn.seq
+";
+
+const RELATIONS_AND_TAGS_QUERY: &str = "
+    SELECT
+        r.id, r.version, r.timestamp, r.changeset, r.uid, r.[user],
+        relation_tags.tags
+    FROM
+        relation r
+    LEFT JOIN (
+        SELECT
+            rt.relation_id,
+            GROUP_CONCAT(k.text || '=' || v.text, ',' ORDER BY k.text) as tags
+        FROM
+            relation_tags rt
+        JOIN tag_key k ON k.id = rt.key_id
+        JOIN tag_value v ON v.id = rt.value_id
+        GROUP BY
+            rt.relation_id
+    ) as relation_tags ON r.id = relation_tags.relation_id
+    ORDER BY
+        r.id
+";
+
+// The members in the order of the relation
+const MEMBERS_QUERY: &str = "
+    SELECT
+        m.relation_id AS parent_id, m.seq AS position, m.ref_id, m.member_type, m.role
+    FROM
+        member m
+    ORDER BY
+        parent_id, position
+";
+
+/// Attaches children fetched by a second query to their parents, e.g. the node references
+/// to ways, walking both streams side by side so only one parent is held at a time.
+///
+/// Both streams must be ordered by the id of the parent. Children of parents missing from
+/// the parent stream are skipped.
+///
+/// ## Arguments
+/// * `parents` - The parents, ordered by id.
+/// * `children` - The children with the id of their parent, ordered by it.
+/// * `parent_id` - Gets the id of a parent.
+/// * `attach` - Adds a child to its parent, in the order the children come in.
+fn attach_children<'a, P: Send + 'a, C: Send + 'a>(
+    parents: impl Stream<Item = Result<P, sqlx::Error>> + Send + 'a,
+    children: impl Stream<Item = Result<(i64, C), sqlx::Error>> + Send + 'a,
+    parent_id: fn(&P) -> i64,
+    attach: fn(&mut P, C),
+) -> impl Stream<Item = Result<P, sqlx::Error>> + Send + 'a {
+    let streams = (parents.boxed(), children.boxed().peekable());
+
+    stream::unfold(streams, move |(mut parents, mut children)| async move {
+        let mut parent = match parents.next().await? {
+            Ok(parent) => parent,
+            Err(error) => return Some((Err(error), (parents, children))),
+        };
+        let id = parent_id(&parent);
+
+        // The parent the next child belongs to, or `None` for an error
+        while let Some(next_parent_id) = Pin::new(&mut children).peek().await
+            .map(|child| child.as_ref().ok().map(|(child_parent_id, _)| *child_parent_id))
+        {
+            match next_parent_id {
+                Some(child_parent_id) if child_parent_id > id => break,
+                Some(child_parent_id) => {
+                    if let Some(Ok((_, child))) = children.next().await {
+                        if child_parent_id == id {
+                            attach(&mut parent, child);
+                        }
+                    }
+                }
+                None => {
+                    if let Some(Err(error)) = children.next().await {
+                        return Some((Err(error), (parents, children)));
+                    }
+                }
+            }
+        }
+
+        Some((Ok(parent), (parents, children)))
+    })
+}
+
+/// Reads a row of `WAY_NODE_REFS_QUERY`.
+fn way_node_ref_from_row(row: SqliteRow) -> Result<(i64, i64), sqlx::Error> {
+    Ok((row.try_get("parent_id")?, row.try_get("ref_id")?))
+}
+
+/// Reads a row of `MEMBERS_QUERY`. Members of an unknown type are skipped.
+fn member_from_row(row: SqliteRow) -> Result<Option<(i64, Member)>, sqlx::Error> {
+    let maps_type = match row.try_get::<&str, _>("member_type")? {
+        "node" => MapsType::Node,
+        "way" => MapsType::Way,
+        "relation" => MapsType::Relation,
+        _ => return Ok(None),
+    };
+
+    let member = Member { ref_id: row.try_get("ref_id")?, maps_type, role: row.try_get("role")? };
+    Ok(Some((row.try_get("parent_id")?, member)))
+}
+
+/// Builds the ways of rows of `WAYS_AND_TAGS_QUERY` and `WAY_NODE_REFS_QUERY`, or queries
+/// selecting from them.
+fn ways_with_node_refs<'a>(
+    way_rows: impl Stream<Item = Result<SqliteRow, sqlx::Error>> + Send + 'a,
+    node_ref_rows: impl Stream<Item = Result<SqliteRow, sqlx::Error>> + Send + 'a,
+) -> impl Stream<Item = Result<Way, sqlx::Error>> + Send + 'a {
+    let ways = way_rows.map(|row| row.and_then(|row| Way::from_row(&row)));
+    let node_refs = node_ref_rows.map(|row| row.and_then(way_node_ref_from_row));
+    attach_children(ways, node_refs, |way| way.id, |way, node_ref| way.node_refs.push(node_ref))
+}
+
+/// Builds the relations of rows of `RELATIONS_AND_TAGS_QUERY` and `MEMBERS_QUERY`, or
+/// queries selecting from them.
+fn relations_with_members<'a>(
+    relation_rows: impl Stream<Item = Result<SqliteRow, sqlx::Error>> + Send + 'a,
+    member_rows: impl Stream<Item = Result<SqliteRow, sqlx::Error>> + Send + 'a,
+) -> impl Stream<Item = Result<Relation, sqlx::Error>> + Send + 'a {
+    let relations = relation_rows.map(|row| row.and_then(|row| Relation::from_row(&row)));
+    let members = member_rows.filter_map(|row| future::ready(row.and_then(member_from_row).transpose()));
+    attach_children(relations, members, |relation| relation.id, |relation, member| relation.members.push(member))
+}
+
+// Every way with its node ids and stored coordinates in node order, the number of node references
+// without a node and its tags, one row per way
+const RENDERABLE_WAYS_QUERY: &str = "
+    SELECT
         w.id,
-        GROUP_CONCAT(DISTINCT n.lat || ' ' || n.lon ORDER BY wn.rowid) AS node_refs,
+        GROUP_CONCAT(n.id || ' ' || n.lat_e7 || ' ' || n.lon_e7 ORDER BY wn.seq) AS node_refs,
+        COUNT(wn.ref_id) - COUNT(n.id) AS missing_nodes,
         way_tags.tags
     FROM
         way w
@@ -15,19 +197,29 @@ pub async fn fetch_all_renderable_ways(sqlite_pool: &SqlitePool) -> Result<Vec<R
     LEFT JOIN (
         SELECT
             wt.way_id,
-            GROUP_CONCAT(wt.[key] || ':' || wt.value) AS tags
+            GROUP_CONCAT(k.text || '=' || v.text ORDER BY k.text) AS tags
         FROM
             way_tags wt
+        JOIN tag_key k ON k.id = wt.key_id
+        JOIN tag_value v ON v.id = wt.value_id
         GROUP BY
             wt.way_id
     ) AS way_tags ON w.id = way_tags.way_id
     GROUP BY
         w.id
-    ORDER BY
-        w.id;
-    ";
+";
 
-    let fetched_result = sqlx::query(query)
+/// Fetches every way with the coordinates of its nodes and its tags.
+///
+/// Node references without a node, e.g. of ways crossing the edge of an extract, are left
+/// out of the nodes and counted in `missing_nodes`.
+///
+/// The ways are ordered by id, their nodes in the order of the way and their tags by key, so
+/// the same data always draws the same.
+pub async fn fetch_all_renderable_ways(sqlite_pool: &SqlitePool) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    let query = format!("{} ORDER BY w.id", RENDERABLE_WAYS_QUERY);
+
+    let fetched_result = sqlx::query(&query)
         .fetch_all(sqlite_pool)
         .await?;
 
@@ -39,68 +231,869 @@ pub async fn fetch_all_renderable_ways(sqlite_pool: &SqlitePool) -> Result<Vec<R
         renderable_ways.push(renderable_way);
     }
 
-    println!("{:#?}", renderable_ways.first().unwrap());
+    debug!(count = renderable_ways.len(), "fetched renderable ways");
+    trace!(first = ?renderable_ways.first(), "first renderable way");
 
     Ok(renderable_ways)
 }
 
-pub async fn fetch_all_nodes_and_tags(sqlite_pool: &SqlitePool) -> Result<Vec<Node>, sqlx::Error> {
+/// Counts the rows of a table without loading any of them.
+pub async fn count_rows(sqlite_pool: &SqlitePool, table: &str) -> Result<i64, sqlx::Error> {
+    let query = format!("SELECT COUNT(*) FROM {}", table);
+    sqlx::query_scalar(&query)
+        .fetch_one(sqlite_pool)
+        .await
+}
+
+/// Fetches a value stored with `save_setting`.
+///
+/// ## Returns
+/// * The value, or `None` if it was never saved.
+pub async fn fetch_setting(sqlite_pool: &SqlitePool, key: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT value FROM settings WHERE [key] = ?")
+        .bind(key)
+        .fetch_optional(sqlite_pool)
+        .await
+}
+
+/// The key the extent of the imported nodes is saved under in the settings table, as
+/// `top,left,bottom,right` in degrees.
+pub const DATA_EXTENT_SETTING: &str = "data_extent";
+
+/// The key the viewport shown when the viewer was last closed is saved under in the settings
+/// table, as `top,left,bottom,right` in degrees.
+pub const VIEWPORT_SETTING: &str = "viewport";
+
+/// Reads a box saved under `DATA_EXTENT_SETTING` or `VIEWPORT_SETTING`.
+fn parse_bbox_setting(value: &str) -> Option<BBox> {
+    let edges: Vec<f64> = value.split(',').map(|edge| edge.trim().parse().ok()).collect::<Option<_>>()?;
+    match edges[..] {
+        [top, left, bottom, right] => Some(BBox { min_lat: bottom, max_lat: top, min_lon: left, max_lon: right }),
+        _ => None,
+    }
+}
+
+/// Fetches the viewport shown when the viewer was last closed, as saved by `save_viewport`.
+///
+/// ## Returns
+/// * The viewport, or `None` if none was saved or the saved value is not a box.
+pub async fn fetch_saved_viewport(sqlite_pool: &SqlitePool) -> Result<Option<BBox>, sqlx::Error> {
+    let Some(value) = fetch_setting(sqlite_pool, VIEWPORT_SETTING).await? else {
+        return Ok(None);
+    };
+
+    let viewport = parse_bbox_setting(&value);
+    if viewport.is_none() {
+        warn!(value, "ignoring the saved viewport");
+    }
+    Ok(viewport)
+}
+
+/// Fetches the extent of the imported data, as kept up to date by `update_data_extent`.
+///
+/// Databases imported into before the extent was saved have no saved value, for them the
+/// extent is computed from the nodes, which takes a scan of the node table.
+///
+/// ## Returns
+/// * The box around every imported node, or `None` if there are no nodes.
+pub async fn fetch_data_extent(sqlite_pool: &SqlitePool) -> Result<Option<BBox>, sqlx::Error> {
+    if let Some(value) = fetch_setting(sqlite_pool, DATA_EXTENT_SETTING).await? {
+        match parse_bbox_setting(&value) {
+            Some(extent) => return Ok(Some(extent)),
+            None => warn!(value, "ignoring the saved data extent"),
+        }
+    }
+
+    let (top, left, bottom, right): (Option<i64>, Option<i64>, Option<i64>, Option<i64>) =
+        sqlx::query_as("SELECT MAX(lat_e7), MIN(lon_e7), MIN(lat_e7), MAX(lon_e7) FROM node")
+            .fetch_one(sqlite_pool)
+            .await?;
+
+    Ok(match (top, left, bottom, right) {
+        (Some(top), Some(left), Some(bottom), Some(right)) => Some(BBox { min_lat: from_e7(bottom), max_lat: from_e7(top), min_lon: from_e7(left), max_lon: from_e7(right) }),
+        _ => None,
+    })
+}
+
+pub async fn count_nodes(sqlite_pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    count_rows(sqlite_pool, "node").await
+}
+
+pub async fn count_ways(sqlite_pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    count_rows(sqlite_pool, "way").await
+}
+
+pub async fn count_relations(sqlite_pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    count_rows(sqlite_pool, "relation").await
+}
+
+/// Fetches the size of the database in bytes, from its pages, so it works for a database in
+/// memory too.
+pub async fn fetch_database_size(sqlite_pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+        .fetch_one(sqlite_pool)
+        .await
+}
+
+/// Fetches all ways tagged with `highway`, with their node references in the order they were imported.
+/// The query stops early once `cancel` is cancelled.
+pub async fn fetch_highway_ways(sqlite_pool: &SqlitePool, cancel: &CancellationToken) -> Result<Vec<Way>, OperationError> {
+    let highway_ways = "SELECT wt.way_id FROM way_tags wt WHERE wt.key_id = (SELECT id FROM tag_key WHERE text = 'highway')";
+    let ways_query = format!("
+        SELECT * FROM ({}) AS w
+        WHERE
+            w.id IN ({})
+        ORDER BY
+            w.id
+    ", WAYS_AND_TAGS_QUERY, highway_ways);
+    let node_refs_query = format!("
+        SELECT * FROM ({}) AS wn
+        WHERE
+            wn.parent_id IN ({})
+        ORDER BY
+            wn.parent_id, wn.position
+    ", WAY_NODE_REFS_QUERY, highway_ways);
+
+    try_collect_cancellable(ways_with_node_refs(
+        sqlx::query(&ways_query).fetch(sqlite_pool),
+        sqlx::query(&node_refs_query).fetch(sqlite_pool),
+    ), cancel).await
+}
+
+/// Fetches the coordinates of every node referenced by a way tagged with `highway`. The query
+/// stops early once `cancel` is cancelled.
+///
+/// ## Returns
+/// * A map from node id to its `(lat, lon)`.
+pub async fn fetch_highway_node_coordinates(sqlite_pool: &SqlitePool, cancel: &CancellationToken) -> Result<HashMap<i64, (f64, f64)>, OperationError> {
     let query = "
-        SELECT
-            n.id, n.lat, n.lon, n.version, n.timestamp, n.changeset, n.uid, n.[user],
-            GROUP_CONCAT(nt.[key] || ':' || nt.value, ',') as tags
+        SELECT DISTINCT
+            n.id, n.lat_e7, n.lon_e7
         FROM
             node n
-        LEFT JOIN
-            node_tags nt ON n.id = nt.node_id
-        GROUP BY
-            n.id
+        JOIN way_nodes wn ON wn.ref_id = n.id
+        JOIN way_tags wt ON wt.way_id = wn.way_id AND wt.key_id = (SELECT id FROM tag_key WHERE text = 'highway')
     ";
 
-    let fetched_result = sqlx::query(query)
+    let fetched_result = try_collect_cancellable(sqlx::query(query).fetch(sqlite_pool), cancel).await?;
+
+    let mut coordinates = HashMap::with_capacity(fetched_result.len());
+
+    for row in fetched_result {
+        let id: i64 = row.try_get("id")?;
+        coordinates.insert(id, (from_e7(row.try_get("lat_e7")?), from_e7(row.try_get("lon_e7")?)));
+    }
+
+    Ok(coordinates)
+}
+
+/// Fetches every relation with a `type=restriction` tag together with its members and tags.
+/// The query stops early once `cancel` is cancelled.
+pub async fn fetch_restriction_relations(sqlite_pool: &SqlitePool, cancel: &CancellationToken) -> Result<Vec<Relation>, OperationError> {
+    let restrictions = "
+        SELECT t.relation_id FROM relation_tags t
+        WHERE t.key_id = (SELECT id FROM tag_key WHERE text = 'type')
+            AND t.value_id = (SELECT id FROM tag_value WHERE text = 'restriction')
+    ";
+    let relations_query = format!("
+        SELECT * FROM ({}) AS r
+        WHERE
+            r.id IN ({})
+        ORDER BY
+            r.id
+    ", RELATIONS_AND_TAGS_QUERY, restrictions);
+    let members_query = format!("
+        SELECT * FROM ({}) AS m
+        WHERE
+            m.parent_id IN ({})
+        ORDER BY
+            m.parent_id, m.position
+    ", MEMBERS_QUERY, restrictions);
+
+    try_collect_cancellable(relations_with_members(
+        sqlx::query(&relations_query).fetch(sqlite_pool),
+        sqlx::query(&members_query).fetch(sqlite_pool),
+    ), cancel).await
+}
+
+// The ids of the relations with a `type=boundary` and an `admin_level` tag
+const BOUNDARY_RELATION_IDS_QUERY: &str = "
+    SELECT t.relation_id FROM relation_tags t
+    WHERE t.key_id = (SELECT id FROM tag_key WHERE text = 'type')
+        AND t.value_id = (SELECT id FROM tag_value WHERE text = 'boundary')
+        AND EXISTS (
+            SELECT 1 FROM relation_tags a
+            WHERE a.relation_id = t.relation_id AND a.key_id = (SELECT id FROM tag_key WHERE text = 'admin_level')
+        )
+";
+
+/// Chains the member ways of a boundary relation into lines, as long as possible.
+///
+/// Member ways are joined where they meet end to end, whichever way they run. A boundary
+/// closed around its area becomes a closed line. Member ways missing from the database
+/// leave a gap, and member ways missing some of their nodes are kept as lines of their own,
+/// as they may not reach the ways next to them.
+///
+/// ## Returns
+/// * The lines, with the id and tags of the relation.
+fn chain_boundary_ways(relation: &Relation, ways: &HashMap<i64, RenderableWay>) -> Vec<RenderableWay> {
+    let (complete, incomplete): (Vec<&RenderableWay>, Vec<&RenderableWay>) = relation.members.iter()
+        .filter(|member| member.maps_type == MapsType::Way)
+        .filter_map(|member| ways.get(&member.ref_id))
+        .partition(|way| way.is_complete());
+
+    let chained = merge_lines_at_junctions(complete.iter().map(|way| way.nodes()).collect());
+
+    chained.iter()
+        .map(|nodes| RenderableWay::from_nodes(relation.id, nodes, relation.tags.clone(), 0))
+        .chain(incomplete.into_iter().map(|way| RenderableWay { id: relation.id, tags: relation.tags.clone(), ..way.clone() }))
+        .collect()
+}
+
+/// Fetches the administrative boundaries as lines, chained from the member ways of every
+/// relation with a `type=boundary` and an `admin_level` tag, see `chain_boundary_ways`.
+///
+/// ## Returns
+/// * The lines of every boundary, carrying the tags of its relation.
+pub async fn fetch_boundary_lines(sqlite_pool: &SqlitePool) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    let relations_query = format!("
+        SELECT * FROM ({}) AS r
+        WHERE
+            r.id IN ({})
+        ORDER BY
+            r.id
+    ", RELATIONS_AND_TAGS_QUERY, BOUNDARY_RELATION_IDS_QUERY);
+    let members_query = format!("
+        SELECT * FROM ({}) AS m
+        WHERE
+            m.parent_id IN ({})
+        ORDER BY
+            m.parent_id, m.position
+    ", MEMBERS_QUERY, BOUNDARY_RELATION_IDS_QUERY);
+    let relations: Vec<Relation> = relations_with_members(
+        sqlx::query(&relations_query).fetch(sqlite_pool),
+        sqlx::query(&members_query).fetch(sqlite_pool),
+    ).try_collect().await?;
+
+    let ways_query = format!("
+        SELECT * FROM ({}) AS w
+        WHERE
+            w.id IN (SELECT m.ref_id FROM member m WHERE m.member_type = 'way' AND m.relation_id IN ({}))
+    ", RENDERABLE_WAYS_QUERY, BOUNDARY_RELATION_IDS_QUERY);
+    let mut ways = HashMap::new();
+    for row in sqlx::query(&ways_query).fetch_all(sqlite_pool).await? {
+        let way = RenderableWay::from_row(&row)?;
+        ways.insert(way.id, way);
+    }
+
+    let lines: Vec<RenderableWay> = relations.iter()
+        .flat_map(|relation| chain_boundary_ways(relation, &ways))
+        .collect();
+    debug!(relations = relations.len(), member_ways = ways.len(), lines = lines.len(), "fetched boundary lines");
+
+    Ok(lines)
+}
+
+// The ids of the relations with a `type=multipolygon` tag and a tag of their own, which
+// decides how the area is drawn. Old style multipolygons carry their tags on the outer way,
+// which is drawn by itself
+const MULTIPOLYGON_RELATION_IDS_QUERY: &str = "
+    SELECT t.relation_id FROM relation_tags t
+    WHERE t.key_id = (SELECT id FROM tag_key WHERE text = 'type')
+        AND t.value_id = (SELECT id FROM tag_value WHERE text = 'multipolygon')
+        AND EXISTS (
+            SELECT 1 FROM relation_tags o
+            WHERE o.relation_id = t.relation_id AND o.key_id != t.key_id
+        )
+";
+
+/// Assembles the areas of a multipolygon relation from its member ways.
+///
+/// The member ways of either role are chained into rings where they meet end to end. Every
+/// outer ring is an area of its own, with the inner rings inside it, e.g. courtyards, as its
+/// holes.
+///
+/// Every chain whose ends meet is a ring. With every member way stored and complete, the
+/// chains whose ends do not meet are closed into rings as well, as the relation says it
+/// encloses an area. Otherwise they are left out, as they may run on in the ways missing.
+///
+/// ## Returns
+/// * The areas, with the id and tags of the relation.
+fn assemble_multipolygon(relation: &Relation, ways: &HashMap<i64, RenderableWay>) -> Vec<RenderableWay> {
+    let member_ways: Vec<&Member> = relation.members.iter().filter(|member| member.maps_type == MapsType::Way).collect();
+    let is_whole = member_ways.iter().all(|member| ways.get(&member.ref_id).is_some_and(RenderableWay::is_complete));
+
+    let rings = |inner: bool| -> Vec<Vec<SimpleNode>> {
+        let lines = member_ways.iter()
+            .filter(|member| (member.role == "inner") == inner)
+            .filter_map(|member| ways.get(&member.ref_id))
+            .filter(|way| way.is_complete())
+            .map(|way| way.nodes())
+            .collect();
+        merge_lines_at_junctions(lines).into_iter()
+            .filter_map(|mut ring| {
+                let is_closed = matches!((ring.first(), ring.last()), (Some(first), Some(last)) if first.id.is_some() && first.id == last.id);
+                if !is_closed && is_whole {
+                    ring.push(ring[0].clone());
+                }
+                (ring.len() >= 4 && (is_closed || is_whole)).then_some(ring)
+            })
+            .collect()
+    };
+    let to_points = |ring: &[SimpleNode]| -> Vec<(f64, f64)> { ring.iter().map(|node| (node.lat, node.lon)).collect() };
+
+    let inner_rings: Vec<Vec<(f64, f64)>> = rings(true).iter().map(|ring| to_points(ring)).collect();
+    rings(false).iter()
+        .map(|outer| {
+            let mut area = RenderableWay::from_nodes(relation.id, outer, relation.tags.clone(), 0);
+            area.inner_rings = inner_rings.iter()
+                .filter(|inner| point_in_polygon(inner[0], &area.coords))
+                .cloned()
+                .collect();
+            area
+        })
+        .collect()
+}
+
+/// Fetches the areas of the multipolygon relations carrying tags of their own, assembled
+/// from their member ways, see `assemble_multipolygon`. A multipolygon split into
+/// sub-relations is assembled from the ways of all of them, see `resolve_relation_tree`.
+pub async fn fetch_multipolygon_areas(sqlite_pool: &SqlitePool) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    let relations_query = format!("
+        SELECT * FROM ({}) AS r
+        WHERE
+            r.id IN ({})
+        ORDER BY
+            r.id
+    ", RELATIONS_AND_TAGS_QUERY, MULTIPOLYGON_RELATION_IDS_QUERY);
+    let members_query = format!("
+        SELECT * FROM ({}) AS m
+        WHERE
+            m.parent_id IN ({})
+        ORDER BY
+            m.parent_id, m.position
+    ", MEMBERS_QUERY, MULTIPOLYGON_RELATION_IDS_QUERY);
+    let mut relations: Vec<Relation> = relations_with_members(
+        sqlx::query(&relations_query).fetch(sqlite_pool),
+        sqlx::query(&members_query).fetch(sqlite_pool),
+    ).try_collect().await?;
+
+    // Few multipolygons have member relations, so only theirs are resolved one by one
+    for relation in relations.iter_mut().filter(|relation| relation.members.iter().any(|member| member.maps_type == MapsType::Relation)) {
+        if let Some(resolved) = resolve_relation_tree(sqlite_pool, relation.id, MAX_RELATION_DEPTH).await? {
+            relation.members = resolved.members.into_iter()
+                .map(|member| Member::new(member.ref_id, member.maps_type, member.role))
+                .collect();
+        }
+    }
+
+    // The member ways of the multipolygons and of the relations nested in them, no deeper than
+    // `MAX_RELATION_DEPTH` levels, which also ends a cycle of relations
+    let ways_query = format!("
+        WITH RECURSIVE nested(relation_id, depth) AS (
+            SELECT relation_id, 0 FROM ({})
+            UNION
+            SELECT m.ref_id, n.depth + 1 FROM member m JOIN nested n ON m.relation_id = n.relation_id
+            WHERE m.member_type = 'relation' AND n.depth < ?
+        )
+        SELECT * FROM ({}) AS w
+        WHERE
+            w.id IN (SELECT m.ref_id FROM member m WHERE m.member_type = 'way' AND m.relation_id IN (SELECT relation_id FROM nested))
+    ", MULTIPOLYGON_RELATION_IDS_QUERY, RENDERABLE_WAYS_QUERY);
+    let mut ways = HashMap::new();
+    for row in sqlx::query(&ways_query).bind(MAX_RELATION_DEPTH as i64).fetch_all(sqlite_pool).await? {
+        let way = RenderableWay::from_row(&row)?;
+        ways.insert(way.id, way);
+    }
+
+    let areas: Vec<RenderableWay> = relations.iter()
+        .flat_map(|relation| assemble_multipolygon(relation, &ways))
+        .collect();
+    debug!(relations = relations.len(), member_ways = ways.len(), areas = areas.len(), "fetched multipolygon areas");
+
+    Ok(areas)
+}
+
+/// Fetches what is drawn of the relations: the lines of the administrative boundaries, see
+/// `fetch_boundary_lines`, followed by the areas of the multipolygons, see
+/// `fetch_multipolygon_areas`.
+pub async fn fetch_relation_ways(sqlite_pool: &SqlitePool) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    let mut relation_ways = fetch_boundary_lines(sqlite_pool).await?;
+    relation_ways.extend(fetch_multipolygon_areas(sqlite_pool).await?);
+    Ok(relation_ways)
+}
+
+/// Fetches a single relation together with its members and tags.
+pub async fn fetch_relation(sqlite_pool: &SqlitePool, id: i64) -> Result<Option<Relation>, sqlx::Error> {
+    let relation_query = format!("SELECT * FROM ({}) AS r WHERE r.id = ?", RELATIONS_AND_TAGS_QUERY);
+    let members_query = format!("SELECT * FROM ({}) AS m WHERE m.parent_id = ? ORDER BY m.position", MEMBERS_QUERY);
+
+    let mut relations = relations_with_members(
+        sqlx::query(&relation_query).bind(id).fetch(sqlite_pool),
+        sqlx::query(&members_query).bind(id).fetch(sqlite_pool),
+    ).boxed();
+    relations.next().await.transpose()
+}
+
+/// The coordinates of a node of a way, in the order of the way.
+#[derive(Debug, Clone, Serialize)]
+pub struct WayNodeCoordinates {
+    pub id: i64,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// A single way with its tags and the coordinates of its nodes, see `fetch_way_by_id`.
+///
+/// # Fields
+/// * `nodes` - The stored nodes of the way, in order.
+/// * `missing_node_ids` - The node references without a stored node, e.g. of a way crossing
+///   the edge of an extract, in order.
+#[derive(Debug, Clone, Serialize)]
+pub struct WayDetail {
+    pub id: i64,
+    pub version: i32,
+    pub timestamp: String,
+    pub changeset: i64,
+    pub uid: i64,
+    pub user: String,
+    pub tags: Vec<Tag>,
+    pub nodes: Vec<WayNodeCoordinates>,
+    pub missing_node_ids: Vec<i64>,
+}
+
+/// A member of a relation returned by `fetch_relation_by_id`.
+///
+/// # Fields
+/// * `member_type` - `node`, `way` or `relation`.
+/// * `ref_id` - The id of the member.
+/// * `role` - The role of the member, empty if it has none.
+/// * `nodes` - The coordinates of the nodes of a way member, or `None` for other members
+///   and ways that are not stored.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberDetail {
+    #[serde(rename = "type")]
+    pub member_type: String,
+    #[serde(rename = "ref")]
+    pub ref_id: i64,
+    pub role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<Vec<WayNodeCoordinates>>,
+}
+
+/// A single relation with its tags and members, see `fetch_relation_by_id`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RelationDetail {
+    pub id: i64,
+    pub version: i32,
+    pub timestamp: String,
+    pub changeset: i64,
+    pub uid: i64,
+    pub user: String,
+    pub tags: Vec<Tag>,
+    pub members: Vec<MemberDetail>,
+}
+
+/// Fetches the tags of one element, looked up by the primary key of its tag table.
+///
+/// ## Arguments
+/// * `tag_table` - `node_tags`, `way_tags` or `relation_tags`.
+/// * `id_column` - The column of the tag table holding the id of the element.
+async fn fetch_tags_of(sqlite_pool: &SqlitePool, tag_table: &str, id_column: &str, id: i64) -> Result<Vec<Tag>, sqlx::Error> {
+    let query = format!("
+        SELECT k.text AS key, v.text AS value
+        FROM {tag_table} t
+        JOIN tag_key k ON k.id = t.key_id
+        JOIN tag_value v ON v.id = t.value_id
+        WHERE t.{id_column} = ?
+        ORDER BY k.text
+    ");
+    sqlx::query_as(&query).bind(id).fetch_all(sqlite_pool).await
+}
+
+/// Fetches the node references of one way in order, with the coordinates of those stored.
+///
+/// ## Returns
+/// * The stored nodes, and the ids of the references without a stored node.
+async fn fetch_way_node_coordinates(sqlite_pool: &SqlitePool, way_id: i64) -> Result<(Vec<WayNodeCoordinates>, Vec<i64>), sqlx::Error> {
+    let rows = sqlx::query("
+        SELECT wn.ref_id, n.lat_e7, n.lon_e7
+        FROM way_nodes wn
+        LEFT JOIN node n ON n.id = wn.ref_id
+        WHERE wn.way_id = ?
+        ORDER BY wn.way_id, wn.seq
+    ")
+        .bind(way_id)
         .fetch_all(sqlite_pool)
         .await?;
 
-    let mut nodes = Vec::new();
+    let mut nodes = Vec::with_capacity(rows.len());
+    let mut missing_node_ids = Vec::new();
+    for row in rows {
+        let id: i64 = row.try_get("ref_id")?;
+        match (row.try_get::<Option<i64>, _>("lat_e7")?, row.try_get::<Option<i64>, _>("lon_e7")?) {
+            (Some(lat), Some(lon)) => nodes.push(WayNodeCoordinates { id, lat: from_e7(lat), lon: from_e7(lon) }),
+            _ => missing_node_ids.push(id),
+        }
+    }
 
-    // Process fetched rows
-    for row in fetched_result {
-        let node: Node = Node::from_row(&row)?;
-        nodes.push(node);
+    Ok((nodes, missing_node_ids))
+}
+
+/// Fetches a single node with its tags, by its primary key rather than a scan of every node.
+///
+/// ## Returns
+/// * The node, or `None` if no node has the id.
+pub async fn fetch_node_by_id(sqlite_pool: &SqlitePool, id: i64) -> Result<Option<Node>, sqlx::Error> {
+    let Some(row) = sqlx::query("SELECT id, lat_e7, lon_e7, version, timestamp, changeset, uid, [user] FROM node WHERE id = ?")
+        .bind(id)
+        .fetch_optional(sqlite_pool)
+        .await? else {
+        return Ok(None);
+    };
+
+    let mut node = Node::from_row(&row)?;
+    node.tags = fetch_tags_of(sqlite_pool, "node_tags", "node_id", id).await?;
+    Ok(Some(node))
+}
+
+/// Fetches a single way with its tags and the coordinates of its nodes, by its primary key
+/// rather than a scan of every way.
+///
+/// ## Returns
+/// * The way, or `None` if no way has the id.
+pub async fn fetch_way_by_id(sqlite_pool: &SqlitePool, id: i64) -> Result<Option<WayDetail>, sqlx::Error> {
+    let Some(row) = sqlx::query("SELECT id, version, timestamp, changeset, uid, [user] FROM way WHERE id = ?")
+        .bind(id)
+        .fetch_optional(sqlite_pool)
+        .await? else {
+        return Ok(None);
+    };
+
+    let tags = fetch_tags_of(sqlite_pool, "way_tags", "way_id", id).await?;
+    let (nodes, missing_node_ids) = fetch_way_node_coordinates(sqlite_pool, id).await?;
+    Ok(Some(WayDetail {
+        id: row.try_get("id")?,
+        version: row.try_get("version")?,
+        timestamp: row.try_get("timestamp")?,
+        changeset: row.try_get("changeset")?,
+        uid: row.try_get("uid")?,
+        user: row.try_get("user")?,
+        tags,
+        nodes,
+        missing_node_ids,
+    }))
+}
+
+/// Fetches a single relation with its tags and members, by its primary key rather than a
+/// scan of every relation. Way members come with the coordinates of their nodes, nested
+/// relations are only referenced.
+///
+/// ## Returns
+/// * The relation, or `None` if no relation has the id.
+pub async fn fetch_relation_by_id(sqlite_pool: &SqlitePool, id: i64) -> Result<Option<RelationDetail>, sqlx::Error> {
+    let Some(row) = sqlx::query("SELECT id, version, timestamp, changeset, uid, [user] FROM relation WHERE id = ?")
+        .bind(id)
+        .fetch_optional(sqlite_pool)
+        .await? else {
+        return Ok(None);
+    };
+
+    let tags = fetch_tags_of(sqlite_pool, "relation_tags", "relation_id", id).await?;
+
+    // The members in the order of the relation
+    let member_rows = sqlx::query("
+        SELECT m.member_type, m.ref_id, m.role, w.id IS NOT NULL AS way_stored
+        FROM member m
+        LEFT JOIN way w ON w.id = m.ref_id AND m.member_type = 'way'
+        WHERE m.relation_id = ?
+        ORDER BY m.seq
+    ")
+        .bind(id)
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    let mut members = Vec::with_capacity(member_rows.len());
+    for member_row in member_rows {
+        let ref_id: i64 = member_row.try_get("ref_id")?;
+        let nodes = if member_row.try_get("way_stored")? {
+            Some(fetch_way_node_coordinates(sqlite_pool, ref_id).await?.0)
+        } else {
+            None
+        };
+        members.push(MemberDetail { member_type: member_row.try_get("member_type")?, ref_id, role: member_row.try_get("role")?, nodes });
     }
 
-    Ok(nodes)
+    Ok(Some(RelationDetail {
+        id: row.try_get("id")?,
+        version: row.try_get("version")?,
+        timestamp: row.try_get("timestamp")?,
+        changeset: row.try_get("changeset")?,
+        uid: row.try_get("uid")?,
+        user: row.try_get("user")?,
+        tags,
+        members,
+    }))
 }
 
-pub async fn fetch_all_ways_and_tags(sqlite_pool: &SqlitePool) -> Result<Vec<Way>, sqlx::Error> {
+/// How deep `resolve_relation_tree` follows nested relations by default.
+pub const MAX_RELATION_DEPTH: usize = 8;
+
+/// A node or way reached through a tree of nested relations.
+///
+/// # Fields
+/// * `maps_type` - Whether the member is a node or a way.
+/// * `ref_id` - The id of the node or way.
+/// * `role` - The role of the member, or of the relation holding it if it has none of its own.
+/// * `path` - The ids of the relations from the root down to the one holding the member.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedMember {
+    pub maps_type: MapsType,
+    pub ref_id: i64,
+    pub role: String,
+    pub path: Vec<i64>,
+}
+
+/// A relation with the members of its member relations flattened into it.
+///
+/// # Fields
+/// * `id` - The id of the root relation.
+/// * `tags` - The tags of the root relation.
+/// * `members` - The nodes and ways of the whole tree, in member order.
+/// * `warnings` - The member relations that were not followed: cycles, missing relations and
+///   relations deeper than the maximum depth.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedRelation {
+    pub id: i64,
+    pub tags: Vec<Tag>,
+    pub members: Vec<ResolvedMember>,
+    pub warnings: Vec<String>,
+}
+
+/// Loads a relation and, recursively, the relations among its members, e.g. the routes of a
+/// route master or the parts of a multipolygon split into sub-relations.
+///
+/// A relation reached a second time is not followed again, so dirty data where a relation
+/// contains one of its parents ends with a warning instead of recursing forever.
+///
+/// ## Arguments
+/// * `sqlite_pool` - The database to load the relations from.
+/// * `relation_id` - The id of the root relation.
+/// * `max_depth` - How many levels of member relations to follow, 0 for the direct members only.
+///
+/// ## Returns
+/// * The nodes and ways of the tree with their roles and parent relations, or `None` if the
+///   root relation does not exist.
+pub async fn resolve_relation_tree(sqlite_pool: &SqlitePool, relation_id: i64, max_depth: usize) -> Result<Option<ResolvedRelation>, sqlx::Error> {
+    let Some(relation) = fetch_relation(sqlite_pool, relation_id).await? else {
+        return Ok(None);
+    };
+
+    let mut resolved = ResolvedRelation {
+        id: relation.id,
+        tags: relation.tags.clone(),
+        members: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let mut visited = HashSet::from([relation.id]);
+
+    flatten_relation(sqlite_pool, relation, vec![relation_id], String::new(), max_depth, &mut visited, &mut resolved).await?;
+
+    debug!(relation_id, members = resolved.members.len(), warnings = resolved.warnings.len(), "resolved relation tree");
+    Ok(Some(resolved))
+}
+
+// Async functions cannot call themselves directly, so the recursion goes through a boxed future
+fn flatten_relation<'a>(
+    sqlite_pool: &'a SqlitePool,
+    relation: Relation,
+    path: Vec<i64>,
+    inherited_role: String,
+    max_depth: usize,
+    visited: &'a mut HashSet<i64>,
+    resolved: &'a mut ResolvedRelation,
+) -> BoxFuture<'a, Result<(), sqlx::Error>> {
+    async move {
+        for member in relation.members {
+            let role = if member.role.is_empty() { inherited_role.clone() } else { member.role };
+
+            if member.maps_type != MapsType::Relation {
+                resolved.members.push(ResolvedMember { maps_type: member.maps_type, ref_id: member.ref_id, role, path: path.clone() });
+                continue;
+            }
+
+            let warning = if path.contains(&member.ref_id) {
+                Some(format!("relation {} contains relation {}, which is one of its parents", relation.id, member.ref_id))
+            } else if path.len() > max_depth {
+                Some(format!("relation {} is nested deeper than {} levels", member.ref_id, max_depth))
+            } else {
+                None
+            };
+            if let Some(warning) = warning {
+                warn!(root = resolved.id, %warning, "not following member relation");
+                resolved.warnings.push(warning);
+                continue;
+            }
+
+            // A relation reached through two parents is only flattened once
+            if !visited.insert(member.ref_id) {
+                continue;
+            }
+
+            let Some(child) = fetch_relation(sqlite_pool, member.ref_id).await? else {
+                let warning = format!("relation {} is missing from the database", member.ref_id);
+                warn!(root = resolved.id, %warning, "not following member relation");
+                resolved.warnings.push(warning);
+                continue;
+            };
+
+            let mut child_path = path.clone();
+            child_path.push(child.id);
+            flatten_relation(sqlite_pool, child, child_path, role, max_depth, visited, resolved).await?;
+        }
+
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Fetches every GPS track with at least one point inside the box.
+///
+/// Matching tracks are returned whole, including the points outside the box.
+pub async fn fetch_gps_tracks_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox) -> Result<Vec<GpsTrack>, sqlx::Error> {
     let query = "
         SELECT
-            w.id, w.version, w.timestamp, w.changeset, w.uid, w.[user],
-            node_refs.node_refs,
-            way_tags.tags
+            t.id, t.name, p.segment, p.lat, p.lon, p.elevation, p.time
         FROM
-            way w
-        LEFT JOIN (
-            SELECT
-                wn.way_id,
-                GROUP_CONCAT(wn.ref_id, ',') as node_refs
-            FROM
-                way_nodes wn
-            GROUP BY
-                wn.way_id
-        ) as node_refs ON w.id = node_refs.way_id
-        LEFT JOIN (
-            SELECT
-                wt.way_id,
-                GROUP_CONCAT(wt.[key] || ':' || wt.value, ',') as tags
-            FROM
-                way_tags wt
-            GROUP BY
-                wt.way_id
-        ) as way_tags ON w.id = way_tags.way_id
+            gps_track t
+        JOIN gps_track_point p ON p.track_id = t.id
+        WHERE
+            t.id IN (
+                SELECT DISTINCT track_id FROM gps_track_point
+                WHERE lat BETWEEN ? AND ? AND lon BETWEEN ? AND ?
+            )
+        ORDER BY t.id, p.segment, p.seq
     ";
 
     let fetched_result = sqlx::query(query)
+        .bind(bbox.min_lat)
+        .bind(bbox.max_lat)
+        .bind(bbox.min_lon)
+        .bind(bbox.max_lon)
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    let mut tracks: Vec<GpsTrack> = Vec::new();
+    let mut current_segment = -1;
+
+    // Rows are ordered by track and segment, so a change in either starts a new one
+    for row in fetched_result {
+        let id: i64 = row.try_get("id")?;
+        let segment: i64 = row.try_get("segment")?;
+
+        if tracks.last().map(|track| track.id) != Some(id) {
+            tracks.push(GpsTrack { id, name: row.try_get("name")?, segments: Vec::new() });
+            current_segment = -1;
+        }
+
+        let track = tracks.last_mut().unwrap();
+        if segment != current_segment {
+            track.segments.push(Vec::new());
+            current_segment = segment;
+        }
+
+        track.segments.last_mut().unwrap().push(GpsPoint {
+            lat: row.try_get("lat")?,
+            lon: row.try_get("lon")?,
+            elevation: row.try_get("elevation")?,
+            time: row.try_get("time")?,
+        });
+    }
+
+    Ok(tracks)
+}
+
+/// The feature found at a coordinate by `reverse_geocode`.
+///
+/// # Fields
+/// * `maps_type` - Whether the feature is a node or a way.
+/// * `id` - The id of the node or way.
+/// * `name` - The value of the `name` tag, if any.
+/// * `address` - The `addr:*` tags of the feature.
+/// * `distance_m` - The distance from the coordinate to the feature, 0 when the coordinate lies inside it.
+#[derive(Debug, Clone)]
+pub struct PlaceInfo {
+    pub maps_type: MapsType,
+    pub id: i64,
+    pub name: Option<String>,
+    pub address: Vec<Tag>,
+    pub distance_m: f64,
+}
+
+impl PlaceInfo {
+    fn new(maps_type: MapsType, id: i64, tags: &[Tag], distance_m: f64) -> Self {
+        PlaceInfo {
+            maps_type,
+            id,
+            name: tags.iter().find(|tag| tag.key == "name").map(|tag| tag.value.clone()),
+            address: tags.iter().filter(|tag| tag.key.starts_with("addr:")).cloned().collect(),
+            distance_m,
+        }
+    }
+}
+
+/// How far from the coordinate `reverse_geocode` looks for addressed nodes and ways.
+pub const REVERSE_GEOCODE_ADDRESS_RADIUS_M: f64 = 50.0;
+/// How far from the coordinate `reverse_geocode` looks for named ways.
+pub const REVERSE_GEOCODE_NAME_RADIUS_M: f64 = 500.0;
+
+// Ways with their node ids and stored coordinates in node order and their tags, filtered by the bounding boxes
+// in way_geom. The WHERE clause is appended by the caller.
+const WAY_SHAPES_QUERY: &str = "
+    SELECT
+        w.id,
+        (
+            SELECT GROUP_CONCAT(n.id || ' ' || n.lat_e7 || ' ' || n.lon_e7, ',' ORDER BY wn.way_id, wn.seq)
+            FROM way_nodes wn JOIN node n ON n.id = wn.ref_id
+            WHERE wn.way_id = w.id
+        ) as node_refs,
+        (
+            SELECT GROUP_CONCAT(k.text || '=' || v.text, ',' ORDER BY k.text)
+            FROM way_tags wt
+            JOIN tag_key k ON k.id = wt.key_id
+            JOIN tag_value v ON v.id = wt.value_id
+            WHERE wt.way_id = w.id
+        ) as tags,
+        (
+            SELECT COUNT(*)
+            FROM way_nodes wn LEFT JOIN node n ON n.id = wn.ref_id
+            WHERE wn.way_id = w.id AND n.id IS NULL
+        ) as missing_nodes,
+        (g.max_lat - g.min_lat) * (g.max_lon - g.min_lon) as bbox_area
+    FROM
+        way w
+    JOIN way_geom g ON g.way_id = w.id
+";
+
+/// Fetches the shapes of the ways whose bounding box intersects the given box and that
+/// match `condition`, an SQL expression over `w`.
+///
+/// The ways are ordered by the area of their bounding box, ways of the same area by id.
+async fn fetch_way_shapes_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox, condition: &str) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    let query = format!("
+        {}
+        WHERE
+            g.max_lat >= ? AND g.min_lat <= ? AND g.max_lon >= ? AND g.min_lon <= ?
+            AND {}
+        ORDER BY
+            bbox_area, w.id
+    ", WAY_SHAPES_QUERY, condition);
+
+    let fetched_result = sqlx::query(&query)
+        .bind(bbox.min_lat)
+        .bind(bbox.max_lat)
+        .bind(bbox.min_lon)
+        .bind(bbox.max_lon)
         .fetch_all(sqlite_pool)
         .await?;
 
@@ -108,52 +1101,986 @@ pub async fn fetch_all_ways_and_tags(sqlite_pool: &SqlitePool) -> Result<Vec<Way
 
     // Process fetched rows
     for row in fetched_result {
-        let way: Way = Way::from_row(&row)?;
+        let way: RenderableWay = RenderableWay::from_row(&row)?;
         ways.push(way);
     }
 
     Ok(ways)
 }
 
-pub async fn fetch_all_relations_and_tags(sqlite_pool: &SqlitePool) -> Result<Vec<Relation>, sqlx::Error> {
+/// Fetches every way whose bounding box intersects the given box.
+pub async fn fetch_renderable_ways_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    fetch_way_shapes_in_bbox(sqlite_pool, bbox, "1").await
+}
+
+/// The bounding box of a way as stored in `way_geom`, without its shape.
+///
+/// # Fields
+/// * `way_id` - The way.
+/// * `bbox` - Its bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WayBbox {
+    pub way_id: i64,
+    pub bbox: BBox,
+}
+
+/// Fetches the bounding boxes of the ways intersecting the viewport, largest first. This only
+/// reads `way_geom`, so it is much quicker than fetching the shapes of the ways.
+///
+/// ## Arguments
+/// * `limit` - The most boxes fetched, the smaller ones are left out.
+pub async fn fetch_way_bboxes_in_viewport(sqlite_pool: &SqlitePool, view: &BBox, limit: usize) -> Result<Vec<WayBbox>, sqlx::Error> {
+    let rows = sqlx::query("
+        SELECT way_id, min_lat, min_lon, max_lat, max_lon
+        FROM way_geom
+        WHERE max_lat >= ? AND min_lat <= ? AND max_lon >= ? AND min_lon <= ?
+        ORDER BY (max_lat - min_lat) * (max_lon - min_lon) DESC, way_id
+        LIMIT ?
+    ")
+        .bind(view.min_lat)
+        .bind(view.max_lat)
+        .bind(view.min_lon)
+        .bind(view.max_lon)
+        .bind(limit as i64)
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| Ok(WayBbox {
+            way_id: row.try_get("way_id")?,
+            bbox: BBox {
+                min_lat: row.try_get("min_lat")?,
+                max_lat: row.try_get("max_lat")?,
+                min_lon: row.try_get("min_lon")?,
+                max_lon: row.try_get("max_lon")?,
+            },
+        }))
+        .collect()
+}
+
+/// Fetches every way tagged with `highway` whose bounding box intersects the given box.
+pub async fn fetch_highway_shapes_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    fetch_way_shapes_in_bbox(sqlite_pool, bbox, "
+        EXISTS (SELECT 1 FROM way_tags wt WHERE wt.way_id = w.id AND wt.key_id = (SELECT id FROM tag_key WHERE text = 'highway'))
+    ").await
+}
+
+/// Fetches every way tagged with `natural=coastline` whose bounding box intersects the given box.
+pub async fn fetch_coastline_shapes_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox) -> Result<Vec<RenderableWay>, sqlx::Error> {
+    fetch_way_shapes_in_bbox(sqlite_pool, bbox, "
+        EXISTS (
+            SELECT 1 FROM way_tags wt
+            WHERE wt.way_id = w.id
+                AND wt.key_id = (SELECT id FROM tag_key WHERE text = 'natural')
+                AND wt.value_id = (SELECT id FROM tag_value WHERE text = 'coastline')
+        )
+    ").await
+}
+
+/// Counts the nodes within the given box, e.g. to pick the stride `fetch_nodes_in_bbox`
+/// samples them with.
+pub async fn count_nodes_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox) -> Result<usize, sqlx::Error> {
+    let count: i64 = sqlx::query_scalar("
+        SELECT COUNT(*) FROM node
+        WHERE lat_e7 BETWEEN ? AND ? AND lon_e7 BETWEEN ? AND ?
+    ")
+        .bind(to_e7(bbox.min_lat))
+        .bind(to_e7(bbox.max_lat))
+        .bind(to_e7(bbox.min_lon))
+        .bind(to_e7(bbox.max_lon))
+        .fetch_one(sqlite_pool)
+        .await?;
+
+    Ok(count as usize)
+}
+
+/// Fetches the coordinates of the nodes within the given box, without their tags, ordered
+/// by id, e.g. to draw them as dots where there are no ways.
+///
+/// Only every `stride`th node is fetched, counted in the order of their ids rather than by
+/// the ids themselves as those have gaps, so a box of many nodes is thinned out evenly in
+/// the database instead of loaded whole.
+///
+/// ## Arguments
+/// * `stride` - Fetches the first node and every `stride`th after it, 1 fetches all of them.
+/// * `limit` - The most nodes fetched.
+pub async fn fetch_nodes_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox, stride: usize, limit: usize) -> Result<Vec<SimpleNode>, sqlx::Error> {
+    let rows: Vec<(i64, i64, i64)> = sqlx::query_as("
+        SELECT id, lat_e7, lon_e7 FROM (
+            SELECT id, lat_e7, lon_e7, ROW_NUMBER() OVER (ORDER BY id) - 1 AS position FROM node
+            WHERE lat_e7 BETWEEN ? AND ? AND lon_e7 BETWEEN ? AND ?
+        )
+        WHERE position % ? = 0
+        ORDER BY id
+        LIMIT ?
+    ")
+        .bind(to_e7(bbox.min_lat))
+        .bind(to_e7(bbox.max_lat))
+        .bind(to_e7(bbox.min_lon))
+        .bind(to_e7(bbox.max_lon))
+        .bind(stride.max(1) as i64)
+        .bind(limit as i64)
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|(id, lat_e7, lon_e7)| SimpleNode { id: Some(id), lat: from_e7(lat_e7), lon: from_e7(lon_e7) }).collect())
+}
+
+/// The number of elements on a page unless the caller asks for another number.
+pub const DEFAULT_PAGE_LIMIT: usize = 1000;
+/// The most elements on a page, however many the caller asks for.
+pub const MAX_PAGE_LIMIT: usize = 10_000;
+
+/// One page of elements ordered by id, see `fetch_nodes_in_bbox_page`.
+///
+/// # Fields
+/// * `items` - The elements of the page.
+/// * `next_after_id` - The id to fetch the next page after, or `None` on the last page.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_after_id: Option<i64>,
+}
+
+/// Fetches one page of the nodes within the given box, with their tags.
+///
+/// Pages are cut by id rather than by offset, so walking them stays stable while other
+/// nodes are imported or deleted: every node that exists throughout the walk is on
+/// exactly one page.
+///
+/// ## Arguments
+/// * `after_id` - Only nodes with a larger id are fetched, `None` for the first page.
+/// * `limit` - The most nodes on the page, kept between 1 and `MAX_PAGE_LIMIT`.
+pub async fn fetch_nodes_in_bbox_page(sqlite_pool: &SqlitePool, bbox: &BBox, after_id: Option<i64>, limit: usize) -> Result<Page<Node>, sqlx::Error> {
+    let limit = limit.clamp(1, MAX_PAGE_LIMIT);
     let query = "
         SELECT
-            r.id, r.version, r.timestamp, r.changeset, r.uid, r.[user],
-            relation_tags.tags,
-            member.members
+            n.id, n.lat_e7, n.lon_e7, n.version, n.timestamp, n.changeset, n.uid, n.[user],
+            (
+                SELECT GROUP_CONCAT(k.text || '=' || v.text, ',' ORDER BY k.text)
+                FROM node_tags nt
+                JOIN tag_key k ON k.id = nt.key_id
+                JOIN tag_value v ON v.id = nt.value_id
+                WHERE nt.node_id = n.id
+            ) as tags
         FROM
-            relation r
-        LEFT JOIN (
-            SELECT
-                rt.relation_id,
-                GROUP_CONCAT(rt.[key] || ':' || rt.value, ',') as tags
-            FROM
-                relation_tags rt
-            GROUP BY
-                rt.relation_id
-        ) as relation_tags ON r.id = relation_tags.relation_id
-        LEFT JOIN (
-            SELECT
-                m.relation_id,
-                GROUP_CONCAT(m.id || ':' || IFNULL(m.node_id, '') || ':' || IFNULL(m.way_id, '') || ':' || IFNULL(m.relation_ref_id, '') || ':' || m.member_type || ':' || m.role, ',') as members
-            FROM
-                member m
-            GROUP BY
-                m.relation_id
-        ) as member ON r.id = member.relation_id
+            node n
+        WHERE
+            n.id > ? AND n.lat_e7 BETWEEN ? AND ? AND n.lon_e7 BETWEEN ? AND ?
+        ORDER BY
+            n.id
+        LIMIT ?
     ";
 
+    // One node more than asked for tells whether there is another page
     let fetched_result = sqlx::query(query)
+        .bind(after_id.unwrap_or(i64::MIN))
+        .bind(to_e7(bbox.min_lat))
+        .bind(to_e7(bbox.max_lat))
+        .bind(to_e7(bbox.min_lon))
+        .bind(to_e7(bbox.max_lon))
+        .bind(limit as i64 + 1)
         .fetch_all(sqlite_pool)
         .await?;
 
-    let mut relations = Vec::new();
+    let mut nodes = fetched_result.iter()
+        .map(Node::from_row)
+        .collect::<Result<Vec<Node>, sqlx::Error>>()?;
+
+    let next_after_id = if nodes.len() > limit {
+        nodes.truncate(limit);
+        nodes.last().map(|node| node.id)
+    } else {
+        None
+    };
+
+    Ok(Page { items: nodes, next_after_id })
+}
+
+/// Replaces `nearest` with `candidate` if the candidate is within `radius_m` and closer.
+fn keep_nearest(nearest: &mut Option<PlaceInfo>, candidate: PlaceInfo, radius_m: f64) {
+    let closer = nearest.as_ref().is_none_or(|best| candidate.distance_m < best.distance_m);
+    if candidate.distance_m <= radius_m && closer {
+        *nearest = Some(candidate);
+    }
+}
+
+/// Finds the most relevant feature at or near a coordinate.
+///
+/// The candidates are tried in this order:
+/// 1. The building or landuse polygon containing the coordinate, buildings before landuse
+///    and smaller polygons before larger ones.
+/// 2. The nearest node or way with an `addr:*` tag within `REVERSE_GEOCODE_ADDRESS_RADIUS_M`.
+/// 3. The nearest way with a `name` tag within `REVERSE_GEOCODE_NAME_RADIUS_M`.
+///
+/// Ways are only found if their bounding box is stored in `way_geom`.
+///
+/// ## Returns
+/// * The feature, or `None` if nothing matched.
+pub async fn reverse_geocode(sqlite_pool: &SqlitePool, lat: f64, lon: f64) -> Result<Option<PlaceInfo>, sqlx::Error> {
+    let point = (lat, lon);
+
+    // Polygons whose bounding box contains the point
+    let polygons = fetch_way_shapes_in_bbox(sqlite_pool, &BBox { min_lat: lat, max_lat: lat, min_lon: lon, max_lon: lon }, "
+        EXISTS (SELECT 1 FROM way_tags wt WHERE wt.way_id = w.id AND wt.key_id IN (SELECT id FROM tag_key WHERE text IN ('building', 'landuse')))
+    ").await?;
+
+    let containing = |key: &str| polygons.iter()
+        .filter(|way| way.tags.iter().any(|tag| tag.key == key))
+        .find(|way| point_in_polygon(point, &way.coords));
+
+    if let Some(way) = containing("building").or_else(|| containing("landuse")) {
+        return Ok(Some(PlaceInfo::new(MapsType::Way, way.id, &way.tags, 0.0)));
+    }
+
+    // Addressed nodes and ways nearby
+    let bbox = bbox_around(point, REVERSE_GEOCODE_ADDRESS_RADIUS_M);
+    let mut nearest: Option<PlaceInfo> = None;
+
+    let node_query = "
+        SELECT
+            n.id, n.lat_e7, n.lon_e7, n.version, n.timestamp, n.changeset, n.uid, n.[user],
+            (
+                SELECT GROUP_CONCAT(k.text || '=' || v.text, ',' ORDER BY k.text)
+                FROM node_tags nt
+                JOIN tag_key k ON k.id = nt.key_id
+                JOIN tag_value v ON v.id = nt.value_id
+                WHERE nt.node_id = n.id
+            ) as tags
+        FROM
+            node n
+        WHERE
+            n.lat_e7 BETWEEN ? AND ? AND n.lon_e7 BETWEEN ? AND ?
+            AND EXISTS (SELECT 1 FROM node_tags nt WHERE nt.node_id = n.id AND nt.key_id IN (SELECT id FROM tag_key WHERE text LIKE 'addr:%'))
+    ";
+
+    let fetched_result = sqlx::query(node_query)
+        .bind(to_e7(bbox.min_lat))
+        .bind(to_e7(bbox.max_lat))
+        .bind(to_e7(bbox.min_lon))
+        .bind(to_e7(bbox.max_lon))
+        .fetch_all(sqlite_pool)
+        .await?;
 
-    // Process fetched rows
     for row in fetched_result {
-        let relation: Relation = Relation::from_row(&row)?;
-        relations.push(relation);
+        let node = Node::from_row(&row)?;
+        let distance_m = haversine_distance(point, (node.lat, node.lon));
+        keep_nearest(&mut nearest, PlaceInfo::new(MapsType::Node, node.id, &node.tags, distance_m), REVERSE_GEOCODE_ADDRESS_RADIUS_M);
     }
 
-    Ok(relations)
+    let addressed_ways = fetch_way_shapes_in_bbox(sqlite_pool, &bbox, "
+        EXISTS (SELECT 1 FROM way_tags wt WHERE wt.way_id = w.id AND wt.key_id IN (SELECT id FROM tag_key WHERE text LIKE 'addr:%'))
+    ").await?;
+
+    for way in addressed_ways {
+        let distance_m = distance_to_polyline(point, &way.coords);
+        keep_nearest(&mut nearest, PlaceInfo::new(MapsType::Way, way.id, &way.tags, distance_m), REVERSE_GEOCODE_ADDRESS_RADIUS_M);
+    }
+
+    if nearest.is_some() {
+        return Ok(nearest);
+    }
+
+    // Named ways, e.g. the road the point is on
+    let bbox = bbox_around(point, REVERSE_GEOCODE_NAME_RADIUS_M);
+    let named_ways = fetch_way_shapes_in_bbox(sqlite_pool, &bbox, "
+        EXISTS (SELECT 1 FROM way_tags wt WHERE wt.way_id = w.id AND wt.key_id = (SELECT id FROM tag_key WHERE text = 'name'))
+    ").await?;
+
+    for way in named_ways {
+        let distance_m = distance_to_polyline(point, &way.coords);
+        keep_nearest(&mut nearest, PlaceInfo::new(MapsType::Way, way.id, &way.tags, distance_m), REVERSE_GEOCODE_NAME_RADIUS_M);
+    }
+
+    Ok(nearest)
+}
+
+/// Which tag a point of interest has to carry, parsed from `key=value`, or from `key` alone
+/// to match any value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl std::str::FromStr for TagFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match s.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+        if key.is_empty() || value.is_some_and(str::is_empty) {
+            return Err(format!("expected key=value or key, got '{}'", s));
+        }
+        Ok(TagFilter { key: key.to_string(), value: value.map(str::to_string) })
+    }
+}
+
+impl fmt::Display for TagFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={}", self.key, value),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+/// A node found by `find_nearest_poi`.
+///
+/// # Fields
+/// * `id` - The id of the node.
+/// * `lat`, `lon` - Where the node is.
+/// * `name` - The value of the `name` tag, if any.
+/// * `distance_m` - The great circle distance from the coordinate searched from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoiResult {
+    pub id: i64,
+    pub lat: f64,
+    pub lon: f64,
+    pub name: Option<String>,
+    pub distance_m: f64,
+}
+
+impl fmt::Display for PoiResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (node {}) at {:.7},{:.7}, {:.0} m", self.name.as_deref().unwrap_or("unnamed"), self.id, self.lat, self.lon, self.distance_m)
+    }
+}
+
+/// The radius `find_nearest_poi` searches first, doubled until enough nodes are found.
+pub const NEAREST_POI_START_RADIUS_M: f64 = 250.0;
+/// The radius beyond which `find_nearest_poi` stops searching.
+pub const NEAREST_POI_MAX_RADIUS_M: f64 = 25_000.0;
+
+/// Fetches the nodes carrying a tag within `radius_m` of a coordinate.
+async fn fetch_pois_within(sqlite_pool: &SqlitePool, point: (f64, f64), tag_filter: &TagFilter, radius_m: f64) -> Result<Vec<PoiResult>, sqlx::Error> {
+    // The box is searched through the index on the node coordinates, the corners beyond
+    // the radius are left out afterwards
+    let bbox = bbox_around(point, radius_m);
+    let query = "
+        SELECT
+            n.id, n.lat_e7, n.lon_e7,
+            (
+                SELECT v.text
+                FROM node_tags nt
+                JOIN tag_value v ON v.id = nt.value_id
+                WHERE nt.node_id = n.id AND nt.key_id = (SELECT id FROM tag_key WHERE text = 'name')
+            ) AS name
+        FROM
+            node n
+        WHERE
+            n.lat_e7 BETWEEN ? AND ? AND n.lon_e7 BETWEEN ? AND ?
+            AND EXISTS (
+                SELECT 1 FROM node_tags nt
+                WHERE nt.node_id = n.id
+                    AND nt.key_id = (SELECT id FROM tag_key WHERE text = ?)
+                    AND (? IS NULL OR nt.value_id = (SELECT id FROM tag_value WHERE text = ?))
+            )
+        ORDER BY
+            n.id
+    ";
+
+    let fetched_result = sqlx::query(query)
+        .bind(to_e7(bbox.min_lat))
+        .bind(to_e7(bbox.max_lat))
+        .bind(to_e7(bbox.min_lon))
+        .bind(to_e7(bbox.max_lon))
+        .bind(&tag_filter.key)
+        .bind(&tag_filter.value)
+        .bind(&tag_filter.value)
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    let mut pois = Vec::new();
+    for row in fetched_result {
+        let (lat, lon) = (from_e7(row.try_get("lat_e7")?), from_e7(row.try_get("lon_e7")?));
+        let distance_m = haversine_distance(point, (lat, lon));
+        if distance_m <= radius_m {
+            pois.push(PoiResult { id: row.try_get("id")?, lat, lon, name: row.try_get("name")?, distance_m });
+        }
+    }
+    Ok(pois)
+}
+
+/// Finds the nodes carrying a tag nearest to a coordinate, e.g. the closest supermarkets.
+///
+/// Rather than looking at every node with the tag, a window around the coordinate is
+/// searched, starting at `NEAREST_POI_START_RADIUS_M` and doubling until it holds `limit`
+/// nodes or reaches `NEAREST_POI_MAX_RADIUS_M`. Only nodes within the radius of the window
+/// count, so no node outside it can be nearer than the ones found.
+///
+/// ## Arguments
+/// * `tag_filter` - The tag the nodes carry, e.g. `shop=supermarket`.
+/// * `limit` - The most nodes returned.
+///
+/// ## Returns
+/// * The nodes ordered by distance, ties by id, fewer than `limit` if there are no more
+///   within `NEAREST_POI_MAX_RADIUS_M`.
+pub async fn find_nearest_poi(sqlite_pool: &SqlitePool, lat: f64, lon: f64, tag_filter: &TagFilter, limit: usize) -> Result<Vec<PoiResult>, sqlx::Error> {
+    let mut radius_m = NEAREST_POI_START_RADIUS_M;
+    loop {
+        let mut pois = fetch_pois_within(sqlite_pool, (lat, lon), tag_filter, radius_m).await?;
+        debug!(radius_m, found = pois.len(), %tag_filter, "searched for the nearest points of interest");
+
+        if pois.len() >= limit || radius_m >= NEAREST_POI_MAX_RADIUS_M {
+            pois.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m).then(a.id.cmp(&b.id)));
+            pois.truncate(limit);
+            return Ok(pois);
+        }
+        radius_m = (radius_m * 2.0).min(NEAREST_POI_MAX_RADIUS_M);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{insert_node_data, insert_way_data, update_way_geometry, InsertConfig};
+    use crate::test_support::{import_osm_xml, memory_pool, synthetic_nodes, synthetic_ways, SYNTHETIC_BBOX};
+
+    // A building split into sub-relations: 30 holds 31 with the outer ways, 31 holds 32 with
+    // the courtyard, and 32 holds 30 again, a cycle as found in dirty data
+    const NESTED_MULTIPOLYGON_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.00080" lon="11.00030" version="1"/>
+ <node id="2" lat="55.00080" lon="11.00130" version="1"/>
+ <node id="3" lat="55.00020" lon="11.00130" version="1"/>
+ <node id="4" lat="55.00020" lon="11.00030" version="1"/>
+ <node id="5" lat="55.00065" lon="11.00060" version="1"/>
+ <node id="6" lat="55.00065" lon="11.00105" version="1"/>
+ <node id="7" lat="55.00035" lon="11.00105" version="1"/>
+ <node id="8" lat="55.00035" lon="11.00060" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="4"/><nd ref="1"/></way>
+ <way id="12" version="1"><nd ref="5"/><nd ref="6"/><nd ref="7"/><nd ref="8"/><nd ref="5"/></way>
+ <relation id="30" version="1">
+  <member type="relation" ref="31" role=""/>
+  <tag k="type" v="multipolygon"/><tag k="building" v="yes"/>
+ </relation>
+ <relation id="31" version="1">
+  <member type="way" ref="10" role="outer"/><member type="way" ref="11" role="outer"/>
+  <member type="relation" ref="32" role="inner"/><member type="relation" ref="99" role="outer"/>
+  <tag k="type" v="multipolygon"/>
+ </relation>
+ <relation id="32" version="1">
+  <member type="way" ref="12" role=""/><member type="relation" ref="30" role=""/>
+  <tag k="type" v="multipolygon"/>
+ </relation>
+</osm>
+"#;
+
+    #[tokio::test]
+    async fn nested_relations_are_flattened_with_their_roles_and_paths() {
+        let pool = memory_pool("nested_relations").await;
+        import_osm_xml(&pool, "nested_relations", NESTED_MULTIPOLYGON_OSM).await;
+
+        let resolved = resolve_relation_tree(&pool, 30, MAX_RELATION_DEPTH).await.unwrap().unwrap();
+        let members: Vec<(i64, &str, &[i64])> = resolved.members.iter()
+            .map(|member| (member.ref_id, member.role.as_str(), member.path.as_slice()))
+            .collect();
+        assert_eq!(members, [(10, "outer", &[30, 31][..]), (11, "outer", &[30, 31][..]), (12, "inner", &[30, 31, 32][..])]);
+        assert!(resolved.members.iter().all(|member| member.maps_type == MapsType::Way));
+        assert_eq!(resolved.warnings, [
+            "relation 32 contains relation 30, which is one of its parents",
+            "relation 99 is missing from the database",
+        ]);
+        let tags: Vec<(&str, &str)> = resolved.tags.iter().map(|tag| (tag.key.as_str(), tag.value.as_str())).collect();
+        assert_eq!(tags, [("building", "yes"), ("type", "multipolygon")]);
+
+        assert!(resolve_relation_tree(&pool, 404, MAX_RELATION_DEPTH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn nested_relations_stop_at_the_maximum_depth() {
+        let pool = memory_pool("nested_relations_depth").await;
+        import_osm_xml(&pool, "nested_relations_depth", NESTED_MULTIPOLYGON_OSM).await;
+
+        let resolved = resolve_relation_tree(&pool, 30, 0).await.unwrap().unwrap();
+        assert!(resolved.members.is_empty());
+        assert_eq!(resolved.warnings, ["relation 31 is nested deeper than 0 levels"]);
+
+        let resolved = resolve_relation_tree(&pool, 30, 1).await.unwrap().unwrap();
+        assert_eq!(resolved.members.iter().map(|member| member.ref_id).collect::<Vec<_>>(), [10, 11]);
+        assert_eq!(resolved.warnings.len(), 2, "{:?}", resolved.warnings);
+    }
+
+    // Way 40 runs through its nodes out of the order of their ids, way 41 is closed
+    const WAY_ORDER_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.00080" lon="11.00030" version="1"/>
+ <node id="2" lat="55.00080" lon="11.00130" version="1"/>
+ <node id="3" lat="55.00020" lon="11.00130" version="1"/>
+ <node id="4" lat="55.00020" lon="11.00030" version="1"/>
+ <way id="40" version="1"><nd ref="3"/><nd ref="1"/><nd ref="4"/><nd ref="2"/><tag k="highway" v="path"/></way>
+ <way id="41" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><nd ref="1"/><tag k="building" v="yes"/></way>
+</osm>
+"#;
+
+    #[tokio::test]
+    async fn ways_are_read_back_in_their_order_and_closed_ways_stay_closed() {
+        let pool = memory_pool("way_node_order").await;
+        import_osm_xml(&pool, "way_node_order", WAY_ORDER_OSM).await;
+
+        let node_ids = |way: &WayDetail| way.nodes.iter().map(|node| node.id).collect::<Vec<i64>>();
+        assert_eq!(node_ids(&fetch_way_by_id(&pool, 40).await.unwrap().unwrap()), [3, 1, 4, 2]);
+        assert_eq!(node_ids(&fetch_way_by_id(&pool, 41).await.unwrap().unwrap()), [1, 2, 3, 4, 1]);
+
+        let ways = fetch_all_renderable_ways(&pool).await.unwrap();
+        let node_ids: Vec<Vec<i64>> = ways.iter()
+            .map(|way| way.node_ids.iter().map(|id| id.unwrap().get()).collect())
+            .collect();
+        assert_eq!(node_ids, [vec![3, 1, 4, 2], vec![1, 2, 3, 4, 1]]);
+        assert_eq!(ways[1].coords.first(), ways[1].coords.last());
+
+        let shapes = fetch_way_shapes_in_bbox(&pool, &BBox { min_lat: 55.0, max_lat: 55.001, min_lon: 11.0, max_lon: 11.002 }, "1").await.unwrap();
+        let closed = shapes.iter().find(|way| way.id == 41).unwrap();
+        assert_eq!(closed.coords.len(), 5);
+        assert_eq!(closed.coords.first(), closed.coords.last());
+    }
+
+    // Relation 60 holds a node, a way, a relation and a way missing from the database. Way
+    // 51 refers to node 9, which is removed behind the back of the foreign keys
+    const DETAIL_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.0" lon="11.0" version="2" changeset="7" uid="3" user="mapper"><tag k="amenity" v="bench"/></node>
+ <node id="2" lat="55.1" lon="11.1" version="1"/>
+ <node id="9" lat="55.2" lon="11.2" version="1"/>
+ <way id="50" version="1"><nd ref="2"/><nd ref="1"/><tag k="highway" v="path"/></way>
+ <way id="51" version="1"><nd ref="1"/><nd ref="9"/></way>
+ <relation id="61" version="1"><member type="node" ref="2" role=""/></relation>
+ <relation id="60" version="3">
+  <member type="node" ref="1" role="stop"/><member type="way" ref="50" role="platform"/>
+  <member type="relation" ref="61" role=""/><member type="way" ref="404" role="outer"/>
+  <tag k="type" v="route"/>
+ </relation>
+</osm>
+"#;
+
+    #[tokio::test]
+    async fn single_elements_are_fetched_with_their_tags_and_coordinates() {
+        let pool = memory_pool("element_detail").await;
+        import_osm_xml(&pool, "element_detail", DETAIL_OSM).await;
+        let mut connection = pool.acquire().await.unwrap();
+        for statement in ["PRAGMA foreign_keys = OFF", "DELETE FROM node WHERE id = 9", "PRAGMA foreign_keys = ON"] {
+            sqlx::query(statement).execute(&mut *connection).await.unwrap();
+        }
+        drop(connection);
+
+        let node = fetch_node_by_id(&pool, 1).await.unwrap().unwrap();
+        assert_eq!((node.id, node.lat, node.lon, node.version, node.changeset, node.uid, node.user.as_str()), (1, 55.0, 11.0, 2, 7, 3, "mapper"));
+        assert_eq!(node.tags.iter().map(|tag| (tag.key.as_str(), tag.value.as_str())).collect::<Vec<_>>(), [("amenity", "bench")]);
+
+        let way = fetch_way_by_id(&pool, 50).await.unwrap().unwrap();
+        let coordinates: Vec<(i64, f64, f64)> = way.nodes.iter().map(|node| (node.id, node.lat, node.lon)).collect();
+        assert_eq!(coordinates, [(2, 55.1, 11.1), (1, 55.0, 11.0)]);
+        assert!(way.missing_node_ids.is_empty());
+        let broken = fetch_way_by_id(&pool, 51).await.unwrap().unwrap();
+        assert_eq!((broken.nodes.len(), broken.missing_node_ids.as_slice()), (1, &[9][..]));
+
+        // The node coordinates are embedded in the way
+        let json: serde_json::Value = serde_json::to_value(&way).unwrap();
+        assert_eq!(json["nodes"][0]["lat"], 55.1);
+        assert_eq!(json["tags"][0]["key"], "highway");
+    }
+
+    #[tokio::test]
+    async fn elements_not_stored_are_not_found() {
+        let pool = memory_pool("element_detail_missing").await;
+        import_osm_xml(&pool, "element_detail_missing", DETAIL_OSM).await;
+
+        assert!(fetch_node_by_id(&pool, 404).await.unwrap().is_none());
+        assert!(fetch_way_by_id(&pool, 404).await.unwrap().is_none());
+        assert!(fetch_relation_by_id(&pool, 404).await.unwrap().is_none());
+        // Ids of other kinds of elements are not mistaken for them
+        assert!(fetch_way_by_id(&pool, 1).await.unwrap().is_none());
+        assert!(fetch_node_by_id(&pool, 50).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn a_relation_resolves_the_coordinates_of_its_way_members() {
+        let pool = memory_pool("relation_detail").await;
+        import_osm_xml(&pool, "relation_detail", DETAIL_OSM).await;
+
+        let relation = fetch_relation_by_id(&pool, 60).await.unwrap().unwrap();
+        assert_eq!(relation.version, 3);
+        let members: Vec<(&str, i64, &str, Option<usize>)> = relation.members.iter()
+            .map(|member| (member.member_type.as_str(), member.ref_id, member.role.as_str(), member.nodes.as_ref().map(Vec::len)))
+            .collect();
+        assert_eq!(members, [("node", 1, "stop", None), ("way", 50, "platform", Some(2)), ("relation", 61, "", None), ("way", 404, "outer", None)]);
+
+        // Members are written with the names of OSM, and only ways carry their nodes
+        let json: serde_json::Value = serde_json::to_value(&relation).unwrap();
+        assert_eq!((json["members"][1]["type"].as_str(), json["members"][1]["ref"].as_i64()), (Some("way"), Some(50)));
+        assert_eq!(json["members"][1]["nodes"][1]["id"], 1);
+        assert!(json["members"][0].get("nodes").is_none());
+    }
+
+    #[tokio::test]
+    async fn a_multipolygon_split_into_sub_relations_is_drawn_whole() {
+        let pool = memory_pool("nested_multipolygon_areas").await;
+        import_osm_xml(&pool, "nested_multipolygon_areas", NESTED_MULTIPOLYGON_OSM).await;
+
+        let areas = fetch_multipolygon_areas(&pool).await.unwrap();
+        assert_eq!(areas.len(), 1);
+        assert_eq!(areas[0].id, 30);
+        assert_eq!(areas[0].coords.len(), 5);
+        assert_eq!(areas[0].inner_rings.len(), 1);
+    }
+
+    // A building around a courtyard on a park: 40 is assembled from the outer ways 10 and 11
+    // and the closed inner way 12. 41 lacks its outer way 13, so its open chain is left out,
+    // while its closed inner ring is no area of its own
+    const COURTYARD_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.00080" lon="11.00030" version="1"/>
+ <node id="2" lat="55.00080" lon="11.00130" version="1"/>
+ <node id="3" lat="55.00020" lon="11.00130" version="1"/>
+ <node id="4" lat="55.00020" lon="11.00030" version="1"/>
+ <node id="5" lat="55.00065" lon="11.00060" version="1"/>
+ <node id="6" lat="55.00065" lon="11.00105" version="1"/>
+ <node id="7" lat="55.00035" lon="11.00105" version="1"/>
+ <node id="8" lat="55.00035" lon="11.00060" version="1"/>
+ <node id="9" lat="55.00000" lon="11.00000" version="1"/>
+ <node id="10" lat="55.00100" lon="11.00000" version="1"/>
+ <node id="11" lat="55.00100" lon="11.00160" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="4"/><nd ref="1"/></way>
+ <way id="12" version="1"><nd ref="5"/><nd ref="6"/><nd ref="7"/><nd ref="8"/><nd ref="5"/></way>
+ <way id="14" version="1"><nd ref="9"/><nd ref="10"/><nd ref="11"/></way>
+ <way id="20" version="1"><nd ref="9"/><nd ref="10"/><nd ref="11"/><nd ref="9"/><tag k="leisure" v="park"/></way>
+ <relation id="40" version="1">
+  <member type="way" ref="11" role="outer"/><member type="way" ref="12" role="inner"/><member type="way" ref="10" role="outer"/>
+  <tag k="type" v="multipolygon"/><tag k="building" v="yes"/><tag k="building:levels" v="3"/>
+ </relation>
+ <relation id="41" version="1">
+  <member type="way" ref="14" role="outer"/><member type="way" ref="13" role="outer"/><member type="way" ref="12" role="inner"/>
+  <tag k="type" v="multipolygon"/><tag k="landuse" v="grass"/>
+ </relation>
+ <relation id="42" version="1">
+  <member type="way" ref="10" role="outer"/><member type="way" ref="11" role="outer"/>
+  <tag k="type" v="multipolygon"/>
+ </relation>
+</osm>
+"#;
+
+    #[tokio::test]
+    async fn a_courtyard_is_a_hole_in_the_building_around_it() {
+        let pool = memory_pool("courtyard_areas").await;
+        import_osm_xml(&pool, "courtyard_areas", COURTYARD_OSM).await;
+
+        // 41 is missing a way and 42 has no tags of its own
+        let areas = fetch_multipolygon_areas(&pool).await.unwrap();
+        assert_eq!(areas.len(), 1);
+        let building = &areas[0];
+        assert_eq!(building.id, 40);
+        assert!(building.tags.iter().any(|tag| tag.key == "building:levels" && tag.value == "3"));
+        assert_eq!(building.coords.len(), 5);
+        assert_eq!(building.coords.first(), building.coords.last());
+        assert_eq!(building.inner_rings.len(), 1);
+        assert!(point_in_polygon((55.00050, 11.00080), &building.inner_rings[0]));
+
+        // The park below is a way of its own and comes after the boundaries with the areas
+        let relation_ways = fetch_relation_ways(&pool).await.unwrap();
+        assert_eq!(relation_ways.iter().map(|way| way.id).collect::<Vec<_>>(), [40]);
+    }
+
+    #[tokio::test]
+    async fn keys_with_a_colon_are_read_back_whole_on_nodes() {
+        let pool = memory_pool("address_node").await;
+        import_osm_xml(&pool, "address_node", r#"<osm version="0.6">
+ <node id="1" lat="55.00050" lon="11.00050" version="1"><tag k="addr:street" v="Havnegade"/><tag k="addr:housenumber" v="4"/><tag k="opening_hours" v="Mo-Fr 08:00-16:00"/></node>
+</osm>"#).await;
+
+        let place = reverse_geocode(&pool, 55.00051, 11.00050).await.unwrap().unwrap();
+        assert_eq!((place.maps_type, place.id), (MapsType::Node, 1));
+        let pairs = |tags: &[Tag]| -> Vec<(String, String)> { tags.iter().map(|tag| (tag.key.clone(), tag.value.clone())).collect() };
+        assert_eq!(pairs(&place.address), [("addr:housenumber".to_string(), "4".to_string()), ("addr:street".to_string(), "Havnegade".to_string())]);
+        let page = fetch_nodes_in_bbox_page(&pool, &BBox { min_lat: 55.0, max_lat: 55.001, min_lon: 11.0, max_lon: 11.001 }, None, 10).await.unwrap();
+        assert!(pairs(&page.items[0].tags).contains(&("opening_hours".to_string(), "Mo-Fr 08:00-16:00".to_string())), "{:?}", page.items[0].tags);
+    }
+
+    // The boundary 50 is chained from ways running either way: 11 runs against 10 and 12,
+    // and 13 is missing from the database. Boundary 51 closes around its area
+    const BOUNDARY_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.000" lon="11.000" version="1"/>
+ <node id="2" lat="55.000" lon="11.010" version="1"/>
+ <node id="3" lat="55.010" lon="11.010" version="1"/>
+ <node id="4" lat="55.010" lon="11.020" version="1"/>
+ <node id="5" lat="55.020" lon="11.020" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="2"/></way>
+ <way id="12" version="1"><nd ref="3"/><nd ref="4"/></way>
+ <way id="14" version="1"><nd ref="4"/><nd ref="5"/></way>
+ <way id="15" version="1"><nd ref="5"/><nd ref="3"/></way>
+ <relation id="50" version="1">
+  <member type="way" ref="12" role="outer"/><member type="way" ref="10" role="outer"/>
+  <member type="way" ref="13" role="outer"/><member type="way" ref="11" role="outer"/>
+  <tag k="type" v="boundary"/><tag k="boundary" v="administrative"/><tag k="admin_level" v="8"/>
+ </relation>
+ <relation id="51" version="1">
+  <member type="way" ref="12" role="outer"/><member type="way" ref="15" role="outer"/><member type="way" ref="14" role="outer"/>
+  <tag k="type" v="boundary"/><tag k="boundary" v="administrative"/><tag k="admin_level" v="9"/>
+ </relation>
+ <relation id="52" version="1">
+  <member type="way" ref="10" role="outer"/>
+  <tag k="type" v="boundary"/>
+ </relation>
+</osm>
+"#;
+
+    #[tokio::test]
+    async fn boundary_member_ways_are_chained_whichever_way_they_run() {
+        let pool = memory_pool("boundary_lines").await;
+        import_osm_xml(&pool, "boundary_lines", BOUNDARY_OSM).await;
+
+        let lines = fetch_boundary_lines(&pool).await.unwrap();
+        let node_ids: Vec<(i64, Vec<i64>)> = lines.iter()
+            .map(|line| (line.id, line.node_ids.iter().map(|id| id.unwrap().get()).collect()))
+            .collect();
+        // Relation 52 has no admin level, so it is not a boundary drawn
+        assert_eq!(node_ids.len(), 2, "{:?}", node_ids);
+        let (id, nodes) = &node_ids[0];
+        assert_eq!(*id, 50);
+        assert!(nodes == &[1, 2, 3, 4] || nodes == &[4, 3, 2, 1], "{:?}", nodes);
+        let (id, nodes) = &node_ids[1];
+        assert_eq!(*id, 51);
+        assert_eq!((nodes.len(), nodes.first()), (4, nodes.last()));
+
+        // The lines carry the tags of their relation
+        assert!(lines[0].tags.iter().any(|tag| tag.key == "admin_level" && tag.value == "8"));
+        assert!(lines.iter().all(|line| line.is_complete()));
+    }
+
+    /// Stores nodes and ways, in the order given and in batches of at most `max_rows_per_batch` rows.
+    async fn insert_synthetic(pool: &SqlitePool, nodes: Vec<Node>, ways: Vec<Way>, max_rows_per_batch: usize) {
+        let config = InsertConfig { max_rows_per_batch, ..Default::default() };
+        let way_ids: Vec<i64> = ways.iter().map(|way| way.id).collect();
+        insert_node_data(pool, nodes, None, &config).await.unwrap();
+        insert_way_data(pool, ways, None, &config).await.unwrap();
+        update_way_geometry(pool, &way_ids, &[], &config).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetches_come_in_the_same_order_however_the_data_was_inserted() {
+        let nodes = synthetic_nodes(60);
+        let ways = synthetic_ways(&nodes, 5);
+
+        let in_order = memory_pool("fetch_order_in_order").await;
+        insert_synthetic(&in_order, nodes.clone(), ways.clone(), 4000).await;
+        let reversed = memory_pool("fetch_order_reversed").await;
+        insert_synthetic(&reversed, nodes.into_iter().rev().collect(), ways.into_iter().rev().collect(), 7).await;
+
+        let renderable = format!("{:?}", fetch_all_renderable_ways(&in_order).await.unwrap());
+        assert_eq!(renderable, format!("{:?}", fetch_all_renderable_ways(&in_order).await.unwrap()));
+        assert_eq!(renderable, format!("{:?}", fetch_all_renderable_ways(&reversed).await.unwrap()));
+        let ids: Vec<i64> = fetch_all_renderable_ways(&reversed).await.unwrap().iter().map(|way| way.id).collect();
+        assert_eq!(ids, (1..=12).collect::<Vec<i64>>());
+
+        let in_bbox = |pool| async move { format!("{:?}", fetch_renderable_ways_in_bbox(pool, &SYNTHETIC_BBOX).await.unwrap()) };
+        assert_eq!(in_bbox(&in_order).await, in_bbox(&reversed).await);
+
+        let highways = |pool| async move { format!("{:?}", fetch_highway_ways(pool, &CancellationToken::default()).await.unwrap()) };
+        assert_eq!(highways(&in_order).await, highways(&reversed).await);
+    }
+
+    #[tokio::test]
+    async fn counts_and_streams_cover_every_row_without_collecting_them() {
+        let pool = memory_pool("fetch_counts_and_streams").await;
+        let nodes = synthetic_nodes(10_000);
+        let ways = synthetic_ways(&nodes, 10);
+        insert_synthetic(&pool, nodes, ways, 4000).await;
+
+        assert_eq!((count_nodes(&pool).await.unwrap(), count_ways(&pool).await.unwrap(), count_relations(&pool).await.unwrap()), (10_000, 1000, 0));
+
+        // Each way is handed on as soon as its node references are read
+        let ways = std::pin::pin!(ways_with_node_refs(
+            sqlx::query(WAYS_AND_TAGS_QUERY).fetch(&pool),
+            sqlx::query(WAY_NODE_REFS_QUERY).fetch(&pool),
+        ));
+        let (way_count, node_ref_count) = ways.try_fold((0, 0), |(ways, node_refs), way| async move { Ok((ways + 1, node_refs + way.node_refs.len())) }).await.unwrap();
+        assert_eq!((way_count, node_ref_count), (1000, 10_000));
+    }
+
+    #[tokio::test]
+    async fn pages_of_nodes_cover_the_box_once_while_nodes_are_imported() {
+        let pool = memory_pool("fetch_node_pages").await;
+        let nodes = synthetic_nodes(25);
+        insert_synthetic(&pool, nodes.clone(), Vec::new(), 4000).await;
+
+        let mut fetched = Vec::new();
+        let mut after_id = None;
+        let mut pages = 0;
+        loop {
+            let page = fetch_nodes_in_bbox_page(&pool, &SYNTHETIC_BBOX, after_id, 10).await.unwrap();
+            fetched.extend(page.items.iter().map(|node| node.id));
+            pages += 1;
+            if pages == 1 {
+                // Nodes imported during the walk before the cursor do not shift the later pages
+                let mut imported = synthetic_nodes(1);
+                imported[0].id = -1;
+                insert_synthetic(&pool, imported, Vec::new(), 4000).await;
+            }
+            match page.next_after_id {
+                Some(next) => after_id = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages, 3);
+        assert_eq!(fetched, nodes.iter().map(|node| node.id).collect::<Vec<i64>>());
+        let first = fetch_nodes_in_bbox_page(&pool, &SYNTHETIC_BBOX, None, 10).await.unwrap();
+        assert_eq!(format!("{:?}", first.items[0].tags), format!("{:?}", nodes[0].tags));
+    }
+
+    #[tokio::test]
+    async fn a_way_longer_than_a_batch_keeps_the_order_of_its_nodes() {
+        let nodes = synthetic_nodes(3000);
+        // The nodes are visited out of the order of their ids, and the way ends where it began
+        let mut node_refs: Vec<i64> = (0..3000).map(|index| (index * 1237) % 3000 + 1).collect();
+        node_refs.push(node_refs[0]);
+        let mut way = synthetic_ways(&nodes, 2).remove(0);
+        way.node_refs = node_refs.clone();
+
+        let pool = memory_pool("fetch_long_way").await;
+        insert_synthetic(&pool, nodes, vec![way], 100).await;
+
+        let detail = fetch_way_by_id(&pool, 1).await.unwrap().unwrap();
+        assert_eq!(detail.nodes.iter().map(|node| node.id).collect::<Vec<i64>>(), node_refs);
+        let highways = fetch_highway_ways(&pool, &CancellationToken::default()).await.unwrap();
+        assert_eq!(highways[0].node_refs, node_refs);
+        let renderable = fetch_all_renderable_ways(&pool).await.unwrap();
+        assert_eq!(renderable[0].node_ids.iter().map(|id| id.unwrap().get()).collect::<Vec<i64>>(), node_refs);
+    }
+
+    #[tokio::test]
+    async fn the_nearest_supermarkets_come_in_order_of_distance() {
+        const CENTER: (f64, f64) = (55.0, 12.0);
+        // The coordinate `north_m` meters north and `east_m` meters east of the center
+        let offset = |north_m: f64, east_m: f64| {
+            let meters_per_degree = crate::geo::EARTH_RADIUS_M.to_radians();
+            (CENTER.0 + north_m / meters_per_degree, CENTER.1 + east_m / (meters_per_degree * CENTER.0.to_radians().cos()))
+        };
+        let shops = [
+            // In a corner of the first window, but farther than its radius
+            (1, offset(240.0, 240.0), "supermarket", ""),
+            (2, offset(260.0, 0.0), "supermarket", r#"<tag k="name" v="Corner Shop"/>"#),
+            (3, offset(0.0, -1000.0), "supermarket", ""),
+            (4, offset(-3000.0, 0.0), "supermarket", ""),
+            // As far south as north
+            (6, offset(-5000.0, 0.0), "supermarket", ""),
+            (5, offset(5000.0, 0.0), "supermarket", ""),
+            (7, offset(0.0, 30_000.0), "supermarket", ""),
+            (8, offset(50.0, 0.0), "bakery", ""),
+        ];
+        let nodes: String = shops.iter()
+            .map(|(id, (lat, lon), shop, name)| format!(r#" <node id="{id}" lat="{lat:.7}" lon="{lon:.7}" version="1"><tag k="shop" v="{shop}"/>{name}</node>"#))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let pool = memory_pool("nearest_poi").await;
+        import_osm_xml(&pool, "nearest_poi", &format!("<osm version=\"0.6\">\n{nodes}\n</osm>")).await;
+
+        let supermarket: TagFilter = "shop = supermarket".parse().unwrap();
+        let nearest = |limit: usize, tag_filter: TagFilter| {
+            let pool = pool.clone();
+            async move { find_nearest_poi(&pool, CENTER.0, CENTER.1, &tag_filter, limit).await.unwrap() }
+        };
+
+        let found = nearest(1, supermarket.clone()).await;
+        assert_eq!(found.iter().map(|poi| poi.id).collect::<Vec<i64>>(), [2]);
+        assert_eq!(found[0].name.as_deref(), Some("Corner Shop"));
+        assert!((found[0].distance_m - 260.0).abs() < 1.0, "{}", found[0].distance_m);
+
+        // The window grows until it reaches the most distant radius, and stops there with
+        // fewer than asked for. The shop 30 km away is beyond it
+        let found = nearest(20, supermarket.clone()).await;
+        assert_eq!(found.iter().map(|poi| poi.id).collect::<Vec<i64>>(), [2, 1, 3, 4, 5, 6]);
+        assert!(found.windows(2).all(|pair| pair[0].distance_m <= pair[1].distance_m));
+        for (poi, expected_m) in found.iter().zip([260.0, 339.4, 1000.0, 3000.0, 5000.0, 5000.0]) {
+            assert!((poi.distance_m - expected_m).abs() < 1.0, "node {} is {} m away", poi.id, poi.distance_m);
+        }
+
+        // Any value of the key, and a value nobody has
+        assert_eq!(nearest(2, "shop".parse().unwrap()).await.iter().map(|poi| poi.id).collect::<Vec<i64>>(), [8, 2]);
+        assert!(nearest(3, "shop=florist".parse().unwrap()).await.is_empty());
+        assert!(nearest(0, supermarket).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn way_bounding_boxes_in_the_viewport_come_largest_first() {
+        let pool = memory_pool("way_bboxes").await;
+        import_osm_xml(&pool, "way_bboxes", r#"<osm version="0.6">
+ <node id="1" lat="55.000" lon="12.000" version="1"/>
+ <node id="2" lat="55.001" lon="12.001" version="1"/>
+ <node id="3" lat="55.010" lon="12.010" version="1"/>
+ <node id="4" lat="56.000" lon="13.000" version="1"/>
+ <node id="5" lat="56.001" lon="13.001" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/></way>
+ <way id="11" version="1"><nd ref="1"/><nd ref="3"/></way>
+ <way id="12" version="1"><nd ref="2"/><nd ref="3"/></way>
+ <way id="13" version="1"><nd ref="4"/><nd ref="5"/></way>
+</osm>"#).await;
+        let view = BBox { min_lat: 54.9, max_lat: 55.1, min_lon: 11.9, max_lon: 12.1 };
+
+        // Way 13 is outside the viewport
+        let bboxes = fetch_way_bboxes_in_viewport(&pool, &view, 10).await.unwrap();
+        assert_eq!(bboxes.iter().map(|bbox| bbox.way_id).collect::<Vec<i64>>(), [11, 12, 10]);
+        assert_eq!(bboxes[2].bbox, BBox { min_lat: 55.0, max_lat: 55.001, min_lon: 12.0, max_lon: 12.001 });
+
+        // The smallest are left out
+        let bboxes = fetch_way_bboxes_in_viewport(&pool, &view, 2).await.unwrap();
+        assert_eq!(bboxes.iter().map(|bbox| bbox.way_id).collect::<Vec<i64>>(), [11, 12]);
+    }
+
+    #[tokio::test]
+    async fn members_of_every_type_round_trip_with_their_roles() {
+        let pool = memory_pool("member_round_trip").await;
+        // The roles hold the characters the members were once joined and split by
+        import_osm_xml(&pool, "member_round_trip", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"/>
+ <node id="2" lat="55.1" lon="12.1" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/></way>
+ <relation id="20" version="1"><member type="node" ref="1" role=""/><tag k="type" v="site"/></relation>
+ <relation id="21" version="1">
+  <member type="node" ref="1" role="stop:entry_only"/>
+  <member type="way" ref="10" role="platform,left;right"/>
+  <member type="relation" ref="20" role="a:b,c"/>
+  <member type="way" ref="10" role="forward"/>
+  <member type="node" ref="999" role="missing|node"/>
+  <tag k="type" v="route"/>
+ </relation>
+</osm>"#).await;
+
+        let expected = [
+            (MapsType::Node, 1, "stop:entry_only"),
+            (MapsType::Way, 10, "platform,left;right"),
+            (MapsType::Relation, 20, "a:b,c"),
+            (MapsType::Way, 10, "forward"),
+            (MapsType::Node, 999, "missing|node"),
+        ];
+        let relation = fetch_relation(&pool, 21).await.unwrap().unwrap();
+        let members: Vec<(MapsType, i64, &str)> = relation.members.iter().map(|member| (member.maps_type.clone(), member.ref_id, member.role.as_str())).collect();
+        assert_eq!(members, expected);
+
+        let detail = fetch_relation_by_id(&pool, 21).await.unwrap().unwrap();
+        let members: Vec<(&str, i64, &str)> = detail.members.iter().map(|member| (member.member_type.as_str(), member.ref_id, member.role.as_str())).collect();
+        assert_eq!(members, expected.each_ref().map(|(maps_type, ref_id, role)| (maps_type.as_str(), *ref_id, *role)));
+        // Only the stored way member has its nodes
+        assert_eq!(detail.members.iter().map(|member| member.nodes.as_ref().map(Vec::len)).collect::<Vec<_>>(), [None, Some(2), None, Some(2), None]);
+
+        // A made up member type is refused
+        let result = sqlx::query("INSERT INTO member (relation_id, seq, ref_id, member_type, role) VALUES (21, 5, 1, 'area', '')").execute(&pool).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn tag_filters_are_parsed_from_key_and_value() {
+        assert_eq!("shop=supermarket".parse(), Ok(TagFilter { key: "shop".to_string(), value: Some("supermarket".to_string()) }));
+        assert_eq!(" amenity ".parse(), Ok(TagFilter { key: "amenity".to_string(), value: None }));
+        assert_eq!("shop = bakery".parse::<TagFilter>().unwrap().to_string(), "shop=bakery");
+        for malformed in ["", "=supermarket", "shop=", " = "] {
+            assert!(malformed.parse::<TagFilter>().is_err(), "{:?}", malformed);
+        }
+    }
 }
@@ -1,251 +1,1224 @@
-use sqlx::{QueryBuilder, SqlitePool};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::ops::AddAssign;
+use std::str::FromStr;
+use std::time::Duration;
+
+use sqlx::{query_builder::Separated, QueryBuilder, Row, Sqlite, SqlitePool};
+use tracing::debug;
 
 use crate::{
+    database::{fetch_data_extent, forget_unchanged_tags, max_variable_number, record_replaced_versions, DATA_EXTENT_SETTING, LEGACY_MAX_VARIABLE_NUMBER, VIEWPORT_SETTING},
+    geo::BBox,
+    gpx::{GpsPoint, GpsTrack},
+    metrics,
     osm_entities::{Node, Relation, Way},
-    utils::MapsType
+    utils::{to_e7, ELLIPSIS}
 };
 
-pub async fn insert_node_data(sqlite_pool: &SqlitePool, nodes: Vec<Node>) -> Result<(), sqlx::Error> {
-    // SQLite's max number of variables per statement
-    const SQLITE_MAX_VARIABLE_NUMBER: usize = 999;
-    let node_field_count = 8; // Number of fields per node
-    let tag_field_count = 3;  // Number of fields per tag (node_id, key, value)
+// Primary SQLite result codes for lock contention, see https://www.sqlite.org/rescode.html
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
 
-    // Calculate max nodes and tags per batch
-    let max_nodes_per_batch = SQLITE_MAX_VARIABLE_NUMBER / node_field_count;
-    let max_tags_per_batch = SQLITE_MAX_VARIABLE_NUMBER / tag_field_count;
+/// Error type returned by the inserters.
+#[derive(Debug)]
+pub enum InsertError {
+    /// Any database error that is not caused by lock contention, e.g. constraint violations.
+    Sqlx(sqlx::Error),
+    /// The database stayed busy or locked for every attempt allowed by the `RetryPolicy`.
+    RetriesExhausted {
+        attempts: u32,
+        source: sqlx::Error,
+    },
+}
 
-    // Ensure we do not exceed the batch size of 4000
-    let node_batch_size = max_nodes_per_batch.min(4000);
-    let tag_batch_size = max_tags_per_batch.min(4000);
+impl From<sqlx::Error> for InsertError {
+    fn from(err: sqlx::Error) -> Self {
+        InsertError::Sqlx(err)
+    }
+}
 
-    // Insert nodes in batches
-    for chunk in nodes.chunks(node_batch_size) {
-        let mut query_builder = QueryBuilder::new(
-            "INSERT OR IGNORE INTO node (id, lat, lon, version, timestamp, changeset, uid, [user]) "
-        );
-
-        query_builder.push_values(chunk, |mut b, node| {
-            b.push_bind(node.id)
-                .push_bind(node.lat)
-                .push_bind(node.lon)
-                .push_bind(node.version)
-                .push_bind(&node.timestamp)
-                .push_bind(node.changeset)
-                .push_bind(node.uid)
-                .push_bind(&node.user);
-        });
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertError::Sqlx(e) => write!(f, "Database error: {}", e),
+            InsertError::RetriesExhausted { attempts, source } => {
+                write!(f, "Database still locked after {} attempts: {}", attempts, source)
+            }
+        }
+    }
+}
 
-        let query = query_builder.build();
-        query.execute(sqlite_pool).await?;
+impl StdError for InsertError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            InsertError::Sqlx(e) => Some(e),
+            InsertError::RetriesExhausted { source, .. } => Some(source),
+        }
     }
+}
 
-    // Insert node tags in batches
-    for chunk in nodes.chunks(node_batch_size) {
-        let mut tags: Vec<(i64, &str, &str)> = Vec::new();
+/// Controls how often a batch is retried when SQLite reports `SQLITE_BUSY` or `SQLITE_LOCKED`.
+///
+/// The delay between attempts starts at `initial_backoff` and doubles after every
+/// failed attempt, but never grows beyond `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
 
-        for node in chunk {
-            for tag in &node.tags {
-                tags.push((node.id, &tag.key, &tag.value));
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 8,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait before the given retry (0 based).
+    fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry);
+        self.initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+
+    /// Waits before the given retry (0 based) after an attempt failed with `error`.
+    ///
+    /// ## Returns
+    /// * The error to give up with instead, if it is not caused by lock contention or every
+    ///   retry has been made.
+    async fn wait_to_retry(&self, retry: u32, error: sqlx::Error) -> Result<(), InsertError> {
+        if !is_lock_contention(&error) {
+            return Err(InsertError::Sqlx(error));
+        }
+        if retry >= self.max_retries {
+            return Err(InsertError::RetriesExhausted { attempts: retry + 1, source: error });
+        }
+        let backoff = self.backoff(retry);
+        debug!(retry, ?backoff, %error, "database is busy, retrying");
+        tokio::time::sleep(backoff).await;
+        Ok(())
+    }
+
+    /// Runs a statement or a transaction, again with exponential backoff while the database
+    /// is busy or locked. A transaction is rolled back when an attempt fails, so `attempt`
+    /// starts it anew every time.
+    pub async fn run<T, F, Fut>(&self, mut attempt: F) -> Result<T, InsertError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let mut retry = 0;
+
+        loop {
+            match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(error) => {
+                    self.wait_to_retry(retry, error).await?;
+                    retry += 1;
+                }
             }
         }
+    }
+}
 
-        for tag_chunk in tags.chunks(tag_batch_size) {
-            let mut tag_query_builder = QueryBuilder::new(
-                "INSERT OR IGNORE INTO node_tags (node_id, [key], value) "
-            );
+/// Returns true if the error is caused by another connection holding a lock on the database.
+pub fn is_lock_contention(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(db_error) => db_error
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            // SQLite reports extended result codes, the primary code lives in the lowest byte
+            .map(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Binds `rows` as the VALUES of the statement in `query_builder` and executes it,
+/// retrying with exponential backoff while the database is busy or locked.
+///
+/// The builder is reset before every attempt, so the same builder can be reused for
+/// every chunk of a table. Chunks of equal size produce identical SQL text, which lets
+/// sqlx reuse the prepared statement from its per-connection statement cache.
+async fn execute_batch<'args, I, F>(
+    sqlite_pool: &SqlitePool,
+    query_builder: &mut QueryBuilder<'args, Sqlite>,
+    rows: I,
+    mut push_row: F,
+    retry_policy: &RetryPolicy,
+) -> Result<(), InsertError>
+where
+    I: IntoIterator + Clone,
+    F: FnMut(Separated<'_, 'args, Sqlite, &'static str>, I::Item),
+{
+    let mut retry = 0;
 
-            tag_query_builder.push_values(tag_chunk, |mut b, (node_id, key, value)| {
-                b.push_bind(node_id)
-                    .push_bind(key)
-                    .push_bind(value);
-            });
+    loop {
+        query_builder.reset();
+        query_builder.push_values(rows.clone(), &mut push_row);
 
-            let tag_query = tag_query_builder.build();
-            tag_query.execute(sqlite_pool).await?;
+        // The builder is borrowed by the statement, so it cannot be rebuilt by `RetryPolicy::run`
+        match query_builder.build().execute(sqlite_pool).await {
+            Ok(_) => return Ok(()),
+            Err(error) => {
+                retry_policy.wait_to_retry(retry, error).await?;
+                retry += 1;
+            }
         }
     }
+}
 
-    Ok(())
+/// What is done with the control characters in tag keys and values, e.g. the line breaks of
+/// a multi-line `description` or a NUL byte of a broken editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlCharacters {
+    /// Written out as escapes, `\n`, `\r`, `\t`, `\0` or `\u{1b}`, so nothing is lost.
+    #[default]
+    Escape,
+    /// Left out, line breaks and tabs are replaced by a space so the words around them stay apart.
+    Strip,
 }
 
-pub async fn insert_way_data(sqlite_pool: &SqlitePool, ways: Vec<Way>) -> Result<(), sqlx::Error> {
-    // SQLite's max number of variables per statement
-    const SQLITE_MAX_VARIABLE_NUMBER: usize = 999;
-    let way_field_count = 6; // Number of fields per way
-    let way_node_field_count = 2; // Number of fields per way_node
-    let tag_field_count = 3;  // Number of fields per tag (way_id, key, value)
+impl FromStr for ControlCharacters {
+    type Err = String;
 
-    // Calculate max ways and tags per batch
-    let max_ways_per_batch = SQLITE_MAX_VARIABLE_NUMBER / way_field_count;
-    let max_way_nodes_per_batch = SQLITE_MAX_VARIABLE_NUMBER / way_node_field_count;
-    let max_tags_per_batch = SQLITE_MAX_VARIABLE_NUMBER / tag_field_count;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "escape" => Ok(ControlCharacters::Escape),
+            "strip" => Ok(ControlCharacters::Strip),
+            _ => Err(format!("unknown handling of control characters '{}', expected 'escape' or 'strip'", s)),
+        }
+    }
+}
 
-    // Ensure we do not exceed the batch size of 4000
-    let way_batch_size = max_ways_per_batch.min(4000);
-    let way_node_batch_size = max_way_nodes_per_batch.min(4000);
-    let tag_batch_size = max_tags_per_batch.min(4000);
+/// The keys `ImportTagFilter::RenderingOnly` keeps, with the keys below them such as `addr:street`
+/// or `name:en`. Besides what the map is drawn by, it keeps the relation types, boundaries,
+/// access restrictions and heights the viewer and the router read.
+pub const RENDERING_TAG_KEYS: &[&str] = &[
+    "highway", "building", "natural", "landuse", "waterway", "railway", "name", "ref", "oneway",
+    "maxspeed", "layer", "bridge", "tunnel", "amenity", "shop", "place", "addr",
+    "type", "route", "restriction", "boundary", "admin_level", "aeroway", "water", "tourism",
+    "area", "junction", "service", "height", "min_height",
+    "access", "motor_vehicle", "motorcar", "vehicle", "bicycle", "foot",
+];
 
-    // Insert ways in batches
-    for chunk in ways.chunks(way_batch_size) {
-        let mut query_builder = QueryBuilder::new(
-            "INSERT OR IGNORE INTO way (id, version, timestamp, changeset, uid, [user]) "
-        );
-
-        query_builder.push_values(chunk, |mut b, way| {
-            b.push_bind(way.id)
-                .push_bind(way.version)
-                .push_bind(&way.timestamp)
-                .push_bind(way.changeset)
-                .push_bind(way.uid)
-                .push_bind(&way.user);
-        });
+/// Which tags are stored on import. Most tags, such as `source`, `created_by` or survey
+/// dates, are never read, yet they make up most of the tag tables. Elements left without
+/// tags are still stored, ways need their geometry.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ImportTagFilter {
+    /// Every tag is stored.
+    #[default]
+    KeepAll,
+    /// Only the tags with a key of `RENDERING_TAG_KEYS`, for a database only used to view
+    /// and route on. A style sheet drawing other keys needs `Custom`.
+    RenderingOnly,
+    /// Only the tags with one of the keys, with the keys below them. A trailing `:*` is
+    /// allowed, `addr:*` keeps the same tags as `addr`.
+    Custom(Vec<String>),
+}
 
-        let query = query_builder.build();
-        query.execute(sqlite_pool).await?;
+impl ImportTagFilter {
+    /// Whether a tag with `key` is stored.
+    pub fn keeps(&self, key: &str) -> bool {
+        match self {
+            ImportTagFilter::KeepAll => true,
+            ImportTagFilter::RenderingOnly => RENDERING_TAG_KEYS.iter().any(|prefix| key_has_prefix(key, prefix)),
+            ImportTagFilter::Custom(prefixes) => prefixes.iter().any(|prefix| key_has_prefix(key, prefix)),
+        }
     }
+}
 
-    // Insert way_nodes in batches
-    for chunk in ways.chunks(way_batch_size) {
-        let way_nodes = Way::extract_way_node_refs(&chunk);
+/// Whether `key` is `prefix` or a key below it, `name:en` is below `name` but `names` is not.
+fn key_has_prefix(key: &str, prefix: &str) -> bool {
+    let prefix = prefix.strip_suffix(":*").unwrap_or(prefix);
+    match key.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with(':'),
+        None => false,
+    }
+}
 
-        for tag_chunk in way_nodes.chunks(way_node_batch_size) {
-            let mut way_node_query_builder = QueryBuilder::new(
-                "INSERT OR IGNORE INTO way_nodes (way_id, ref_id) "
-            );
-            way_node_query_builder.push_values(tag_chunk, |mut b, (way_id, ref_id)| {
-                b.push_bind(way_id)
-                .push_bind(ref_id);
-            });
+impl FromStr for ImportTagFilter {
+    type Err = String;
 
-            let way_node_query = way_node_query_builder.build();
-            way_node_query.execute(sqlite_pool).await?;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(ImportTagFilter::KeepAll),
+            "rendering" => Ok(ImportTagFilter::RenderingOnly),
+            keys => {
+                let keys: Vec<String> = keys.split(',').map(str::trim).filter(|key| !key.is_empty()).map(str::to_string).collect();
+                if keys.is_empty() {
+                    return Err(format!("unknown tag filter '{}', expected 'rendering', 'all' or a list of keys", s));
+                }
+                Ok(ImportTagFilter::Custom(keys))
+            }
         }
     }
+}
 
-    // Insert way tags in batches
-    for chunk in ways.chunks(way_batch_size) {
-        let mut tags: Vec<(i64, &str, &str)> = Vec::new();
+/// How tags are cleaned up before they are stored. SQLite ignores the lengths the tag tables
+/// declare, so without it a 10 KB `note` or a value with line breaks ends up in every
+/// GROUP_CONCAT of the tags and in every text shown.
+///
+/// # Fields
+/// * `max_value_chars` - Values longer than this many characters are cut off with an ellipsis.
+/// * `max_key_chars` - Tags with a longer key are left out, no real key comes close.
+/// * `control_characters` - What is done with control characters in keys and values.
+/// * `tag_filter` - Which tags are stored at all.
+#[derive(Debug, Clone)]
+pub struct TagPolicy {
+    pub max_value_chars: usize,
+    pub max_key_chars: usize,
+    pub control_characters: ControlCharacters,
+    pub tag_filter: ImportTagFilter,
+}
+
+impl Default for TagPolicy {
+    fn default() -> Self {
+        TagPolicy {
+            max_value_chars: 1024,
+            max_key_chars: 255,
+            control_characters: ControlCharacters::default(),
+            tag_filter: ImportTagFilter::default(),
+        }
+    }
+}
+
+/// How many tags the `TagPolicy` changed or left out.
+///
+/// # Fields
+/// * `truncated_values` - Values cut off at `max_value_chars`.
+/// * `normalized_tags` - Tags whose key or value had control characters escaped or stripped.
+/// * `rejected_keys` - Tags left out for a key longer than `max_key_chars`.
+/// * `filtered_tags` - Tags left out as the `tag_filter` does not keep their key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TagPolicyStats {
+    pub truncated_values: usize,
+    pub normalized_tags: usize,
+    pub rejected_keys: usize,
+    pub filtered_tags: usize,
+}
+
+impl TagPolicyStats {
+    /// Whether the policy changed no tag at all.
+    pub fn is_empty(&self) -> bool {
+        *self == TagPolicyStats::default()
+    }
+
+    /// Whether the policy had to clean up a tag, leaving out the tags it filtered on purpose.
+    pub fn has_cleanups(&self) -> bool {
+        self.truncated_values > 0 || self.normalized_tags > 0 || self.rejected_keys > 0
+    }
+}
+
+impl AddAssign for TagPolicyStats {
+    fn add_assign(&mut self, other: Self) {
+        self.truncated_values += other.truncated_values;
+        self.normalized_tags += other.normalized_tags;
+        self.rejected_keys += other.rejected_keys;
+        self.filtered_tags += other.filtered_tags;
+    }
+}
 
-        for way in chunk {
-            for tag in &way.tags {
-                tags.push((way.id, &tag.key, &tag.value));
+impl fmt::Display for TagPolicyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} tag values truncated, {} tags with control characters, {} tags with too long keys left out and {} tags filtered out",
+            self.truncated_values, self.normalized_tags, self.rejected_keys, self.filtered_tags,
+        )
+    }
+}
+
+impl TagPolicy {
+    /// Applies the policy to a tag, counting what it changed in `stats`.
+    ///
+    /// ## Returns
+    /// * The key and value to store, borrowed if unchanged, or `None` if the tag is left out.
+    pub fn apply<'a>(&self, key: &'a str, value: &'a str, stats: &mut TagPolicyStats) -> Option<(Cow<'a, str>, Cow<'a, str>)> {
+        if !self.tag_filter.keeps(key) {
+            stats.filtered_tags += 1;
+            return None;
+        }
+
+        let key = self.normalize(key);
+        // Checked on the key as stored, escaping makes it longer
+        if key.chars().count() > self.max_key_chars {
+            stats.rejected_keys += 1;
+            return None;
+        }
+
+        let normalized_value = self.normalize(value);
+        if matches!(key, Cow::Owned(_)) || matches!(normalized_value, Cow::Owned(_)) {
+            stats.normalized_tags += 1;
+        }
+
+        let value = if normalized_value.chars().count() > self.max_value_chars {
+            stats.truncated_values += 1;
+            Cow::Owned(self.truncate(value))
+        } else {
+            normalized_value
+        };
+
+        Some((key, value))
+    }
+
+    /// Escapes or strips the control characters of a text, see `ControlCharacters`.
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !text.chars().any(char::is_control) {
+            return Cow::Borrowed(text);
+        }
+
+        let mut normalized = String::with_capacity(text.len());
+        for c in text.chars() {
+            self.push_normalized(&mut normalized, c);
+        }
+        Cow::Owned(normalized)
+    }
+
+    /// Cuts a value off with an ellipsis so that it is at most `max_value_chars` long once
+    /// normalized. The value is cut between its characters before they are escaped, so an
+    /// escape is kept whole or left out, never cut in half.
+    fn truncate(&self, value: &str) -> String {
+        let max_chars = self.max_value_chars.saturating_sub(1);
+        let mut truncated = String::new();
+        let mut truncated_chars = 0;
+        let mut normalized = String::new();
+        for c in value.chars() {
+            normalized.clear();
+            self.push_normalized(&mut normalized, c);
+            let normalized_chars = normalized.chars().count();
+            if truncated_chars + normalized_chars > max_chars {
+                break;
             }
+            truncated.push_str(&normalized);
+            truncated_chars += normalized_chars;
         }
+        truncated.push(ELLIPSIS);
+        truncated
+    }
 
-        for tag_chunk in tags.chunks(tag_batch_size) {
-            let mut tag_query_builder = QueryBuilder::new(
-                "INSERT OR IGNORE INTO way_tags (way_id, [key], value) "
-            );
+    /// Pushes a character as it is stored, escaped or stripped if it is a control character.
+    fn push_normalized(&self, normalized: &mut String, c: char) {
+        match (self.control_characters, c) {
+            (_, c) if !c.is_control() => normalized.push(c),
+            (ControlCharacters::Escape, '\n') => normalized.push_str("\\n"),
+            (ControlCharacters::Escape, '\r') => normalized.push_str("\\r"),
+            (ControlCharacters::Escape, '\t') => normalized.push_str("\\t"),
+            (ControlCharacters::Escape, '\0') => normalized.push_str("\\0"),
+            (ControlCharacters::Escape, c) => normalized.extend(c.escape_unicode()),
+            (ControlCharacters::Strip, '\n' | '\r' | '\t') => normalized.push(' '),
+            (ControlCharacters::Strip, _) => (),
+        }
+    }
+}
 
-            tag_query_builder.push_values(tag_chunk, |mut b, (way_id, key, value)| {
-                b.push_bind(way_id)
-                    .push_bind(key)
-                    .push_bind(value);
-            });
+/// Settings shared by all inserters.
+///
+/// # Fields
+/// * `max_variable_number` - How many variables SQLite accepts in one statement, see `max_variable_number`.
+/// * `max_rows_per_batch` - An upper bound on the rows per statement, however many variables would fit.
+/// * `retry_policy` - How batches are retried while the database is locked.
+/// * `tag_policy` - How tags are cleaned up before they are stored.
+#[derive(Debug, Clone)]
+pub struct InsertConfig {
+    pub max_variable_number: usize,
+    pub max_rows_per_batch: usize,
+    pub retry_policy: RetryPolicy,
+    pub tag_policy: TagPolicy,
+}
 
-            let tag_query = tag_query_builder.build();
-            tag_query.execute(sqlite_pool).await?;
+impl Default for InsertConfig {
+    fn default() -> Self {
+        InsertConfig {
+            max_variable_number: LEGACY_MAX_VARIABLE_NUMBER,
+            max_rows_per_batch: 4000,
+            retry_policy: RetryPolicy::default(),
+            tag_policy: TagPolicy::default(),
         }
     }
+}
+
+impl InsertConfig {
+    /// Creates the default config with the variable limit of the SQLite behind `sqlite_pool`.
+    pub async fn detect(sqlite_pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        Ok(InsertConfig {
+            max_variable_number: max_variable_number(sqlite_pool).await?,
+            ..Default::default()
+        })
+    }
+
+    /// Returns how many rows with `fields_per_row` variables each fit in one statement.
+    fn batch_size(&self, fields_per_row: usize) -> usize {
+        (self.max_variable_number / fields_per_row).clamp(1, self.max_rows_per_batch.max(1))
+    }
+}
+
+/// Inserts `rows` with as few statements as the variable limit allows.
+///
+/// ## Arguments
+/// * `table_sql` - The start of the statement up to the VALUES, e.g. `INSERT INTO tag (key, value) `.
+/// * `rows` - The rows to insert.
+/// * `bind_row` - Binds the `fields_per_row` values of one row.
+/// * `fields_per_row` - The number of values `bind_row` binds for every row.
+async fn insert_in_batches<'args, T, F>(
+    sqlite_pool: &SqlitePool,
+    table_sql: &str,
+    rows: &'args [T],
+    mut bind_row: F,
+    fields_per_row: usize,
+    config: &InsertConfig,
+) -> Result<(), InsertError>
+where
+    F: FnMut(Separated<'_, 'args, Sqlite, &'static str>, &'args T),
+{
+    let mut query_builder = QueryBuilder::new(table_sql);
+    let batch_size = config.batch_size(fields_per_row);
+    debug!(statement = table_sql.trim(), rows = rows.len(), batch_size, "inserting");
+
+    for chunk in rows.chunks(batch_size) {
+        execute_batch(sqlite_pool, &mut query_builder, chunk, &mut bind_row, &config.retry_policy).await?;
+        metrics::INSERTED_ROWS.add(chunk.len());
+        metrics::INSERT_BATCHES.add(1);
+    }
 
     Ok(())
 }
 
-pub async fn insert_relation_data(sqlite_pool: &SqlitePool, relations: Vec<Relation>) -> Result<(), sqlx::Error> {
-    // SQLite's max number of variables per statement
-    const SQLITE_MAX_VARIABLE_NUMBER: usize = 999;
-    let relation_field_count = 6; // Number of fields per relation
-    let relation_member_field_count = 4; // Number of fields per member in a relation
-    let tag_field_count = 3;  // Number of fields per tag (relation_id, key, value)
+/// Looks up the ids of tag keys or values, adding the texts not stored yet to the dictionary.
+///
+/// ## Arguments
+/// * `dictionary` - The dictionary table, `tag_key` or `tag_value`.
+/// * `texts` - The texts to look up, repeats are looked up once.
+///
+/// ## Returns
+/// * A map from every text to its id in the dictionary.
+async fn intern_tag_texts<'a>(
+    sqlite_pool: &SqlitePool,
+    dictionary: &str,
+    texts: impl IntoIterator<Item = &'a str>,
+    config: &InsertConfig,
+) -> Result<HashMap<String, i64>, InsertError> {
+    let texts: Vec<&str> = texts.into_iter().collect::<HashSet<_>>().into_iter().collect();
+    let mut ids = fetch_tag_text_ids(sqlite_pool, dictionary, &texts, config).await?;
 
-    // Calculate max relations and tags per batch
-    let max_relations_per_batch = SQLITE_MAX_VARIABLE_NUMBER / relation_field_count;
-    let max_relation_members_per_batch = SQLITE_MAX_VARIABLE_NUMBER / relation_member_field_count;
-    let max_tags_per_batch = SQLITE_MAX_VARIABLE_NUMBER / tag_field_count;
+    let new_texts: Vec<&str> = texts.iter().copied().filter(|text| !ids.contains_key(*text)).collect();
+    if !new_texts.is_empty() {
+        // The ids are assigned by SQLite, so they are read back after the insert
+        insert_in_batches(sqlite_pool, &format!("INSERT OR IGNORE INTO {} (text) ", dictionary), &new_texts, |mut b, text| {
+            b.push_bind(*text);
+        }, 1, config).await?;
+        ids.extend(fetch_tag_text_ids(sqlite_pool, dictionary, &new_texts, config).await?);
+    }
 
-    // Ensure we do not exceed the batch size of 4000
-    let relation_batch_size = max_relations_per_batch.min(4000);
-    let relation_member_batch_size = max_relation_members_per_batch.min(4000);
-    let tag_batch_size = max_tags_per_batch.min(4000);
+    debug!(dictionary, texts = texts.len(), new = new_texts.len(), "interned tag texts");
+    Ok(ids)
+}
 
-    // Insert relations in batches
-    for chunk in relations.chunks(relation_batch_size) {
-        let mut query_builder = QueryBuilder::new(
-            "INSERT OR IGNORE INTO relation (id, version, timestamp, changeset, uid, [user]) "
-        );
-
-        query_builder.push_values(chunk, |mut b, relation| {
-            b.push_bind(relation.id)
-                .push_bind(relation.version)
-                .push_bind(&relation.timestamp)
-                .push_bind(relation.changeset)
-                .push_bind(relation.uid)
-                .push_bind(&relation.user);
-        });
+/// Fetches the ids of those texts that are stored in the dictionary table.
+async fn fetch_tag_text_ids(sqlite_pool: &SqlitePool, dictionary: &str, texts: &[&str], config: &InsertConfig) -> Result<HashMap<String, i64>, sqlx::Error> {
+    let mut ids = HashMap::with_capacity(texts.len());
 
-        let query = query_builder.build();
-        query.execute(sqlite_pool).await?;
+    for chunk in texts.chunks(config.batch_size(1)) {
+        let mut query_builder = QueryBuilder::<Sqlite>::new(format!("SELECT id, text FROM {} WHERE text IN (", dictionary));
+        let mut separated = query_builder.separated(", ");
+        for text in chunk {
+            separated.push_bind(*text);
+        }
+        query_builder.push(")");
+
+        for row in query_builder.build().fetch_all(sqlite_pool).await? {
+            ids.insert(row.try_get("text")?, row.try_get("id")?);
+        }
     }
 
-    // Insert relation_members in batches
-    for chunk in relations.chunks(relation_batch_size) {
-        let relation_members = Relation::extract_members(&chunk);
-
-        for member_chunk in relation_members.chunks(relation_member_batch_size) {
-            let mut relation_node_query_builder = QueryBuilder::new(
-                "INSERT OR IGNORE INTO member (id, relation_id, node_id, way_id, relation_ref_id, member_type, role) "
-            );
-
-            relation_node_query_builder.push_values(member_chunk, |mut b, (relation_id, member)| {
-                b.push_bind(member.id)
-                    .push_bind(relation_id)
-                    .push_bind(match member.maps_type {
-                        MapsType::Node => Some(member.ref_id),
-                        _ => None,
-                    })
-                    .push_bind(match member.maps_type {
-                        MapsType::Way => Some(member.ref_id),
-                        _ => None,
-                    })
-                    .push_bind(match member.maps_type {
-                        MapsType::Relation => Some(member.ref_id),
-                        _ => None,
-                    })
-                    .push_bind(member.maps_type.as_str())
-                    .push_bind(&member.role);
-            });
-
-            let relation_node_query = relation_node_query_builder.build();
-            relation_node_query.execute(sqlite_pool).await?;
+    Ok(ids)
+}
+
+/// Inserts `(element id, key, value)` tags into a tag table, interning their keys and values
+/// first. The tags are cleaned up by the `tag_policy` of the config before.
+///
+/// ## Arguments
+/// * `table_sql` - The start of the statement up to the VALUES, binding the element id, key id and value id.
+/// * `tags` - The tags to insert.
+///
+/// ## Returns
+/// * How many tags the policy changed or left out.
+async fn insert_tags(sqlite_pool: &SqlitePool, table_sql: &str, tags: &[(i64, &str, &str)], config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    let mut stats = TagPolicyStats::default();
+    let tags: Vec<(i64, Cow<str>, Cow<str>)> = tags.iter()
+        .filter_map(|(id, key, value)| config.tag_policy.apply(key, value, &mut stats).map(|(key, value)| (*id, key, value)))
+        .collect();
+    if !stats.is_empty() {
+        debug!(table_sql = table_sql.trim(), ?stats, "cleaned up tags");
+    }
+
+    let key_ids = intern_tag_texts(sqlite_pool, "tag_key", tags.iter().map(|(_, key, _)| key.as_ref()), config).await?;
+    let value_ids = intern_tag_texts(sqlite_pool, "tag_value", tags.iter().map(|(_, _, value)| value.as_ref()), config).await?;
+
+    let tags: Vec<(i64, i64, i64)> = tags.iter()
+        .map(|(id, key, value)| (*id, key_ids[key.as_ref()], value_ids[value.as_ref()]))
+        .collect();
+
+    insert_in_batches(sqlite_pool, table_sql, &tags, |mut b, (id, key_id, value_id)| {
+        b.push_bind(*id)
+            .push_bind(*key_id)
+            .push_bind(*value_id);
+    }, 3, config).await?;
+
+    Ok(stats)
+}
+
+/// The elements of an import sorted by what storing them takes, see `split_by_stored_version`.
+///
+/// # Fields
+/// * `new` - The elements not stored yet, to insert.
+/// * `updated` - The elements stored in another version, to replace.
+/// * `unchanged` - How many elements are stored in the same version and are left alone.
+#[derive(Debug)]
+pub struct VersionSplit<T> {
+    pub new: Vec<T>,
+    pub updated: Vec<T>,
+    pub unchanged: usize,
+}
+
+/// Sorts the elements of an import by whether they are stored, and in which version, so
+/// importing a file again only writes what changed in it.
+///
+/// An element whose version differs from the stored one replaces it, even an older one, as
+/// the imported file is taken to be right about the elements it holds. The version replaced
+/// is recorded, so `deduplicate` can bring back the higher one of overlapping extracts.
+///
+/// ## Arguments
+/// * `table` - The table the elements are stored in, `node`, `way` or `relation`.
+/// * `elements` - The elements read from the file.
+/// * `id_and_version` - Gives the id and version of an element.
+pub async fn split_by_stored_version<T>(
+    sqlite_pool: &SqlitePool,
+    table: &str,
+    elements: Vec<T>,
+    id_and_version: impl Fn(&T) -> (i64, i32),
+    config: &InsertConfig,
+) -> Result<VersionSplit<T>, sqlx::Error> {
+    let mut stored_versions: HashMap<i64, i32> = HashMap::new();
+    let ids: Vec<i64> = elements.iter().map(|element| id_and_version(element).0).collect();
+    for chunk in ids.chunks(config.batch_size(1)) {
+        let mut query_builder = QueryBuilder::<Sqlite>::new(format!("SELECT id, version FROM {} WHERE id IN (", table));
+        let mut separated = query_builder.separated(", ");
+        for id in chunk {
+            separated.push_bind(*id);
+        }
+        query_builder.push(")");
+
+        for row in query_builder.build().fetch_all(sqlite_pool).await? {
+            stored_versions.insert(row.try_get("id")?, row.try_get("version")?);
         }
     }
 
-    // Insert relation tags in batches
-    for chunk in relations.chunks(relation_batch_size) {
-        let mut tags: Vec<(i64, &str, &str)> = Vec::new();
+    let mut split = VersionSplit { new: Vec::new(), updated: Vec::new(), unchanged: 0 };
+    for element in elements {
+        let (id, version) = id_and_version(&element);
+        match stored_versions.get(&id) {
+            None => split.new.push(element),
+            Some(&stored_version) if stored_version != version => split.updated.push(element),
+            Some(_) => split.unchanged += 1,
+        }
+    }
+
+    debug!(table, new = split.new.len(), updated = split.updated.len(), unchanged = split.unchanged, "compared with the stored versions");
+    Ok(split)
+}
 
-        for relation in chunk {
-            for tag in &relation.tags {
-                tags.push((relation.id, &tag.key, &tag.value));
+/// Deletes the rows of a table referring to any of `ids`, e.g. the tags of updated elements
+/// before their new tags are inserted. Every batch is retried while the database is busy, see
+/// `RetryPolicy`.
+async fn delete_references(sqlite_pool: &SqlitePool, table: &str, id_column: &str, ids: &[i64], config: &InsertConfig) -> Result<(), InsertError> {
+    for chunk in ids.chunks(config.batch_size(1)) {
+        let sql = format!("DELETE FROM {} WHERE {} IN ({})", table, id_column, vec!["?"; chunk.len()].join(", "));
+        config.retry_policy.run(|| {
+            let mut query = sqlx::query(&sql);
+            for id in chunk {
+                query = query.bind(*id);
             }
+            query.execute(sqlite_pool)
+        }).await?;
+    }
+
+    Ok(())
+}
+
+/// Inserts nodes with their tags, see `TagPolicy` for how the tags are cleaned up.
+///
+/// ## Returns
+/// * How many tags the tag policy changed or left out.
+pub async fn insert_node_data(sqlite_pool: &SqlitePool, nodes: Vec<Node>, source_id: Option<i64>, config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    // Insert nodes in batches
+    insert_in_batches(sqlite_pool, "INSERT OR IGNORE INTO node (id, lat_e7, lon_e7, version, timestamp, changeset, uid, [user], source_id) ", &nodes, |mut b, node| {
+        b.push_bind(node.id)
+            .push_bind(to_e7(node.lat))
+            .push_bind(to_e7(node.lon))
+            .push_bind(node.version)
+            .push_bind(&node.timestamp)
+            .push_bind(node.changeset)
+            .push_bind(node.uid)
+            .push_bind(&node.user)
+            .push_bind(source_id);
+    }, 9, config).await?;
+
+    insert_node_tags(sqlite_pool, &nodes, config).await
+}
+
+/// Replaces stored nodes and their tags by another version of them, see `split_by_stored_version`.
+///
+/// ## Returns
+/// * How many tags the tag policy changed or left out.
+pub async fn update_node_data(sqlite_pool: &SqlitePool, nodes: Vec<Node>, source_id: Option<i64>, config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    if nodes.is_empty() {
+        return Ok(TagPolicyStats::default());
+    }
+
+    let ids: Vec<i64> = nodes.iter().map(|node| node.id).collect();
+    record_replaced_versions(sqlite_pool, "node", &ids, config).await?;
+
+    // The ways and tags referring to the nodes stay, so the rows are updated in place
+    let nodes_to_update = &nodes;
+    config.retry_policy.run(|| async move {
+        let mut tx = sqlite_pool.begin().await?;
+        for node in nodes_to_update {
+            sqlx::query("UPDATE node SET lat_e7 = ?, lon_e7 = ?, version = ?, timestamp = ?, changeset = ?, uid = ?, [user] = ?, source_id = ? WHERE id = ?")
+                .bind(to_e7(node.lat)).bind(to_e7(node.lon)).bind(node.version).bind(&node.timestamp).bind(node.changeset).bind(node.uid).bind(&node.user).bind(source_id).bind(node.id)
+                .execute(&mut *tx)
+                .await?;
         }
+        tx.commit().await
+    }).await?;
 
-        for tag_chunk in tags.chunks(tag_batch_size) {
-            let mut tag_query_builder = QueryBuilder::new(
-                "INSERT OR IGNORE INTO relation_tags (relation_id, [key], value) "
-            );
+    delete_references(sqlite_pool, "node_tags", "node_id", &ids, config).await?;
+    let stats = insert_node_tags(sqlite_pool, &nodes, config).await?;
+    forget_unchanged_tags(sqlite_pool, "node", &ids, config).await?;
+    Ok(stats)
+}
+
+async fn insert_node_tags(sqlite_pool: &SqlitePool, nodes: &[Node], config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    // Insert node tags in batches
+    let tags: Vec<(i64, &str, &str)> = nodes.iter()
+        .flat_map(|node| node.tags.iter().map(move |tag| (node.id, tag.key.as_str(), tag.value.as_str())))
+        .collect();
+
+    insert_tags(sqlite_pool, "INSERT OR IGNORE INTO node_tags (node_id, key_id, value_id) ", &tags, config).await
+}
+
+/// Inserts ways with their node references and tags, see `TagPolicy` for how the tags are cleaned up.
+///
+/// ## Returns
+/// * How many tags the tag policy changed or left out.
+pub async fn insert_way_data(sqlite_pool: &SqlitePool, ways: Vec<Way>, source_id: Option<i64>, config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    // Insert ways in batches
+    insert_in_batches(sqlite_pool, "INSERT OR IGNORE INTO way (id, version, timestamp, changeset, uid, [user], source_id) ", &ways, |mut b, way| {
+        b.push_bind(way.id)
+            .push_bind(way.version)
+            .push_bind(&way.timestamp)
+            .push_bind(way.changeset)
+            .push_bind(way.uid)
+            .push_bind(&way.user)
+            .push_bind(source_id);
+    }, 7, config).await?;
 
-            tag_query_builder.push_values(tag_chunk, |mut b, (relation_id, key, value)| {
-                b.push_bind(relation_id)
-                    .push_bind(key)
-                    .push_bind(value);
-            });
+    insert_way_references(sqlite_pool, &ways, config).await
+}
 
-            let tag_query = tag_query_builder.build();
-            tag_query.execute(sqlite_pool).await?;
+/// Replaces stored ways with their node references and tags by another version of them, see
+/// `split_by_stored_version`. The bounding boxes are left to `update_way_geometry`.
+///
+/// ## Returns
+/// * How many tags the tag policy changed or left out.
+pub async fn update_way_data(sqlite_pool: &SqlitePool, ways: Vec<Way>, source_id: Option<i64>, config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    if ways.is_empty() {
+        return Ok(TagPolicyStats::default());
+    }
+
+    let ids: Vec<i64> = ways.iter().map(|way| way.id).collect();
+    record_replaced_versions(sqlite_pool, "way", &ids, config).await?;
+
+    let ways_to_update = &ways;
+    config.retry_policy.run(|| async move {
+        let mut tx = sqlite_pool.begin().await?;
+        for way in ways_to_update {
+            sqlx::query("UPDATE way SET version = ?, timestamp = ?, changeset = ?, uid = ?, [user] = ?, source_id = ? WHERE id = ?")
+                .bind(way.version).bind(&way.timestamp).bind(way.changeset).bind(way.uid).bind(&way.user).bind(source_id).bind(way.id)
+                .execute(&mut *tx)
+                .await?;
         }
+        tx.commit().await
+    }).await?;
+
+    delete_references(sqlite_pool, "way_nodes", "way_id", &ids, config).await?;
+    delete_references(sqlite_pool, "way_tags", "way_id", &ids, config).await?;
+    let stats = insert_way_references(sqlite_pool, &ways, config).await?;
+    forget_unchanged_tags(sqlite_pool, "way", &ids, config).await?;
+    Ok(stats)
+}
+
+async fn insert_way_references(sqlite_pool: &SqlitePool, ways: &[Way], config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    // Insert way_nodes in batches
+    let way_nodes = Way::extract_way_node_refs(ways);
+
+    insert_in_batches(sqlite_pool, "INSERT OR IGNORE INTO way_nodes (way_id, seq, ref_id) ", &way_nodes, |mut b, (way_id, seq, ref_id)| {
+        b.push_bind(*way_id)
+            .push_bind(*seq)
+            .push_bind(*ref_id);
+    }, 3, config).await?;
+
+    // Insert way tags in batches
+    let tags: Vec<(i64, &str, &str)> = ways.iter()
+        .flat_map(|way| way.tags.iter().map(move |tag| (way.id, tag.key.as_str(), tag.value.as_str())))
+        .collect();
+
+    insert_tags(sqlite_pool, "INSERT OR IGNORE INTO way_tags (way_id, key_id, value_id) ", &tags, config).await
+}
+
+/// Inserts relations with their members and tags, see `TagPolicy` for how the tags are cleaned up.
+///
+/// ## Returns
+/// * How many tags the tag policy changed or left out.
+pub async fn insert_relation_data(sqlite_pool: &SqlitePool, relations: Vec<Relation>, source_id: Option<i64>, config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    // Insert relations in batches
+    insert_in_batches(sqlite_pool, "INSERT OR IGNORE INTO relation (id, version, timestamp, changeset, uid, [user], source_id) ", &relations, |mut b, relation| {
+        b.push_bind(relation.id)
+            .push_bind(relation.version)
+            .push_bind(&relation.timestamp)
+            .push_bind(relation.changeset)
+            .push_bind(relation.uid)
+            .push_bind(&relation.user)
+            .push_bind(source_id);
+    }, 7, config).await?;
+
+    insert_relation_references(sqlite_pool, &relations, config).await
+}
+
+/// Replaces stored relations with their members and tags by another version of them, see
+/// `split_by_stored_version`.
+///
+/// ## Returns
+/// * How many tags the tag policy changed or left out.
+pub async fn update_relation_data(sqlite_pool: &SqlitePool, relations: Vec<Relation>, source_id: Option<i64>, config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    if relations.is_empty() {
+        return Ok(TagPolicyStats::default());
+    }
+
+    let ids: Vec<i64> = relations.iter().map(|relation| relation.id).collect();
+    record_replaced_versions(sqlite_pool, "relation", &ids, config).await?;
+
+    let relations_to_update = &relations;
+    config.retry_policy.run(|| async move {
+        let mut tx = sqlite_pool.begin().await?;
+        for relation in relations_to_update {
+            sqlx::query("UPDATE relation SET version = ?, timestamp = ?, changeset = ?, uid = ?, [user] = ?, source_id = ? WHERE id = ?")
+                .bind(relation.version).bind(&relation.timestamp).bind(relation.changeset).bind(relation.uid).bind(&relation.user).bind(source_id).bind(relation.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }).await?;
+
+    delete_references(sqlite_pool, "member", "relation_id", &ids, config).await?;
+    delete_references(sqlite_pool, "relation_tags", "relation_id", &ids, config).await?;
+    let stats = insert_relation_references(sqlite_pool, &relations, config).await?;
+    forget_unchanged_tags(sqlite_pool, "relation", &ids, config).await?;
+    Ok(stats)
+}
+
+async fn insert_relation_references(sqlite_pool: &SqlitePool, relations: &[Relation], config: &InsertConfig) -> Result<TagPolicyStats, InsertError> {
+    // Insert relation_members in batches
+    let relation_members = Relation::extract_members(relations);
+
+    insert_in_batches(sqlite_pool, "INSERT OR IGNORE INTO member (relation_id, seq, ref_id, member_type, role) ", &relation_members, |mut b, (relation_id, seq, member)| {
+        b.push_bind(*relation_id)
+            .push_bind(*seq)
+            .push_bind(member.ref_id)
+            .push_bind(member.maps_type.as_str())
+            .push_bind(&member.role);
+    }, 5, config).await?;
+
+    // Insert relation tags in batches
+    let tags: Vec<(i64, &str, &str)> = relations.iter()
+        .flat_map(|relation| relation.tags.iter().map(move |tag| (relation.id, tag.key.as_str(), tag.value.as_str())))
+        .collect();
+
+    insert_tags(sqlite_pool, "INSERT OR IGNORE INTO relation_tags (relation_id, key_id, value_id) ", &tags, config).await
+}
+
+/// Recomputes the bounding boxes in the `way_geom` table of the ways an import touched from
+/// the coordinates of their nodes, rather than of every way stored.
+///
+/// Must run after the nodes and ways of an import have been inserted. Ways without any
+/// known node get no bounding box.
+///
+/// ## Arguments
+/// * `way_ids` - The ways inserted or replaced.
+/// * `node_ids` - The nodes replaced, which may have moved. The ways referring to them are
+///   recomputed as well, found by a scan of `way_nodes`, so it is skipped if there are none.
+pub async fn update_way_geometry(sqlite_pool: &SqlitePool, way_ids: &[i64], node_ids: &[i64], config: &InsertConfig) -> Result<(), InsertError> {
+    // A temporary table belongs to its connection, so everything runs in one transaction
+    let mut tx = sqlite_pool.begin().await?;
+    sqlx::query("CREATE TEMP TABLE IF NOT EXISTS touched_way (id INTEGER PRIMARY KEY)").execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM temp.touched_way").execute(&mut *tx).await?;
+
+    for chunk in way_ids.chunks(config.batch_size(1)) {
+        let mut query_builder = QueryBuilder::<Sqlite>::new("INSERT OR IGNORE INTO temp.touched_way (id) ");
+        query_builder.push_values(chunk, |mut b, id| {
+            b.push_bind(*id);
+        });
+        query_builder.build().execute(&mut *tx).await?;
+    }
+    for chunk in node_ids.chunks(config.batch_size(1)) {
+        let mut query_builder = QueryBuilder::<Sqlite>::new("INSERT OR IGNORE INTO temp.touched_way (id) SELECT way_id FROM way_nodes WHERE ref_id IN (");
+        let mut separated = query_builder.separated(", ");
+        for id in chunk {
+            separated.push_bind(*id);
+        }
+        query_builder.push(")");
+        query_builder.build().execute(&mut *tx).await?;
     }
 
+    sqlx::query("
+        INSERT OR REPLACE INTO way_geom (way_id, min_lat, min_lon, max_lat, max_lon)
+        SELECT
+            wn.way_id, MIN(n.lat_e7) / 1e7, MIN(n.lon_e7) / 1e7, MAX(n.lat_e7) / 1e7, MAX(n.lon_e7) / 1e7
+        FROM
+            temp.touched_way t
+        JOIN way_nodes wn ON wn.way_id = t.id
+        JOIN node n ON n.id = wn.ref_id
+        GROUP BY
+            wn.way_id
+    ")
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DROP TABLE temp.touched_way").execute(&mut *tx).await?;
+    tx.commit().await?;
+
     Ok(())
 }
+
+/// Stores a value under a key, replacing the value stored before.
+pub async fn save_setting(sqlite_pool: &SqlitePool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT OR REPLACE INTO settings ([key], value) VALUES (?, ?)")
+        .bind(key)
+        .bind(value)
+        .execute(sqlite_pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Grows the saved extent of the imported data to cover the box of an import.
+///
+/// Must run after the nodes of the import have been inserted, so a database without a saved
+/// extent gets the extent of all its nodes.
+///
+/// ## Arguments
+/// * `imported` - The box around the imported nodes.
+pub async fn update_data_extent(sqlite_pool: &SqlitePool, imported: &BBox) -> Result<(), sqlx::Error> {
+    let extent = match fetch_data_extent(sqlite_pool).await? {
+        Some(extent) => extent.union(imported),
+        None => *imported,
+    };
+
+    save_setting(sqlite_pool, DATA_EXTENT_SETTING, &bbox_setting_value(&extent)).await
+}
+
+/// Saves the viewport shown, to show it again on the next run.
+pub async fn save_viewport(sqlite_pool: &SqlitePool, viewport: &BBox) -> Result<(), sqlx::Error> {
+    save_setting(sqlite_pool, VIEWPORT_SETTING, &bbox_setting_value(viewport)).await
+}
+
+/// Formats a box as saved in the settings table, `top,left,bottom,right` in degrees.
+fn bbox_setting_value(bbox: &BBox) -> String {
+    format!("{},{},{},{}", bbox.max_lat, bbox.min_lon, bbox.min_lat, bbox.max_lon)
+}
+
+/// Inserts GPS tracks and their points.
+///
+/// ## Returns
+/// * The database ids assigned to the tracks, in the order they were given.
+pub async fn insert_gps_tracks(sqlite_pool: &SqlitePool, tracks: Vec<GpsTrack>, config: &InsertConfig) -> Result<Vec<i64>, InsertError> {
+    let mut track_ids = Vec::with_capacity(tracks.len());
+
+    for track in &tracks {
+        // The id is assigned by SQLite, so every track is inserted on its own
+        let track_id = sqlx::query("INSERT INTO gps_track (name) VALUES (?)")
+            .bind(&track.name)
+            .execute(sqlite_pool)
+            .await?
+            .last_insert_rowid();
+        track_ids.push(track_id);
+
+        let points: Vec<(i64, usize, &GpsPoint)> = track.segments.iter()
+            .enumerate()
+            .flat_map(|(segment, points)| points.iter().enumerate().map(move |(seq, point)| (segment as i64, seq, point)))
+            .collect();
+
+        insert_in_batches(sqlite_pool, "INSERT OR IGNORE INTO gps_track_point (track_id, segment, seq, lat, lon, elevation, time) ", &points, |mut b, (segment, seq, point)| {
+            b.push_bind(track_id)
+                .push_bind(*segment)
+                .push_bind(*seq as i64)
+                .push_bind(point.lat)
+                .push_bind(point.lon)
+                .push_bind(point.elevation)
+                .push_bind(&point.time);
+        }, 7, config).await?;
+    }
+
+    Ok(track_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(control_characters: ControlCharacters, tag_filter: ImportTagFilter) -> TagPolicy {
+        TagPolicy { max_value_chars: 8, max_key_chars: 6, control_characters, tag_filter }
+    }
+
+    #[test]
+    fn a_key_has_the_prefixes_it_is_below() {
+        assert!(key_has_prefix("name", "name"));
+        assert!(key_has_prefix("name:en", "name"));
+        assert!(key_has_prefix("addr:street", "addr:*"));
+        assert!(key_has_prefix("addr", "addr:*"));
+        assert!(!key_has_prefix("names", "name"));
+        assert!(!key_has_prefix("nam", "name"));
+        assert!(!key_has_prefix("old_name", "name"));
+    }
+
+    #[test]
+    fn the_tag_filters_keep_their_keys() {
+        assert!(ImportTagFilter::KeepAll.keeps("created_by"));
+
+        assert!(ImportTagFilter::RenderingOnly.keeps("highway"));
+        assert!(ImportTagFilter::RenderingOnly.keeps("name:de"));
+        assert!(ImportTagFilter::RenderingOnly.keeps("addr:housenumber"));
+        assert!(!ImportTagFilter::RenderingOnly.keeps("created_by"));
+        assert!(!ImportTagFilter::RenderingOnly.keeps("source"));
+
+        let custom: ImportTagFilter = "shop, addr:*".parse().unwrap();
+        assert_eq!(custom, ImportTagFilter::Custom(vec!["shop".to_string(), "addr:*".to_string()]));
+        assert!(custom.keeps("shop"));
+        assert!(custom.keeps("addr:city"));
+        assert!(!custom.keeps("highway"));
+        assert!(!custom.keeps("shops"));
+    }
+
+    #[test]
+    fn tag_filters_are_read_by_name_or_as_keys() {
+        assert_eq!("all".parse::<ImportTagFilter>(), Ok(ImportTagFilter::KeepAll));
+        assert_eq!("rendering".parse::<ImportTagFilter>(), Ok(ImportTagFilter::RenderingOnly));
+        assert!(" , ".parse::<ImportTagFilter>().is_err());
+        assert_eq!("strip".parse::<ControlCharacters>(), Ok(ControlCharacters::Strip));
+        assert_eq!("escape".parse::<ControlCharacters>(), Ok(ControlCharacters::Escape));
+        assert!("drop".parse::<ControlCharacters>().is_err());
+    }
+
+    #[test]
+    fn a_clean_tag_is_stored_as_it_is() {
+        let mut stats = TagPolicyStats::default();
+        let (key, value) = policy(ControlCharacters::Escape, ImportTagFilter::KeepAll).apply("shop", "bakery", &mut stats).unwrap();
+        assert!(matches!((&key, &value), (Cow::Borrowed("shop"), Cow::Borrowed("bakery"))));
+        assert!(stats.is_empty());
+    }
+
+    #[test]
+    fn the_policy_leaves_out_filtered_tags_and_long_keys() {
+        let mut stats = TagPolicyStats::default();
+        let policy = policy(ControlCharacters::Escape, ImportTagFilter::Custom(vec!["note".to_string()]));
+        assert_eq!(policy.apply("source", "survey", &mut stats), None);
+        assert_eq!(policy.apply("note:longer", "x", &mut stats), None);
+        assert_eq!(stats, TagPolicyStats { filtered_tags: 1, rejected_keys: 1, ..Default::default() });
+        assert!(stats.has_cleanups());
+    }
+
+    #[test]
+    fn control_characters_are_escaped_or_stripped() {
+        let mut stats = TagPolicyStats::default();
+        let (_, value) = policy(ControlCharacters::Escape, ImportTagFilter::KeepAll).apply("note", "a\nb\u{1b}", &mut stats).unwrap();
+        // Escaping made the value longer than the limit, it is cut off before the escape that
+        // would not fit rather than within it
+        assert_eq!(value, "a\\nb\u{2026}");
+        assert_eq!(stats, TagPolicyStats { normalized_tags: 1, truncated_values: 1, ..Default::default() });
+
+        let mut stats = TagPolicyStats::default();
+        let (key, value) = policy(ControlCharacters::Strip, ImportTagFilter::KeepAll).apply("no\0te", "a\nb\tc\u{1b}d", &mut stats).unwrap();
+        assert_eq!((key.as_ref(), value.as_ref()), ("note", "a b cd"));
+        assert_eq!(stats, TagPolicyStats { normalized_tags: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn the_key_limit_applies_to_the_escaped_key() {
+        let mut stats = TagPolicyStats::default();
+        let policy = policy(ControlCharacters::Escape, ImportTagFilter::KeepAll);
+        // Six characters as given, eight once the line breaks are escaped
+        assert_eq!(policy.apply("no\n\nte", "x", &mut stats), None);
+        assert_eq!(stats, TagPolicyStats { rejected_keys: 1, ..Default::default() });
+
+        let (key, _) = policy.apply("no\nte", "x", &mut stats).unwrap();
+        assert_eq!(key, "no\\nte");
+    }
+
+    #[test]
+    fn long_values_are_cut_off_with_an_ellipsis() {
+        let mut stats = TagPolicyStats::default();
+        let (_, value) = policy(ControlCharacters::Escape, ImportTagFilter::KeepAll).apply("note", "ærøskøbing", &mut stats).unwrap();
+        assert_eq!(value.chars().count(), 8);
+        assert_eq!(value, "ærøskøb\u{2026}");
+        assert_eq!(stats.truncated_values, 1);
+    }
+
+    #[tokio::test]
+    async fn the_variable_limit_of_the_linked_sqlite_sizes_the_batches() {
+        let pool = crate::test_support::memory_pool("variable_limit").await;
+        let config = InsertConfig::detect(&pool).await.unwrap();
+        // The SQLite bundled with sqlx is newer than 3.32.0
+        assert!(config.max_variable_number >= crate::database::DEFAULT_MAX_VARIABLE_NUMBER, "{}", config.max_variable_number);
+        assert_eq!(config.batch_size(9), config.max_variable_number / 9);
+        // Rows with few fields are held to the upper bound on rows
+        assert_eq!(config.batch_size(3), config.max_rows_per_batch);
+
+        let legacy = InsertConfig::default();
+        assert_eq!(legacy.batch_size(9), 111);
+        // A row with more fields than the limit still goes into statements of its own
+        assert_eq!(legacy.batch_size(1000), 1);
+    }
+
+    #[tokio::test]
+    async fn rows_are_split_over_as_many_statements_as_the_limit_needs() {
+        let nodes = crate::test_support::synthetic_nodes(1000);
+        let whole = crate::test_support::memory_pool("batches_whole").await;
+        insert_node_data(&whole, nodes.clone(), None, &InsertConfig::detect(&whole).await.unwrap()).await.unwrap();
+
+        // Two nodes per statement
+        let split = crate::test_support::memory_pool("batches_split").await;
+        let tiny = InsertConfig { max_variable_number: 20, ..Default::default() };
+        insert_node_data(&split, nodes, None, &tiny).await.unwrap();
+
+        for query in ["SELECT COUNT(*) FROM node", "SELECT COUNT(*) FROM node_tags"] {
+            let expected: i64 = sqlx::query_scalar(query).fetch_one(&whole).await.unwrap();
+            let count: i64 = sqlx::query_scalar(query).fetch_one(&split).await.unwrap();
+            assert_eq!(count, expected, "{}", query);
+        }
+        let nodes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM node").fetch_one(&split).await.unwrap();
+        assert_eq!(nodes, 1000);
+    }
+
+    #[tokio::test]
+    async fn updates_wait_for_another_connection_holding_the_write_lock() {
+        // A shared database in memory makes the other connections wait for its lock rather than
+        // fail, so a file is used, opened again by connections that do not wait at all
+        let path = std::env::temp_dir().join(format!("gmc_retry_updates_{}.db", std::process::id()));
+        let setup = crate::database::connect_pool(&format!("sqlite://{}?mode=rwc", path.display())).await.unwrap();
+        crate::database::create_tables(&setup).await.unwrap();
+        let nodes = crate::test_support::synthetic_nodes(20);
+        insert_node_data(&setup, nodes.clone(), None, &InsertConfig::default()).await.unwrap();
+        setup.close().await;
+        let pool = SqlitePool::connect_with(sqlx::sqlite::SqliteConnectOptions::new().filename(&path).busy_timeout(Duration::ZERO)).await.unwrap();
+        let updated: Vec<Node> = nodes.into_iter().map(|node| Node { version: 2, ..node }).collect();
+
+        // Another connection takes the write lock and holds it until it commits
+        let mut locker = pool.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *locker).await.unwrap();
+
+        let impatient = InsertConfig { retry_policy: RetryPolicy { max_retries: 0, ..Default::default() }, ..Default::default() };
+        let error = update_node_data(&pool, updated.clone(), None, &impatient).await.unwrap_err();
+        assert!(matches!(error, InsertError::RetriesExhausted { attempts: 1, .. }), "{}", error);
+
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            sqlx::query("COMMIT").execute(&mut *locker).await.unwrap();
+        });
+        let patient = InsertConfig {
+            retry_policy: RetryPolicy { max_retries: 20, initial_backoff: Duration::from_millis(10), max_backoff: Duration::from_millis(40) },
+            ..Default::default()
+        };
+        update_node_data(&pool, updated, None, &patient).await.unwrap();
+        release.await.unwrap();
+
+        let versions: Vec<i64> = sqlx::query_scalar("SELECT DISTINCT version FROM node").fetch_all(&pool).await.unwrap();
+        assert_eq!(versions, [2]);
+        let tags: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM node_tags").fetch_one(&pool).await.unwrap();
+        assert_eq!(tags, 4);
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_new_tag_text_gets_a_dictionary_row_and_a_repeat_reuses_it() {
+        let pool = crate::test_support::memory_pool("intern_tag_texts").await;
+        let config = InsertConfig::detect(&pool).await.unwrap();
+
+        let first = intern_tag_texts(&pool, "tag_key", ["highway", "name", "highway"], &config).await.unwrap();
+        assert_eq!(first.len(), 2);
+        let second = intern_tag_texts(&pool, "tag_key", ["shop", "highway"], &config).await.unwrap();
+        assert_eq!(second["highway"], first["highway"]);
+        assert!(!first.values().any(|&id| id == second["shop"]));
+
+        let rows: Vec<(i64, String)> = sqlx::query_as("SELECT id, text FROM tag_key ORDER BY text").fetch_all(&pool).await.unwrap();
+        assert_eq!(rows, [("highway", first["highway"]), ("name", first["name"]), ("shop", second["shop"])].map(|(text, id)| (id, text.to_string())));
+        // The values have a dictionary of their own
+        let values: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tag_value").fetch_one(&pool).await.unwrap();
+        assert_eq!(values, 0);
+    }
+
+    #[tokio::test]
+    async fn imports_share_the_texts_of_their_tags() {
+        let pool = crate::test_support::memory_pool("intern_imports").await;
+        let xml = |id: i64, name: &str| format!(r#"<osm version="0.6">
+ <node id="{id}" lat="55.0" lon="12.0" version="1"><tag k="amenity" v="cafe"/><tag k="name" v="{name}"/></node>
+</osm>"#);
+        crate::test_support::import_osm_xml(&pool, "first", &xml(1, "Harbour")).await;
+        crate::test_support::import_osm_xml(&pool, "second", &xml(2, "Station")).await;
+
+        let keys: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tag_key").fetch_one(&pool).await.unwrap();
+        let values: Vec<String> = sqlx::query_scalar("SELECT text FROM tag_value ORDER BY text").fetch_all(&pool).await.unwrap();
+        assert_eq!(keys, 2);
+        assert_eq!(values, ["Harbour", "Station", "cafe"]);
+        let tags: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM node_tags").fetch_one(&pool).await.unwrap();
+        assert_eq!(tags, 4);
+    }
+
+    #[tokio::test]
+    async fn equal_members_of_different_relations_are_all_stored() {
+        // Both relations hold the same members, and relation 21 holds the way twice, as a
+        // route running there and back does. A key made from the member alone collides here
+        let pool = crate::test_support::memory_pool("member_keys").await;
+        crate::test_support::import_osm_xml(&pool, "member_keys", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"/>
+ <node id="2" lat="55.1" lon="12.1" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/></way>
+ <relation id="20" version="1"><member type="way" ref="10" role=""/><member type="node" ref="1" role="stop"/></relation>
+ <relation id="21" version="1">
+  <member type="way" ref="10" role=""/><member type="node" ref="1" role="stop"/><member type="way" ref="10" role=""/>
+ </relation>
+</osm>"#).await;
+
+        let members: Vec<(i64, i64, i64, String)> = sqlx::query_as("SELECT relation_id, seq, ref_id, member_type FROM member ORDER BY relation_id, seq")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let expected = [(20, 0, 10, "way"), (20, 1, 1, "node"), (21, 0, 10, "way"), (21, 1, 1, "node"), (21, 2, 10, "way")];
+        assert_eq!(members, expected.map(|(relation_id, seq, ref_id, member_type)| (relation_id, seq, ref_id, member_type.to_string())));
+    }
+}
@@ -0,0 +1,66 @@
+use sqlx::{Row, SqlitePool};
+
+use crate::coastline::{LandWaterGrid, Surface};
+
+/// Replaces the stored land and water grid, see `LandWaterGrid`.
+///
+/// ## Arguments
+/// * `grid` - The grid of the data, or `None` to store none, e.g. once its coastlines are deleted.
+pub async fn save_land_water_grid(sqlite_pool: &SqlitePool, grid: Option<&LandWaterGrid>) -> Result<(), sqlx::Error> {
+    let mut tx = sqlite_pool.begin().await?;
+
+    sqlx::query("DELETE FROM land_water_cell").execute(&mut *tx).await?;
+    for (&(row, column), &surface) in grid.map(|grid| &grid.cells).into_iter().flatten() {
+        sqlx::query("INSERT INTO land_water_cell (row, col, water) VALUES (?, ?, ?)")
+            .bind(row)
+            .bind(column)
+            .bind(surface == Surface::Water)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await
+}
+
+/// Fetches the stored land and water grid.
+///
+/// ## Returns
+/// * The grid, or `None` if none is stored.
+pub async fn fetch_land_water_grid(sqlite_pool: &SqlitePool) -> Result<Option<LandWaterGrid>, sqlx::Error> {
+    let rows = sqlx::query("SELECT row, col, water FROM land_water_cell")
+        .fetch_all(sqlite_pool)
+        .await?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+
+    let mut grid = LandWaterGrid::default();
+    for row in rows {
+        let surface = if row.try_get::<bool, _>("water")? { Surface::Water } else { Surface::Land };
+        grid.cells.insert((row.try_get("row")?, row.try_get("col")?), surface);
+    }
+    Ok(Some(grid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::memory_pool;
+
+    #[tokio::test]
+    async fn the_grid_round_trips_and_is_replaced() {
+        let pool = memory_pool("land_water_round_trip").await;
+        assert_eq!(fetch_land_water_grid(&pool).await.unwrap(), None);
+
+        let grid = LandWaterGrid { cells: [((-3, 7), Surface::Land), ((-3, 8), Surface::Water), ((12, 0), Surface::Water)].into() };
+        save_land_water_grid(&pool, Some(&grid)).await.unwrap();
+        assert_eq!(fetch_land_water_grid(&pool).await.unwrap(), Some(grid));
+
+        let smaller = LandWaterGrid { cells: [((0, 0), Surface::Land)].into() };
+        save_land_water_grid(&pool, Some(&smaller)).await.unwrap();
+        assert_eq!(fetch_land_water_grid(&pool).await.unwrap(), Some(smaller));
+
+        save_land_water_grid(&pool, None).await.unwrap();
+        assert_eq!(fetch_land_water_grid(&pool).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,323 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use sqlx::{Connection, SqlitePool};
+use tracing::{info, warn};
+
+use super::is_lock_contention;
+
+/// How many problems of `PRAGMA integrity_check` are reported, it stops looking after as many.
+pub const MAX_INTEGRITY_PROBLEMS: u32 = 100;
+/// How long `maintain_database` waits for a lock. Long enough to ride out the locks SQLite
+/// holds for a moment on its own, e.g. while another connection checkpoints the log, and
+/// short enough that a database in use is refused at once.
+pub const MAINTENANCE_BUSY_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Options for `maintain_database`.
+///
+/// # Fields
+/// * `vacuum` - Whether the file is rewritten to give the space of deleted rows back, which
+///   takes as long as copying the database and needs it to be otherwise unused.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceOptions {
+    pub vacuum: bool,
+}
+
+/// What `maintain_database` did and how long it took.
+///
+/// # Fields
+/// * `steps` - Every step run, with how long it took, in the order run.
+/// * `size_before` - The size of the database file and its write-ahead log in bytes before,
+///   or `None` for a database in memory.
+/// * `size_after` - The same size after.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    pub steps: Vec<(&'static str, Duration)>,
+    pub size_before: Option<u64>,
+    pub size_after: Option<u64>,
+}
+
+impl fmt::Display for MaintenanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (step, duration) in &self.steps {
+            writeln!(f, "{} took {:.1} ms", step, duration.as_secs_f64() * 1000.0)?;
+        }
+        match (self.size_before, self.size_after) {
+            (Some(before), Some(after)) if after < before => writeln!(f, "{} before, {} after, {} freed", format_size(before), format_size(after), format_size(before - after)),
+            (Some(before), Some(after)) => writeln!(f, "{} before, {} after", format_size(before), format_size(after)),
+            _ => writeln!(f, "The database is held in memory and has no file size"),
+        }
+    }
+}
+
+/// Formats a number of bytes with the largest unit it has at least one of, e.g. `1.5 MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// An error of `maintain_database`.
+#[derive(Debug)]
+pub enum MaintenanceError {
+    Sqlx(sqlx::Error),
+    /// `PRAGMA integrity_check` found the database damaged, with what it reported. Nothing
+    /// else is done to a damaged database.
+    Integrity(Vec<String>),
+    /// Another connection, e.g. of the map open on the same file, holds a transaction, so the
+    /// database could not be analyzed or vacuumed.
+    Busy(sqlx::Error),
+}
+
+impl From<sqlx::Error> for MaintenanceError {
+    fn from(err: sqlx::Error) -> Self {
+        MaintenanceError::Sqlx(err)
+    }
+}
+
+impl fmt::Display for MaintenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaintenanceError::Sqlx(e) => write!(f, "Database error: {}", e),
+            MaintenanceError::Integrity(problems) => write!(f, "The integrity check found {} problems: {}", problems.len(), problems.join("; ")),
+            MaintenanceError::Busy(e) => write!(f, "Can not maintain the database while another connection uses it, close it and try again: {}", e),
+        }
+    }
+}
+
+impl StdError for MaintenanceError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            MaintenanceError::Sqlx(e) | MaintenanceError::Busy(e) => Some(e),
+            MaintenanceError::Integrity(_) => None,
+        }
+    }
+}
+
+/// The size of the database file and its write-ahead log in bytes, or `None` for a database
+/// in memory.
+pub async fn database_file_size(sqlite_pool: &SqlitePool) -> Result<Option<u64>, sqlx::Error> {
+    let file: String = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+        .fetch_one(sqlite_pool)
+        .await?;
+    if file.is_empty() {
+        return Ok(None);
+    }
+
+    // A log that is not there holds nothing
+    let size = |path: &str| std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    Ok(Some(size(&file) + size(&format!("{}-wal", file))))
+}
+
+/// How many bytes of the database file are unused, e.g. by rows deleted since, which `VACUUM`
+/// would give back to the file system.
+pub async fn unused_file_space(sqlite_pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    let free_pages: i64 = sqlx::query_scalar("PRAGMA freelist_count").fetch_one(sqlite_pool).await?;
+    let page_size: i64 = sqlx::query_scalar("PRAGMA page_size").fetch_one(sqlite_pool).await?;
+    Ok((free_pages * page_size) as u64)
+}
+
+/// Checks the database for damage and refreshes the statistics the query planner picks
+/// indexes by, e.g. after a large import, delete or migration, and optionally vacuums it.
+///
+/// `VACUUM` rewrites the whole file and can not run while another connection holds a
+/// transaction. It and `ANALYZE` run on a connection of their own, taken out of the pool and
+/// closed after, that hardly waits on locks, see `MAINTENANCE_BUSY_TIMEOUT`: a database in use
+/// by anyone else, e.g. the map open on the same file, fails with `MaintenanceError::Busy` at
+/// once instead of blocking until the busy timeout. The write-ahead log is checkpointed after vacuuming, so the file
+/// shrinks right away.
+///
+/// ## Arguments
+/// * `pool` - The database to maintain.
+/// * `options` - Whether to vacuum.
+///
+/// ## Returns
+/// * The steps run with their times, and the size of the file before and after.
+pub async fn maintain_database(sqlite_pool: &SqlitePool, options: &MaintenanceOptions) -> Result<MaintenanceReport, MaintenanceError> {
+    let mut report = MaintenanceReport {
+        size_before: database_file_size(sqlite_pool).await?,
+        ..Default::default()
+    };
+
+    let started = Instant::now();
+    let problems: Vec<String> = sqlx::query_scalar(&format!("PRAGMA integrity_check({})", MAX_INTEGRITY_PROBLEMS))
+        .fetch_all(sqlite_pool)
+        .await?;
+    if problems != ["ok"] {
+        warn!(problems = problems.len(), "the integrity check failed");
+        return Err(MaintenanceError::Integrity(problems));
+    }
+    report.steps.push(("integrity_check", started.elapsed()));
+
+    // The statistics are written to the database too, so analyzing waits on locks like vacuuming
+    let mut connection = sqlite_pool.acquire().await?.detach();
+    sqlx::query(&format!("PRAGMA busy_timeout = {}", MAINTENANCE_BUSY_TIMEOUT.as_millis())).execute(&mut connection).await?;
+    let without_waiting = |error: sqlx::Error| if is_lock_contention(&error) { MaintenanceError::Busy(error) } else { error.into() };
+
+    let started = Instant::now();
+    sqlx::query("ANALYZE").execute(&mut connection).await.map_err(without_waiting)?;
+    report.steps.push(("analyze", started.elapsed()));
+
+    if options.vacuum {
+        let started = Instant::now();
+        sqlx::query("VACUUM").execute(&mut connection).await.map_err(without_waiting)?;
+        // A reader still on the log keeps it from being truncated, which only delays the shrinking
+        let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+            .fetch_one(&mut connection)
+            .await?;
+        if busy != 0 {
+            warn!("the write-ahead log is in use and was not truncated");
+        }
+        report.steps.push(("vacuum", started.elapsed()));
+    }
+    connection.close().await?;
+
+    report.size_after = database_file_size(sqlite_pool).await?;
+    info!(steps = ?report.steps, size_before = ?report.size_before, size_after = ?report.size_after, "maintained the database");
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{connect_pool, create_tables, delete_by_source};
+    use crate::test_support::{import_osm_xml, memory_pool, synthetic_nodes};
+
+    // A database file of its own per test, removed with its log when done
+    struct TempDatabase {
+        path: std::path::PathBuf,
+        url: String,
+    }
+
+    impl TempDatabase {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("gmc_{}_{}.db", name, std::process::id()));
+            let url = format!("sqlite://{}?mode=rwc", path.display());
+            TempDatabase { path, url }
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.path.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    // An import large enough that deleting it leaves pages to give back
+    fn many_nodes_osm() -> String {
+        let nodes: String = synthetic_nodes(3000).iter()
+            .map(|node| format!(r#"<node id="{}" lat="{}" lon="{}" version="1"><tag k="name" v="Node number {}"/></node>"#, node.id, node.lat, node.lon, node.id))
+            .collect();
+        format!(r#"<osm version="0.6">{}</osm>"#, nodes)
+    }
+
+    #[tokio::test]
+    async fn a_healthy_database_is_checked_analyzed_and_vacuumed() {
+        let database = TempDatabase::new("maintain_vacuum");
+        let pool = connect_pool(&database.url).await.unwrap();
+        create_tables(&pool).await.unwrap();
+        let stats = import_osm_xml(&pool, "maintain_vacuum", &many_nodes_osm()).await;
+        delete_by_source(&pool, stats.source_id).await.unwrap();
+        assert!(unused_file_space(&pool).await.unwrap() > 0);
+
+        let report = maintain_database(&pool, &MaintenanceOptions { vacuum: true }).await.unwrap();
+        assert_eq!(report.steps.iter().map(|(step, _)| *step).collect::<Vec<_>>(), ["integrity_check", "analyze", "vacuum"]);
+        let (before, after) = (report.size_before.unwrap(), report.size_after.unwrap());
+        assert!(after < before, "{} before, {} after", before, after);
+        assert_eq!(report.size_after, database_file_size(&pool).await.unwrap());
+        assert_eq!(unused_file_space(&pool).await.unwrap(), 0);
+        assert!(report.to_string().ends_with(&format!("{} before, {} after, {} freed\n", format_size(before), format_size(after), format_size(before - after))), "{}", report);
+
+        // The planner has statistics now
+        let analyzed: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_stat1").fetch_one(&pool).await.unwrap();
+        assert!(analyzed > 0);
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn a_database_in_memory_is_maintained_without_a_size() {
+        let pool = memory_pool("maintain_memory").await;
+        let report = maintain_database(&pool, &MaintenanceOptions::default()).await.unwrap();
+        assert_eq!(report.steps.iter().map(|(step, _)| *step).collect::<Vec<_>>(), ["integrity_check", "analyze"]);
+        assert_eq!((report.size_before, report.size_after), (None, None));
+        assert!(report.to_string().ends_with("The database is held in memory and has no file size\n"), "{}", report);
+    }
+
+    #[tokio::test]
+    async fn a_database_in_use_is_refused_at_once() {
+        let database = TempDatabase::new("maintain_busy");
+        let pool = connect_pool(&database.url).await.unwrap();
+        create_tables(&pool).await.unwrap();
+
+        // Another program, e.g. the map, writing to the same file
+        let other = connect_pool(&database.url).await.unwrap();
+        let mut writer = other.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut *writer).await.unwrap();
+
+        let started = Instant::now();
+        let error = maintain_database(&pool, &MaintenanceOptions { vacuum: true }).await.unwrap_err();
+        assert!(matches!(error, MaintenanceError::Busy(_)), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(2), "waited {:?}", started.elapsed());
+        assert!(error.to_string().starts_with("Can not maintain the database while another connection uses it"), "{}", error);
+
+        sqlx::query("ROLLBACK").execute(&mut *writer).await.unwrap();
+        drop(writer);
+        other.close().await;
+        assert!(maintain_database(&pool, &MaintenanceOptions { vacuum: true }).await.is_ok());
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn a_damaged_database_fails_the_integrity_check() {
+        let database = TempDatabase::new("maintain_damaged");
+        let pool = connect_pool(&database.url).await.unwrap();
+        // Two indexes whose pages are swapped, so each holds the entries of the other
+        sqlx::raw_sql("
+            CREATE TABLE pair (a INTEGER, b INTEGER);
+            CREATE INDEX pair_a ON pair (a);
+            CREATE INDEX pair_b ON pair (b);
+            INSERT INTO pair VALUES (1, 30), (2, 20), (3, 10);
+            CREATE TEMP TABLE roots AS SELECT name, rootpage FROM sqlite_master WHERE name IN ('pair_a', 'pair_b');
+            PRAGMA writable_schema = ON;
+            UPDATE sqlite_master SET rootpage = (SELECT rootpage FROM roots WHERE roots.name <> sqlite_master.name)
+                WHERE name IN ('pair_a', 'pair_b');
+            PRAGMA writable_schema = OFF;
+        ").execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let pool = connect_pool(&database.url).await.unwrap();
+        let error = maintain_database(&pool, &MaintenanceOptions::default()).await.unwrap_err();
+        let MaintenanceError::Integrity(problems) = &error else {
+            panic!("{}", error);
+        };
+        assert!(!problems.is_empty() && problems.iter().any(|problem| problem.contains("pair_")), "{:?}", problems);
+        assert!(error.to_string().starts_with(&format!("The integrity check found {} problems: ", problems.len())));
+        // Nothing else was done to it
+        let analyzed: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'sqlite_stat1')").fetch_one(&pool).await.unwrap();
+        assert!(!analyzed);
+        pool.close().await;
+    }
+
+    #[test]
+    fn sizes_are_written_in_the_largest_unit() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1000), "1.0 KB");
+        assert_eq!(format_size(679_900), "679.9 KB");
+        assert_eq!(format_size(1_500_000), "1.5 MB");
+        assert_eq!(format_size(2_000_000_000_000_000), "2000.0 TB");
+    }
+}
@@ -0,0 +1,325 @@
+use std::fmt;
+
+use sqlx::{Row, SqlitePool};
+
+use crate::geo::BBox;
+use crate::style::parse_hex_color;
+
+/// The color of markers added without one, as `#rrggbb`.
+pub const DEFAULT_MARKER_COLOR: &str = "#e63946";
+
+/// A pin dropped on the map, kept between runs.
+///
+/// # Fields
+/// * `id` - Assigned by the database, see `insert_marker`.
+/// * `lat`, `lon` - Where the marker is, in degrees.
+/// * `label` - What the marker is shown and listed as.
+/// * `color` - The color it is drawn in, as `#rrggbb`.
+/// * `created_at` - When the marker was added, in UTC as `YYYY-MM-DDTHH:MM:SSZ`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub id: i64,
+    pub lat: f64,
+    pub lon: f64,
+    pub label: String,
+    pub color: String,
+    pub created_at: String,
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} at {:.6},{:.6} in {} (added {})", self.id, self.label, self.lat, self.lon, self.color, self.created_at)
+    }
+}
+
+/// A marker read from a CSV file, not stored yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewMarker {
+    pub lat: f64,
+    pub lon: f64,
+    pub label: String,
+    pub color: String,
+}
+
+/// A line of a CSV file that `parse_marker_csv` skipped.
+///
+/// # Fields
+/// * `line` - The number of the line, counted from 1.
+/// * `reason` - What is wrong with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedRow {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// The outcome of `import_marker_csv`.
+#[derive(Debug, Clone, Default)]
+pub struct MarkerImport {
+    pub added: usize,
+    pub skipped: Vec<SkippedRow>,
+}
+
+impl fmt::Display for MarkerImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Added {} markers, skipped {} rows", self.added, self.skipped.len())?;
+        for row in &self.skipped {
+            writeln!(f, "  {}", row)?;
+        }
+        Ok(())
+    }
+}
+
+/// The label of a marker added without one, its position.
+pub fn default_marker_label(lat: f64, lon: f64) -> String {
+    format!("{:.5}, {:.5}", lat, lon)
+}
+
+/// Checks the position and color of a marker before it is stored.
+///
+/// ## Returns
+/// * What is wrong with the marker, if anything.
+pub fn validate_marker(lat: f64, lon: f64, color: &str) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(format!("latitude {} is not within -90 and 90", lat));
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(format!("longitude {} is not within -180 and 180", lon));
+    }
+    if parse_hex_color(color).is_none() {
+        return Err(format!("'{}' is not a color, expected #rrggbb", color));
+    }
+    Ok(())
+}
+
+/// Parses one line of a marker CSV file.
+fn parse_marker_row(row: &str) -> Result<NewMarker, String> {
+    let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+    let (lat, lon, label, color) = match fields[..] {
+        [lat, lon] => (lat, lon, None, DEFAULT_MARKER_COLOR),
+        [lat, lon, label] => (lat, lon, Some(label), DEFAULT_MARKER_COLOR),
+        [lat, lon, label, color] => (lat, lon, Some(label), color),
+        _ => return Err(format!("expected 2 to 4 fields, found {}", fields.len())),
+    };
+
+    let lat: f64 = lat.parse().map_err(|_| format!("'{}' is not a latitude", lat))?;
+    let lon: f64 = lon.parse().map_err(|_| format!("'{}' is not a longitude", lon))?;
+    validate_marker(lat, lon, color)?;
+
+    let label = match label {
+        Some(label) if !label.is_empty() => label.to_string(),
+        _ => default_marker_label(lat, lon),
+    };
+    Ok(NewMarker { lat, lon, label, color: color.to_string() })
+}
+
+/// Parses a CSV file of markers, one `lat,lon[,label[,color]]` per line.
+///
+/// Labels can not contain commas, as the fields are not quoted. Empty lines, lines starting
+/// with `#` and a first line starting with `lat` as a header are ignored.
+///
+/// ## Returns
+/// * The markers of the valid lines and the lines skipped, in the order of the file.
+pub fn parse_marker_csv(text: &str) -> (Vec<NewMarker>, Vec<SkippedRow>) {
+    let mut markers = Vec::new();
+    let mut skipped = Vec::new();
+
+    for (index, row) in text.lines().enumerate() {
+        let row = row.trim();
+        let is_header = index == 0 && row.to_ascii_lowercase().starts_with("lat");
+        if row.is_empty() || row.starts_with('#') || is_header {
+            continue;
+        }
+
+        match parse_marker_row(row) {
+            Ok(marker) => markers.push(marker),
+            Err(reason) => skipped.push(SkippedRow { line: index + 1, reason }),
+        }
+    }
+
+    (markers, skipped)
+}
+
+/// Adds a marker, checked with `validate_marker` before.
+///
+/// ## Arguments
+/// * `color` - The color to draw it in, as `#rrggbb`.
+///
+/// ## Returns
+/// * The id of the marker.
+pub async fn insert_marker(sqlite_pool: &SqlitePool, lat: f64, lon: f64, label: &str, color: &str) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query("INSERT INTO marker (lat, lon, label, color) VALUES (?, ?, ?, ?)")
+        .bind(lat)
+        .bind(lon)
+        .bind(label)
+        .bind(color)
+        .execute(sqlite_pool)
+        .await?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Adds the valid rows of a CSV file of markers in one transaction, see `parse_marker_csv`.
+pub async fn import_marker_csv(sqlite_pool: &SqlitePool, text: &str) -> Result<MarkerImport, sqlx::Error> {
+    let (markers, skipped) = parse_marker_csv(text);
+
+    let mut tx = sqlite_pool.begin().await?;
+    for marker in &markers {
+        sqlx::query("INSERT INTO marker (lat, lon, label, color) VALUES (?, ?, ?, ?)")
+            .bind(marker.lat)
+            .bind(marker.lon)
+            .bind(&marker.label)
+            .bind(&marker.color)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    Ok(MarkerImport { added: markers.len(), skipped })
+}
+
+fn marker_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Marker, sqlx::Error> {
+    Ok(Marker {
+        id: row.try_get("id")?,
+        lat: row.try_get("lat")?,
+        lon: row.try_get("lon")?,
+        label: row.try_get("label")?,
+        color: row.try_get("color")?,
+        created_at: row.try_get("created_at")?,
+    })
+}
+
+/// Fetches every marker, oldest first.
+pub async fn fetch_markers(sqlite_pool: &SqlitePool) -> Result<Vec<Marker>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, lat, lon, label, color, created_at FROM marker ORDER BY id")
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    rows.iter().map(marker_from_row).collect()
+}
+
+/// Fetches the markers inside the box, oldest first.
+pub async fn fetch_markers_in_bbox(sqlite_pool: &SqlitePool, bbox: &BBox) -> Result<Vec<Marker>, sqlx::Error> {
+    let rows = sqlx::query("
+        SELECT id, lat, lon, label, color, created_at FROM marker
+        WHERE lat BETWEEN ? AND ? AND lon BETWEEN ? AND ?
+        ORDER BY id
+    ")
+        .bind(bbox.min_lat)
+        .bind(bbox.max_lat)
+        .bind(bbox.min_lon)
+        .bind(bbox.max_lon)
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    rows.iter().map(marker_from_row).collect()
+}
+
+/// Deletes a marker.
+///
+/// ## Returns
+/// * Whether there was a marker with the id.
+pub async fn delete_marker(sqlite_pool: &SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM marker WHERE id = ?")
+        .bind(id)
+        .execute(sqlite_pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::memory_pool;
+
+    #[tokio::test]
+    async fn markers_read_back_after_a_restart() {
+        // A file rather than memory, so the markers have to survive the pool being closed
+        let path = std::env::temp_dir().join(format!("gmc_markers_{}.db", std::process::id()));
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let pool = crate::database::connect_pool(&url).await.unwrap();
+        crate::database::create_tables(&pool).await.unwrap();
+        let harbour = insert_marker(&pool, 55.6761234, 12.5683371, "Harbour", "#00ff00").await.unwrap();
+        let station = insert_marker(&pool, 55.672, 12.564, &default_marker_label(55.672, 12.564), DEFAULT_MARKER_COLOR).await.unwrap();
+        pool.close().await;
+
+        let pool = crate::database::connect_pool(&url).await.unwrap();
+        crate::database::create_tables(&pool).await.unwrap();
+        let markers = fetch_markers(&pool).await.unwrap();
+        let read_back: Vec<(i64, f64, f64, &str, &str)> = markers.iter()
+            .map(|marker| (marker.id, marker.lat, marker.lon, marker.label.as_str(), marker.color.as_str()))
+            .collect();
+        assert_eq!(read_back, [
+            (harbour, 55.6761234, 12.5683371, "Harbour", "#00ff00"),
+            (station, 55.672, 12.564, "55.67200, 12.56400", DEFAULT_MARKER_COLOR),
+        ]);
+        // Stamped by the database when added, as YYYY-MM-DDTHH:MM:SSZ
+        assert!(markers.iter().all(|marker| marker.created_at.len() == 20 && marker.created_at.ends_with('Z')), "{:?}", markers);
+
+        assert!(delete_marker(&pool, harbour).await.unwrap());
+        assert!(!delete_marker(&pool, harbour).await.unwrap());
+        assert_eq!(fetch_markers(&pool).await.unwrap().iter().map(|marker| marker.id).collect::<Vec<_>>(), [station]);
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_markers_in_the_box_are_fetched() {
+        let pool = memory_pool("markers_in_bbox").await;
+        let inside = insert_marker(&pool, 55.5, 12.5, "inside", DEFAULT_MARKER_COLOR).await.unwrap();
+        let on_the_edge = insert_marker(&pool, 56.0, 12.0, "on the edge", DEFAULT_MARKER_COLOR).await.unwrap();
+        insert_marker(&pool, 57.0, 12.5, "north", DEFAULT_MARKER_COLOR).await.unwrap();
+        insert_marker(&pool, 55.5, 11.0, "west", DEFAULT_MARKER_COLOR).await.unwrap();
+
+        let bbox = BBox { min_lat: 55.0, max_lat: 56.0, min_lon: 12.0, max_lon: 13.0 };
+        let found = fetch_markers_in_bbox(&pool, &bbox).await.unwrap();
+        assert_eq!(found.iter().map(|marker| marker.id).collect::<Vec<_>>(), [inside, on_the_edge]);
+    }
+
+    #[tokio::test]
+    async fn malformed_csv_rows_are_skipped_and_counted() {
+        let csv = "lat,lon,label,color
+55.5,12.5,Harbour,#00ff00
+
+# a comment
+55.6, 12.6 ,Station
+55.7,12.7
+not a number,12.0,Broken
+95.0,12.0,Too far north
+55.0,200.0,Too far east
+55.0,12.0,Odd color,green
+55.0
+55.0,12.0,a,#000000,extra
+55.8,12.8,,#0000ff
+";
+        let (markers, skipped) = parse_marker_csv(csv);
+        let parsed: Vec<(f64, f64, &str, &str)> = markers.iter().map(|marker| (marker.lat, marker.lon, marker.label.as_str(), marker.color.as_str())).collect();
+        assert_eq!(parsed, [
+            (55.5, 12.5, "Harbour", "#00ff00"),
+            (55.6, 12.6, "Station", DEFAULT_MARKER_COLOR),
+            (55.7, 12.7, "55.70000, 12.70000", DEFAULT_MARKER_COLOR),
+            (55.8, 12.8, "55.80000, 12.80000", "#0000ff"),
+        ]);
+        assert_eq!(skipped, [
+            SkippedRow { line: 7, reason: "'not a number' is not a latitude".to_string() },
+            SkippedRow { line: 8, reason: "latitude 95 is not within -90 and 90".to_string() },
+            SkippedRow { line: 9, reason: "longitude 200 is not within -180 and 180".to_string() },
+            SkippedRow { line: 10, reason: "'green' is not a color, expected #rrggbb".to_string() },
+            SkippedRow { line: 11, reason: "expected 2 to 4 fields, found 1".to_string() },
+            SkippedRow { line: 12, reason: "expected 2 to 4 fields, found 5".to_string() },
+        ]);
+
+        let pool = memory_pool("marker_csv").await;
+        let import = import_marker_csv(&pool, csv).await.unwrap();
+        assert_eq!((import.added, import.skipped.len()), (4, 6));
+        assert!(import.to_string().starts_with("Added 4 markers, skipped 6 rows\n  line 7: "), "{}", import);
+        assert_eq!(fetch_markers(&pool).await.unwrap().len(), 4);
+    }
+}
@@ -1,7 +1,27 @@
 pub mod tables;
 pub mod fetchers;
 pub mod inserters;
+pub mod connection;
+pub mod validate;
+pub mod dedupe;
+pub mod changes;
+pub mod sources;
+pub mod markers;
+pub mod attribution;
+pub mod maintenance;
+pub mod land_water;
+pub mod summary;
 
 pub use tables::*;
 pub use fetchers::*;
 pub use inserters::*;
+pub use connection::*;
+pub use validate::*;
+pub use dedupe::*;
+pub use changes::*;
+pub use sources::*;
+pub use markers::*;
+pub use attribution::*;
+pub use maintenance::*;
+pub use land_water::*;
+pub use summary::*;
@@ -0,0 +1,429 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::Serialize;
+use sqlx::{Row, SqlitePool};
+
+use crate::utils::MapsType;
+
+use super::ELEMENT_TABLES;
+
+/// A file or download whose elements were imported.
+///
+/// # Fields
+/// * `id` - The id the imported elements refer to in their `source_id` column.
+/// * `filename` - The path of the imported file, or a description of the download.
+/// * `imported_at` - When the import started, in UTC as `YYYY-MM-DDTHH:MM:SSZ`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceFile {
+    pub id: i64,
+    pub filename: String,
+    pub imported_at: String,
+}
+
+impl fmt::Display for SourceFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} (imported {})", self.id, self.filename, self.imported_at)
+    }
+}
+
+/// What an imported file held, to tell whether it changed since.
+///
+/// # Fields
+/// * `size` - The length of the file in bytes.
+/// * `modified_at` - When the file was last modified, in seconds since the Unix epoch.
+/// * `checksum` - The SHA-256 of the contents of the file, in lowercase hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileFingerprint {
+    pub size: i64,
+    pub modified_at: i64,
+    pub checksum: String,
+}
+
+/// How far an import got, recorded in `source_file` once every phase is committed so an
+/// import cut short, e.g. by a crash, is resumed instead of taken for done. The phases are
+/// in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImportPhase {
+    /// The import is recorded, nothing of it is known to be stored.
+    Started,
+    NodesInserted,
+    /// The ways are stored, their bounding boxes included.
+    WaysInserted,
+    RelationsInserted,
+    /// The snapshot and the land and water grid show the import, it is done.
+    Finalized,
+}
+
+impl ImportPhase {
+    /// The name stored in the `import_phase` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImportPhase::Started => "started",
+            ImportPhase::NodesInserted => "nodes_inserted",
+            ImportPhase::WaysInserted => "ways_inserted",
+            ImportPhase::RelationsInserted => "relations_inserted",
+            ImportPhase::Finalized => "finalized",
+        }
+    }
+
+    /// Reads the name stored in the `import_phase` column.
+    pub fn parse(name: &str) -> Option<ImportPhase> {
+        [ImportPhase::Started, ImportPhase::NodesInserted, ImportPhase::WaysInserted, ImportPhase::RelationsInserted, ImportPhase::Finalized]
+            .into_iter()
+            .find(|phase| phase.as_str() == name)
+    }
+}
+
+impl fmt::Display for ImportPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ImportPhase::Started => "the start",
+            ImportPhase::NodesInserted => "the nodes",
+            ImportPhase::WaysInserted => "the ways",
+            ImportPhase::RelationsInserted => "the relations",
+            ImportPhase::Finalized => "the end",
+        })
+    }
+}
+
+/// How to import a file, given what is recorded of earlier imports of the same contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStart {
+    /// Import the file as a new import.
+    Fresh,
+    /// Leave the file alone, the import of the same contents with this id is done.
+    Skip { source_id: i64 },
+    /// Go on with the import of the same contents with this id, after the phases it completed.
+    Resume { source_id: i64, completed: ImportPhase },
+}
+
+/// Decides how to import a file, see `ImportStart`.
+///
+/// ## Arguments
+/// * `earlier` - The latest import with the same size and checksum, as its id and the phase it
+///   recorded, or `None` if the contents were never imported. The phase is `None` for an
+///   import from before phases were recorded, which is taken to be done.
+/// * `force` - Whether the file is imported even if the same contents were, see `ImportOptions::force`.
+pub fn decide_import_start(earlier: Option<(i64, Option<ImportPhase>)>, force: bool) -> ImportStart {
+    match earlier {
+        _ if force => ImportStart::Fresh,
+        None => ImportStart::Fresh,
+        Some((source_id, None | Some(ImportPhase::Finalized))) => ImportStart::Skip { source_id },
+        Some((source_id, Some(completed))) => ImportStart::Resume { source_id, completed },
+    }
+}
+
+/// How many elements `delete_by_source` deleted.
+///
+/// # Fields
+/// * `kept` - Elements kept because another import still refers to them, now part of that import.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeletedSource {
+    pub nodes: u64,
+    pub ways: u64,
+    pub relations: u64,
+    pub kept: u64,
+}
+
+impl fmt::Display for DeletedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} nodes, {} ways and {} relations, {} kept because other imports refer to them", self.nodes, self.ways, self.relations, self.kept)
+    }
+}
+
+// The sources of the ways and relations of other imports referring to an element, as
+// `(table, query)`. `?1` is the import being deleted.
+const REFERRING_SOURCES: [(&str, &str); 3] = [
+    ("relation", "
+        SELECT r.source_id FROM member m JOIN relation r ON r.id = m.relation_id
+        WHERE m.ref_id = relation.id AND m.member_type = 'relation' AND r.source_id IS NOT ?1
+    "),
+    ("way", "
+        SELECT r.source_id FROM member m JOIN relation r ON r.id = m.relation_id
+        WHERE m.ref_id = way.id AND m.member_type = 'way' AND r.source_id IS NOT ?1
+    "),
+    ("node", "
+        SELECT w.source_id FROM way_nodes wn JOIN way w ON w.id = wn.way_id
+        WHERE wn.ref_id = node.id AND w.source_id IS NOT ?1
+        UNION ALL
+        SELECT r.source_id FROM member m JOIN relation r ON r.id = m.relation_id
+        WHERE m.ref_id = node.id AND m.member_type = 'node' AND r.source_id IS NOT ?1
+    "),
+];
+
+/// Records the start of an import, at `ImportPhase::Started`.
+///
+/// ## Arguments
+/// * `filename` - The path of the imported file, or a description of the download.
+/// * `fingerprint` - What the imported file held, `None` for a download.
+///
+/// ## Returns
+/// * The id to store the imported elements with.
+pub async fn insert_source_file(sqlite_pool: &SqlitePool, filename: &str, fingerprint: Option<&FileFingerprint>) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query("INSERT INTO source_file (filename, size, modified_at, checksum, import_phase) VALUES (?, ?, ?, ?, ?)")
+        .bind(filename)
+        .bind(fingerprint.map(|fingerprint| fingerprint.size))
+        .bind(fingerprint.map(|fingerprint| fingerprint.modified_at))
+        .bind(fingerprint.map(|fingerprint| fingerprint.checksum.as_str()))
+        .bind(ImportPhase::Started.as_str())
+        .execute(sqlite_pool)
+        .await?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Looks up the latest import of a file with the same contents. The contents decide, not
+/// the name or modification time, so a file copied or touched since is found as well.
+///
+/// ## Returns
+/// * The import, or `None` if no file with the same size and checksum was imported or its
+///   import was deleted since.
+pub async fn find_identical_import(sqlite_pool: &SqlitePool, fingerprint: &FileFingerprint) -> Result<Option<SourceFile>, sqlx::Error> {
+    let row = sqlx::query("SELECT id, filename, imported_at FROM source_file WHERE size = ? AND checksum = ? ORDER BY id DESC LIMIT 1")
+        .bind(fingerprint.size)
+        .bind(&fingerprint.checksum)
+        .fetch_optional(sqlite_pool)
+        .await?;
+
+    row.map(|row| Ok(SourceFile { id: row.try_get("id")?, filename: row.try_get("filename")?, imported_at: row.try_get("imported_at")? }))
+        .transpose()
+}
+
+/// Records that an import completed a phase, see `ImportPhase`.
+pub async fn save_import_phase(sqlite_pool: &SqlitePool, source_id: i64, phase: ImportPhase) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE source_file SET import_phase = ? WHERE id = ?")
+        .bind(phase.as_str())
+        .bind(source_id)
+        .execute(sqlite_pool)
+        .await?;
+    Ok(())
+}
+
+/// Looks up the phase an import completed last.
+///
+/// ## Returns
+/// * The phase, or `None` for an import recorded before phases were, or one that is not recorded.
+pub async fn fetch_import_phase(sqlite_pool: &SqlitePool, source_id: i64) -> Result<Option<ImportPhase>, sqlx::Error> {
+    let name: Option<String> = sqlx::query_scalar("SELECT import_phase FROM source_file WHERE id = ?")
+        .bind(source_id)
+        .fetch_optional(sqlite_pool)
+        .await?
+        .flatten();
+    Ok(name.as_deref().and_then(ImportPhase::parse))
+}
+
+/// Fetches the ids of the elements of one type stored by an import.
+///
+/// ## Arguments
+/// * `table` - The table of the elements, `node`, `way` or `relation`.
+pub async fn fetch_element_ids_of_source(sqlite_pool: &SqlitePool, table: &str, source_id: i64) -> Result<HashSet<i64>, sqlx::Error> {
+    let ids: Vec<i64> = sqlx::query_scalar(&format!("SELECT id FROM {} WHERE source_id = ?", table))
+        .bind(source_id)
+        .fetch_all(sqlite_pool)
+        .await?;
+    Ok(ids.into_iter().collect())
+}
+
+/// Fetches every recorded import, oldest first.
+pub async fn fetch_source_files(sqlite_pool: &SqlitePool) -> Result<Vec<SourceFile>, sqlx::Error> {
+    let rows = sqlx::query("SELECT id, filename, imported_at FROM source_file ORDER BY id")
+        .fetch_all(sqlite_pool)
+        .await?;
+
+    rows.iter()
+        .map(|row| Ok(SourceFile { id: row.try_get("id")?, filename: row.try_get("filename")?, imported_at: row.try_get("imported_at")? }))
+        .collect()
+}
+
+/// Looks up which file an element was imported from.
+///
+/// ## Returns
+/// * The file name, or `None` if the element is not stored or was stored before imports
+///   recorded their source, or by a diff.
+pub async fn fetch_source_filename(sqlite_pool: &SqlitePool, maps_type: &MapsType, id: i64) -> Result<Option<String>, sqlx::Error> {
+    let table = match maps_type {
+        MapsType::Node | MapsType::Way | MapsType::Relation => maps_type.as_str(),
+        MapsType::Other(_) => return Ok(None),
+    };
+    let query = format!("SELECT s.filename FROM {} e JOIN source_file s ON s.id = e.source_id WHERE e.id = ?", table);
+    sqlx::query_scalar(&query)
+        .bind(id)
+        .fetch_optional(sqlite_pool)
+        .await
+}
+
+/// Deletes the elements of one import, in a single transaction, together with their tags,
+/// node references, members and bounding boxes. The tag keys and values no other tag uses
+/// any more are deleted as well, and so is the record of the import.
+///
+/// An element that several imports contained belongs to the one that stored it first. If a
+/// way or relation of another import still refers to it, it is kept and handed over to that
+/// import instead, as deleting it would break the element referring to it.
+///
+/// ## Arguments
+/// * `source_id` - The id of the import, see `fetch_source_files`.
+///
+/// ## Returns
+/// * How many elements were deleted.
+pub async fn delete_by_source(sqlite_pool: &SqlitePool, source_id: i64) -> Result<DeletedSource, sqlx::Error> {
+    let mut tx = sqlite_pool.begin().await?;
+    let mut kept = 0;
+
+    // Relations first, so the ways and nodes a handed over relation refers to are kept as well
+    for (table, referring_sources) in REFERRING_SOURCES {
+        kept += sqlx::query(&format!("
+            UPDATE {table} SET source_id = ({referring_sources} ORDER BY 1 LIMIT 1)
+            WHERE source_id = ?1 AND EXISTS ({referring_sources})
+        "))
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    }
+
+    // The dependent rows first, while the elements they hang off can still be selected by source
+    let dependents = [
+        "DELETE FROM member WHERE relation_id IN (SELECT id FROM relation WHERE source_id = ?)",
+        "DELETE FROM relation_tags WHERE relation_id IN (SELECT id FROM relation WHERE source_id = ?)",
+        "DELETE FROM way_geom WHERE way_id IN (SELECT id FROM way WHERE source_id = ?)",
+        "DELETE FROM way_nodes WHERE way_id IN (SELECT id FROM way WHERE source_id = ?)",
+        "DELETE FROM way_tags WHERE way_id IN (SELECT id FROM way WHERE source_id = ?)",
+        "DELETE FROM node_tags WHERE node_id IN (SELECT id FROM node WHERE source_id = ?)",
+    ];
+    for query in dependents {
+        sqlx::query(query).bind(source_id).execute(&mut *tx).await?;
+    }
+
+    let mut deleted = [0; ELEMENT_TABLES.len()];
+    for (table, deleted) in ELEMENT_TABLES.iter().zip(&mut deleted) {
+        *deleted = sqlx::query(&format!("DELETE FROM {} WHERE source_id = ?", table))
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    }
+    let [nodes, ways, relations] = deleted;
+
+    sqlx::query("
+        DELETE FROM tag_key WHERE id NOT IN (
+            SELECT key_id FROM node_tags UNION SELECT key_id FROM way_tags UNION SELECT key_id FROM relation_tags
+        )
+    ").execute(&mut *tx).await?;
+    sqlx::query("
+        DELETE FROM tag_value WHERE id NOT IN (
+            SELECT value_id FROM node_tags UNION SELECT value_id FROM way_tags UNION SELECT value_id FROM relation_tags
+        )
+    ").execute(&mut *tx).await?;
+    sqlx::query("DELETE FROM source_file WHERE id = ?").bind(source_id).execute(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(DeletedSource { nodes, ways, relations, kept })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    const PHASES: [ImportPhase; 5] = [ImportPhase::Started, ImportPhase::NodesInserted, ImportPhase::WaysInserted, ImportPhase::RelationsInserted, ImportPhase::Finalized];
+
+    #[test]
+    fn contents_never_imported_are_imported_fresh() {
+        assert_eq!(decide_import_start(None, false), ImportStart::Fresh);
+        assert_eq!(decide_import_start(None, true), ImportStart::Fresh);
+    }
+
+    #[test]
+    fn every_phase_short_of_finalized_is_resumed() {
+        for phase in PHASES {
+            let expected = match phase {
+                ImportPhase::Finalized => ImportStart::Skip { source_id: 7 },
+                completed => ImportStart::Resume { source_id: 7, completed },
+            };
+            assert_eq!(decide_import_start(Some((7, Some(phase))), false), expected, "after {:?}", phase);
+        }
+    }
+
+    #[test]
+    fn an_import_from_before_phases_were_recorded_is_done() {
+        assert_eq!(decide_import_start(Some((3, None)), false), ImportStart::Skip { source_id: 3 });
+    }
+
+    #[test]
+    fn force_imports_fresh_whatever_the_phase() {
+        assert_eq!(decide_import_start(Some((3, None)), true), ImportStart::Fresh);
+        for phase in PHASES {
+            assert_eq!(decide_import_start(Some((3, Some(phase))), true), ImportStart::Fresh, "after {:?}", phase);
+        }
+    }
+
+    // The first import holds a street and a cafe, the second a street sharing node 2 with it
+    const FIRST_OSM: &str = r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="11.0" version="1"><tag k="amenity" v="cafe"/></node>
+ <node id="2" lat="55.1" lon="11.1" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/><tag k="name" v="First Street"/></way>
+ <relation id="20" version="1"><member type="way" ref="10" role=""/><tag k="type" v="route"/></relation>
+</osm>"#;
+    const SECOND_OSM: &str = r#"<osm version="0.6">
+ <node id="2" lat="55.1" lon="11.1" version="1"/>
+ <node id="3" lat="55.2" lon="11.2" version="1"/>
+ <way id="11" version="1"><nd ref="2"/><nd ref="3"/><tag k="highway" v="residential"/><tag k="name" v="Second Street"/></way>
+</osm>"#;
+
+    async fn ids(pool: &SqlitePool, table: &str) -> Vec<(i64, Option<i64>)> {
+        sqlx::query_as(&format!("SELECT id, source_id FROM {} ORDER BY id", table)).fetch_all(pool).await.unwrap()
+    }
+
+    async fn texts(pool: &SqlitePool, table: &str) -> Vec<String> {
+        sqlx::query_scalar(&format!("SELECT text FROM {} ORDER BY text", table)).fetch_all(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn deleting_an_import_leaves_only_what_the_others_hold() {
+        let pool = memory_pool("delete_by_source").await;
+        let first = import_osm_xml(&pool, "delete_by_source_first", FIRST_OSM).await.source_id;
+        let second = import_osm_xml(&pool, "delete_by_source_second", SECOND_OSM).await.source_id;
+        assert_ne!(first, second);
+        // Node 2 was stored by the first import
+        assert_eq!(ids(&pool, "node").await, [(1, Some(first)), (2, Some(first)), (3, Some(second))]);
+
+        let deleted = delete_by_source(&pool, first).await.unwrap();
+        assert_eq!(deleted, DeletedSource { nodes: 1, ways: 1, relations: 1, kept: 1 });
+
+        // Node 2 is handed over to the import whose way still refers to it
+        assert_eq!(ids(&pool, "node").await, [(2, Some(second)), (3, Some(second))]);
+        assert_eq!(ids(&pool, "way").await, [(11, Some(second))]);
+        assert!(ids(&pool, "relation").await.is_empty());
+        let sources: Vec<i64> = sqlx::query_scalar("SELECT id FROM source_file").fetch_all(&pool).await.unwrap();
+        assert_eq!(sources, [second]);
+
+        // No tag, node reference, member or box is left behind, nor a text only the first used
+        for query in [
+            "SELECT COUNT(*) FROM node_tags WHERE node_id NOT IN (SELECT id FROM node)",
+            "SELECT COUNT(*) FROM way_tags WHERE way_id NOT IN (SELECT id FROM way)",
+            "SELECT COUNT(*) FROM relation_tags WHERE relation_id NOT IN (SELECT id FROM relation)",
+            "SELECT COUNT(*) FROM way_nodes WHERE way_id NOT IN (SELECT id FROM way)",
+            "SELECT COUNT(*) FROM way_geom WHERE way_id NOT IN (SELECT id FROM way)",
+            "SELECT COUNT(*) FROM member",
+        ] {
+            let count: i64 = sqlx::query_scalar(query).fetch_one(&pool).await.unwrap();
+            assert_eq!(count, 0, "{}", query);
+        }
+        assert_eq!(texts(&pool, "tag_key").await, ["highway", "name"]);
+        assert_eq!(texts(&pool, "tag_value").await, ["Second Street", "residential"]);
+
+        // Deleting the last import empties the database
+        let deleted = delete_by_source(&pool, second).await.unwrap();
+        assert_eq!(deleted, DeletedSource { nodes: 2, ways: 1, relations: 0, kept: 0 });
+        assert!(texts(&pool, "tag_key").await.is_empty());
+    }
+
+    #[test]
+    fn phases_are_stored_by_name_in_order() {
+        for phase in PHASES {
+            assert_eq!(ImportPhase::parse(phase.as_str()), Some(phase));
+        }
+        assert_eq!(ImportPhase::parse("halfway"), None);
+        assert!(PHASES.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
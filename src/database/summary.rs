@@ -0,0 +1,139 @@
+use std::fmt;
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::geo::{format_distance, BBox};
+
+use super::{count_rows, database_file_size, fetch_data_extent, fetch_source_files, format_size, SourceFile};
+
+/// What a database holds, from counts and settings that take no scan of the element tables.
+///
+/// # Fields
+/// * `node_tags` - The tags of the nodes, one per node and key.
+/// * `tag_keys` - The distinct keys of the tags of every element.
+/// * `data_extent` - The box around every imported node, or `None` if there are none.
+/// * `imports` - How many imports are recorded, see `fetch_source_files`.
+/// * `last_import` - The latest of them, or `None` if there are none.
+/// * `file_size` - The size of the database file and its write-ahead log in bytes, or `None`
+///   for a database in memory.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DatabaseSummary {
+    pub nodes: i64,
+    pub ways: i64,
+    pub relations: i64,
+    pub node_tags: i64,
+    pub way_tags: i64,
+    pub relation_tags: i64,
+    pub tag_keys: i64,
+    pub data_extent: Option<BBox>,
+    pub imports: usize,
+    pub last_import: Option<SourceFile>,
+    pub file_size: Option<u64>,
+}
+
+impl DatabaseSummary {
+    /// Gathers the summary of a database whose tables were created.
+    pub async fn gather(sqlite_pool: &SqlitePool) -> Result<DatabaseSummary, sqlx::Error> {
+        let mut imports = fetch_source_files(sqlite_pool).await?;
+        Ok(DatabaseSummary {
+            nodes: count_rows(sqlite_pool, "node").await?,
+            ways: count_rows(sqlite_pool, "way").await?,
+            relations: count_rows(sqlite_pool, "relation").await?,
+            node_tags: count_rows(sqlite_pool, "node_tags").await?,
+            way_tags: count_rows(sqlite_pool, "way_tags").await?,
+            relation_tags: count_rows(sqlite_pool, "relation_tags").await?,
+            tag_keys: count_rows(sqlite_pool, "tag_key").await?,
+            data_extent: fetch_data_extent(sqlite_pool).await?,
+            imports: imports.len(),
+            last_import: imports.pop(),
+            file_size: database_file_size(sqlite_pool).await?,
+        })
+    }
+}
+
+impl fmt::Display for DatabaseSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} nodes, {} ways and {} relations", self.nodes, self.ways, self.relations)?;
+        writeln!(f, "{} node tags, {} way tags and {} relation tags with {} keys", self.node_tags, self.way_tags, self.relation_tags, self.tag_keys)?;
+        match self.data_extent {
+            Some(extent) => writeln!(
+                f,
+                "Extent: {:.5},{:.5},{:.5},{:.5} (minLon,minLat,maxLon,maxLat), {} wide and {} high",
+                extent.min_lon, extent.min_lat, extent.max_lon, extent.max_lat, format_distance(extent.width_m()), format_distance(extent.height_m()),
+            )?,
+            None => writeln!(f, "Extent: none, nothing is imported")?,
+        }
+        match &self.last_import {
+            Some(source) => writeln!(f, "{} imports, the last {}", self.imports, source)?,
+            None => writeln!(f, "No imports recorded")?,
+        }
+        match self.file_size {
+            Some(size) => writeln!(f, "Size: {}", format_size(size)),
+            None => writeln!(f, "Size: held in memory"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{connect_pool, create_tables};
+    use crate::fetcher::{process_map_file, ImportOptions};
+    use crate::test_support::memory_pool;
+
+    const VEJRO_PATH: &str = "utils/mapdata/vejrø";
+
+    #[tokio::test]
+    async fn the_summary_of_an_import_matches_the_census_of_its_file() {
+        let path = std::env::temp_dir().join(format!("gmc_summary_{}.db", std::process::id()));
+        let pool = connect_pool(&format!("sqlite://{}?mode=rwc", path.display())).await.unwrap();
+        create_tables(&pool).await.unwrap();
+        process_map_file(&pool, VEJRO_PATH, &ImportOptions::default()).await.unwrap();
+
+        let summary = DatabaseSummary::gather(&pool).await.unwrap();
+        // Counted in the file: every element, and every tag and key on them
+        assert_eq!((summary.nodes, summary.ways, summary.relations), (1749, 135, 6));
+        assert_eq!((summary.node_tags, summary.way_tags, summary.relation_tags), (302, 159, 64));
+        assert_eq!(summary.tag_keys, 183);
+
+        // Around the northernmost, southernmost, easternmost and westernmost nodes
+        let extent = summary.data_extent.unwrap();
+        assert!((extent.min_lat - 55.0230429).abs() < 1e-7 && (extent.max_lat - 55.0398329).abs() < 1e-7, "{:?}", extent);
+        assert!((extent.min_lon - 11.3428688).abs() < 1e-7 && (extent.max_lon - 11.3766257).abs() < 1e-7, "{:?}", extent);
+
+        assert_eq!(summary.imports, 1);
+        assert!(summary.last_import.as_ref().unwrap().filename.ends_with("vejrø"), "{:?}", summary.last_import);
+        assert_eq!(summary.file_size, database_file_size(&pool).await.unwrap());
+        assert!(summary.file_size.unwrap() > 0);
+
+        let text = summary.to_string();
+        assert!(text.starts_with("1749 nodes, 135 ways and 6 relations\n302 node tags, 159 way tags and 64 relation tags with 183 keys\n"), "{}", text);
+        assert!(text.contains("\n1 imports, the last ") && text.contains(&format!("Size: {}\n", format_size(summary.file_size.unwrap()))), "{}", text);
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["nodes"], 1749);
+        assert_eq!(json["last_import"]["filename"], summary.last_import.as_ref().unwrap().filename.as_str());
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn an_empty_database_in_memory_is_summarized_as_such() {
+        let pool = memory_pool("summary_empty").await;
+        let summary = DatabaseSummary::gather(&pool).await.unwrap();
+
+        assert_eq!(summary, DatabaseSummary {
+            nodes: 0, ways: 0, relations: 0,
+            node_tags: 0, way_tags: 0, relation_tags: 0, tag_keys: 0,
+            data_extent: None, imports: 0, last_import: None, file_size: None,
+        });
+        assert_eq!(summary.to_string(), "0 nodes, 0 ways and 0 relations\n\
+            0 node tags, 0 way tags and 0 relation tags with 0 keys\n\
+            Extent: none, nothing is imported\n\
+            No imports recorded\n\
+            Size: held in memory\n");
+    }
+}
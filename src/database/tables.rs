@@ -1,18 +1,389 @@
+use sqlx::sqlite::SqliteQueryResult;
 use sqlx::SqlitePool;
+use tracing::{debug, error, info};
 
+/// The tag tables as `(table, id column, table of the tagged elements)`.
+const TAG_TABLES: [(&str, &str, &str); 3] = [
+    ("node_tags", "node_id", "node"),
+    ("way_tags", "way_id", "way"),
+    ("relation_tags", "relation_id", "relation"),
+];
+
+/// The tables of the elements, which record the file they were imported from in `source_id`.
+pub const ELEMENT_TABLES: [&str; 3] = ["node", "way", "relation"];
+
+/// Every table `create_tables` creates.
+pub const SCHEMA_TABLES: [&str; 19] = [
+    "node", "way", "source_file", "way_nodes", "relation", "member", "tag_key", "tag_value",
+    "node_tags", "way_tags", "relation_tags", "gps_track", "gps_track_point", "way_geom",
+    "import_duplicate", "import_duplicate_tag", "settings", "marker", "land_water_cell",
+];
+
+/// Logs the outcome of creating one table, index or trigger. Creating them is idempotent,
+/// so successes only show at the debug level.
+fn log_create_result(name: &str, result: Result<SqliteQueryResult, sqlx::Error>) {
+    match result {
+        Ok(_) => debug!(name, "created"),
+        Err(error) => error!(name, %error, "could not create"),
+    }
+}
+
+/// The statement creating a tag table, whose keys and values refer to `tag_key` and `tag_value`.
+fn tag_table_sql(table: &str, id_column: &str, element_table: &str) -> String {
+    format!("
+    CREATE TABLE IF NOT EXISTS {table} (
+        {id_column} BIGINT NOT NULL,
+        key_id INTEGER NOT NULL,
+        value_id INTEGER NOT NULL,
+        FOREIGN KEY ({id_column}) REFERENCES {element_table}(id),
+        FOREIGN KEY (key_id) REFERENCES tag_key(id),
+        FOREIGN KEY (value_id) REFERENCES tag_value(id),
+        PRIMARY KEY ({id_column}, key_id)
+    );")
+}
+
+/// Converts the tag tables of a database created before tag keys and values were interned,
+/// which hold the texts in `[key]` and `value` columns, to refer to `tag_key` and `tag_value`.
+///
+/// Every table is rebuilt under its old name in one transaction, so a failed migration leaves
+/// the database as it was. Tables that are already converted or do not exist yet are left alone.
+async fn migrate_tag_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    for (table, id_column, element_table) in TAG_TABLES {
+        let has_text_columns: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = 'key')")
+            .bind(table)
+            .fetch_one(&mut *tx)
+            .await?;
+        if !has_text_columns {
+            continue;
+        }
+
+        // Dropping the old table also drops its duplicate trigger, which `create_tables` recreates
+        let interned_table = format!("{}_interned", table);
+        let migration = format!("
+            INSERT OR IGNORE INTO tag_key (text) SELECT DISTINCT [key] FROM {table};
+            INSERT OR IGNORE INTO tag_value (text) SELECT DISTINCT value FROM {table};
+            {create_interned_table}
+            INSERT INTO {interned_table} ({id_column}, key_id, value_id)
+                SELECT t.{id_column}, k.id, v.id
+                FROM {table} t
+                JOIN tag_key k ON k.text = t.[key]
+                JOIN tag_value v ON v.text = t.value;
+            DROP TABLE {table};
+            ALTER TABLE {interned_table} RENAME TO {table};
+        ", create_interned_table = tag_table_sql(&interned_table, id_column, element_table));
+
+        sqlx::raw_sql(&migration).execute(&mut *tx).await?;
+        info!(table, "interned tag keys and values");
+    }
+
+    tx.commit().await
+}
+
+/// The statement creating the member table, whose members are identified by their relation
+/// and their position within it. `member_type` tells what `ref_id` refers to.
+fn member_table_sql(table: &str) -> String {
+    format!("
+    CREATE TABLE IF NOT EXISTS {table} (
+        relation_id BIGINT NOT NULL,
+        seq INTEGER NOT NULL,
+        ref_id BIGINT NOT NULL,
+        member_type VARCHAR(50) NOT NULL,
+        role VARCHAR(50) NOT NULL,
+
+        PRIMARY KEY (relation_id, seq),
+        FOREIGN KEY (relation_id) REFERENCES relation(id),
+
+        CONSTRAINT member_type_check CHECK (member_type IN ('node', 'way', 'relation'))
+    );")
+}
+
+/// Converts the member table of a database created before members had a single reference
+/// column, which splits it across `node_id`, `way_id` and `relation_ref_id`, only the one of
+/// its type set.
+///
+/// Databases older still identify their members by a hash of their relation, reference and
+/// role rather than their position. Members of different relations could get the same hash,
+/// and the one imported later was dropped. Their members are numbered in the order they were
+/// imported.
+///
+/// The table is rebuilt under its old name in one transaction. A table already converted or
+/// not created yet is left alone.
+async fn migrate_member_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let has_split_columns: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('member') WHERE name = 'node_id')")
+        .fetch_one(&mut *tx)
+        .await?;
+    if !has_split_columns {
+        return Ok(());
+    }
+
+    let has_hash_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('member') WHERE name = 'id')")
+        .fetch_one(&mut *tx)
+        .await?;
+    let seq = if has_hash_column { "ROW_NUMBER() OVER (PARTITION BY relation_id ORDER BY rowid) - 1" } else { "seq" };
+
+    // The primary key of the new table covers the lookups by relation the old index was for
+    let migration = format!("
+        {create_unified_table}
+        INSERT INTO member_unified (relation_id, seq, ref_id, member_type, role)
+            SELECT
+                relation_id, {seq},
+                CASE member_type WHEN 'node' THEN node_id WHEN 'way' THEN way_id ELSE relation_ref_id END,
+                member_type, role
+            FROM member;
+        DROP TABLE member;
+        ALTER TABLE member_unified RENAME TO member;
+    ", create_unified_table = member_table_sql("member_unified"));
+
+    sqlx::raw_sql(&migration).execute(&mut *tx).await?;
+    info!(numbered = has_hash_column, "merged the member reference columns");
+
+    tx.commit().await
+}
+
+/// The statement creating the table of the node references of the ways, identified by their
+/// way and their position within it. A closed way refers to its first node again last.
+fn way_nodes_table_sql(table: &str) -> String {
+    format!("
+    CREATE TABLE IF NOT EXISTS {table} (
+        way_id BIGINT NOT NULL,
+        seq INTEGER NOT NULL,
+        ref_id BIGINT NOT NULL,
+        FOREIGN KEY (way_id) REFERENCES way(id),
+        FOREIGN KEY (ref_id) REFERENCES node(id),
+        PRIMARY KEY (way_id, seq)
+    );")
+}
+
+/// Converts the `way_nodes` table of a database created before the node references had a
+/// position, which is keyed on the way and node instead. The references are numbered in the
+/// order they were imported, the order of the way.
+///
+/// The key kept every node of a way once, so the closed ways stored so far do not end at their
+/// first node again. Importing them again stores them whole.
+///
+/// The table is rebuilt under its old name in one transaction. A table already converted or
+/// not created yet is left alone.
+async fn migrate_way_nodes_table(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let has_table: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'way_nodes')")
+        .fetch_one(&mut *tx)
+        .await?;
+    let has_seq_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('way_nodes') WHERE name = 'seq')")
+        .fetch_one(&mut *tx)
+        .await?;
+    if !has_table || has_seq_column {
+        return Ok(());
+    }
+
+    let migration = format!("
+        {create_ordered_table}
+        INSERT INTO way_nodes_ordered (way_id, seq, ref_id)
+            SELECT way_id, ROW_NUMBER() OVER (PARTITION BY way_id ORDER BY rowid) - 1, ref_id
+            FROM way_nodes;
+        DROP TABLE way_nodes;
+        ALTER TABLE way_nodes_ordered RENAME TO way_nodes;
+    ", create_ordered_table = way_nodes_table_sql("way_nodes_ordered"));
+
+    sqlx::raw_sql(&migration).execute(&mut *tx).await?;
+    info!("numbered the node references of the ways");
+
+    tx.commit().await
+}
+
+/// Converts the node table of a database created before coordinates were stored as integers,
+/// which holds them as floats in `lat` and `lon`, to `lat_e7` and `lon_e7`, see `COORDINATE_SCALE`.
+///
+/// The columns are replaced in place rather than the table rebuilt, as the node references
+/// and tags refer to the table. The space freed is reused by later imports, `VACUUM` gives it
+/// back to the file system. A table already converted or not created yet is left alone.
+async fn migrate_node_coordinates(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let has_float_columns: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('node') WHERE name = 'lat')")
+        .fetch_one(&mut *tx)
+        .await?;
+    if !has_float_columns {
+        return Ok(());
+    }
+
+    // A column a trigger refers to can not be dropped, `create_tables` recreates the duplicate
+    // trigger for the new columns. Added columns need a default, though every insert sets them
+    let migration = "
+        DROP TRIGGER IF EXISTS node_duplicate;
+        ALTER TABLE node ADD COLUMN lat_e7 INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE node ADD COLUMN lon_e7 INTEGER NOT NULL DEFAULT 0;
+        UPDATE node SET lat_e7 = ROUND(lat * 1e7), lon_e7 = ROUND(lon * 1e7);
+        ALTER TABLE node DROP COLUMN lat;
+        ALTER TABLE node DROP COLUMN lon;
+    ";
+
+    sqlx::raw_sql(migration).execute(&mut *tx).await?;
+    info!("stored the node coordinates as integers");
+
+    tx.commit().await
+}
+
+/// Adds the `source_id` column to the element tables of a database created before imports
+/// recorded their source file. The elements stored so far keep no source.
+async fn migrate_source_columns(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    for table in ELEMENT_TABLES {
+        let has_source_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = 'source_id')")
+            .bind(table)
+            .fetch_one(pool)
+            .await?;
+        if has_source_column {
+            continue;
+        }
+
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN source_id INTEGER NULL REFERENCES source_file(id)", table))
+            .execute(pool)
+            .await?;
+        info!(table, "added the source column");
+    }
+
+    Ok(())
+}
+
+// The columns recording what an imported file held, as `(column, type)`
+const FINGERPRINT_COLUMNS: [(&str, &str); 3] = [("size", "BIGINT"), ("modified_at", "BIGINT"), ("checksum", "VARCHAR(64)")];
+
+/// Adds the fingerprint columns to the `source_file` table of a database created before
+/// imports recorded them. The files imported so far have no fingerprint, so they are
+/// imported again when asked to.
+async fn migrate_source_file_fingerprints(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    for (column, column_type) in FINGERPRINT_COLUMNS {
+        let has_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('source_file') WHERE name = ?)")
+            .bind(column)
+            .fetch_one(pool)
+            .await?;
+        if has_column {
+            continue;
+        }
+
+        sqlx::query(&format!("ALTER TABLE source_file ADD COLUMN {} {} NULL", column, column_type))
+            .execute(pool)
+            .await?;
+        info!(column, "added the fingerprint column to source_file");
+    }
+
+    Ok(())
+}
+
+/// Adds the `import_phase` column to the `source_file` table of a database created before
+/// imports recorded how far they got. The imports so far are taken to be done.
+async fn migrate_source_file_import_phase(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let has_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('source_file') WHERE name = 'import_phase')")
+        .fetch_one(pool)
+        .await?;
+    if has_column {
+        return Ok(());
+    }
+
+    sqlx::query("ALTER TABLE source_file ADD COLUMN import_phase VARCHAR(20) NULL")
+        .execute(pool)
+        .await?;
+    info!("added the import phase column to source_file");
+    Ok(())
+}
+
+/// Finds what `create_tables` would still have to create or migrate, without changing anything.
+///
+/// ## Returns
+/// * A description of every missing table and every table of an older layout, empty if the
+///   schema is current.
+pub async fn schema_problems(pool: &SqlitePool) -> Result<Vec<String>, sqlx::Error> {
+    let existing: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table'")
+        .fetch_all(pool)
+        .await?;
+    let mut problems: Vec<String> = SCHEMA_TABLES.iter()
+        .filter(|table| !existing.iter().any(|name| name == *table))
+        .map(|table| format!("table {} is missing", table))
+        .collect();
+
+    let member_has_hash_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('member') WHERE name = 'id')")
+        .fetch_one(pool)
+        .await?;
+    let member_has_split_columns: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('member') WHERE name = 'node_id')")
+        .fetch_one(pool)
+        .await?;
+    if member_has_hash_column {
+        problems.push("table member identifies its members by a hash".to_string());
+    } else if member_has_split_columns {
+        problems.push("table member splits its references across a column per type".to_string());
+    }
+
+    let node_has_float_columns: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('node') WHERE name = 'lat')")
+        .fetch_one(pool)
+        .await?;
+    if node_has_float_columns {
+        problems.push("table node stores its coordinates as floats".to_string());
+    }
+
+    if existing.iter().any(|name| name == "way_nodes") {
+        let has_seq_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('way_nodes') WHERE name = 'seq')")
+            .fetch_one(pool)
+            .await?;
+        if !has_seq_column {
+            problems.push("table way_nodes keeps no positions of the node references".to_string());
+        }
+    }
+
+    for (table, _, _) in TAG_TABLES {
+        let has_text_columns: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = 'key')")
+            .bind(table)
+            .fetch_one(pool)
+            .await?;
+        if has_text_columns {
+            problems.push(format!("table {} holds its tags as text", table));
+        }
+    }
+
+    for table in ELEMENT_TABLES.iter().filter(|table| existing.iter().any(|name| name == *table)) {
+        let has_source_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = 'source_id')")
+            .bind(table)
+            .fetch_one(pool)
+            .await?;
+        if !has_source_column {
+            problems.push(format!("table {} has no source column", table));
+        }
+    }
+
+    if existing.iter().any(|name| name == "source_file") {
+        let has_checksum_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('source_file') WHERE name = 'checksum')")
+            .fetch_one(pool)
+            .await?;
+        if !has_checksum_column {
+            problems.push("table source_file records no checksums".to_string());
+        }
+        let has_phase_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('source_file') WHERE name = 'import_phase')")
+            .fetch_one(pool)
+            .await?;
+        if !has_phase_column {
+            problems.push("table source_file records no import phases".to_string());
+        }
+    }
+
+    Ok(problems)
+}
 
 pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    // Create tables if they do not exist
+    // Create tables if they do not exist. The coordinates of nodes are integers, see `COORDINATE_SCALE`
     let create_node_table = "
     CREATE TABLE IF NOT EXISTS node (
         id BIGINT PRIMARY KEY NOT NULL,
-        lat FLOAT NOT NULL,
-        lon FLOAT NOT NULL,
+        lat_e7 INTEGER NOT NULL,
+        lon_e7 INTEGER NOT NULL,
         version INT NOT NULL,
         timestamp VARCHAR(50) NOT NULL,
         changeset BIGINT NOT NULL,
         uid BIGINT NOT NULL,
-        [user] VARCHAR(50) NOT NULL
+        [user] VARCHAR(50) NOT NULL,
+        source_id INTEGER NULL REFERENCES source_file(id)
     );";
 
     let create_way_table = "
@@ -22,16 +393,22 @@ pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
         timestamp VARCHAR(50) NOT NULL,
         changeset BIGINT NOT NULL,
         uid BIGINT NOT NULL,
-        [user] VARCHAR(50) NOT NULL
+        [user] VARCHAR(50) NOT NULL,
+        source_id INTEGER NULL REFERENCES source_file(id)
     );";
 
-    let create_way_nodes_table = "
-    CREATE TABLE IF NOT EXISTS way_nodes (
-        way_id BIGINT NOT NULL,
-        ref_id BIGINT NOT NULL,
-        FOREIGN KEY (way_id) REFERENCES way(id),
-        FOREIGN KEY (ref_id) REFERENCES node(id),
-        PRIMARY KEY (way_id, ref_id)
+    // The files and downloads imported, which the elements refer to by `source_id`. Files
+    // record what they held, see `FileFingerprint`, so importing them again can be skipped,
+    // and every import how far it got, see `ImportPhase`, so one cut short is resumed
+    let create_source_file_table = "
+    CREATE TABLE IF NOT EXISTS source_file (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        filename VARCHAR(255) NOT NULL,
+        imported_at VARCHAR(50) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+        size BIGINT NULL,
+        modified_at BIGINT NULL,
+        checksum VARCHAR(64) NULL,
+        import_phase VARCHAR(20) NULL
     );";
 
     let create_relation_table = "
@@ -41,79 +418,643 @@ pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
         timestamp VARCHAR(50) NOT NULL,
         changeset BIGINT NOT NULL,
         uid BIGINT NOT NULL,
-        [user] VARCHAR(50) NOT NULL
+        [user] VARCHAR(50) NOT NULL,
+        source_id INTEGER NULL REFERENCES source_file(id)
     );";
 
-    let create_member_table = "
-    CREATE TABLE IF NOT EXISTS member (
-        id BIGINT PRIMARY KEY NOT NULL,
-        relation_id BIGINT NOT NULL,
-        node_id BIGINT NULL,
-        way_id BIGINT NULL,
-        relation_ref_id BIGINT NULL,
-        member_type VARCHAR(50) NOT NULL,
-        role VARCHAR(50) NOT NULL,
+    // Tag keys and values repeat a lot ("highway", "residential", "yes"), so every distinct
+    // text is stored once and the tag tables refer to it by id
+    let create_tag_key_table = "
+    CREATE TABLE IF NOT EXISTS tag_key (
+        id INTEGER PRIMARY KEY,
+        text VARCHAR(255) NOT NULL UNIQUE
+    );";
 
-        FOREIGN KEY (relation_id) REFERENCES relation(id),
+    let create_tag_value_table = "
+    CREATE TABLE IF NOT EXISTS tag_value (
+        id INTEGER PRIMARY KEY,
+        text VARCHAR(255) NOT NULL UNIQUE
+    );";
 
-        CONSTRAINT member_type_check CHECK (
-            (member_type = 'node' AND node_id IS NOT NULL AND way_id IS NULL AND relation_ref_id IS NULL) OR
-            (member_type = 'way' AND way_id IS NOT NULL AND node_id IS NULL AND relation_ref_id IS NULL) OR
-            (member_type = 'relation' AND relation_ref_id IS NOT NULL AND node_id IS NULL AND way_id IS NULL)
-        )
+    let create_gps_track_table = "
+    CREATE TABLE IF NOT EXISTS gps_track (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name VARCHAR(255) NULL
     );";
 
-    let create_node_tags_table = "
-    CREATE TABLE IF NOT EXISTS node_tags (
-        node_id BIGINT NOT NULL,
-        [key] VARCHAR(50) NOT NULL,
-        value VARCHAR(50) NOT NULL,
-        FOREIGN KEY (node_id) REFERENCES node(id),
-        PRIMARY KEY (node_id, [key])
+    let create_gps_track_point_table = "
+    CREATE TABLE IF NOT EXISTS gps_track_point (
+        track_id BIGINT NOT NULL,
+        segment INT NOT NULL,
+        seq INT NOT NULL,
+        lat FLOAT NOT NULL,
+        lon FLOAT NOT NULL,
+        elevation FLOAT NULL,
+        time VARCHAR(50) NULL,
+        FOREIGN KEY (track_id) REFERENCES gps_track(id),
+        PRIMARY KEY (track_id, segment, seq)
     );";
 
-    let create_way_tags_table = "
-    CREATE TABLE IF NOT EXISTS way_tags (
-        way_id BIGINT NOT NULL,
-        [key] VARCHAR(50) NOT NULL,
-        value VARCHAR(50) NOT NULL,
-        FOREIGN KEY (way_id) REFERENCES way(id),
-        PRIMARY KEY (way_id, [key])
+    let create_way_geom_table = "
+    CREATE TABLE IF NOT EXISTS way_geom (
+        way_id BIGINT PRIMARY KEY NOT NULL,
+        min_lat FLOAT NOT NULL,
+        min_lon FLOAT NOT NULL,
+        max_lat FLOAT NOT NULL,
+        max_lon FLOAT NOT NULL,
+        FOREIGN KEY (way_id) REFERENCES way(id)
     );";
 
-    let create_relation_tags_table = "
-    CREATE TABLE IF NOT EXISTS relation_tags (
-        relation_id BIGINT NOT NULL,
+    let create_way_geom_index = "
+    CREATE INDEX IF NOT EXISTS way_geom_bbox ON way_geom (min_lat, max_lat, min_lon, max_lon);";
+
+    // Elements and tags that were already present, in another version, when an overlapping
+    // extract was imported. The inserts ignore them, the triggers below keep a copy for
+    // `deduplicate`, and the updates replacing them record them, see `record_replaced_versions`.
+    let create_import_duplicate_table = "
+    CREATE TABLE IF NOT EXISTS import_duplicate (
+        maps_type VARCHAR(50) NOT NULL,
+        id BIGINT NOT NULL,
+        version INT NOT NULL,
+        timestamp VARCHAR(50) NOT NULL,
+        changeset BIGINT NOT NULL,
+        uid BIGINT NOT NULL,
+        [user] VARCHAR(50) NOT NULL,
+        lat FLOAT NULL,
+        lon FLOAT NULL,
+        PRIMARY KEY (maps_type, id, version, timestamp)
+    );";
+
+    let create_import_duplicate_tag_table = "
+    CREATE TABLE IF NOT EXISTS import_duplicate_tag (
+        maps_type VARCHAR(50) NOT NULL,
+        id BIGINT NOT NULL,
         [key] VARCHAR(50) NOT NULL,
         value VARCHAR(50) NOT NULL,
-        FOREIGN KEY (relation_id) REFERENCES relation(id),
-        PRIMARY KEY (relation_id, [key])
+        PRIMARY KEY (maps_type, id, [key], value)
+    );";
+
+    // Viewer state kept between runs, e.g. which layers are shown
+    let create_settings_table = "
+    CREATE TABLE IF NOT EXISTS settings (
+        [key] VARCHAR(50) PRIMARY KEY NOT NULL,
+        value VARCHAR(255) NOT NULL
+    );";
+
+    // Pins dropped on the map, see `markers.rs`
+    let create_marker_table = "
+    CREATE TABLE IF NOT EXISTS marker (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        lat FLOAT NOT NULL,
+        lon FLOAT NOT NULL,
+        label VARCHAR(255) NOT NULL,
+        color VARCHAR(7) NOT NULL,
+        created_at VARCHAR(50) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
     );";
 
-    // Execute the queries to create tables and print results
+    // Whether the ground is land or water in the cells of a coarse grid over the data, built
+    // from the coastlines on import, see `LandWaterGrid`
+    let create_land_water_cell_table = "
+    CREATE TABLE IF NOT EXISTS land_water_cell (
+        row INTEGER NOT NULL,
+        col INTEGER NOT NULL,
+        water BOOLEAN NOT NULL,
+        PRIMARY KEY (row, col)
+    ) WITHOUT ROWID;";
+
+    let create_duplicate_triggers = "
+    CREATE TRIGGER IF NOT EXISTS node_duplicate BEFORE INSERT ON node
+    WHEN EXISTS (SELECT 1 FROM node WHERE id = NEW.id AND (version != NEW.version OR timestamp != NEW.timestamp))
+    BEGIN
+        INSERT OR IGNORE INTO import_duplicate VALUES ('node', NEW.id, NEW.version, NEW.timestamp, NEW.changeset, NEW.uid, NEW.[user], NEW.lat_e7 / 1e7, NEW.lon_e7 / 1e7);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS way_duplicate BEFORE INSERT ON way
+    WHEN EXISTS (SELECT 1 FROM way WHERE id = NEW.id AND (version != NEW.version OR timestamp != NEW.timestamp))
+    BEGIN
+        INSERT OR IGNORE INTO import_duplicate VALUES ('way', NEW.id, NEW.version, NEW.timestamp, NEW.changeset, NEW.uid, NEW.[user], NULL, NULL);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS relation_duplicate BEFORE INSERT ON relation
+    WHEN EXISTS (SELECT 1 FROM relation WHERE id = NEW.id AND (version != NEW.version OR timestamp != NEW.timestamp))
+    BEGIN
+        INSERT OR IGNORE INTO import_duplicate VALUES ('relation', NEW.id, NEW.version, NEW.timestamp, NEW.changeset, NEW.uid, NEW.[user], NULL, NULL);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS node_tag_duplicate BEFORE INSERT ON node_tags
+    WHEN EXISTS (
+        SELECT 1 FROM node_tags t JOIN tag_key k ON k.id = t.key_id
+        WHERE t.node_id = NEW.node_id AND k.text = (SELECT text FROM tag_key WHERE id = NEW.key_id) COLLATE NOCASE
+            AND (t.key_id != NEW.key_id OR t.value_id != NEW.value_id)
+    )
+    BEGIN
+        INSERT OR IGNORE INTO import_duplicate_tag VALUES ('node', NEW.node_id, (SELECT text FROM tag_key WHERE id = NEW.key_id), (SELECT text FROM tag_value WHERE id = NEW.value_id));
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS way_tag_duplicate BEFORE INSERT ON way_tags
+    WHEN EXISTS (
+        SELECT 1 FROM way_tags t JOIN tag_key k ON k.id = t.key_id
+        WHERE t.way_id = NEW.way_id AND k.text = (SELECT text FROM tag_key WHERE id = NEW.key_id) COLLATE NOCASE
+            AND (t.key_id != NEW.key_id OR t.value_id != NEW.value_id)
+    )
+    BEGIN
+        INSERT OR IGNORE INTO import_duplicate_tag VALUES ('way', NEW.way_id, (SELECT text FROM tag_key WHERE id = NEW.key_id), (SELECT text FROM tag_value WHERE id = NEW.value_id));
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS relation_tag_duplicate BEFORE INSERT ON relation_tags
+    WHEN EXISTS (
+        SELECT 1 FROM relation_tags t JOIN tag_key k ON k.id = t.key_id
+        WHERE t.relation_id = NEW.relation_id AND k.text = (SELECT text FROM tag_key WHERE id = NEW.key_id) COLLATE NOCASE
+            AND (t.key_id != NEW.key_id OR t.value_id != NEW.value_id)
+    )
+    BEGIN
+        INSERT OR IGNORE INTO import_duplicate_tag VALUES ('relation', NEW.relation_id, (SELECT text FROM tag_key WHERE id = NEW.key_id), (SELECT text FROM tag_value WHERE id = NEW.value_id));
+    END;";
+
+    // Execute the queries to create tables and log results
     let result = sqlx::query(create_node_table).execute(pool).await;
-    println!("Create node table result: {:?}", result);
+    log_create_result("node", result);
+
+    // Databases from before the coordinates were integers hold them as floats
+    if let Err(error) = migrate_node_coordinates(pool).await {
+        error!(%error, "could not convert the node coordinates");
+    }
+
+    // Searches around a coordinate, e.g. `find_nearest_poi`, look up nodes by their position
+    let result = sqlx::query("CREATE INDEX IF NOT EXISTS node_position ON node (lat_e7, lon_e7);").execute(pool).await;
+    log_create_result("node position index", result);
 
     let result = sqlx::query(create_way_table).execute(pool).await;
-    println!("Create way table result: {:?}", result);
+    log_create_result("way", result);
+
+    // Databases from before the positions keep every node of a way once, in the order of the rowid
+    if let Err(error) = migrate_way_nodes_table(pool).await {
+        error!(%error, "could not number the node references of the ways");
+    }
 
-    let result = sqlx::query(create_way_nodes_table).execute(pool).await;
-    println!("Create way_nodes table result: {:?}", result);
+    let result = sqlx::query(&way_nodes_table_sql("way_nodes")).execute(pool).await;
+    log_create_result("way_nodes", result);
 
     let result = sqlx::query(create_relation_table).execute(pool).await;
-    println!("Create relation table result: {:?}", result);
+    log_create_result("relation", result);
+
+    let result = sqlx::query(create_source_file_table).execute(pool).await;
+    log_create_result("source_file", result);
+
+    // Databases from before the source files have no source column yet
+    if let Err(error) = migrate_source_columns(pool).await {
+        error!(%error, "could not add the source columns");
+    }
+
+    // Databases from before the fingerprints record only the names of the files
+    if let Err(error) = migrate_source_file_fingerprints(pool).await {
+        error!(%error, "could not add the fingerprint columns");
+    }
+
+    // Databases from before the import phases cannot resume an import cut short
+    if let Err(error) = migrate_source_file_import_phase(pool).await {
+        error!(%error, "could not add the import phase column");
+    }
+
+    for table in ELEMENT_TABLES {
+        let result = sqlx::query(&format!("CREATE INDEX IF NOT EXISTS {table}_source ON {table} (source_id);")).execute(pool).await;
+        log_create_result(&format!("{} source index", table), result);
+    }
+
+    // Reviewing the edits of a user or a changeset, see `fetch_entities_by_user`, looks them up
+    for table in ELEMENT_TABLES {
+        let result = sqlx::query(&format!("CREATE INDEX IF NOT EXISTS {table}_user ON {table} ([user]);")).execute(pool).await;
+        log_create_result(&format!("{} user index", table), result);
+
+        let result = sqlx::query(&format!("CREATE INDEX IF NOT EXISTS {table}_changeset ON {table} (changeset);")).execute(pool).await;
+        log_create_result(&format!("{} changeset index", table), result);
+    }
+
+    // Databases from before the members had one reference column have one per type
+    if let Err(error) = migrate_member_table(pool).await {
+        error!(%error, "could not merge the member reference columns");
+    }
+
+    let result = sqlx::query(&member_table_sql("member")).execute(pool).await;
+    log_create_result("member", result);
+
+    // Finding the relations an element is a member of, e.g. before deleting it, looks up the reference
+    let result = sqlx::query("CREATE INDEX IF NOT EXISTS member_ref ON member (ref_id, member_type);").execute(pool).await;
+    log_create_result("member reference index", result);
 
-    let result = sqlx::query(create_member_table).execute(pool).await;
-    println!("Create member table result: {:?}", result);
+    let result = sqlx::query(create_tag_key_table).execute(pool).await;
+    log_create_result("tag_key", result);
 
-    let result = sqlx::query(create_node_tags_table).execute(pool).await;
-    println!("Create node_tags table result: {:?}", result);
+    let result = sqlx::query(create_tag_value_table).execute(pool).await;
+    log_create_result("tag_value", result);
 
-    let result = sqlx::query(create_way_tags_table).execute(pool).await;
-    println!("Create way_tags table result: {:?}", result);
+    // Databases from before the interning hold the texts in the tag tables themselves
+    if let Err(error) = migrate_tag_tables(pool).await {
+        error!(%error, "could not intern the tag tables");
+    }
 
-    let result = sqlx::query(create_relation_tags_table).execute(pool).await;
-    println!("Create relation_tags table result: {:?}", result);
+    for (table, id_column, element_table) in TAG_TABLES {
+        let result = sqlx::query(&tag_table_sql(table, id_column, element_table)).execute(pool).await;
+        log_create_result(table, result);
+    }
+
+    let result = sqlx::query(create_gps_track_table).execute(pool).await;
+    log_create_result("gps_track", result);
+
+    let result = sqlx::query(create_gps_track_point_table).execute(pool).await;
+    log_create_result("gps_track_point", result);
+
+    let result = sqlx::query(create_way_geom_table).execute(pool).await;
+    log_create_result("way_geom", result);
+
+    let result = sqlx::query(create_way_geom_index).execute(pool).await;
+    log_create_result("way_geom index", result);
+
+    let result = sqlx::query(create_import_duplicate_table).execute(pool).await;
+    log_create_result("import_duplicate", result);
+
+    let result = sqlx::query(create_import_duplicate_tag_table).execute(pool).await;
+    log_create_result("import_duplicate_tag", result);
+
+    let result = sqlx::raw_sql(create_duplicate_triggers).execute(pool).await;
+    log_create_result("duplicate triggers", result);
+
+    let result = sqlx::query(create_settings_table).execute(pool).await;
+    log_create_result("settings", result);
+
+    let result = sqlx::query(create_marker_table).execute(pool).await;
+    log_create_result("marker", result);
+
+    // The viewer only fetches the markers in view, see `fetch_markers_in_bbox`
+    let result = sqlx::query("CREATE INDEX IF NOT EXISTS marker_position ON marker (lat, lon);").execute(pool).await;
+    log_create_result("marker position index", result);
+
+    let result = sqlx::query(create_land_water_cell_table).execute(pool).await;
+    log_create_result("land_water_cell", result);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::memory_pool;
+
+    #[tokio::test]
+    async fn schema_tables_lists_every_table_created() {
+        let pool = memory_pool("schema_tables").await;
+        let mut created: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        created.sort();
+        let mut listed: Vec<&str> = SCHEMA_TABLES.to_vec();
+        listed.sort();
+        assert_eq!(created, listed);
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn way_nodes_without_positions_are_numbered_in_the_order_imported() {
+        // The ways and nodes are stored in the current layout, which the references must refer to
+        let pool = memory_pool("migrate_way_nodes").await;
+        sqlx::raw_sql("
+            INSERT INTO node (id, lat_e7, lon_e7, version, timestamp, changeset, uid, [user])
+                VALUES (1, 0, 0, 1, '', 1, 1, ''), (2, 0, 0, 1, '', 1, 1, ''), (10, 0, 0, 1, '', 1, 1, ''),
+                    (20, 0, 0, 1, '', 1, 1, ''), (30, 0, 0, 1, '', 1, 1, '');
+            INSERT INTO way (id, version, timestamp, changeset, uid, [user]) VALUES (5, 1, '', 1, 1, ''), (7, 1, '', 1, 1, '');
+            DROP TABLE way_nodes;
+            CREATE TABLE way_nodes (
+                way_id BIGINT NOT NULL,
+                ref_id BIGINT NOT NULL,
+                PRIMARY KEY (way_id, ref_id)
+            );
+            INSERT INTO way_nodes (way_id, ref_id) VALUES (7, 30), (7, 10), (5, 2), (7, 20), (5, 1);
+        ").execute(&pool).await.unwrap();
+        assert_eq!(schema_problems(&pool).await.unwrap().iter().filter(|problem| problem.contains("way_nodes")).count(), 1);
+
+        create_tables(&pool).await.unwrap();
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as("SELECT way_id, seq, ref_id FROM way_nodes ORDER BY way_id, seq")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, [(5, 0, 2), (5, 1, 1), (7, 0, 30), (7, 1, 10), (7, 2, 20)]);
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+
+        // A closed way refers to its first node again, which the old key could not keep
+        sqlx::query("INSERT INTO way_nodes (way_id, seq, ref_id) VALUES (5, 2, 2)").execute(&pool).await.unwrap();
+        // Converted once, the table is left alone
+        create_tables(&pool).await.unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM way_nodes").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 6);
+    }
+
+    #[tokio::test]
+    async fn elements_stored_before_sources_were_recorded_keep_no_source() {
+        // The element tables as they were before imports recorded their source file
+        let pool = crate::database::connect_pool("sqlite://file:migrate_source_columns?mode=memory&cache=shared").await.unwrap();
+        let old_table = |table: &str, columns: &str| format!("
+            CREATE TABLE {table} (
+                id BIGINT PRIMARY KEY NOT NULL,{columns}
+                version INT NOT NULL,
+                timestamp VARCHAR(50) NOT NULL,
+                changeset BIGINT NOT NULL,
+                uid BIGINT NOT NULL,
+                [user] VARCHAR(50) NOT NULL
+            );");
+        let old_schema = [
+            old_table("node", "\n                lat_e7 INTEGER NOT NULL,\n                lon_e7 INTEGER NOT NULL,"),
+            old_table("way", ""),
+            old_table("relation", ""),
+            "INSERT INTO node VALUES (1, 0, 0, 1, '', 1, 1, ''); INSERT INTO way VALUES (5, 1, '', 1, 1, '');".to_string(),
+        ];
+        sqlx::raw_sql(&old_schema.concat()).execute(&pool).await.unwrap();
+
+        create_tables(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+        for table in ELEMENT_TABLES {
+            let has_source_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = 'source_id')")
+                .bind(table)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert!(has_source_column, "{}", table);
+        }
+        let sources: Vec<Option<i64>> = sqlx::query_scalar("SELECT source_id FROM node UNION ALL SELECT source_id FROM way")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(sources, [None, None]);
+
+        // The column refers to the imports, and a second run leaves it alone
+        let source_id: i64 = sqlx::query_scalar("INSERT INTO source_file (filename) VALUES ('extract.osm') RETURNING id").fetch_one(&pool).await.unwrap();
+        sqlx::query("UPDATE way SET source_id = ?").bind(source_id).execute(&pool).await.unwrap();
+        assert!(sqlx::query("UPDATE node SET source_id = 404").execute(&pool).await.is_err());
+        create_tables(&pool).await.unwrap();
+        let way_source: Option<i64> = sqlx::query_scalar("SELECT source_id FROM way").fetch_one(&pool).await.unwrap();
+        assert_eq!(way_source, Some(source_id));
+    }
+
+    #[tokio::test]
+    async fn node_coordinates_stored_as_floats_become_integers() {
+        // The node table as it was before coordinates were stored as integers
+        let pool = crate::database::connect_pool("sqlite://file:migrate_node_coordinates?mode=memory&cache=shared").await.unwrap();
+        let coordinates = [(1, 55.6761234, 12.5683371), (2, -33.8688197, 151.2092955), (3, 0.0, -180.0), (4, 89.99999995, -0.00000004)];
+        sqlx::raw_sql("
+            CREATE TABLE node (
+                id BIGINT PRIMARY KEY NOT NULL,
+                lat DOUBLE NOT NULL,
+                lon DOUBLE NOT NULL,
+                version INT NOT NULL,
+                timestamp VARCHAR(50) NOT NULL,
+                changeset BIGINT NOT NULL,
+                uid BIGINT NOT NULL,
+                [user] VARCHAR(50) NOT NULL
+            );").execute(&pool).await.unwrap();
+        for (id, lat, lon) in coordinates {
+            sqlx::query("INSERT INTO node VALUES (?, ?, ?, 1, '', 1, 1, '')").bind(id).bind(lat).bind(lon).execute(&pool).await.unwrap();
+        }
+        assert!(schema_problems(&pool).await.unwrap().contains(&"table node stores its coordinates as floats".to_string()));
+
+        create_tables(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+        let stored: Vec<(i64, i64, i64)> = sqlx::query_as("SELECT id, lat_e7, lon_e7 FROM node ORDER BY id").fetch_all(&pool).await.unwrap();
+        let expected: Vec<(i64, i64, i64)> = coordinates.iter().map(|&(id, lat, lon)| (id, crate::utils::to_e7(lat), crate::utils::to_e7(lon))).collect();
+        assert_eq!(stored, expected);
+
+        for (id, lat, lon) in coordinates {
+            let node = crate::database::fetch_node_by_id(&pool, id).await.unwrap().unwrap();
+            assert!((node.lat - lat).abs() < 1e-7 && (node.lon - lon).abs() < 1e-7, "node {} moved to {}, {}", id, node.lat, node.lon);
+        }
+
+        // The duplicate trigger is back, on the new columns, and a second run leaves them alone
+        let has_trigger: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'trigger' AND name = 'node_duplicate')").fetch_one(&pool).await.unwrap();
+        assert!(has_trigger);
+        create_tables(&pool).await.unwrap();
+        let restored: Vec<(i64, i64, i64)> = sqlx::query_as("SELECT id, lat_e7, lon_e7 FROM node ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(restored, expected);
+    }
+
+    #[tokio::test]
+    async fn files_imported_before_fingerprints_are_imported_again() {
+        // The source_file table as it was before imports recorded what a file held
+        let pool = crate::database::connect_pool("sqlite://file:migrate_fingerprints?mode=memory&cache=shared").await.unwrap();
+        sqlx::raw_sql("
+            CREATE TABLE source_file (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                filename VARCHAR(255) NOT NULL,
+                imported_at VARCHAR(50) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+            );
+            INSERT INTO source_file (filename) VALUES ('denmark.osm');").execute(&pool).await.unwrap();
+
+        create_tables(&pool).await.unwrap();
+        for (column, _) in FINGERPRINT_COLUMNS {
+            let has_column: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pragma_table_info('source_file') WHERE name = ?)")
+                .bind(column)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert!(has_column, "source_file has no column {}", column);
+        }
+        let fingerprint: (String, Option<i64>, Option<i64>, Option<String>) = sqlx::query_as("SELECT filename, size, modified_at, checksum FROM source_file")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(fingerprint, ("denmark.osm".to_string(), None, None, None));
+
+        // Without a checksum the old import matches no file, and a second run changes nothing
+        let any_file = crate::database::FileFingerprint { size: 0, modified_at: 0, checksum: String::new() };
+        assert!(crate::database::find_identical_import(&pool, &any_file).await.unwrap().is_none());
+        create_tables(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn imports_recorded_before_phases_are_taken_to_be_done() {
+        // The source_file table with fingerprints, as it was before imports recorded their phase
+        let pool = crate::database::connect_pool("sqlite://file:migrate_import_phase?mode=memory&cache=shared").await.unwrap();
+        sqlx::raw_sql("
+            CREATE TABLE source_file (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                filename VARCHAR(255) NOT NULL,
+                imported_at VARCHAR(50) NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+                size BIGINT NULL,
+                modified_at BIGINT NULL,
+                checksum VARCHAR(64) NULL
+            );
+            INSERT INTO source_file (filename, size, modified_at, checksum) VALUES ('denmark.osm', 42, 7, 'abc');").execute(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().contains(&"table source_file records no import phases".to_string()));
+
+        create_tables(&pool).await.unwrap();
+        let phase: (String, Option<String>) = sqlx::query_as("SELECT filename, import_phase FROM source_file")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(phase, ("denmark.osm".to_string(), None));
+
+        // The same file again is skipped rather than resumed
+        let same_file = crate::database::FileFingerprint { size: 42, modified_at: 7, checksum: "abc".to_string() };
+        let earlier = crate::database::find_identical_import(&pool, &same_file).await.unwrap().unwrap();
+        let recorded = crate::database::fetch_import_phase(&pool, earlier.id).await.unwrap();
+        assert_eq!(recorded, None);
+        assert_eq!(crate::database::decide_import_start(Some((earlier.id, recorded)), false), crate::database::ImportStart::Skip { source_id: earlier.id });
+
+        // New imports record their phase in the added column, and a second run changes nothing
+        crate::database::save_import_phase(&pool, earlier.id, crate::database::ImportPhase::NodesInserted).await.unwrap();
+        assert_eq!(crate::database::fetch_import_phase(&pool, earlier.id).await.unwrap(), Some(crate::database::ImportPhase::NodesInserted));
+        create_tables(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn members_keyed_by_a_hash_are_numbered_in_the_order_imported() {
+        let pool = memory_pool("migrate_member_hash").await;
+        sqlx::raw_sql("
+            INSERT INTO relation (id, version, timestamp, changeset, uid, [user]) VALUES (1, 1, '', 1, 1, ''), (2, 1, '', 1, 1, '');
+            DROP TABLE member;
+            CREATE TABLE member (
+                id BIGINT PRIMARY KEY NOT NULL,
+                relation_id BIGINT NOT NULL,
+                node_id BIGINT NULL,
+                way_id BIGINT NULL,
+                relation_ref_id BIGINT NULL,
+                member_type VARCHAR(50) NOT NULL,
+                role VARCHAR(50) NOT NULL,
+                FOREIGN KEY (relation_id) REFERENCES relation(id)
+            );
+            CREATE INDEX member_relation ON member (relation_id);
+            INSERT INTO member (id, relation_id, node_id, way_id, relation_ref_id, member_type, role) VALUES
+                (900, 2, NULL, 10, NULL, 'way', 'outer'),
+                (5, 1, 1, NULL, NULL, 'node', 'stop'),
+                (7, 2, 1, NULL, NULL, 'node', ''),
+                (3, 1, NULL, NULL, 2, 'relation', '');
+        ").execute(&pool).await.unwrap();
+        assert_eq!(schema_problems(&pool).await.unwrap(), ["table member identifies its members by a hash"]);
+
+        create_tables(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+        let members: Vec<(i64, i64, i64, String, String)> = sqlx::query_as("SELECT relation_id, seq, ref_id, member_type, role FROM member ORDER BY relation_id, seq")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let expected = [(1, 0, 1, "node", "stop"), (1, 1, 2, "relation", ""), (2, 0, 10, "way", "outer"), (2, 1, 1, "node", "")];
+        assert_eq!(members, expected.map(|(relation_id, seq, ref_id, member_type, role)| (relation_id, seq, ref_id, member_type.to_string(), role.to_string())));
+
+        // The same member may now be stored for both relations, and twice within one
+        sqlx::query("INSERT INTO member (relation_id, seq, ref_id, member_type, role) VALUES (1, 2, 10, 'way', 'outer'), (2, 2, 10, 'way', 'outer')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let indexes: Vec<String> = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'member' AND sql IS NOT NULL ORDER BY name")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(indexes, ["member_ref"]);
+    }
+
+    #[tokio::test]
+    async fn member_references_split_by_type_are_merged_into_one_column() {
+        let pool = memory_pool("migrate_member_columns").await;
+        // The numbered member table as it was while every type had a column of its own
+        sqlx::raw_sql("
+            INSERT INTO relation (id, version, timestamp, changeset, uid, [user]) VALUES (1, 1, '', 1, 1, ''), (2, 1, '', 1, 1, '');
+            DROP TABLE member;
+            CREATE TABLE member (
+                relation_id BIGINT NOT NULL,
+                seq INTEGER NOT NULL,
+                node_id BIGINT NULL,
+                way_id BIGINT NULL,
+                relation_ref_id BIGINT NULL,
+                member_type VARCHAR(50) NOT NULL,
+                role VARCHAR(50) NOT NULL,
+                PRIMARY KEY (relation_id, seq),
+                FOREIGN KEY (relation_id) REFERENCES relation(id),
+                CONSTRAINT member_type_check CHECK (
+                    (member_type = 'node' AND node_id IS NOT NULL AND way_id IS NULL AND relation_ref_id IS NULL) OR
+                    (member_type = 'way' AND way_id IS NOT NULL AND node_id IS NULL AND relation_ref_id IS NULL) OR
+                    (member_type = 'relation' AND relation_ref_id IS NOT NULL AND node_id IS NULL AND way_id IS NULL)
+                )
+            );
+            INSERT INTO member (relation_id, seq, node_id, way_id, relation_ref_id, member_type, role) VALUES
+                (1, 0, 5, NULL, NULL, 'node', 'stop:entry_only'),
+                (1, 1, NULL, NULL, 2, 'relation', ''),
+                (2, 0, NULL, 10, NULL, 'way', 'outer'),
+                (2, 3, 5, NULL, NULL, 'node', 'a,b');
+        ").execute(&pool).await.unwrap();
+        assert_eq!(schema_problems(&pool).await.unwrap(), ["table member splits its references across a column per type"]);
+
+        create_tables(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info('member') ORDER BY cid").fetch_all(&pool).await.unwrap();
+        assert_eq!(columns, ["relation_id", "seq", "ref_id", "member_type", "role"]);
+        // The positions are kept as they were
+        let members: Vec<(i64, i64, i64, String, String)> = sqlx::query_as("SELECT relation_id, seq, ref_id, member_type, role FROM member ORDER BY relation_id, seq")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let expected = [(1, 0, 5, "node", "stop:entry_only"), (1, 1, 2, "relation", ""), (2, 0, 10, "way", "outer"), (2, 3, 5, "node", "a,b")];
+        assert_eq!(members, expected.map(|(relation_id, seq, ref_id, member_type, role)| (relation_id, seq, ref_id, member_type.to_string(), role.to_string())));
+
+        // A second run leaves the table alone
+        create_tables(&pool).await.unwrap();
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM member").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 4);
+    }
+
+    #[tokio::test]
+    async fn tags_stored_as_text_are_interned() {
+        let pool = memory_pool("migrate_tag_tables").await;
+        sqlx::raw_sql("
+            INSERT INTO node (id, lat_e7, lon_e7, version, timestamp, changeset, uid, [user]) VALUES (1, 0, 0, 1, '', 1, 1, '');
+            INSERT INTO way (id, version, timestamp, changeset, uid, [user]) VALUES (5, 1, '', 1, 1, ''), (7, 1, '', 1, 1, '');
+            DROP TABLE node_tags;
+            DROP TABLE way_tags;
+            CREATE TABLE node_tags (
+                node_id BIGINT NOT NULL,
+                [key] VARCHAR(50) NOT NULL,
+                value VARCHAR(50) NOT NULL,
+                FOREIGN KEY (node_id) REFERENCES node(id),
+                PRIMARY KEY (node_id, [key])
+            );
+            CREATE TABLE way_tags (
+                way_id BIGINT NOT NULL,
+                [key] VARCHAR(50) NOT NULL,
+                value VARCHAR(50) NOT NULL,
+                FOREIGN KEY (way_id) REFERENCES way(id),
+                PRIMARY KEY (way_id, [key])
+            );
+            INSERT INTO node_tags VALUES (1, 'amenity', 'cafe');
+            INSERT INTO way_tags VALUES (5, 'highway', 'residential'), (5, 'oneway', 'yes'), (7, 'building', 'yes'), (7, 'name', 'Café');
+        ").execute(&pool).await.unwrap();
+        let problems = schema_problems(&pool).await.unwrap();
+        assert_eq!(problems, ["table node_tags holds its tags as text", "table way_tags holds its tags as text"]);
+
+        create_tables(&pool).await.unwrap();
+        assert!(schema_problems(&pool).await.unwrap().is_empty());
+
+        let tags: Vec<(i64, String, String)> = sqlx::query_as("
+            SELECT t.way_id, k.text, v.text FROM way_tags t
+            JOIN tag_key k ON k.id = t.key_id
+            JOIN tag_value v ON v.id = t.value_id
+            ORDER BY t.way_id, k.text
+        ").fetch_all(&pool).await.unwrap();
+        let expected = [(5, "highway", "residential"), (5, "oneway", "yes"), (7, "building", "yes"), (7, "name", "Café")];
+        assert_eq!(tags, expected.map(|(id, key, value)| (id, key.to_string(), value.to_string())));
+
+        // Every text is stored once, however many tags share it
+        let value_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tag_value").fetch_one(&pool).await.unwrap();
+        assert_eq!(value_count, 4);
+        let node_tag: (String, String) = sqlx::query_as("SELECT k.text, v.text FROM node_tags t JOIN tag_key k ON k.id = t.key_id JOIN tag_value v ON v.id = t.value_id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(node_tag, ("amenity".to_string(), "cafe".to_string()));
+
+        // The duplicate triggers are back for the new columns, and the relation tags were left alone
+        let triggers: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'trigger' AND tbl_name IN ('node_tags', 'way_tags', 'relation_tags')")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(triggers, 3);
+    }
+}
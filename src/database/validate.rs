@@ -0,0 +1,263 @@
+use std::fmt;
+
+use sqlx::{Row, SqlitePool};
+
+/// How many offending ids are kept per check.
+pub const VALIDATION_SAMPLE_SIZE: i64 = 10;
+
+// Each check is a query selecting the ids of the offending entities. Missing members
+// are reported by the relation they belong to.
+const WAYS_WITH_MISSING_NODES_QUERY: &str = "
+    SELECT DISTINCT wn.way_id
+    FROM way_nodes wn
+    LEFT JOIN node n ON n.id = wn.ref_id
+    WHERE n.id IS NULL
+";
+
+const MEMBERS_WITH_MISSING_NODES_QUERY: &str = "
+    SELECT DISTINCT m.relation_id
+    FROM member m
+    LEFT JOIN node n ON n.id = m.ref_id
+    WHERE m.member_type = 'node' AND n.id IS NULL
+";
+
+const MEMBERS_WITH_MISSING_WAYS_QUERY: &str = "
+    SELECT DISTINCT m.relation_id
+    FROM member m
+    LEFT JOIN way w ON w.id = m.ref_id
+    WHERE m.member_type = 'way' AND w.id IS NULL
+";
+
+const MEMBERS_WITH_MISSING_RELATIONS_QUERY: &str = "
+    SELECT DISTINCT m.relation_id
+    FROM member m
+    LEFT JOIN relation r ON r.id = m.ref_id
+    WHERE m.member_type = 'relation' AND r.id IS NULL
+";
+
+const SHORT_WAYS_QUERY: &str = "
+    SELECT w.id
+    FROM way w
+    LEFT JOIN way_nodes wn ON wn.way_id = w.id
+    GROUP BY w.id
+    HAVING COUNT(wn.ref_id) < 2
+";
+
+const NODES_OUT_OF_RANGE_QUERY: &str = "
+    SELECT n.id
+    FROM node n
+    WHERE n.lat_e7 NOT BETWEEN -900000000 AND 900000000 OR n.lon_e7 NOT BETWEEN -1800000000 AND 1800000000
+";
+
+// Every way but the first of a group with the exact same node sequence
+const DUPLICATE_WAY_SEQUENCES_QUERY: &str = "
+    SELECT s.way_id
+    FROM (
+        SELECT wn.way_id, GROUP_CONCAT(wn.ref_id, ',') AS refs
+        FROM (SELECT way_id, ref_id FROM way_nodes ORDER BY way_id, seq) wn
+        GROUP BY wn.way_id
+    ) s
+    JOIN (
+        SELECT wn.way_id, GROUP_CONCAT(wn.ref_id, ',') AS refs
+        FROM (SELECT way_id, ref_id FROM way_nodes ORDER BY way_id, seq) wn
+        GROUP BY wn.way_id
+    ) o ON o.refs = s.refs AND o.way_id < s.way_id
+    GROUP BY s.way_id
+";
+
+/// The outcome of one integrity check.
+///
+/// # Fields
+/// * `name` - A short identifier of the check.
+/// * `description` - What the offending ids are.
+/// * `critical` - Whether offenders make the data unfit to render, rather than merely incomplete.
+/// * `count` - How many offenders there are.
+/// * `sample` - The first few offending ids, at most `VALIDATION_SAMPLE_SIZE`, with the file
+///   each was imported from if that is known.
+#[derive(Debug, Clone)]
+pub struct ValidationCheck {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub critical: bool,
+    pub count: i64,
+    pub sample: Vec<(i64, Option<String>)>,
+}
+
+/// The outcome of every check run by `validate_database`.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub checks: Vec<ValidationCheck>,
+}
+
+impl ValidationReport {
+    /// Returns true if any critical check found offenders.
+    pub fn has_critical_errors(&self) -> bool {
+        self.checks.iter().any(|check| check.critical && check.count > 0)
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match (check.count, check.critical) {
+                (0, _) => "ok",
+                (_, true) => "ERROR",
+                (_, false) => "warning",
+            };
+            write!(f, "[{}] {}: {} {}", status, check.name, check.count, check.description)?;
+
+            if !check.sample.is_empty() {
+                let sample: Vec<String> = check.sample.iter()
+                    .map(|(id, source)| match source {
+                        Some(source) => format!("{} from {}", id, source),
+                        None => id.to_string(),
+                    })
+                    .collect();
+                let more = if check.count > check.sample.len() as i64 { ", ..." } else { "" };
+                write!(f, " (e.g. {}{})", sample.join(", "), more)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the referential integrity checks, e.g. after importing an extract.
+///
+/// ## Returns
+/// * A report with the number of offenders and a sample of their ids per check.
+pub async fn validate_database(pool: &SqlitePool) -> Result<ValidationReport, sqlx::Error> {
+    let checks = [
+        ("ways_with_missing_nodes", "ways referencing nodes that do not exist", true, "way", WAYS_WITH_MISSING_NODES_QUERY),
+        ("members_with_missing_nodes", "relations with members referencing nodes that do not exist", false, "relation", MEMBERS_WITH_MISSING_NODES_QUERY),
+        ("members_with_missing_ways", "relations with members referencing ways that do not exist", false, "relation", MEMBERS_WITH_MISSING_WAYS_QUERY),
+        ("members_with_missing_relations", "relations with members referencing relations that do not exist", false, "relation", MEMBERS_WITH_MISSING_RELATIONS_QUERY),
+        ("short_ways", "ways with fewer than 2 nodes", false, "way", SHORT_WAYS_QUERY),
+        ("nodes_out_of_range", "nodes with a latitude or longitude out of range", true, "node", NODES_OUT_OF_RANGE_QUERY),
+        ("duplicate_way_sequences", "ways with the same node sequence as another way", false, "way", DUPLICATE_WAY_SEQUENCES_QUERY),
+    ];
+
+    let mut report = ValidationReport::default();
+
+    for (name, description, critical, table, query) in checks {
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM ({})", query))
+            .fetch_one(pool)
+            .await?;
+
+        // The offenders are looked up in `table` for the file they were imported from
+        let sample_query = format!("
+            WITH offender(id) AS ({query})
+            SELECT o.id, s.filename
+            FROM offender o
+            LEFT JOIN {table} e ON e.id = o.id
+            LEFT JOIN source_file s ON s.id = e.source_id
+            ORDER BY o.id
+            LIMIT ?
+        ");
+        let sample = sqlx::query(&sample_query)
+            .bind(VALIDATION_SAMPLE_SIZE)
+            .fetch_all(pool)
+            .await?
+            .iter()
+            .map(|row| Ok((row.try_get("id")?, row.try_get("filename")?)))
+            .collect::<Result<Vec<(i64, Option<String>)>, sqlx::Error>>()?;
+
+        report.checks.push(ValidationCheck { name, description, critical, count, sample });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    // Way 10 is fine, 12 has a single node and 13 repeats the nodes of 10. Relation 20 refers
+    // to a missing node, way and relation
+    const BROKEN_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"/>
+ <node id="2" lat="55.1" lon="12.1" version="1"/>
+ <node id="3" lat="55.2" lon="12.2" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/></way>
+ <way id="12" version="1"><nd ref="3"/></way>
+ <way id="13" version="1"><nd ref="1"/><nd ref="2"/></way>
+ <relation id="20" version="1">
+  <member type="node" ref="8" role=""/><member type="way" ref="18" role=""/><member type="relation" ref="28" role=""/>
+  <member type="way" ref="10" role=""/>
+ </relation>
+</osm>
+"#;
+
+    fn check<'a>(report: &'a ValidationReport, name: &str) -> &'a ValidationCheck {
+        report.checks.iter().find(|check| check.name == name).unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_kind_of_broken_reference_is_found() {
+        let pool = memory_pool("validate_broken").await;
+        import_osm_xml(&pool, "validate_broken", BROKEN_OSM).await;
+        // The readers leave out coordinates out of range and the foreign keys keep out references
+        // to missing nodes, so these are broken in the database: way 11 refers to the missing node 9
+        let mut connection = pool.acquire().await.unwrap();
+        for statement in [
+            "PRAGMA foreign_keys = OFF",
+            "UPDATE node SET lat_e7 = 950000000 WHERE id = 3",
+            "INSERT INTO way (id, version, timestamp, changeset, uid, [user], source_id) SELECT 11, 1, '', 0, 0, '', source_id FROM way WHERE id = 10",
+            "INSERT INTO way_nodes (way_id, seq, ref_id) VALUES (11, 0, 2), (11, 1, 9)",
+            "PRAGMA foreign_keys = ON",
+        ] {
+            sqlx::query(statement).execute(&mut *connection).await.unwrap();
+        }
+        drop(connection);
+
+        let report = validate_database(&pool).await.unwrap();
+        let offenders = |name| check(&report, name).sample.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(offenders("ways_with_missing_nodes"), vec![11]);
+        assert_eq!(offenders("members_with_missing_nodes"), vec![20]);
+        assert_eq!(offenders("members_with_missing_ways"), vec![20]);
+        assert_eq!(offenders("members_with_missing_relations"), vec![20]);
+        assert_eq!(offenders("short_ways"), vec![12]);
+        assert_eq!(offenders("nodes_out_of_range"), vec![3]);
+        assert_eq!(offenders("duplicate_way_sequences"), vec![13]);
+        // The sample says which file the offenders came from
+        let (_, source) = &check(&report, "ways_with_missing_nodes").sample[0];
+        assert!(source.as_deref().is_some_and(|source| source.contains("validate_broken")), "{:?}", source);
+
+        assert!(report.has_critical_errors());
+        let text = report.to_string();
+        assert!(text.contains("[ERROR] ways_with_missing_nodes: 1 "), "{}", text);
+        assert!(text.contains("[warning] short_ways: 1 "), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn a_consistent_database_passes_every_check() {
+        let pool = memory_pool("validate_consistent").await;
+        import_osm_xml(&pool, "validate_consistent", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"/>
+ <node id="2" lat="55.1" lon="12.1" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="1"/></way>
+ <relation id="20" version="1"><member type="way" ref="10" role="outer"/></relation>
+</osm>"#).await;
+
+        let report = validate_database(&pool).await.unwrap();
+        assert_eq!(report.checks.len(), 7);
+        assert!(report.checks.iter().all(|check| check.count == 0 && check.sample.is_empty()), "{}", report);
+        assert!(!report.has_critical_errors());
+    }
+
+    #[tokio::test]
+    async fn the_sample_is_bounded_but_the_count_is_not() {
+        let pool = memory_pool("validate_sample").await;
+        let ways: String = (100..125).map(|id| format!(r#"<way id="{}" version="1"><nd ref="1"/></way>"#, id)).collect();
+        import_osm_xml(&pool, "validate_sample", &format!(r#"<osm version="0.6"><node id="1" lat="55.0" lon="12.0" version="1"/>{}</osm>"#, ways)).await;
+
+        let report = validate_database(&pool).await.unwrap();
+        let short_ways = check(&report, "short_ways");
+        assert_eq!(short_ways.count, 25);
+        assert_eq!(short_ways.sample.len(), VALIDATION_SAMPLE_SIZE as usize);
+        assert!(report.to_string().contains(", ...)"));
+    }
+}
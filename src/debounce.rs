@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+use crate::history::Viewport;
+
+/// How long the camera has to stay still before the viewport it stopped at is fetched, so
+/// holding a key or scrolling fetches once it stops instead of at every step.
+pub const FETCH_QUIET_PERIOD: Duration = Duration::from_millis(150);
+/// How far the camera may move past an edge of the viewport the map was loaded for before it
+/// is fetched without waiting, as a share of the loaded width or height. Beyond it the edge of
+/// the loaded map would come into view.
+pub const LOADED_MARGIN: f64 = 0.25;
+
+/// Decides when the map is fetched for the viewport while the camera moves.
+///
+/// Moving the camera only redraws the map already loaded, moved by the camera uniform. The
+/// viewport is fetched once the camera stayed still for `FETCH_QUIET_PERIOD`, or right away
+/// once it moved more than `LOADED_MARGIN` beyond the loaded viewport.
+///
+/// # Fields
+/// * `loaded` - The viewport the map was last fetched for, or `None` before it was.
+/// * `moved_at` - When the camera last moved since the map was fetched, if it did.
+#[derive(Debug, Default)]
+pub struct FetchDebouncer {
+    loaded: Option<Viewport>,
+    moved_at: Option<Instant>,
+}
+
+impl FetchDebouncer {
+    /// Notes that the map was fetched for a viewport, which ends the wait.
+    pub fn loaded(&mut self, viewport: Viewport) {
+        self.loaded = Some(viewport);
+        self.moved_at = None;
+    }
+
+    /// Notes that the camera moved to a viewport, which starts the wait over.
+    ///
+    /// ## Returns
+    /// * Whether the viewport is to be fetched right away, as it left the loaded one.
+    pub fn moved(&mut self, viewport: Viewport, now: Instant) -> bool {
+        self.moved_at = Some(now);
+        self.loaded.is_none_or(|loaded| is_beyond_loaded(&viewport, &loaded, LOADED_MARGIN))
+    }
+
+    /// When the viewport is due to be fetched, if the camera moved since it last was.
+    pub fn due_at(&self) -> Option<Instant> {
+        self.moved_at.map(|moved_at| moved_at + FETCH_QUIET_PERIOD)
+    }
+
+    /// Tells whether the camera has stayed still for `FETCH_QUIET_PERIOD` since it moved.
+    /// The caller fetches and reports it with `loaded`.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.due_at().is_some_and(|due_at| now >= due_at)
+    }
+}
+
+/// Tells whether a viewport reaches further than `margin` beyond any edge of the loaded one,
+/// as a share of its width or height.
+pub fn is_beyond_loaded(viewport: &Viewport, loaded: &Viewport, margin: f64) -> bool {
+    !loaded.expand(margin).contains_bbox(viewport)
+}
@@ -0,0 +1,421 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use sqlx::migrate::MigrateDatabase;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Sqlite, SqlitePool};
+use tracing::{error, info, warn};
+
+use crate::database::{is_memory_url, schema_problems, ELEMENT_TABLES};
+use crate::fetcher::{list_files_in_directory, MAPDATA_DIRECTORY};
+use crate::gpu::{request_device, select_headless_adapter, GpuOptions, BACKEND_ENV};
+use crate::texture::EMBEDDED_TEXTURES;
+
+// Written and removed again next to the database to find out whether SQLite can create its journal there
+const WRITE_PROBE_FILE: &str = ".doctor-write-test";
+
+/// How a prerequisite check came out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    /// The prerequisite is met.
+    Ok,
+    /// The map opens, but lacks something, e.g. data to show.
+    Warning,
+    /// The map cannot open, or fails once it has.
+    Error,
+}
+
+/// The outcome of one prerequisite check.
+///
+/// # Fields
+/// * `name` - A short identifier of the check.
+/// * `status` - Whether the prerequisite is met.
+/// * `detail` - What the check found.
+/// * `hint` - How to fix what the check found, if it did not pass.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        CheckResult { name, status: CheckStatus::Ok, detail: detail.into(), hint: None }
+    }
+
+    fn warning(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        CheckResult { name, status: CheckStatus::Warning, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    fn error(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        CheckResult { name, status: CheckStatus::Error, detail: detail.into(), hint: Some(hint.into()) }
+    }
+}
+
+/// The outcome of every check run by `run_doctor` or `startup_checks`.
+#[derive(Debug, Clone, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    /// Returns true if any check failed with an error rather than a warning.
+    pub fn has_errors(&self) -> bool {
+        self.checks.iter().any(|check| check.status == CheckStatus::Error)
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warning => "warning",
+                CheckStatus::Error => "ERROR",
+            };
+            writeln!(f, "[{}] {}: {}", status, check.name, check.detail)?;
+            if let Some(hint) = &check.hint {
+                writeln!(f, "    {}", hint)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks every prerequisite of the map, e.g. for `--doctor`. A failed check does not stop
+/// the checks after it, and the database is opened read-only, so nothing is changed.
+///
+/// ## Arguments
+/// * `db_url` - The URL of the database the map would open.
+pub async fn run_doctor(db_url: &str) -> DoctorReport {
+    let mut checks = vec![check_database_directory(db_url)];
+
+    if is_memory_url(db_url) {
+        checks.push(CheckResult::ok("database", "held in memory, created with its tables when the map opens"));
+    } else {
+        match open_database_read_only(db_url).await {
+            Ok(pool) => {
+                checks.push(CheckResult::ok("database", format!("opened {}", db_url)));
+                checks.push(check_schema(&pool).await);
+                checks.push(check_row_counts(&pool).await);
+                pool.close().await;
+            }
+            Err(check) => checks.push(check),
+        }
+    }
+
+    checks.push(check_mapdata(MAPDATA_DIRECTORY));
+    checks.push(check_textures());
+    checks.push(check_shaders().await);
+
+    DoctorReport { checks }
+}
+
+/// The checks cheap enough to run whenever the map opens: everything `run_doctor` checks
+/// but the shaders, which opening the window compiles anyway.
+///
+/// ## Arguments
+/// * `pool` - The database the map opens, after `create_tables`.
+pub async fn startup_checks(db_url: &str, pool: &SqlitePool) -> DoctorReport {
+    let mut checks = vec![check_database_directory(db_url)];
+    if !is_memory_url(db_url) {
+        checks.push(check_schema(pool).await);
+    }
+    checks.push(check_row_counts(pool).await);
+    checks.push(check_mapdata(MAPDATA_DIRECTORY));
+    checks.push(check_textures());
+
+    DoctorReport { checks }
+}
+
+/// Runs `startup_checks` and logs every check that did not pass, with its hint.
+pub async fn log_startup_checks(db_url: &str, pool: &SqlitePool) {
+    let report = startup_checks(db_url, pool).await;
+
+    for check in &report.checks {
+        let hint = check.hint.as_deref().unwrap_or_default();
+        match check.status {
+            CheckStatus::Ok => (),
+            CheckStatus::Warning => warn!(check = check.name, detail = %check.detail, hint, "startup check"),
+            CheckStatus::Error => error!(check = check.name, detail = %check.detail, hint, "startup check failed"),
+        }
+    }
+    if !report.has_errors() {
+        info!(checks = report.checks.len(), "startup checks done, see --doctor for the full list");
+    }
+}
+
+/// The path of the database file of a `sqlite:` URL, without the options after `?`.
+fn database_file_path(db_url: &str) -> Option<&Path> {
+    let path = db_url.strip_prefix("sqlite://").or_else(|| db_url.strip_prefix("sqlite:"))?;
+    let path = path.split('?').next().unwrap_or_default();
+    (!path.is_empty()).then(|| Path::new(path))
+}
+
+/// Checks that the directory of the database exists and can be written to, as SQLite
+/// creates the database and its journal files there.
+pub fn check_database_directory(db_url: &str) -> CheckResult {
+    const NAME: &str = "database_directory";
+    if is_memory_url(db_url) {
+        return CheckResult::ok(NAME, "the database is held in memory");
+    }
+
+    let Some(path) = database_file_path(db_url) else {
+        return CheckResult::error(NAME, format!("{} is no SQLite URL", db_url), "give the database as --db sqlite://path/to/file.db");
+    };
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !directory.is_dir() {
+        return CheckResult::error(NAME, format!("{} does not exist", directory.display()), format!("create it with `mkdir -p {}`", directory.display()));
+    }
+
+    let probe = directory.join(WRITE_PROBE_FILE);
+    match fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe)) {
+        Ok(()) => CheckResult::ok(NAME, format!("{} is writable", directory.display())),
+        Err(error) => CheckResult::error(
+            NAME,
+            format!("cannot write to {}: {}", directory.display(), error),
+            "allow writing to the directory, or give another database with --db url",
+        ),
+    }
+}
+
+/// Opens an existing database without creating, migrating or checkpointing it.
+///
+/// ## Returns
+/// * The pool, or the failed `database` check if there is no database or it cannot be opened.
+async fn open_database_read_only(db_url: &str) -> Result<SqlitePool, CheckResult> {
+    const NAME: &str = "database";
+    let hint = "the database is created when the map opens, or import a file into it with --import file";
+
+    match Sqlite::database_exists(db_url).await {
+        Ok(true) => (),
+        Ok(false) => return Err(CheckResult::error(NAME, format!("there is no database at {}", db_url), hint)),
+        Err(error) => return Err(CheckResult::error(NAME, format!("could not look for {}: {}", db_url, error), hint)),
+    }
+
+    let options = SqliteConnectOptions::from_str(db_url)
+        .map_err(|error| CheckResult::error(NAME, format!("{} is no SQLite URL: {}", db_url, error), hint))?
+        .read_only(true);
+    SqlitePool::connect_with(options).await
+        .map_err(|error| CheckResult::error(NAME, format!("could not open {}: {}", db_url, error), "check that the file is a SQLite database and readable"))
+}
+
+/// Checks that every table exists in its current layout, see `schema_problems`.
+pub async fn check_schema(pool: &SqlitePool) -> CheckResult {
+    const NAME: &str = "schema";
+    let hint = "open the map once, or run --validate, to create and migrate the tables";
+
+    match schema_problems(pool).await {
+        Ok(problems) if problems.is_empty() => CheckResult::ok(NAME, "every table is current"),
+        Ok(problems) => CheckResult::error(NAME, problems.join(", "), hint),
+        Err(error) => CheckResult::error(NAME, format!("could not read the schema: {}", error), hint),
+    }
+}
+
+/// Counts the rows of the element tables. An empty database opens, but shows nothing.
+pub async fn check_row_counts(pool: &SqlitePool) -> CheckResult {
+    const NAME: &str = "rows";
+
+    let mut counts = Vec::new();
+    for table in ELEMENT_TABLES {
+        match sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {}", table)).fetch_one(pool).await {
+            Ok(count) => counts.push((table, count)),
+            Err(error) => return CheckResult::warning(NAME, format!("could not count the rows of {}: {}", table, error), "see the schema check"),
+        }
+    }
+
+    let detail = counts.iter().map(|(table, count)| format!("{} {}", count, table)).collect::<Vec<_>>().join(", ");
+    if counts.iter().all(|&(_, count)| count == 0) {
+        return CheckResult::warning(
+            NAME,
+            format!("the database is empty ({})", detail),
+            "import an extract with --import file, or download a box with --download minLon,minLat,maxLon,maxLat",
+        );
+    }
+    CheckResult::ok(NAME, detail)
+}
+
+/// Checks that the directory of map files exists and lists what can be imported from it.
+pub fn check_mapdata(directory: &str) -> CheckResult {
+    const NAME: &str = "mapdata";
+    let hint = format!("put OpenStreetMap extracts or GPX tracks into {}, or import a file from anywhere with --import file", directory);
+
+    match list_files_in_directory(directory) {
+        Ok(mut files) if !files.is_empty() => {
+            files.sort();
+            CheckResult::ok(NAME, format!("{} files to import in {}: {}", files.len(), directory, files.join(", ")))
+        }
+        Ok(_) => CheckResult::warning(NAME, format!("{} holds no files", directory), hint),
+        Err(error) => CheckResult::warning(NAME, format!("cannot list {}: {}", directory, error), hint),
+    }
+}
+
+/// Checks that the textures built into the program decode.
+pub fn check_textures() -> CheckResult {
+    const NAME: &str = "textures";
+
+    let failures: Vec<String> = EMBEDDED_TEXTURES.iter()
+        .filter_map(|(name, bytes)| image::load_from_memory(bytes).err().map(|error| format!("{}: {}", name, error)))
+        .collect();
+    if failures.is_empty() {
+        CheckResult::ok(NAME, format!("{} textures decode", EMBEDDED_TEXTURES.len()))
+    } else {
+        CheckResult::error(NAME, failures.join(", "), "replace the files in utils/textures with valid PNG images and build again")
+    }
+}
+
+/// Compiles `map.wgsl` and `icon.wgsl` on a device without a window, reporting the validation errors.
+pub async fn check_shaders() -> CheckResult {
+    const NAME: &str = "shaders";
+    let driver_hint = format!("install a Vulkan, Metal, DX12 or OpenGL driver, or try another backend with {}", BACKEND_ENV);
+
+    let gpu_options = GpuOptions::from_env();
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: gpu_options.backends,
+        ..Default::default()
+    });
+    let adapter = match select_headless_adapter(&instance, &gpu_options) {
+        Ok(adapter) => adapter,
+        Err(error) => return CheckResult::error(NAME, error.to_string(), driver_hint),
+    };
+    let (device, _queue) = match request_device(&adapter).await {
+        Ok(device) => device,
+        Err(error) => return CheckResult::error(NAME, error.to_string(), driver_hint),
+    };
+
+    let shaders = [wgpu::include_wgsl!("map.wgsl"), wgpu::include_wgsl!("icon.wgsl")];
+    let mut failures = Vec::new();
+    for shader in shaders {
+        let name = shader.label.unwrap_or_default();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let _shader = device.create_shader_module(shader);
+        if let Some(error) = device.pop_error_scope().await {
+            failures.push(format!("{} does not compile: {}", name, error));
+        }
+    }
+
+    let adapter_info = adapter.get_info();
+    if failures.is_empty() {
+        CheckResult::ok(NAME, format!("map.wgsl and icon.wgsl compile on {} ({:?})", adapter_info.name, adapter_info.backend))
+    } else {
+        CheckResult::error(NAME, failures.join(", "), "fix the shaders in src and build again")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    /// A directory of its own under the temporary directory, empty.
+    fn temp_directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("gmc_doctor_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn the_database_file_is_found_in_its_url() {
+        assert_eq!(database_file_path("sqlite://database/sqlite.db"), Some(Path::new("database/sqlite.db")));
+        assert_eq!(database_file_path("sqlite:///tmp/x.db?mode=rwc"), Some(Path::new("/tmp/x.db")));
+        assert_eq!(database_file_path("sqlite:map.db"), Some(Path::new("map.db")));
+        assert_eq!(database_file_path("postgres://localhost/map"), None);
+        assert_eq!(database_file_path("sqlite://"), None);
+    }
+
+    #[test]
+    fn the_database_directory_must_exist_and_be_writable() {
+        let directory = temp_directory("database_directory");
+        let url = format!("sqlite://{}", directory.join("map.db").display());
+        assert_eq!(check_database_directory(&url).status, CheckStatus::Ok);
+        // The probe is removed again
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+
+        let missing = format!("sqlite://{}", directory.join("missing").join("map.db").display());
+        let check = check_database_directory(&missing);
+        assert_eq!(check.status, CheckStatus::Error);
+        assert!(check.hint.unwrap().starts_with("create it with `mkdir -p "));
+
+        assert_eq!(check_database_directory("postgres://localhost/map").status, CheckStatus::Error);
+        assert_eq!(check_database_directory(crate::database::MEMORY_DB_URL).status, CheckStatus::Ok);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_empty_database_is_a_warning_and_a_missing_schema_an_error() {
+        let pool = memory_pool("doctor_rows").await;
+        assert_eq!(check_schema(&pool).await.status, CheckStatus::Ok);
+        let check = check_row_counts(&pool).await;
+        assert_eq!((check.status, check.detail.as_str()), (CheckStatus::Warning, "the database is empty (0 node, 0 way, 0 relation)"));
+
+        import_osm_xml(&pool, "doctor_rows", r#"<osm version="0.6"><node id="1" lat="55.0" lon="11.0" version="1"/></osm>"#).await;
+        let check = check_row_counts(&pool).await;
+        assert_eq!((check.status, check.detail.as_str()), (CheckStatus::Ok, "1 node, 0 way, 0 relation"));
+
+        // Without the tables the schema check fails and the rows cannot be counted
+        let bare = crate::database::connect_pool("sqlite://file:doctor_bare?mode=memory&cache=shared").await.unwrap();
+        assert_eq!(check_schema(&bare).await.status, CheckStatus::Error);
+        assert_eq!(check_row_counts(&bare).await.status, CheckStatus::Warning);
+    }
+
+    #[test]
+    fn the_map_files_are_listed_in_order() {
+        let directory = temp_directory("mapdata");
+        let path = directory.to_str().unwrap();
+        assert_eq!(check_mapdata(path).status, CheckStatus::Warning);
+
+        fs::write(directory.join("b.osm"), "").unwrap();
+        fs::write(directory.join("a.gpx"), "").unwrap();
+        fs::create_dir(directory.join("nested")).unwrap();
+        let check = check_mapdata(path);
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert!(check.detail.ends_with(": a.gpx, b.osm"), "{}", check.detail);
+        assert!(check.detail.starts_with("2 files to import"), "{}", check.detail);
+
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(check_mapdata(path).status, CheckStatus::Warning);
+    }
+
+    #[test]
+    fn the_embedded_textures_decode() {
+        assert_eq!(check_textures().status, CheckStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn a_missing_database_does_not_stop_the_other_checks() {
+        let directory = temp_directory("run_doctor");
+        let url = format!("sqlite://{}", directory.join("missing.db").display());
+
+        let report = run_doctor(&url).await;
+        let names: Vec<&str> = report.checks.iter().map(|check| check.name).collect();
+        assert_eq!(names, ["database_directory", "database", "mapdata", "textures", "shaders"]);
+        assert_eq!(report.checks[1].status, CheckStatus::Error);
+        assert!(report.has_errors());
+        // Nothing was created while checking
+        assert!(!directory.join("missing.db").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn the_report_lists_every_check_with_its_hint() {
+        let report = DoctorReport { checks: vec![
+            CheckResult::ok("schema", "every table is current"),
+            CheckResult::warning("rows", "the database is empty", "import an extract"),
+        ] };
+        assert!(!report.has_errors());
+        assert_eq!(report.to_string(), "[ok] schema: every table is current\n[warning] rows: the database is empty\n    import an extract\n");
+
+        let report = DoctorReport { checks: vec![CheckResult::error("textures", "icon.png: invalid", "replace it")] };
+        assert!(report.has_errors());
+        assert!(report.to_string().starts_with("[ERROR] textures: "));
+    }
+}
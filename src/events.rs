@@ -0,0 +1,145 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+use tracing::warn;
+use winit::event_loop::EventLoopProxy;
+
+use crate::cancel::OperationError;
+use crate::coastline::LandWaterGrid;
+use crate::database::{Marker, WayBbox};
+use crate::fetcher::ImportStats;
+use crate::geo::BBox;
+use crate::history::Viewport;
+use crate::inspect::Inspection;
+use crate::osm_entities::{RenderableWay, SimpleNode};
+use crate::routing::{ReachGrid, Route};
+use crate::stats::ViewportStats;
+use crate::status::StatusLevel;
+use crate::tiles::Tile;
+use crate::utils::MapsType;
+
+/// The most events `EventQueue::drain` hands out at once, so a burst of prefetched tiles
+/// is spread over several frames instead of stalling one.
+pub const MAX_EVENTS_PER_FRAME: usize = 64;
+
+/// What a background task reports to the event loop.
+///
+/// Background tasks never touch the `State` of the window. They send what they produced as
+/// an event, which the event loop applies in `update`, so no lock is ever held while rendering.
+#[derive(Debug)]
+pub enum AppEvent {
+    /// The ways of the database were loaded anew, e.g. after an import.
+    WaysLoaded(Vec<RenderableWay>),
+    /// The bounding boxes of the ways in the viewport were fetched, to show as placeholders
+    /// until the ways are loaded and drawn, see `Skeleton`.
+    SkeletonLoaded(Vec<WayBbox>),
+    /// The lines of the administrative boundaries and the areas of the multipolygons were
+    /// loaded anew, e.g. after an import.
+    RelationWaysLoaded(Vec<RenderableWay>),
+    /// The grid telling land from water was fetched anew after an import, `None` if the data
+    /// has none.
+    LandWaterLoaded(Option<LandWaterGrid>),
+    /// The extent of the imported data was fetched anew, e.g. after an import.
+    DataExtentLoaded(Option<BBox>),
+    /// The nodes in view to draw as dots were fetched, with how many are in view. Those of an
+    /// outdated fetch have an older `generation`, see `update_node_dots`.
+    NodeDotsLoaded { generation: u64, result: Result<(Vec<SimpleNode>, usize), sqlx::Error> },
+    /// The markers around the viewport were fetched. Those of an outdated fetch have an
+    /// older `generation`, see `fetch_markers`.
+    MarkersLoaded { generation: u64, result: Result<Vec<Marker>, sqlx::Error> },
+    /// A marker asked for with `DatabaseWrite::AddMarker` was stored, the result holds its id.
+    MarkerAdded { lat: f64, lon: f64, label: String, result: Result<i64, sqlx::Error> },
+    /// A marker asked for with `DatabaseWrite::DeleteMarker` was deleted, the result tells
+    /// whether it was still there.
+    MarkerDeleted { id: i64, result: Result<bool, sqlx::Error> },
+    /// The element at a point the user asked about was fetched for the inspection panel,
+    /// `None` if there is no element there or it is not stored. Those of an outdated
+    /// request have an older `generation`, see `start_inspection`.
+    InspectionLoaded { generation: u64, element: Option<(MapsType, i64)>, result: Result<Option<Inspection>, sqlx::Error> },
+    /// A prefetched tile is built. Tiles of an outdated request have an older `generation`.
+    TileReady { generation: u64, tile: Tile },
+    /// A message for the status line.
+    Status(StatusLevel, String),
+    /// An import finished, or failed with the given message.
+    ///
+    /// # Fields
+    /// * `what` - What was imported, e.g. `the viewport` or the path of a file.
+    ImportFinished { what: String, result: Result<ImportStats, String> },
+    /// A watched map file appeared or changed, see `MapFileWatcher`.
+    MapFileChanged(PathBuf),
+    /// The statistics of a viewport the camera settled on are computed. `operation` is the
+    /// id of the computation, see `Operations::finish`.
+    ViewportStatsReady { operation: u64, viewport: Viewport, stats: ViewportStats },
+    /// The area reachable from a point is computed, `None` if no road was near the point.
+    IsochroneReady { operation: u64, result: Result<Option<ReachGrid>, OperationError> },
+    /// The fastest route and its alternatives are found, nothing if no road was near either
+    /// end or no route connects them.
+    RoutesReady { operation: u64, result: Result<Vec<Route>, OperationError> },
+}
+
+/// Sends events to the event loop and wakes it up, so it does not have to redraw
+/// continuously to notice them. Can be cloned into as many tasks as needed.
+///
+/// # Fields
+/// * `sender` - The channel the events are queued on.
+/// * `waker` - Wakes the event loop, or `None` without one, e.g. when importing from the command line.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<AppEvent>,
+    waker: Option<EventLoopProxy<()>>,
+}
+
+impl EventSender {
+    /// Queues an event and wakes the event loop.
+    ///
+    /// ## Returns
+    /// * Whether the event was queued, false once the event loop is gone.
+    pub fn send(&self, event: AppEvent) -> bool {
+        if self.sender.send(event).is_err() {
+            return false;
+        }
+
+        if let Some(waker) = &self.waker {
+            if waker.send_event(()).is_err() {
+                warn!("could not wake the event loop, it has stopped");
+            }
+        }
+        true
+    }
+}
+
+/// The receiving end of the events, owned by the event loop.
+pub struct EventQueue {
+    receiver: Receiver<AppEvent>,
+}
+
+impl EventQueue {
+    /// Takes the events queued so far, without waiting for more.
+    ///
+    /// ## Arguments
+    /// * `max` - The most events to take. The rest stay queued for the next call.
+    ///
+    /// ## Returns
+    /// * The events in the order they were sent.
+    pub fn drain(&self, max: usize) -> Vec<AppEvent> {
+        let mut events = Vec::new();
+
+        while events.len() < max {
+            match self.receiver.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            }
+        }
+
+        events
+    }
+}
+
+/// Creates the channel background tasks report to the event loop through.
+///
+/// ## Arguments
+/// * `waker` - Wakes the event loop once an event is sent, or `None` to only queue the events.
+pub fn event_channel(waker: Option<EventLoopProxy<()>>) -> (EventSender, EventQueue) {
+    let (sender, receiver) = mpsc::channel();
+    (EventSender { sender, waker }, EventQueue { receiver })
+}
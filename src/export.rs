@@ -0,0 +1,284 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use quick_xml::escape::escape;
+
+use crate::coastline::Polygon;
+use crate::geo::format_distance;
+use crate::osm_entities::SimpleNode;
+use crate::routing::{Instruction, Maneuver, RouteSummary};
+
+/// The file formats a route can be saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RouteFormat {
+    #[default]
+    Gpx,
+    GeoJson,
+}
+
+impl FromStr for RouteFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "gpx" => Ok(RouteFormat::Gpx),
+            "geojson" | "json" => Ok(RouteFormat::GeoJson),
+            other => Err(format!("unknown route format '{}', expected 'gpx' or 'geojson'", other)),
+        }
+    }
+}
+
+/// Writes a route in the given format.
+pub fn export_route(format: RouteFormat, name: &str, nodes: &[SimpleNode], summary: Option<&RouteSummary>, instructions: &[Instruction], out: impl Write) -> io::Result<()> {
+    match format {
+        RouteFormat::Gpx => export_route_gpx(name, nodes, summary, instructions, out),
+        RouteFormat::GeoJson => export_route_geojson(name, nodes, summary, instructions, out),
+    }
+}
+
+/// Writes a route as a GPX 1.1 document. The nodes are written twice, as a `<rte>` for
+/// navigation devices and as a `<trk>` for viewers that only show tracks.
+///
+/// ## Arguments
+/// * `name` - The name of the route, escaped as needed.
+/// * `nodes` - The nodes along the route, in travel order.
+/// * `summary` - The distance and time of the route, written as its description if given.
+/// * `instructions` - The turn-by-turn instructions, written as the description of the route
+///   point they happen at.
+/// * `out` - Where to write the document.
+pub fn export_route_gpx(name: &str, nodes: &[SimpleNode], summary: Option<&RouteSummary>, instructions: &[Instruction], mut out: impl Write) -> io::Result<()> {
+    let name = escape(name);
+    let description = summary.map(|summary| format!("{} in {:.0} min", format_distance(summary.distance_m), summary.time_s / 60.0));
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<gpx version="1.1" creator="GoogleMapsClone" xmlns="http://www.topografix.com/GPX/1/1">"#)?;
+
+    writeln!(out, "  <rte>")?;
+    writeln!(out, "    <name>{}</name>", name)?;
+    if let Some(description) = &description {
+        writeln!(out, "    <desc>{}</desc>", escape(description))?;
+    }
+    for (index, node) in nodes.iter().enumerate() {
+        match instructions.iter().find(|instruction| instruction.path_index == index) {
+            Some(instruction) => {
+                writeln!(out, r#"    <rtept lat="{:.7}" lon="{:.7}">"#, node.lat, node.lon)?;
+                writeln!(out, "      <desc>{}</desc>", escape(&instruction.to_string()))?;
+                writeln!(out, "    </rtept>")?;
+            }
+            None => writeln!(out, r#"    <rtept lat="{:.7}" lon="{:.7}"/>"#, node.lat, node.lon)?,
+        }
+    }
+    writeln!(out, "  </rte>")?;
+
+    writeln!(out, "  <trk>")?;
+    writeln!(out, "    <name>{}</name>", name)?;
+    writeln!(out, "    <trkseg>")?;
+    for node in nodes {
+        writeln!(out, r#"      <trkpt lat="{:.7}" lon="{:.7}"/>"#, node.lat, node.lon)?;
+    }
+    writeln!(out, "    </trkseg>")?;
+    writeln!(out, "  </trk>")?;
+
+    writeln!(out, "</gpx>")?;
+    out.flush()
+}
+
+/// Writes a route as a GeoJSON `Feature` with a `LineString` geometry. The properties hold
+/// the name, the total distance in meters and time in seconds if a summary is given, and the
+/// turn-by-turn instructions with the index of the coordinate they happen at.
+pub fn export_route_geojson(name: &str, nodes: &[SimpleNode], summary: Option<&RouteSummary>, instructions: &[Instruction], mut out: impl Write) -> io::Result<()> {
+    write!(out, r#"{{"type":"Feature","properties":{{"name":{}"#, json_string(name))?;
+    if let Some(summary) = summary {
+        write!(out, r#","distance_m":{:.1},"time_s":{:.1}"#, summary.distance_m, summary.time_s)?;
+    }
+    if !instructions.is_empty() {
+        write!(out, r#","instructions":["#)?;
+        for (index, instruction) in instructions.iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            write!(out, r#"{}{{"maneuver":"{}""#, separator, instruction.maneuver.as_str())?;
+            if let Maneuver::RoundaboutExit(exit) = instruction.maneuver {
+                write!(out, r#","exit":{}"#, exit)?;
+            }
+            if let Some(street) = &instruction.street {
+                write!(out, r#","street":{}"#, json_string(street))?;
+            }
+            write!(out, r#","distance_m":{:.1},"index":{},"text":{}}}"#, instruction.distance_m, instruction.path_index, json_string(&instruction.to_string()))?;
+        }
+        write!(out, "]")?;
+    }
+
+    // GeoJSON positions are (lon, lat)
+    write!(out, r#"}},"geometry":{{"type":"LineString","coordinates":["#)?;
+    for (index, node) in nodes.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(out, "{}[{:.7},{:.7}]", separator, node.lon, node.lat)?;
+    }
+    writeln!(out, "]}}}}")?;
+    out.flush()
+}
+
+/// Writes the area reachable within a time limit as a GeoJSON `Feature` with a
+/// `MultiPolygon` geometry, a polygon without holes for every outline. The properties hold
+/// the name and the time limit in seconds.
+///
+/// ## Arguments
+/// * `outlines` - Closed rings of `(lat, lon)`, see `ReachGrid::outlines`.
+pub fn export_isochrone_geojson(name: &str, max_seconds: f64, outlines: &[Vec<(f64, f64)>], mut out: impl Write) -> io::Result<()> {
+    write!(out, r#"{{"type":"Feature","properties":{{"name":{},"time_s":{:.1}}}"#, json_string(name), max_seconds)?;
+
+    // GeoJSON positions are (lon, lat)
+    write!(out, r#","geometry":{{"type":"MultiPolygon","coordinates":["#)?;
+    for (index, outline) in outlines.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(out, "{}[[", separator)?;
+        for (index, (lat, lon)) in outline.iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            write!(out, "{}[{:.7},{:.7}]", separator, lon, lat)?;
+        }
+        write!(out, "]]")?;
+    }
+    writeln!(out, "]}}}}")?;
+    out.flush()
+}
+
+/// Writes the water assembled from coastlines as a GeoJSON `Feature` with a `MultiPolygon`
+/// geometry, with the islands as holes. The properties hold the name.
+///
+/// ## Arguments
+/// * `polygons` - The water, see `assemble_coastline`.
+pub fn export_water_geojson(name: &str, polygons: &[Polygon], mut out: impl Write) -> io::Result<()> {
+    write!(out, r#"{{"type":"Feature","properties":{{"name":{}}}"#, json_string(name))?;
+
+    // GeoJSON positions are (lon, lat), and its outlines run counter clockwise and its holes
+    // clockwise, the other way around than the polygons
+    write!(out, r#","geometry":{{"type":"MultiPolygon","coordinates":["#)?;
+    for (index, polygon) in polygons.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        write!(out, "{}[", separator)?;
+        for (index, ring) in std::iter::once(&polygon.outer).chain(&polygon.holes).enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            write!(out, "{}[", separator)?;
+            for (index, (lat, lon)) in ring.iter().rev().enumerate() {
+                let separator = if index == 0 { "" } else { "," };
+                write!(out, "{}[{:.7},{:.7}]", separator, lon, lat)?;
+            }
+            write!(out, "]")?;
+        }
+        write!(out, "]")?;
+    }
+    writeln!(out, "]}}}}")?;
+    out.flush()
+}
+
+/// Quotes a string for JSON, escaping quotes, backslashes and control characters.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quick_xml::events::Event;
+    use quick_xml::Reader;
+    use serde_json::Value;
+
+    use crate::gpx::read_gpx_file;
+
+    const NAME: &str = "Harbour <Café> & \"Station\"";
+
+    fn route() -> (Vec<SimpleNode>, RouteSummary, Vec<Instruction>) {
+        let nodes = (0..5).map(|index| SimpleNode { id: Some(index), lat: 55.0 + index as f64 * 0.001, lon: 12.1234567 }).collect();
+        let summary = RouteSummary { distance_m: 445.3, time_s: 53.4, by_highway: Vec::new() };
+        let instructions = vec![
+            Instruction { maneuver: Maneuver::Depart, street: Some("Quay & Co".to_string()), distance_m: 445.3, path_index: 0 },
+            Instruction { maneuver: Maneuver::Arrive, street: None, distance_m: 0.0, path_index: 4 },
+        ];
+        (nodes, summary, instructions)
+    }
+
+    #[test]
+    fn an_exported_gpx_route_reads_back_as_a_track_with_every_point() {
+        let (nodes, summary, instructions) = route();
+        let mut gpx = Vec::new();
+        export_route_gpx(NAME, &nodes, Some(&summary), &instructions, &mut gpx).unwrap();
+
+        let path = std::env::temp_dir().join(format!("gmc_route_{}.gpx", std::process::id()));
+        std::fs::write(&path, &gpx).unwrap();
+        let tracks = read_gpx_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        let tracks = tracks.unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].name.as_deref(), Some(NAME));
+        assert_eq!(tracks[0].segments.len(), 1);
+        let points: Vec<(f64, f64)> = tracks[0].segments[0].iter().map(|point| (point.lat, point.lon)).collect();
+        assert_eq!(points, nodes.iter().map(|node| (node.lat, node.lon)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn the_gpx_route_is_well_formed_with_a_point_per_node() {
+        let (nodes, summary, instructions) = route();
+        let mut gpx = Vec::new();
+        export_route_gpx(NAME, &nodes, Some(&summary), &instructions, &mut gpx).unwrap();
+
+        let mut reader = Reader::from_reader(gpx.as_slice());
+        reader.config_mut().check_end_names = true;
+        let (mut route_points, mut texts) = (0, Vec::new());
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event_into(&mut buf).unwrap() {
+                Event::Start(ref e) | Event::Empty(ref e) if e.name().as_ref() == b"rtept" => route_points += 1,
+                Event::Text(ref e) => texts.push(e.unescape().unwrap().into_owned()),
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+
+        assert_eq!(route_points, nodes.len());
+        assert!(texts.iter().any(|text| text == NAME));
+        assert!(texts.iter().any(|text| text == "445 m in 1 min"), "{:?}", texts);
+        assert!(texts.iter().any(|text| text.starts_with("Start on Quay & Co")), "{:?}", texts);
+    }
+
+    #[test]
+    fn the_geojson_route_is_a_line_of_lon_lat_positions_with_its_summary() {
+        let (nodes, summary, instructions) = route();
+        let mut geojson = Vec::new();
+        export_route(RouteFormat::GeoJson, NAME, &nodes, Some(&summary), &instructions, &mut geojson).unwrap();
+
+        let feature: Value = serde_json::from_slice(&geojson).unwrap();
+        assert_eq!(feature["properties"]["name"], NAME);
+        assert_eq!(feature["properties"]["distance_m"], 445.3);
+        assert_eq!(feature["properties"]["time_s"], 53.4);
+        assert_eq!(feature["properties"]["instructions"][0]["street"], "Quay & Co");
+        assert_eq!(feature["properties"]["instructions"][1]["index"], 4);
+
+        let coordinates = feature["geometry"]["coordinates"].as_array().unwrap();
+        assert_eq!(coordinates.len(), nodes.len());
+        assert_eq!(coordinates[1], serde_json::json!([12.1234567, 55.001]));
+    }
+
+    #[test]
+    fn control_characters_are_escaped_in_json_strings() {
+        let quoted = json_string("a\"b\\c\nd\u{1}");
+        assert_eq!(quoted, r#""a\"b\\c\nd\u0001""#);
+        assert_eq!(serde_json::from_str::<String>(&quoted).unwrap(), "a\"b\\c\nd\u{1}");
+        assert_eq!("JSON".parse::<RouteFormat>(), Ok(RouteFormat::GeoJson));
+        assert!("kml".parse::<RouteFormat>().is_err());
+    }
+}
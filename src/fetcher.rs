@@ -1,14 +1,104 @@
+use std::fmt;
 use std::fs;
 use std::io::{self, Write};
-use std::time::Instant;
+use std::path::Path;
+use std::time::{Instant, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
 use sqlx::SqlitePool;
 use anyhow::Result;
+use tracing::{debug, debug_span, info, info_span, warn, Instrument};
 
-use crate::database::{insert_node_data, insert_relation_data, insert_way_data};
+use crate::coastline::LandWaterGrid;
+use crate::database::{apply_changeset, decide_import_start, delete_by_source, fetch_coastline_shapes_in_bbox, fetch_data_extent, fetch_element_ids_of_source, fetch_import_phase, find_identical_import, insert_gps_tracks, insert_source_file, is_in_memory, insert_node_data, insert_relation_data, insert_way_data, save_import_phase, save_land_water_grid, split_by_stored_version, update_data_extent, update_node_data, update_relation_data, update_way_data, update_way_geometry, ChangeStats, ControlCharacters, DeletedSource, FileFingerprint, InsertConfig, ImportPhase, ImportStart, ImportTagFilter, TagPolicyStats, VersionSplit};
+use crate::geo::{bbox_of_points, BBox};
+use crate::gpx::read_gpx_file;
+use crate::metrics;
+use crate::simplify::simplify_import;
+use crate::snapshot::{save_snapshot, SNAPSHOT_PATH};
 use crate::osm_entities::{node, relation, way};
-use crate::open_street_map::{read_nodes_from_file, read_relations_from_file, read_ways_from_file};
+use crate::open_street_map::{download_bbox, open_osm_input, read_osc_file, read_osm_input, OsmAction, OverpassConfig, ReadOutcome, TcpHttpClient, MAX_WARNINGS, STDIN_PATH};
 
-fn list_files_in_directory(directory: &str) -> io::Result<Vec<String>> {
+/// Where the map and GPX files to choose from are kept.
+pub const MAPDATA_DIRECTORY: &str = "utils/mapdata/";
+
+/// Options for importing map data, from a file or a download.
+///
+/// # Fields
+/// * `tag_filter` - Which tags are stored, see `ImportTagFilter`.
+/// * `control_characters` - What is done with the control characters in tags, see `ControlCharacters`.
+/// * `force` - Whether a file is imported even if a file with the same contents was imported before.
+/// * `simplify_tolerance_m` - How far in meters the ways may stray from their shape to drop
+///   nodes, see `simplify_import`, or `None` to store every node. Meant for databases only
+///   viewed zoomed out. Ways stored before in the same version keep their nodes.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    pub tag_filter: ImportTagFilter,
+    pub control_characters: ControlCharacters,
+    pub force: bool,
+    pub simplify_tolerance_m: Option<f64>,
+}
+
+/// How many elements an import read and stored.
+///
+/// # Fields
+/// * `source_id` - The id of the import in the `source_file` table, which its elements refer to.
+///   For a file that was skipped, the id of the earlier import of the same contents.
+/// * `skipped` - Whether the file was skipped, as the same contents were imported before.
+/// * `new` - The elements inserted, as they were not stored yet.
+/// * `updated` - The stored elements replaced, as the file holds another version of them.
+/// * `unchanged` - The elements left alone, as they are stored in the same version.
+/// * `tags` - How many tags were cut off or left out on the way in, see `TagPolicy`.
+/// * `elided_nodes` - The nodes dropped from the ways by simplifying them, see
+///   `ImportOptions::simplify_tolerance_m`.
+/// * `resumed_after` - The phase an earlier import of the same contents was cut short after,
+///   if this import went on with it. The elements of the phases it completed are not counted
+///   as new, updated or unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportStats {
+    pub source_id: i64,
+    pub skipped: bool,
+    pub nodes: usize,
+    pub ways: usize,
+    pub relations: usize,
+    pub new: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub tags: TagPolicyStats,
+    pub elided_nodes: usize,
+    pub resumed_after: Option<ImportPhase>,
+}
+
+impl ImportStats {
+    /// Counts how the elements of one type were stored.
+    fn add_split<T>(&mut self, split: &VersionSplit<T>) {
+        self.new += split.new.len();
+        self.updated += split.updated.len();
+        self.unchanged += split.unchanged;
+    }
+}
+
+impl fmt::Display for ImportStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.skipped {
+            return write!(f, "the same contents were imported as import {}", self.source_id);
+        }
+
+        if let Some(phase) = self.resumed_after {
+            write!(f, "resumed import {} after {}: ", self.source_id, phase)?;
+        }
+        write!(f, "{} nodes, {} ways and {} relations, {} new, {} updated and {} unchanged", self.nodes, self.ways, self.relations, self.new, self.updated, self.unchanged)?;
+        if !self.tags.is_empty() {
+            write!(f, " ({})", self.tags)?;
+        }
+        if self.elided_nodes > 0 {
+            write!(f, ", {} nodes simplified away", self.elided_nodes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the names of the files in `directory`, leaving out subdirectories.
+pub fn list_files_in_directory(directory: &str) -> io::Result<Vec<String>> {
     let mut files = Vec::new();
     for entry in fs::read_dir(directory)? {
         let entry = entry?;
@@ -25,7 +115,7 @@ fn list_files_in_directory(directory: &str) -> io::Result<Vec<String>> {
 }
 
 fn choose_file(files: &[String]) -> Option<String> {
-    println!("Available map files:");
+    println!("Available map and GPX files:");
     for (index, file) in files.iter().enumerate() {
         println!("{}: {}", index + 1, file);
     }
@@ -40,62 +130,851 @@ fn choose_file(files: &[String]) -> Option<String> {
     }
 }
 
-async fn process_map_file(pool: &SqlitePool, file_path: &str) -> Result<()> {
-    let full_path = format!("utils/mapdata/{}", file_path);
+/// Logs how many elements were read and skipped, along with the recorded warnings.
+fn report_read_outcome<T>(element: &str, outcome: ReadOutcome<T>) -> Vec<T> {
+    info!(element, count = outcome.items.len(), skipped = outcome.skipped, "read");
+
+    for warning in &outcome.warnings {
+        warn!(element, "{}", warning);
+    }
+    if outcome.warnings.len() == MAX_WARNINGS {
+        warn!(element, "only the first {} warnings are shown", MAX_WARNINGS);
+    }
+
+    outcome.items
+}
+
+/// The elements read from an OSM XML or JSON file, before they are imported.
+struct MapFileData {
+    nodes: Vec<node::Node>,
+    ways: Vec<way::Way>,
+    relations: Vec<relation::Relation>,
+}
 
-    // Read nodes from file
-    println!("Reading data");
-    let start = Instant::now();
-    println!("Reading nodes");
-    let nodes: Vec<node::Node> = match read_nodes_from_file(&full_path) {
-        Ok(nodes) => nodes,
-        Err(error) => panic!("There was a problem reading the nodes: {:?}", error),
+/// Reads the elements of a map file, or of standard input for `STDIN_PATH`, logging what
+/// was skipped. The format is told from the contents, see `read_osm_input`, so the file is
+/// read in a single pass whatever its name.
+fn read_map_file(path: &str) -> Result<MapFileData> {
+    let _span = info_span!("read", file = %path).entered();
+    let started = Instant::now();
+
+    let input = open_osm_input(path).map_err(|error| anyhow::anyhow!("Could not open {}: {}", path, error))?;
+    let data = match read_osm_input(input) {
+        Ok(data) => data,
+        Err(error) => return Err(anyhow::anyhow!("There was a problem reading {}: {}", input_name(path), error)),
     };
-    println!("Read {} nodes", nodes.len());
 
-    // Read ways from file
-    println!("Reading ways");
-    let ways: Vec<way::Way> = match read_ways_from_file(&full_path) {
-        Ok(ways) => ways,
-        Err(error) => panic!("There was a problem reading the ways: {:?}", error),
+    metrics::IMPORT_READ_SECONDS.observe_since(started);
+    Ok(MapFileData {
+        nodes: report_read_outcome("nodes", data.nodes),
+        ways: report_read_outcome("ways", data.ways),
+        relations: report_read_outcome("relations", data.relations),
+    })
+}
+
+/// How a map file is named in messages and in `source_file`, standard input included.
+fn input_name(path: &str) -> &str {
+    if path == STDIN_PATH { "standard input" } else { path }
+}
+
+/// Fingerprints a file, reading it in chunks so files larger than memory are no problem.
+pub fn fingerprint_file(path: &str) -> io::Result<FileFingerprint> {
+    let mut file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    // A modification time before 1970 or unknown to the platform is recorded as 0
+    let modified_at = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_secs() as i64);
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    let checksum = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    Ok(FileFingerprint { size: metadata.len() as i64, modified_at, checksum })
+}
+
+/// Looks for an earlier import of the same contents, which makes importing a file again a
+/// no-op once that import is done, or goes on with it if it was cut short.
+///
+/// ## Returns
+/// * How to import the file, see `decide_import_start`.
+async fn find_earlier_import(pool: &SqlitePool, path: &str, fingerprint: &FileFingerprint, options: &ImportOptions) -> Result<ImportStart> {
+    let earlier = match find_identical_import(pool, fingerprint).await? {
+        Some(source) => {
+            let phase = fetch_import_phase(pool, source.id).await?;
+            Some((source, phase))
+        }
+        None => None,
     };
-    println!("Read {} ways", ways.len());
 
-    // Read relations from file
-    println!("Reading relations");
-    let relations: Vec<relation::Relation> = match read_relations_from_file(&full_path) {
-        Ok(relations) => relations,
-        Err(error) => panic!("There was a problem reading the relations: {:?}", error),
+    let start = decide_import_start(earlier.as_ref().map(|(source, phase)| (source.id, *phase)), options.force);
+    match (start, earlier) {
+        (ImportStart::Skip { source_id }, Some((source, _))) => {
+            info!(file = %path, source_id, imported_as = %source.filename, imported_at = %source.imported_at, "skipping, the same contents were imported before, --force imports them again");
+            metrics::SKIPPED_FILES.add(1);
+        }
+        (ImportStart::Resume { source_id, completed }, _) => {
+            info!(file = %path, source_id, %completed, "resuming the import of the same contents, it was cut short");
+        }
+        _ => {}
+    }
+    Ok(start)
+}
+
+/// The stats of a file left alone, as the import of the same contents with the id is done.
+fn skipped_import(source_id: i64) -> ImportStats {
+    ImportStats { source_id, skipped: true, ..Default::default() }
+}
+
+/// Reads an OSM XML or JSON file and imports its elements, unless the same contents were
+/// imported before, see `ImportOptions::force`.
+///
+/// `STDIN_PATH` reads standard input instead. It cannot be fingerprinted before it is read,
+/// so it is always imported, like a download.
+///
+/// A file that cannot be read is an error rather than a panic, as the watcher imports files
+/// while the map is open.
+pub async fn process_map_file(pool: &SqlitePool, path: &str, options: &ImportOptions) -> Result<ImportStats> {
+    if path == STDIN_PATH {
+        let data = read_map_file(path)?;
+        return import_osm_data(pool, input_name(path), None, data.nodes, data.ways, data.relations, options, None).await;
+    }
+
+    let fingerprint = fingerprint_file(path).map_err(|error| anyhow::anyhow!("Could not read {}: {}", path, error))?;
+    let resume = match find_earlier_import(pool, path, &fingerprint, options).await? {
+        ImportStart::Fresh => None,
+        ImportStart::Skip { source_id } => return Ok(skipped_import(source_id)),
+        ImportStart::Resume { source_id, completed } => Some((source_id, completed)),
     };
-    println!("Read {} relations", relations.len());
-    let duration = start.elapsed();
-    println!("Read data in {:?}", duration);
-
-    // Measure the time taken to insert the data
-    println!("Inserting data");
-    let start = Instant::now();
-    insert_node_data(&pool, nodes).await?;
-    println!("Inserted nodes");
-    insert_way_data(&pool, ways).await?;
-    println!("Inserted ways");
-    insert_relation_data(&pool, relations).await?;
-    println!("Inserted relations");
-    let duration = start.elapsed();
-    println!("Inserted data in {:?}", duration);
-    println!("Done with insertion");
 
-    Ok(())
+    // Reading is synchronous, so the span is only entered around it and not across the import
+    let data = read_map_file(path)?;
+    import_osm_data(pool, path, Some(&fingerprint), data.nodes, data.ways, data.relations, options, resume).await
+}
+
+/// What `import_map_directory` did with the files of a directory.
+///
+/// # Fields
+/// * `imported` - The files imported, with what they held, in the order they were imported.
+///   The files skipped as their contents were imported before come first.
+/// * `failed` - The file that could not be read or imported, with why, which ended the import.
+/// * `not_imported` - The files after the one that failed, left alone.
+#[derive(Debug, Default)]
+pub struct DirectoryImport {
+    pub imported: Vec<(String, ImportStats)>,
+    pub failed: Option<(String, anyhow::Error)>,
+    pub not_imported: Vec<String>,
+}
+
+impl fmt::Display for DirectoryImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (path, stats) in &self.imported {
+            let verb = if stats.skipped { "Skipped" } else { "Imported" };
+            writeln!(f, "{} {}: {}", verb, path, stats)?;
+        }
+        if let Some((path, error)) = &self.failed {
+            writeln!(f, "Could not import {}: {:#}", path, error)?;
+        }
+        for path in &self.not_imported {
+            writeln!(f, "Not imported {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the map files of a directory in the order `import_map_directory` imports them, by
+/// name. GPS tracks and OsmChange files are left out, as they are not imported as maps.
+pub fn list_map_files(directory: &str) -> io::Result<Vec<String>> {
+    let mut files: Vec<String> = list_files_in_directory(directory)?.into_iter()
+        .filter(|file| {
+            let file = file.to_lowercase();
+            !file.ends_with(".gpx") && !file.ends_with(".osc")
+        })
+        .map(|file| Path::new(directory).join(file).to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// A file `import_map_directory` imports: its path, its fingerprint, and the source and
+/// phase to resume from if an import of the same contents was cut short.
+type FileToImport = (String, FileFingerprint, Option<(i64, ImportPhase)>);
+
+/// Imports every map file of a directory, see `list_map_files`, reading the next file
+/// while the one before it is inserted. Files whose contents were imported before are
+/// skipped, see `ImportOptions::force`.
+///
+/// A task reads the files in order and hands them over one at a time, so at most one file
+/// waits read while another is inserted. The first file that cannot be read or inserted ends
+/// the import, the files before it stay imported and the files after it are not imported, so
+/// a broken extract is noticed before anything is imported on top of it.
+///
+/// ## Returns
+/// * What was imported, and which file failed if one did.
+pub async fn import_map_directory(pool: &SqlitePool, directory: &str, options: &ImportOptions) -> Result<DirectoryImport> {
+    let files = list_map_files(directory)?;
+    info!(directory, files = files.len(), "importing every map file");
+
+    // The files imported before are left out before anything is read. A file that cannot be
+    // fingerprinted cannot be read either, so it ends the import after the files before it
+    let mut report = DirectoryImport::default();
+    let mut files_to_import: Vec<FileToImport> = Vec::new();
+    let mut unreadable: Option<(usize, anyhow::Error)> = None;
+    for (index, path) in files.iter().enumerate() {
+        let fingerprint = match fingerprint_file(path) {
+            Ok(fingerprint) => fingerprint,
+            Err(error) => {
+                unreadable = Some((index, anyhow::anyhow!("Could not read {}: {}", path, error)));
+                break;
+            }
+        };
+        match find_earlier_import(pool, path, &fingerprint, options).await? {
+            ImportStart::Fresh => files_to_import.push((path.clone(), fingerprint, None)),
+            ImportStart::Skip { source_id } => report.imported.push((path.clone(), skipped_import(source_id))),
+            ImportStart::Resume { source_id, completed } => files_to_import.push((path.clone(), fingerprint, Some((source_id, completed)))),
+        }
+    }
+
+    // Reading is synchronous, so it runs on a thread of its own. It stops once the receiver
+    // is dropped, which ends the loop below
+    let (sender, mut receiver) = tokio::sync::mpsc::channel::<(FileToImport, Result<MapFileData>)>(1);
+    let reader_files = files_to_import.clone();
+    let reader = tokio::task::spawn_blocking(move || {
+        for (path, fingerprint, resume) in reader_files {
+            let data = read_map_file(&path);
+            let failed = data.is_err();
+            if sender.blocking_send(((path, fingerprint, resume), data)).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let mut done = 0;
+    while let Some(((path, fingerprint, resume), data)) = receiver.recv().await {
+        done += 1;
+        let result = match data {
+            Ok(data) => import_osm_data(pool, &path, Some(&fingerprint), data.nodes, data.ways, data.relations, options, resume).await,
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(stats) => {
+                info!(file = %path, source_id = stats.source_id, %stats, "imported");
+                report.imported.push((path, stats));
+            }
+            Err(error) => {
+                warn!(file = %path, error = %format!("{:#}", error), "could not import, not importing the files after it");
+                report.failed = Some((path, error));
+                break;
+            }
+        }
+    }
+    drop(receiver);
+    reader.await?;
+
+    report.not_imported = files_to_import[done..].iter().map(|(path, _, _)| path.clone()).collect();
+    if let Some((index, error)) = unreadable {
+        if report.failed.is_none() {
+            warn!(file = %files[index], error = %format!("{:#}", error), "could not import, not importing the files after it");
+            report.failed = Some((files[index].clone(), error));
+        } else {
+            report.not_imported.push(files[index].clone());
+        }
+        report.not_imported.extend_from_slice(&files[index + 1..]);
+    }
+    Ok(report)
+}
+
+/// Writes the snapshot of the renderable ways anew after the database changed. The change
+/// itself succeeded, so a failure here is only logged, it costs a slower start.
+///
+/// The snapshot belongs to the database file, so nothing is written for a database in memory.
+async fn refresh_snapshot(pool: &SqlitePool) {
+    match is_in_memory(pool).await {
+        Ok(true) => {
+            debug!("not saving a snapshot of a database in memory");
+            return;
+        }
+        Ok(false) => (),
+        Err(error) => {
+            warn!(%error, "could not tell where the database is, not saving a snapshot");
+            return;
+        }
+    }
+
+    match save_snapshot(pool, None, SNAPSHOT_PATH).instrument(debug_span!("save_snapshot")).await {
+        Ok(count) => info!(path = SNAPSHOT_PATH, count, "saved snapshot"),
+        Err(error) => warn!(path = SNAPSHOT_PATH, %error, "could not save the snapshot"),
+    }
+}
+
+/// Builds the grid telling land from water anew from the coastlines of the data and stores it,
+/// see `LandWaterGrid`. Like the snapshot, it only matters for drawing, so a failure is logged
+/// and the map goes without it.
+///
+/// ## Returns
+/// * The grid stored, or `None` if the data has no coastline or the grid could not be built.
+pub async fn refresh_land_water_grid(pool: &SqlitePool) -> Option<LandWaterGrid> {
+    let started = Instant::now();
+    let built = async {
+        let Some(extent) = fetch_data_extent(pool).await? else {
+            return Ok(None);
+        };
+        let coastlines = fetch_coastline_shapes_in_bbox(pool, &extent).await?;
+        let grid = LandWaterGrid::build(&coastlines, &extent);
+        save_land_water_grid(pool, grid.as_ref()).await?;
+        Ok::<_, sqlx::Error>(grid)
+    };
+
+    match built.await {
+        Ok(grid) => {
+            info!(cells = grid.as_ref().map_or(0, |grid| grid.cells.len()), elapsed = ?started.elapsed(), "built the land and water grid");
+            grid
+        }
+        Err(error) => {
+            warn!(%error, "could not build the land and water grid");
+            None
+        }
+    }
+}
+
+/// Stores the elements read from a file or a download, followed by the way bounding boxes,
+/// and regenerates the snapshot of the renderable ways and the land and water grid.
+/// Every phase is a span, so its time is logged once it is done.
+///
+/// Elements not stored yet are inserted and elements stored in another version replace the
+/// stored ones, see `split_by_stored_version`. Elements stored in the same version are not
+/// written at all, so importing a changed file only writes what changed in it.
+///
+/// Every phase completed is recorded in `source_file`, see `ImportPhase`, so an import cut
+/// short is resumed from the phase it was in. The elements that phase stored already are
+/// stored in the same version by then, so they are inserted again instead of left alone,
+/// in case their tags, node references or members were not stored yet. The inserts ignore
+/// what is stored, so nothing is stored twice.
+///
+/// ## Arguments
+/// * `source` - The file or download the elements came from, recorded in `source_file`.
+/// * `fingerprint` - What the file held, `None` for a download.
+/// * `options` - Which tags are stored and how much the ways are simplified.
+/// * `resume` - The id of the import of the same contents to go on with and the phase it
+///   completed, or `None` to record a new import.
+#[allow(clippy::too_many_arguments)]
+async fn import_osm_data(pool: &SqlitePool, source: &str, fingerprint: Option<&FileFingerprint>, nodes: Vec<node::Node>, ways: Vec<way::Way>, relations: Vec<relation::Relation>, options: &ImportOptions, resume: Option<(i64, ImportPhase)>) -> Result<ImportStats> {
+    let span = info_span!("import", source, nodes = nodes.len(), ways = ways.len(), relations = relations.len());
+    let points: Vec<(f64, f64)> = nodes.iter().map(|node| (node.lat, node.lon)).collect();
+
+    async {
+        let mut config = InsertConfig::detect(pool).await?;
+        config.tag_policy.tag_filter = options.tag_filter.clone();
+        config.tag_policy.control_characters = options.control_characters;
+        debug!(max_variable_number = config.max_variable_number, tag_filter = ?config.tag_policy.tag_filter, control_characters = ?config.tag_policy.control_characters, "inserting");
+
+        let (source_id, completed) = match resume {
+            Some((source_id, completed)) => (source_id, completed),
+            None => (insert_source_file(pool, source, fingerprint).await?, ImportPhase::Started),
+        };
+        let mut stats = ImportStats { source_id, nodes: nodes.len(), ways: ways.len(), relations: relations.len(), resumed_after: resume.map(|(_, completed)| completed), ..Default::default() };
+        metrics::IMPORTED_FILES.add(1);
+
+        let (nodes, ways) = match options.simplify_tolerance_m {
+            Some(tolerance_m) => {
+                let (nodes, ways, simplified) = debug_span!("simplify", tolerance_m).in_scope(|| simplify_import(nodes, ways, &relations, tolerance_m));
+                info!(tolerance_m, elided_nodes = simplified.elided_nodes, skipped_nodes = simplified.skipped_nodes, "simplified the ways");
+                stats.elided_nodes = simplified.elided_nodes;
+                (nodes, ways)
+            }
+            None => (nodes, ways),
+        };
+
+        // The nodes replaced move the ways referring to them. Which they were is not known once
+        // an earlier run inserted them, so then every node of the import is taken to be
+        let mut replaced_node_ids: Vec<i64> = if completed >= ImportPhase::NodesInserted {
+            nodes.iter().map(|node| node.id).collect()
+        } else {
+            Vec::new()
+        };
+
+        if completed < ImportPhase::NodesInserted {
+            let started = Instant::now();
+            let stored = stored_by_import(pool, "node", source_id, resume.is_some(), &nodes, |node| node.id).await?;
+            let mut nodes = split_by_stored_version(pool, "node", nodes, |node| (node.id, node.version), &config).await?;
+            stats.add_split(&nodes);
+            metrics::IMPORTED_NODES.add(nodes.new.len() + nodes.updated.len());
+            metrics::UNCHANGED_ELEMENTS.add(nodes.unchanged);
+            nodes.new.extend(stored);
+            let count = nodes.new.len();
+            stats.tags += insert_node_data(pool, nodes.new, Some(source_id), &config).instrument(debug_span!("insert_nodes", count)).await?;
+            let count = nodes.updated.len();
+            replaced_node_ids = nodes.updated.iter().map(|node| node.id).collect();
+            stats.tags += update_node_data(pool, nodes.updated, Some(source_id), &config).instrument(debug_span!("update_nodes", count)).await?;
+            // The viewer shades everything beyond the imported data
+            if let Some(imported) = bbox_of_points(&points) {
+                update_data_extent(pool, &imported).await?;
+            }
+            save_import_phase(pool, source_id, ImportPhase::NodesInserted).await?;
+            metrics::IMPORT_NODES_SECONDS.observe_since(started);
+        }
+
+        if completed < ImportPhase::WaysInserted {
+            let started = Instant::now();
+            let stored = stored_by_import(pool, "way", source_id, resume.is_some(), &ways, |way| way.id).await?;
+            let mut ways = split_by_stored_version(pool, "way", ways, |way| (way.id, way.version), &config).await?;
+            stats.add_split(&ways);
+            metrics::IMPORTED_WAYS.add(ways.new.len() + ways.updated.len());
+            metrics::UNCHANGED_ELEMENTS.add(ways.unchanged);
+            ways.new.extend(stored);
+            let way_ids: Vec<i64> = ways.new.iter().chain(&ways.updated).map(|way| way.id).collect();
+            let count = ways.new.len();
+            stats.tags += insert_way_data(pool, ways.new, Some(source_id), &config).instrument(debug_span!("insert_ways", count)).await?;
+            let count = ways.updated.len();
+            stats.tags += update_way_data(pool, ways.updated, Some(source_id), &config).instrument(debug_span!("update_ways", count)).await?;
+            metrics::IMPORT_WAYS_SECONDS.observe_since(started);
+            let started = Instant::now();
+            let count = way_ids.len();
+            update_way_geometry(pool, &way_ids, &replaced_node_ids, &config).instrument(debug_span!("update_way_geometry", count)).await?;
+            save_import_phase(pool, source_id, ImportPhase::WaysInserted).await?;
+            metrics::IMPORT_GEOMETRY_SECONDS.observe_since(started);
+        }
+
+        if completed < ImportPhase::RelationsInserted {
+            let started = Instant::now();
+            let stored = stored_by_import(pool, "relation", source_id, resume.is_some(), &relations, |relation| relation.id).await?;
+            let mut relations = split_by_stored_version(pool, "relation", relations, |relation| (relation.id, relation.version), &config).await?;
+            stats.add_split(&relations);
+            metrics::IMPORTED_RELATIONS.add(relations.new.len() + relations.updated.len());
+            metrics::UNCHANGED_ELEMENTS.add(relations.unchanged);
+            relations.new.extend(stored);
+            let count = relations.new.len();
+            stats.tags += insert_relation_data(pool, relations.new, Some(source_id), &config).instrument(debug_span!("insert_relations", count)).await?;
+            let count = relations.updated.len();
+            stats.tags += update_relation_data(pool, relations.updated, Some(source_id), &config).instrument(debug_span!("update_relations", count)).await?;
+            save_import_phase(pool, source_id, ImportPhase::RelationsInserted).await?;
+            metrics::IMPORT_RELATIONS_SECONDS.observe_since(started);
+        }
+        if stats.tags.filtered_tags > 0 {
+            info!(filtered_tags = stats.tags.filtered_tags, "left out the tags the tag filter does not keep");
+        }
+        if stats.tags.has_cleanups() {
+            warn!(truncated_values = stats.tags.truncated_values, normalized_tags = stats.tags.normalized_tags, rejected_keys = stats.tags.rejected_keys, "cleaned up tags");
+        }
+
+        // The snapshot would show the map as it was before the import, so it is written anew
+        let started = Instant::now();
+        refresh_snapshot(pool).await;
+        metrics::IMPORT_SNAPSHOT_SECONDS.observe_since(started);
+        refresh_land_water_grid(pool).await;
+        save_import_phase(pool, source_id, ImportPhase::Finalized).await?;
+
+        Ok(stats)
+    }
+    .instrument(span)
+    .await
+}
+
+/// Picks the elements of a resumed import that it stored before it was cut short, to insert
+/// again, see `import_osm_data`. A new import stored none of them.
+///
+/// ## Arguments
+/// * `table` - The table of the elements, `node`, `way` or `relation`.
+/// * `resumed` - Whether the import is resumed.
+async fn stored_by_import<T: Clone>(pool: &SqlitePool, table: &str, source_id: i64, resumed: bool, elements: &[T], id: impl Fn(&T) -> i64) -> Result<Vec<T>> {
+    if !resumed {
+        return Ok(Vec::new());
+    }
+
+    let stored = fetch_element_ids_of_source(pool, table, source_id).await?;
+    Ok(elements.iter().filter(|element| stored.contains(&id(element))).cloned().collect())
+}
+
+/// Applies an OsmChange (`.osc`) file to the database and regenerates the snapshot of the
+/// renderable ways and the land and water grid.
+///
+/// ## Arguments
+/// * `pool` - The database to change.
+/// * `path` - The path to the OsmChange file.
+///
+/// ## Returns
+/// * What was changed and skipped.
+pub async fn apply_diff_file(pool: &SqlitePool, path: &str) -> Result<ChangeStats> {
+    let changes = info_span!("read", file = %path)
+        .in_scope(|| read_osc_file(path))
+        .map_err(|error| anyhow::anyhow!("Could not read {}: {}", path, error))?;
+
+    info!(nodes = changes.nodes.len(), ways = changes.ways.len(), relations = changes.relations.len(), skipped = changes.skipped, "read changes");
+    for warning in &changes.warnings {
+        warn!("{}", warning);
+    }
+    if changes.warnings.len() == MAX_WARNINGS {
+        warn!("only the first {} warnings are shown", MAX_WARNINGS);
+    }
+
+    let points: Vec<(f64, f64)> = changes.nodes.iter()
+        .filter(|change| change.action != OsmAction::Delete)
+        .map(|change| (change.element.lat, change.element.lon))
+        .collect();
+    let stats = apply_changeset(pool, changes).instrument(info_span!("apply_changes", file = %path)).await?;
+    if let Some(changed) = bbox_of_points(&points) {
+        update_data_extent(pool, &changed).await?;
+    }
+
+    refresh_snapshot(pool).await;
+    refresh_land_water_grid(pool).await;
+
+    Ok(stats)
+}
+
+/// Deletes the elements of one import, see `delete_by_source`, and regenerates the snapshot
+/// of the renderable ways and the land and water grid.
+///
+/// ## Arguments
+/// * `pool` - The database to delete from.
+/// * `source_id` - The id of the import in the `source_file` table.
+///
+/// ## Returns
+/// * How many elements were deleted.
+pub async fn delete_source(pool: &SqlitePool, source_id: i64) -> Result<DeletedSource> {
+    let deleted = delete_by_source(pool, source_id).instrument(info_span!("delete_source", source_id)).await?;
+    refresh_snapshot(pool).await;
+    refresh_land_water_grid(pool).await;
+
+    Ok(deleted)
+}
+
+/// Downloads the elements within a box from Overpass and imports them.
+///
+/// ## Arguments
+/// * `pool` - The database to import into.
+/// * `bbox` - The box to download.
+/// * `options` - Which tags are stored.
+///
+/// ## Returns
+/// * How many elements were imported.
+pub async fn download_and_import(pool: &SqlitePool, bbox: &BBox, options: &ImportOptions) -> Result<ImportStats> {
+    let config = OverpassConfig::from_env();
+    let span = info_span!("download", %bbox, endpoint = %config.endpoint);
+
+    // The download blocks on the socket, so keep it off the async runtime
+    let bbox = *bbox;
+    let data = tokio::task::spawn_blocking(move || download_bbox(&TcpHttpClient, &config, &bbox))
+        .instrument(span)
+        .await??;
+
+    let nodes = report_read_outcome("nodes", data.nodes);
+    let ways = report_read_outcome("ways", data.ways);
+    let relations = report_read_outcome("relations", data.relations);
+
+    let source = format!("overpass {:.5},{:.5} {:.5},{:.5}", bbox.max_lat, bbox.min_lon, bbox.min_lat, bbox.max_lon);
+    import_osm_data(pool, &source, None, nodes, ways, relations, options, None).await
+}
+
+async fn process_gpx_file(pool: &SqlitePool, path: &str) -> Result<()> {
+    // Read tracks from file
+    let tracks = info_span!("read", file = %path)
+        .in_scope(|| read_gpx_file(path))
+        .map_err(|error| anyhow::anyhow!("Could not read the GPS tracks of {}: {}", path, error))?;
+    let point_count: usize = tracks.iter().flat_map(|track| &track.segments).map(Vec::len).sum();
+    info!(tracks = tracks.len(), points = point_count, "read GPS tracks");
+
+    let span = info_span!("import_gps_tracks", tracks = tracks.len());
+    async {
+        insert_gps_tracks(pool, tracks, &InsertConfig::detect(pool).await?).await?;
+        Ok(())
+    }
+    .instrument(span)
+    .await
 }
 
 pub async fn read_openstreet_map_file(pool: &SqlitePool) -> Result<()> {
-    let directory = "utils/mapdata/";
+    let directory = MAPDATA_DIRECTORY;
     let files = list_files_in_directory(directory)?;
 
     if let Some(chosen_file) = choose_file(&files) {
-        process_map_file(pool, &chosen_file).await?;
+        import_file(pool, &format!("{}{}", directory, chosen_file), &ImportOptions::default()).await?;
     } else {
-        println!("Invalid selection.");
+        warn!("invalid selection");
     }
 
     Ok(())
 }
+
+/// Imports an OpenStreetMap XML or JSON file, or the tracks of a GPX file.
+///
+/// ## Arguments
+/// * `pool` - The database to import into.
+/// * `path` - The path to the file, read as GPX if it ends in `.gpx`, or `STDIN_PATH` for
+///   map data piped in.
+/// * `options` - Which tags of a map file are stored, GPS tracks have none.
+pub async fn import_file(pool: &SqlitePool, path: &str, options: &ImportOptions) -> Result<()> {
+    // GPX files hold recorded tracks rather than map data
+    if path.to_lowercase().ends_with(".gpx") {
+        process_gpx_file(pool, path).await
+    } else {
+        let stats = process_map_file(pool, path, options).await?;
+        info!(file = path, source_id = stats.source_id, %stats, "imported");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::SCHEMA_TABLES;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    const COURTYARD_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.00080" lon="11.00030" version="1"><tag k="entrance" v="main"/></node>
+ <node id="2" lat="55.00080" lon="11.00130" version="1"/>
+ <node id="3" lat="55.00020" lon="11.00130" version="1"/>
+ <node id="4" lat="55.00020" lon="11.00030" version="1"/>
+ <node id="5" lat="55.00065" lon="11.00060" version="1"/>
+ <node id="6" lat="55.00065" lon="11.00105" version="1"/>
+ <node id="7" lat="55.00035" lon="11.00105" version="1"/>
+ <node id="8" lat="55.00035" lon="11.00060" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><tag k="barrier" v="wall"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="4"/><nd ref="1"/><tag k="barrier" v="fence"/></way>
+ <way id="12" version="1"><nd ref="5"/><nd ref="6"/><nd ref="7"/><nd ref="8"/><nd ref="5"/><tag k="leisure" v="garden"/></way>
+ <relation id="20" version="1">
+  <member type="way" ref="10" role="outer"/><member type="way" ref="11" role="outer"/><member type="way" ref="12" role="inner"/>
+  <tag k="type" v="multipolygon"/><tag k="building" v="yes"/>
+ </relation>
+</osm>
+"#;
+
+    async fn row_counts(pool: &SqlitePool) -> Vec<(&'static str, i64)> {
+        let mut counts = Vec::new();
+        for table in SCHEMA_TABLES {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table)).fetch_one(pool).await.unwrap();
+            counts.push((table, count));
+        }
+        counts
+    }
+
+    #[tokio::test]
+    async fn an_import_cut_short_is_resumed_to_what_a_whole_one_stores() {
+        let path = std::env::temp_dir().join(format!("gmc_resume_{}.osm", std::process::id()));
+        fs::write(&path, COURTYARD_OSM).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let options = ImportOptions::default();
+
+        let whole = memory_pool("resume_whole").await;
+        let stats = process_map_file(&whole, &path, &options).await.unwrap();
+        assert_eq!((stats.nodes, stats.ways, stats.relations, stats.resumed_after), (8, 3, 1, None));
+        let expected = row_counts(&whole).await;
+
+        // Cut short within the ways: they are stored, but not all of their tags, and nothing after
+        let cut = memory_pool("resume_cut").await;
+        let stats = process_map_file(&cut, &path, &options).await.unwrap();
+        for statement in [
+            "DELETE FROM way_tags WHERE way_id = 11",
+            "DELETE FROM relation_tags",
+            "DELETE FROM member",
+            "DELETE FROM relation",
+        ] {
+            sqlx::query(statement).execute(&cut).await.unwrap();
+        }
+        save_import_phase(&cut, stats.source_id, ImportPhase::NodesInserted).await.unwrap();
+
+        let resumed = process_map_file(&cut, &path, &options).await.unwrap();
+        assert_eq!(resumed.source_id, stats.source_id);
+        assert_eq!(resumed.resumed_after, Some(ImportPhase::NodesInserted));
+        assert_eq!(fetch_import_phase(&cut, stats.source_id).await.unwrap(), Some(ImportPhase::Finalized));
+        assert_eq!(row_counts(&cut).await, expected);
+
+        // Once done, the same contents are left alone
+        assert!(process_map_file(&cut, &path, &options).await.unwrap().skipped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unchanged_files_and_elements_are_not_stored_again() {
+        let path = std::env::temp_dir().join(format!("gmc_reimport_{}.osm", std::process::id()));
+        fs::write(&path, COURTYARD_OSM).unwrap();
+        let path_str = path.to_str().unwrap().to_string();
+        let options = ImportOptions::default();
+
+        let pool = memory_pool("reimport").await;
+        let first = process_map_file(&pool, &path_str, &options).await.unwrap();
+        assert_eq!((first.new, first.updated, first.unchanged), (12, 0, 0));
+        let expected = row_counts(&pool).await;
+
+        // The same contents are skipped without a new import being recorded
+        let second = process_map_file(&pool, &path_str, &options).await.unwrap();
+        assert!(second.skipped);
+        assert_eq!(second.source_id, first.source_id);
+        assert_eq!((second.new, second.updated, second.unchanged), (0, 0, 0));
+        assert_eq!(row_counts(&pool).await, expected);
+
+        // A file touched since, with the same contents, is skipped as well
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000)).unwrap();
+        drop(file);
+        assert_eq!(fingerprint_file(&path_str).unwrap().modified_at, 1_000_000_000);
+        assert!(process_map_file(&pool, &path_str, &options).await.unwrap().skipped);
+        assert_eq!(row_counts(&pool).await, expected);
+
+        // Forced, every element is read again but none of them is stored
+        let forced = process_map_file(&pool, &path_str, &ImportOptions { force: true, ..ImportOptions::default() }).await.unwrap();
+        assert!(!forced.skipped);
+        assert_eq!((forced.new, forced.updated, forced.unchanged), (0, 0, 12));
+
+        // A new version of one node updates that node alone
+        let moved = COURTYARD_OSM.replace(
+            r#"<node id="5" lat="55.00065" lon="11.00060" version="1"/>"#,
+            r#"<node id="5" lat="55.00070" lon="11.00060" version="2"/>"#,
+        );
+        fs::write(&path, moved).unwrap();
+        let changed = process_map_file(&pool, &path_str, &options).await.unwrap();
+        assert!(!changed.skipped);
+        assert_eq!((changed.new, changed.updated, changed.unchanged), (0, 1, 11));
+        let versions: Vec<(i64, i64)> = sqlx::query_as("SELECT id, version FROM node WHERE version > 1").fetch_all(&pool).await.unwrap();
+        assert_eq!(versions, [(5, 2)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_overpass_json_file_is_imported_like_an_xml_one() {
+        let pool = memory_pool("import_json").await;
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/utils/mapdata/overpass.json");
+        let stats = process_map_file(&pool, path, &ImportOptions::default()).await.unwrap();
+        assert_eq!((stats.nodes, stats.ways, stats.relations), (3, 2, 1));
+
+        let node_refs: Vec<i64> = sqlx::query_scalar("SELECT ref_id FROM way_nodes WHERE way_id = 201 ORDER BY seq").fetch_all(&pool).await.unwrap();
+        assert_eq!(node_refs, [103, 101, 102, 103]);
+        let name: String = sqlx::query_scalar("SELECT v.text FROM node_tags nt JOIN tag_value v ON v.id = nt.value_id WHERE nt.node_id = 101 AND nt.key_id = (SELECT id FROM tag_key WHERE text = 'name')")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(name, "Kaffebaren");
+    }
+
+    #[tokio::test]
+    async fn a_simplified_import_stores_fewer_nodes_but_every_junction() {
+        let path = std::env::temp_dir().join(format!("gmc_simplify_{}.osm", std::process::id()));
+        fs::write(&path, r#"<osm version="0.6">
+ <node id="1" lat="55.00000" lon="12.000" version="1"/>
+ <node id="2" lat="55.00002" lon="12.001" version="1"/>
+ <node id="3" lat="54.99998" lon="12.002" version="1"/>
+ <node id="4" lat="55.00000" lon="12.003" version="1"/>
+ <node id="5" lat="55.00002" lon="12.004" version="1"/>
+ <node id="6" lat="55.00000" lon="12.005" version="1"/>
+ <node id="7" lat="55.00100" lon="12.003" version="1"/>
+ <node id="8" lat="54.99900" lon="12.003" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><nd ref="5"/><nd ref="6"/><tag k="highway" v="track"/></way>
+ <way id="11" version="1"><nd ref="7"/><nd ref="4"/><nd ref="8"/><tag k="highway" v="track"/></way>
+</osm>"#).unwrap();
+        let options = ImportOptions { simplify_tolerance_m: Some(10.0), ..ImportOptions::default() };
+
+        let pool = memory_pool("simplify_import").await;
+        let stats = process_map_file(&pool, path.to_str().unwrap(), &options).await.unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(stats.elided_nodes, 3);
+        assert!(stats.to_string().contains(", 3 nodes simplified away"), "{}", stats);
+
+        let node_ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM node ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(node_ids, [1, 4, 6, 7, 8]);
+        let node_refs: Vec<i64> = sqlx::query_scalar("SELECT ref_id FROM way_nodes WHERE way_id = 10 ORDER BY seq").fetch_all(&pool).await.unwrap();
+        assert_eq!(node_refs, [1, 4, 6]);
+    }
+
+    #[tokio::test]
+    async fn a_corrupt_file_ends_the_import_of_a_directory() {
+        let directory = std::env::temp_dir().join(format!("gmc_import_all_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let extract = |id: i64| format!(r#"<osm version="0.6"><node id="{id}" lat="55.0" lon="11.0" version="1"/></osm>"#);
+        fs::write(directory.join("a.osm"), extract(1)).unwrap();
+        fs::write(directory.join("b.osm"), r#"<osm version="0.6"><node id="2" lat="55.0" lon="11.0" version="1"></way></osm>"#).unwrap();
+        fs::write(directory.join("c.osm"), extract(3)).unwrap();
+        fs::write(directory.join("track.gpx"), "<gpx/>").unwrap();
+        let path_of = |name: &str| directory.join(name).to_string_lossy().into_owned();
+        let directory_path = directory.to_str().unwrap();
+
+        // GPS tracks are not maps
+        assert_eq!(list_map_files(directory_path).unwrap(), [path_of("a.osm"), path_of("b.osm"), path_of("c.osm")]);
+
+        let pool = memory_pool("import_all").await;
+        let report = import_map_directory(&pool, directory_path, &ImportOptions::default()).await.unwrap();
+        assert_eq!(report.imported.iter().map(|(path, stats)| (path.clone(), stats.nodes)).collect::<Vec<_>>(), [(path_of("a.osm"), 1)]);
+        assert_eq!(report.failed.as_ref().map(|(path, _)| path.clone()), Some(path_of("b.osm")));
+        assert_eq!(report.not_imported, [path_of("c.osm")]);
+        let text = report.to_string();
+        assert!(text.contains(&format!("Could not import {}: ", path_of("b.osm"))), "{}", text);
+        assert!(text.ends_with(&format!("Not imported {}\n", path_of("c.osm"))), "{}", text);
+
+        // Nothing of the corrupt file or the one after it was stored
+        let node_ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM node ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(node_ids, [1]);
+
+        // Once repaired, the first file is skipped and the others are imported
+        fs::write(directory.join("b.osm"), extract(2)).unwrap();
+        let report = import_map_directory(&pool, directory_path, &ImportOptions::default()).await.unwrap();
+        assert!(report.failed.is_none() && report.not_imported.is_empty());
+        assert_eq!(report.imported.iter().map(|(_, stats)| stats.skipped).collect::<Vec<_>>(), [true, false, false]);
+        let node_ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM node ORDER BY id").fetch_all(&pool).await.unwrap();
+        assert_eq!(node_ids, [1, 2, 3]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_data_extent_covers_every_import() {
+        let pool = memory_pool("data_extent").await;
+        assert_eq!(fetch_data_extent(&pool).await.unwrap(), None);
+
+        import_osm_xml(&pool, "data_extent_west", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="11.0" version="1"/>
+ <node id="2" lat="55.5" lon="11.5" version="1"/>
+</osm>"#).await;
+        let west = BBox { min_lat: 55.0, max_lat: 55.5, min_lon: 11.0, max_lon: 11.5 };
+        assert_eq!(fetch_data_extent(&pool).await.unwrap(), Some(west));
+
+        // A region to the south east, not overlapping the first
+        import_osm_xml(&pool, "data_extent_east", r#"<osm version="0.6">
+ <node id="3" lat="54.0" lon="12.0" version="1"/>
+ <node id="4" lat="54.5" lon="12.5" version="1"/>
+</osm>"#).await;
+        let union = BBox { min_lat: 54.0, max_lat: 55.5, min_lon: 11.0, max_lon: 12.5 };
+        assert_eq!(fetch_data_extent(&pool).await.unwrap(), Some(union));
+
+        // Without the saved value, as before it was saved, the extent is found from the nodes
+        sqlx::query("DELETE FROM settings").execute(&pool).await.unwrap();
+        assert_eq!(fetch_data_extent(&pool).await.unwrap(), Some(union));
+    }
+
+    #[tokio::test]
+    async fn a_gpx_file_that_cannot_be_read_is_an_error() {
+        let pool = memory_pool("gpx_missing").await;
+        let path = std::env::temp_dir().join(format!("gmc_missing_{}.gpx", std::process::id()));
+        let error = import_file(&pool, path.to_str().unwrap(), &ImportOptions::default()).await.unwrap_err();
+        assert!(error.to_string().starts_with("Could not read the GPS tracks of "), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn an_import_only_recomputes_the_bounding_boxes_of_the_ways_it_touched() {
+        let pool = memory_pool("way_geometry_touched").await;
+        import_osm_xml(&pool, "way_geometry_first", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="11.0" version="1"/>
+ <node id="2" lat="55.1" lon="11.1" version="1"/>
+ <node id="3" lat="56.0" lon="12.0" version="1"/>
+ <node id="4" lat="56.1" lon="12.1" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="4"/></way>
+</osm>"#).await;
+        // Way 11 is left alone by the next import, so a box it would recompute is told apart
+        sqlx::query("UPDATE way_geom SET min_lat = -1 WHERE way_id = 11").execute(&pool).await.unwrap();
+
+        // Node 2 moves way 10, which the import does not hold, and way 12 is new
+        import_osm_xml(&pool, "way_geometry_second", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="11.0" version="1"/>
+ <node id="2" lat="55.2" lon="11.2" version="2"/>
+ <node id="3" lat="56.0" lon="12.0" version="1"/>
+ <way id="12" version="1"><nd ref="1"/><nd ref="3"/></way>
+</osm>"#).await;
+
+        let boxes: Vec<(i64, f64, f64, f64, f64)> = sqlx::query_as("SELECT way_id, min_lat, min_lon, max_lat, max_lon FROM way_geom ORDER BY way_id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(boxes, [(10, 55.0, 11.0, 55.2, 11.2), (11, -1.0, 12.0, 56.1, 12.1), (12, 55.0, 11.0, 56.0, 12.0)]);
+    }
+}
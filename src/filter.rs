@@ -0,0 +1,222 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::osm_entities::Tag;
+
+/// Why a filter expression could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterError {
+    /// Nothing but spaces was given.
+    Empty,
+    /// The condition at this position, counted from 1, is empty, e.g. in `a=b,,c=d`.
+    EmptyCondition(usize),
+    /// A condition without `=` or `!=`.
+    MissingOperator(String),
+    /// A condition without a key before its operator, e.g. `=residential`.
+    MissingKey(String),
+    /// A condition without a value after its operator, e.g. `highway=`.
+    MissingValue(String),
+    /// `key!=*`, which would only match ways without the key.
+    NegatedWildcard(String),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::Empty => write!(f, "the filter is empty"),
+            FilterError::EmptyCondition(position) => write!(f, "condition {} is empty", position),
+            FilterError::MissingOperator(condition) => write!(f, "'{}' is not key=value, key=* or key!=value", condition),
+            FilterError::MissingKey(condition) => write!(f, "'{}' has no key", condition),
+            FilterError::MissingValue(condition) => write!(f, "'{}' has no value, key=* matches any value", condition),
+            FilterError::NegatedWildcard(condition) => write!(f, "'{}' is not supported, only values can be negated", condition),
+        }
+    }
+}
+
+impl StdError for FilterError {}
+
+/// A condition on a single tag of a way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagCondition {
+    /// `key=value`, the way has the tag.
+    Equals { key: String, value: String },
+    /// `key=*`, the way has the key, whatever its value.
+    HasKey(String),
+    /// `key!=value`, the way has the key with another value, or does not have the key.
+    NotEquals { key: String, value: String },
+}
+
+impl TagCondition {
+    pub fn matches(&self, tags: &[Tag]) -> bool {
+        let value_of = |key: &str| tags.iter().find(|tag| tag.key == key).map(|tag| tag.value.as_str());
+        match self {
+            TagCondition::Equals { key, value } => value_of(key) == Some(value.as_str()),
+            TagCondition::HasKey(key) => value_of(key).is_some(),
+            TagCondition::NotEquals { key, value } => value_of(key) != Some(value.as_str()),
+        }
+    }
+}
+
+impl FromStr for TagCondition {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let condition = s.trim();
+        // The value may hold `=` itself, e.g. in `name=a=b`, so the first one is the operator
+        let Some(equals) = condition.find('=') else {
+            return Err(FilterError::MissingOperator(condition.to_string()));
+        };
+        let (key, negated) = match condition[..equals].strip_suffix('!') {
+            Some(key) => (key.trim(), true),
+            None => (condition[..equals].trim(), false),
+        };
+        let value = condition[equals + 1..].trim();
+
+        if key.is_empty() {
+            return Err(FilterError::MissingKey(condition.to_string()));
+        }
+        if value.is_empty() {
+            return Err(FilterError::MissingValue(condition.to_string()));
+        }
+
+        let (key, value) = (key.to_string(), value.to_string());
+        match (negated, value.as_str()) {
+            (true, "*") => Err(FilterError::NegatedWildcard(condition.to_string())),
+            (true, _) => Ok(TagCondition::NotEquals { key, value }),
+            (false, "*") => Ok(TagCondition::HasKey(key)),
+            (false, _) => Ok(TagCondition::Equals { key, value }),
+        }
+    }
+}
+
+impl fmt::Display for TagCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagCondition::Equals { key, value } => write!(f, "{}={}", key, value),
+            TagCondition::HasKey(key) => write!(f, "{}=*", key),
+            TagCondition::NotEquals { key, value } => write!(f, "{}!={}", key, value),
+        }
+    }
+}
+
+/// Picks ways by their tags, e.g. to highlight them while checking imported data.
+///
+/// Written as conditions joined by commas, every one of which has to hold, e.g.
+/// `highway=residential,name=*,oneway!=yes`. There is no OR, so no precedence to get wrong.
+///
+/// # Fields
+/// * `conditions` - The conditions, at least one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WayFilter {
+    pub conditions: Vec<TagCondition>,
+}
+
+impl WayFilter {
+    /// Whether the tags of a way meet every condition.
+    pub fn matches(&self, tags: &[Tag]) -> bool {
+        self.conditions.iter().all(|condition| condition.matches(tags))
+    }
+}
+
+impl FromStr for WayFilter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err(FilterError::Empty);
+        }
+
+        let conditions = s.split(',')
+            .enumerate()
+            .map(|(index, condition)| match condition.trim() {
+                "" => Err(FilterError::EmptyCondition(index + 1)),
+                condition => condition.parse(),
+            })
+            .collect::<Result<Vec<TagCondition>, FilterError>>()?;
+
+        Ok(WayFilter { conditions })
+    }
+}
+
+impl fmt::Display for WayFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, condition) in self.conditions.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<Tag> {
+        pairs.iter().map(|(key, value)| Tag::new(key.to_string(), value.to_string())).collect()
+    }
+
+    fn matches(filter: &str, pairs: &[(&str, &str)]) -> bool {
+        filter.parse::<WayFilter>().unwrap().matches(&tags(pairs))
+    }
+
+    #[test]
+    fn each_operator_is_parsed() {
+        let equals = |key: &str, value: &str| TagCondition::Equals { key: key.to_string(), value: value.to_string() };
+        assert_eq!("highway=residential".parse(), Ok(equals("highway", "residential")));
+        assert_eq!("building=*".parse(), Ok(TagCondition::HasKey("building".to_string())));
+        assert_eq!(" oneway != yes ".parse(), Ok(TagCondition::NotEquals { key: "oneway".to_string(), value: "yes".to_string() }));
+        // Only the first `=` is the operator
+        assert_eq!("name=a=b".parse(), Ok(equals("name", "a=b")));
+
+        for filter in ["highway=residential", "building=*", "oneway!=yes", "highway=primary,name=*,oneway!=yes"] {
+            assert_eq!(filter.parse::<WayFilter>().unwrap().to_string(), filter);
+        }
+    }
+
+    #[test]
+    fn each_operator_matches_as_written() {
+        assert!(matches("highway=residential", &[("highway", "residential")]));
+        assert!(!matches("highway=residential", &[("highway", "primary")]));
+        assert!(!matches("highway=residential", &[]));
+
+        assert!(matches("building=*", &[("building", "yes")]));
+        assert!(matches("building=*", &[("building", "")]));
+        assert!(!matches("building=*", &[("building:part", "yes")]));
+
+        assert!(matches("oneway!=yes", &[("oneway", "no")]));
+        assert!(matches("oneway!=yes", &[("highway", "primary")]));
+        assert!(!matches("oneway!=yes", &[("oneway", "yes")]));
+    }
+
+    #[test]
+    fn every_condition_joined_by_commas_has_to_hold() {
+        let filter = "highway=residential, name=*, oneway!=yes";
+        assert!(matches(filter, &[("highway", "residential"), ("name", "Main Street")]));
+        assert!(!matches(filter, &[("highway", "residential")]));
+        assert!(!matches(filter, &[("highway", "residential"), ("name", "Main Street"), ("oneway", "yes")]));
+        assert!(!matches(filter, &[("highway", "primary"), ("name", "Main Street")]));
+        assert_eq!(filter.parse::<WayFilter>().unwrap().conditions.len(), 3);
+    }
+
+    #[test]
+    fn malformed_filters_are_errors_with_a_message() {
+        let error = |filter: &str| filter.parse::<WayFilter>().unwrap_err();
+        assert_eq!(error(""), FilterError::Empty);
+        assert_eq!(error("   "), FilterError::Empty);
+        assert_eq!(error("a=b,,c=d"), FilterError::EmptyCondition(2));
+        assert_eq!(error("a=b,"), FilterError::EmptyCondition(2));
+        assert_eq!(error("highway"), FilterError::MissingOperator("highway".to_string()));
+        assert_eq!(error("=residential"), FilterError::MissingKey("=residential".to_string()));
+        assert_eq!(error("!=yes"), FilterError::MissingKey("!=yes".to_string()));
+        assert_eq!(error("highway=residential,name="), FilterError::MissingValue("name=".to_string()));
+        assert_eq!(error("oneway!=*"), FilterError::NegatedWildcard("oneway!=*".to_string()));
+
+        assert_eq!(error("highway").to_string(), "'highway' is not key=value, key=* or key!=value");
+        assert_eq!(error("name=").to_string(), "'name=' has no value, key=* matches any value");
+        assert_eq!(error("a=b,,c=d").to_string(), "condition 2 is empty");
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::{Duration, Instant};
+
+/// How often the frame rate is reported.
+pub const FRAME_RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts the frames drawn to tell the frame rate, e.g. while redrawing continuously to
+/// measure how fast the map can be drawn.
+///
+/// # Fields
+/// * `frames` - The frames drawn since `since`.
+/// * `since` - When the current interval started, or `None` before the first frame.
+#[derive(Debug, Default)]
+pub struct FrameRateMeter {
+    frames: u32,
+    since: Option<Instant>,
+}
+
+impl FrameRateMeter {
+    /// Counts a frame drawn.
+    ///
+    /// ## Returns
+    /// * The frames per second over the last interval, once `FRAME_RATE_INTERVAL` has passed since it started.
+    pub fn frame(&mut self, now: Instant) -> Option<f64> {
+        // The first frame starts the interval, the frames after it are counted
+        let Some(since) = self.since else {
+            self.since = Some(now);
+            return None;
+        };
+        self.frames += 1;
+
+        let elapsed = now.duration_since(since);
+        if elapsed < FRAME_RATE_INTERVAL {
+            return None;
+        }
+        let frames_per_second = self.frames as f64 / elapsed.as_secs_f64();
+        self.frames = 0;
+        self.since = Some(now);
+        Some(frames_per_second)
+    }
+
+    /// Starts counting over, e.g. after the frames were not drawn continuously for a while.
+    pub fn reset(&mut self) {
+        *self = FrameRateMeter::default();
+    }
+}
@@ -0,0 +1,1784 @@
+// Geometric helpers working on `(lat, lon)` points and on bounding boxes, see `BBox`.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+/// Computes the bounding box of a set of points. The box of a single point has no area.
+///
+/// ## Returns
+/// * The box, or `None` if `points` is empty.
+pub fn bbox_of_points(points: &[(f64, f64)]) -> Option<BBox> {
+    let first = points.first()?;
+    let mut bbox = BBox { min_lat: first.0, max_lat: first.0, min_lon: first.1, max_lon: first.1 };
+
+    for &(lat, lon) in points {
+        bbox.min_lat = bbox.min_lat.min(lat);
+        bbox.max_lat = bbox.max_lat.max(lat);
+        bbox.min_lon = bbox.min_lon.min(lon);
+        bbox.max_lon = bbox.max_lon.max(lon);
+    }
+
+    Some(bbox)
+}
+
+/// A box of latitudes and longitudes, e.g. the viewport or an area given on the command line.
+///
+/// A box crossing the antimeridian has a `max_lon` beyond 180, e.g. 170 to 190 for the 20
+/// degrees around it, so its minimum stays smaller than its maximum.
+///
+/// # Fields
+/// * `min_lat` - The latitude of the southern edge, at least -90.
+/// * `max_lat` - The latitude of the northern edge, at most 90.
+/// * `min_lon` - The longitude of the western edge.
+/// * `max_lon` - The longitude of the eastern edge. A box more than 360 degrees wide, e.g. the
+///   whole world fitted to a wide window, contains every longitude.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct BBox {
+    pub min_lat: f64,
+    pub max_lat: f64,
+    pub min_lon: f64,
+    pub max_lon: f64,
+}
+
+/// What is wrong with a box, see `BBox::new`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BBoxError {
+    /// A value is not a number, given the value.
+    InvalidNumber(String),
+    /// There are not exactly four values, given how many there are.
+    ValueCount(usize),
+    /// A value is infinite or not a number.
+    NotFinite,
+    LatOutOfRange(f64),
+    LonOutOfRange(f64),
+    /// A minimum is not smaller than its maximum, so the box is empty.
+    Empty,
+}
+
+impl fmt::Display for BBoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BBoxError::InvalidNumber(value) => write!(f, "'{}' is not a number", value),
+            BBoxError::ValueCount(count) => write!(f, "expected minLon,minLat,maxLon,maxLat but got {} values", count),
+            BBoxError::NotFinite => write!(f, "the edges must be finite"),
+            BBoxError::LatOutOfRange(lat) => write!(f, "latitude {} is not between -90 and 90", lat),
+            BBoxError::LonOutOfRange(lon) => write!(f, "longitude {} is not between -180 and 180", lon),
+            BBoxError::Empty => write!(f, "the minimum must be smaller than the maximum"),
+        }
+    }
+}
+
+impl StdError for BBoxError {}
+
+impl BBox {
+    /// Creates a box from its edges.
+    ///
+    /// ## Returns
+    /// * The box, or what is wrong with the edges: a latitude beyond a pole or a minimum not
+    ///   smaller than its maximum.
+    pub fn new(min_lat: f64, max_lat: f64, min_lon: f64, max_lon: f64) -> Result<BBox, BBoxError> {
+        if ![min_lat, max_lat, min_lon, max_lon].iter().all(|value| value.is_finite()) {
+            return Err(BBoxError::NotFinite);
+        }
+        if let Some(&lat) = [min_lat, max_lat].iter().find(|lat| !(-90.0..=90.0).contains(*lat)) {
+            return Err(BBoxError::LatOutOfRange(lat));
+        }
+        if min_lat >= max_lat || min_lon >= max_lon {
+            return Err(BBoxError::Empty);
+        }
+        Ok(BBox { min_lat, max_lat, min_lon, max_lon })
+    }
+
+    /// Checks a box built from its edges, e.g. one computed from a window, like `new` does.
+    pub fn validated(self) -> Result<BBox, BBoxError> {
+        BBox::new(self.min_lat, self.max_lat, self.min_lon, self.max_lon)
+    }
+
+    /// Creates a box around a `(lat, lon)` point.
+    ///
+    /// ## Arguments
+    /// * `span` - The `(lat_span, lon_span)` of the box in degrees.
+    pub fn from_center_and_span(center: (f64, f64), (lat_span, lon_span): (f64, f64)) -> Result<BBox, BBoxError> {
+        BBox::new(center.0 - lat_span / 2.0, center.0 + lat_span / 2.0, center.1 - lon_span / 2.0, center.1 + lon_span / 2.0)
+    }
+
+    /// The top left `(max_lat, min_lon)` corner.
+    pub fn top_left(&self) -> (f64, f64) {
+        (self.max_lat, self.min_lon)
+    }
+
+    /// The bottom right `(min_lat, max_lon)` corner.
+    pub fn bottom_right(&self) -> (f64, f64) {
+        (self.min_lat, self.max_lon)
+    }
+
+    /// Tells whether a point lies in the box, on its edges included. The longitude may be
+    /// given in any turn of the world, e.g. -175 lies in a box from 170 to 190.
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let lon = self.min_lon + (lon - self.min_lon).rem_euclid(360.0);
+        (self.min_lat..=self.max_lat).contains(&lat) && lon <= self.max_lon
+    }
+
+    /// Tells whether another box lies completely inside this one, on its edges included.
+    pub fn contains_bbox(&self, inner: &BBox) -> bool {
+        self.min_lat <= inner.min_lat && inner.max_lat <= self.max_lat && self.min_lon <= inner.min_lon && inner.max_lon <= self.max_lon
+    }
+
+    /// Tells whether two boxes overlap, touching edges included, also where one of them
+    /// crosses the antimeridian.
+    pub fn intersects(&self, other: &BBox) -> bool {
+        // Moved by whole turns to start east of the western edge, the other box overlaps if it
+        // starts before the eastern edge or reaches around to the western one
+        let other_min_lon = self.min_lon + (other.min_lon - self.min_lon).rem_euclid(360.0);
+        let other_max_lon = other_min_lon + (other.max_lon - other.min_lon);
+        self.min_lat <= other.max_lat
+            && other.min_lat <= self.max_lat
+            && (other_min_lon <= self.max_lon || other_max_lon >= self.min_lon + 360.0)
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(&self, other: &BBox) -> BBox {
+        BBox {
+            min_lat: self.min_lat.min(other.min_lat),
+            max_lat: self.max_lat.max(other.max_lat),
+            min_lon: self.min_lon.min(other.min_lon),
+            max_lon: self.max_lon.max(other.max_lon),
+        }
+    }
+
+    /// Grows the box by `fraction` of its height and width on every side, but no further than
+    /// the poles.
+    pub fn expand(&self, fraction: f64) -> BBox {
+        let lat_margin = (self.max_lat - self.min_lat) * fraction;
+        let lon_margin = (self.max_lon - self.min_lon) * fraction;
+        BBox {
+            min_lat: (self.min_lat - lat_margin).max(-90.0),
+            max_lat: (self.max_lat + lat_margin).min(90.0),
+            min_lon: self.min_lon - lon_margin,
+            max_lon: self.max_lon + lon_margin,
+        }
+    }
+
+    /// The width of the box in meters along its middle latitude.
+    pub fn width_m(&self) -> f64 {
+        (self.max_lon - self.min_lon) * METERS_PER_DEGREE * self.center().0.to_radians().cos()
+    }
+
+    /// The height of the box in meters.
+    pub fn height_m(&self) -> f64 {
+        (self.max_lat - self.min_lat) * METERS_PER_DEGREE
+    }
+
+    /// The `(lat, lon)` center of the box, with the longitude between -180 and 180.
+    pub fn center(&self) -> (f64, f64) {
+        let lon = (self.min_lon + self.max_lon) / 2.0;
+        ((self.min_lat + self.max_lat) / 2.0, (lon + 180.0).rem_euclid(360.0) - 180.0)
+    }
+
+    /// The width of the box over its height, both in meters.
+    pub fn aspect(&self) -> f64 {
+        self.width_m() / self.height_m()
+    }
+}
+
+/// Writes the box as `minLon,minLat,maxLon,maxLat`, the order used by most OSM tools. The
+/// eastern edge of a box crossing the antimeridian is written west of the western one.
+impl fmt::Display for BBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let max_lon = if self.max_lon > 180.0 { self.max_lon - 360.0 } else { self.max_lon };
+        write!(f, "{},{},{},{}", self.min_lon, self.min_lat, max_lon, self.max_lat)
+    }
+}
+
+impl FromStr for BBox {
+    type Err = BBoxError;
+
+    /// Reads `minLon,minLat,maxLon,maxLat`. A box whose `maxLon` is smaller than its `minLon`
+    /// crosses the antimeridian, as written by `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f64> = s.split(',')
+            .map(|value| value.trim().parse::<f64>().map_err(|_| BBoxError::InvalidNumber(value.trim().to_string())))
+            .collect::<Result<_, _>>()?;
+
+        let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+            return Err(BBoxError::ValueCount(values.len()));
+        };
+        if let Some(&lon) = [min_lon, max_lon].iter().find(|lon| !(-180.0..=180.0).contains(*lon)) {
+            return Err(BBoxError::LonOutOfRange(lon));
+        }
+        let max_lon = if max_lon < min_lon { max_lon + 360.0 } else { max_lon };
+        BBox::new(min_lat, max_lat, min_lon, max_lon)
+    }
+}
+
+/// Clips the segment `a`-`b` to a box using the Liang–Barsky algorithm.
+///
+/// ## Returns
+/// * The parameters `(t0, t1)` along the segment of the visible part, or `None` if
+///   the segment lies completely outside the box.
+fn clip_segment_parameters(a: (f64, f64), b: (f64, f64), bounds: (f64, f64, f64, f64)) -> Option<(f64, f64)> {
+    let (min_lat, min_lon, max_lat, max_lon) = bounds;
+    let d_lat = b.0 - a.0;
+    let d_lon = b.1 - a.1;
+
+    let mut t0: f64 = 0.0;
+    let mut t1: f64 = 1.0;
+
+    // Each pair is (p, q) for one edge of the box: the point is inside that edge when p * t <= q
+    let edges = [
+        (-d_lon, a.1 - min_lon),
+        (d_lon, max_lon - a.1),
+        (-d_lat, a.0 - min_lat),
+        (d_lat, max_lat - a.0),
+    ];
+
+    for (p, q) in edges {
+        if p == 0.0 {
+            // Parallel to this edge, so it is either fully inside or fully outside of it
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+
+    if t0 > t1 {
+        None
+    } else {
+        Some((t0, t1))
+    }
+}
+
+fn interpolate(a: (f64, f64), b: (f64, f64), t: f64) -> (f64, f64) {
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t)
+}
+
+/// Clips a polyline to a box.
+///
+/// ## Arguments
+/// * `points` - The `(lat, lon)` points of the polyline.
+/// * `bbox` - The box to clip to.
+///
+/// ## Returns
+/// * The parts of the polyline inside the box, in order. A polyline that leaves and
+///   re-enters the box is split into several parts, one entirely outside yields none.
+pub fn clip_polyline_to_bbox(points: &[(f64, f64)], bbox: &BBox) -> Vec<Vec<(f64, f64)>> {
+    let bounds = (bbox.min_lat, bbox.min_lon, bbox.max_lat, bbox.max_lon);
+    let mut parts: Vec<Vec<(f64, f64)>> = Vec::new();
+    let mut current: Vec<(f64, f64)> = Vec::new();
+
+    for segment in points.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+
+        match clip_segment_parameters(a, b, bounds) {
+            Some((t0, t1)) => {
+                // The segment only continues the current part if it starts inside the box
+                if t0 > 0.0 || current.is_empty() {
+                    if current.len() > 1 {
+                        parts.push(std::mem::take(&mut current));
+                    }
+                    current.clear();
+                    current.push(interpolate(a, b, t0));
+                }
+                current.push(interpolate(a, b, t1));
+
+                // The segment leaves the box, so the part ends here
+                if t1 < 1.0 {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            None => {
+                if current.len() > 1 {
+                    parts.push(std::mem::take(&mut current));
+                }
+                current.clear();
+            }
+        }
+    }
+
+    if current.len() > 1 {
+        parts.push(current);
+    }
+
+    parts
+}
+
+/// An edge of the box a polygon is clipped to: whether a point lies on the inside of it, and
+/// where the segment between two points crosses it.
+type ClipEdge<'a> = (&'a dyn Fn((f64, f64)) -> bool, &'a dyn Fn((f64, f64), (f64, f64)) -> (f64, f64));
+
+/// Clips a polygon to a box using the Sutherland–Hodgman algorithm.
+///
+/// ## Arguments
+/// * `points` - The `(lat, lon)` points of the polygon ring. The ring may or may not repeat its first point at the end.
+/// * `bbox` - The box to clip to.
+///
+/// ## Returns
+/// * The clipped ring, closed by repeating its first point if the input was closed that way,
+///   or an empty vector if the polygon lies completely outside the box.
+pub fn clip_polygon_to_bbox(points: &[(f64, f64)], bbox: &BBox) -> Vec<(f64, f64)> {
+    let BBox { min_lat, max_lat, min_lon, max_lon } = *bbox;
+    let explicitly_closed = points.len() > 1 && points.first() == points.last();
+
+    let mut ring: Vec<(f64, f64)> = if explicitly_closed {
+        points[..points.len() - 1].to_vec()
+    } else {
+        points.to_vec()
+    };
+
+    // Each clip edge is described by an "inside" test and the intersection with the edge line
+    let clip_edges: [ClipEdge; 4] = [
+        (&|p| p.1 >= min_lon, &|a, b| interpolate(a, b, (min_lon - a.1) / (b.1 - a.1))),
+        (&|p| p.1 <= max_lon, &|a, b| interpolate(a, b, (max_lon - a.1) / (b.1 - a.1))),
+        (&|p| p.0 >= min_lat, &|a, b| interpolate(a, b, (min_lat - a.0) / (b.0 - a.0))),
+        (&|p| p.0 <= max_lat, &|a, b| interpolate(a, b, (max_lat - a.0) / (b.0 - a.0))),
+    ];
+
+    for (inside, intersect) in clip_edges {
+        if ring.is_empty() {
+            break;
+        }
+
+        let input = std::mem::take(&mut ring);
+        let mut previous = *input.last().unwrap();
+
+        for &current in &input {
+            match (inside(previous), inside(current)) {
+                (true, true) => ring.push(current),
+                (true, false) => ring.push(intersect(previous, current)),
+                (false, true) => {
+                    ring.push(intersect(previous, current));
+                    ring.push(current);
+                }
+                (false, false) => (),
+            }
+            previous = current;
+        }
+    }
+
+    // A ring needs at least three corners to enclose any area
+    if ring.len() < 3 {
+        return Vec::new();
+    }
+
+    if explicitly_closed {
+        ring.push(ring[0]);
+    }
+
+    ring
+}
+
+/// Approximate number of meters per degree of latitude.
+pub const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Computes an openstreetmap.org style zoom level from the longitude span of the viewport,
+/// where zoom 0 shows the whole world and every level halves the span.
+pub fn zoom_level(view: &BBox) -> f64 {
+    let lon_span = (view.max_lon - view.min_lon).abs();
+    (360.0 / lon_span).log2()
+}
+
+/// Returns how many meters one unit of normalized device coordinates covers horizontally
+/// at the center of the viewport. NDC spans two units from one edge of the screen to the other.
+pub fn meters_per_ndc_unit(view: &BBox) -> f64 {
+    view.width_m().abs() / 2.0
+}
+
+/// Mean radius of the earth in meters, as used by the haversine formula.
+pub const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Computes the great circle distance in meters between two `(lat, lon)` points.
+pub fn haversine_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let d_lat = (b.0 - a.0).to_radians();
+    let d_lon = (b.1 - a.1).to_radians();
+
+    let h = (d_lat / 2.0).sin().powi(2)
+        + a.0.to_radians().cos() * b.0.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+/// Computes the initial bearing in degrees from `a` towards `b`, clockwise from north in `[0, 360)`.
+pub fn bearing(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat_a, lat_b) = (a.0.to_radians(), b.0.to_radians());
+    let d_lon = (b.1 - a.1).to_radians();
+
+    let y = d_lon.sin() * lat_b.cos();
+    let x = lat_a.cos() * lat_b.sin() - lat_a.sin() * lat_b.cos() * d_lon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Computes the length in meters of a polyline of `(lat, lon)` points.
+pub fn polyline_length(points: &[(f64, f64)]) -> f64 {
+    points.windows(2).map(|segment| haversine_distance(segment[0], segment[1])).sum()
+}
+
+/// Computes the area in square meters of a ring of `(lat, lon)` points.
+///
+/// The ring is projected onto a plane tangent at its mean latitude, where a degree of
+/// longitude is shortened by the cosine of the latitude, and the area is taken with the
+/// shoelace formula. For rings the size of a city or smaller the error stays far below a percent.
+/// The ring may or may not repeat its first point at the end, either winding gives a positive area.
+pub fn polygon_area(ring: &[(f64, f64)]) -> f64 {
+    if ring.len() < 3 {
+        return 0.0;
+    }
+
+    let meters_per_degree = EARTH_RADIUS_M.to_radians();
+    let mean_lat = ring.iter().map(|&(lat, _)| lat).sum::<f64>() / ring.len() as f64;
+    let lon_scale = mean_lat.to_radians().cos();
+    // Relative to the first point, so the products stay small and precise
+    let origin = ring[0];
+    let project = |(lat, lon): (f64, f64)| ((lon - origin.1) * lon_scale * meters_per_degree, (lat - origin.0) * meters_per_degree);
+
+    let twice_area: f64 = ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(&a, &b)| {
+            let (a, b) = (project(a), project(b));
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum();
+    twice_area.abs() / 2.0
+}
+
+/// Splits a polyline into dashes of `dash_m` meters separated by gaps of `gap_m` meters.
+///
+/// ## Returns
+/// * The dashes in order, each with at least two points. Corners inside a dash are kept,
+///   so dashes follow the polyline around bends.
+pub fn dash_polyline(points: &[(f64, f64)], dash_m: f64, gap_m: f64) -> Vec<Vec<(f64, f64)>> {
+    let mut dashes: Vec<Vec<(f64, f64)>> = Vec::new();
+    if dash_m <= 0.0 || gap_m < 0.0 {
+        return dashes;
+    }
+
+    let mut drawing = true;
+    // Meters left of the current dash or gap
+    let mut remaining = dash_m;
+    let mut current: Vec<(f64, f64)> = points.first().map(|&p| vec![p]).unwrap_or_default();
+
+    for segment in points.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        let length = haversine_distance(a, b);
+        let mut travelled = 0.0;
+
+        // Cut the segment wherever a dash or gap ends
+        while length - travelled > remaining {
+            travelled += remaining;
+            let point = interpolate(a, b, travelled / length);
+
+            if drawing {
+                current.push(point);
+                dashes.push(std::mem::take(&mut current));
+                remaining = gap_m;
+            } else {
+                current = vec![point];
+                remaining = dash_m;
+            }
+            drawing = !drawing;
+        }
+
+        remaining -= length - travelled;
+        if drawing {
+            current.push(b);
+        }
+    }
+
+    if drawing && current.len() > 1 {
+        dashes.push(current);
+    }
+
+    dashes
+}
+
+/// Formats a distance in meters, switching to kilometers from 1 km.
+pub fn format_distance(meters: f64) -> String {
+    if meters < 1000.0 {
+        format!("{:.0} m", meters)
+    } else {
+        format!("{:.2} km", meters / 1000.0)
+    }
+}
+
+/// Formats an area in square meters, switching to hectares from 1 ha and to square kilometers from 1 km².
+pub fn format_area(square_meters: f64) -> String {
+    if square_meters < 10_000.0 {
+        format!("{:.0} m²", square_meters)
+    } else if square_meters < 1_000_000.0 {
+        format!("{:.1} ha", square_meters / 10_000.0)
+    } else {
+        format!("{:.2} km²", square_meters / 1_000_000.0)
+    }
+}
+
+/// Returns true if `point` lies inside the polygon `ring` or on its boundary.
+///
+/// Uses ray casting towards increasing longitude. An edge only counts as crossed when
+/// exactly one of its end points lies strictly above the ray, so a ray passing through
+/// a vertex is counted once rather than once for each of the two edges meeting there.
+/// The ring may or may not repeat its first point at the end.
+pub fn point_in_polygon(point: (f64, f64), ring: &[(f64, f64)]) -> bool {
+    if ring.len() < 3 {
+        return false;
+    }
+
+    let (lat, lon) = point;
+    let mut inside = false;
+    let mut previous = ring[ring.len() - 1];
+
+    for &current in ring {
+        if point_on_segment(point, previous, current) {
+            return true;
+        }
+
+        if (current.0 > lat) != (previous.0 > lat) {
+            // Longitude where the edge crosses the latitude of the point
+            let crossing_lon = current.1 + (lat - current.0) / (previous.0 - current.0) * (previous.1 - current.1);
+            if lon < crossing_lon {
+                inside = !inside;
+            }
+        }
+        previous = current;
+    }
+
+    inside
+}
+
+fn point_on_segment(point: (f64, f64), a: (f64, f64), b: (f64, f64)) -> bool {
+    let cross = (b.0 - a.0) * (point.1 - a.1) - (b.1 - a.1) * (point.0 - a.0);
+    if cross.abs() > f64::EPSILON {
+        return false;
+    }
+
+    point.0 >= a.0.min(b.0) && point.0 <= a.0.max(b.0) && point.1 >= a.1.min(b.1) && point.1 <= a.1.max(b.1)
+}
+
+// Points closer than this in degrees, about a centimeter, are the same point to `sanitize_ring`
+const RING_EPSILON_DEG: f64 = 1e-7;
+
+/// Cleans up the outline of an area before it is triangulated, as real outlines repeat
+/// points, run straight through corners or cross themselves.
+///
+/// Consecutive duplicate points are dropped, and so are corners lying within a centimeter
+/// of the line between their neighbours, including spikes doubling back on themselves.
+/// Dropping a corner can line up its neighbours, so this goes on until no corner is dropped.
+/// The ring keeps repeating its first point at the end if it did.
+///
+/// ## Returns
+/// * The cleaned ring, or `None` if fewer than three corners are left or its edges cross
+///   or touch each other, so it cannot be filled.
+pub fn sanitize_ring(points: &[(f64, f64)]) -> Option<Vec<(f64, f64)>> {
+    let is_closed = points.len() > 1 && same_point(points[0], points[points.len() - 1]);
+    let mut ring = dedup_ring(points.to_vec());
+
+    let mut changed = true;
+    while changed && ring.len() >= 3 {
+        changed = false;
+        let mut index = 0;
+        while index < ring.len() && ring.len() >= 3 {
+            let count = ring.len();
+            let previous = ring[(index + count - 1) % count];
+            let next = ring[(index + 1) % count];
+            if distance_to_line(ring[index], previous, next) < RING_EPSILON_DEG {
+                ring.remove(index);
+                changed = true;
+            } else {
+                index += 1;
+            }
+        }
+        ring = dedup_ring(ring);
+    }
+
+    if ring.len() < 3 || ring_self_intersects(&ring) {
+        return None;
+    }
+
+    if is_closed {
+        ring.push(ring[0]);
+    }
+    Some(ring)
+}
+
+fn same_point(a: (f64, f64), b: (f64, f64)) -> bool {
+    (a.0 - b.0).abs() < RING_EPSILON_DEG && (a.1 - b.1).abs() < RING_EPSILON_DEG
+}
+
+/// Drops consecutive duplicate points, including the last point repeating the first.
+fn dedup_ring(mut ring: Vec<(f64, f64)>) -> Vec<(f64, f64)> {
+    ring.dedup_by(|b, a| same_point(*a, *b));
+    while ring.len() > 1 && same_point(ring[0], ring[ring.len() - 1]) {
+        ring.pop();
+    }
+    ring
+}
+
+/// Distance from `p` to the line through `a` and `b`, treating degrees as plane coordinates.
+/// A point between two equal neighbours is the tip of a spike, whose line has no direction.
+fn distance_to_line(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let length = (b.0 - a.0).hypot(b.1 - a.1);
+    if length < RING_EPSILON_DEG {
+        return 0.0;
+    }
+
+    ((b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)).abs() / length
+}
+
+/// Whether any two edges of a ring without a repeated first point cross or touch, other
+/// than neighbouring edges meeting at their corner. Compares every pair of edges, which is
+/// fine for rings the size of buildings.
+pub fn ring_self_intersects(ring: &[(f64, f64)]) -> bool {
+    let count = ring.len();
+    let edge = |index: usize| (ring[index], ring[(index + 1) % count]);
+
+    for i in 0..count {
+        for j in i + 2..count {
+            // The last edge meets the first one at the first corner
+            if i == 0 && j == count - 1 {
+                continue;
+            }
+
+            let ((a, b), (c, d)) = (edge(i), edge(j));
+            if segments_intersect(a, b, c, d) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Whether the segments `a`-`b` and `c`-`d` share a point.
+fn segments_intersect(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    let orientation = |p: (f64, f64), q: (f64, f64), r: (f64, f64)| {
+        let cross = (q.0 - p.0) * (r.1 - p.1) - (q.1 - p.1) * (r.0 - p.0);
+        if cross > 0.0 { 1 } else if cross < 0.0 { -1 } else { 0 }
+    };
+    let (o1, o2, o3, o4) = (orientation(a, b, c), orientation(a, b, d), orientation(c, d, a), orientation(c, d, b));
+
+    if o1 != o2 && o3 != o4 {
+        return true;
+    }
+
+    // Collinear segments only touch if one reaches onto the other
+    let within = |p: (f64, f64), q: (f64, f64), r: (f64, f64)| {
+        r.0 >= p.0.min(q.0) && r.0 <= p.0.max(q.0) && r.1 >= p.1.min(q.1) && r.1 <= p.1.max(q.1)
+    };
+    (o1 == 0 && within(a, b, c)) || (o2 == 0 && within(a, b, d)) || (o3 == 0 && within(c, d, a)) || (o4 == 0 && within(c, d, b))
+}
+
+/// Returns the box reaching `radius_m` meters from `point` in every direction.
+pub fn bbox_around(point: (f64, f64), radius_m: f64) -> BBox {
+    let lat_margin = radius_m / METERS_PER_DEGREE;
+    let lon_margin = radius_m / (METERS_PER_DEGREE * point.0.to_radians().cos());
+
+    BBox { min_lat: point.0 - lat_margin, max_lat: point.0 + lat_margin, min_lon: point.1 - lon_margin, max_lon: point.1 + lon_margin }
+}
+
+/// Computes the distance in meters from `point` to the closest point of a polyline.
+///
+/// ## Returns
+/// * The distance, or `f64::INFINITY` if `points` is empty.
+pub fn distance_to_polyline(point: (f64, f64), points: &[(f64, f64)]) -> f64 {
+    closest_point_on_polyline(point, points).map(|(_, _, distance_m)| distance_m).unwrap_or(f64::INFINITY)
+}
+
+/// Finds the point of a polyline closest to `point`.
+///
+/// The polyline is projected onto a plane around `point`, with the longitude scaled by the
+/// cosine of the latitude, which is accurate for the short distances this is used for.
+///
+/// ## Returns
+/// * The index of the segment the closest point lies on, the closest `(lat, lon)` and its
+///   distance in meters, or `None` if `points` is empty. Of equally close segments the first wins.
+pub fn closest_point_on_polyline(point: (f64, f64), points: &[(f64, f64)]) -> Option<(usize, (f64, f64), f64)> {
+    let meters_per_degree_lon = METERS_PER_DEGREE * point.0.to_radians().cos();
+    let project = |p: (f64, f64)| ((p.1 - point.1) * meters_per_degree_lon, (p.0 - point.0) * METERS_PER_DEGREE);
+
+    if let [single] = points {
+        let (x, y) = project(*single);
+        return Some((0, *single, x.hypot(y)));
+    }
+
+    points.windows(2)
+        .enumerate()
+        .map(|(index, segment)| {
+            let (ax, ay) = project(segment[0]);
+            let (bx, by) = project(segment[1]);
+            let (dx, dy) = (bx - ax, by - ay);
+            let length_squared = dx * dx + dy * dy;
+
+            // The point is the origin, find the closest point on the segment to it
+            let t = if length_squared == 0.0 {
+                0.0
+            } else {
+                (-(ax * dx + ay * dy) / length_squared).clamp(0.0, 1.0)
+            };
+            (index, interpolate(segment[0], segment[1], t), (ax + dx * t).hypot(ay + dy * t))
+        })
+        .fold(None, |closest: Option<(usize, (f64, f64), f64)>, candidate| match closest {
+            Some(closest) if closest.2 <= candidate.2 => Some(closest),
+            _ => Some(candidate),
+        })
+}
+
+/// Picks the length of a scale bar: the largest 1, 2 or 5 times a power of ten meters
+/// that is not longer than `max_m`.
+///
+/// ## Returns
+/// * The length in meters, or 0 if `max_m` is not positive.
+pub fn round_scale_length(max_m: f64) -> f64 {
+    if max_m.is_nan() || max_m <= 0.0 || max_m.is_infinite() {
+        return 0.0;
+    }
+
+    let magnitude = 10f64.powf(max_m.log10().floor());
+    [5.0, 2.0, 1.0].iter()
+        .map(|step| step * magnitude)
+        .find(|&length| length <= max_m)
+        .unwrap_or(magnitude)
+}
+
+/// The latitude Web Mercator ends at, north and south, where the map is square.
+pub const MAX_MERCATOR_LAT: f64 = 85.051_128_78;
+
+// The graticule aims for about this many lines across the viewport. Its steps are powers of
+// ten degrees between these, and an axis that would take more lines than the limit is left out
+const GRATICULE_TARGET_LINES: f64 = 10.0;
+const MIN_GRATICULE_STEP: f64 = 1e-6;
+const MAX_GRATICULE_STEP: f64 = 10.0;
+const MAX_GRATICULE_LINES: f64 = 500.0;
+
+/// Picks the spacing of the graticule for a viewport spanning `span_deg` degrees: the power of
+/// ten closest to `GRATICULE_TARGET_LINES` lines, e.g. 1° for 10°, which makes 3 to 30 lines.
+///
+/// ## Returns
+/// * The step in degrees, between 1e-6 and 10, or 10 if `span_deg` is not positive.
+pub fn graticule_step(span_deg: f64) -> f64 {
+    if span_deg.is_nan() || span_deg <= 0.0 || span_deg.is_infinite() {
+        return MAX_GRATICULE_STEP;
+    }
+
+    10f64.powf((span_deg / GRATICULE_TARGET_LINES).log10().round()).clamp(MIN_GRATICULE_STEP, MAX_GRATICULE_STEP)
+}
+
+/// Whether a line of the graticule keeps its latitude or its longitude.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraticuleKind {
+    /// A line of constant latitude, running east to west.
+    Parallel,
+    /// A line of constant longitude, running north to south.
+    Meridian,
+}
+
+/// A line of the graticule across the viewport, see `graticule_lines`.
+///
+/// # Fields
+/// * `value` - The latitude or longitude of the line in degrees. Longitudes are wrapped into
+///   `[-180, 180)`, so past the antimeridian they count on from -180.
+/// * `from`, `to` - The `(lat, lon)` ends of the line at the edges of the viewport. Lines of
+///   either kind are straight in Web Mercator, so the ends are all it takes to draw them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GraticuleLine {
+    pub kind: GraticuleKind,
+    pub value: f64,
+    pub from: (f64, f64),
+    pub to: (f64, f64),
+}
+
+/// Lays out the lines of constant latitude and longitude at every multiple of `step` within
+/// a box, clipped to it.
+///
+/// The viewport may reach past the antimeridian, where longitudes go on beyond 180 as the
+/// projection continues the map. The lines there are drawn where they are and labelled with
+/// the longitude they stand for. Parallels end where Web Mercator does, at `MAX_MERCATOR_LAT`.
+///
+/// ## Arguments
+/// * `step` - The spacing in degrees, see `graticule_step`.
+///
+/// ## Returns
+/// * The parallels from south to north, then the meridians from west to east.
+pub fn graticule_lines(view: &BBox, step: f64) -> Vec<GraticuleLine> {
+    let BBox { min_lat, max_lat, min_lon, max_lon } = *view;
+    let (min_lat, max_lat) = (min_lat.max(-MAX_MERCATOR_LAT), max_lat.min(MAX_MERCATOR_LAT));
+    if step.is_nan() || step <= 0.0 || step.is_infinite() {
+        return Vec::new();
+    }
+
+    // Lines are counted in whole steps, so they land on round values without adding up
+    // rounding errors, and the same lines are found whatever the viewport
+    let multiples = |min: f64, max: f64| -> Vec<f64> {
+        let (first, last) = ((min / step).ceil(), (max / step).floor());
+        if last < first || last - first >= MAX_GRATICULE_LINES {
+            return Vec::new();
+        }
+        (first as i64..=last as i64).map(|multiple| multiple as f64 * step).collect()
+    };
+
+    let parallels = multiples(min_lat, max_lat).into_iter().map(|lat| GraticuleLine {
+        kind: GraticuleKind::Parallel,
+        value: lat,
+        from: (lat, min_lon),
+        to: (lat, max_lon),
+    });
+    let meridians = multiples(min_lon, max_lon).into_iter().map(|lon| GraticuleLine {
+        kind: GraticuleKind::Meridian,
+        value: (lon + 180.0).rem_euclid(360.0) - 180.0,
+        from: (max_lat, lon),
+        to: (min_lat, lon),
+    });
+    parallels.chain(meridians).collect()
+}
+
+/// Simplifies a polyline with the Douglas–Peucker algorithm.
+///
+/// ## Arguments
+/// * `points` - The `(lat, lon)` points of the polyline.
+/// * `tolerance` - The largest distance in degrees a dropped point may lie from the simplified line.
+///
+/// ## Returns
+/// * The kept points, always including the first and last one.
+pub fn simplify_polyline(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    let keep = douglas_peucker(points, tolerance);
+    points.iter().zip(keep).filter(|(_, keep)| *keep).map(|(&point, _)| point).collect()
+}
+
+/// Marks the points of a polyline the Douglas–Peucker algorithm keeps, see `simplify_polyline`.
+/// The points are treated as plane coordinates, so `tolerance` is in their unit.
+///
+/// ## Returns
+/// * Whether each point is kept, the first and last one always are.
+pub fn douglas_peucker(points: &[(f64, f64)], tolerance: f64) -> Vec<bool> {
+    if points.len() < 3 {
+        return vec![true; points.len()];
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    // Ranges still to be simplified, as (first, last) indices
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let (a, b) = (points[first], points[last]);
+
+        let farthest = (first + 1..last)
+            .map(|i| (i, planar_distance_to_segment(points[i], a, b)))
+            .max_by(|x, y| x.1.total_cmp(&y.1));
+
+        if let Some((i, distance)) = farthest {
+            if distance > tolerance {
+                keep[i] = true;
+                stack.push((first, i));
+                stack.push((i, last));
+            }
+        }
+    }
+
+    keep
+}
+
+/// Distance from `p` to the segment `a`-`b`, treating degrees as plane coordinates.
+fn planar_distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+
+    (p.0 - (a.0 + dx * t)).hypot(p.1 - (a.1 + dy * t))
+}
+
+/// Triangulates a polygon with holes by ear clipping, e.g. a building around a courtyard.
+/// The points are plane coordinates with `y` pointing up, e.g. normalized device coordinates.
+///
+/// Every hole is joined to the outline by a bridge, a pair of edges running to the hole and
+/// back, so the outline and its holes become a single ring with the holes left out of it.
+/// Rings may run either way around, and must not repeat their first point at the end. Holes
+/// outside of the outline are ignored.
+///
+/// ## Returns
+/// * The triangles, three indices each into the points of `outer` followed by those of every
+///   hole in turn, running counter clockwise.
+pub fn triangulate_polygon(outer: &[(f64, f64)], holes: &[Vec<(f64, f64)>]) -> Vec<usize> {
+    let points: Vec<(f64, f64)> = outer.iter().chain(holes.iter().flatten()).copied().collect();
+    if outer.len() < 3 {
+        return Vec::new();
+    }
+
+    // The ring and the holes as indices into the points
+    let mut ring: Vec<usize> = (0..outer.len()).collect();
+    if planar_signed_area(&points, &ring) < 0.0 {
+        ring.reverse();
+    }
+
+    // The holes run the other way around, and are bridged from the rightmost one on, so a
+    // bridge never crosses a hole bridged later
+    let mut start = outer.len();
+    let mut hole_rings: Vec<Vec<usize>> = Vec::with_capacity(holes.len());
+    for hole in holes {
+        let mut hole_ring: Vec<usize> = (start..start + hole.len()).collect();
+        start += hole.len();
+        if hole_ring.len() < 3 {
+            continue;
+        }
+        if planar_signed_area(&points, &hole_ring) > 0.0 {
+            hole_ring.reverse();
+        }
+        hole_rings.push(hole_ring);
+    }
+    let max_x = |ring: &[usize]| ring.iter().map(|&index| points[index].0).fold(f64::MIN, f64::max);
+    hole_rings.sort_by(|a, b| max_x(b).total_cmp(&max_x(a)));
+    for hole_ring in &hole_rings {
+        if let Some(bridged) = bridge_hole(&points, &ring, hole_ring) {
+            ring = bridged;
+        }
+    }
+
+    clip_ears(&points, &ring)
+}
+
+/// Twice the signed area of a ring of plane coordinates, positive if it runs counter clockwise.
+fn planar_signed_area(points: &[(f64, f64)], ring: &[usize]) -> f64 {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(&a, &b)| points[a].0 * points[b].1 - points[b].0 * points[a].1)
+        .sum()
+}
+
+/// Which side of the line from `a` to `b` the point `c` is on, positive on the left.
+fn cross(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// Whether `p` lies inside the counter clockwise triangle `a`, `b`, `c` or on its edges.
+fn in_triangle(p: (f64, f64), a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+/// Joins a clockwise hole to a counter clockwise ring around it, by a bridge from the
+/// rightmost point of the hole to a point of the ring it can see to its right.
+///
+/// ## Returns
+/// * The ring with the hole, or `None` if the hole is not inside the ring.
+fn bridge_hole(points: &[(f64, f64)], ring: &[usize], hole: &[usize]) -> Option<Vec<usize>> {
+    let hole_start = (0..hole.len()).max_by(|&a, &b| points[hole[a]].0.total_cmp(&points[hole[b]].0))?;
+    let m = points[hole[hole_start]];
+
+    // The closest edge of the ring a ray from the hole to the right hits, and where it hits
+    let mut hit: Option<(f64, usize)> = None;
+    for index in 0..ring.len() {
+        let (a, b) = (points[ring[index]], points[ring[(index + 1) % ring.len()]]);
+        if (a.1 > m.1) == (b.1 > m.1) || a.1 == b.1 {
+            continue;
+        }
+        let x = a.0 + (m.1 - a.1) / (b.1 - a.1) * (b.0 - a.0);
+        if x >= m.0 && hit.is_none_or(|(closest, _)| x < closest) {
+            hit = Some((x, index));
+        }
+    }
+    let (hit_x, edge) = hit?;
+
+    // The end of the edge further right is seen from the hole, unless a point of the ring
+    // pokes into the triangle between them, then the one at the smallest angle to the ray is
+    let (a, b) = (edge, (edge + 1) % ring.len());
+    let mut target = if points[ring[a]].0 > points[ring[b]].0 { a } else { b };
+    let hit_point = (hit_x, m.1);
+    let target_point = points[ring[target]];
+    let (corner_a, corner_b) = if target_point.1 < m.1 { (target_point, hit_point) } else { (hit_point, target_point) };
+    let angle = |point: (f64, f64)| ((point.1 - m.1).abs() / (point.0 - m.0), point.0 - m.0);
+    let mut best = angle(target_point);
+    for (index, point) in ring.iter().map(|&index| points[index]).enumerate() {
+        if index == target || point.0 <= m.0 || point == hit_point || !in_triangle(point, m, corner_a, corner_b) {
+            continue;
+        }
+        if angle(point) < best {
+            best = angle(point);
+            target = index;
+        }
+    }
+
+    // The ring runs to the bridge, around the hole and back over the bridge
+    let mut bridged = Vec::with_capacity(ring.len() + hole.len() + 2);
+    bridged.extend_from_slice(&ring[..=target]);
+    bridged.extend(hole[hole_start..].iter().chain(&hole[..=hole_start]));
+    bridged.extend_from_slice(&ring[target..]);
+    Some(bridged)
+}
+
+/// Cuts a counter clockwise ring, which may touch itself at its bridges, into triangles by
+/// clipping off one ear, a corner with no other point inside, at a time.
+///
+/// ## Returns
+/// * The triangles, three indices into `points` each. A ring left with no ear, e.g. one
+///   crossing itself, is triangulated as far as it goes.
+fn clip_ears(points: &[(f64, f64)], ring: &[usize]) -> Vec<usize> {
+    let mut remaining = ring.to_vec();
+    let mut triangles = Vec::with_capacity(ring.len().saturating_sub(2) * 3);
+    let corner = |remaining: &[usize], index: usize| {
+        let count = remaining.len();
+        (remaining[(index + count - 1) % count], remaining[index], remaining[(index + 1) % count])
+    };
+    let mut index = 0;
+    let mut misses = 0;
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        index %= count;
+        let (previous, current, next) = corner(&remaining, index);
+        let (a, b, c) = (points[previous], points[current], points[next]);
+
+        // The points of the bridges are there twice, and do not keep an ear from being clipped
+        let is_ear = cross(a, b, c) > 0.0 && remaining.iter()
+            .map(|&other| points[other])
+            .all(|p| p == a || p == b || p == c || !in_triangle(p, a, b, c));
+        if is_ear {
+            triangles.extend_from_slice(&[previous, current, next]);
+            remaining.remove(index);
+            misses = 0;
+            continue;
+        }
+
+        // A corner in a straight line, e.g. where a bridge leaves the ring, has no area to clip
+        misses += 1;
+        if misses >= count {
+            let is_straight = |&i: &usize| {
+                let (previous, current, next) = corner(&remaining, i);
+                cross(points[previous], points[current], points[next]) == 0.0
+            };
+            let Some(straight) = (0..count).find(is_straight) else {
+                break;
+            };
+            remaining.remove(straight);
+            misses = 0;
+            continue;
+        }
+        index += 1;
+    }
+
+    if let [a, b, c] = remaining[..] {
+        if cross(points[a], points[b], points[c]) > 0.0 {
+            triangles.extend_from_slice(&[a, b, c]);
+        }
+    }
+    triangles
+}
+
+/// The sphere radius of the Web Mercator projection.
+pub const MERCATOR_RADIUS_M: f64 = 6_378_137.0;
+
+/// Projects `(lat, lon)` to Web Mercator `(x, y)` in meters, with y growing northwards.
+pub fn lat_lon_to_mercator(lat: f64, lon: f64) -> (f64, f64) {
+    let x = MERCATOR_RADIUS_M * lon.to_radians();
+    let y = MERCATOR_RADIUS_M * (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln();
+    (x, y)
+}
+
+/// Inverse of `lat_lon_to_mercator`.
+pub fn mercator_to_lat_lon(x: f64, y: f64) -> (f64, f64) {
+    let lat = (2.0 * (y / MERCATOR_RADIUS_M).exp().atan() - std::f64::consts::FRAC_PI_2).to_degrees();
+    let lon = (x / MERCATOR_RADIUS_M).to_degrees();
+    (lat, lon)
+}
+
+/// The smallest width and height in Web Mercator meters of a box fitted by `fit_bbox_to_window`,
+/// so a box around a single node is not zoomed into without end.
+pub const MIN_FIT_SPAN_M: f64 = 200.0;
+
+/// Grows a box to the aspect ratio of a window, so it fills the window without being stretched.
+///
+/// The box keeps its center and grows along the side that is too short. The sides are measured
+/// in Web Mercator like the rendering, so a box far from the equator is not squashed either.
+///
+/// ## Arguments
+/// * `size` - The `(width, height)` of the window in pixels.
+pub fn fit_bbox_to_window(bbox: &BBox, (width, height): (u32, u32)) -> BBox {
+    let (left, top) = lat_lon_to_mercator(bbox.max_lat, bbox.min_lon);
+    let (right, bottom) = lat_lon_to_mercator(bbox.min_lat, bbox.max_lon);
+    let center = ((left + right) / 2.0, (top + bottom) / 2.0);
+
+    let mut half_width = (right - left).abs().max(MIN_FIT_SPAN_M) / 2.0;
+    let mut half_height = (top - bottom).abs().max(MIN_FIT_SPAN_M) / 2.0;
+    let aspect = width.max(1) as f64 / height.max(1) as f64;
+    if half_width / half_height < aspect {
+        half_width = half_height * aspect;
+    } else {
+        half_height = half_width / aspect;
+    }
+
+    mercator_bbox(center, half_width, half_height)
+}
+
+/// The box of latitudes and longitudes reaching `half_width` and `half_height` Web Mercator
+/// meters from `center` in every direction.
+fn mercator_bbox(center: (f64, f64), half_width: f64, half_height: f64) -> BBox {
+    let (max_lat, min_lon) = mercator_to_lat_lon(center.0 - half_width, center.1 + half_height);
+    let (min_lat, max_lon) = mercator_to_lat_lon(center.0 + half_width, center.1 - half_height);
+    BBox { min_lat, max_lat, min_lon, max_lon }
+}
+
+/// The deepest zoom level a permalink may have, as openstreetmap.org does not go further either.
+pub const MAX_PERMALINK_ZOOM: u8 = 24;
+
+/// A view of the map as openstreetmap.org writes it in its URLs, `#map=zoom/lat/lon`, to share
+/// the exact view with someone else.
+///
+/// The zoom level is that of `zoom_level`, rounded, so the view it describes has the longitude
+/// span of the one shared to within a factor of √2. The window it is opened in decides its
+/// latitude span, see `permalink_to_viewport`.
+///
+/// # Fields
+/// * `lat` - The latitude of the center of the view.
+/// * `lon` - The longitude of the center of the view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Permalink {
+    pub zoom: u8,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Permalink {
+    /// The permalink of a viewport, centered in Web Mercator like the rendering.
+    pub fn for_viewport(view: &BBox) -> Permalink {
+        let (left, top) = lat_lon_to_mercator(view.max_lat, view.min_lon);
+        let (right, bottom) = lat_lon_to_mercator(view.min_lat, view.max_lon);
+        let (lat, lon) = mercator_to_lat_lon((left + right) / 2.0, (top + bottom) / 2.0);
+
+        // A viewport narrower than a degree of the deepest zoom, or a broken one, still gets a zoom level
+        let zoom = zoom_level(view);
+        let zoom = if zoom.is_nan() { 0.0 } else { zoom.round().clamp(0.0, MAX_PERMALINK_ZOOM as f64) };
+        Permalink { zoom: zoom as u8, lat, lon }
+    }
+}
+
+impl fmt::Display for Permalink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // As openstreetmap.org does, a decimal more for every three zoom levels, about a
+        // tenth of the view at every zoom level
+        let decimals = (self.zoom / 3) as usize;
+        // Rounded first, so a coordinate just below zero is not written as -0.00
+        let scale = 10f64.powi(decimals as i32);
+        let round = |value: f64| (value * scale).round() / scale + 0.0;
+        write!(f, "#map={}/{:.*}/{:.*}", self.zoom, decimals, round(self.lat), decimals, round(self.lon))
+    }
+}
+
+/// Why a permalink could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PermalinkError {
+    /// There is no `map=`, neither at the start nor in the fragment of a URL.
+    MissingMap,
+    /// There are not exactly three parts separated by `/`, given how many there are.
+    PartCount(usize),
+    /// A part is not a number, given the part.
+    InvalidNumber(String),
+    ZoomOutOfRange(u64),
+    LatOutOfRange(f64),
+    LonOutOfRange(f64),
+}
+
+impl fmt::Display for PermalinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PermalinkError::MissingMap => write!(f, "a permalink looks like #map=zoom/lat/lon"),
+            PermalinkError::PartCount(count) => write!(f, "a permalink has a zoom, a latitude and a longitude, this one has {} parts", count),
+            PermalinkError::InvalidNumber(part) => write!(f, "'{}' is not a number", part),
+            PermalinkError::ZoomOutOfRange(zoom) => write!(f, "zoom {} is not between 0 and {}", zoom, MAX_PERMALINK_ZOOM),
+            PermalinkError::LatOutOfRange(lat) => write!(f, "latitude {} is not between -90 and 90", lat),
+            PermalinkError::LonOutOfRange(lon) => write!(f, "longitude {} is not between -180 and 180", lon),
+        }
+    }
+}
+
+impl StdError for PermalinkError {}
+
+impl FromStr for Permalink {
+    type Err = PermalinkError;
+
+    /// Reads `#map=zoom/lat/lon`, with or without the `#`, or a whole openstreetmap.org URL
+    /// ending in one. Anything after `&` in the fragment, e.g. `&layers=C`, is ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let fragment = s.rsplit_once('#').map_or(s, |(_, fragment)| fragment);
+        let map = fragment.split('&').find_map(|part| part.strip_prefix("map=")).ok_or(PermalinkError::MissingMap)?;
+
+        let parts: Vec<&str> = map.split('/').collect();
+        let [zoom, lat, lon] = parts[..] else {
+            return Err(PermalinkError::PartCount(parts.len()));
+        };
+        let number = |part: &str| part.parse::<f64>().ok().filter(|value| value.is_finite()).ok_or_else(|| PermalinkError::InvalidNumber(part.to_string()));
+
+        let zoom = zoom.parse::<u64>().map_err(|_| PermalinkError::InvalidNumber(zoom.to_string()))?;
+        let (lat, lon) = (number(lat)?, number(lon)?);
+        if zoom > MAX_PERMALINK_ZOOM as u64 {
+            return Err(PermalinkError::ZoomOutOfRange(zoom));
+        }
+        if !(-90.0..=90.0).contains(&lat) {
+            return Err(PermalinkError::LatOutOfRange(lat));
+        }
+        if !(-180.0..=180.0).contains(&lon) {
+            return Err(PermalinkError::LonOutOfRange(lon));
+        }
+        Ok(Permalink { zoom: zoom as u8, lat, lon })
+    }
+}
+
+/// Writes the permalink of a viewport, e.g. `#map=14/55.0309/11.3585`, see `Permalink`.
+pub fn viewport_to_permalink(view: &BBox) -> String {
+    Permalink::for_viewport(view).to_string()
+}
+
+/// The viewport a permalink describes in a window: its longitude span is the one of its zoom
+/// level, and its latitude span what the aspect ratio of the window leaves, in Web Mercator.
+/// A center beyond `MAX_MERCATOR_LAT` is moved onto it.
+///
+/// ## Arguments
+/// * `size` - The `(width, height)` of the window in pixels.
+///
+/// ## Returns
+/// * The viewport, whose `zoom_level` is the zoom of the permalink.
+pub fn permalink_to_viewport(permalink: &Permalink, (width, height): (u32, u32)) -> BBox {
+    let lat = permalink.lat.clamp(-MAX_MERCATOR_LAT, MAX_MERCATOR_LAT);
+    let center = lat_lon_to_mercator(lat, permalink.lon);
+
+    let lon_span = 360.0 / (permalink.zoom as f64).exp2();
+    let half_width = MERCATOR_RADIUS_M * (lon_span / 2.0).to_radians();
+    let half_height = half_width * height.max(1) as f64 / width.max(1) as f64;
+
+    mercator_bbox(center, half_width, half_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The box from -1 to 1 in both directions
+    const BOX: BBox = BBox { min_lat: -1.0, max_lat: 1.0, min_lon: -1.0, max_lon: 1.0 };
+
+    fn assert_points_eq(actual: &[(f64, f64)], expected: &[(f64, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} is not {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a.0 - e.0).abs() < 1e-12 && (a.1 - e.1).abs() < 1e-12, "{:?} is not {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn a_bbox_is_read_and_written_in_osm_order() {
+        let bbox: BBox = "12.5,55.6,12.7,55.7".parse().unwrap();
+        assert_eq!(bbox, BBox { min_lat: 55.6, max_lat: 55.7, min_lon: 12.5, max_lon: 12.7 });
+        assert_eq!(bbox.to_string(), "12.5,55.6,12.7,55.7");
+        assert_eq!(bbox.top_left(), (55.7, 12.5));
+        assert_eq!(bbox.bottom_right(), (55.6, 12.7));
+    }
+
+    #[test]
+    fn a_bbox_crossing_the_antimeridian_reaches_east_of_it() {
+        let bbox: BBox = "170,-10,-170,10".parse().unwrap();
+        assert_eq!((bbox.min_lon, bbox.max_lon), (170.0, 190.0));
+        assert_eq!(bbox.to_string(), "170,-10,-170,10");
+        assert_eq!(bbox.to_string().parse::<BBox>(), Ok(bbox));
+        assert_eq!(bbox.center(), (0.0, -180.0));
+    }
+
+    #[test]
+    fn a_bbox_crossing_the_antimeridian_contains_both_sides() {
+        let bbox: BBox = "170,-10,-170,10".parse().unwrap();
+        for lon in [170.0, 175.0, 180.0, -180.0, -175.0, -170.0, 185.0] {
+            assert!(bbox.contains(0.0, lon), "{} lies in {}", lon, bbox);
+        }
+        for lon in [0.0, 169.0, -169.0, 100.0] {
+            assert!(!bbox.contains(0.0, lon), "{} lies outside {}", lon, bbox);
+        }
+        assert!(!bbox.contains(11.0, 175.0));
+    }
+
+    #[test]
+    fn a_bbox_wider_than_the_world_contains_every_longitude() {
+        let bbox = BBox::new(-80.0, 80.0, -300.0, 300.0).unwrap();
+        assert!([-180.0, -90.0, 0.0, 90.0, 180.0].iter().all(|&lon| bbox.contains(0.0, lon)));
+    }
+
+    #[test]
+    fn expanding_a_bbox_stops_at_the_poles() {
+        let bbox = BBox::new(80.0, 89.0, 175.0, 185.0).unwrap().expand(0.5);
+        assert_eq!((bbox.min_lat, bbox.max_lat), (75.5, 90.0));
+        assert_eq!((bbox.min_lon, bbox.max_lon), (170.0, 190.0));
+        assert!(bbox.contains(85.0, -171.0));
+    }
+
+    #[test]
+    fn invalid_bboxes_are_rejected() {
+        assert_eq!("1,2,3".parse::<BBox>(), Err(BBoxError::ValueCount(3)));
+        assert_eq!("a,2,3,4".parse::<BBox>(), Err(BBoxError::InvalidNumber("a".to_string())));
+        assert_eq!("190,0,10,1".parse::<BBox>(), Err(BBoxError::LonOutOfRange(190.0)));
+        assert_eq!("0,-91,1,1".parse::<BBox>(), Err(BBoxError::LatOutOfRange(-91.0)));
+        assert_eq!("0,5,1,5".parse::<BBox>(), Err(BBoxError::Empty));
+        assert_eq!(BBox::new(0.0, f64::NAN, 0.0, 1.0), Err(BBoxError::NotFinite));
+        assert_eq!(BBox::from_center_and_span((89.0, 0.0), (4.0, 1.0)), Err(BBoxError::LatOutOfRange(91.0)));
+        assert_eq!(BBox { min_lat: 1.0, max_lat: 1.0, min_lon: 0.0, max_lon: 1.0 }.validated(), Err(BBoxError::Empty));
+        assert_eq!(BOX.validated(), Ok(BOX));
+    }
+
+    #[test]
+    fn a_bbox_is_centered_on_the_point_it_was_created_around() {
+        let bbox = BBox::from_center_and_span((55.0, 12.0), (0.2, 0.4)).unwrap();
+        assert_eq!(bbox, BBox { min_lat: 54.9, max_lat: 55.1, min_lon: 11.8, max_lon: 12.2 });
+        assert!((bbox.center().0 - 55.0).abs() < 1e-12 && (bbox.center().1 - 12.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn a_bbox_contains_the_boxes_within_its_edges() {
+        assert!(BOX.contains_bbox(&BOX));
+        assert!(BOX.contains_bbox(&BBox { min_lat: -0.5, max_lat: 0.5, min_lon: 0.0, max_lon: 1.0 }));
+        assert!(!BOX.contains_bbox(&BBox { min_lat: -0.5, max_lat: 0.5, min_lon: 0.0, max_lon: 1.5 }));
+        assert!(!BOX.contains_bbox(&BBox { min_lat: 2.0, max_lat: 3.0, min_lon: 2.0, max_lon: 3.0 }));
+    }
+
+    #[test]
+    fn bboxes_intersect_where_they_overlap_or_touch() {
+        assert!(BOX.intersects(&BBox { min_lat: 0.5, max_lat: 2.0, min_lon: 0.5, max_lon: 2.0 }));
+        assert!(BOX.intersects(&BBox { min_lat: 1.0, max_lat: 2.0, min_lon: -1.0, max_lon: 1.0 }));
+        assert!(BOX.intersects(&BBox { min_lat: -5.0, max_lat: 5.0, min_lon: -5.0, max_lon: 5.0 }));
+        assert!(!BOX.intersects(&BBox { min_lat: 1.5, max_lat: 2.0, min_lon: -1.0, max_lon: 1.0 }));
+        assert!(!BOX.intersects(&BBox { min_lat: -1.0, max_lat: 1.0, min_lon: 1.5, max_lon: 2.0 }));
+    }
+
+    #[test]
+    fn bboxes_intersect_across_the_antimeridian() {
+        let across: BBox = "170,-10,-170,10".parse().unwrap();
+        let east_of_it = BBox::new(-5.0, 5.0, -175.0, -160.0).unwrap();
+        let west_of_it = BBox::new(-5.0, 5.0, 160.0, 175.0).unwrap();
+        let elsewhere = BBox::new(-5.0, 5.0, 0.0, 10.0).unwrap();
+        for bbox in [east_of_it, west_of_it] {
+            assert!(across.intersects(&bbox) && bbox.intersects(&across), "{} overlaps {}", bbox, across);
+        }
+        assert!(!across.intersects(&elsewhere) && !elsewhere.intersects(&across));
+        assert!(!east_of_it.intersects(&west_of_it));
+    }
+
+    #[test]
+    fn the_union_of_bboxes_contains_both() {
+        let other = BBox { min_lat: 0.0, max_lat: 3.0, min_lon: -2.0, max_lon: 0.0 };
+        let union = BOX.union(&other);
+        assert_eq!(union, BBox { min_lat: -1.0, max_lat: 3.0, min_lon: -2.0, max_lon: 1.0 });
+        assert!(union.contains_bbox(&BOX) && union.contains_bbox(&other));
+        assert_eq!(BOX.union(&BOX), BOX);
+    }
+
+    #[test]
+    fn a_bbox_is_measured_in_meters() {
+        assert!((BOX.height_m() - 2.0 * METERS_PER_DEGREE).abs() < 1e-6);
+        assert!((BOX.width_m() - 2.0 * METERS_PER_DEGREE).abs() < 1e-6);
+        assert!((BOX.aspect() - 1.0).abs() < 1e-12);
+
+        // A degree of longitude is half as wide at 60 degrees north
+        let north = BBox::new(59.0, 61.0, 10.0, 12.0).unwrap();
+        assert!((north.height_m() - 2.0 * METERS_PER_DEGREE).abs() < 1e-6);
+        assert!((north.width_m() - METERS_PER_DEGREE).abs() < 1e-6);
+        assert!((north.aspect() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn a_polyline_inside_the_box_is_kept_whole() {
+        let line = [(0.0, -0.5), (0.5, 0.0), (0.0, 0.5)];
+        let parts = clip_polyline_to_bbox(&line, &BOX);
+        assert_eq!(parts.len(), 1);
+        assert_points_eq(&parts[0], &line);
+    }
+
+    #[test]
+    fn a_polyline_crossing_the_box_is_cut_at_its_edges() {
+        let parts = clip_polyline_to_bbox(&[(0.0, -3.0), (0.0, 3.0)], &BOX);
+        assert_eq!(parts.len(), 1);
+        assert_points_eq(&parts[0], &[(0.0, -1.0), (0.0, 1.0)]);
+
+        // Diagonally across a corner, entering at the bottom edge and leaving at the right one
+        let parts = clip_polyline_to_bbox(&[(-2.0, -1.0), (1.0, 2.0)], &BOX);
+        assert_eq!(parts.len(), 1);
+        assert_points_eq(&parts[0], &[(-1.0, 0.0), (0.0, 1.0)]);
+    }
+
+    #[test]
+    fn a_polyline_leaving_and_entering_again_is_split() {
+        let line = [(0.0, -0.5), (3.0, 0.0), (0.0, 0.5)];
+        let parts = clip_polyline_to_bbox(&line, &BOX);
+        assert_eq!(parts.len(), 2);
+        assert_points_eq(&parts[0], &[(0.0, -0.5), (1.0, -0.5 + 0.5 / 3.0)]);
+        assert_points_eq(&parts[1], &[(1.0, 0.5 - 0.5 / 3.0), (0.0, 0.5)]);
+    }
+
+    #[test]
+    fn a_polyline_outside_the_box_yields_nothing() {
+        assert!(clip_polyline_to_bbox(&[(2.0, -3.0), (2.0, 3.0), (3.0, 3.0)], &BOX).is_empty());
+        assert!(clip_polyline_to_bbox(&[(0.0, 0.0)], &BOX).is_empty());
+    }
+
+    /// A square of `side_m` meters with its south west corner at `(lat, lon)`, measured with
+    /// the mean length of a degree rather than the radius `polygon_area` uses.
+    fn square_of(side_m: f64, (lat, lon): (f64, f64)) -> Vec<(f64, f64)> {
+        let lat_span = side_m / METERS_PER_DEGREE;
+        let lon_span = side_m / (METERS_PER_DEGREE * (lat + lat_span / 2.0).to_radians().cos());
+        vec![(lat, lon), (lat, lon + lon_span), (lat + lat_span, lon + lon_span), (lat + lat_span, lon)]
+    }
+
+    #[test]
+    fn a_hundred_meter_square_covers_a_hectare() {
+        for corner in [(55.68, 12.57), (45.0, -93.0), (-33.87, 151.21), (0.0, 0.0), (69.6, 18.9)] {
+            let square = square_of(100.0, corner);
+            let area = polygon_area(&square);
+            assert!((area - 10_000.0).abs() < 100.0, "{} m² at {:?}", area, corner);
+
+            // Closed or open, either way round. The repeated point moves the mean latitude a little
+            let mut closed = square.clone();
+            closed.push(square[0]);
+            let reversed: Vec<(f64, f64)> = square.iter().rev().copied().collect();
+            assert!((polygon_area(&closed) - area).abs() < area * 1e-5);
+            assert!((polygon_area(&reversed) - area).abs() < 1e-6);
+        }
+
+        // Ten times the side is a hundred times the area
+        let large = polygon_area(&square_of(1000.0, (55.68, 12.57)));
+        assert!((large - 1_000_000.0).abs() < 10_000.0, "{}", large);
+
+        assert_eq!(polygon_area(&[(55.0, 12.0), (55.1, 12.1)]), 0.0);
+        assert_eq!(polygon_area(&[(55.0, 12.0), (55.1, 12.1), (55.2, 12.2)]), 0.0);
+        assert_eq!(format_area(10_000.0), "1.0 ha");
+        assert_eq!(format_area(2_500_000.0), "2.50 km²");
+        assert_eq!(format_area(420.4), "420 m²");
+    }
+
+    #[test]
+    fn a_polygon_covering_the_box_is_clipped_to_it() {
+        let square = [(-2.0, -2.0), (-2.0, 2.0), (2.0, 2.0), (2.0, -2.0), (-2.0, -2.0)];
+        let ring = clip_polygon_to_bbox(&square, &BOX);
+        assert_eq!(ring.first(), ring.last(), "a closed ring stays closed");
+        assert!((polygon_area(&ring) - polygon_area(&[(-1.0, -1.0), (-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)])).abs() < 1e-6);
+        assert!(ring.iter().all(|&(lat, lon)| lat.abs() <= 1.0 && lon.abs() <= 1.0));
+    }
+
+    #[test]
+    fn a_polygon_is_clipped_where_it_crosses_an_edge() {
+        // A triangle poking out of the right edge loses its tip, which leaves four corners
+        let triangle = [(-0.5, 0.0), (0.0, 2.0), (0.5, 0.0)];
+        let ring = clip_polygon_to_bbox(&triangle, &BOX);
+        assert_eq!(ring.len(), 4, "an open ring stays open: {:?}", ring);
+        assert!(ring.iter().all(|&(_, lon)| lon <= 1.0));
+        assert_eq!(ring.iter().filter(|&&(_, lon)| lon == 1.0).count(), 2);
+    }
+
+    #[test]
+    fn a_polygon_inside_the_box_is_kept_and_one_outside_vanishes() {
+        let inside = [(0.0, 0.0), (0.0, 0.5), (0.5, 0.5), (0.0, 0.0)];
+        assert_points_eq(&clip_polygon_to_bbox(&inside, &BOX), &inside);
+
+        let outside = [(2.0, 2.0), (2.0, 3.0), (3.0, 3.0), (2.0, 2.0)];
+        assert!(clip_polygon_to_bbox(&outside, &BOX).is_empty());
+    }
+
+    #[test]
+    fn a_bow_tie_is_detected_as_crossing_itself() {
+        let bow_tie = [(0.0, 0.0), (1.0, 1.0), (1.0, 0.0), (0.0, 1.0), (0.0, 0.0)];
+        assert!(ring_self_intersects(&bow_tie[..4]));
+        assert_eq!(sanitize_ring(&bow_tie), None);
+    }
+
+    #[test]
+    fn collinear_midpoints_and_duplicates_are_dropped() {
+        // Three points along the bottom edge, and the top right corner twice
+        let ring = [(0.0, 0.0), (0.0, 0.25), (0.0, 0.5), (0.0, 0.75), (0.0, 1.0), (1.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+        let square = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+        assert_points_eq(&sanitize_ring(&ring).unwrap(), &square);
+
+        // An open ring stays open
+        assert_points_eq(&sanitize_ring(&ring[..8]).unwrap(), &square[..4]);
+    }
+
+    #[test]
+    fn a_valid_concave_ring_is_kept_as_it_is() {
+        let l_shape = [(0.0, 0.0), (0.0, 2.0), (1.0, 2.0), (1.0, 1.0), (2.0, 1.0), (2.0, 0.0), (0.0, 0.0)];
+        assert!(!ring_self_intersects(&l_shape[..6]));
+        assert_points_eq(&sanitize_ring(&l_shape).unwrap(), &l_shape);
+    }
+
+    #[test]
+    fn spikes_are_removed_and_touching_corners_rejected() {
+        // The spike to (2, 1) doubles back to the corner it left from
+        let spike = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (2.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+        assert_points_eq(&sanitize_ring(&spike).unwrap(), &[(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)]);
+
+        // The corner at (0, 0.5) lies on the first edge
+        let touching = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.0, 0.5), (1.0, 0.0), (0.0, 0.0)];
+        assert_eq!(sanitize_ring(&touching), None);
+    }
+
+    #[test]
+    fn rings_left_with_fewer_than_three_corners_are_rejected() {
+        assert_eq!(sanitize_ring(&[]), None);
+        assert_eq!(sanitize_ring(&[(0.0, 0.0), (0.0, 0.0), (0.0, 0.0)]), None);
+        assert_eq!(sanitize_ring(&[(0.0, 0.0), (0.0, 1.0), (0.0, 2.0), (0.0, 0.0)]), None);
+    }
+
+    /// The lengths in meters of the pieces of polylines, and where they start along `points`.
+    fn piece_lengths(pieces: &[Vec<(f64, f64)>], points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+        pieces.iter()
+            .map(|piece| (haversine_distance(points[0], piece[0]), polyline_length(piece)))
+            .collect()
+    }
+
+    fn assert_lengths_eq(actual: &[(f64, f64)], expected: &[(f64, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} is not {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a.0 - e.0).abs() < 1e-6 && (a.1 - e.1).abs() < 1e-6, "{:?} is not {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn a_segment_is_cut_into_dashes_and_gaps() {
+        // 10 meters along the equator, in dashes of 3 and gaps of 2
+        let ten_m = 10.0 / haversine_distance((0.0, 0.0), (0.0, 1.0));
+        let segment = [(0.0, 0.0), (0.0, ten_m)];
+        let dashes = dash_polyline(&segment, 3.0, 2.0);
+        assert_lengths_eq(&piece_lengths(&dashes, &segment), &[(0.0, 3.0), (5.0, 3.0)]);
+        assert!(dashes.iter().all(|dash| dash.len() == 2));
+
+        // A dash cut short by the end of the line is kept
+        let dashes = dash_polyline(&segment, 4.0, 2.0);
+        assert_lengths_eq(&piece_lengths(&dashes, &segment), &[(0.0, 4.0), (6.0, 4.0)]);
+        let dashes = dash_polyline(&segment, 3.0, 1.0);
+        assert_lengths_eq(&piece_lengths(&dashes, &segment), &[(0.0, 3.0), (4.0, 3.0), (8.0, 2.0)]);
+
+        // Without a dash there is nothing to draw, without a gap the dashes touch
+        assert!(dash_polyline(&segment, 0.0, 2.0).is_empty());
+        assert!(dash_polyline(&segment, 3.0, -1.0).is_empty());
+        assert_eq!(dash_polyline(&segment, 3.0, 0.0).len(), 4);
+    }
+
+    #[test]
+    fn a_dash_follows_the_line_around_a_corner() {
+        let degree = haversine_distance((0.0, 0.0), (0.0, 1.0));
+        let corner = (0.0, 2.0 / degree);
+        let line = [(0.0, 0.0), corner, (2.0 / degree, 2.0 / degree)];
+        let dashes = dash_polyline(&line, 3.0, 2.0);
+
+        // The first dash turns the corner, the gap reaches the end
+        assert_eq!(dashes.len(), 1);
+        assert_eq!(dashes[0].len(), 3);
+        assert_eq!(dashes[0][1], corner);
+        assert!((polyline_length(&dashes[0]) - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn a_scale_bar_is_the_longest_round_length_that_fits() {
+        // A bar of at most 0.3 of a 1.7 km wide viewport stands for 500 m
+        assert_eq!(round_scale_length(0.3 * 1700.0), 500.0);
+        assert_eq!(round_scale_length(1999.0), 1000.0);
+        assert_eq!(round_scale_length(2000.0), 2000.0);
+        assert_eq!(round_scale_length(4999.0), 2000.0);
+        assert_eq!(round_scale_length(12_000.0), 10_000.0);
+        assert_eq!(round_scale_length(0.7), 0.5);
+    }
+
+    #[test]
+    fn a_scale_bar_has_no_length_without_a_positive_distance() {
+        for max_m in [0.0, -5.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(round_scale_length(max_m), 0.0, "{}", max_m);
+        }
+    }
+
+    #[test]
+    fn the_graticule_step_gives_about_ten_lines() {
+        let cases = [(100.0, 10.0), (30.0, 1.0), (10.0, 1.0), (3.0, 0.1), (1.0, 0.1), (0.02, 0.001), (0.001, 1e-4)];
+        for (span_deg, step) in cases {
+            assert!((graticule_step(span_deg) - step).abs() < step * 1e-9, "{} gave {}", span_deg, graticule_step(span_deg));
+        }
+
+        // Every zoom in between gives from about 3 to about 30 lines
+        let mut span_deg = 200.0;
+        while span_deg > 1e-4 {
+            let lines = span_deg / graticule_step(span_deg);
+            assert!((3.0..=32.0).contains(&lines), "{} lines across {}°", lines, span_deg);
+            span_deg *= 0.9;
+        }
+
+        // Clamped at either end, and sane for spans that are not
+        assert_eq!(graticule_step(360.0), 10.0);
+        assert_eq!(graticule_step(1e-9), 1e-6);
+        for span_deg in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(graticule_step(span_deg), 10.0, "{}", span_deg);
+        }
+    }
+
+    #[test]
+    fn graticule_lines_are_clipped_to_the_viewport() {
+        let view = BBox { min_lat: 55.2, max_lat: 57.9, min_lon: 10.5, max_lon: 13.3 };
+        let lines = graticule_lines(&view, 1.0);
+
+        let values = |kind: GraticuleKind| lines.iter().filter(|line| line.kind == kind).map(|line| line.value).collect::<Vec<f64>>();
+        assert_eq!(values(GraticuleKind::Parallel), [56.0, 57.0]);
+        assert_eq!(values(GraticuleKind::Meridian), [11.0, 12.0, 13.0]);
+        assert_eq!(lines[0], GraticuleLine { kind: GraticuleKind::Parallel, value: 56.0, from: (56.0, 10.5), to: (56.0, 13.3) });
+        assert_eq!(lines[2], GraticuleLine { kind: GraticuleKind::Meridian, value: 11.0, from: (57.9, 11.0), to: (55.2, 11.0) });
+
+        // Steps of a tenth of a degree are counted, not added up, so the last line is still in place
+        let tenths = graticule_lines(&BBox { min_lat: 55.0, max_lat: 55.35, min_lon: 12.0, max_lon: 12.05 }, 0.1);
+        let expected = [55.0, 55.1, 55.2, 55.3, 12.0];
+        assert_eq!(tenths.len(), expected.len());
+        assert!(tenths.iter().zip(expected).all(|(line, value)| (line.value - value).abs() < 1e-12));
+    }
+
+    #[test]
+    fn graticule_lines_past_the_antimeridian_and_the_poles() {
+        // Past 180 the map goes on, and the lines there count on from -180
+        let view = BBox { min_lat: -20.0, max_lat: -17.5, min_lon: 178.5, max_lon: 181.5 };
+        let meridians: Vec<(f64, f64)> = graticule_lines(&view, 1.0).iter()
+            .filter(|line| line.kind == GraticuleKind::Meridian)
+            .map(|line| (line.value, line.from.1))
+            .collect();
+        assert_eq!(meridians, [(179.0, 179.0), (-180.0, 180.0), (-179.0, 181.0)]);
+
+        // Parallels end where Web Mercator does
+        let polar = graticule_lines(&BBox { min_lat: 80.0, max_lat: 90.0, min_lon: 0.0, max_lon: 1.0 }, 1.0);
+        let parallels: Vec<f64> = polar.iter().filter(|line| line.kind == GraticuleKind::Parallel).map(|line| line.value).collect();
+        assert_eq!(parallels, [80.0, 81.0, 82.0, 83.0, 84.0, 85.0]);
+        assert!(polar.iter().all(|line| line.from.0 <= MAX_MERCATOR_LAT));
+
+        // A step far too fine for the viewport, or none at all, draws nothing
+        assert!(graticule_lines(&view, 1e-6).is_empty());
+        for step in [0.0, -1.0, f64::NAN] {
+            assert!(graticule_lines(&view, step).is_empty(), "{}", step);
+        }
+    }
+
+    #[test]
+    fn viewports_round_trip_through_permalinks_within_a_zoom_level() {
+        let window = (1200, 800);
+        // Sydney, the equator, Copenhagen and Svalbard, from a continent to a street
+        for (lat, lon) in [(-33.8688, 151.2093), (0.0, -78.5), (55.6761, 12.5683), (78.2232, 15.6267)] {
+            for span in [40.0, 3.7, 0.21, 0.013, 0.0009] {
+                let half_width = MERCATOR_RADIUS_M * (span / 2.0_f64).to_radians();
+                let view = mercator_bbox(lat_lon_to_mercator(lat, lon), half_width, half_width * 0.75);
+
+                let permalink: Permalink = viewport_to_permalink(&view).parse().unwrap();
+                let reopened = permalink_to_viewport(&permalink, window);
+                assert!((zoom_level(&reopened) - zoom_level(&view)).abs() <= 0.5 + 1e-9, "{:?} became {:?}", view, reopened);
+                assert!((zoom_level(&reopened) - permalink.zoom as f64).abs() < 1e-9);
+
+                // The center is kept to the decimals written, about a tenth of the view
+                let center = Permalink::for_viewport(&reopened);
+                assert!((center.lat - lat).abs() < span / 10.0 && (center.lon - lon).abs() < span / 10.0, "{} {} became {:?}", lat, lon, center);
+                // Shared again, the view gives the same permalink
+                assert_eq!(viewport_to_permalink(&reopened), permalink.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn permalinks_are_written_like_openstreetmap_org_does() {
+        let permalink = Permalink { zoom: 14, lat: 55.030_94, lon: 11.358_52 };
+        assert_eq!(permalink.to_string(), "#map=14/55.0309/11.3585");
+        assert_eq!(Permalink { zoom: 2, lat: 12.3, lon: -45.6 }.to_string(), "#map=2/12/-46");
+        assert_eq!(Permalink { zoom: 7, lat: -0.001, lon: -0.4 }.to_string(), "#map=7/0.00/-0.40");
+
+        // With or without the hash, or as a whole URL with more after the map
+        for text in ["#map=14/55.0309/11.3585", "map=14/55.0309/11.3585", " https://www.openstreetmap.org/#map=14/55.0309/11.3585&layers=C "] {
+            assert_eq!(text.parse(), Ok(Permalink { zoom: 14, lat: 55.0309, lon: 11.3585 }), "{}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_permalinks_are_rejected() {
+        let parse = |text: &str| text.parse::<Permalink>().unwrap_err();
+        assert_eq!(parse(""), PermalinkError::MissingMap);
+        assert_eq!(parse("#zoom=14/55/11"), PermalinkError::MissingMap);
+        assert_eq!(parse("#map=14/55"), PermalinkError::PartCount(2));
+        assert_eq!(parse("#map=14/55/11/3"), PermalinkError::PartCount(4));
+        assert_eq!(parse("#map=14.5/55/11"), PermalinkError::InvalidNumber("14.5".to_string()));
+        assert_eq!(parse("#map=-1/55/11"), PermalinkError::InvalidNumber("-1".to_string()));
+        assert_eq!(parse("#map=14/north/11"), PermalinkError::InvalidNumber("north".to_string()));
+        assert_eq!(parse("#map=14/55/NaN"), PermalinkError::InvalidNumber("NaN".to_string()));
+        assert_eq!(parse("#map=25/55/11"), PermalinkError::ZoomOutOfRange(25));
+        assert_eq!(parse("#map=14/90.5/11"), PermalinkError::LatOutOfRange(90.5));
+        assert_eq!(parse("#map=14/55/-181"), PermalinkError::LonOutOfRange(-181.0));
+        assert_eq!(parse("#map=25/55/11").to_string(), "zoom 25 is not between 0 and 24");
+    }
+
+    #[test]
+    fn a_permalink_near_the_pole_is_centered_on_the_edge_of_the_map() {
+        let view = permalink_to_viewport(&"#map=10/89.9/0".parse().unwrap(), (800, 800));
+        let center = Permalink::for_viewport(&view);
+        assert!((center.lat - MAX_MERCATOR_LAT).abs() < 1e-9 && center.lon.abs() < 1e-12, "{:?}", view);
+        assert!((zoom_level(&view) - 10.0).abs() < 1e-9);
+    }
+
+    /// The area of every triangle, checking that each runs counter clockwise.
+    fn triangle_areas(outer: &[(f64, f64)], holes: &[Vec<(f64, f64)>]) -> Vec<f64> {
+        let points: Vec<(f64, f64)> = outer.iter().chain(holes.iter().flatten()).copied().collect();
+        triangulate_polygon(outer, holes).chunks(3)
+            .map(|triangle| {
+                let area = cross(points[triangle[0]], points[triangle[1]], points[triangle[2]]) / 2.0;
+                assert!(area > 0.0, "{:?} runs clockwise", triangle);
+                area
+            })
+            .collect()
+    }
+
+    fn square((x, y): (f64, f64), side: f64) -> Vec<(f64, f64)> {
+        vec![(x, y), (x + side, y), (x + side, y + side), (x, y + side)]
+    }
+
+    #[test]
+    fn polygons_are_triangulated_around_their_holes() {
+        // (outline, holes, area)
+        let l_shape = vec![(0.0, 0.0), (2.0, 0.0), (2.0, 1.0), (1.0, 1.0), (1.0, 2.0), (0.0, 2.0)];
+        let u_shape = vec![(0.0, 0.0), (3.0, 0.0), (3.0, 3.0), (2.0, 3.0), (2.0, 1.0), (1.0, 1.0), (1.0, 3.0), (0.0, 3.0)];
+        let table = [
+            (square((0.0, 0.0), 4.0), vec![square((1.0, 1.0), 2.0)], 12.0),
+            (square((0.0, 0.0), 4.0), vec![square((0.5, 0.5), 1.0), square((2.5, 2.5), 1.0)], 14.0),
+            (l_shape, vec![], 3.0),
+            (u_shape, vec![square((0.25, 0.25), 0.5)], 6.75),
+        ];
+
+        for (outer, holes, area) in table {
+            let areas = triangle_areas(&outer, &holes);
+            assert!((areas.iter().sum::<f64>() - area).abs() < 1e-9, "{:?} with {:?}", outer, holes);
+            // Each bridge adds two points, every point beyond a triangle adds one triangle
+            assert_eq!(areas.len(), outer.len() + holes.iter().map(|hole| hole.len() + 2).sum::<usize>() - 2);
+
+            // Either way around
+            let reversed: Vec<(f64, f64)> = outer.iter().rev().copied().collect();
+            let reversed_holes: Vec<Vec<(f64, f64)>> = holes.iter().map(|hole| hole.iter().rev().copied().collect()).collect();
+            assert!((triangle_areas(&reversed, &reversed_holes).iter().sum::<f64>() - area).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn nothing_is_drawn_in_a_courtyard() {
+        let outer = square((0.0, 0.0), 4.0);
+        let holes = vec![square((1.0, 1.0), 2.0)];
+        let points: Vec<(f64, f64)> = outer.iter().chain(holes.iter().flatten()).copied().collect();
+
+        for triangle in triangulate_polygon(&outer, &holes).chunks(3) {
+            let (a, b, c) = (points[triangle[0]], points[triangle[1]], points[triangle[2]]);
+            let centroid = ((a.0 + b.0 + c.0) / 3.0, (a.1 + b.1 + c.1) / 3.0);
+            assert!(!(1.0..=3.0).contains(&centroid.0) || !(1.0..=3.0).contains(&centroid.1), "{:?}", centroid);
+        }
+    }
+
+    #[test]
+    fn holes_outside_and_rings_too_small_are_left_out() {
+        let outer = square((0.0, 0.0), 1.0);
+        assert!((triangle_areas(&outer, &[square((5.0, 5.0), 1.0)]).iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!((triangle_areas(&outer, &[vec![(0.2, 0.2), (0.4, 0.4)]]).iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(triangulate_polygon(&outer[..2], &[]).is_empty());
+    }
+}
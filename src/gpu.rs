@@ -0,0 +1,360 @@
+use std::env;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+/// Names the backends to try, e.g. `vulkan` or `dx12,gl`. Unset means the primary backends.
+pub const BACKEND_ENV: &str = "GMC_WGPU_BACKEND";
+/// `high`, `low` or `none`. Unset means a discrete GPU is preferred.
+pub const POWER_PREF_ENV: &str = "GMC_POWER_PREF";
+
+/// How often a lost or outdated surface is reconfigured before it is created anew.
+pub const SURFACE_RECONFIGURE_ATTEMPTS: u32 = 3;
+/// The wait before the first retry of a lost surface, doubled with every further failure.
+pub const SURFACE_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+/// The longest wait between two retries of a lost surface.
+pub const SURFACE_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// An error while setting up the GPU.
+#[derive(Debug)]
+pub enum GpuError {
+    /// No surface could be created for the window.
+    Surface(String),
+    /// None of the adapters of the backends can present to the surface.
+    NoAdapter { backends: wgpu::Backends, adapters: usize },
+    /// The adapter refused to create a device.
+    Device { adapter: String, message: String },
+    /// The adapter supports no format for the surface.
+    UnsupportedSurface { adapter: String },
+    /// A frame drawn into a texture could not be copied back from the GPU.
+    Readback(String),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::Surface(e) => write!(f, "Could not create a surface for the window: {}", e),
+            GpuError::NoAdapter { backends, adapters: 0 } => {
+                write!(f, "No graphics adapter found for the backends {:?}, check the drivers or set {}", backends, BACKEND_ENV)
+            }
+            GpuError::NoAdapter { backends, adapters } => {
+                write!(f, "None of the {} adapters for the backends {:?} supports this surface, try another backend with {}", adapters, backends, BACKEND_ENV)
+            }
+            GpuError::Device { adapter, message } => write!(f, "Could not open a device on {}: {}", adapter, message),
+            GpuError::UnsupportedSurface { adapter } => write!(f, "{} supports no format for this surface", adapter),
+            GpuError::Readback(e) => write!(f, "Could not read the frame back from the GPU: {}", e),
+        }
+    }
+}
+
+impl StdError for GpuError {}
+
+/// Which adapters to consider and which to prefer.
+///
+/// # Fields
+/// * `backends` - The backends whose adapters are enumerated.
+/// * `power_preference` - `HighPerformance` prefers discrete GPUs, `LowPower` integrated ones.
+#[derive(Debug, Clone, Copy)]
+pub struct GpuOptions {
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+}
+
+impl Default for GpuOptions {
+    fn default() -> Self {
+        GpuOptions {
+            backends: wgpu::Backends::PRIMARY,
+            power_preference: wgpu::PowerPreference::HighPerformance,
+        }
+    }
+}
+
+impl GpuOptions {
+    /// The default options, overridden by `GMC_WGPU_BACKEND` and `GMC_POWER_PREF`. Values that
+    /// cannot be parsed are ignored with a warning.
+    pub fn from_env() -> Self {
+        let mut options = GpuOptions::default();
+
+        if let Ok(value) = env::var(BACKEND_ENV) {
+            match parse_backends(&value) {
+                Ok(backends) => options.backends = backends,
+                Err(error) => warn!(variable = BACKEND_ENV, %error, "ignoring"),
+            }
+        }
+        if let Ok(value) = env::var(POWER_PREF_ENV) {
+            match parse_power_preference(&value) {
+                Ok(power_preference) => options.power_preference = power_preference,
+                Err(error) => warn!(variable = POWER_PREF_ENV, %error, "ignoring"),
+            }
+        }
+
+        options
+    }
+}
+
+/// Parses a comma separated list of backend names, e.g. `vulkan,gl`.
+pub fn parse_backends(value: &str) -> Result<wgpu::Backends, String> {
+    let mut backends = wgpu::Backends::empty();
+
+    for name in value.split(',').map(|name| name.trim().to_lowercase()).filter(|name| !name.is_empty()) {
+        backends |= match name.as_str() {
+            "vulkan" | "vk" => wgpu::Backends::VULKAN,
+            "dx12" | "d3d12" => wgpu::Backends::DX12,
+            "metal" | "mtl" => wgpu::Backends::METAL,
+            "opengl" | "gles" | "gl" => wgpu::Backends::GL,
+            "primary" => wgpu::Backends::PRIMARY,
+            "all" => wgpu::Backends::all(),
+            other => return Err(format!("unknown backend '{}', expected vulkan, dx12, metal, gl, primary or all", other)),
+        };
+    }
+
+    if backends.is_empty() {
+        return Err("no backend named".to_string());
+    }
+    Ok(backends)
+}
+
+pub fn parse_power_preference(value: &str) -> Result<wgpu::PowerPreference, String> {
+    match value.trim().to_lowercase().as_str() {
+        "high" => Ok(wgpu::PowerPreference::HighPerformance),
+        "low" => Ok(wgpu::PowerPreference::LowPower),
+        "none" => Ok(wgpu::PowerPreference::None),
+        other => Err(format!("unknown power preference '{}', expected high, low or none", other)),
+    }
+}
+
+/// Orders device types by preference, lower ranks first.
+fn device_type_rank(device_type: wgpu::DeviceType, power_preference: wgpu::PowerPreference) -> u8 {
+    match (power_preference, device_type) {
+        (wgpu::PowerPreference::LowPower, wgpu::DeviceType::IntegratedGpu) => 0,
+        (wgpu::PowerPreference::LowPower, wgpu::DeviceType::DiscreteGpu) => 1,
+        (_, wgpu::DeviceType::DiscreteGpu) => 0,
+        (_, wgpu::DeviceType::IntegratedGpu) => 1,
+        (_, wgpu::DeviceType::VirtualGpu) => 2,
+        (_, wgpu::DeviceType::Other) => 3,
+        (_, wgpu::DeviceType::Cpu) => 4,
+    }
+}
+
+/// Logs every adapter of the backends and picks the one to render with.
+///
+/// ## Arguments
+/// * `instance` - The instance to enumerate the adapters of.
+/// * `surface` - The surface the adapter has to present to.
+/// * `options` - The backends and the preferred kind of GPU.
+///
+/// ## Returns
+/// * The most preferred adapter supporting the surface, or an error naming what is missing.
+pub fn select_adapter(instance: &wgpu::Instance, surface: &wgpu::Surface<'_>, options: &GpuOptions) -> Result<wgpu::Adapter, GpuError> {
+    pick_adapter(instance, options, |adapter| adapter.is_surface_supported(surface))
+}
+
+/// Like `select_adapter`, for drawing into textures without a window.
+pub fn select_headless_adapter(instance: &wgpu::Instance, options: &GpuOptions) -> Result<wgpu::Adapter, GpuError> {
+    pick_adapter(instance, options, |_| true)
+}
+
+fn pick_adapter(instance: &wgpu::Instance, options: &GpuOptions, is_supported: impl Fn(&wgpu::Adapter) -> bool) -> Result<wgpu::Adapter, GpuError> {
+    let adapters = instance.enumerate_adapters(options.backends);
+    let adapter_count = adapters.len();
+
+    let mut candidates = Vec::new();
+    for adapter in adapters {
+        let adapter_info = adapter.get_info();
+        let supports_surface = is_supported(&adapter);
+        info!(
+            name = %adapter_info.name, backend = ?adapter_info.backend, device_type = ?adapter_info.device_type,
+            driver = %adapter_info.driver, driver_info = %adapter_info.driver_info, supports_surface,
+            "found adapter",
+        );
+
+        if supports_surface {
+            candidates.push(adapter);
+        }
+    }
+
+    // The adapters keep the order of their backends among equally preferred device types
+    candidates.sort_by_key(|adapter| device_type_rank(adapter.get_info().device_type, options.power_preference));
+    let adapter = candidates.into_iter().next()
+        .ok_or(GpuError::NoAdapter { backends: options.backends, adapters: adapter_count })?;
+
+    let adapter_info = adapter.get_info();
+    info!(name = %adapter_info.name, backend = ?adapter_info.backend, power_preference = ?options.power_preference, "selected adapter");
+    Ok(adapter)
+}
+
+/// Opens the device and its queue on the adapter, with timestamp queries if the adapter
+/// supports them, see `GpuTimer`.
+pub async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), GpuError> {
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                // WebGL doesn't support all of wgpu's features, so if
+                // we're building for the web we'll have to disable some.
+                required_limits: if cfg!(target_arch = "wasm32") {
+                    wgpu::Limits::downlevel_webgl2_defaults()
+                } else {
+                    wgpu::Limits::default()
+                },
+                memory_hints: Default::default(),
+            },
+            // Some(&std::path::Path::new("trace")), // Trace path
+            None,
+        )
+        .await
+        .map_err(|error| GpuError::Device { adapter: adapter.get_info().name, message: error.to_string() })
+}
+
+/// Builds the configuration of a surface for the adapter.
+///
+/// ## Returns
+/// * The configuration, or an error if the adapter supports no format for the surface.
+pub fn surface_config(surface: &wgpu::Surface<'_>, adapter: &wgpu::Adapter, size: winit::dpi::PhysicalSize<u32>) -> Result<wgpu::SurfaceConfiguration, GpuError> {
+    let surface_caps = surface.get_capabilities(adapter);
+    // Shader code in this tutorial assumes an Srgb surface texture. Using a different
+    // one will result all the colors comming out darker. If you want to support non
+    // Srgb surfaces, you'll need to account for that when drawing to the frame.
+    let surface_format = surface_caps
+        .formats
+        .iter()
+        .copied()
+        .find(|f| f.is_srgb())
+        .or(surface_caps.formats.first().copied())
+        .ok_or_else(|| GpuError::UnsupportedSurface { adapter: adapter.get_info().name })?;
+
+    Ok(wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: surface_format,
+        width: size.width,
+        height: size.height,
+        present_mode: surface_caps.present_modes.first().copied().unwrap_or(wgpu::PresentMode::Fifo),
+        alpha_mode: surface_caps.alpha_modes.first().copied().unwrap_or(wgpu::CompositeAlphaMode::Auto),
+        desired_maximum_frame_latency: 2,
+        view_formats: vec![],
+    })
+}
+
+/// The wait before the given retry of a lost surface, starting at 1.
+pub fn surface_retry_backoff(attempt: u32) -> Duration {
+    SURFACE_RETRY_BACKOFF.saturating_mul(1 << attempt.saturating_sub(1).min(16)).min(SURFACE_RETRY_MAX_BACKOFF)
+}
+
+// The timestamps written at the start and the end of the render pass
+const TIMESTAMP_COUNT: u32 = 2;
+
+/// Measures how long the GPU takes for the render pass, with timestamps written at its start
+/// and end. The timestamps are read back once the GPU is done, usually a frame later, and
+/// no new ones are written until then.
+///
+/// # Fields
+/// * `period_ns` - The nanoseconds between two ticks of the timestamps.
+/// * `resolved` - Whether the timestamps of this frame are copied to `readback_buffer`.
+/// * `mapped` - Set once the readback buffer is mapped, to whether that worked, or `None`
+///   while no readback is in flight.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    period_ns: f32,
+    resolved: bool,
+    mapped: Option<Arc<Mutex<Option<bool>>>>,
+}
+
+impl GpuTimer {
+    /// Creates the queries and their buffers.
+    ///
+    /// ## Returns
+    /// * The timer, or `None` if the device was opened without timestamp queries.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let size = TIMESTAMP_COUNT as u64 * wgpu::QUERY_SIZE as u64;
+        Some(GpuTimer {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Frame Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: TIMESTAMP_COUNT,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Frame Timestamps Resolve"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Frame Timestamps Readback"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            period_ns: queue.get_timestamp_period(),
+            resolved: false,
+            mapped: None,
+        })
+    }
+
+    /// The timestamps for the render pass to write, or `None` while the last ones are still
+    /// being read back.
+    pub fn timestamp_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.mapped.is_none().then_some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        })
+    }
+
+    /// Copies the timestamps written by the render pass to the readback buffer, after the pass.
+    /// Only call it if the pass wrote the timestamps of `timestamp_writes`.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..TIMESTAMP_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, self.resolve_buffer.size());
+        self.resolved = true;
+    }
+
+    /// Starts reading back the timestamps resolved, once the commands were submitted.
+    pub fn read_back(&mut self) {
+        if !std::mem::replace(&mut self.resolved, false) {
+            return;
+        }
+
+        let mapped = Arc::new(Mutex::new(None));
+        let callback_mapped = mapped.clone();
+        self.readback_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            if let Ok(mut mapped) = callback_mapped.lock() {
+                *mapped = Some(result.is_ok());
+            }
+        });
+        self.mapped = Some(mapped);
+    }
+
+    /// Checks whether the timestamps read back arrived, without waiting for the GPU.
+    ///
+    /// ## Returns
+    /// * How long the GPU took for the render pass the timestamps are of, or `None` if they
+    ///   did not arrive yet or could not be read.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Duration> {
+        let mapped = self.mapped.as_ref()?;
+        device.poll(wgpu::Maintain::Poll);
+        let result = mapped.lock().ok().and_then(|mapped| *mapped)?;
+        self.mapped = None;
+        if !result {
+            warn!("could not read the frame timestamps back");
+            return None;
+        }
+
+        let ticks = {
+            let range = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&range);
+            timestamps[1].saturating_sub(timestamps[0])
+        };
+        self.readback_buffer.unmap();
+        Some(Duration::from_nanos((ticks as f64 * self.period_ns as f64) as u64))
+    }
+}
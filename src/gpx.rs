@@ -0,0 +1,216 @@
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use std::fs::File;
+use std::io::BufReader;
+use std::error::Error;
+
+/// A single recorded position of a GPS track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpsPoint {
+    pub lat: f64,
+    pub lon: f64,
+    /// Elevation in meters, from the `<ele>` element.
+    pub elevation: Option<f64>,
+    /// The time the point was recorded, from the `<time>` element.
+    pub time: Option<String>,
+}
+
+/// A recorded GPS track, made of one or more continuous segments.
+///
+/// # Fields
+/// * `id` - The database id of the track, 0 for tracks that have not been stored yet.
+/// * `name` - The name of the track from the `<name>` element, if any.
+/// * `segments` - The points of every `<trkseg>` in recording order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GpsTrack {
+    pub id: i64,
+    pub name: Option<String>,
+    pub segments: Vec<Vec<GpsPoint>>,
+}
+
+/// The element whose text content is currently being read.
+enum TextTarget {
+    None,
+    TrackName,
+    Elevation,
+    Time,
+}
+
+/// Reads the `<lat>` and `<lon>` attributes of a `<trkpt>` element.
+fn parse_track_point(e: &quick_xml::events::BytesStart) -> Result<GpsPoint, Box<dyn Error>> {
+    let mut lat = None;
+    let mut lon = None;
+
+    for attr in e.attributes() {
+        match attr? {
+            a if a.key == quick_xml::name::QName(b"lat") => lat = Some(String::from_utf8(a.value.to_vec())?.parse()?),
+            a if a.key == quick_xml::name::QName(b"lon") => lon = Some(String::from_utf8(a.value.to_vec())?.parse()?),
+            _ => (),
+        }
+    }
+
+    match (lat, lon) {
+        (Some(lat), Some(lon)) => Ok(GpsPoint { lat, lon, elevation: None, time: None }),
+        _ => Err("trkpt is missing its lat or lon attribute".into()),
+    }
+}
+
+/// Reads the tracks from a GPX file.
+///
+/// ## Arguments
+/// * `path` - The path to the GPX file.
+///
+/// ## Returns
+/// * A result containing a vector of `GpsTrack` if successful, or an error if the reading fails.
+pub fn read_gpx_file(path: &str) -> Result<Vec<GpsTrack>, Box<dyn Error>> {
+    // Open the XML file
+    let file = File::open(path)?;
+    let mut reader = Reader::from_reader(BufReader::new(file));
+
+    let mut tracks: Vec<GpsTrack> = Vec::new();
+    let mut current_point: Option<GpsPoint> = None;
+    let mut text_target = TextTarget::None;
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            // A new track starts
+            Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"trk") => {
+                tracks.push(GpsTrack { id: 0, name: None, segments: Vec::new() });
+            }
+            // A new segment of the current track starts
+            Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"trkseg") => {
+                if let Some(track) = tracks.last_mut() {
+                    track.segments.push(Vec::new());
+                }
+            }
+            // A point with nested <ele> and <time> elements
+            Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"trkpt") => {
+                current_point = Some(parse_track_point(e)?);
+            }
+            // A self-closing point without elevation or time
+            Ok(Event::Empty(ref e)) if e.name() == quick_xml::name::QName(b"trkpt") => {
+                let point = parse_track_point(e)?;
+                if let Some(segment) = tracks.last_mut().and_then(|track| track.segments.last_mut()) {
+                    segment.push(point);
+                }
+            }
+            Ok(Event::End(ref e)) if e.name() == quick_xml::name::QName(b"trkpt") => {
+                if let (Some(point), Some(segment)) = (current_point.take(), tracks.last_mut().and_then(|track| track.segments.last_mut())) {
+                    segment.push(point);
+                }
+            }
+            // Elements whose text we are interested in
+            // Only the name of the track itself, not of the file metadata or waypoints
+            Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"name")
+                && current_point.is_none()
+                && tracks.last().is_some_and(|track| track.segments.is_empty()) => {
+                text_target = TextTarget::TrackName;
+            }
+            Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"ele") && current_point.is_some() => {
+                text_target = TextTarget::Elevation;
+            }
+            Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"time") && current_point.is_some() => {
+                text_target = TextTarget::Time;
+            }
+            Ok(Event::Text(ref e)) => {
+                let text = e.unescape()?.trim().to_string();
+                match text_target {
+                    TextTarget::TrackName => {
+                        if let Some(track) = tracks.last_mut() {
+                            track.name = Some(text);
+                        }
+                    }
+                    TextTarget::Elevation => {
+                        if let Some(point) = current_point.as_mut() {
+                            point.elevation = text.parse().ok();
+                        }
+                    }
+                    TextTarget::Time => {
+                        if let Some(point) = current_point.as_mut() {
+                            point.time = Some(text);
+                        }
+                    }
+                    TextTarget::None => (),
+                }
+            }
+            Ok(Event::End(_)) => text_target = TextTarget::None,
+            // End of the XML document
+            Ok(Event::Eof) => break,
+            // Handle errors
+            Err(e) => return Err(Box::new(e)),
+            _ => (),
+        }
+        // Clear buffer for the next read
+        buf.clear();
+    }
+
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::fetch_gps_tracks_in_bbox;
+    use crate::fetcher::{import_file, ImportOptions};
+    use crate::geo::BBox;
+    use crate::test_support::memory_pool;
+
+    // A track with a segment of points with elevation and time, and a self-closing one without
+    const TWO_SEGMENTS_GPX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test">
+ <metadata><name>Not the track</name></metadata>
+ <trk>
+  <name>Morning &amp; ride</name>
+  <trkseg>
+   <trkpt lat="55.0000" lon="12.0000"><ele>10.5</ele><time>2024-05-01T08:00:00Z</time></trkpt>
+   <trkpt lat="55.0010" lon="12.0010"><ele>11.0</ele><time>2024-05-01T08:00:10Z</time></trkpt>
+   <trkpt lat="55.0005" lon="12.0020"><ele>9.5</ele><time>2024-05-01T08:00:20Z</time></trkpt>
+  </trkseg>
+  <trkseg>
+   <trkpt lat="55.0100" lon="12.0100"/>
+   <trkpt lat="55.0090" lon="12.0110"/>
+  </trkseg>
+ </trk>
+</gpx>
+"#;
+
+    fn write_fixture(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("gmc_{}_{}.gpx", name, std::process::id()));
+        std::fs::write(&path, TWO_SEGMENTS_GPX).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn both_segments_are_read_in_recording_order() {
+        let path = write_fixture("gpx_parse");
+        let tracks = read_gpx_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(tracks.len(), 1);
+        let track = &tracks[0];
+        assert_eq!(track.name.as_deref(), Some("Morning & ride"));
+        assert_eq!(track.segments.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 2]);
+        assert_eq!(track.segments[0][1], GpsPoint { lat: 55.001, lon: 12.001, elevation: Some(11.0), time: Some("2024-05-01T08:00:10Z".to_string()) });
+        assert_eq!(track.segments[1][1], GpsPoint { lat: 55.009, lon: 12.011, elevation: None, time: None });
+    }
+
+    #[tokio::test]
+    async fn an_imported_track_reads_back_with_its_points_in_order() {
+        let path = write_fixture("gpx_round_trip");
+        let mut expected = read_gpx_file(&path).unwrap();
+        let pool = memory_pool("gpx_round_trip").await;
+        import_file(&pool, &path, &ImportOptions::default()).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // A box around the second segment alone finds the whole track
+        let bbox = BBox { min_lat: 55.008, max_lat: 55.011, min_lon: 12.009, max_lon: 12.012 };
+        let tracks = fetch_gps_tracks_in_bbox(&pool, &bbox).await.unwrap();
+        assert_eq!(tracks.len(), 1);
+        expected[0].id = tracks[0].id;
+        assert_eq!(tracks, expected);
+
+        let elsewhere = BBox { min_lat: 56.0, max_lat: 56.1, min_lon: 12.0, max_lon: 12.1 };
+        assert!(fetch_gps_tracks_in_bbox(&pool, &elsewhere).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::geo::BBox;
+
+/// How many viewports the history keeps, the oldest are dropped beyond that.
+pub const NAVIGATION_HISTORY_LIMIT: usize = 50;
+/// How long zooming has to stop before the viewport is recorded, so a scroll of many wheel
+/// steps is a single entry.
+pub const NAVIGATION_SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// A viewport, the box of latitudes and longitudes shown.
+pub type Viewport = BBox;
+
+/// The viewports navigated to, to walk back and forward through like the history of a
+/// browser.
+///
+/// Discrete jumps, e.g. clicking the minimap, are recorded with `push` right away. Continuous
+/// zooming only reports that the viewport `moved`, and the viewport is recorded once it has
+/// settled, so the history holds no viewports passed on the way.
+///
+/// # Fields
+/// * `entries` - The recorded viewports, oldest first.
+/// * `position` - The index of the entry shown, entries after it are the forward history.
+/// * `settle_at` - When the viewport moved continuously is recorded, if it moved since the last entry.
+#[derive(Debug, Default)]
+pub struct NavigationHistory {
+    entries: VecDeque<Viewport>,
+    position: usize,
+    settle_at: Option<Instant>,
+}
+
+impl NavigationHistory {
+    /// Records a viewport navigated to. The forward history is dropped, and the oldest entry
+    /// once there are more than `NAVIGATION_HISTORY_LIMIT`. The viewport shown is not recorded twice.
+    pub fn push(&mut self, viewport: Viewport) {
+        self.settle_at = None;
+        if self.entries.get(self.position) == Some(&viewport) {
+            return;
+        }
+
+        self.entries.truncate(self.position + 1);
+        self.entries.push_back(viewport);
+        if self.entries.len() > NAVIGATION_HISTORY_LIMIT {
+            self.entries.pop_front();
+        }
+        self.position = self.entries.len() - 1;
+    }
+
+    /// Notes that the viewport moved continuously, to be recorded by `settle` once it stops.
+    pub fn moved(&mut self, now: Instant) {
+        self.settle_at = Some(now + NAVIGATION_SETTLE_DELAY);
+    }
+
+    /// When the viewport that moved is due to be recorded, if it moved.
+    pub fn settles_at(&self) -> Option<Instant> {
+        self.settle_at
+    }
+
+    /// Records the viewport once it has not moved for `NAVIGATION_SETTLE_DELAY`.
+    ///
+    /// ## Returns
+    /// * Whether the viewport was recorded.
+    pub fn settle(&mut self, viewport: Viewport, now: Instant) -> bool {
+        if self.settle_at.is_some_and(|settle_at| now >= settle_at) {
+            self.push(viewport);
+            return true;
+        }
+        false
+    }
+
+    /// Records the viewport if it moved and has not settled yet, e.g. before a jump, so the
+    /// jump can be walked back to where the viewport was.
+    pub fn flush(&mut self, viewport: Viewport) {
+        if self.settle_at.is_some() {
+            self.push(viewport);
+        }
+    }
+
+    /// Walks one entry back.
+    ///
+    /// ## Arguments
+    /// * `viewport` - The viewport shown, recorded first if it moved since the last entry.
+    ///
+    /// ## Returns
+    /// * The viewport to show, or `None` at the oldest entry.
+    pub fn back(&mut self, viewport: Viewport) -> Option<Viewport> {
+        self.flush(viewport);
+        self.position = self.position.checked_sub(1)?;
+        self.entries.get(self.position).copied()
+    }
+
+    /// Walks one entry forward again.
+    ///
+    /// ## Arguments
+    /// * `viewport` - The viewport shown. If it moved since the last entry, it is recorded,
+    ///   which drops the forward history, so there is nothing to walk forward to.
+    ///
+    /// ## Returns
+    /// * The viewport to show, or `None` at the newest entry.
+    pub fn forward(&mut self, viewport: Viewport) -> Option<Viewport> {
+        self.flush(viewport);
+        if self.position + 1 >= self.entries.len() {
+            return None;
+        }
+        self.position += 1;
+        self.entries.get(self.position).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A viewport told apart from others by `index`.
+    fn viewport(index: usize) -> Viewport {
+        let lat = index as f64 * 0.01;
+        BBox { min_lat: lat, max_lat: lat + 0.01, min_lon: 12.0, max_lon: 12.02 }
+    }
+
+    #[test]
+    fn back_and_forward_walk_the_viewports_pushed() {
+        let mut history = NavigationHistory::default();
+        for index in 0..3 {
+            history.push(viewport(index));
+        }
+
+        assert_eq!(history.back(viewport(2)), Some(viewport(1)));
+        assert_eq!(history.back(viewport(1)), Some(viewport(0)));
+        assert_eq!(history.back(viewport(0)), None);
+        assert_eq!(history.forward(viewport(0)), Some(viewport(1)));
+        assert_eq!(history.forward(viewport(1)), Some(viewport(2)));
+        assert_eq!(history.forward(viewport(2)), None);
+        // Walking past either end stays at it
+        assert_eq!(history.back(viewport(2)), Some(viewport(1)));
+    }
+
+    #[test]
+    fn an_empty_history_goes_nowhere() {
+        let mut history = NavigationHistory::default();
+        assert_eq!(history.back(viewport(0)), None);
+        assert_eq!(history.forward(viewport(0)), None);
+    }
+
+    #[test]
+    fn navigating_after_going_back_drops_the_forward_history() {
+        let mut history = NavigationHistory::default();
+        for index in 0..3 {
+            history.push(viewport(index));
+        }
+        history.back(viewport(2));
+        history.back(viewport(1));
+
+        history.push(viewport(7));
+        assert_eq!(history.forward(viewport(7)), None);
+        assert_eq!(history.back(viewport(7)), Some(viewport(0)));
+        assert_eq!(history.back(viewport(0)), None);
+    }
+
+    #[test]
+    fn the_viewport_shown_is_not_recorded_twice() {
+        let mut history = NavigationHistory::default();
+        history.push(viewport(0));
+        history.push(viewport(1));
+        history.push(viewport(1));
+        assert_eq!(history.back(viewport(1)), Some(viewport(0)));
+        assert_eq!(history.back(viewport(0)), None);
+    }
+
+    #[test]
+    fn the_oldest_entries_are_dropped_beyond_the_limit() {
+        let mut history = NavigationHistory::default();
+        let pushed = NAVIGATION_HISTORY_LIMIT + 10;
+        for index in 0..pushed {
+            history.push(viewport(index));
+        }
+
+        let mut shown = viewport(pushed - 1);
+        let mut steps = 0;
+        while let Some(previous) = history.back(shown) {
+            shown = previous;
+            steps += 1;
+        }
+        assert_eq!(steps, NAVIGATION_HISTORY_LIMIT - 1);
+        assert_eq!(shown, viewport(10));
+    }
+
+    #[test]
+    fn continuous_moves_are_recorded_once_they_settle() {
+        let mut history = NavigationHistory::default();
+        let start = Instant::now();
+        history.push(viewport(0));
+        assert_eq!(history.settles_at(), None);
+
+        // Every step of a zoom pushes the moment it settles back
+        history.moved(start);
+        history.moved(start + Duration::from_millis(200));
+        assert_eq!(history.settles_at(), Some(start + Duration::from_millis(200) + NAVIGATION_SETTLE_DELAY));
+        assert!(!history.settle(viewport(1), start + Duration::from_millis(600)));
+        assert!(history.settle(viewport(2), start + Duration::from_millis(700)));
+        assert_eq!(history.settles_at(), None);
+        assert!(!history.settle(viewport(3), start + Duration::from_secs(5)));
+
+        // Only the settled viewport is an entry, not the ones passed on the way
+        assert_eq!(history.back(viewport(2)), Some(viewport(0)));
+        assert_eq!(history.forward(viewport(0)), Some(viewport(2)));
+    }
+
+    #[test]
+    fn walking_back_before_a_move_settled_records_it_first() {
+        let mut history = NavigationHistory::default();
+        history.push(viewport(0));
+        history.moved(Instant::now());
+
+        // The viewport zoomed to is recorded, so walking forward returns to it
+        assert_eq!(history.back(viewport(1)), Some(viewport(0)));
+        assert_eq!(history.forward(viewport(0)), Some(viewport(1)));
+
+        // A move after going back drops the forward history when walking forward
+        history.back(viewport(1));
+        history.moved(Instant::now());
+        assert_eq!(history.forward(viewport(5)), None);
+        assert_eq!(history.back(viewport(5)), Some(viewport(0)));
+    }
+}
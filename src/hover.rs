@@ -0,0 +1,266 @@
+use std::time::{Duration, Instant};
+
+use crate::osm_entities::Tag;
+
+/// How long the cursor has to rest on a way before it is highlighted and described.
+pub const HOVER_DELAY: Duration = Duration::from_millis(300);
+
+/// Ways within this many pixels of the cursor can be hovered once zoomed in, see `hover_radius_px`.
+pub const HOVER_RADIUS_PX: f64 = 8.0;
+/// Ways within this many pixels of the cursor can be hovered once zoomed out, where roads
+/// are drawn only a pixel or two wide.
+pub const HOVER_RADIUS_ZOOMED_OUT_PX: f64 = 14.0;
+// The zoom levels between which the radius shrinks from the zoomed out one to the zoomed in one
+const ZOOMED_OUT_LEVEL: f64 = 10.0;
+const ZOOMED_IN_LEVEL: f64 = 16.0;
+
+/// How far the tooltip is from the tip of the cursor, so the cursor does not cover it.
+pub const TOOLTIP_OFFSET_PX: f32 = 16.0;
+/// The space between the edge of the tooltip and its text.
+pub const TOOLTIP_PADDING_PX: f32 = 6.0;
+/// The longest description shown, longer ones are cut off with an ellipsis.
+pub const TOOLTIP_MAX_CHARS: usize = 48;
+
+/// What the cursor rests on, shown once it rested there for `HOVER_DELAY`.
+///
+/// The target is looked up as the cursor moves, but only shown after the delay, so moving
+/// the cursor across the map does not flicker. Moving the cursor, or the map under it, hides
+/// it again right away.
+///
+/// # Fields
+/// * `moved_at` - When the cursor or the map last moved, or `None` before the cursor moved.
+/// * `target` - What is under the cursor, with its id, or `None` if nothing is.
+/// * `shown` - Whether the target is shown.
+#[derive(Debug)]
+pub struct HoverState<T> {
+    moved_at: Option<Instant>,
+    target: Option<(i64, T)>,
+    shown: bool,
+}
+
+impl<T> Default for HoverState<T> {
+    fn default() -> Self {
+        HoverState { moved_at: None, target: None, shown: false }
+    }
+}
+
+impl<T> HoverState<T> {
+    /// The cursor or the map under it moved, which starts the wait over.
+    ///
+    /// ## Returns
+    /// * Whether a shown target was hidden, so the frame has to be redrawn.
+    pub fn moved(&mut self, now: Instant) -> bool {
+        self.moved_at = Some(now);
+        std::mem::replace(&mut self.shown, false)
+    }
+
+    /// Sets what is under the cursor now. The same target as before keeps being shown or
+    /// waited for, another one is waited for anew.
+    ///
+    /// ## Returns
+    /// * Whether the target changed.
+    pub fn set_target(&mut self, target: Option<(i64, T)>) -> bool {
+        let same = match (&self.target, &target) {
+            (Some((old, _)), Some((new, _))) => old == new,
+            (None, None) => true,
+            _ => false,
+        };
+        if same {
+            return false;
+        }
+
+        self.target = target;
+        self.shown = false;
+        true
+    }
+
+    /// Forgets the target, e.g. when the cursor left the window or the ways were reloaded.
+    ///
+    /// ## Returns
+    /// * Whether a shown target was hidden.
+    pub fn clear(&mut self) -> bool {
+        self.target = None;
+        std::mem::replace(&mut self.shown, false)
+    }
+
+    /// When the target is due to be shown, to wake the event loop for it, or `None` if there
+    /// is nothing to wait for.
+    pub fn due_at(&self) -> Option<Instant> {
+        if self.shown || self.target.is_none() {
+            return None;
+        }
+        self.moved_at.map(|moved_at| moved_at + HOVER_DELAY)
+    }
+
+    /// Shows the target once the cursor rested long enough.
+    ///
+    /// ## Returns
+    /// * Whether the target is shown from now on.
+    pub fn show_if_rested(&mut self, now: Instant) -> bool {
+        match self.due_at() {
+            Some(due_at) if now >= due_at => {
+                self.shown = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The target shown, with its id, or `None` while nothing is.
+    pub fn shown(&self) -> Option<&(i64, T)> {
+        self.target.as_ref().filter(|_| self.shown)
+    }
+}
+
+/// How close to the cursor a way has to be to be hovered. Zoomed out the radius grows, as
+/// lines get thin and hard to hit, zoomed in it is `HOVER_RADIUS_PX`.
+pub fn hover_radius_px(zoom: f64) -> f64 {
+    let zoomed_out = ((ZOOMED_IN_LEVEL - zoom) / (ZOOMED_IN_LEVEL - ZOOMED_OUT_LEVEL)).clamp(0.0, 1.0);
+    HOVER_RADIUS_PX + (HOVER_RADIUS_ZOOMED_OUT_PX - HOVER_RADIUS_PX) * zoomed_out
+}
+
+// The tags telling what kind of thing a way is, in the order they are looked for
+const KIND_KEYS: [&str; 12] = ["highway", "railway", "waterway", "aeroway", "building", "amenity", "leisure", "landuse", "natural", "place", "boundary", "barrier"];
+
+/// Describes a way in one line by its name and what it is, e.g. `Vestergade (highway=residential)`.
+pub fn describe_way(tags: &[Tag]) -> String {
+    let value_of = |key: &str| tags.iter().find(|tag| tag.key == key).map(|tag| tag.value.as_str());
+    let kind = KIND_KEYS.iter().find_map(|&key| value_of(key).map(|value| format!("{}={}", key, value)));
+
+    let description = match (value_of("name"), kind) {
+        (Some(name), Some(kind)) => format!("{} ({})", name, kind),
+        (Some(name), None) => name.to_string(),
+        (None, Some(kind)) => kind,
+        (None, None) => "unnamed way".to_string(),
+    };
+
+    if description.chars().count() > TOOLTIP_MAX_CHARS {
+        let cut: String = description.chars().take(TOOLTIP_MAX_CHARS - 1).collect();
+        format!("{}…", cut)
+    } else {
+        description
+    }
+}
+
+/// Places a tooltip next to the cursor, below and to the right of it unless it would not
+/// fit there, in which case it goes to the other side. It is kept within the window either way.
+///
+/// ## Arguments
+/// * `cursor` - The cursor in pixels, from the top left corner of the window.
+/// * `size` - The width and height of the tooltip in pixels.
+/// * `window` - The width and height of the window in pixels.
+///
+/// ## Returns
+/// * The top left corner of the tooltip in pixels.
+pub fn tooltip_anchor(cursor: (f32, f32), size: (f32, f32), window: (f32, f32)) -> (f32, f32) {
+    let place = |cursor: f32, size: f32, window: f32| {
+        let after = cursor + TOOLTIP_OFFSET_PX;
+        let start = if after + size <= window { after } else { cursor - TOOLTIP_OFFSET_PX - size };
+        // A tooltip larger than the window sticks to its top or left edge
+        start.min(window - size).max(0.0)
+    };
+    (place(cursor.0, size.0, window.0), place(cursor.1, size.1, window.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<Tag> {
+        pairs.iter().map(|&(key, value)| Tag::new(key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn a_target_is_shown_once_the_cursor_rested_on_it() {
+        let start = Instant::now();
+        let mut hover = HoverState::default();
+        assert!(!hover.moved(start));
+        assert!(hover.set_target(Some((10, "Vestergade"))));
+        assert_eq!(hover.due_at(), Some(start + HOVER_DELAY));
+
+        assert!(!hover.show_if_rested(start + HOVER_DELAY / 2));
+        assert!(hover.shown().is_none());
+        assert!(hover.show_if_rested(start + HOVER_DELAY));
+        assert_eq!(hover.shown(), Some(&(10, "Vestergade")));
+        // Nothing is left to wait for
+        assert_eq!(hover.due_at(), None);
+        assert!(!hover.show_if_rested(start + HOVER_DELAY * 2));
+
+        // The same way found again stays shown
+        assert!(!hover.set_target(Some((10, "Vestergade"))));
+        assert!(hover.shown().is_some());
+    }
+
+    #[test]
+    fn moving_hides_the_target_and_starts_the_wait_over() {
+        let start = Instant::now();
+        let mut hover = HoverState::default();
+        hover.moved(start);
+        hover.set_target(Some((10, ())));
+        hover.show_if_rested(start + HOVER_DELAY);
+
+        let later = start + Duration::from_secs(1);
+        assert!(hover.moved(later));
+        assert!(hover.shown().is_none());
+        assert!(!hover.moved(later));
+        assert!(!hover.show_if_rested(later + HOVER_DELAY / 2));
+        assert!(hover.show_if_rested(later + HOVER_DELAY));
+
+        // Another way is waited for anew, and no way has nothing to wait for
+        assert!(hover.set_target(Some((11, ()))));
+        assert!(hover.shown().is_none());
+        assert!(hover.set_target(None));
+        assert_eq!(hover.due_at(), None);
+    }
+
+    #[test]
+    fn clearing_forgets_the_target() {
+        let start = Instant::now();
+        let mut hover = HoverState::default();
+        // Without the cursor having moved there is no time to wait from
+        hover.set_target(Some((10, ())));
+        assert_eq!(hover.due_at(), None);
+
+        hover.moved(start);
+        hover.show_if_rested(start + HOVER_DELAY);
+        assert!(hover.clear());
+        assert!(hover.shown().is_none());
+        assert!(!hover.clear());
+        assert!(!hover.show_if_rested(start + HOVER_DELAY * 2));
+    }
+
+    #[test]
+    fn the_hover_radius_grows_as_the_map_is_zoomed_out() {
+        assert_eq!(hover_radius_px(19.0), HOVER_RADIUS_PX);
+        assert_eq!(hover_radius_px(ZOOMED_IN_LEVEL), HOVER_RADIUS_PX);
+        assert_eq!(hover_radius_px(13.0), (HOVER_RADIUS_PX + HOVER_RADIUS_ZOOMED_OUT_PX) / 2.0);
+        assert_eq!(hover_radius_px(ZOOMED_OUT_LEVEL), HOVER_RADIUS_ZOOMED_OUT_PX);
+        assert_eq!(hover_radius_px(4.0), HOVER_RADIUS_ZOOMED_OUT_PX);
+    }
+
+    #[test]
+    fn a_way_is_described_by_its_name_and_kind() {
+        assert_eq!(describe_way(&tags(&[("name", "Vestergade"), ("highway", "residential"), ("oneway", "yes")])), "Vestergade (highway=residential)");
+        // The first kind in the order of KIND_KEYS is used
+        assert_eq!(describe_way(&tags(&[("building", "yes"), ("amenity", "school")])), "building=yes");
+        assert_eq!(describe_way(&tags(&[("name", "Søen")])), "Søen");
+        assert_eq!(describe_way(&tags(&[("source", "survey")])), "unnamed way");
+
+        let long = describe_way(&tags(&[("name", &"Å".repeat(60))]));
+        assert_eq!(long.chars().count(), TOOLTIP_MAX_CHARS);
+        assert!(long.ends_with("Å…"));
+    }
+
+    #[test]
+    fn the_tooltip_stays_within_the_window() {
+        let window = (800.0, 600.0);
+        let size = (200.0, 30.0);
+        // Below and to the right of the cursor where it fits
+        assert_eq!(tooltip_anchor((100.0, 100.0), size, window), (116.0, 116.0));
+        // Near the bottom right corner it goes above and to the left
+        assert_eq!(tooltip_anchor((700.0, 590.0), size, window), (484.0, 544.0));
+        // Near the top left corner, with no room on the other side either
+        assert_eq!(tooltip_anchor((0.0, 0.0), size, window), (16.0, 16.0));
+        assert_eq!(tooltip_anchor((790.0, 300.0), (900.0, 30.0), window), (0.0, 316.0));
+    }
+}
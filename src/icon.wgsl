@@ -0,0 +1,43 @@
+// Draws the icons of points of interest, textured quads on the ground of the map.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+
+// The same camera as the map is drawn with, see `map.wgsl`.
+struct CameraUniform {
+    offset: vec2<f32>,
+    scale: vec2<f32>,
+    extrusion: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var t_icon: texture_2d<f32>;
+@group(1) @binding(1)
+var s_icon: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    let height = model.position.z * abs(camera.scale.y);
+    let xy = (model.position.xy - camera.offset) * camera.scale + vec2<f32>(0.0, height * camera.extrusion.x);
+    out.clip_position = vec4<f32>(xy, 0.5, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_icon, s_icon, in.tex_coords);
+}
@@ -0,0 +1,245 @@
+use crate::database::{RelationDetail, WayDetail};
+use crate::osm_entities::{Node, Tag};
+use crate::utils::{truncate, MapsType};
+
+/// The size of a character of the panel text in pixels, as laid out in a monospaced font.
+pub const CHAR_WIDTH_PX: u32 = 8;
+pub const LINE_HEIGHT_PX: u32 = 16;
+/// Tag values longer than this many characters are cut off, e.g. long `description`s or
+/// lists of opening hours.
+pub const MAX_VALUE_CHARS: usize = 120;
+
+// Lines continuing a wrapped line are indented by this much, so they read as one entry
+const CONTINUATION_INDENT: &str = "  ";
+
+/// How much text fits into the panel.
+///
+/// # Fields
+/// * `columns` - The characters on a line.
+/// * `rows` - The lines on a page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PanelSize {
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl PanelSize {
+    /// The text fitting into a panel of `width` by `height` pixels, at least a character.
+    pub fn from_pixels(width: u32, height: u32) -> Self {
+        PanelSize {
+            columns: (width / CHAR_WIDTH_PX).max(1) as usize,
+            rows: (height / LINE_HEIGHT_PX).max(1) as usize,
+        }
+    }
+}
+
+/// Breaks a text into lines of at most `columns` characters, at spaces where there are any.
+/// Lines after the first are indented by `CONTINUATION_INDENT`.
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let columns = columns.max(CONTINUATION_INDENT.len() + 1);
+    let mut lines = Vec::new();
+    let mut rest: Vec<char> = text.chars().collect();
+
+    loop {
+        let indent = if lines.is_empty() { "" } else { CONTINUATION_INDENT };
+        let width = columns - indent.len();
+        if rest.len() <= width {
+            lines.push(format!("{}{}", indent, rest.iter().collect::<String>()));
+            return lines;
+        }
+
+        // Break after the last space that fits, or in the middle of a word without one
+        let break_at = rest[..=width].iter().rposition(|&c| c == ' ').filter(|&space| space > 0).unwrap_or(width);
+        let line: String = rest[..break_at].iter().collect();
+        lines.push(format!("{}{}", indent, line.trim_end()));
+        rest.drain(..break_at);
+        while rest.first() == Some(&' ') {
+            rest.remove(0);
+        }
+    }
+}
+
+/// The number of pages `line_count` lines take up, at least one.
+pub fn page_count(line_count: usize, rows: usize) -> usize {
+    line_count.div_ceil(rows.max(1)).max(1)
+}
+
+/// The lines on a page, the last page if `page` is beyond it.
+pub fn page_of(lines: &[String], rows: usize, page: usize) -> &[String] {
+    let rows = rows.max(1);
+    let page = page.min(page_count(lines.len(), rows) - 1);
+    let start = (page * rows).min(lines.len());
+    &lines[start..(start + rows).min(lines.len())]
+}
+
+/// The lines describing an element: its type and id, version, timestamp and user, then every
+/// tag as `key = value` sorted by key, with values cut off at `MAX_VALUE_CHARS`.
+pub fn entity_lines(maps_type: &MapsType, id: i64, version: i32, timestamp: &str, user: &str, tags: &[Tag]) -> Vec<String> {
+    let mut lines = vec![
+        format!("{} {}", maps_type.as_str(), id),
+        format!("version {}", version),
+        format!("timestamp {}", timestamp),
+        format!("user {}", user),
+        String::new(),
+    ];
+
+    let mut tags: Vec<&Tag> = tags.iter().collect();
+    tags.sort_by(|a, b| a.key.cmp(&b.key).then_with(|| a.value.cmp(&b.value)));
+    if tags.is_empty() {
+        lines.push("no tags".to_string());
+    }
+    lines.extend(tags.iter().map(|tag| format!("{} = {}", tag.key, truncate(&tag.value, MAX_VALUE_CHARS))));
+    lines
+}
+
+/// The element shown in the inspection panel, and the page of its lines shown.
+///
+/// # Fields
+/// * `maps_type`, `id` - The element.
+/// * `lines` - What is known about the element, see `entity_lines`, before wrapping.
+/// * `page` - The page shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    pub maps_type: MapsType,
+    pub id: i64,
+    pub lines: Vec<String>,
+    pub page: usize,
+}
+
+impl Inspection {
+    pub fn of_node(node: &Node) -> Self {
+        Inspection::new(MapsType::Node, node.id, entity_lines(&MapsType::Node, node.id, node.version, &node.timestamp, &node.user, &node.tags))
+    }
+
+    pub fn of_way(way: &WayDetail) -> Self {
+        Inspection::new(MapsType::Way, way.id, entity_lines(&MapsType::Way, way.id, way.version, &way.timestamp, &way.user, &way.tags))
+    }
+
+    pub fn of_relation(relation: &RelationDetail) -> Self {
+        Inspection::new(MapsType::Relation, relation.id, entity_lines(&MapsType::Relation, relation.id, relation.version, &relation.timestamp, &relation.user, &relation.tags))
+    }
+
+    fn new(maps_type: MapsType, id: i64, lines: Vec<String>) -> Self {
+        Inspection { maps_type, id, lines, page: 0 }
+    }
+
+    /// The lines wrapped to the width of the panel.
+    pub fn wrapped_lines(&self, size: PanelSize) -> Vec<String> {
+        self.lines.iter().flat_map(|line| wrap(line, size.columns)).collect()
+    }
+
+    /// The number of pages the lines take up in the panel.
+    pub fn page_count(&self, size: PanelSize) -> usize {
+        page_count(self.wrapped_lines(size).len(), size.rows)
+    }
+
+    /// The lines on the page shown.
+    pub fn visible_lines(&self, size: PanelSize) -> Vec<String> {
+        page_of(&self.wrapped_lines(size), size.rows, self.page).to_vec()
+    }
+
+    /// Turns the pages, forward for a positive `pages`, staying within the pages there are.
+    ///
+    /// ## Returns
+    /// * Whether another page is shown now.
+    pub fn scroll(&mut self, pages: isize, size: PanelSize) -> bool {
+        let last_page = self.page_count(size) - 1;
+        let page = self.page.min(last_page).saturating_add_signed(pages).min(last_page);
+        let changed = page != self.page;
+        self.page = page;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(key: &str, value: &str) -> Tag {
+        Tag::new(key.to_string(), value.to_string())
+    }
+
+    fn numbered_lines(count: usize) -> Vec<String> {
+        (0..count).map(|index| index.to_string()).collect()
+    }
+
+    #[test]
+    fn lines_wrap_at_spaces_and_continue_indented() {
+        assert_eq!(wrap("name = Main Street", 40), ["name = Main Street"]);
+        assert_eq!(wrap("", 10), [""]);
+        assert_eq!(wrap("name = Old Main Street", 12), ["name = Old", "  Main", "  Street"]);
+        // A word longer than the line is broken inside it
+        assert_eq!(wrap("abcdefghijkl", 5), ["abcde", "  fgh", "  ijk", "  l"]);
+
+        for line in wrap("opening_hours = Mo-Fr 08:00-18:00; Sa 09:00-14:00; PH off", 16) {
+            assert!(line.chars().count() <= 16, "{line:?}");
+        }
+        // Too narrow for the indent still makes progress
+        assert!(wrap("a b c d", 1).iter().all(|line| !line.trim().is_empty()));
+    }
+
+    #[test]
+    fn the_lines_of_an_element_list_its_tags_sorted_and_cut_off() {
+        let long_value = "x".repeat(MAX_VALUE_CHARS + 10);
+        let tags = [tag("name", "Main Street"), tag("description", &long_value), tag("highway", "primary")];
+        let lines = entity_lines(&MapsType::Way, 42, 3, "2024-01-01T00:00:00Z", "mapper", &tags);
+
+        assert_eq!(lines[..5], ["way 42", "version 3", "timestamp 2024-01-01T00:00:00Z", "user mapper", ""]);
+        assert!(lines[5].starts_with("description = xxx"));
+        assert_eq!(lines[5].chars().count(), "description = ".len() + MAX_VALUE_CHARS);
+        assert!(lines[5].ends_with('…'));
+        assert_eq!(lines[6..], ["highway = primary", "name = Main Street"]);
+
+        let lines = entity_lines(&MapsType::Node, 7, 1, "", "", &[]);
+        assert_eq!(lines.last().map(String::as_str), Some("no tags"));
+    }
+
+    #[test]
+    fn lines_are_split_into_pages_of_the_panel_height() {
+        let lines = numbered_lines(7);
+        assert_eq!(page_count(lines.len(), 3), 3);
+        assert_eq!(page_count(6, 3), 2);
+        assert_eq!(page_count(0, 3), 1);
+        assert_eq!(page_count(5, 0), 5);
+
+        assert_eq!(page_of(&lines, 3, 0), ["0", "1", "2"]);
+        assert_eq!(page_of(&lines, 3, 2), ["6"]);
+        // Beyond the last page shows the last page
+        assert_eq!(page_of(&lines, 3, 9), ["6"]);
+        assert!(page_of(&[], 3, 0).is_empty());
+
+        assert_eq!(PanelSize::from_pixels(320, 100), PanelSize { columns: 40, rows: 6 });
+        assert_eq!(PanelSize::from_pixels(0, 0), PanelSize { columns: 1, rows: 1 });
+    }
+
+    #[test]
+    fn scrolling_stays_within_the_pages() {
+        let size = PanelSize { columns: 40, rows: 4 };
+        let mut inspection = Inspection::new(MapsType::Node, 1, numbered_lines(10));
+        assert_eq!(inspection.page_count(size), 3);
+        assert_eq!(inspection.visible_lines(size), ["0", "1", "2", "3"]);
+
+        assert!(!inspection.scroll(-1, size));
+        assert!(inspection.scroll(1, size));
+        assert_eq!(inspection.visible_lines(size), ["4", "5", "6", "7"]);
+        assert!(inspection.scroll(5, size));
+        assert_eq!((inspection.page, inspection.visible_lines(size)), (2, vec!["8".to_string(), "9".to_string()]));
+        assert!(!inspection.scroll(1, size));
+
+        // A taller panel has fewer pages, and the page shown is kept within them
+        let taller = PanelSize { columns: 40, rows: 8 };
+        assert!(inspection.scroll(-1, taller));
+        assert_eq!(inspection.page, 0);
+    }
+
+    #[test]
+    fn wrapped_lines_take_up_more_pages() {
+        let inspection = Inspection::new(MapsType::Way, 1, vec!["name = a rather long name of a street".to_string(), "x".to_string()]);
+        let narrow = PanelSize { columns: 12, rows: 2 };
+        let wrapped = inspection.wrapped_lines(narrow);
+        assert!(wrapped.len() > 2);
+        assert!(wrapped[1].starts_with(CONTINUATION_INDENT));
+        assert_eq!(wrapped.last().map(String::as_str), Some("x"));
+        assert_eq!(inspection.page_count(narrow), page_count(wrapped.len(), 2));
+    }
+}
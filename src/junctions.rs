@@ -0,0 +1,463 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::num::NonZeroI64;
+use std::sync::OnceLock;
+
+use tracing::debug;
+
+use crate::geo::distance_to_polyline;
+use crate::osm_entities::{RenderableWay, SimpleNode};
+
+// Lines meeting at a node are tessellated one by one, so where their quads meet they leave a
+// crack or overlap. Lines that merely continue each other are joined into one polyline first,
+// and the ends of lines meeting at true intersections are reported so they can be extended.
+
+/// Counts the node ids referenced by more than one line, i.e. the junctions between lines.
+///
+/// The repeated first node of a closed line is counted once.
+///
+/// ## Arguments
+/// * `lines` - The node ids of every line, see `RenderableWay::node_ids`.
+pub fn shared_node_ids<'a>(lines: impl IntoIterator<Item = &'a [Option<NonZeroI64>]>) -> HashSet<i64> {
+    let mut counts: HashMap<i64, usize> = HashMap::new();
+
+    for line in lines {
+        let mut seen = HashSet::new();
+        for id in line.iter().flatten().map(|id| id.get()) {
+            if seen.insert(id) {
+                *counts.entry(id).or_insert(0) += 1;
+            }
+        }
+    }
+
+    counts.into_iter()
+        .filter(|&(_, count)| count > 1)
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Joins lines that meet end to end into continuous polylines.
+///
+/// Two lines are joined at a node only if the node is an end of both and no other of the
+/// lines references it, so intersections are left as they are. The shared node appears once
+/// in the joined line.
+///
+/// ## Arguments
+/// * `lines` - Lines drawn alike, e.g. the ways of one style.
+///
+/// ## Returns
+/// * The joined lines, with the lines that could not be joined unchanged.
+pub fn merge_lines_at_junctions(lines: Vec<Vec<SimpleNode>>) -> Vec<Vec<SimpleNode>> {
+    let node_ids: Vec<Vec<Option<i64>>> = lines.iter().map(|line| line.iter().map(|node| node.id).collect()).collect();
+
+    chain_lines(&node_ids, true).iter()
+        .map(|chain| join_chain(chain, &lines))
+        .collect()
+}
+
+/// Finds the chains of lines meeting end to end, see `merge_lines_at_junctions`.
+///
+/// ## Arguments
+/// * `lines` - The node ids of every line, `None` for nodes without one.
+/// * `may_reverse` - Whether a line may be turned around to continue another, which a oneway street may not.
+///
+/// ## Returns
+/// * Every chain as the indices of its lines in order, with whether the line is turned
+///   around in it. Every line is in exactly one chain, and the first line of every chain
+///   keeps its direction.
+pub fn chain_lines(lines: &[Vec<Option<i64>>], may_reverse: bool) -> Vec<Vec<(usize, bool)>> {
+    // Which ends of which lines every node is, and how often it is passed through instead
+    let mut ends: HashMap<i64, Vec<(usize, bool)>> = HashMap::new();
+    let mut passed_through: HashSet<i64> = HashSet::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        if line.len() < 2 {
+            continue;
+        }
+        for (position, id) in line.iter().enumerate() {
+            let Some(id) = *id else {
+                continue;
+            };
+            if position == 0 || position == line.len() - 1 {
+                ends.entry(id).or_default().push((index, position != 0));
+            } else {
+                passed_through.insert(id);
+            }
+        }
+    }
+
+    // The end of the other line continuing a line at its given end, if any. A line keeps its
+    // direction if it is met at the other kind of end, its start after an end
+    let continuation = |index: usize, at_end: bool| -> Option<(usize, bool)> {
+        let id = if at_end { lines[index].last() } else { lines[index].first() };
+        let id = (*id?)?;
+        let meeting = ends.get(&id)?;
+
+        if meeting.len() != 2 || passed_through.contains(&id) {
+            return None;
+        }
+        meeting.iter().copied()
+            .find(|&(other, _)| other != index)
+            .filter(|&(_, other_at_end)| may_reverse || other_at_end != at_end)
+    };
+
+    let mut consumed = vec![false; lines.len()];
+    let mut chains = Vec::new();
+
+    for start in 0..lines.len() {
+        if consumed[start] {
+            continue;
+        }
+        consumed[start] = true;
+        let mut chain = VecDeque::from([(start, false)]);
+
+        // Grow the chain past the end of the line, then past its start
+        for (open_end, forwards) in [((start, true), true), ((start, false), false)] {
+            let (mut index, mut at_end) = open_end;
+
+            while let Some((next, next_at_end)) = continuation(index, at_end) {
+                if consumed[next] {
+                    break;
+                }
+                consumed[next] = true;
+
+                // Past the end a line met at its end is turned around, before the start one met at its start
+                if forwards {
+                    chain.push_back((next, next_at_end));
+                } else {
+                    chain.push_front((next, !next_at_end));
+                }
+                (index, at_end) = (next, !next_at_end);
+            }
+        }
+
+        chains.push(chain.into());
+    }
+
+    chains
+}
+
+/// Joins the points of a chain of lines found by `chain_lines`, with the points shared by
+/// lines following each other once.
+pub fn join_chain<T: Clone, L: AsRef<[T]>>(chain: &[(usize, bool)], lines: &[L]) -> Vec<T> {
+    let mut joined: Vec<T> = Vec::new();
+    for &(index, reversed) in chain {
+        let line = lines[index].as_ref();
+        // The shared point is already the last point of the joined line
+        let skip = usize::from(!joined.is_empty());
+        if reversed {
+            joined.extend(line.iter().rev().skip(skip).cloned());
+        } else {
+            joined.extend(line.iter().skip(skip).cloned());
+        }
+    }
+    joined
+}
+
+// The tags telling what a line is. Ways are only merged with ways of the same kind, and
+// ways without one, e.g. the outlines of areas, are left alone
+const KIND_KEYS: [&str; 3] = ["highway", "railway", "waterway"];
+// Besides their kind, merged ways agree on these, as they decide how a way is drawn and named
+const CLASS_KEYS: [&str; 5] = ["layer", "bridge", "tunnel", "oneway", "name"];
+
+/// Which ways a merged way was made of, see `merge_contiguous_ways`.
+///
+/// # Fields
+/// * `parts` - For every merged way by its id, the id of every way in it with the index in
+///   its `coords` that way starts at, in order. Ways left as they were are not in it.
+#[derive(Debug, Default)]
+pub struct WayOrigins {
+    parts: HashMap<i64, Vec<(i64, usize)>>,
+}
+
+impl WayOrigins {
+    /// The number of ways merged into others.
+    pub fn merged_count(&self) -> usize {
+        self.parts.values().map(|parts| parts.len() - 1).sum()
+    }
+
+    /// The id of the imported way closest to a point of a way, e.g. the way picked with the
+    /// cursor. It is the id of the way itself unless it was merged from several.
+    pub fn original_id(&self, way: &RenderableWay, point: (f64, f64)) -> i64 {
+        let Some(parts) = self.parts.get(&way.id) else {
+            return way.id;
+        };
+
+        // A part ends where the next one starts, at the point they share
+        parts.iter().enumerate()
+            .map(|(index, &(id, start))| {
+                let end = parts.get(index + 1).map_or(way.coords.len(), |&(_, next_start)| next_start + 1);
+                (id, distance_to_polyline(point, &way.coords[start..end]))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(way.id, |(id, _)| id)
+    }
+}
+
+/// What a way has to agree on with the ways it is merged with, or `None` if it is not merged.
+fn way_class(way: &RenderableWay) -> Option<Vec<Option<&str>>> {
+    let value_of = |key: &str| way.tags.iter().find(|tag| tag.key == key).map(|tag| tag.value.as_str());
+
+    // Closed ways are rings, and a way missing nodes may not reach the node it seems to end at
+    let is_closed = way.node_ids.len() > 1 && way.node_ids.first() == way.node_ids.last();
+    if !way.is_complete() || is_closed || way.coords.len() < 2 || value_of("area") == Some("yes") {
+        return None;
+    }
+
+    let (kind_key, kind) = KIND_KEYS.iter().find_map(|&key| value_of(key).map(|value| (key, value)))?;
+    let mut class = vec![Some(kind_key), Some(kind)];
+    class.extend(CLASS_KEYS.iter().map(|&key| value_of(key)));
+    Some(class)
+}
+
+/// Joins lines split into several ways, e.g. a road split where its speed limit changes,
+/// so there are fewer and longer ways to draw and index.
+///
+/// Ways are joined at a node that ends both if they are of the same kind, see `KIND_KEYS`,
+/// and agree on `CLASS_KEYS`. Ways are turned around to connect unless they are oneway. Where
+/// more than two such ways meet, or one passes through, none of them is joined there, so the
+/// ways of a junction can still be told apart when picking.
+///
+/// ## Returns
+/// * The ways, with every joined line as one way with the id of its first way and the tags
+///   all of its ways have, and where the ways in it start, see `WayOrigins::original_id`.
+pub fn merge_contiguous_ways(ways: Vec<RenderableWay>) -> (Vec<RenderableWay>, WayOrigins) {
+    let mut groups: HashMap<Vec<Option<&str>>, Vec<usize>> = HashMap::new();
+    for (index, way) in ways.iter().enumerate() {
+        if let Some(class) = way_class(way) {
+            groups.entry(class).or_default().push(index);
+        }
+    }
+
+    let mut chains: Vec<Vec<(usize, bool)>> = Vec::new();
+    for members in groups.into_values() {
+        // The ways of a group agree on `oneway`
+        let may_reverse = ways[members[0]].tags.iter().find(|tag| tag.key == "oneway").is_none_or(|tag| tag.value == "no");
+        let node_ids: Vec<Vec<Option<i64>>> = members.iter()
+            .map(|&index| ways[index].node_ids.iter().map(|id| id.map(NonZeroI64::get)).collect())
+            .collect();
+
+        chains.extend(chain_lines(&node_ids, may_reverse).into_iter()
+            .filter(|chain| chain.len() > 1)
+            .map(|chain| chain.into_iter().map(|(member, reversed)| (members[member], reversed)).collect()));
+    }
+
+    // A merged way takes the place of its first way, the others are dropped
+    let coords: Vec<&Vec<(f64, f64)>> = ways.iter().map(|way| &way.coords).collect();
+    let node_ids: Vec<&Vec<Option<NonZeroI64>>> = ways.iter().map(|way| &way.node_ids).collect();
+    let mut origins = WayOrigins::default();
+    let mut merged: HashMap<usize, RenderableWay> = HashMap::new();
+    let mut dropped = vec![false; ways.len()];
+    for chain in chains {
+        let (first, _) = chain[0];
+        let mut parts = Vec::with_capacity(chain.len());
+        let mut start = 0;
+        for &(index, _) in &chain {
+            parts.push((ways[index].id, start));
+            start += ways[index].coords.len() - 1;
+            dropped[index] = true;
+        }
+
+        let tags = ways[first].tags.iter()
+            .filter(|tag| chain.iter().all(|&(index, _)| ways[index].tags.iter().any(|other| other.key == tag.key && other.value == tag.value)))
+            .cloned()
+            .collect();
+        let way = RenderableWay { id: ways[first].id, coords: join_chain(&chain, &coords), node_ids: join_chain(&chain, &node_ids), tags, missing_nodes: 0, inner_rings: Vec::new() };
+        origins.parts.insert(way.id, parts);
+        merged.insert(first, way);
+    }
+
+    let ways = ways.into_iter().enumerate()
+        .filter_map(|(index, way)| match merged.remove(&index) {
+            Some(merged) => Some(merged),
+            None => (!dropped[index]).then_some(way),
+        })
+        .collect();
+    (ways, origins)
+}
+
+/// Set `GMC_MERGE_WAYS=1` to merge the ways continuing each other after fetching them, see
+/// `merge_contiguous_ways`.
+pub const MERGE_WAYS_ENV: &str = "GMC_MERGE_WAYS";
+
+/// Merges the ways with `merge_contiguous_ways` if `GMC_MERGE_WAYS=1`, else hands them back
+/// as they are. The variable is read once.
+pub fn merge_ways_if_enabled(ways: Vec<RenderableWay>) -> (Vec<RenderableWay>, WayOrigins) {
+    static MERGE_WAYS: OnceLock<bool> = OnceLock::new();
+    if !*MERGE_WAYS.get_or_init(|| env::var(MERGE_WAYS_ENV).is_ok_and(|value| value == "1")) {
+        return (ways, WayOrigins::default());
+    }
+
+    let count = ways.len();
+    let (ways, origins) = merge_contiguous_ways(ways);
+    debug!(ways = count, merged = origins.merged_count(), "merged the ways continuing each other");
+    (ways, origins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::Tag;
+
+    fn node(id: i64, lon: f64) -> SimpleNode {
+        SimpleNode { id: Some(id), lat: 55.0, lon }
+    }
+
+    fn road(id: i64, nodes: &[SimpleNode]) -> RenderableWay {
+        RenderableWay::from_nodes(id, nodes, vec![Tag::new("highway".to_string(), "residential".to_string())], 0)
+    }
+
+    #[test]
+    fn collinear_lines_sharing_an_end_become_one_line_with_the_shared_node_once() {
+        // The second line runs the other way, so it is turned around to continue the first
+        let lines = vec![
+            vec![node(1, 12.0), node(2, 12.001)],
+            vec![node(3, 12.002), node(2, 12.001)],
+        ];
+
+        let merged = merge_lines_at_junctions(lines);
+
+        assert_eq!(merged.len(), 1);
+        let ids: Vec<Option<i64>> = merged[0].iter().map(|node| node.id).collect();
+        assert_eq!(ids, [Some(1), Some(2), Some(3)]);
+        let lons: Vec<f64> = merged[0].iter().map(|node| node.lon).collect();
+        assert_eq!(lons, [12.0, 12.001, 12.002]);
+    }
+
+    #[test]
+    fn lines_meeting_at_an_intersection_are_left_apart() {
+        // Three lines end at node 2, and a fourth passes through node 5 where two others end
+        let lines = vec![
+            vec![Some(1), Some(2)],
+            vec![Some(2), Some(3)],
+            vec![Some(2), Some(4)],
+            vec![Some(5), Some(6)],
+            vec![Some(6), Some(7)],
+            vec![Some(8), Some(6), Some(9)],
+        ];
+
+        let chains = chain_lines(&lines, true);
+
+        assert_eq!(chains.len(), lines.len());
+        assert!(chains.iter().all(|chain| chain.len() == 1));
+        let node_ids: Vec<Vec<Option<NonZeroI64>>> = lines.iter()
+            .map(|line| line.iter().map(|id| id.and_then(NonZeroI64::new)).collect())
+            .collect();
+        assert_eq!(shared_node_ids(node_ids.iter().map(Vec::as_slice)), HashSet::from([2, 6]));
+    }
+
+    #[test]
+    fn oneway_lines_are_only_chained_in_their_direction() {
+        let head_to_head = vec![vec![Some(1), Some(2)], vec![Some(3), Some(2)]];
+        assert_eq!(chain_lines(&head_to_head, false).len(), 2);
+        assert_eq!(chain_lines(&head_to_head, true), vec![vec![(0, false), (1, true)]]);
+
+        // Found from the second line, the first is put before it
+        let in_a_row = vec![vec![Some(2), Some(3)], vec![Some(1), Some(2)]];
+        assert_eq!(chain_lines(&in_a_row, false), vec![vec![(1, false), (0, false)]]);
+    }
+
+    #[test]
+    fn merged_ways_keep_where_each_original_way_starts() {
+        let first = road(10, &[node(1, 12.0), node(2, 12.001)]);
+        let second = road(11, &[node(2, 12.001), node(3, 12.002), node(4, 12.003)]);
+        let mut building = road(12, &[node(4, 12.003), node(5, 12.004)]);
+        building.tags = vec![Tag::new("building".to_string(), "yes".to_string())];
+
+        let (ways, origins) = merge_contiguous_ways(vec![first, second, building]);
+
+        assert_eq!(ways.len(), 2);
+        assert_eq!(origins.merged_count(), 1);
+        let merged = ways.iter().find(|way| way.id == 10).unwrap();
+        assert_eq!(merged.coords.len(), 4);
+        assert_eq!(merged.node_ids.iter().map(|id| id.unwrap().get()).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(origins.original_id(merged, (55.0, 12.0004)), 10);
+        assert_eq!(origins.original_id(merged, (55.0, 12.0025)), 11);
+    }
+
+    fn tagged(mut way: RenderableWay, key: &str, value: &str) -> RenderableWay {
+        way.tags.push(Tag::new(key.to_string(), value.to_string()));
+        way
+    }
+
+    fn node_ids(way: &RenderableWay) -> Vec<i64> {
+        way.node_ids.iter().map(|id| id.unwrap().get()).collect()
+    }
+
+    #[test]
+    fn three_collinear_residential_ways_become_one() {
+        let ways = vec![
+            road(10, &[node(1, 12.0), node(2, 12.001)]),
+            road(11, &[node(2, 12.001), node(3, 12.002)]),
+            road(12, &[node(3, 12.002), node(4, 12.003)]),
+        ];
+
+        let (ways, origins) = merge_contiguous_ways(ways);
+
+        assert_eq!(ways.len(), 1);
+        assert_eq!((ways[0].id, node_ids(&ways[0])), (10, vec![1, 2, 3, 4]));
+        assert_eq!(ways[0].coords.iter().map(|&(_, lon)| lon).collect::<Vec<_>>(), [12.0, 12.001, 12.002, 12.003]);
+        assert_eq!(origins.merged_count(), 2);
+        assert_eq!(origins.original_id(&ways[0], (55.0, 12.0026)), 12);
+    }
+
+    #[test]
+    fn ways_meeting_at_a_t_junction_are_not_merged() {
+        // 11 and 12 continue 10 at node 2, and 13 passes through node 5 where 14 ends
+        let side = |id: i64, nodes: &[(i64, f64, f64)]| road(id, &nodes.iter().map(|&(id, lat, lon)| SimpleNode { id: Some(id), lat, lon }).collect::<Vec<_>>());
+        let ways = vec![
+            road(10, &[node(1, 12.0), node(2, 12.001)]),
+            road(11, &[node(2, 12.001), node(3, 12.002)]),
+            side(12, &[(2, 55.0, 12.001), (4, 55.001, 12.001)]),
+            road(13, &[node(6, 12.004), node(5, 12.005), node(7, 12.006)]),
+            side(14, &[(5, 55.0, 12.005), (8, 55.001, 12.005)]),
+        ];
+
+        let (merged, origins) = merge_contiguous_ways(ways.clone());
+
+        assert_eq!(origins.merged_count(), 0);
+        assert_eq!(merged.iter().map(|way| (way.id, node_ids(way))).collect::<Vec<_>>(), ways.iter().map(|way| (way.id, node_ids(way))).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn a_way_running_the_other_way_is_turned_around_to_continue() {
+        let ways = vec![
+            road(10, &[node(1, 12.0), node(2, 12.001)]),
+            road(11, &[node(4, 12.003), node(3, 12.002), node(2, 12.001)]),
+        ];
+
+        let (ways, _) = merge_contiguous_ways(ways);
+
+        assert_eq!(ways.len(), 1);
+        assert_eq!(node_ids(&ways[0]), [1, 2, 3, 4]);
+        // The points run on without jumping back
+        assert!(ways[0].coords.windows(2).all(|pair| pair[1].1 > pair[0].1));
+
+        // A oneway running the other way is not turned around
+        let oneways = vec![
+            tagged(road(10, &[node(1, 12.0), node(2, 12.001)]), "oneway", "yes"),
+            tagged(road(11, &[node(3, 12.002), node(2, 12.001)]), "oneway", "yes"),
+        ];
+        assert_eq!(merge_contiguous_ways(oneways).0.len(), 2);
+    }
+
+    #[test]
+    fn only_ways_of_the_same_class_are_merged() {
+        let mut incomplete = road(15, &[node(6, 12.005), node(7, 12.006)]);
+        incomplete.missing_nodes = 1;
+        let ways = vec![
+            tagged(road(10, &[node(1, 12.0), node(2, 12.001)]), "name", "Main Street"),
+            tagged(road(11, &[node(2, 12.001), node(3, 12.002)]), "name", "High Street"),
+            tagged(road(12, &[node(3, 12.002), node(4, 12.003)]), "bridge", "yes"),
+            // A ring, and a way missing a node, are left alone
+            road(13, &[node(4, 12.003), node(5, 12.004), SimpleNode { id: Some(9), lat: 55.001, lon: 12.004 }, node(4, 12.003)]),
+            road(14, &[node(5, 12.004), node(6, 12.005)]),
+            incomplete,
+        ];
+
+        let (merged, origins) = merge_contiguous_ways(ways);
+
+        assert_eq!(origins.merged_count(), 0);
+        assert_eq!(merged.iter().map(|way| way.id).collect::<Vec<_>>(), [10, 11, 12, 13, 14, 15]);
+    }
+}
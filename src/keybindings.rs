@@ -0,0 +1,416 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+use winit::keyboard::KeyCode;
+
+/// The key the key bindings are saved under in the settings table.
+pub const KEY_BINDINGS_SETTING: &str = "key_bindings";
+
+/// What a key does in the viewer. Typing a tag filter takes every key and is not rebindable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    ReloadStyle,
+    /// Only while the watcher reported a changed map file.
+    ImportChangedFile,
+    AddMarker,
+    SelectNextMarker,
+    /// Only while a marker is selected.
+    DeleteMarker,
+    ShowIsochrone,
+    HideIsochrone,
+    /// Only while a marker is selected, the route runs from it to the cursor.
+    ShowRoute,
+    HideRoute,
+    /// Only while alternative routes are shown.
+    NextRoute,
+    ToggleGraticule,
+    ToggleGpsTracks,
+    ToggleContinuousRedraw,
+    ToggleProfiler,
+    CycleTheme,
+    ToggleBuildings3d,
+    ToggleBuildings,
+    ToggleHighways,
+    ToggleWater,
+    TogglePois,
+    ToggleLabels,
+    ToggleTransport,
+    ToggleMeasure,
+    /// Typing a permalink instead of a filter jumps to it.
+    TypeFilter,
+    /// Logs the permalink of the viewport and shows it in the status line.
+    SharePermalink,
+    DownloadViewport,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    HistoryBack,
+    HistoryForward,
+    /// Stops the operations running in the background, clears the measurement, closes the
+    /// inspection panel, deselects the marker or clears the tag filter, the first of them that
+    /// applies. With nothing to cancel it closes the window.
+    Cancel,
+    /// Only while the inspection panel is open.
+    PreviousInspectionPage,
+    NextInspectionPage,
+}
+
+impl Action {
+    pub const ALL: [Action; 35] = [
+        Action::ReloadStyle, Action::ImportChangedFile, Action::AddMarker, Action::SelectNextMarker, Action::DeleteMarker,
+        Action::ShowIsochrone, Action::HideIsochrone, Action::ShowRoute, Action::HideRoute, Action::NextRoute, Action::ToggleGraticule, Action::ToggleGpsTracks,
+        Action::ToggleContinuousRedraw, Action::ToggleProfiler, Action::CycleTheme, Action::ToggleBuildings3d, Action::ToggleBuildings,
+        Action::ToggleHighways, Action::ToggleWater, Action::TogglePois, Action::ToggleLabels, Action::ToggleTransport,
+        Action::ToggleMeasure, Action::TypeFilter, Action::SharePermalink, Action::DownloadViewport,
+        Action::PanUp, Action::PanDown, Action::PanLeft, Action::PanRight, Action::HistoryBack, Action::HistoryForward,
+        Action::Cancel, Action::PreviousInspectionPage, Action::NextInspectionPage,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::ReloadStyle => "reload_style",
+            Action::ImportChangedFile => "import_changed_file",
+            Action::AddMarker => "add_marker",
+            Action::SelectNextMarker => "select_next_marker",
+            Action::DeleteMarker => "delete_marker",
+            Action::ShowIsochrone => "show_isochrone",
+            Action::HideIsochrone => "hide_isochrone",
+            Action::ShowRoute => "show_route",
+            Action::HideRoute => "hide_route",
+            Action::NextRoute => "next_route",
+            Action::ToggleGraticule => "toggle_graticule",
+            Action::ToggleGpsTracks => "toggle_gps_tracks",
+            Action::ToggleContinuousRedraw => "toggle_continuous_redraw",
+            Action::ToggleProfiler => "toggle_profiler",
+            Action::CycleTheme => "cycle_theme",
+            Action::ToggleBuildings3d => "toggle_buildings_3d",
+            Action::ToggleBuildings => "toggle_buildings",
+            Action::ToggleHighways => "toggle_highways",
+            Action::ToggleWater => "toggle_water",
+            Action::TogglePois => "toggle_pois",
+            Action::ToggleLabels => "toggle_labels",
+            Action::ToggleTransport => "toggle_transport",
+            Action::ToggleMeasure => "toggle_measure",
+            Action::TypeFilter => "type_filter",
+            Action::SharePermalink => "share_permalink",
+            Action::DownloadViewport => "download_viewport",
+            Action::PanUp => "pan_up",
+            Action::PanDown => "pan_down",
+            Action::PanLeft => "pan_left",
+            Action::PanRight => "pan_right",
+            Action::HistoryBack => "history_back",
+            Action::HistoryForward => "history_forward",
+            Action::Cancel => "cancel",
+            Action::PreviousInspectionPage => "previous_inspection_page",
+            Action::NextInspectionPage => "next_inspection_page",
+        }
+    }
+
+    /// The keys bound to the action unless the settings say otherwise.
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::ReloadStyle => &["F5"],
+            Action::ImportChangedFile => &["KeyI"],
+            Action::AddMarker => &["KeyK"],
+            Action::SelectNextMarker => &["Shift+Tab"],
+            Action::DeleteMarker => &["Delete"],
+            Action::ShowIsochrone => &["KeyO"],
+            Action::HideIsochrone => &["Shift+KeyO"],
+            Action::ShowRoute => &["KeyN"],
+            Action::HideRoute => &["Shift+KeyN"],
+            Action::NextRoute => &["Tab"],
+            Action::ToggleGraticule => &["Shift+KeyG"],
+            Action::ToggleGpsTracks => &["KeyG"],
+            Action::ToggleContinuousRedraw => &["F9"],
+            Action::ToggleProfiler => &["F10"],
+            Action::CycleTheme => &["KeyT"],
+            Action::ToggleBuildings3d => &["Digit3"],
+            Action::ToggleBuildings => &["KeyB"],
+            Action::ToggleHighways => &["KeyH"],
+            Action::ToggleWater => &["KeyW"],
+            Action::TogglePois => &["KeyP"],
+            Action::ToggleLabels => &["KeyL"],
+            Action::ToggleTransport => &["KeyR"],
+            Action::ToggleMeasure => &["KeyM"],
+            Action::TypeFilter => &["KeyF"],
+            Action::SharePermalink => &["KeyU"],
+            Action::DownloadViewport => &["KeyD"],
+            Action::PanUp => &["ArrowUp"],
+            Action::PanDown => &["ArrowDown"],
+            Action::PanLeft => &["ArrowLeft"],
+            Action::PanRight => &["ArrowRight"],
+            Action::HistoryBack => &["Backspace", "BracketLeft"],
+            Action::HistoryForward => &["Shift+Backspace", "BracketRight"],
+            Action::Cancel => &["Escape"],
+            Action::PreviousInspectionPage => &["PageUp"],
+            Action::NextInspectionPage => &["PageDown"],
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Action::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| format!("'{}' is not an action", s))
+    }
+}
+
+// The names keys are written with, those of `KeyCode`. winit is built without serde, so
+// the names are listed here.
+macro_rules! key_names {
+    ($($code:ident),* $(,)?) => {
+        &[$((stringify!($code), KeyCode::$code)),*]
+    };
+}
+
+const KEY_NAMES: &[(&str, KeyCode)] = key_names![
+    KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM,
+    KeyN, KeyO, KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ,
+    Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Escape, Tab, Delete, Backspace, Enter, Space, Insert, Home, End, PageUp, PageDown,
+    ArrowUp, ArrowDown, ArrowLeft, ArrowRight,
+    BracketLeft, BracketRight, Minus, Equal, Comma, Period, Slash, Backslash, Semicolon, Quote, Backquote,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd, NumpadSubtract, NumpadMultiply, NumpadDivide, NumpadDecimal, NumpadEnter,
+];
+
+/// A key, pressed with or without Shift.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub code: KeyCode,
+    pub shift: bool,
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = KEY_NAMES.iter().find(|(_, code)| *code == self.code).map_or("?", |(name, _)| *name);
+        if self.shift {
+            write!(f, "Shift+{}", name)
+        } else {
+            f.write_str(name)
+        }
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (shift, name) = match s.strip_prefix("Shift+") {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+        match KEY_NAMES.iter().find(|(known, _)| *known == name) {
+            Some(&(_, code)) => Ok(KeyChord { code, shift }),
+            None => Err(format!("'{}' is not a key, expected a name like KeyG, Digit3, F5 or Shift+KeyG", s)),
+        }
+    }
+}
+
+/// Why the saved key bindings could not be used.
+#[derive(Debug)]
+pub enum KeyBindingsError {
+    /// The setting is not a JSON object of action names to lists of keys.
+    Json(String),
+    UnknownAction(String),
+    UnknownKey { action: Action, message: String },
+    /// The same key is bound to two actions, so one of them could never be used.
+    Duplicate { chord: KeyChord, first: Action, second: Action },
+}
+
+impl fmt::Display for KeyBindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyBindingsError::Json(message) => write!(f, "the key bindings are not a JSON object of actions to lists of keys: {}", message),
+            KeyBindingsError::UnknownAction(message) => write!(f, "{}", message),
+            KeyBindingsError::UnknownKey { action, message } => write!(f, "{}: {}", action, message),
+            KeyBindingsError::Duplicate { chord, first, second } => write!(f, "{} is bound to both {} and {}", chord, first, second),
+        }
+    }
+}
+
+impl StdError for KeyBindingsError {}
+
+/// The keys bound to each action. The defaults are in the code, the settings table holds the
+/// actions bound differently as a JSON object like `{"toggle_graticule": ["Shift+KeyG"]}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    bindings: Vec<(Action, Vec<KeyChord>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let bindings = Action::ALL
+            .into_iter()
+            .map(|action| (action, action.default_keys().iter().map(|key| key.parse().expect("default key names are valid")).collect()))
+            .collect();
+        KeyBindings { bindings }
+    }
+}
+
+impl KeyBindings {
+    /// Reads saved key bindings. The actions missing from them keep their default keys.
+    ///
+    /// ## Arguments
+    /// * `json` - A JSON object of action names to lists of keys, an empty list unbinds the action.
+    ///
+    /// ## Returns
+    /// The bindings, or an error if the JSON is malformed, names an unknown action or key or
+    /// binds a key to two actions.
+    pub fn from_json(json: &str) -> Result<KeyBindings, KeyBindingsError> {
+        let object: Map<String, Value> = serde_json::from_str(json).map_err(|error| KeyBindingsError::Json(error.to_string()))?;
+
+        let mut key_bindings = KeyBindings::default();
+        for (name, keys) in object {
+            let action: Action = name.parse().map_err(KeyBindingsError::UnknownAction)?;
+            let Value::Array(keys) = keys else {
+                return Err(KeyBindingsError::Json(format!("the keys of {} are not a list", action)));
+            };
+            let chords = keys
+                .iter()
+                .map(|key| match key {
+                    Value::String(key) => key.parse().map_err(|message| KeyBindingsError::UnknownKey { action, message }),
+                    other => Err(KeyBindingsError::UnknownKey { action, message: format!("{} is not a key name", other) }),
+                })
+                .collect::<Result<Vec<KeyChord>, KeyBindingsError>>()?;
+            if let Some((_, bound)) = key_bindings.bindings.iter_mut().find(|(bound_action, _)| *bound_action == action) {
+                *bound = chords;
+            }
+        }
+
+        key_bindings.validate()?;
+        Ok(key_bindings)
+    }
+
+    /// Writes every binding, the defaults included, as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        let object: Map<String, Value> = self
+            .bindings
+            .iter()
+            .map(|(action, chords)| (action.to_string(), Value::Array(chords.iter().map(|chord| Value::String(chord.to_string())).collect())))
+            .collect();
+        serde_json::to_string_pretty(&object).expect("a JSON object can be written")
+    }
+
+    /// Checks that no key is bound to two actions.
+    fn validate(&self) -> Result<(), KeyBindingsError> {
+        for (index, (first, chords)) in self.bindings.iter().enumerate() {
+            for chord in chords {
+                if let Some((second, _)) = self.bindings[index + 1..].iter().find(|(_, other)| other.contains(chord)) {
+                    return Err(KeyBindingsError::Duplicate { chord: *chord, first: *first, second: *second });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The action of a pressed key. A key pressed with Shift that is not bound with Shift
+    /// does what it does without.
+    ///
+    /// ## Arguments
+    /// * `code` - The physical key pressed.
+    /// * `shift` - Whether Shift is held.
+    pub fn action_for(&self, code: KeyCode, shift: bool) -> Option<Action> {
+        let find = |chord: KeyChord| self.bindings.iter().find(|(_, chords)| chords.contains(&chord)).map(|(action, _)| *action);
+        find(KeyChord { code, shift }).or_else(|| if shift { find(KeyChord { code, shift: false }) } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{fetch_setting, save_setting};
+    use crate::test_support::memory_pool;
+
+    #[test]
+    fn the_default_keys_are_valid_and_distinct() {
+        let keys = KeyBindings::default();
+        assert!(keys.validate().is_ok());
+        assert_eq!(keys.bindings.len(), Action::ALL.len());
+        for action in Action::ALL {
+            assert_eq!(action.as_str().parse(), Ok(action));
+            assert!(keys.bindings.iter().any(|(bound, chords)| *bound == action && !chords.is_empty()), "{} has no key", action);
+        }
+        assert_eq!(keys.action_for(KeyCode::ArrowLeft, false), Some(Action::PanLeft));
+        assert_eq!(keys.action_for(KeyCode::Escape, false), Some(Action::Cancel));
+    }
+
+    #[test]
+    fn key_names_read_back_as_written() {
+        for (name, code) in KEY_NAMES {
+            for shift in [false, true] {
+                let chord = KeyChord { code: *code, shift };
+                assert_eq!(chord.to_string().parse(), Ok(chord));
+            }
+            assert_eq!(name.parse::<KeyChord>().map(|chord| chord.code), Ok(*code));
+        }
+        assert_eq!(" Shift+KeyG ".parse(), Ok(KeyChord { code: KeyCode::KeyG, shift: true }));
+        assert!("Ctrl+KeyG".parse::<KeyChord>().is_err());
+        assert!("g".parse::<KeyChord>().is_err());
+    }
+
+    #[test]
+    fn bindings_survive_the_json_round_trip() {
+        let remapped = KeyBindings::from_json(r#"{"toggle_graticule": ["KeyY", "Shift+F1"], "toggle_gps_tracks": []}"#).unwrap();
+        assert_ne!(remapped, KeyBindings::default());
+        assert_eq!(KeyBindings::from_json(&remapped.to_json()).unwrap(), remapped);
+        assert_eq!(KeyBindings::from_json(&KeyBindings::default().to_json()).unwrap(), KeyBindings::default());
+        assert_eq!(KeyBindings::from_json("{}").unwrap(), KeyBindings::default());
+    }
+
+    #[test]
+    fn a_remapped_key_looks_up_its_new_action() {
+        let keys = KeyBindings::from_json(r#"{"toggle_graticule": ["KeyY"], "toggle_gps_tracks": ["Shift+KeyG"]}"#).unwrap();
+        assert_eq!(keys.action_for(KeyCode::KeyY, false), Some(Action::ToggleGraticule));
+        assert_eq!(keys.action_for(KeyCode::KeyG, true), Some(Action::ToggleGpsTracks));
+        // The old keys do nothing now, and the other actions keep theirs
+        assert_eq!(keys.action_for(KeyCode::KeyG, false), None);
+        assert_eq!(keys.action_for(KeyCode::KeyB, false), Some(Action::ToggleBuildings));
+        // A key bound only without Shift also works with it
+        assert_eq!(keys.action_for(KeyCode::KeyY, true), Some(Action::ToggleGraticule));
+        // An empty list unbinds the action
+        let keys = KeyBindings::from_json(r#"{"toggle_buildings": []}"#).unwrap();
+        assert_eq!(keys.action_for(KeyCode::KeyB, false), None);
+    }
+
+    #[test]
+    fn unusable_bindings_are_rejected_with_a_clear_error() {
+        let error = |json: &str| KeyBindings::from_json(json).unwrap_err().to_string();
+        // KeyB is still bound to toggle_buildings by default
+        assert_eq!(error(r#"{"toggle_graticule": ["KeyB"]}"#), "KeyB is bound to both toggle_graticule and toggle_buildings");
+        assert_eq!(error(r#"{"pan_up": ["KeyZ"], "pan_down": ["KeyZ"]}"#), "KeyZ is bound to both pan_up and pan_down");
+        assert_eq!(error(r#"{"fly": ["KeyZ"]}"#), "'fly' is not an action");
+        assert!(error(r#"{"pan_up": ["Hyper"]}"#).starts_with("pan_up: 'Hyper' is not a key"));
+        assert_eq!(error(r#"{"pan_up": [5]}"#), "pan_up: 5 is not a key name");
+        assert_eq!(error(r#"{"pan_up": "KeyZ"}"#), "the key bindings are not a JSON object of actions to lists of keys: the keys of pan_up are not a list");
+        assert!(matches!(KeyBindings::from_json("[1, 2]"), Err(KeyBindingsError::Json(_))));
+    }
+
+    #[tokio::test]
+    async fn bindings_saved_in_the_settings_are_loaded() {
+        let pool = memory_pool("key_bindings").await;
+        assert_eq!(fetch_setting(&pool, KEY_BINDINGS_SETTING).await.unwrap(), None);
+
+        save_setting(&pool, KEY_BINDINGS_SETTING, r#"{"cycle_theme": ["F2"]}"#).await.unwrap();
+        let saved = fetch_setting(&pool, KEY_BINDINGS_SETTING).await.unwrap().unwrap();
+        let keys = KeyBindings::from_json(&saved).unwrap();
+        assert_eq!(keys.action_for(KeyCode::F2, false), Some(Action::CycleTheme));
+        assert_eq!(keys.action_for(KeyCode::KeyT, false), None);
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::osm_entities::Tag;
+
+/// The key the layer visibility is saved under in the settings table.
+pub const LAYER_VISIBILITY_SETTING: &str = "layer_visibility";
+
+/// The categories of map geometry that can be hidden separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MapLayer {
+    Buildings,
+    Highways,
+    Water,
+    Pois,
+    /// Railways, ferry routes and the runways and taxiways of airports.
+    Transport,
+    /// Everything else, always drawn.
+    Other,
+}
+
+impl MapLayer {
+    /// Decides the layer of a way from its tags.
+    pub fn of_tags(tags: &[Tag]) -> MapLayer {
+        let has = |key: &str| tags.iter().any(|tag| tag.key == key);
+        let is = |key: &str, value: &str| tags.iter().any(|tag| tag.key == key && tag.value == value);
+
+        if has("building") || has("building:part") {
+            MapLayer::Buildings
+        } else if has("highway") {
+            MapLayer::Highways
+        } else if has("railway") || has("aeroway") || is("route", "ferry") {
+            MapLayer::Transport
+        } else if has("waterway") || has("water") || is("natural", "water") || is("natural", "coastline") {
+            MapLayer::Water
+        } else if has("amenity") || has("shop") || has("tourism") {
+            MapLayer::Pois
+        } else {
+            MapLayer::Other
+        }
+    }
+}
+
+/// Where a way lies above or below the ways it crosses, as tagged with `layer`, `bridge`
+/// and `tunnel`. Ways are drawn in ascending order, so bridges end up over the ways they cross
+/// and tunnels under them.
+///
+/// # Fields
+/// * `level` - The `layer` of the way. Without a usable `layer` tag, bridges are on 1, tunnels on -1 and other ways on 0.
+/// * `tunnel` - Whether the way runs through a tunnel, drawn dashed so the ways above it show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerticalLayer {
+    pub level: i8,
+    pub tunnel: bool,
+}
+
+impl VerticalLayer {
+    /// Decides the vertical layer of a way from its tags. A `layer` that is not a whole
+    /// number, e.g. `1;2` or `high`, is ignored.
+    pub fn of_tags(tags: &[Tag]) -> VerticalLayer {
+        let value = |key: &str| tags.iter().find(|tag| tag.key == key).map(|tag| tag.value.trim());
+        let bridge = value("bridge").is_some_and(|value| value != "no");
+        let tunnel = value("tunnel").is_some_and(|value| value != "no");
+
+        let level = match value("layer").and_then(|value| value.parse().ok()) {
+            Some(level) => level,
+            None if bridge => 1,
+            None if tunnel => -1,
+            None => 0,
+        };
+        VerticalLayer { level, tunnel }
+    }
+}
+
+/// Which layers are shown, as a set of bits with one bit per layer. Labels have a bit of their
+/// own, so they can be hidden the same way once they are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerVisibility(u8);
+
+impl LayerVisibility {
+    pub const BUILDINGS: LayerVisibility = LayerVisibility(1 << 0);
+    pub const HIGHWAYS: LayerVisibility = LayerVisibility(1 << 1);
+    pub const WATER: LayerVisibility = LayerVisibility(1 << 2);
+    pub const POIS: LayerVisibility = LayerVisibility(1 << 3);
+    pub const LABELS: LayerVisibility = LayerVisibility(1 << 4);
+    pub const TRANSPORT: LayerVisibility = LayerVisibility(1 << 5);
+    pub const ALL: LayerVisibility = LayerVisibility(0b11_1111);
+
+    /// Builds the set from its bits, ignoring bits of no layer.
+    pub fn from_bits(bits: u8) -> Self {
+        LayerVisibility(bits & Self::ALL.0)
+    }
+
+    pub fn contains(self, other: LayerVisibility) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Shows the layers if they are hidden and hides them if they are shown.
+    pub fn toggle(&mut self, other: LayerVisibility) {
+        self.0 ^= other.0;
+    }
+
+    /// Returns true if the geometry of the layer is drawn. `MapLayer::Other` is always drawn.
+    pub fn shows(self, layer: MapLayer) -> bool {
+        match layer {
+            MapLayer::Buildings => self.contains(Self::BUILDINGS),
+            MapLayer::Highways => self.contains(Self::HIGHWAYS),
+            MapLayer::Water => self.contains(Self::WATER),
+            MapLayer::Pois => self.contains(Self::POIS),
+            MapLayer::Transport => self.contains(Self::TRANSPORT),
+            MapLayer::Other => true,
+        }
+    }
+}
+
+impl Default for LayerVisibility {
+    fn default() -> Self {
+        LayerVisibility::ALL
+    }
+}
+
+// Saved as the bits in decimal
+impl fmt::Display for LayerVisibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for LayerVisibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim().parse::<u8>()
+            .map(LayerVisibility::from_bits)
+            .map_err(|_| format!("'{}' is not a layer visibility", s))
+    }
+}
+
+/// A run of indices of one layer in the index buffer of the map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerRange {
+    pub layer: MapLayer,
+    pub indices: Range<u32>,
+}
+
+/// Records that the indices up to `end` belong to `layer`, extending the last run if it is of the same layer.
+///
+/// ## Arguments
+/// * `ranges` - The runs so far, in draw order.
+/// * `layer` - The layer of the indices added since the last run.
+/// * `end` - The number of indices in the index buffer.
+pub fn push_layer_range(ranges: &mut Vec<LayerRange>, layer: MapLayer, end: u32) {
+    let start = ranges.last().map(|range| range.indices.end).unwrap_or(0);
+    if start == end {
+        return;
+    }
+
+    match ranges.last_mut() {
+        Some(last) if last.layer == layer => last.indices.end = end,
+        _ => ranges.push(LayerRange { layer, indices: start..end }),
+    }
+}
+
+/// Collects the runs of the shown layers, joining neighbouring runs so they take one draw call.
+pub fn visible_index_ranges(ranges: &[LayerRange], visibility: LayerVisibility) -> Vec<Range<u32>> {
+    let mut visible: Vec<Range<u32>> = Vec::new();
+
+    for range in ranges.iter().filter(|range| visibility.shows(range.layer)) {
+        match visible.last_mut() {
+            Some(last) if last.end == range.indices.start => last.end = range.indices.end,
+            _ => visible.push(range.indices.clone()),
+        }
+    }
+
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keybindings::{Action, KeyBindings};
+    use winit::keyboard::KeyCode;
+
+    #[test]
+    fn the_layer_keys_toggle_their_layer_and_nothing_else() {
+        let keys = KeyBindings::default();
+        let toggles = [
+            (KeyCode::KeyB, Action::ToggleBuildings, LayerVisibility::BUILDINGS, MapLayer::Buildings),
+            (KeyCode::KeyH, Action::ToggleHighways, LayerVisibility::HIGHWAYS, MapLayer::Highways),
+            (KeyCode::KeyW, Action::ToggleWater, LayerVisibility::WATER, MapLayer::Water),
+            (KeyCode::KeyP, Action::TogglePois, LayerVisibility::POIS, MapLayer::Pois),
+        ];
+
+        for (code, action, bit, layer) in toggles {
+            assert_eq!(keys.action_for(code, false), Some(action));
+
+            let mut visibility = LayerVisibility::default();
+            visibility.toggle(bit);
+            assert!(!visibility.shows(layer), "{:?}", layer);
+            assert!(visibility.shows(MapLayer::Other));
+            assert_eq!(visibility, LayerVisibility::from_bits(LayerVisibility::ALL.0 & !bit.0));
+
+            visibility.toggle(bit);
+            assert_eq!(visibility, LayerVisibility::ALL);
+        }
+        assert_eq!(keys.action_for(KeyCode::KeyL, false), Some(Action::ToggleLabels));
+    }
+
+    #[test]
+    fn the_visibility_reads_back_as_it_was_saved() {
+        let mut visibility = LayerVisibility::default();
+        visibility.toggle(LayerVisibility::WATER);
+        visibility.toggle(LayerVisibility::LABELS);
+
+        assert_eq!(visibility.to_string().parse::<LayerVisibility>(), Ok(visibility));
+        // Bits of no layer, e.g. saved by a later version, are dropped
+        assert_eq!("255".parse::<LayerVisibility>(), Ok(LayerVisibility::ALL));
+        assert!("all".parse::<LayerVisibility>().is_err());
+    }
+
+    #[test]
+    fn the_vertical_layer_follows_layer_bridge_and_tunnel() {
+        let layer_of = |pairs: &[(&str, &str)]| {
+            let tags: Vec<Tag> = pairs.iter().map(|(key, value)| Tag::new(key.to_string(), value.to_string())).collect();
+            let VerticalLayer { level, tunnel } = VerticalLayer::of_tags(&tags);
+            (level, tunnel)
+        };
+
+        assert_eq!(layer_of(&[("highway", "primary")]), (0, false));
+        assert_eq!(layer_of(&[("bridge", "yes")]), (1, false));
+        assert_eq!(layer_of(&[("bridge", "viaduct")]), (1, false));
+        assert_eq!(layer_of(&[("tunnel", "yes")]), (-1, true));
+        assert_eq!(layer_of(&[("tunnel", "culvert")]), (-1, true));
+        assert_eq!(layer_of(&[("bridge", "no"), ("tunnel", "no")]), (0, false));
+
+        // The layer tag wins over what bridges and tunnels imply
+        assert_eq!(layer_of(&[("layer", "2")]), (2, false));
+        assert_eq!(layer_of(&[("layer", " -3 ")]), (-3, false));
+        assert_eq!(layer_of(&[("bridge", "yes"), ("layer", "3")]), (3, false));
+        assert_eq!(layer_of(&[("tunnel", "yes"), ("layer", "-2")]), (-2, true));
+        assert_eq!(layer_of(&[("tunnel", "yes"), ("layer", "0")]), (0, true));
+
+        // Junk layers are ignored
+        for junk in ["1;2", "high", "1.5", "", "300"] {
+            assert_eq!(layer_of(&[("layer", junk)]), (0, false), "{junk:?}");
+            assert_eq!(layer_of(&[("bridge", "yes"), ("layer", junk)]), (1, false), "{junk:?}");
+            assert_eq!(layer_of(&[("tunnel", "yes"), ("layer", junk)]), (-1, true), "{junk:?}");
+        }
+
+        assert!(VerticalLayer { level: -1, tunnel: true } < VerticalLayer::default());
+        assert!(VerticalLayer::default() < VerticalLayer { level: 1, tunnel: false });
+    }
+
+    #[test]
+    fn hidden_layers_are_cut_out_of_the_draw_ranges() {
+        let mut ranges = Vec::new();
+        push_layer_range(&mut ranges, MapLayer::Other, 6);
+        push_layer_range(&mut ranges, MapLayer::Buildings, 12);
+        push_layer_range(&mut ranges, MapLayer::Buildings, 18);
+        push_layer_range(&mut ranges, MapLayer::Buildings, 18);
+        push_layer_range(&mut ranges, MapLayer::Highways, 24);
+        assert_eq!(ranges, [
+            LayerRange { layer: MapLayer::Other, indices: 0..6 },
+            LayerRange { layer: MapLayer::Buildings, indices: 6..18 },
+            LayerRange { layer: MapLayer::Highways, indices: 18..24 },
+        ]);
+
+        // Everything shown is one draw call
+        assert_eq!(visible_index_ranges(&ranges, LayerVisibility::ALL), vec![Range { start: 0, end: 24 }]);
+
+        let mut visibility = LayerVisibility::ALL;
+        visibility.toggle(LayerVisibility::BUILDINGS);
+        assert_eq!(visible_index_ranges(&ranges, visibility), [0..6, 18..24]);
+    }
+}
@@ -0,0 +1,47 @@
+//! The map viewer, with the commands of the binary in `commands` and the window in `app`.
+
+pub mod database;
+pub mod osm_entities;
+mod utils;
+pub mod open_street_map;
+pub mod fetcher;
+pub mod app;
+mod texture;
+pub mod geo;
+pub mod style;
+mod routing;
+mod gpx;
+mod tiles;
+mod junctions;
+mod layers;
+pub mod logging;
+pub mod gpu;
+pub mod spatial;
+mod export;
+mod snapshot;
+mod status;
+mod events;
+pub mod theme;
+mod threads;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod doctor;
+mod history;
+mod frame_rate;
+mod inspect;
+mod watch;
+mod stats;
+mod filter;
+mod progressive;
+mod coastline;
+mod keybindings;
+mod skeleton;
+pub mod metrics;
+mod hover;
+mod simplify;
+mod profiler;
+mod debounce;
+mod cancel;
+pub mod commands;
+mod node_dots;
+mod writer;
@@ -0,0 +1,279 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+/// The filter used when `RUST_LOG` is not set.
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+// sqlx logs every statement at the debug level, which would drown the detail of an import.
+// Its statements only show when `sqlx` is named in the filter.
+const QUIET_TARGETS: [(&str, LevelFilter); 1] = [("sqlx", LevelFilter::WARN)];
+
+/// Which events are logged, parsed from a list of `RUST_LOG` style directives, e.g. `info`,
+/// `debug` or `info,GoogleMapsClone::database=trace`.
+///
+/// # Fields
+/// * `default` - The most verbose level logged for targets without a directive of their own.
+/// * `targets` - The level per target prefix. The longest matching prefix wins.
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    /// Parses comma separated directives, each either a level or `target=level`.
+    ///
+    /// ## Returns
+    /// * The filter, or a description of the first directive that could not be parsed.
+    pub fn parse(directives: &str) -> Result<Self, String> {
+        let mut filter = LogFilter { default: LevelFilter::ERROR, targets: Vec::new() };
+
+        for directive in directives.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            let parse_level = |level: &str| level.trim().parse::<LevelFilter>()
+                .map_err(|_| format!("invalid log level '{}' in '{}'", level.trim(), directive));
+
+            match directive.split_once('=') {
+                Some((target, level)) => filter.targets.push((target.trim().to_string(), parse_level(level)?)),
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+
+        for (target, level) in QUIET_TARGETS {
+            if !filter.targets.iter().any(|(named, _)| named == target) {
+                filter.targets.push((target.to_string(), level));
+            }
+        }
+
+        // Longer prefixes first, so the first match is the most specific one
+        filter.targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(filter)
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets.iter()
+            .find(|(prefix, _)| target == prefix || target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with("::")))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|(_, level)| *level).fold(self.default, LevelFilter::max)
+    }
+}
+
+/// An open span.
+///
+/// # Fields
+/// * `scope` - The names of the span and its parents, joined with `:`.
+/// * `level` - The level the timing is logged at once the span closes.
+/// * `target` - The module the span was created in.
+/// * `fields` - The formatted fields of the span.
+/// * `created` - When the span was created, for the timing.
+/// * `references` - How many handles to the span exist, it closes once the last is dropped.
+struct SpanData {
+    scope: String,
+    level: Level,
+    target: String,
+    fields: String,
+    created: Instant,
+    references: usize,
+}
+
+thread_local! {
+    // The spans entered on this thread, innermost last
+    static ENTERED_SPANS: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Writes events to stderr as `elapsed LEVEL scope: message key=value ...`, and the time
+/// every span was open once it closes. Stdout is left to the output of the commands.
+pub struct LogSubscriber {
+    filter: LogFilter,
+    started: Instant,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl LogSubscriber {
+    pub fn new(filter: LogFilter) -> Self {
+        LogSubscriber {
+            filter,
+            started: Instant::now(),
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn current_span(&self) -> Option<Id> {
+        ENTERED_SPANS.with(|entered| entered.borrow().last().cloned())
+    }
+
+    fn scope_of(&self, span: Option<&Id>) -> Option<String> {
+        let spans = self.spans.lock().unwrap();
+        span.and_then(|id| spans.get(&id.into_u64())).map(|data| data.scope.clone())
+    }
+
+    fn write_line(&self, level: &Level, target: &str, scope: Option<&str>, message: &str, fields: &str) {
+        let mut line = format!("{:>9.3}s {:>5} ", self.started.elapsed().as_secs_f64(), level);
+        match scope {
+            Some(scope) => write!(line, "{}: ", scope),
+            None => write!(line, "{}: ", target),
+        }.ok();
+        line.push_str(message);
+        line.push_str(fields);
+
+        // A failing stderr leaves nowhere to report the failure to
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+}
+
+impl Subscriber for LogSubscriber {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.filter.max_level())
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let parent = if attributes.is_contextual() { self.current_span() } else { attributes.parent().cloned() };
+        let name = attributes.metadata().name();
+        let scope = match self.scope_of(parent.as_ref()) {
+            Some(parent_scope) => format!("{}:{}", parent_scope, name),
+            None => name.to_string(),
+        };
+
+        let mut visitor = FieldVisitor::default();
+        attributes.record(&mut visitor);
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.spans.lock().unwrap().insert(id, SpanData {
+            scope,
+            level: *attributes.metadata().level(),
+            target: attributes.metadata().target().to_string(),
+            fields: visitor.fields,
+            created: Instant::now(),
+            references: 1,
+        });
+
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            let mut visitor = FieldVisitor { message: None, fields: std::mem::take(&mut data.fields) };
+            values.record(&mut visitor);
+            data.fields = visitor.fields;
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let span = if event.is_contextual() { self.current_span() } else { event.parent().cloned() };
+        let scope = self.scope_of(span.as_ref());
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        let metadata = event.metadata();
+        self.write_line(metadata.level(), metadata.target(), scope.as_deref(), visitor.message.as_deref().unwrap_or(""), &visitor.fields);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED_SPANS.with(|entered| entered.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED_SPANS.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(position) = entered.iter().rposition(|id| id == span) {
+                entered.remove(position);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let closed = {
+            let mut spans = self.spans.lock().unwrap();
+            let Some(data) = spans.get_mut(&span.into_u64()) else {
+                return false;
+            };
+            data.references -= 1;
+            if data.references > 0 {
+                return false;
+            }
+            spans.remove(&span.into_u64())
+        };
+
+        // Every span reports how long it was open, so the phases of an import are timed
+        if let Some(data) = closed {
+            let fields = format!("{} elapsed={:?}", data.fields, data.created.elapsed());
+            self.write_line(&data.level, &data.target, Some(&data.scope), "done", &fields);
+        }
+        true
+    }
+}
+
+/// Collects the message and formats the other fields as ` key=value`.
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: String,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// Installs the `LogSubscriber` as the global default, filtered by `RUST_LOG` or else
+/// `DEFAULT_LOG_FILTER`. Call this once at the start of `main`.
+pub fn init_logging() {
+    let directives = env::var("RUST_LOG").ok()
+        .filter(|directives| !directives.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_LOG_FILTER.to_string());
+    let filter = LogFilter::parse(&directives).unwrap_or_else(|error| {
+        eprintln!("Ignoring RUST_LOG: {}", error);
+        LogFilter::parse(DEFAULT_LOG_FILTER).expect("the default log filter is valid")
+    });
+
+    if tracing::subscriber::set_global_default(LogSubscriber::new(filter)).is_err() {
+        eprintln!("A logger is already installed");
+    }
+}
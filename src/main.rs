@@ -1,14 +1,7 @@
-mod database;
-mod osm_entities;
-mod utils;
-mod open_street_map;
-mod fetcher;
-mod app;
-mod texture;
-
-use app::run;
-use database::{create_tables, fetch_all_nodes_and_tags, fetch_all_relations_and_tags, fetch_all_ways_and_tags};
-use fetcher::read_openstreet_map_file;
+use google_maps_clone::app::run;
+use google_maps_clone::commands::{parse_command, run_command, Command};
+use google_maps_clone::database::{self, create_tables};
+use google_maps_clone::{doctor, fetcher, geo, logging, metrics};
 
 use anyhow::Result;
 
@@ -16,28 +9,167 @@ const DB_URL: &str = "sqlite://database/sqlite.db";
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
-    run().await;
+    logging::init_logging();
+    let args: Vec<String> = std::env::args().collect();
 
-    // // Read and process the chosen map file
-    // read_openstreet_map_file(&pool).await?;
+    // The database is the file `DB_URL` unless another is given, `--ephemeral` keeps it in memory
+    let db_url = match database_url(&args) {
+        Ok(db_url) => db_url,
+        Err(usage) => {
+            println!("{}", usage);
+            std::process::exit(2);
+        }
+    };
 
-    // let nodes = match fetch_all_nodes_and_tags(&pool).await {
-    //     Ok(nodes) => nodes,
-    //     Err(error) => panic!("There was a problem fetching the nodes: {:?}", error),
-    // };
+    // Imports store the tags `--tags` keeps, every tag unless it is given, and skip the files
+    // imported before unless `--force` is given
+    let import_options = match import_options(&args) {
+        Ok(import_options) => import_options,
+        Err(usage) => {
+            println!("{}", usage);
+            std::process::exit(2);
+        }
+    };
 
-    // let ways = match fetch_all_ways_and_tags(&pool).await {
-    //     Ok(ways) => ways,
-    //     Err(error) => panic!("There was a problem fetching the ways: {:?}", error),
-    // };
+    // The map opens on the view of `--goto "#map=zoom/lat/lon"` instead of the one it was
+    // closed on, e.g. to open a permalink shared by someone else
+    let goto = match goto(&args) {
+        Ok(goto) => goto,
+        Err(usage) => {
+            println!("{}", usage);
+            std::process::exit(2);
+        }
+    };
 
-    // let relations = match fetch_all_relations_and_tags(&pool).await {
-    //         Ok(relations) => relations,
-    //         Err(error) => panic!("There was a problem fetching the relations: {:?}", error),
-    //     };
+    // The metrics are written to `--metrics-file path` in the text format of Prometheus, for
+    // the textfile collector of the node exporter, see `metrics::write_metrics_file`
+    let metrics_file = match metrics_file(&args) {
+        Ok(metrics_file) => metrics_file,
+        Err(usage) => {
+            println!("{}", usage);
+            std::process::exit(2);
+        }
+    };
 
-    // println!("Number of nodes: {}", nodes.len());
-    // println!("Number of ways: {}", ways.len());
-    // println!("Number of relations: {}", relations.len());
+    // What to do instead of opening the map, see `commands::Command`
+    let command = match parse_command(&args, &db_url) {
+        Ok(command) => command,
+        Err(usage) => {
+            println!("{}", usage);
+            std::process::exit(2);
+        }
+    };
+
+    // Check what the map needs before opening it, without changing the database
+    if let Command::Doctor = command {
+        let report = doctor::run_doctor(&db_url).await;
+        print!("{}", report);
+
+        if report.has_errors() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // The map opens on a database created if there is none yet
+    if let Command::Map { .. } = command {
+        database::prepare_database(&db_url).await?;
+    }
+    let pool = database::connect_pool(&db_url).await?;
+    if command.migrates() {
+        create_tables(&pool).await?;
+    }
+
+    let Command::Map { import, watch_mode } = command else {
+        if !run_command(command, &pool, &import_options, metrics_file.as_deref()).await? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    };
+
+    if let Some(path) = import {
+        fetcher::import_file(&pool, &path, &import_options).await?;
+    }
+    doctor::log_startup_checks(&db_url, &pool).await;
+    if let Some(metrics_file) = metrics_file {
+        metrics::spawn_metrics_writer(pool.clone(), metrics_file);
+    }
+    run(pool, import_options, watch_mode, goto).await?;
     Ok(())
 }
+
+/// Finds the URL of the database from the arguments: `--db url`, `--ephemeral` for a database
+/// in memory, or `DB_URL`.
+///
+/// ## Returns
+/// * The URL, or the usage if the arguments are incomplete or contradict each other.
+fn database_url(args: &[String]) -> Result<String, &'static str> {
+    let usage = "Usage: --db url or --ephemeral, not both";
+    let ephemeral = args.iter().any(|arg| arg == "--ephemeral");
+
+    match args.iter().position(|arg| arg == "--db") {
+        Some(_) if ephemeral => Err(usage),
+        Some(index) => args.get(index + 1).filter(|argument| !argument.starts_with("--")).cloned().ok_or(usage),
+        None if ephemeral => Ok(database::MEMORY_DB_URL.to_string()),
+        None => Ok(DB_URL.to_string()),
+    }
+}
+
+/// Finds the options of imports from the arguments: `--tags rendering|all|key1,key2,...`
+/// for the tags to store, see `database::ImportTagFilter`, `--tag-control-chars escape|strip`
+/// for what is done with control characters in tags, see `database::ControlCharacters`, and
+/// `--simplify meters` for how far the ways may be simplified, see
+/// `ImportOptions::simplify_tolerance_m`.
+///
+/// ## Returns
+/// * The options, or the usage if the tag filter, control characters or tolerance are
+///   missing or invalid.
+fn import_options(args: &[String]) -> Result<fetcher::ImportOptions, String> {
+    let mut options = fetcher::ImportOptions::default();
+    if let Some(index) = args.iter().position(|arg| arg == "--tags") {
+        let Some(filter) = args.get(index + 1).filter(|argument| !argument.starts_with("--")) else {
+            return Err("Usage: --tags rendering|all|key1,key2,...".to_string());
+        };
+        options.tag_filter = filter.parse().map_err(|error| format!("Invalid tag filter: {}", error))?;
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--tag-control-chars") {
+        let Some(handling) = args.get(index + 1).filter(|argument| !argument.starts_with("--")) else {
+            return Err("Usage: --tag-control-chars escape|strip".to_string());
+        };
+        options.control_characters = handling.parse().map_err(|error| format!("Invalid --tag-control-chars: {}", error))?;
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--simplify") {
+        let tolerance_m = args.get(index + 1).and_then(|argument| argument.parse::<f64>().ok()).filter(|tolerance_m| tolerance_m.is_finite() && *tolerance_m > 0.0);
+        let Some(tolerance_m) = tolerance_m else {
+            return Err("Usage: --simplify meters, a tolerance above 0".to_string());
+        };
+        options.simplify_tolerance_m = Some(tolerance_m);
+    }
+    // Files whose contents were imported before are skipped unless forced
+    options.force = args.iter().any(|arg| arg == "--force");
+    Ok(options)
+}
+
+/// Finds the file the metrics are written to from the arguments: `--metrics-file path`.
+///
+/// ## Returns
+/// * The path, `None` if no metrics are written, or the usage if the path is missing.
+fn metrics_file(args: &[String]) -> Result<Option<std::path::PathBuf>, &'static str> {
+    match args.iter().position(|arg| arg == "--metrics-file") {
+        Some(index) => args.get(index + 1).filter(|argument| !argument.starts_with("--")).map(|path| Some(path.into())).ok_or("Usage: --metrics-file path"),
+        None => Ok(None),
+    }
+}
+
+/// Parses `--goto permalink`, see `geo::Permalink` for what it reads.
+fn goto(args: &[String]) -> Result<Option<geo::Permalink>, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--goto") else {
+        return Ok(None);
+    };
+    let usage = "Usage: --goto \"#map=zoom/lat/lon\", e.g. --goto \"#map=14/55.0309/11.3585\"";
+    match args.get(index + 1).filter(|argument| !argument.starts_with("--")) {
+        Some(argument) => argument.parse().map(Some).map_err(|error| format!("{}: {}", usage, error)),
+        None => Err(usage.to_string()),
+    }
+}
+
@@ -0,0 +1,55 @@
+// Draws the flat colored geometry of the map and of the overlays. Nothing is textured, the
+// color of a vertex comes from the palette.
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) palette_index: u32,
+    @location(2) shade: f32,
+};
+
+// Vertex positions are relative to an origin near the camera, the offset moves them to
+// the current camera center before the scale maps them to clip space.
+// The z coordinate is a height, the extrusion tilts the view so heights rise towards the top
+// of the screen (x) and come closer to the viewer (y). Without extrusion everything is flat.
+struct CameraUniform {
+    offset: vec2<f32>,
+    scale: vec2<f32>,
+    extrusion: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// The colors of the active theme. Vertices refer to them by index, so switching the theme
+// only rewrites this uniform. The shade darkens a color, e.g. for the walls of buildings.
+struct Palette {
+    colors: array<vec4<f32>, 256>,
+};
+
+@group(1) @binding(0)
+var<uniform> palette: Palette;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let color = palette.colors[model.palette_index];
+    out.color = vec4<f32>(color.rgb * model.shade, color.a);
+    let height = model.position.z * abs(camera.scale.y);
+    let xy = (model.position.xy - camera.offset) * camera.scale + vec2<f32>(0.0, height * camera.extrusion.x);
+    // The ground lies halfway into the depth range, so heights have room to come closer
+    let depth = clamp(0.5 - height * camera.extrusion.y, 0.0, 1.0);
+    out.clip_position = vec4<f32>(xy, depth, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,351 @@
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use sqlx::SqlitePool;
+use tracing::{debug, warn};
+
+use crate::database::{count_nodes, count_relations, count_ways, fetch_database_size};
+use crate::threads::spawn_runtime_thread;
+
+/// How often `spawn_metrics_writer` writes the metrics, with the gauges of the database
+/// refreshed.
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The upper bounds of the buckets of every histogram, in seconds. Wide enough for both a
+/// slice of tessellation and the import of a whole country.
+const DURATION_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0, 30.0, 120.0];
+
+/// A count that only goes up, e.g. of the nodes imported.
+///
+/// Counting is a single atomic add, so counters can be used in the loops inserting rows.
+///
+/// # Fields
+/// * `name` - The name of the metric, shared by the counters told apart by their labels.
+/// * `labels` - The labels, e.g. `table="node"`, or empty.
+/// * `help` - What is counted.
+pub struct Counter {
+    name: &'static str,
+    labels: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, labels: &'static str, help: &'static str) -> Self {
+        Counter { name, labels, help, value: AtomicU64::new(0) }
+    }
+
+    pub fn add(&self, count: usize) {
+        self.value.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down, e.g. the size of the database. Stored as the bits of an `f64`.
+pub struct Gauge {
+    name: &'static str,
+    labels: &'static str,
+    help: &'static str,
+    bits: AtomicU64,
+}
+
+impl Gauge {
+    pub const fn new(name: &'static str, labels: &'static str, help: &'static str) -> Self {
+        Gauge { name, labels, help, bits: AtomicU64::new(0) }
+    }
+
+    pub fn set(&self, value: f64) {
+        self.bits.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+}
+
+/// How long something took, counted in the buckets of `DURATION_BUCKETS`.
+///
+/// # Fields
+/// * `buckets` - How many durations fell in every bucket, not cumulative yet.
+/// * `sum_micros` - The sum of the durations, in microseconds so it can be added atomically.
+/// * `count` - How many durations were observed.
+pub struct Histogram {
+    name: &'static str,
+    labels: &'static str,
+    help: &'static str,
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub const fn new(name: &'static str, labels: &'static str, help: &'static str) -> Self {
+        Histogram {
+            name,
+            labels,
+            help,
+            buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len()],
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        // Longer than the last bucket is only counted in `+Inf`, which is `count`
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Observes how long it has been since `started`.
+    pub fn observe_since(&self, started: Instant) {
+        self.observe(started.elapsed());
+    }
+
+    /// Starts timing something that may return early, observed once the timer is dropped.
+    pub fn start_timer(&'static self) -> HistogramTimer {
+        HistogramTimer { histogram: self, started: Instant::now() }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Observes how long it lived in its histogram when dropped, see `Histogram::start_timer`.
+pub struct HistogramTimer {
+    histogram: &'static Histogram,
+    started: Instant,
+}
+
+impl Drop for HistogramTimer {
+    fn drop(&mut self) {
+        self.histogram.observe_since(self.started);
+    }
+}
+
+pub static IMPORTED_NODES: Counter = Counter::new("gmc_imported_elements_total", "type=\"node\"", "The elements inserted or updated by imports.");
+pub static IMPORTED_WAYS: Counter = Counter::new("gmc_imported_elements_total", "type=\"way\"", "The elements inserted or updated by imports.");
+pub static IMPORTED_RELATIONS: Counter = Counter::new("gmc_imported_elements_total", "type=\"relation\"", "The elements inserted or updated by imports.");
+pub static UNCHANGED_ELEMENTS: Counter = Counter::new("gmc_unchanged_elements_total", "", "The elements left alone by imports as they were stored in the same version.");
+pub static IMPORTED_FILES: Counter = Counter::new("gmc_import_files_total", "result=\"imported\"", "The map files and downloads imports were started for.");
+pub static SKIPPED_FILES: Counter = Counter::new("gmc_import_files_total", "result=\"skipped\"", "The map files and downloads imports were started for.");
+pub static INSERTED_ROWS: Counter = Counter::new("gmc_inserted_rows_total", "", "The rows written by batched inserts, including tags and references.");
+pub static INSERT_BATCHES: Counter = Counter::new("gmc_insert_batches_total", "", "The batched insert statements executed.");
+
+pub static IMPORT_READ_SECONDS: Histogram = Histogram::new("gmc_import_phase_seconds", "phase=\"read\"", "How long the phases of an import took.");
+pub static IMPORT_NODES_SECONDS: Histogram = Histogram::new("gmc_import_phase_seconds", "phase=\"nodes\"", "How long the phases of an import took.");
+pub static IMPORT_WAYS_SECONDS: Histogram = Histogram::new("gmc_import_phase_seconds", "phase=\"ways\"", "How long the phases of an import took.");
+pub static IMPORT_GEOMETRY_SECONDS: Histogram = Histogram::new("gmc_import_phase_seconds", "phase=\"geometry\"", "How long the phases of an import took.");
+pub static IMPORT_RELATIONS_SECONDS: Histogram = Histogram::new("gmc_import_phase_seconds", "phase=\"relations\"", "How long the phases of an import took.");
+pub static IMPORT_SNAPSHOT_SECONDS: Histogram = Histogram::new("gmc_import_phase_seconds", "phase=\"snapshot\"", "How long the phases of an import took.");
+
+pub static TESSELLATION_SECONDS: Histogram = Histogram::new("gmc_tessellation_seconds", "mode=\"full\"", "How long tessellating the map took, whole or a slice per frame.");
+pub static TESSELLATION_SLICE_SECONDS: Histogram = Histogram::new("gmc_tessellation_seconds", "mode=\"slice\"", "How long tessellating the map took, whole or a slice per frame.");
+pub static TILE_FETCH_SECONDS: Histogram = Histogram::new("gmc_tile_fetch_seconds", "", "How long fetching the ways of a prefetched tile took.");
+
+pub static ROUTE_SECONDS: Histogram = Histogram::new("gmc_routing_seconds", "query=\"route\"", "How long answering a routing query took.");
+pub static SNAP_SECONDS: Histogram = Histogram::new("gmc_routing_seconds", "query=\"snap\"", "How long answering a routing query took.");
+pub static ISOCHRONE_SECONDS: Histogram = Histogram::new("gmc_routing_seconds", "query=\"isochrone\"", "How long answering a routing query took.");
+
+pub static DATABASE_SIZE: Gauge = Gauge::new("gmc_database_size_bytes", "", "The size of the database.");
+pub static NODE_ROWS: Gauge = Gauge::new("gmc_database_rows", "table=\"node\"", "The rows of the element tables.");
+pub static WAY_ROWS: Gauge = Gauge::new("gmc_database_rows", "table=\"way\"", "The rows of the element tables.");
+pub static RELATION_ROWS: Gauge = Gauge::new("gmc_database_rows", "table=\"relation\"", "The rows of the element tables.");
+
+// The metrics of a name are listed together, as the exposition format wants them
+static COUNTERS: [&Counter; 8] = [&IMPORTED_NODES, &IMPORTED_WAYS, &IMPORTED_RELATIONS, &UNCHANGED_ELEMENTS, &IMPORTED_FILES, &SKIPPED_FILES, &INSERTED_ROWS, &INSERT_BATCHES];
+static HISTOGRAMS: [&Histogram; 12] = [
+    &IMPORT_READ_SECONDS, &IMPORT_NODES_SECONDS, &IMPORT_WAYS_SECONDS, &IMPORT_GEOMETRY_SECONDS, &IMPORT_RELATIONS_SECONDS, &IMPORT_SNAPSHOT_SECONDS,
+    &TESSELLATION_SECONDS, &TESSELLATION_SLICE_SECONDS, &TILE_FETCH_SECONDS,
+    &ROUTE_SECONDS, &SNAP_SECONDS, &ISOCHRONE_SECONDS,
+];
+static GAUGES: [&Gauge; 4] = [&DATABASE_SIZE, &NODE_ROWS, &WAY_ROWS, &RELATION_ROWS];
+
+/// Writes the `# HELP` and `# TYPE` lines of a metric, once for all the metrics sharing its name.
+fn write_header(out: &mut String, last_name: &mut &'static str, name: &'static str, help: &str, kind: &str) -> fmt::Result {
+    if *last_name != name {
+        writeln!(out, "# HELP {} {}", name, help)?;
+        writeln!(out, "# TYPE {} {}", name, kind)?;
+        *last_name = name;
+    }
+    Ok(())
+}
+
+/// Writes the labels of a metric in braces, with another label such as the bound of a
+/// bucket, or nothing if there are none.
+fn label_set(labels: &str, extra: Option<&str>) -> String {
+    let labels: Vec<&str> = iter::once(labels).chain(extra).filter(|label| !label.is_empty()).collect();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+/// Renders every metric in the text exposition format of Prometheus.
+pub fn render_metrics() -> String {
+    let mut out = String::new();
+    // Writing to a string does not fail
+    let _ = render_into(&mut out);
+    out
+}
+
+fn render_into(out: &mut String) -> fmt::Result {
+    let mut last_name = "";
+    for counter in COUNTERS {
+        write_header(out, &mut last_name, counter.name, counter.help, "counter")?;
+        writeln!(out, "{}{} {}", counter.name, label_set(counter.labels, None), counter.get())?;
+    }
+
+    for gauge in GAUGES {
+        write_header(out, &mut last_name, gauge.name, gauge.help, "gauge")?;
+        writeln!(out, "{}{} {}", gauge.name, label_set(gauge.labels, None), gauge.get())?;
+    }
+
+    for histogram in HISTOGRAMS {
+        write_header(out, &mut last_name, histogram.name, histogram.help, "histogram")?;
+        // The buckets of the exposition format count every duration up to their bound
+        let mut cumulative = 0;
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(out, "{}_bucket{} {}", histogram.name, label_set(histogram.labels, Some(&format!("le=\"{}\"", bound))), cumulative)?;
+        }
+        let count = histogram.count();
+        writeln!(out, "{}_bucket{} {}", histogram.name, label_set(histogram.labels, Some("le=\"+Inf\"")), count)?;
+        let sum = histogram.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        writeln!(out, "{}_sum{} {}", histogram.name, label_set(histogram.labels, None), sum)?;
+        writeln!(out, "{}_count{} {}", histogram.name, label_set(histogram.labels, None), count)?;
+    }
+
+    Ok(())
+}
+
+/// Refreshes the gauges of the size and the rows of the database. Counting the rows scans
+/// the tables, so this is done every `METRICS_INTERVAL` rather than on every change.
+pub async fn refresh_database_gauges(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    DATABASE_SIZE.set(fetch_database_size(pool).await? as f64);
+    NODE_ROWS.set(count_nodes(pool).await? as f64);
+    WAY_ROWS.set(count_ways(pool).await? as f64);
+    RELATION_ROWS.set(count_relations(pool).await? as f64);
+    Ok(())
+}
+
+/// Refreshes the gauges and writes every metric to a file, for the textfile collector of the
+/// Prometheus node exporter. The file is replaced at once, so it is never read half written.
+///
+/// ## Arguments
+/// * `path` - The file, e.g. `/var/lib/node_exporter/gmc.prom`.
+pub async fn write_metrics_file(pool: &SqlitePool, path: &Path) -> io::Result<()> {
+    if let Err(error) = refresh_database_gauges(pool).await {
+        warn!(%error, "could not refresh the database metrics");
+    }
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    fs::write(&partial, render_metrics())?;
+    fs::rename(&partial, path)
+}
+
+/// Writes the metrics to a file every `METRICS_INTERVAL` on a thread of its own, as the
+/// event loop of the map holds the main thread. It runs until the program exits.
+pub fn spawn_metrics_writer(pool: SqlitePool, path: PathBuf) {
+    let writer = spawn_runtime_thread("metrics-writer", move || async move {
+        loop {
+            match write_metrics_file(&pool, &path).await {
+                Ok(()) => debug!(path = %path.display(), "wrote the metrics"),
+                Err(error) => warn!(%error, path = %path.display(), "could not write the metrics"),
+            }
+            tokio::time::sleep(METRICS_INTERVAL).await;
+        }
+    });
+    if let Err(error) = writer {
+        warn!(%error, "could not start writing the metrics");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    // The samples of the exposition format, by name and labels
+    fn samples(text: &str) -> HashMap<String, f64> {
+        text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (series, value) = line.rsplit_once(' ').unwrap();
+                (series.to_string(), value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn durations_are_counted_in_the_first_bucket_they_fit() {
+        let histogram = Histogram::new("test_seconds", "", "");
+        histogram.observe(Duration::from_micros(500));
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_millis(5));
+        // Beyond the last bucket, counted in +Inf alone
+        histogram.observe(Duration::from_secs(200));
+
+        let buckets: Vec<u64> = histogram.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect();
+        assert_eq!(buckets, [1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.sum_micros.load(Ordering::Relaxed), 200_008_500);
+    }
+
+    #[test]
+    fn labels_are_joined_in_braces() {
+        assert_eq!(label_set("", None), "");
+        assert_eq!(label_set("", Some("le=\"0.5\"")), "{le=\"0.5\"}");
+        assert_eq!(label_set("type=\"node\"", Some("le=\"+Inf\"")), "{type=\"node\",le=\"+Inf\"}");
+    }
+
+    #[tokio::test]
+    async fn the_metrics_file_shows_an_import() {
+        let pool = memory_pool("metrics_import").await;
+        // Other tests import at the same time, so the counters are at least what this import adds
+        let nodes_before = IMPORTED_NODES.get();
+        let files_before = IMPORTED_FILES.get();
+        let read_before = IMPORT_READ_SECONDS.count();
+        import_osm_xml(&pool, "metrics_import", r#"<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"/>
+ <node id="2" lat="55.1" lon="12.1" version="1"/>
+ <node id="3" lat="55.2" lon="12.2" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><tag k="highway" v="residential"/></way>
+</osm>"#).await;
+
+        let path = std::env::temp_dir().join(format!("gmc_metrics_{}.prom", std::process::id()));
+        write_metrics_file(&pool, &path).await.unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let samples = samples(&text);
+
+        assert!(samples["gmc_imported_elements_total{type=\"node\"}"] >= (nodes_before + 3) as f64);
+        assert!(samples["gmc_import_files_total{result=\"imported\"}"] >= (files_before + 1) as f64);
+        assert!(samples["gmc_inserted_rows_total"] >= 4.0);
+        assert!(samples["gmc_import_phase_seconds_count{phase=\"read\"}"] >= (read_before + 1) as f64);
+        assert_eq!(samples["gmc_import_phase_seconds_bucket{phase=\"read\",le=\"+Inf\"}"], samples["gmc_import_phase_seconds_count{phase=\"read\"}"]);
+        assert_eq!(samples["gmc_database_rows{table=\"node\"}"], 3.0);
+        assert_eq!(samples["gmc_database_rows{table=\"way\"}"], 1.0);
+        assert!(samples["gmc_database_size_bytes"] > 0.0);
+
+        // Every name is described once, before its samples
+        assert_eq!(text.matches("# TYPE gmc_import_phase_seconds histogram\n").count(), 1);
+        assert!(text.find("# HELP gmc_database_rows ").unwrap() < text.find("gmc_database_rows{table=\"node\"}").unwrap());
+    }
+}
@@ -0,0 +1,67 @@
+use std::env;
+
+use tracing::warn;
+
+/// The environment variable setting the most nodes drawn as dots, see `max_node_dots_from_env`.
+pub const MAX_NODE_DOTS_ENV: &str = "GMC_MAX_NODE_DOTS";
+/// How many nodes are drawn as dots unless `GMC_MAX_NODE_DOTS` says otherwise.
+pub const DEFAULT_MAX_NODE_DOTS: usize = 5000;
+/// The most dots there can be, as they share one overlay buffer with 16 bit indices and every
+/// dot has four vertices.
+pub const MAX_NODE_DOTS: usize = u16::MAX as usize / 4;
+
+/// The most nodes drawn as dots where no ways are in view, `DEFAULT_MAX_NODE_DOTS` unless
+/// overridden by `GMC_MAX_NODE_DOTS`. Zero draws none, a value above `MAX_NODE_DOTS` is
+/// lowered to it, and one that cannot be parsed is ignored with a warning.
+pub fn max_node_dots_from_env() -> usize {
+    match env::var(MAX_NODE_DOTS_ENV) {
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(max) => max.min(MAX_NODE_DOTS),
+            Err(error) => {
+                warn!(variable = MAX_NODE_DOTS_ENV, value, %error, "ignoring");
+                DEFAULT_MAX_NODE_DOTS
+            }
+        },
+        Err(_) => DEFAULT_MAX_NODE_DOTS,
+    }
+}
+
+/// The stride to sample nodes with so at most `max` of `count` are drawn, e.g. of a survey
+/// too dense to draw every one of, see `fetch_nodes_in_bbox`.
+///
+/// Sampling by stride picks the same nodes of a viewport every time, so the dots do not
+/// flicker when it is drawn again.
+///
+/// ## Returns
+/// * 1 if there are no more than `max` nodes, otherwise the smallest stride leaving at most `max`.
+pub fn node_dot_stride(count: usize, max: usize) -> usize {
+    count.div_ceil(max.max(1)).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_node_is_drawn_below_the_cap() {
+        assert_eq!(node_dot_stride(0, 5000), 1);
+        assert_eq!(node_dot_stride(4999, 5000), 1);
+        assert_eq!(node_dot_stride(5000, 5000), 1);
+    }
+
+    #[test]
+    fn the_stride_leaves_at_most_the_cap() {
+        for (count, max) in [(5001, 5000), (20_000, 5000), (20_001, 5000), (1_000_000, 16_383), (7, 3)] {
+            let stride = node_dot_stride(count, max);
+            // The sampled nodes are the first and every `stride`th after it
+            let sampled = count.div_ceil(stride);
+            assert!(sampled <= max, "{} of {} nodes sampled with a stride of {}, more than {}", sampled, count, stride, max);
+            assert!(count.div_ceil(stride - 1) > max, "a stride of {} would do for {} of {} nodes", stride - 1, max, count);
+        }
+    }
+
+    #[test]
+    fn a_cap_of_zero_does_not_divide_by_zero() {
+        assert_eq!(node_dot_stride(10, 0), 10);
+    }
+}
@@ -0,0 +1,198 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+
+use flate2::read::MultiGzDecoder;
+
+use super::{read_osm_json, read_osm_xml, OsmData};
+
+/// The path standing for standard input, e.g. to pipe an extract in with `--import -`.
+pub const STDIN_PATH: &str = "-";
+
+// How much of the input is looked at to tell its format, enough for any leading whitespace
+const SNIFF_LEN: usize = 4096;
+// The magic number gzip streams start with
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// The type of the first blob of a PBF file, after the length of its header and the tag of the field
+const PBF_HEADER_TYPE: &[u8] = b"OSMHeader";
+
+/// The formats of map data, as told by `sniff_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Xml,
+    Json,
+    Gzip,
+    Pbf,
+}
+
+impl fmt::Display for InputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputFormat::Xml => "OSM XML",
+            InputFormat::Json => "OSM JSON",
+            InputFormat::Gzip => "gzip",
+            InputFormat::Pbf => "OSM PBF",
+        })
+    }
+}
+
+/// Tells the format of map data from its first bytes, for input without a file extension.
+///
+/// ## Returns
+/// * The format, or `None` if the bytes look like none of them.
+pub fn sniff_format(start: &[u8]) -> Option<InputFormat> {
+    if start.starts_with(&GZIP_MAGIC) {
+        return Some(InputFormat::Gzip);
+    }
+    // A PBF file starts with the big-endian length of the first blob header, whose first field is its type
+    if start.len() >= 6 + PBF_HEADER_TYPE.len() && start[4] == 0x0a && start[6..].starts_with(PBF_HEADER_TYPE) {
+        return Some(InputFormat::Pbf);
+    }
+
+    let text = start.strip_prefix("\u{feff}".as_bytes()).unwrap_or(start);
+    let text = &text[text.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(text.len())..];
+    if text.starts_with(b"<?xml") || text.starts_with(b"<osm") {
+        Some(InputFormat::Xml)
+    } else if text.starts_with(b"{") {
+        Some(InputFormat::Json)
+    } else {
+        None
+    }
+}
+
+/// An error while reading map data whose format is told from its contents.
+#[derive(Debug)]
+pub enum InputError {
+    Io(io::Error),
+    /// The input holds nothing.
+    Empty,
+    /// The first bytes match none of the formats, shown as far as they are text.
+    UnknownFormat { start: String },
+    /// The input is in a format that can be told but not read.
+    Unsupported(InputFormat),
+    /// The input is not valid in the format it was told to be in.
+    Parse { format: InputFormat, message: String },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Io(error) => write!(f, "{}", error),
+            InputError::Empty => write!(f, "the input is empty"),
+            InputError::UnknownFormat { start } => write!(f, "could not detect the format, expected OSM XML, OSM JSON or either gzipped, but the input starts with {:?}", start),
+            InputError::Unsupported(InputFormat::Pbf) => write!(f, "OSM PBF can not be read, convert it to XML first, e.g. with `osmium cat -f osm`"),
+            InputError::Unsupported(format) => write!(f, "{} can not be read here", format),
+            InputError::Parse { format, message } => write!(f, "could not read the {}: {}", format, message),
+        }
+    }
+}
+
+impl StdError for InputError {}
+
+impl From<io::Error> for InputError {
+    fn from(error: io::Error) -> Self {
+        InputError::Io(error)
+    }
+}
+
+/// Opens a map file to read with `read_osm_input`, or standard input for `STDIN_PATH`.
+pub fn open_osm_input(path: &str) -> io::Result<Box<dyn Read>> {
+    if path == STDIN_PATH {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+/// Reads map data in whatever format it is in, told from its first bytes by `sniff_format`.
+/// Gzipped data is decompressed and its format told in turn. The input is read once, from
+/// start to end, so it may be a pipe.
+///
+/// ## Returns
+/// * The elements read, or an error naming the format if it could not be told or read.
+pub fn read_osm_input<'a>(source: impl Read + 'a) -> Result<OsmData, InputError> {
+    read_sniffed(Box::new(source), true)
+}
+
+// The source is boxed, as decompressing wraps it in another reader to sniff again
+fn read_sniffed(mut source: Box<dyn Read + '_>, decompress: bool) -> Result<OsmData, InputError> {
+    // The bytes looked at are read ahead and put back in front of the rest
+    let mut start = Vec::with_capacity(SNIFF_LEN);
+    source.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut start)?;
+    if start.is_empty() {
+        return Err(InputError::Empty);
+    }
+    let Some(format) = sniff_format(&start) else {
+        let start: String = String::from_utf8_lossy(&start).chars().take(32).collect();
+        return Err(InputError::UnknownFormat { start });
+    };
+    let source = BufReader::new(Cursor::new(start).chain(source));
+
+    let parsed = match format {
+        InputFormat::Xml => read_osm_xml(source),
+        InputFormat::Json => read_osm_json(source),
+        // Gzip within gzip is not something extracts come as
+        InputFormat::Gzip if decompress => return read_sniffed(Box::new(MultiGzDecoder::new(source)), false),
+        InputFormat::Gzip | InputFormat::Pbf => return Err(InputError::Unsupported(format)),
+    };
+    parsed.map_err(|error| InputError::Parse { format, message: error.to_string() })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    const VEJRO_OSM: &[u8] = include_bytes!("../../utils/mapdata/vejrø");
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn counts(data: &OsmData) -> (usize, usize, usize) {
+        (data.nodes.items.len(), data.ways.items.len(), data.relations.items.len())
+    }
+
+    #[test]
+    fn an_extract_read_from_memory_is_sniffed_as_xml() {
+        assert_eq!(sniff_format(VEJRO_OSM), Some(InputFormat::Xml));
+        let data = read_osm_input(Cursor::new(VEJRO_OSM)).unwrap();
+        assert_eq!(counts(&data), (1749, 135, 6));
+
+        // Gzipped, as `osmium ... -o - | gzip` writes it, the same elements come out
+        let gzipped = gzip(VEJRO_OSM);
+        assert_eq!(sniff_format(&gzipped), Some(InputFormat::Gzip));
+        assert_eq!(counts(&read_osm_input(Cursor::new(gzipped)).unwrap()), (1749, 135, 6));
+    }
+
+    #[test]
+    fn the_format_is_told_from_the_first_bytes() {
+        assert_eq!(sniff_format(b"\xef\xbb\xbf  \n<osm version=\"0.6\">"), Some(InputFormat::Xml));
+        assert_eq!(sniff_format(b"\n\t{\"elements\": []}"), Some(InputFormat::Json));
+        assert_eq!(sniff_format(b"\0\0\0\x0d\x0a\x09OSMHeader\x18"), Some(InputFormat::Pbf));
+        assert_eq!(sniff_format(b"<gpx>"), None);
+        assert_eq!(sniff_format(b"   "), None);
+
+        // JSON within gzip is sniffed again once decompressed
+        let data = read_osm_input(Cursor::new(gzip(br#"{"elements": [{"type": "node", "id": 1, "lat": 55.0, "lon": 12.0}]}"#))).unwrap();
+        assert_eq!(counts(&data), (1, 0, 0));
+    }
+
+    #[test]
+    fn input_that_cannot_be_read_says_why() {
+        let error = |input: &[u8]| read_osm_input(Cursor::new(input.to_vec())).unwrap_err().to_string();
+
+        assert_eq!(error(b""), "the input is empty");
+        assert_eq!(error(b"id,lat,lon\n1,55,12"), "could not detect the format, expected OSM XML, OSM JSON or either gzipped, but the input starts with \"id,lat,lon\\n1,55,12\"");
+        assert!(error(b"\0\0\0\x0d\x0a\x09OSMHeader\x18").starts_with("OSM PBF can not be read, convert it to XML first"));
+        assert_eq!(error(&gzip(&gzip(VEJRO_OSM))), "gzip can not be read here");
+        assert!(error(b"{\"version\": 0.6}").starts_with("could not read the OSM JSON: "));
+        assert!(error(b"<osm><node id=\"1\" lat=\"55\" lon=\"12\"></way></osm>").starts_with("could not read the OSM XML: "));
+    }
+}
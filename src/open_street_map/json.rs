@@ -0,0 +1,328 @@
+use std::error::Error;
+use std::io::Read;
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    osm_entities::{Member, Node, Relation, Tag, Way},
+    utils::MapsType
+};
+
+use super::{OsmData, ReadOutcome, MAX_WARNINGS};
+
+/// An OSM JSON document, as returned by Overpass for `[out:json]`. Only `elements` is
+/// required, the version and generator are ignored.
+///
+/// # Fields
+/// * `elements` - The nodes, ways and relations, and whatever else the query asked for.
+/// * `remark` - Why the server stopped early, e.g. a timeout, in which case `elements` is incomplete.
+#[derive(Deserialize)]
+struct OsmJsonDocument {
+    elements: Vec<Value>,
+    remark: Option<String>,
+}
+
+/// Whether a document starts like JSON rather than XML, judged from its first bytes, so
+/// files without an extension are read right too.
+pub fn is_osm_json(start: &[u8]) -> bool {
+    start.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{')
+}
+
+/// Reads the nodes, ways and relations of an OSM JSON document, the format of Overpass
+/// for `[out:json]`.
+///
+/// The elements are read like the XML readers read theirs: an element with a missing or
+/// malformed `id`, or a node without valid coordinates, is skipped, and a malformed tag,
+/// node reference or member only drops that. The metadata Overpass leaves out without
+/// `out meta` falls back to its default. Elements of other types, e.g. `area`, are ignored.
+///
+/// ## Returns
+/// * The elements read, or an error if the document is not JSON or has no `elements`.
+pub fn read_osm_json(reader: impl Read) -> Result<OsmData, Box<dyn Error>> {
+    let document: OsmJsonDocument = serde_json::from_reader(reader)?;
+
+    let mut data = OsmData { nodes: ReadOutcome::new(), ways: ReadOutcome::new(), relations: ReadOutcome::new() };
+    if let Some(remark) = &document.remark {
+        let message = format!("the document may be incomplete: {}", remark);
+        data.nodes.warnings.push(message.clone());
+        data.ways.warnings.push(message.clone());
+        data.relations.warnings.push(message);
+    }
+
+    for (index, element) in document.elements.iter().enumerate() {
+        let Some(element) = element.as_object().map(JsonElement) else {
+            continue;
+        };
+
+        match element.0.get("type").and_then(Value::as_str) {
+            Some("node") => push_or_skip(&mut data.nodes, index, "node", parse_node(&element)),
+            Some("way") => push_or_skip(&mut data.ways, index, "way", parse_way(&element)),
+            Some("relation") => push_or_skip(&mut data.relations, index, "relation", parse_relation(&element)),
+            _ => (),
+        }
+    }
+
+    Ok(data)
+}
+
+fn warn(warnings: &mut Vec<String>, index: usize, message: String) {
+    if warnings.len() < MAX_WARNINGS {
+        warnings.push(format!("element {}: {}", index, message));
+    }
+}
+
+/// Keeps a parsed element with the warnings about it, or counts it as skipped.
+fn push_or_skip<T>(outcome: &mut ReadOutcome<T>, index: usize, element: &str, parsed: Result<(T, Vec<String>), String>) {
+    match parsed {
+        Ok((item, warnings)) => {
+            outcome.items.push(item);
+            for warning in warnings {
+                warn(&mut outcome.warnings, index, warning);
+            }
+        }
+        Err(error) => {
+            outcome.skipped += 1;
+            warn(&mut outcome.warnings, index, format!("skipped {}: {}", element, error));
+        }
+    }
+}
+
+/// The members of one element of the document.
+struct JsonElement<'a>(&'a Map<String, Value>);
+
+impl JsonElement<'_> {
+    /// Reads a member the element cannot do without.
+    fn required<'de, T: Deserialize<'de>>(&'de self, key: &str) -> Result<T, String> {
+        let value = self.0.get(key).ok_or_else(|| format!("missing `{}`", key))?;
+        T::deserialize(value).map_err(|error| format!("invalid `{}` {}: {}", key, value, error))
+    }
+
+    /// Reads a member that falls back to its default when missing or malformed. Malformed
+    /// values are reported in `warnings`.
+    fn optional<'de, T: Deserialize<'de> + Default>(&'de self, key: &str, warnings: &mut Vec<String>) -> T {
+        match self.0.get(key) {
+            None | Some(Value::Null) => T::default(),
+            Some(value) => T::deserialize(value).unwrap_or_else(|error| {
+                warnings.push(format!("invalid `{}` {}: {}", key, value, error));
+                T::default()
+            }),
+        }
+    }
+
+    /// The tags, from an object of keys and values. A value that is not a string drops its tag.
+    fn tags(&self, warnings: &mut Vec<String>) -> Vec<Tag> {
+        let Some(tags) = self.0.get("tags") else {
+            return Vec::new();
+        };
+        let Some(tags) = tags.as_object() else {
+            warnings.push(format!("skipped tags: expected an object, got {}", tags));
+            return Vec::new();
+        };
+
+        tags.iter()
+            .filter_map(|(key, value)| match value.as_str() {
+                Some(value) => Some(Tag { key: key.clone(), value: value.to_string() }),
+                None => {
+                    warnings.push(format!("skipped tag `{}`: expected a string, got {}", key, value));
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+fn parse_node(element: &JsonElement) -> Result<(Node, Vec<String>), String> {
+    let mut warnings = Vec::new();
+    let id = element.required("id")?;
+    let lat: f64 = element.required("lat")?;
+    let lon: f64 = element.required("lon")?;
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(format!("`lat` {} is not between -90 and 90", lat));
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(format!("`lon` {} is not between -180 and 180", lon));
+    }
+
+    let node = Node {
+        id,
+        lat,
+        lon,
+        version: element.optional("version", &mut warnings),
+        timestamp: element.optional("timestamp", &mut warnings),
+        changeset: element.optional("changeset", &mut warnings),
+        uid: element.optional("uid", &mut warnings),
+        user: element.optional("user", &mut warnings),
+        tags: element.tags(&mut warnings),
+    };
+    Ok((node, warnings))
+}
+
+fn parse_way(element: &JsonElement) -> Result<(Way, Vec<String>), String> {
+    let mut warnings = Vec::new();
+    let id = element.required("id")?;
+
+    // The order of the references is the shape of the way, a malformed one only drops itself
+    let node_refs = match element.0.get("nodes") {
+        None => Vec::new(),
+        Some(Value::Array(refs)) => refs.iter()
+            .filter_map(|node_ref| {
+                let parsed = node_ref.as_i64();
+                if parsed.is_none() {
+                    warnings.push(format!("skipped node reference: invalid `ref` {}", node_ref));
+                }
+                parsed
+            })
+            .collect(),
+        Some(other) => {
+            warnings.push(format!("skipped node references: expected an array, got {}", other));
+            Vec::new()
+        }
+    };
+
+    let way = Way {
+        id,
+        version: element.optional("version", &mut warnings),
+        timestamp: element.optional("timestamp", &mut warnings),
+        changeset: element.optional("changeset", &mut warnings),
+        uid: element.optional("uid", &mut warnings),
+        user: element.optional("user", &mut warnings),
+        node_refs,
+        tags: element.tags(&mut warnings),
+    };
+    Ok((way, warnings))
+}
+
+fn parse_member(member: &Value, warnings: &mut Vec<String>) -> Result<Member, String> {
+    let member = member.as_object().map(JsonElement).ok_or_else(|| format!("expected an object, got {}", member))?;
+
+    // Matched here like the XML reader does, so made up types are not kept
+    let maps_type = match member.required::<String>("type")?.as_str() {
+        "node" => MapsType::Node,
+        "way" => MapsType::Way,
+        "relation" => MapsType::Relation,
+        other => return Err(format!("unknown member type `{}`", other)),
+    };
+    let ref_id = member.required("ref")?;
+    let role = member.optional("role", warnings);
+
+    Ok(Member::new(ref_id, maps_type, role))
+}
+
+fn parse_relation(element: &JsonElement) -> Result<(Relation, Vec<String>), String> {
+    let mut warnings = Vec::new();
+    let id = element.required("id")?;
+
+    let members = match element.0.get("members") {
+        None => Vec::new(),
+        Some(Value::Array(members)) => members.iter()
+            .filter_map(|member| match parse_member(member, &mut warnings) {
+                Ok(member) => Some(member),
+                Err(error) => {
+                    warnings.push(format!("skipped member: {}", error));
+                    None
+                }
+            })
+            .collect(),
+        Some(other) => {
+            warnings.push(format!("skipped members: expected an array, got {}", other));
+            Vec::new()
+        }
+    };
+
+    let relation = Relation {
+        id,
+        version: element.optional("version", &mut warnings),
+        timestamp: element.optional("timestamp", &mut warnings),
+        changeset: element.optional("changeset", &mut warnings),
+        uid: element.optional("uid", &mut warnings),
+        user: element.optional("user", &mut warnings),
+        tags: element.tags(&mut warnings),
+        members,
+    };
+    Ok((relation, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Overpass output for `[out:json]`: node 103 and way 202 come without metadata as if
+    // queried without `out meta`, node 104 lies beyond the pole and the relation has an area member
+    const OVERPASS_JSON: &str = include_str!("../../utils/mapdata/overpass.json");
+
+    fn tags_of(tags: &[Tag]) -> Vec<(&str, &str)> {
+        let mut tags: Vec<(&str, &str)> = tags.iter().map(|tag| (tag.key.as_str(), tag.value.as_str())).collect();
+        tags.sort();
+        tags
+    }
+
+    #[test]
+    fn an_overpass_document_is_read_into_nodes_ways_and_relations() {
+        let data = read_osm_json(OVERPASS_JSON.as_bytes()).unwrap();
+        assert_eq!((data.nodes.items.len(), data.ways.items.len(), data.relations.items.len()), (3, 2, 1));
+        assert_eq!((data.nodes.skipped, data.ways.skipped, data.relations.skipped), (1, 0, 0));
+        assert_eq!(data.nodes.warnings, ["element 3: skipped node: `lat` 95 is not between -90 and 90"]);
+
+        let cafe = &data.nodes.items[0];
+        assert_eq!((cafe.id, cafe.lat, cafe.lon, cafe.version, cafe.changeset, cafe.uid), (101, 55.6761, 12.5683, 3, 150000001, 4711));
+        assert_eq!((cafe.timestamp.as_str(), cafe.user.as_str()), ("2024-05-01T08:00:00Z", "mapper"));
+        assert_eq!(tags_of(&cafe.tags), [("amenity", "cafe"), ("name", "Kaffebaren")]);
+
+        // Without metadata the defaults are used, which is not worth a warning
+        let bare = &data.nodes.items[2];
+        assert_eq!((bare.id, bare.version, bare.timestamp.as_str(), bare.changeset, bare.uid, bare.user.as_str()), (103, 0, "", 0, 0, ""));
+        assert!(bare.tags.is_empty());
+
+        // The references keep the order of the document, which is the shape of the way
+        let building = &data.ways.items[0];
+        assert_eq!((building.id, building.version), (201, 2));
+        assert_eq!(building.node_refs, [103, 101, 102, 103]);
+        assert_eq!(tags_of(&building.tags), [("building", "yes"), ("building:levels", "4")]);
+
+        let relation = &data.relations.items[0];
+        let members: Vec<(i64, MapsType, &str)> = relation.members.iter().map(|member| (member.ref_id, member.maps_type.clone(), member.role.as_str())).collect();
+        assert_eq!(members, [(201, MapsType::Way, "outer"), (101, MapsType::Node, "")]);
+        assert_eq!(data.relations.warnings, ["element 6: skipped member: unknown member type `area`"]);
+    }
+
+    #[test]
+    fn malformed_parts_of_elements_are_dropped_with_a_warning() {
+        let data = read_osm_json(r#"{"elements": [
+            {"type": "node", "id": 1, "lat": 55.0, "lon": 12.0, "version": "two", "tags": {"name": "A", "level": 3}},
+            {"type": "node", "lat": 55.0, "lon": 12.0},
+            {"type": "way", "id": 10, "nodes": [1, "2", 3]},
+            {"type": "way", "id": 11, "nodes": {"1": 1}},
+            "not an element"
+        ]}"#.as_bytes()).unwrap();
+
+        let node = &data.nodes.items[0];
+        assert_eq!((node.version, tags_of(&node.tags)), (0, vec![("name", "A")]));
+        assert_eq!(data.nodes.skipped, 1);
+        assert_eq!(data.nodes.warnings.len(), 3);
+        assert!(data.nodes.warnings[2].starts_with("element 1: skipped node: missing `id`"), "{:?}", data.nodes.warnings);
+
+        assert_eq!(data.ways.items[0].node_refs, [1, 3]);
+        assert!(data.ways.items[1].node_refs.is_empty());
+        assert_eq!(data.ways.warnings, [
+            "element 2: skipped node reference: invalid `ref` \"2\"",
+            "element 3: skipped node references: expected an array, got {\"1\":1}",
+        ]);
+    }
+
+    #[test]
+    fn a_remark_warns_that_the_document_may_be_incomplete() {
+        let data = read_osm_json(r#"{"elements": [], "remark": "runtime error: Query timed out"}"#.as_bytes()).unwrap();
+        assert_eq!(data.ways.warnings, ["the document may be incomplete: runtime error: Query timed out"]);
+
+        assert!(read_osm_json(r#"{"version": 0.6}"#.as_bytes()).is_err());
+        assert!(read_osm_json("<osm/>".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn json_is_told_apart_from_xml_by_its_first_byte() {
+        assert!(is_osm_json(b"  \n{\"elements\": []}"));
+        assert!(!is_osm_json(b"<?xml version=\"1.0\"?>"));
+        assert!(!is_osm_json(b""));
+    }
+}
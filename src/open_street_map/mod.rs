@@ -1,3 +1,11 @@
 pub mod readers;
+pub mod overpass;
+pub mod osc;
+pub mod json;
+pub mod input;
 
 pub use readers::*;
+pub use overpass::*;
+pub use osc::*;
+pub use json::*;
+pub use input::*;
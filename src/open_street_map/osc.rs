@@ -0,0 +1,235 @@
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::osm_entities::{Node, Relation, Tag, Way};
+
+use super::readers::{parse_member, parse_node, parse_node_ref, parse_relation, parse_tag, parse_way, ElementAttributes, MAX_WARNINGS};
+
+/// What an OsmChange file does with an element, given by the section it is listed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsmAction {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl OsmAction {
+    fn from_section(name: &[u8]) -> Option<Self> {
+        match name {
+            b"create" => Some(OsmAction::Create),
+            b"modify" => Some(OsmAction::Modify),
+            b"delete" => Some(OsmAction::Delete),
+            _ => None,
+        }
+    }
+}
+
+/// An element of an OsmChange file with the action to apply to it.
+#[derive(Debug, Clone)]
+pub struct Change<T> {
+    pub action: OsmAction,
+    pub element: T,
+}
+
+/// The changes read from an OsmChange (`.osc`) file, in the order they are listed.
+///
+/// # Fields
+/// * `nodes`, `ways`, `relations` - The changed elements of each type.
+/// * `skipped` - The number of elements left out because a required attribute was missing or malformed.
+/// * `warnings` - Descriptions of the first `MAX_WARNINGS` problems, including those that did not skip an element.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSet {
+    pub nodes: Vec<Change<Node>>,
+    pub ways: Vec<Change<Way>>,
+    pub relations: Vec<Change<Relation>>,
+    pub skipped: usize,
+    pub warnings: Vec<String>,
+}
+
+impl ChangeSet {
+    fn warn(&mut self, position: u64, message: String) {
+        if self.warnings.len() < MAX_WARNINGS {
+            self.warnings.push(format!("byte {}: {}", position, message));
+        }
+    }
+
+    /// Parses a node, way or relation and keeps it as a change of the current section, or
+    /// counts it as skipped.
+    ///
+    /// ## Returns
+    /// * The element nested elements now belong to, or `None` if it was skipped.
+    fn push_or_skip(&mut self, position: u64, action: Option<OsmAction>, e: &BytesStart, warnings: &mut Vec<String>) -> Option<OpenElement> {
+        let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+        let Some(action) = action else {
+            self.skipped += 1;
+            self.warn(position, format!("skipped {}: not within <create>, <modify> or <delete>", name));
+            return None;
+        };
+
+        let parsed = match e.name().as_ref() {
+            b"node" => parse_change_node(e, action, warnings).map(|element| {
+                self.nodes.push(Change { action, element });
+                OpenElement::Node
+            }),
+            b"way" => parse_way(e, warnings).map(|element| {
+                self.ways.push(Change { action, element });
+                OpenElement::Way
+            }),
+            _ => parse_relation(e, warnings).map(|element| {
+                self.relations.push(Change { action, element });
+                OpenElement::Relation
+            }),
+        };
+
+        match parsed {
+            Ok(open) => Some(open),
+            Err(error) => {
+                self.skipped += 1;
+                self.warn(position, format!("skipped {}: {}", name, error));
+                None
+            }
+        }
+    }
+
+    /// Adds a tag to the element read last of the given type.
+    fn push_tag(&mut self, open: Option<OpenElement>, tag: Tag) {
+        let tags = match open {
+            Some(OpenElement::Node) => self.nodes.last_mut().map(|change| &mut change.element.tags),
+            Some(OpenElement::Way) => self.ways.last_mut().map(|change| &mut change.element.tags),
+            Some(OpenElement::Relation) => self.relations.last_mut().map(|change| &mut change.element.tags),
+            None => None,
+        };
+        if let Some(tags) = tags {
+            tags.push(tag);
+        }
+    }
+}
+
+// The element nested tags, node references and members belong to
+#[derive(Clone, Copy, PartialEq)]
+enum OpenElement {
+    Node,
+    Way,
+    Relation,
+}
+
+/// Parses a changed node. Deleting a node does not need its position, so only nodes that
+/// are created or modified must have one.
+fn parse_change_node(e: &BytesStart, action: OsmAction, warnings: &mut Vec<String>) -> Result<Node, String> {
+    if action != OsmAction::Delete {
+        return parse_node(e, warnings);
+    }
+
+    let attributes = ElementAttributes::read(e)?;
+    Ok(Node {
+        id: attributes.required("id")?,
+        lat: attributes.optional("lat", warnings),
+        lon: attributes.optional("lon", warnings),
+        version: attributes.optional("version", warnings),
+        timestamp: attributes.optional("timestamp", warnings),
+        changeset: attributes.optional("changeset", warnings),
+        uid: attributes.optional("uid", warnings),
+        user: attributes.optional("user", warnings),
+        tags: Vec::new(),
+    })
+}
+
+/// Reads an OsmChange (`.osc`) file, e.g. a minutely diff of the OpenStreetMap replication.
+///
+/// The elements are read from the `<create>`, `<modify>` and `<delete>` sections. A deleted
+/// node may come without a position, it then gets `0.0` for both. Elements that cannot be
+/// parsed are skipped like in `read_nodes_from_file`, elements outside of a section are
+/// skipped with a warning.
+///
+/// ## Arguments
+/// * `path` - The path to the OsmChange file.
+///
+/// ## Returns
+/// * The changes if successful, or an error if the file cannot be opened or is not well-formed XML.
+pub fn read_osc_file(path: &str) -> Result<ChangeSet, Box<dyn Error>> {
+    let file = File::open(path)?;
+    read_osc(BufReader::new(file))
+}
+
+/// Reads OsmChange XML held in memory, e.g. a downloaded diff.
+pub fn read_osc_from_bytes(bytes: &[u8]) -> Result<ChangeSet, Box<dyn Error>> {
+    read_osc(bytes)
+}
+
+fn read_osc<R: BufRead>(source: R) -> Result<ChangeSet, Box<dyn Error>> {
+    let mut reader = Reader::from_reader(source);
+
+    let mut changes = ChangeSet::default();
+    let mut action: Option<OsmAction> = None;
+    let mut open: Option<OpenElement> = None;
+    let mut buf = Vec::new();
+
+    loop {
+        let position = reader.buffer_position();
+        let mut warnings = Vec::new();
+
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) if OsmAction::from_section(e.name().as_ref()).is_some() => {
+                action = OsmAction::from_section(e.name().as_ref());
+            }
+            Ok(Event::End(ref e)) if OsmAction::from_section(e.name().as_ref()).is_some() => action = None,
+
+            // Handle the start of an element with nested elements (non-self-closing)
+            Ok(Event::Start(ref e)) if matches!(e.name().as_ref(), b"node" | b"way" | b"relation") => {
+                open = changes.push_or_skip(position, action, e, &mut warnings);
+            }
+            Ok(Event::End(ref e)) if matches!(e.name().as_ref(), b"node" | b"way" | b"relation") => open = None,
+            // Handle self-closing elements, e.g. most deleted ones
+            Ok(Event::Empty(ref e)) if matches!(e.name().as_ref(), b"node" | b"way" | b"relation") => {
+                changes.push_or_skip(position, action, e, &mut warnings);
+            }
+
+            // Handle <tag> elements nested within any element
+            Ok(Event::Empty(ref e)) if open.is_some() && e.name().as_ref() == b"tag" => {
+                match parse_tag(e, &mut warnings) {
+                    Ok(tag) => changes.push_tag(open, tag),
+                    Err(error) => warnings.push(format!("skipped tag: {}", error)),
+                }
+            }
+
+            // Handle <nd> elements nested within <way> elements
+            Ok(Event::Empty(ref e)) if open == Some(OpenElement::Way) && e.name().as_ref() == b"nd" => {
+                match parse_node_ref(e) {
+                    Ok(node_ref) => {
+                        if let Some(last_way) = changes.ways.last_mut() {
+                            last_way.element.node_refs.push(node_ref);
+                        }
+                    }
+                    Err(error) => warnings.push(format!("skipped node reference: {}", error)),
+                }
+            }
+
+            // Handle <member> elements nested within <relation> elements
+            Ok(Event::Empty(ref e)) if open == Some(OpenElement::Relation) && e.name().as_ref() == b"member" => {
+                if let Some(last_relation) = changes.relations.last_mut() {
+                    match parse_member(e, &mut warnings) {
+                        Ok(member) => last_relation.element.members.push(member),
+                        Err(error) => warnings.push(format!("skipped member: {}", error)),
+                    }
+                }
+            }
+
+            // End of the XML document
+            Ok(Event::Eof) => break,
+            // Handle errors
+            Err(e) => return Err(Box::new(e)),
+            _ => (),
+        }
+
+        for warning in warnings {
+            changes.warn(position, warning);
+        }
+        // Clear buffer for the next read
+        buf.clear();
+    }
+
+    Ok(changes)
+}
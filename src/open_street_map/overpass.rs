@@ -0,0 +1,546 @@
+use std::env;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use native_tls::TlsConnector;
+use url::{form_urlencoded, Position, Url};
+
+use crate::geo::{BBox, BBoxError};
+use crate::osm_entities::{Node, Relation, Way};
+
+use super::{is_osm_json, read_osm_json, read_nodes_from_bytes, read_relations_from_bytes, read_ways_from_bytes, ReadOutcome};
+
+/// The public Overpass instance used unless `OVERPASS_ENDPOINT` names another one.
+pub const DEFAULT_OVERPASS_ENDPOINT: &str = "https://overpass-api.de/api/interpreter";
+
+/// Overpass asks clients to identify themselves, so a shared instance can contact heavy users.
+pub const OVERPASS_USER_AGENT: &str = concat!("GoogleMapsClone/", env!("CARGO_PKG_VERSION"), " (OpenStreetMap viewer)");
+
+/// Where and how much to download.
+///
+/// # Fields
+/// * `endpoint` - The URL of the Overpass interpreter.
+/// * `max_area_deg2` - The largest box in square degrees a download may cover, to keep requests polite.
+/// * `timeout` - How long the server may work on the query, and the client waits for a response.
+#[derive(Debug, Clone)]
+pub struct OverpassConfig {
+    pub endpoint: String,
+    pub max_area_deg2: f64,
+    pub timeout: Duration,
+}
+
+impl Default for OverpassConfig {
+    fn default() -> Self {
+        OverpassConfig {
+            endpoint: DEFAULT_OVERPASS_ENDPOINT.to_string(),
+            max_area_deg2: 0.25,
+            timeout: Duration::from_secs(180),
+        }
+    }
+}
+
+impl OverpassConfig {
+    /// The default configuration, with the endpoint taken from `OVERPASS_ENDPOINT` if it is set.
+    pub fn from_env() -> Self {
+        let mut config = OverpassConfig::default();
+        if let Ok(endpoint) = env::var("OVERPASS_ENDPOINT") {
+            config.endpoint = endpoint;
+        }
+        config
+    }
+}
+
+/// Everything read from a downloaded extract.
+#[derive(Debug, Clone)]
+pub struct OsmData {
+    pub nodes: ReadOutcome<Node>,
+    pub ways: ReadOutcome<Way>,
+    pub relations: ReadOutcome<Relation>,
+}
+
+/// An error while downloading from Overpass.
+#[derive(Debug)]
+pub enum OverpassError {
+    /// The box is larger than `OverpassConfig::max_area_deg2`.
+    AreaTooLarge { area_deg2: f64, max_area_deg2: f64 },
+    /// The server is busy (HTTP 429 or 504), the same request may succeed later.
+    RetryLater { status: u16 },
+    /// Any other unsuccessful HTTP status.
+    Http { status: u16, message: String },
+    /// The request could not be sent or the response could not be received.
+    Transport(String),
+    /// The response is not well-formed OSM XML or JSON.
+    Parse(String),
+}
+
+impl fmt::Display for OverpassError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverpassError::AreaTooLarge { area_deg2, max_area_deg2 } => {
+                write!(f, "The area of {:.4} square degrees is larger than the allowed {:.4}, zoom in first", area_deg2, max_area_deg2)
+            }
+            OverpassError::RetryLater { status } => {
+                write!(f, "The Overpass server is busy (HTTP {}), please try again in a few minutes", status)
+            }
+            OverpassError::Http { status, message } => write!(f, "Overpass returned HTTP {}: {}", status, message),
+            OverpassError::Transport(e) => write!(f, "Could not reach the Overpass server: {}", e),
+            OverpassError::Parse(e) => write!(f, "Could not read the Overpass response: {}", e),
+        }
+    }
+}
+
+impl StdError for OverpassError {}
+
+/// A response, reduced to what the download needs.
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Sends form encoded POST requests. Implemented by `TcpHttpClient`, and by canned
+/// responses where no network is wanted.
+pub trait HttpClient {
+    fn post_form(&self, url: &str, user_agent: &str, form: &str, timeout: Duration) -> Result<HttpResponse, Box<dyn StdError>>;
+}
+
+/// How long connecting to a server may take, at most the timeout of the request.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many redirects are followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+/// A minimal HTTP/1.1 client over a blocking socket, with TLS for `https` URLs.
+///
+/// Connecting and the whole exchange are bounded by the timeout of the request, and up to
+/// `MAX_REDIRECTS` redirects are followed, never from `https` to `http`. A redirect by 301,
+/// 302 or 303 is followed with a GET without the form, as other clients do, 307 and 308 send
+/// the form again. The body is read as long as `Content-Length` or the chunks say, so a
+/// server keeping the connection open does not hold the response up. Looking up the host is
+/// not bounded by the timeout.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpHttpClient;
+
+impl HttpClient for TcpHttpClient {
+    fn post_form(&self, url: &str, user_agent: &str, form: &str, timeout: Duration) -> Result<HttpResponse, Box<dyn StdError>> {
+        let deadline = Instant::now() + timeout;
+        let mut url = Url::parse(url)?;
+        let mut form = Some(form);
+
+        for _ in 0..=MAX_REDIRECTS {
+            let (response, location) = send_request(&url, user_agent, form, deadline)?;
+            let Some(location) = location.filter(|_| matches!(response.status, 301 | 302 | 303 | 307 | 308)) else {
+                return Ok(response);
+            };
+
+            let redirect = url.join(&location)?;
+            if url.scheme() == "https" && redirect.scheme() != "https" {
+                return Err(format!("refusing the redirect from {} to {}", url, redirect).into());
+            }
+            if !matches!(response.status, 307 | 308) {
+                form = None;
+            }
+            url = redirect;
+        }
+        Err(format!("more than {} redirects", MAX_REDIRECTS).into())
+    }
+}
+
+/// Sends one request and reads its response, a POST of `form` or a GET without one.
+///
+/// ## Returns
+/// * The response, and where it redirects to if it has a `Location`.
+fn send_request(url: &Url, user_agent: &str, form: Option<&str>, deadline: Instant) -> Result<(HttpResponse, Option<String>), Box<dyn StdError>> {
+    let host = url.host_str().ok_or("the URL has no host")?;
+    let port = url.port_or_known_default().ok_or("the URL has no port")?;
+    let target = &url[Position::BeforePath..Position::AfterQuery];
+    // The port is only named if it is not the default one of the scheme
+    let host_header = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+
+    let request = match form {
+        Some(form) => format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            target, host_header, user_agent, form.len(), form,
+        ),
+        None => format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nConnection: close\r\n\r\n", target, host_header, user_agent),
+    };
+
+    let stream = connect(host, port, deadline)?;
+    match url.scheme() {
+        "https" => {
+            let mut stream = TlsConnector::new()?.connect(host, stream)?;
+            stream.write_all(request.as_bytes())?;
+            read_response(&mut stream, deadline)
+        }
+        "http" => {
+            let mut stream = stream;
+            stream.write_all(request.as_bytes())?;
+            read_response(&mut stream, deadline)
+        }
+        scheme => Err(format!("unsupported scheme {}", scheme).into()),
+    }
+}
+
+/// Connects to the first address of a host that answers before the deadline.
+fn connect(host: &str, port: u16, deadline: Instant) -> Result<TcpStream, Box<dyn StdError>> {
+    let mut last_error = None;
+    for address in (host, port).to_socket_addrs()? {
+        let remaining = remaining(deadline)?;
+        match TcpStream::connect_timeout(&address, remaining.min(CONNECT_TIMEOUT)) {
+            Ok(stream) => {
+                // A single read or write may take what is left, `ResponseReader` checks the rest
+                stream.set_read_timeout(Some(remaining))?;
+                stream.set_write_timeout(Some(remaining))?;
+                return Ok(stream);
+            }
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(match last_error {
+        Some(error) => error.into(),
+        None => format!("{} has no address", host).into(),
+    })
+}
+
+/// The time left until the deadline, or an error once it has passed.
+fn remaining(deadline: Instant) -> Result<Duration, Box<dyn StdError>> {
+    deadline.checked_duration_since(Instant::now())
+        .filter(|remaining| !remaining.is_zero())
+        .ok_or_else(|| "the request timed out".into())
+}
+
+/// Reads a response from a connection into a buffer, as far as it is needed.
+struct ResponseReader<'a, R> {
+    stream: &'a mut R,
+    buffer: Vec<u8>,
+    deadline: Instant,
+}
+
+impl<R: Read> ResponseReader<'_, R> {
+    /// Reads more of the response into the buffer.
+    ///
+    /// ## Returns
+    /// * False if the server closed the connection.
+    fn read_more(&mut self) -> Result<bool, Box<dyn StdError>> {
+        remaining(self.deadline)?;
+        let mut chunk = [0; 64 * 1024];
+        let read = self.stream.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read > 0)
+    }
+
+    /// Reads until the buffer holds at least `len` bytes.
+    fn fill(&mut self, len: usize) -> Result<(), Box<dyn StdError>> {
+        while self.buffer.len() < len {
+            if !self.read_more()? {
+                return Err("the response was cut off".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads until `pattern` follows `start`, returning where it starts.
+    fn find(&mut self, start: usize, pattern: &[u8]) -> Result<usize, Box<dyn StdError>> {
+        let mut searched = start;
+        loop {
+            if let Some(position) = self.buffer[searched..].windows(pattern.len()).position(|window| window == pattern) {
+                return Ok(searched + position);
+            }
+            searched = self.buffer.len().saturating_sub(pattern.len() - 1).max(start);
+            if !self.read_more()? {
+                return Err("the response was cut off".into());
+            }
+        }
+    }
+}
+
+/// Reads one HTTP/1.1 response, skipping informational ones, undoing chunked transfer
+/// encoding, and reading to the end of the connection only if the body has no length.
+///
+/// ## Returns
+/// * The response, and where it redirects to if it has a `Location`.
+fn read_response(stream: &mut impl Read, deadline: Instant) -> Result<(HttpResponse, Option<String>), Box<dyn StdError>> {
+    let mut reader = ResponseReader { stream, buffer: Vec::new(), deadline };
+
+    let (status, headers) = loop {
+        let header_end = reader.find(0, b"\r\n\r\n")?;
+        let head = std::str::from_utf8(&reader.buffer[..header_end])?;
+        let mut lines = head.split("\r\n");
+        let status: u16 = lines.next()
+            .and_then(|status_line| status_line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .ok_or("the response has no status")?;
+        let headers: Vec<(String, String)> = lines.filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        reader.buffer.drain(..header_end + 4);
+
+        // e.g. `100 Continue`, followed by the actual response
+        if !(100..200).contains(&status) {
+            break (status, headers);
+        }
+    };
+    let header = |name: &str| headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str());
+
+    let chunked = header("transfer-encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    let body = if matches!(status, 204 | 304) {
+        Vec::new()
+    } else if chunked {
+        read_chunks(&mut reader)?
+    } else if let Some(length) = header("content-length") {
+        let length: usize = length.parse().map_err(|_| format!("invalid Content-Length {}", length))?;
+        reader.fill(length)?;
+        reader.buffer.truncate(length);
+        reader.buffer
+    } else {
+        // Without a length, the body ends with the connection
+        while reader.read_more()? {}
+        reader.buffer
+    };
+
+    let location = header("location").map(str::to_string);
+    Ok((HttpResponse { status, body }, location))
+}
+
+/// Reads a chunked body up to its last chunk, ignoring chunk extensions and trailers.
+fn read_chunks(reader: &mut ResponseReader<'_, impl Read>) -> Result<Vec<u8>, Box<dyn StdError>> {
+    let mut decoded = Vec::new();
+    let mut position = 0;
+
+    loop {
+        let line_end = reader.find(position, b"\r\n")?;
+        // The size may be followed by chunk extensions after a semicolon
+        let size_text = std::str::from_utf8(&reader.buffer[position..line_end])?;
+        let size = usize::from_str_radix(size_text.split(';').next().unwrap_or("").trim(), 16)?;
+        position = line_end + 2;
+
+        if size == 0 {
+            return Ok(decoded);
+        }
+        reader.fill(position + size + 2)?;
+        decoded.extend_from_slice(&reader.buffer[position..position + size]);
+        position += size + 2;
+    }
+}
+
+/// Builds the Overpass QL query for every element within a box. The nodes of ways crossing
+/// the edge of the box are included too, so those ways are complete.
+pub fn build_bbox_query(bbox: &BBox, timeout: Duration) -> String {
+    let bbox = format!("{},{},{},{}", bbox.min_lat, bbox.min_lon, bbox.max_lat, bbox.max_lon);
+
+    format!(
+        "[out:xml][timeout:{}];\n(\n  node({bbox});\n  way({bbox});\n  relation({bbox});\n);\n(._;>;);\nout meta;",
+        timeout.as_secs(),
+        bbox = bbox,
+    )
+}
+
+/// Downloads every node, way and relation within a box from Overpass.
+///
+/// ## Arguments
+/// * `client` - Sends the request.
+/// * `config` - The endpoint and the limits of the download.
+/// * `bbox` - The box to download.
+///
+/// ## Returns
+/// * The elements read from the response, or an error if the box is too large, the server
+///   refused the request or the response could not be read.
+pub fn download_bbox(client: &impl HttpClient, config: &OverpassConfig, bbox: &BBox) -> Result<OsmData, OverpassError> {
+    let area_deg2 = (bbox.max_lat - bbox.min_lat) * (bbox.max_lon - bbox.min_lon);
+    if area_deg2 > config.max_area_deg2 {
+        return Err(OverpassError::AreaTooLarge { area_deg2, max_area_deg2: config.max_area_deg2 });
+    }
+
+    let query = build_bbox_query(bbox, config.timeout);
+    let form: String = form_urlencoded::Serializer::new(String::new()).append_pair("data", &query).finish();
+
+    let response = client.post_form(&config.endpoint, OVERPASS_USER_AGENT, &form, config.timeout)
+        .map_err(|error| OverpassError::Transport(error.to_string()))?;
+
+    match response.status {
+        200 => (),
+        429 | 504 => return Err(OverpassError::RetryLater { status: response.status }),
+        status => {
+            let message = String::from_utf8_lossy(&response.body).chars().take(200).collect();
+            return Err(OverpassError::Http { status, message });
+        }
+    }
+
+    // Servers and proxies answering with `[out:json]` output are understood too
+    if is_osm_json(&response.body) {
+        return read_osm_json(response.body.as_slice()).map_err(|error| OverpassError::Parse(error.to_string()));
+    }
+
+    Ok(OsmData {
+        nodes: read_nodes_from_bytes(&response.body).map_err(|error| OverpassError::Parse(error.to_string()))?,
+        ways: read_ways_from_bytes(&response.body).map_err(|error| OverpassError::Parse(error.to_string()))?,
+        relations: read_relations_from_bytes(&response.body).map_err(|error| OverpassError::Parse(error.to_string()))?,
+    })
+}
+
+/// Parses a `minLon,minLat,maxLon,maxLat` box, the order used by most OSM tools.
+///
+/// ## Returns
+/// * The box, or a description of what is wrong. A box across the antimeridian is refused,
+///   as neither Overpass nor the queries of the database split it.
+pub fn parse_bbox_argument(argument: &str) -> Result<BBox, String> {
+    let bbox: BBox = argument.parse().map_err(|error: BBoxError| error.to_string())?;
+    if bbox.max_lon > 180.0 {
+        return Err("a box across the antimeridian is not supported, split it in two".to_string());
+    }
+    Ok(bbox)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    const EXTRACT: &str = r#"<osm version="0.6"><node id="1" lat="55.0" lon="11.0"/><node id="2" lat="55.1" lon="11.1"/><way id="10"><nd ref="1"/><nd ref="2"/><tag k="highway" v="primary"/></way></osm>"#;
+
+    /// Answers every request with the same response, counting them.
+    struct CannedClient {
+        response: HttpResponse,
+        requests: Cell<usize>,
+    }
+
+    impl HttpClient for CannedClient {
+        fn post_form(&self, _url: &str, user_agent: &str, form: &str, _timeout: Duration) -> Result<HttpResponse, Box<dyn StdError>> {
+            assert_eq!(user_agent, OVERPASS_USER_AGENT);
+            assert!(form.starts_with("data="));
+            self.requests.set(self.requests.get() + 1);
+            Ok(self.response.clone())
+        }
+    }
+
+    fn canned(status: u16, body: &str) -> CannedClient {
+        CannedClient { response: HttpResponse { status, body: body.as_bytes().to_vec() }, requests: Cell::new(0) }
+    }
+
+    const SMALL_BBOX: BBox = BBox { min_lat: 55.0, max_lat: 55.2, min_lon: 11.0, max_lon: 11.2 };
+
+    #[test]
+    fn a_download_is_read_from_the_response() {
+        let data = download_bbox(&canned(200, EXTRACT), &OverpassConfig::default(), &SMALL_BBOX).unwrap();
+        assert_eq!((data.nodes.items.len(), data.ways.items.len(), data.relations.items.len()), (2, 1, 0));
+
+        assert!(matches!(download_bbox(&canned(429, ""), &OverpassConfig::default(), &SMALL_BBOX), Err(OverpassError::RetryLater { status: 429 })));
+        assert!(matches!(download_bbox(&canned(400, "bad query"), &OverpassConfig::default(), &SMALL_BBOX), Err(OverpassError::Http { status: 400, .. })));
+
+        // A box too large is refused before anything is sent
+        let client = canned(200, EXTRACT);
+        let large = BBox { min_lat: 50.0, max_lat: 56.0, min_lon: 5.0, max_lon: 15.0 };
+        assert!(matches!(download_bbox(&client, &OverpassConfig::default(), &large), Err(OverpassError::AreaTooLarge { .. })));
+        assert_eq!(client.requests.get(), 0);
+    }
+
+    #[test]
+    fn a_json_response_is_read_as_osm_json() {
+        let body = r#"{"version": 0.6, "elements": [
+            {"type": "node", "id": 1, "lat": 55.0, "lon": 11.0},
+            {"type": "node", "id": 2, "lat": 55.1, "lon": 11.1},
+            {"type": "way", "id": 10, "nodes": [2, 1], "tags": {"highway": "primary"}}
+        ]}"#;
+        let data = download_bbox(&canned(200, body), &OverpassConfig::default(), &SMALL_BBOX).unwrap();
+        assert_eq!((data.nodes.items.len(), data.ways.items.len(), data.relations.items.len()), (2, 1, 0));
+        assert_eq!(data.ways.items[0].node_refs, [2, 1]);
+
+        let error = download_bbox(&canned(200, "{\"elements\": 3}"), &OverpassConfig::default(), &SMALL_BBOX).unwrap_err();
+        assert!(matches!(error, OverpassError::Parse(_)), "{}", error);
+    }
+
+    /// The thread of `serve`, returning the requests it received along with the connections,
+    /// which are left open like those of a server keeping them alive.
+    type Server = JoinHandle<(Vec<String>, Vec<TcpStream>)>;
+
+    /// Serves one connection per response on a local port.
+    ///
+    /// ## Returns
+    /// * The URL of the server, and its thread.
+    fn serve(responses: Vec<String>) -> (String, Server) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            let mut connections = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut chunk = [0; 1024];
+                // The head, and as much of the body as it says there is
+                let complete = |request: &[u8]| {
+                    let text = String::from_utf8_lossy(request);
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else { return false };
+                    let length = head.lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .map_or(0, |length| length.parse().unwrap());
+                    body.len() >= length
+                };
+                while !complete(&request) {
+                    let read = stream.read(&mut chunk).unwrap();
+                    request.extend_from_slice(&chunk[..read]);
+                }
+                requests.push(String::from_utf8(request).unwrap());
+                stream.write_all(response.as_bytes()).unwrap();
+                connections.push(stream);
+            }
+            (requests, connections)
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn a_response_is_read_as_far_as_its_length_without_waiting_for_the_connection_to_close() {
+        let (url, server) = serve(vec![
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: keep-alive\r\n\r\nhello".to_string(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\n\r\n".to_string(),
+        ]);
+
+        let timeout = Duration::from_secs(5);
+        let started = Instant::now();
+        let response = TcpHttpClient.post_form(&format!("{}/api/interpreter", url), "test", "data=x", timeout).unwrap();
+        assert_eq!((response.status, response.body.as_slice()), (200, &b"hello"[..]));
+        let response = TcpHttpClient.post_form(&url, "test", "data=x", timeout).unwrap();
+        assert_eq!((response.status, response.body.as_slice()), (200, &b"hello world"[..]));
+        assert!(started.elapsed() < timeout);
+
+        let (requests, _connections) = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /api/interpreter HTTP/1.1\r\n"), "{}", requests[0]);
+        assert!(requests[0].contains("Connection: close\r\n") && requests[0].ends_with("\r\n\r\ndata=x"));
+    }
+
+    #[test]
+    fn redirects_are_followed_with_the_form_only_where_it_is_kept() {
+        let (url, server) = serve(vec![
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: /moved\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "HTTP/1.1 303 See Other\r\nLocation: /result?id=1\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_string(),
+        ]);
+
+        let response = TcpHttpClient.post_form(&url, "test", "data=x", Duration::from_secs(5)).unwrap();
+        assert_eq!((response.status, response.body.as_slice()), (200, &b"ok"[..]));
+
+        let (requests, _connections) = server.join().unwrap();
+        let request_lines: Vec<&str> = requests.iter().map(|request| request.lines().next().unwrap()).collect();
+        assert_eq!(request_lines, ["POST / HTTP/1.1", "POST /moved HTTP/1.1", "GET /result?id=1 HTTP/1.1"]);
+        assert!(requests[1].ends_with("data=x") && requests[2].ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn a_server_that_never_answers_times_out() {
+        let (url, server) = serve(vec![String::new()]);
+
+        let started = Instant::now();
+        let error = TcpHttpClient.post_form(&url, "test", "data=x", Duration::from_millis(300)).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5), "{}", error);
+        server.join().unwrap();
+    }
+}
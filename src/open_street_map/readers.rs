@@ -1,108 +1,270 @@
 use quick_xml::Reader;
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
+use std::fmt::Display;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader};
 use std::error::Error;
+use std::str::FromStr;
 
 use crate::{
     osm_entities::{Member, Node, Relation, Tag, Way},
     utils::MapsType
 };
 
+use super::OsmData;
+
+/// Only the first warnings of a file are kept, the rest are only counted.
+pub const MAX_WARNINGS: usize = 100;
+
+/// The result of reading one kind of element from an OSM file.
+///
+/// # Fields
+/// * `items` - The elements that were read successfully.
+/// * `skipped` - The number of elements left out because a required attribute was missing or malformed.
+/// * `warnings` - Descriptions of the first `MAX_WARNINGS` problems, including those that did not skip an element.
+#[derive(Debug, Clone)]
+pub struct ReadOutcome<T> {
+    pub items: Vec<T>,
+    pub skipped: usize,
+    pub warnings: Vec<String>,
+}
+
+impl<T> ReadOutcome<T> {
+    pub(super) fn new() -> Self {
+        ReadOutcome {
+            items: Vec::new(),
+            skipped: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, position: u64, message: String) {
+        if self.warnings.len() < MAX_WARNINGS {
+            self.warnings.push(format!("byte {}: {}", position, message));
+        }
+    }
+
+    /// Keeps a parsed element, or counts it as skipped if it could not be parsed.
+    ///
+    /// ## Returns
+    /// * True if the element was kept.
+    fn push_or_skip(&mut self, position: u64, element: &str, parsed: Result<T, String>) -> bool {
+        match parsed {
+            Ok(item) => {
+                self.items.push(item);
+                true
+            }
+            Err(error) => {
+                self.skipped += 1;
+                self.warn(position, format!("skipped {}: {}", element, error));
+                false
+            }
+        }
+    }
+}
+
+/// The attributes of a single element, kept as raw bytes until they are asked for,
+/// so a malformed attribute only affects the element it belongs to.
+pub(super) struct ElementAttributes {
+    values: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ElementAttributes {
+    pub(super) fn read(e: &BytesStart) -> Result<Self, String> {
+        let mut values = Vec::new();
+        for attr in e.attributes() {
+            let attr = attr.map_err(|error| format!("malformed attribute: {}", error))?;
+            values.push((attr.key.as_ref().to_vec(), attr.value.to_vec()));
+        }
+        Ok(ElementAttributes { values })
+    }
+
+    /// Returns the text of an attribute, or `None` if the element does not have it.
+    fn text(&self, key: &str) -> Result<Option<String>, String> {
+        match self.values.iter().find(|(k, _)| k == key.as_bytes()) {
+            Some((_, value)) => String::from_utf8(value.clone())
+                .map(Some)
+                .map_err(|error| format!("invalid `{}`: {}", key, error)),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses an attribute the element cannot do without.
+    pub(super) fn required<T: FromStr>(&self, key: &str) -> Result<T, String>
+    where
+        T::Err: Display,
+    {
+        let text = self.text(key)?.ok_or_else(|| format!("missing `{}`", key))?;
+        text.parse().map_err(|error| format!("invalid `{}` \"{}\": {}", key, text, error))
+    }
+
+    /// Parses an attribute that falls back to its default when missing or malformed.
+    /// Malformed values are reported in `warnings`.
+    pub(super) fn optional<T: FromStr + Default>(&self, key: &str, warnings: &mut Vec<String>) -> T
+    where
+        T::Err: Display,
+    {
+        match self.text(key) {
+            Ok(None) => T::default(),
+            Ok(Some(text)) => text.parse().unwrap_or_else(|error| {
+                warnings.push(format!("invalid `{}` \"{}\": {}", key, text, error));
+                T::default()
+            }),
+            Err(error) => {
+                warnings.push(error);
+                T::default()
+            }
+        }
+    }
+
+    /// Parses the `lat` and `lon` of a node, which must be finite and within the range of
+    /// latitudes and longitudes, as `NaN` or `inf` parse as numbers too.
+    fn coordinates(&self) -> Result<(f64, f64), String> {
+        let lat: f64 = self.required("lat")?;
+        let lon: f64 = self.required("lon")?;
+        if !(-90.0..=90.0).contains(&lat) {
+            return Err(format!("`lat` {} is not between -90 and 90", lat));
+        }
+        if !(-180.0..=180.0).contains(&lon) {
+            return Err(format!("`lon` {} is not between -180 and 180", lon));
+        }
+        Ok((lat, lon))
+    }
+}
+
+pub(super) fn parse_node(e: &BytesStart, warnings: &mut Vec<String>) -> Result<Node, String> {
+    let attributes = ElementAttributes::read(e)?;
+    let id = attributes.required("id")?;
+    let (lat, lon) = attributes.coordinates()?;
+
+    Ok(Node {
+        id,
+        lat,
+        lon,
+        version: attributes.optional("version", warnings),
+        timestamp: attributes.optional("timestamp", warnings),
+        changeset: attributes.optional("changeset", warnings),
+        uid: attributes.optional("uid", warnings),
+        user: attributes.optional("user", warnings),
+        tags: Vec::new(),
+    })
+}
+
+pub(super) fn parse_way(e: &BytesStart, warnings: &mut Vec<String>) -> Result<Way, String> {
+    let attributes = ElementAttributes::read(e)?;
+
+    Ok(Way {
+        id: attributes.required("id")?,
+        version: attributes.optional("version", warnings),
+        timestamp: attributes.optional("timestamp", warnings),
+        changeset: attributes.optional("changeset", warnings),
+        uid: attributes.optional("uid", warnings),
+        user: attributes.optional("user", warnings),
+        node_refs: Vec::new(),
+        tags: Vec::new(),
+    })
+}
+
+pub(super) fn parse_relation(e: &BytesStart, warnings: &mut Vec<String>) -> Result<Relation, String> {
+    let attributes = ElementAttributes::read(e)?;
+
+    Ok(Relation {
+        id: attributes.required("id")?,
+        version: attributes.optional("version", warnings),
+        timestamp: attributes.optional("timestamp", warnings),
+        changeset: attributes.optional("changeset", warnings),
+        uid: attributes.optional("uid", warnings),
+        user: attributes.optional("user", warnings),
+        tags: Vec::new(),
+        members: Vec::new(),
+    })
+}
+
+pub(super) fn parse_tag(e: &BytesStart, warnings: &mut Vec<String>) -> Result<Tag, String> {
+    let attributes = ElementAttributes::read(e)?;
+
+    Ok(Tag {
+        key: attributes.required("k")?,
+        value: attributes.optional("v", warnings),
+    })
+}
+
+pub(super) fn parse_node_ref(e: &BytesStart) -> Result<i64, String> {
+    ElementAttributes::read(e)?.required("ref")
+}
+
+pub(super) fn parse_member(e: &BytesStart, warnings: &mut Vec<String>) -> Result<Member, String> {
+    let attributes = ElementAttributes::read(e)?;
+
+    // Matched here instead of parsed into a `MapsType`, which keeps unknown types for good,
+    // so a file full of made up types cannot use up the memory
+    let maps_type = match attributes.required::<String>("type")?.as_str() {
+        "node" => MapsType::Node,
+        "way" => MapsType::Way,
+        "relation" => MapsType::Relation,
+        other => return Err(format!("unknown member type `{}`", other)),
+    };
+    let ref_id = attributes.required("ref")?;
+    let role = attributes.optional("role", warnings);
+
+    Ok(Member::new(ref_id, maps_type, role))
+}
+
 /// Reads nodes from an OpenStreetMap (OSM) XML file.
 ///
+/// A node with a missing or malformed `id`, `lat` or `lon` is skipped together with its tags,
+/// the rest of the file is still read. A `lat` or `lon` that is not finite or out of range is
+/// malformed too. Unknown attributes and elements are ignored.
+///
 /// ## Arguments
 /// * `path` - The path to the OSM XML file.
 ///
 /// ## Returns
-/// * A result containing a vector of `Node` if successful, or an error if the reading fails.
-pub fn read_nodes_from_file(path: &str) -> Result<Vec<Node>, Box<dyn Error>>{
+/// * A result containing the nodes and the number of skipped nodes if successful, or an error
+///   if the file cannot be opened or is not well-formed XML.
+pub fn read_nodes_from_file(path: &str) -> Result<ReadOutcome<Node>, Box<dyn Error>>{
     // Open the XML file
     let file = File::open(path)?;
-    let mut reader = Reader::from_reader(BufReader::new(file));
+    read_nodes(BufReader::new(file))
+}
+
+/// Reads nodes from OSM XML held in memory, e.g. a downloaded response.
+pub fn read_nodes_from_bytes(bytes: &[u8]) -> Result<ReadOutcome<Node>, Box<dyn Error>> {
+    read_nodes(bytes)
+}
 
-    let mut nodes: Vec<Node> = Vec::new();
+fn read_nodes<R: BufRead>(source: R) -> Result<ReadOutcome<Node>, Box<dyn Error>> {
+    let mut reader = Reader::from_reader(source);
+
+    let mut outcome: ReadOutcome<Node> = ReadOutcome::new();
+    // Whether nested elements belong to the last node read
+    let mut in_node = false;
     let mut buf = Vec::new();
 
     loop {
+        let position = reader.buffer_position();
+        let mut warnings = Vec::new();
+
         match reader.read_event_into(&mut buf) {
             // Handle the start of a <node> element with nested tags (non-self-closing)
             Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"node") => {
-                let mut node = Node {
-                    id: 0,
-                    lat: 0.0,
-                    lon: 0.0,
-                    version: 0,
-                    timestamp: String::new(),
-                    changeset: 0,
-                    uid: 0,
-                    user: String::new(),
-                    tags: Vec::new(),
-                };
-
-                // Parse the attributes of the <node> element
-                for attr in e.attributes() {
-                    match attr? {
-                        a if a.key == quick_xml::name::QName(b"id") => node.id = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"lat") => node.lat = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"lon") => node.lon = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"version") => node.version = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"timestamp") => node.timestamp = String::from_utf8(a.value.to_vec())?,
-                        a if a.key == quick_xml::name::QName(b"changeset") => node.changeset = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"uid") => node.uid = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"user") => node.user = String::from_utf8(a.value.to_vec())?,
-                        _ => (),
-                    }
-                }
-                nodes.push(node);
-            },
+                in_node = outcome.push_or_skip(position, "node", parse_node(e, &mut warnings));
+            }
             // Handle self-closing <node> elements
             Ok(Event::Empty(ref e)) if e.name() == quick_xml::name::QName(b"node") => {
-                let mut node = Node {
-                    id: 0,
-                    lat: 0.0,
-                    lon: 0.0,
-                    version: 0,
-                    timestamp: String::new(),
-                    changeset: 0,
-                    uid: 0,
-                    user: String::new(),
-                    tags: Vec::new(),
-                };
-
-                // Parse the attributes of the self-closing <node> element
-                for attr in e.attributes() {
-                    match attr? {
-                        a if a.key == quick_xml::name::QName(b"id") => node.id = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"lat") => node.lat = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"lon") => node.lon = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"version") => node.version = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"timestamp") => node.timestamp = String::from_utf8(a.value.to_vec())?,
-                        a if a.key == quick_xml::name::QName(b"changeset") => node.changeset = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"uid") => node.uid = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"user") => node.user = String::from_utf8(a.value.to_vec())?,
-                        _ => (),
-                    }
-                }
-                nodes.push(node);
+                outcome.push_or_skip(position, "node", parse_node(e, &mut warnings));
+                in_node = false;
             }
+            Ok(Event::End(ref e)) if e.name() == quick_xml::name::QName(b"node") => in_node = false,
             // Handle <tag> elements nested within <node> elements
-            Ok(Event::Empty(ref e)) if e.name() == quick_xml::name::QName(b"tag") => {
-                if let Some(last_node) = nodes.last_mut() {
-                    let mut tag = Tag {
-                        key: String::new(),
-                        value: String::new(),
-                    };
-
-                    // Parse the attributes of the <tag> element
-                    for attr in e.attributes() {
-                        match attr? {
-                            a if a.key == quick_xml::name::QName(b"k") => tag.key = String::from_utf8(a.value.to_vec())?,
-                            a if a.key == quick_xml::name::QName(b"v") => tag.value = String::from_utf8(a.value.to_vec())?,
-                            _ => (),
+            Ok(Event::Empty(ref e)) if in_node && e.name() == quick_xml::name::QName(b"tag") => {
+                match parse_tag(e, &mut warnings) {
+                    Ok(tag) => {
+                        if let Some(last_node) = outcome.items.last_mut() {
+                            last_node.tags.push(tag);
                         }
                     }
-                    last_node.tags.push(tag);
+                    Err(error) => warnings.push(format!("skipped tag: {}", error)),
                 }
             }
             // End of the XML document
@@ -111,92 +273,79 @@ pub fn read_nodes_from_file(path: &str) -> Result<Vec<Node>, Box<dyn Error>>{
             Err(e) => return Err(Box::new(e)),
             _ => (),
         }
+
+        for warning in warnings {
+            outcome.warn(position, warning);
+        }
         // Clear buffer for the next read
         buf.clear();
     }
 
-    Ok(nodes)
+    Ok(outcome)
 }
 
 /// Reads ways from an OpenStreetMap (OSM) XML file.
 ///
+/// A way with a missing or malformed `id` is skipped together with its node references and tags,
+/// a malformed node reference only drops that reference. Unknown attributes and elements are ignored.
+///
 /// ## Arguments
 /// * `path` - The path to the OSM XML file.
 ///
 /// ## Returns
-/// * A result containing a vector of `Way` if successful, or an error if the reading fails.
-pub fn read_ways_from_file(path: &str) -> Result<Vec<Way>, Box<dyn Error>>{
+/// * A result containing the ways and the number of skipped ways if successful, or an error
+///   if the file cannot be opened or is not well-formed XML.
+pub fn read_ways_from_file(path: &str) -> Result<ReadOutcome<Way>, Box<dyn Error>>{
     // Open the XML file
     let file = File::open(path)?;
-    let mut reader = Reader::from_reader(BufReader::new(file));
+    read_ways(BufReader::new(file))
+}
+
+/// Reads ways from OSM XML held in memory, e.g. a downloaded response.
+pub fn read_ways_from_bytes(bytes: &[u8]) -> Result<ReadOutcome<Way>, Box<dyn Error>> {
+    read_ways(bytes)
+}
+
+fn read_ways<R: BufRead>(source: R) -> Result<ReadOutcome<Way>, Box<dyn Error>> {
+    let mut reader = Reader::from_reader(source);
 
-    let mut ways: Vec<Way> = Vec::new();
+    let mut outcome: ReadOutcome<Way> = ReadOutcome::new();
+    // Whether nested elements belong to the last way read
+    let mut in_way = false;
     let mut buf = Vec::new();
 
     loop {
+        let position = reader.buffer_position();
+        let mut warnings = Vec::new();
+
         match reader.read_event_into(&mut buf) {
             // Handle the start of a <way> element with nested tags (non-self-closing)
             Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"way") => {
-                let mut way = Way {
-                    id: 0,
-                    version: 0,
-                    timestamp: String::new(),
-                    changeset: 0,
-                    uid: 0,
-                    user: String::new(),
-                    node_refs: Vec::new(),
-                    tags: Vec::new(),
-                };
-
-                // Parse the attributes of the <way> element
-                for attr in e.attributes() {
-                    match attr? {
-                        a if a.key == quick_xml::name::QName(b"id") => way.id = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"version") => way.version = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"timestamp") => way.timestamp = String::from_utf8(a.value.to_vec())?,
-                        a if a.key == quick_xml::name::QName(b"changeset") => way.changeset = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"uid") => way.uid = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"user") => way.user = String::from_utf8(a.value.to_vec())?,
-                        _ => (),
-                    }
-                }
-                ways.push(way);
-            },
+                in_way = outcome.push_or_skip(position, "way", parse_way(e, &mut warnings));
+            }
+            Ok(Event::End(ref e)) if e.name() == quick_xml::name::QName(b"way") => in_way = false,
 
             // Handle <nd> elements nested within <way> elements
-            Ok(Event::Empty(ref e)) if e.name() == quick_xml::name::QName(b"nd") => {
-                if let Some(last_way) = ways.last_mut() {
-                    // Parse the attributes of the <tag> element
-                    let mut node_ref = -1;
-                    for attr in e.attributes() {
-                        match attr? {
-                            a if a.key == quick_xml::name::QName(b"ref") => node_ref = String::from_utf8(a.value.to_vec())?.parse()?,
-                            _ => (),
+            Ok(Event::Empty(ref e)) if in_way && e.name() == quick_xml::name::QName(b"nd") => {
+                match parse_node_ref(e) {
+                    Ok(node_ref) => {
+                        if let Some(last_way) = outcome.items.last_mut() {
+                            last_way.node_refs.push(node_ref);
                         }
                     }
-                    if node_ref != -1 {
-                        last_way.node_refs.push(node_ref);
-                    }
+                    Err(error) => warnings.push(format!("skipped node reference: {}", error)),
                 }
             }
 
-            // Handle <tag> elements nested within <node> elements
-            Ok(Event::Empty(ref e)) if e.name() == quick_xml::name::QName(b"tag") => {
-                if let Some(last_way) = ways.last_mut() {
-                    let mut tag = Tag {
-                        key: String::new(),
-                        value: String::new(),
-                    };
-
-                    // Parse the attributes of the <tag> element
-                    for attr in e.attributes() {
-                        match attr? {
-                            a if a.key == quick_xml::name::QName(b"k") => tag.key = String::from_utf8(a.value.to_vec())?,
-                            a if a.key == quick_xml::name::QName(b"v") => tag.value = String::from_utf8(a.value.to_vec())?,
-                            _ => (),
+            // Handle <tag> elements nested within <way> elements
+            Ok(Event::Empty(ref e)) if in_way && e.name() == quick_xml::name::QName(b"tag") => {
+                match parse_tag(e, &mut warnings) {
+                    Ok(tag) => {
+                        if let Some(last_way) = outcome.items.last_mut() {
+                            last_way.tags.push(tag);
                         }
                     }
-                    last_way.tags.push(tag);
+                    Err(error) => warnings.push(format!("skipped tag: {}", error)),
                 }
             }
             // End of the XML document
@@ -205,100 +354,78 @@ pub fn read_ways_from_file(path: &str) -> Result<Vec<Way>, Box<dyn Error>>{
             Err(e) => return Err(Box::new(e)),
             _ => (),
         }
+
+        for warning in warnings {
+            outcome.warn(position, warning);
+        }
         // Clear buffer for the next read
         buf.clear();
     }
 
-    Ok(ways)
+    Ok(outcome)
 }
 
 /// Reads relations and it's members from an OpenStreetMap (OSM) XML file.
 ///
+/// A relation with a missing or malformed `id` is skipped together with its members and tags,
+/// a member without a valid `type` or `ref` only drops that member. Unknown attributes and
+/// elements are ignored.
+///
 /// ## Arguments
 /// * `path` - The path to the OSM XML file.
 ///
 /// ## Returns
-/// * A result containing a vector of `Relation` if successful, or an error if the reading fails.
-pub fn read_relations_from_file(path: &str) -> Result<Vec<Relation>, Box<dyn Error>>{
+/// * A result containing the relations and the number of skipped relations if successful, or
+///   an error if the file cannot be opened or is not well-formed XML.
+pub fn read_relations_from_file(path: &str) -> Result<ReadOutcome<Relation>, Box<dyn Error>>{
     // Open the XML file
     let file = File::open(path)?;
-    let mut reader = Reader::from_reader(BufReader::new(file));
+    read_relations(BufReader::new(file))
+}
+
+/// Reads relations from OSM XML held in memory, e.g. a downloaded response.
+pub fn read_relations_from_bytes(bytes: &[u8]) -> Result<ReadOutcome<Relation>, Box<dyn Error>> {
+    read_relations(bytes)
+}
+
+fn read_relations<R: BufRead>(source: R) -> Result<ReadOutcome<Relation>, Box<dyn Error>> {
+    let mut reader = Reader::from_reader(source);
 
-    let mut relations: Vec<Relation> = Vec::new();
+    let mut outcome: ReadOutcome<Relation> = ReadOutcome::new();
+    // Whether nested elements belong to the last relation read
+    let mut in_relation = false;
     let mut buf = Vec::new();
 
     loop {
+        let position = reader.buffer_position();
+        let mut warnings = Vec::new();
+
         match reader.read_event_into(&mut buf) {
             // Handle the start of a <relation> element with nested tags (non-self-closing)
             Ok(Event::Start(ref e)) if e.name() == quick_xml::name::QName(b"relation") => {
-                let mut relation = Relation {
-                    id: 0,
-                    version: 0,
-                    timestamp: String::new(),
-                    changeset: 0,
-                    uid: 0,
-                    user: String::new(),
-                    tags: Vec::new(),
-                    members: Vec::new(),
-                };
-
-                // Parse the attributes of the <way> element
-                for attr in e.attributes() {
-                    match attr? {
-                        a if a.key == quick_xml::name::QName(b"id") => relation.id = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"version") => relation.version = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"timestamp") => relation.timestamp = String::from_utf8(a.value.to_vec())?,
-                        a if a.key == quick_xml::name::QName(b"changeset") => relation.changeset = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"uid") => relation.uid = String::from_utf8(a.value.to_vec())?.parse()?,
-                        a if a.key == quick_xml::name::QName(b"user") => relation.user = String::from_utf8(a.value.to_vec())?,
-                        _ => (),
-                    }
-                }
-                relations.push(relation);
-            },
+                in_relation = outcome.push_or_skip(position, "relation", parse_relation(e, &mut warnings));
+            }
+            Ok(Event::End(ref e)) if e.name() == quick_xml::name::QName(b"relation") => in_relation = false,
 
             // Handle <member> elements nested within <relation> elements
-            Ok(Event::Empty(ref e)) if e.name() == quick_xml::name::QName(b"member") => {
-                if let Some(last_relation) = relations.last_mut() {
-                    // Parse the attributes of the <member> element
-                    let mut ref_id = 0;
-                    let mut maps_type = MapsType::Other("Unknown");
-                    let mut role = String::new();
-
-                    for attr in e.attributes() {
-                        match attr? {
-                            a if a.key == quick_xml::name::QName(b"type") => maps_type = String::from_utf8(a.value.to_vec())?.parse()?,
-                            a if a.key == quick_xml::name::QName(b"ref") => ref_id = String::from_utf8(a.value.to_vec())?.parse()?,
-                            a if a.key == quick_xml::name::QName(b"role") => role = String::from_utf8(a.value.to_vec())?.to_string(),
-                            _ => (),
-                        }
-                    }
-
-                    if maps_type != MapsType::Other("Unknown") {
-                        // Create the member with the correct relation_id
-                        let member = Member::new(last_relation.id, ref_id, maps_type, role);
-                        last_relation.members.push(member);
+            Ok(Event::Empty(ref e)) if in_relation && e.name() == quick_xml::name::QName(b"member") => {
+                if let Some(last_relation) = outcome.items.last_mut() {
+                    match parse_member(e, &mut warnings) {
+                        Ok(member) => last_relation.members.push(member),
+                        Err(error) => warnings.push(format!("skipped member: {}", error)),
                     }
                 }
             }
 
-            // Handle <tag> elements nested within <node> elements
-            Ok(Event::Empty(ref e)) if e.name() == quick_xml::name::QName(b"tag") => {
-                if let Some(last_relation) = relations.last_mut() {
-                    let mut tag = Tag {
-                        key: String::new(),
-                        value: String::new(),
-                    };
-
-                    // Parse the attributes of the <tag> element
-                    for attr in e.attributes() {
-                        match attr? {
-                            a if a.key == quick_xml::name::QName(b"k") => tag.key = String::from_utf8(a.value.to_vec())?,
-                            a if a.key == quick_xml::name::QName(b"v") => tag.value = String::from_utf8(a.value.to_vec())?,
-                            _ => (),
+            // Handle <tag> elements nested within <relation> elements
+            Ok(Event::Empty(ref e)) if in_relation && e.name() == quick_xml::name::QName(b"tag") => {
+                match parse_tag(e, &mut warnings) {
+                    Ok(tag) => {
+                        if let Some(last_relation) = outcome.items.last_mut() {
+                            last_relation.tags.push(tag);
                         }
                     }
-                    last_relation.tags.push(tag);
+                    Err(error) => warnings.push(format!("skipped tag: {}", error)),
                 }
             }
             // End of the XML document
@@ -307,9 +434,174 @@ pub fn read_relations_from_file(path: &str) -> Result<Vec<Relation>, Box<dyn Err
             Err(e) => return Err(Box::new(e)),
             _ => (),
         }
+
+        for warning in warnings {
+            outcome.warn(position, warning);
+        }
         // Clear buffer for the next read
         buf.clear();
     }
 
-    Ok(relations)
+    Ok(outcome)
+}
+
+/// Which element the nested elements being read belong to, see `read_osm_xml`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OpenElement {
+    None,
+    Node,
+    Way,
+    Relation,
+}
+
+/// Reads the nodes, ways and relations of OSM XML in a single pass, for input that can only
+/// be read once, e.g. standard input. The elements are read like `read_nodes_from_file`,
+/// `read_ways_from_file` and `read_relations_from_file` read them.
+///
+/// ## Returns
+/// * The elements read, or an error if the input is not well-formed XML.
+pub fn read_osm_xml(source: impl BufRead) -> Result<OsmData, Box<dyn Error>> {
+    let mut reader = Reader::from_reader(source);
+
+    let mut data = OsmData { nodes: ReadOutcome::new(), ways: ReadOutcome::new(), relations: ReadOutcome::new() };
+    let mut open = OpenElement::None;
+    let mut buf = Vec::new();
+
+    loop {
+        let position = reader.buffer_position();
+        let mut warnings = Vec::new();
+
+        // The warnings go to the outcome of the element they are about
+        let mut warned = open;
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => match e.name().as_ref() {
+                b"node" => {
+                    warned = OpenElement::Node;
+                    let kept = data.nodes.push_or_skip(position, "node", parse_node(e, &mut warnings));
+                    open = if kept { OpenElement::Node } else { OpenElement::None };
+                }
+                b"way" => {
+                    warned = OpenElement::Way;
+                    let kept = data.ways.push_or_skip(position, "way", parse_way(e, &mut warnings));
+                    open = if kept { OpenElement::Way } else { OpenElement::None };
+                }
+                b"relation" => {
+                    warned = OpenElement::Relation;
+                    let kept = data.relations.push_or_skip(position, "relation", parse_relation(e, &mut warnings));
+                    open = if kept { OpenElement::Relation } else { OpenElement::None };
+                }
+                _ => (),
+            },
+            Ok(Event::End(ref e)) if matches!(e.name().as_ref(), b"node" | b"way" | b"relation") => open = OpenElement::None,
+            Ok(Event::Empty(ref e)) => match (e.name().as_ref(), open) {
+                // Only nodes are read when self-closing, a way or relation without children is ignored
+                (b"node", _) => {
+                    warned = OpenElement::Node;
+                    data.nodes.push_or_skip(position, "node", parse_node(e, &mut warnings));
+                    open = OpenElement::None;
+                }
+                (b"tag", OpenElement::Node | OpenElement::Way | OpenElement::Relation) => match parse_tag(e, &mut warnings) {
+                    Ok(tag) => {
+                        let tags = match open {
+                            OpenElement::Node => data.nodes.items.last_mut().map(|node| &mut node.tags),
+                            OpenElement::Way => data.ways.items.last_mut().map(|way| &mut way.tags),
+                            _ => data.relations.items.last_mut().map(|relation| &mut relation.tags),
+                        };
+                        if let Some(tags) = tags {
+                            tags.push(tag);
+                        }
+                    }
+                    Err(error) => warnings.push(format!("skipped tag: {}", error)),
+                },
+                (b"nd", OpenElement::Way) => match parse_node_ref(e) {
+                    Ok(node_ref) => {
+                        if let Some(last_way) = data.ways.items.last_mut() {
+                            last_way.node_refs.push(node_ref);
+                        }
+                    }
+                    Err(error) => warnings.push(format!("skipped node reference: {}", error)),
+                },
+                (b"member", OpenElement::Relation) => {
+                    if let Some(last_relation) = data.relations.items.last_mut() {
+                        match parse_member(e, &mut warnings) {
+                            Ok(member) => last_relation.members.push(member),
+                            Err(error) => warnings.push(format!("skipped member: {}", error)),
+                        }
+                    }
+                }
+                _ => (),
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Box::new(e)),
+            _ => (),
+        }
+
+        for warning in warnings {
+            match warned {
+                OpenElement::Node => data.nodes.warn(position, warning),
+                OpenElement::Way => data.ways.warn(position, warning),
+                OpenElement::Relation => data.relations.warn(position, warning),
+                OpenElement::None => (),
+            }
+        }
+        buf.clear();
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::count_nodes;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    // Node 2 has a latitude that is not a number, node 3 an unknown attribute and child
+    // element, and node 4 a version that is not a number
+    const ONE_MALFORMED_NODE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"><tag k="name" v="first"/></node>
+ <node id="2" lat="abc" lon="12.1" version="1"><tag k="name" v="broken"/></node>
+ <node id="3" lat="55.2" lon="12.2" version="1" colour="red"><note>ignored</note></node>
+ <node id="4" lat="55.3" lon="12.3" version="x"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="3"/></way>
+ <way id="eleven" version="1"><nd ref="3"/><nd ref="4"/></way>
+</osm>
+"#;
+
+    fn tag_names(node: &Node) -> Vec<String> {
+        node.tags.iter().map(|tag| format!("{}={}", tag.key, tag.value)).collect()
+    }
+
+    #[test]
+    fn a_malformed_element_is_skipped_and_the_others_are_read() {
+        let nodes = read_nodes_from_bytes(ONE_MALFORMED_NODE.as_bytes()).unwrap();
+        assert_eq!(nodes.items.iter().map(|node| node.id).collect::<Vec<_>>(), vec![1, 3, 4]);
+        assert_eq!(nodes.skipped, 1);
+        // The tags of the skipped node are not given to the one before it
+        assert_eq!(tag_names(&nodes.items[0]), vec!["name=first"]);
+        assert!(nodes.items[1].tags.is_empty());
+        // The bad version only warns, the node is kept
+        assert_eq!(nodes.warnings.len(), 2, "{:?}", nodes.warnings);
+        assert!(nodes.warnings[0].contains("skipped node"), "{:?}", nodes.warnings);
+
+        let ways = read_ways_from_bytes(ONE_MALFORMED_NODE.as_bytes()).unwrap();
+        assert_eq!(ways.items.iter().map(|way| way.id).collect::<Vec<_>>(), vec![10]);
+        assert_eq!(ways.items[0].node_refs, vec![1, 3]);
+        assert_eq!(ways.skipped, 1);
+
+        // Reading everything in one pass skips the same elements
+        let data = read_osm_xml(ONE_MALFORMED_NODE.as_bytes()).unwrap();
+        assert_eq!((data.nodes.items.len(), data.nodes.skipped), (3, 1));
+        assert_eq!((data.ways.items.len(), data.ways.skipped), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn a_file_with_a_malformed_node_imports_the_valid_ones() {
+        let pool = memory_pool("malformed_node").await;
+        let stats = import_osm_xml(&pool, "malformed_node", ONE_MALFORMED_NODE).await;
+
+        assert_eq!((stats.nodes, stats.ways), (3, 1));
+        assert_eq!(count_nodes(&pool).await.unwrap(), 3);
+    }
 }
@@ -2,7 +2,7 @@ use sqlx::{FromRow, sqlite::SqliteRow, Row};
 
 use crate::{
     osm_entities::Tag,
-    utils::MapsTag
+    utils::{from_e7, MapsTag}
 };
 
 /// Represents a geographic node with various properties and metadata.
@@ -17,7 +17,7 @@ use crate::{
 /// * `uid` - The user ID as an i64 of the user who last modified the node.
 /// * `user` - A String representing the username of the last modifier.
 /// * `tags` - A Vec<Tag> for additional metadata about the node.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct Node {
     pub id: i64,
     pub lat: f64,
@@ -77,8 +77,9 @@ impl Node {
 impl FromRow<'_, SqliteRow> for Node {
     fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
         let id: i64 = row.try_get("id")?;
-        let lat: f64 = row.try_get("lat")?;
-        let lon: f64 = row.try_get("lon")?;
+        // The coordinates are stored as integers, see `COORDINATE_SCALE`
+        let lat = from_e7(row.try_get("lat_e7")?);
+        let lon = from_e7(row.try_get("lon_e7")?);
         let version: i32 = row.try_get("version")?;
         let timestamp: String = row.try_get("timestamp")?;
         let changeset: i64 = row.try_get("changeset")?;
@@ -89,7 +90,7 @@ impl FromRow<'_, SqliteRow> for Node {
         let tags = if let Some(tags_str) = tags_str {
             tags_str.split(',')
                 .filter_map(|tag| {
-                    let mut parts = tag.splitn(2, ':');
+                    let mut parts = tag.splitn(2, '=');
                     let key = parts.next().unwrap_or_default().to_string();
                     let value = parts.next().unwrap_or_default().to_string();
                     if key.is_empty() || value.is_empty() {
@@ -120,6 +121,7 @@ impl FromRow<'_, SqliteRow> for Node {
 /// Represents a simplified node with only the necessary information for rendering.
 #[derive(Debug, Clone, PartialEq)]
 pub struct SimpleNode {
+    pub id: Option<i64>, // None for points made by clipping, which are no node of the map
     pub lat: f64,
     pub lon: f64,
 }
@@ -2,7 +2,7 @@ use sqlx::{FromRow, sqlite::SqliteRow, Row};
 
 use crate::{
     osm_entities::{Member, Tag},
-    utils::{MapsTag, MapsType}
+    utils::MapsTag
 };
 
 #[derive(Clone, Debug)]
@@ -59,17 +59,18 @@ impl Relation {
             .collect()
     }
 
-    /// Extracts members from a slice of relations along with their relation IDs.
+    /// Extracts members from a slice of relations along with their relation IDs and positions.
     ///
     /// # Arguments
     /// * `relations` - A slice of Relation structs.
     ///
     /// # Returns
-    /// A vector of tuples where each tuple contains a relation ID and a Member struct.
-    pub fn extract_members(relations: &[Relation]) -> Vec<(i64, Member)> {
+    /// A vector of tuples where each tuple contains a relation ID, the position of the member
+    /// within the relation, counted from 0, and a Member struct.
+    pub fn extract_members(relations: &[Relation]) -> Vec<(i64, i64, Member)> {
         relations.iter()
             .flat_map(|relation| {
-                relation.members.iter().cloned().map(move |member| (relation.id, member))
+                relation.members.iter().cloned().enumerate().map(move |(seq, member)| (relation.id, seq as i64, member))
             })
             .collect()
     }
@@ -88,7 +89,7 @@ impl FromRow<'_, SqliteRow> for Relation {
         let tags = if let Some(tags_str) = tags_str {
             tags_str.split(',')
                 .filter_map(|tag| {
-                    let mut parts = tag.splitn(2, ':');
+                    let mut parts = tag.splitn(2, '=');
                     let key = parts.next()?.to_string();
                     let value = parts.next()?.to_string();
                     Some(Tag { key, value })
@@ -98,32 +99,8 @@ impl FromRow<'_, SqliteRow> for Relation {
             Vec::new()
         };
 
-        let members_str: Option<String> = row.try_get("members").ok();
-        let members = if let Some(members_str) = members_str {
-            members_str.split(',')
-                .filter_map(|member| {
-                    let mut parts = member.splitn(6, ':');
-                    let id = parts.next()?.parse::<i64>().ok()?;
-                    let node_id = parts.next()?.parse::<i64>().ok();
-                    let way_id = parts.next()?.parse::<i64>().ok();
-                    let relation_ref_id = parts.next()?.parse::<i64>().ok();
-                    let maps_type_str = parts.next()?;
-                    let maps_type = maps_type_str.parse::<MapsType>().ok()?;
-                    let role = parts.next()?.to_string();
-
-                    let ref_id = match maps_type {
-                        MapsType::Node => node_id,
-                        MapsType::Way => way_id,
-                        MapsType::Relation => relation_ref_id,
-                        _ => None,
-                    }?;
-
-                    Some(Member { id, ref_id, maps_type, role })
-                })
-                .collect()
-        } else {
-            Vec::new()
-        };
+        // The members come from a query of their own, see `relations_with_members`
+        let members = Vec::new();
 
         Ok(Self {
             id,
@@ -1,4 +1,4 @@
-#[derive(Debug, Clone, Default, sqlx::FromRow)]
+#[derive(Debug, Clone, Default, sqlx::FromRow, serde::Serialize)]
 pub struct Tag {
     pub key: String,
     pub value: String,
@@ -1,5 +1,8 @@
+use std::num::NonZeroI64;
+
 use sqlx::{FromRow, sqlite::SqliteRow, Row};
 use crate::osm_entities::Tag;
+use crate::utils::from_e7;
 
 use super::SimpleNode;
 
@@ -53,16 +56,17 @@ impl Way {
         ways.iter().map(extractor).collect()
     }
 
-    /// Extracts way ID and node_ref pairs from a slice of ways.
+    /// Extracts the node references of a slice of ways with their positions.
     ///
     /// # Arguments
     /// * `ways` - A slice of way structs from which way IDs and node_refs are extracted.
     ///
     /// # Returns
-    /// A vector of tuples, each containing a way ID and a corresponding node_ref.
-    pub fn extract_way_node_refs(ways: &[Self]) -> Vec<(i64, i64)> {
+    /// A vector of tuples, each containing a way ID, the position of the node_ref within the
+    /// way, counted from 0, and the node_ref. A closed way repeats its first node_ref last.
+    pub fn extract_way_node_refs(ways: &[Self]) -> Vec<(i64, i64, i64)> {
         ways.iter()
-            .flat_map(|way| way.node_refs.iter().map(move |&node_ref| (way.id, node_ref)))
+            .flat_map(|way| way.node_refs.iter().enumerate().map(move |(seq, &node_ref)| (way.id, seq as i64, node_ref)))
             .collect()
     }
 }
@@ -75,11 +79,12 @@ impl FromRow<'_, SqliteRow> for Way {
         let changeset: i64 = row.try_get("changeset")?;
         let uid: i64 = row.try_get("uid")?;
         let user: String = row.try_get("user")?;
+        // Keys may contain ':' themselves, e.g. `oneway:bicycle`, so key and value are joined with '='
         let tags_str: Option<String> = row.try_get("tags").ok();
         let tags = if let Some(tags_str) = tags_str {
             tags_str.split(',')
                 .filter_map(|tag| {
-                    let mut parts = tag.splitn(2, ':');
+                    let mut parts = tag.splitn(2, '=');
                     let key = parts.next().unwrap_or_default().to_string();
                     let value = parts.next().unwrap_or_default().to_string();
                     if key.is_empty() || value.is_empty() {
@@ -117,20 +122,64 @@ impl FromRow<'_, SqliteRow> for Way {
 }
 
 /// Represents a simplified way containing its nodes and relevant tags.
+///
+/// The coordinates and ids of the nodes are kept in two arrays of their own, so the
+/// coordinates can be handed to the tessellator and the spatial index as they are, and a
+/// point takes 24 bytes instead of the 32 of a `SimpleNode`.
+///
+/// A way crossing the edge of an extract may refer to nodes that were never imported. Those
+/// are left out of `coords` and counted in `missing_nodes`.
+///
+/// An area assembled from a multipolygon relation carries the id and tags of the relation,
+/// its outer ring in `coords` and its inner rings, e.g. courtyards, in `inner_rings`.
 #[derive(Debug, Clone)]
 pub struct RenderableWay {
-    pub nodes: Vec<SimpleNode>, // Directly hold the node data for rendering
-    pub tags: Vec<Tag>,         // Tags associated with this way (e.g., "highway", "coastline", etc.)
+    pub id: i64,
+    pub coords: Vec<(f64, f64)>,           // The (lat, lon) of every node, in order
+    pub node_ids: Vec<Option<NonZeroI64>>, // The id of every node in `coords`, None for points made by clipping
+    pub tags: Vec<Tag>,                    // Tags associated with this way (e.g., "highway", "coastline", etc.)
+    pub missing_nodes: u32,                // Node references without a node in the database
+    pub inner_rings: Vec<Vec<(f64, f64)>>, // The holes of an area of a multipolygon relation, empty for ways
+}
+
+impl RenderableWay {
+    /// Builds a way from its nodes.
+    pub fn from_nodes(id: i64, nodes: &[SimpleNode], tags: Vec<Tag>, missing_nodes: u32) -> Self {
+        RenderableWay {
+            id,
+            coords: nodes.iter().map(|node| (node.lat, node.lon)).collect(),
+            node_ids: nodes.iter().map(|node| node.id.and_then(NonZeroI64::new)).collect(),
+            tags,
+            missing_nodes,
+            inner_rings: Vec::new(),
+        }
+    }
+
+    /// Returns true if every node the way refers to is in `coords`. An incomplete way is
+    /// drawn as an open line through the nodes it has, never as an area.
+    pub fn is_complete(&self) -> bool {
+        self.missing_nodes == 0
+    }
+
+    /// The nodes of the way with their ids, for the code joining ways at shared nodes.
+    pub fn nodes(&self) -> Vec<SimpleNode> {
+        self.coords.iter().zip(&self.node_ids)
+            .map(|(&(lat, lon), id)| SimpleNode { id: id.map(NonZeroI64::get), lat, lon })
+            .collect()
+    }
 }
 
 impl FromRow<'_, SqliteRow> for RenderableWay {
     fn from_row(row: &'_ SqliteRow) -> Result<Self, sqlx::Error> {
-        // Parse the tags from the row
+        let id: i64 = row.try_get("id")?;
+
+        // Parse the tags from the row. Keys may contain ':' themselves, e.g. `building:levels`,
+        // so key and value are joined with '=' instead
         let tags_str: Option<String> = row.try_get("tags").ok();
         let tags = if let Some(tags_str) = tags_str {
             tags_str.split(',')
                 .filter_map(|tag| {
-                    let mut parts = tag.splitn(2, ':');
+                    let mut parts = tag.splitn(2, '=');
                     let key = parts.next().unwrap_or_default().to_string();
                     let value = parts.next().unwrap_or_default().to_string();
                     if key.is_empty() || value.is_empty() {
@@ -144,29 +193,35 @@ impl FromRow<'_, SqliteRow> for RenderableWay {
             Vec::new()
         };
 
-        // Parse node references (latitude and longitude)
+        // Parse node references (id, latitude and longitude as stored, see `COORDINATE_SCALE`),
+        // the id keeps junctions between ways apparent
         let node_refs_str: Option<String> = row.try_get("node_refs").ok();
-        let nodes = if let Some(node_refs_str) = node_refs_str {
-            node_refs_str.split(',')
-                .filter_map(|node_ref| {
-                    let coords: Vec<&str> = node_ref.split_whitespace().collect();
-                    if coords.len() == 2 {
-                        Some(SimpleNode {
-                            lat: coords[0].parse().ok()?,
-                            lon: coords[1].parse().ok()?,
-                        })
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        } else {
-            Vec::new()
-        };
+        let mut coords = Vec::new();
+        let mut node_ids = Vec::new();
+        for node_ref in node_refs_str.iter().flat_map(|node_refs_str| node_refs_str.split(',')) {
+            let fields: Vec<&str> = node_ref.split_whitespace().collect();
+            let (id, lat, lon) = match fields[..] {
+                [id, lat, lon] => (id.parse().ok(), lat, lon),
+                [lat, lon] => (None, lat, lon),
+                _ => continue,
+            };
+            let (Ok(lat), Ok(lon)) = (lat.parse(), lon.parse()) else {
+                continue;
+            };
+            coords.push((from_e7(lat), from_e7(lon)));
+            node_ids.push(id);
+        }
+
+        // Queries not counting the missing nodes leave the column out
+        let missing_nodes: i64 = row.try_get("missing_nodes").unwrap_or(0);
 
         Ok(Self {
-            nodes,
+            id,
+            coords,
+            node_ids,
             tags,
+            missing_nodes: u32::try_from(missing_nodes).unwrap_or(u32::MAX),
+            inner_rings: Vec::new(),
         })
     }
 }
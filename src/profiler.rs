@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How many frames the summary is over, two seconds at 60 frames per second.
+pub const FRAME_HISTORY: usize = 120;
+
+/// Measures where the time of a frame goes, as named spans summed per frame, e.g. `update`
+/// or `encode`. The last `FRAME_HISTORY` frames are kept to summarize.
+///
+/// Spans are only measured while enabled, so the calls can stay in the frame when it is not.
+/// Spans of different names may nest, each is measured on its own.
+///
+/// # Fields
+/// * `enabled` - Whether spans are measured.
+/// * `open` - The spans begun and not ended yet, with when they began.
+/// * `current` - The time of every span of the frame being drawn, in the order they first ended.
+/// * `frames` - The spans of the frames drawn, the oldest first.
+/// * `frames_since_summary` - The frames finished since the summary was last due.
+#[derive(Debug, Default)]
+pub struct FrameProfiler {
+    enabled: bool,
+    open: Vec<(&'static str, Instant)>,
+    current: Vec<(&'static str, Duration)>,
+    frames: VecDeque<Vec<(&'static str, Duration)>>,
+    frames_since_summary: usize,
+}
+
+impl FrameProfiler {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts or stops measuring. The frames measured before are forgotten either way.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        *self = FrameProfiler { enabled, ..FrameProfiler::default() };
+    }
+
+    /// Begins a span, ended by `end` with the same name. A span of the name still open, e.g.
+    /// of a frame that failed half way, is started over.
+    pub fn begin(&mut self, name: &'static str, now: Instant) {
+        if !self.enabled {
+            return;
+        }
+        self.open.retain(|(open, _)| *open != name);
+        self.open.push((name, now));
+    }
+
+    /// Ends the span begun with the name, adding its time to the frame. A span that was not
+    /// begun is ignored.
+    pub fn end(&mut self, name: &'static str, now: Instant) {
+        if let Some(index) = self.open.iter().position(|(open, _)| *open == name) {
+            let (_, began) = self.open.remove(index);
+            self.record(name, now.saturating_duration_since(began));
+        }
+    }
+
+    /// Adds time measured elsewhere to the frame, e.g. what the GPU took.
+    pub fn record(&mut self, name: &'static str, duration: Duration) {
+        if !self.enabled {
+            return;
+        }
+        match self.current.iter_mut().find(|(stage, _)| *stage == name) {
+            Some((_, total)) => *total += duration,
+            None => self.current.push((name, duration)),
+        }
+    }
+
+    /// Finishes the frame, dropping the oldest one kept once there are `FRAME_HISTORY`.
+    /// Spans still open carry over into the next frame.
+    ///
+    /// ## Returns
+    /// * Whether another `FRAME_HISTORY` frames were measured since the summary was last due.
+    pub fn end_frame(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.frames.len() == FRAME_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(std::mem::take(&mut self.current));
+
+        self.frames_since_summary += 1;
+        if self.frames_since_summary < FRAME_HISTORY {
+            return false;
+        }
+        self.frames_since_summary = 0;
+        true
+    }
+
+    /// Summarizes the frames kept, every stage in the order it first appeared.
+    pub fn summary(&self) -> FrameSummary {
+        let mut names: Vec<&'static str> = Vec::new();
+        for (name, _) in self.frames.iter().flatten() {
+            if !names.contains(name) {
+                names.push(name);
+            }
+        }
+
+        // A frame without a span of a stage spent no time on it, which counts towards the average
+        let stages = names.into_iter()
+            .map(|name| {
+                let mut durations: Vec<Duration> = self.frames.iter()
+                    .map(|frame| frame.iter().find(|(stage, _)| *stage == name).map_or(Duration::ZERO, |(_, duration)| *duration))
+                    .collect();
+                durations.sort();
+                let average = durations.iter().sum::<Duration>() / durations.len() as u32;
+                let p95 = durations[(durations.len() * 95).div_ceil(100).saturating_sub(1)];
+                StageSummary { name, average, p95 }
+            })
+            .collect();
+        FrameSummary { frames: self.frames.len(), stages }
+    }
+}
+
+/// How long one stage of the frames took.
+///
+/// # Fields
+/// * `average` - The mean over the frames.
+/// * `p95` - The time 95 % of the frames took at most.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StageSummary {
+    pub name: &'static str,
+    pub average: Duration,
+    pub p95: Duration,
+}
+
+/// How long the stages of the last frames took, see `FrameProfiler::summary`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameSummary {
+    pub frames: usize,
+    pub stages: Vec<StageSummary>,
+}
+
+impl fmt::Display for FrameSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let milliseconds = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        write!(f, "{} frames", self.frames)?;
+        for stage in &self.stages {
+            write!(f, ", {} avg {:.2} ms p95 {:.2} ms", stage.name, milliseconds(stage.average), milliseconds(stage.p95))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(milliseconds: u64) -> Duration {
+        Duration::from_millis(milliseconds)
+    }
+
+    fn enabled() -> FrameProfiler {
+        let mut profiler = FrameProfiler::default();
+        profiler.set_enabled(true);
+        profiler
+    }
+
+    #[test]
+    fn spans_of_a_frame_are_summed_per_name() {
+        let start = Instant::now();
+        let mut profiler = enabled();
+        // Two uploads within an encode, and the GPU time recorded on its own
+        profiler.begin("encode", start);
+        profiler.begin("upload", start);
+        profiler.end("upload", start + ms(2));
+        profiler.begin("upload", start + ms(3));
+        profiler.end("upload", start + ms(4));
+        profiler.end("encode", start + ms(10));
+        profiler.record("gpu", ms(5));
+        // Never begun
+        profiler.end("update", start + ms(10));
+        assert!(!profiler.end_frame());
+
+        let summary = profiler.summary();
+        assert_eq!(summary.frames, 1);
+        assert_eq!(summary.stages, [
+            StageSummary { name: "upload", average: ms(3), p95: ms(3) },
+            StageSummary { name: "encode", average: ms(10), p95: ms(10) },
+            StageSummary { name: "gpu", average: ms(5), p95: ms(5) },
+        ]);
+        assert_eq!(summary.to_string(), "1 frames, upload avg 3.00 ms p95 3.00 ms, encode avg 10.00 ms p95 10.00 ms, gpu avg 5.00 ms p95 5.00 ms");
+    }
+
+    #[test]
+    fn the_summary_is_over_the_last_frames_and_due_every_history() {
+        let mut profiler = enabled();
+        let mut due = Vec::new();
+        // Frame n takes n ms to tessellate, and only every tenth frame uploads anything
+        for frame in 1..=250u64 {
+            profiler.record("tessellate", ms(frame));
+            if frame % 10 == 0 {
+                profiler.record("upload", ms(12));
+            }
+            if profiler.end_frame() {
+                due.push(frame);
+            }
+        }
+        assert_eq!(due, [120, 240]);
+
+        // Frames 131 to 250 are kept
+        let summary = profiler.summary();
+        assert_eq!(summary.frames, FRAME_HISTORY);
+        assert_eq!(summary.stages[0], StageSummary { name: "tessellate", average: Duration::from_micros(190_500), p95: ms(244) });
+        // A frame without an upload counts as none
+        assert_eq!(summary.stages[1], StageSummary { name: "upload", average: Duration::from_micros(1200), p95: ms(12) });
+    }
+
+    #[test]
+    fn a_disabled_profiler_measures_nothing() {
+        let start = Instant::now();
+        let mut profiler = FrameProfiler::default();
+        assert!(!profiler.is_enabled());
+        profiler.begin("update", start);
+        profiler.end("update", start + ms(1));
+        profiler.record("gpu", ms(1));
+        assert!(!profiler.end_frame());
+        assert_eq!(profiler.summary(), FrameSummary::default());
+
+        // Enabling it again forgets what was measured before
+        let mut profiler = enabled();
+        profiler.record("gpu", ms(1));
+        profiler.end_frame();
+        profiler.set_enabled(true);
+        assert_eq!(profiler.summary().frames, 0);
+    }
+
+    #[test]
+    fn a_span_left_open_carries_over_or_starts_over() {
+        let start = Instant::now();
+        let mut profiler = enabled();
+        profiler.begin("acquire", start);
+        profiler.end_frame();
+        profiler.end("acquire", start + ms(4));
+        // Begun again before it ended, e.g. after a failed frame
+        profiler.begin("update", start + ms(4));
+        profiler.begin("update", start + ms(6));
+        profiler.end("update", start + ms(7));
+        profiler.end_frame();
+
+        assert_eq!(profiler.summary().stages, [
+            StageSummary { name: "acquire", average: ms(2), p95: ms(4) },
+            StageSummary { name: "update", average: Duration::from_micros(500), p95: ms(1) },
+        ]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long tessellating newly arrived data may take per frame. The rest is left to the
+/// next frames, so a frame at 60 Hz still has most of its 16 ms to be drawn.
+pub const TESSELLATION_BUDGET: Duration = Duration::from_millis(4);
+/// How many items are tessellated between looks at the clock. Enough for the tessellation
+/// threads to share, few enough to stay close to the budget.
+pub const TESSELLATION_BATCH: usize = 64;
+
+/// Work done a slice per frame, in the order it was queued, rather than all at once.
+///
+/// # Fields
+/// * `pending` - The items not done yet.
+#[derive(Debug)]
+pub struct WorkQueue<T> {
+    pending: VecDeque<T>,
+}
+
+impl<T> WorkQueue<T> {
+    pub fn new(items: impl IntoIterator<Item = T>) -> Self {
+        WorkQueue { pending: items.into_iter().collect() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Hands batches of items to `work` until the queue is empty or `budget` has passed.
+    ///
+    /// The first batch is always done, so every call makes progress however small the
+    /// budget. The clock is read through `now` before the first batch and after every
+    /// batch, so it can be driven without waiting.
+    ///
+    /// ## Arguments
+    /// * `budget` - How long to keep working, a batch started in time is finished.
+    /// * `batch_size` - The most items handed to `work` at once, at least one.
+    /// * `now` - Reads the clock.
+    /// * `work` - Does a batch of items, in the order they were queued.
+    ///
+    /// ## Returns
+    /// * The number of items done.
+    pub fn run(&mut self, budget: Duration, batch_size: usize, mut now: impl FnMut() -> Instant, mut work: impl FnMut(Vec<T>)) -> usize {
+        let start = now();
+        let mut done = 0;
+        while !self.pending.is_empty() {
+            let batch: Vec<T> = self.pending.drain(..batch_size.clamp(1, self.pending.len())).collect();
+            done += batch.len();
+            work(batch);
+
+            if now().duration_since(start) >= budget {
+                break;
+            }
+        }
+        done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A clock that only moves when told to, by `step` each time a batch is done.
+    struct FakeClock {
+        now: Cell<Instant>,
+        step: Duration,
+    }
+
+    impl FakeClock {
+        fn new(step: Duration) -> Self {
+            FakeClock { now: Cell::new(Instant::now()), step }
+        }
+
+        fn read(&self) -> Instant {
+            self.now.get()
+        }
+
+        fn advance(&self) {
+            self.now.set(self.now.get() + self.step);
+        }
+    }
+
+    #[test]
+    fn work_stops_once_the_budget_is_spent_and_resumes_in_order() {
+        let clock = FakeClock::new(Duration::from_millis(1));
+        let mut queue = WorkQueue::new(0..1000);
+        let mut done = Vec::new();
+        let mut batches = Vec::new();
+
+        // Every batch takes a millisecond, so four fit into the budget
+        let mut frames = 0;
+        while !queue.is_empty() {
+            let count = queue.run(TESSELLATION_BUDGET, TESSELLATION_BATCH, || clock.read(), |batch| {
+                batches.push(batch.len());
+                done.extend(batch);
+                clock.advance();
+            });
+            frames += 1;
+            assert!(count <= 4 * TESSELLATION_BATCH, "{} items in one frame", count);
+            assert_eq!(done.len() + queue.len(), 1000);
+        }
+
+        assert_eq!(done, (0..1000).collect::<Vec<_>>());
+        assert_eq!(frames, 1000usize.div_ceil(4 * TESSELLATION_BATCH));
+        assert!(batches[..batches.len() - 1].iter().all(|&size| size == TESSELLATION_BATCH));
+        assert_eq!(batches.last(), Some(&(1000 % TESSELLATION_BATCH)));
+    }
+
+    #[test]
+    fn quick_work_is_done_in_one_frame() {
+        let clock = FakeClock::new(Duration::ZERO);
+        let mut queue = WorkQueue::new(0..1000);
+        assert_eq!(queue.run(TESSELLATION_BUDGET, TESSELLATION_BATCH, || clock.read(), |_| clock.advance()), 1000);
+        assert!(queue.is_empty());
+
+        // An empty queue does nothing
+        let mut calls = 0;
+        assert_eq!(queue.run(TESSELLATION_BUDGET, TESSELLATION_BATCH, || clock.read(), |_| calls += 1), 0);
+        assert_eq!(calls, 0);
+    }
+
+    #[test]
+    fn slow_work_still_makes_progress() {
+        // A single batch takes longer than the whole budget
+        let clock = FakeClock::new(TESSELLATION_BUDGET * 10);
+        let mut queue = WorkQueue::new(0..10);
+        assert_eq!(queue.run(TESSELLATION_BUDGET, 3, || clock.read(), |_| clock.advance()), 3);
+        assert_eq!(queue.run(Duration::ZERO, 3, || clock.read(), |_| clock.advance()), 3);
+        // A batch size of zero is taken as one
+        assert_eq!(queue.run(Duration::ZERO, 0, || clock.read(), |_| clock.advance()), 1);
+        assert_eq!(queue.len(), 3);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use super::RoutingGraph;
+
+/// How alternatives to the fastest route are looked for, see `RoutingGraph::alternative_paths`.
+///
+/// # Fields
+/// * `count` - The most alternatives returned besides the fastest route.
+/// * `penalty` - The factor the cost of an edge is multiplied by for every route found on it,
+///   so the next search avoids the roads taken already.
+/// * `max_overlap` - The largest share of the length of an alternative that may run along a
+///   route already returned, see `RoutingGraph::path_overlap`.
+/// * `max_stretch` - The most an alternative may take longer than the fastest route, as a
+///   factor of its travel time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlternativeOptions {
+    pub count: usize,
+    pub penalty: f64,
+    pub max_overlap: f64,
+    pub max_stretch: f64,
+}
+
+impl Default for AlternativeOptions {
+    fn default() -> Self {
+        AlternativeOptions { count: 2, penalty: 1.5, max_overlap: 0.7, max_stretch: 1.25 }
+    }
+}
+
+// Penalized searches may find a route rejected before, so a few more are run than alternatives wanted
+const SEARCHES_PER_ALTERNATIVE: usize = 3;
+
+impl RoutingGraph {
+    /// The indices of the edges a path uses, the fastest where two nodes are connected by more than one.
+    ///
+    /// ## Returns
+    /// * The edges, or `None` if two consecutive nodes of the path are not connected.
+    pub fn path_edges(&self, path: &[i64]) -> Option<Vec<usize>> {
+        path.windows(2).map(|pair| self.fastest_edge_index(pair[0], pair[1])).collect()
+    }
+
+    /// How much of a path runs along other paths: the length of its edges that any of `others`
+    /// uses too, as a share of its whole length.
+    ///
+    /// ## Returns
+    /// * The share from 0 to 1, 0 for a path without length.
+    pub fn path_overlap(&self, path: &[usize], others: &[&[usize]]) -> f64 {
+        let shared: HashSet<usize> = others.iter().flat_map(|other| other.iter().copied()).collect();
+        let length_m: f64 = path.iter().map(|&edge| self.edges[edge].distance_m).sum();
+        if length_m <= 0.0 {
+            return 0.0;
+        }
+
+        let shared_m: f64 = path.iter().filter(|edge| shared.contains(edge)).map(|&edge| self.edges[edge].distance_m).sum();
+        shared_m / length_m
+    }
+
+    /// Finds the fastest path between two nodes and alternatives to it by the penalty method:
+    /// the search is run again with the edges of the paths found so far made more expensive,
+    /// so it strays onto other roads.
+    ///
+    /// An alternative is dropped if it runs along the paths returned before it for more than
+    /// `max_overlap` of its length, or takes longer than `max_stretch` times the fastest path.
+    /// Where there is only one sensible way, no alternative is made up.
+    ///
+    /// ## Returns
+    /// * The fastest path followed by the alternatives, fastest first, each as its node ids,
+    ///   or nothing if `goal` cannot be reached.
+    pub fn alternative_paths(&self, start: i64, goal: i64, options: &AlternativeOptions) -> Vec<Vec<i64>> {
+        let Some(fastest) = self.shortest_path(start, goal) else {
+            return Vec::new();
+        };
+        let Some(fastest_edges) = self.path_edges(&fastest) else {
+            return vec![fastest];
+        };
+        let max_time_s = self.path_time(&fastest_edges) * options.max_stretch;
+
+        // Every path found is penalized, rejected ones too, so the next search does not find it again
+        let mut penalties: HashMap<usize, f64> = HashMap::new();
+        penalize(&mut penalties, &fastest_edges, options.penalty);
+
+        let mut accepted = vec![(fastest, fastest_edges)];
+        for _ in 0..options.count * SEARCHES_PER_ALTERNATIVE {
+            if accepted.len() > options.count {
+                break;
+            }
+
+            let Some(candidate) = self.shortest_path_with(start, goal, |edge| self.edges[edge].cost * penalties.get(&edge).copied().unwrap_or(1.0)) else {
+                break;
+            };
+            let Some(candidate_edges) = self.path_edges(&candidate) else {
+                break;
+            };
+
+            penalize(&mut penalties, &candidate_edges, options.penalty);
+            // A path found again is no alternative, however much overlap is allowed
+            if accepted.iter().any(|(path, _)| *path == candidate) {
+                continue;
+            }
+
+            let others: Vec<&[usize]> = accepted.iter().map(|(_, edges)| edges.as_slice()).collect();
+            let overlap = self.path_overlap(&candidate_edges, &others);
+            if overlap <= options.max_overlap && self.path_time(&candidate_edges) <= max_time_s {
+                accepted.push((candidate, candidate_edges));
+            }
+        }
+
+        accepted.into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// The travel time in seconds along edges.
+    fn path_time(&self, edges: &[usize]) -> f64 {
+        edges.iter().map(|&edge| self.edges[edge].cost).sum()
+    }
+}
+
+fn penalize(penalties: &mut HashMap<usize, f64>, edges: &[usize], penalty: f64) {
+    for &edge in edges {
+        *penalties.entry(edge).or_insert(1.0) *= penalty;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::{Tag, Way};
+    use crate::routing::RoutingProfile;
+
+    fn graph(ways: &[(i64, Vec<i64>)], coordinates: &[(i64, (f64, f64))]) -> RoutingGraph {
+        let ways: Vec<Way> = ways.iter()
+            .map(|(id, node_ids)| Way::new(*id, 1, String::new(), 0, 0, String::new(), node_ids.clone(), vec![Tag::new("highway".to_string(), "residential".to_string())]))
+            .collect();
+        RoutingGraph::from_ways(&ways, coordinates.iter().copied().collect(), &[], RoutingProfile::Car)
+    }
+
+    // Two arcs from node 1 in the west to node 2 in the east, over node 3 to the north and a
+    // slightly longer one over node 4 to the south
+    fn theta() -> RoutingGraph {
+        graph(
+            &[(10, vec![1, 3, 2]), (11, vec![1, 4, 2])],
+            &[(1, (55.0, 12.0)), (2, (55.0, 12.01)), (3, (55.003, 12.005)), (4, (54.9968, 12.005))],
+        )
+    }
+
+    #[test]
+    fn both_arcs_of_a_theta_come_back() {
+        let graph = theta();
+        let paths = graph.alternative_paths(1, 2, &AlternativeOptions::default());
+        assert_eq!(paths, [vec![1, 3, 2], vec![1, 4, 2]]);
+
+        let north = graph.path_edges(&paths[0]).unwrap();
+        let south = graph.path_edges(&paths[1]).unwrap();
+        assert_eq!(graph.path_overlap(&south, &[&north]), 0.0);
+
+        // Asked for none, only the fastest comes back
+        let options = AlternativeOptions { count: 0, ..AlternativeOptions::default() };
+        assert_eq!(graph.alternative_paths(1, 2, &options), [vec![1, 3, 2]]);
+    }
+
+    #[test]
+    fn no_alternative_is_made_up_where_there_is_one_road() {
+        let graph = graph(&[(10, vec![1, 5, 6, 2])], &[(1, (55.0, 12.0)), (5, (55.0, 12.003)), (6, (55.0, 12.006)), (2, (55.0, 12.01))]);
+        assert_eq!(graph.alternative_paths(1, 2, &AlternativeOptions::default()), [vec![1, 5, 6, 2]]);
+
+        // An unreachable goal gives nothing at all
+        let graph = graph_with_island();
+        assert!(graph.alternative_paths(1, 9, &AlternativeOptions::default()).is_empty());
+    }
+
+    fn graph_with_island() -> RoutingGraph {
+        graph(&[(10, vec![1, 2]), (11, vec![8, 9])], &[(1, (55.0, 12.0)), (2, (55.0, 12.01)), (8, (56.0, 12.0)), (9, (56.0, 12.01))])
+    }
+
+    #[test]
+    fn a_detour_along_most_of_the_route_is_dropped() {
+        // A long road with a short bypass of its middle between nodes 5 and 6
+        let graph = graph(
+            &[(10, vec![1, 5, 6, 2]), (11, vec![5, 7, 6])],
+            &[(1, (55.0, 12.0)), (5, (55.0, 12.008)), (6, (55.0, 12.010)), (7, (55.0003, 12.009)), (2, (55.0, 12.018))],
+        );
+        let main = graph.path_edges(&[1, 5, 6, 2]).unwrap();
+        let detour = graph.path_edges(&[1, 5, 7, 6, 2]).unwrap();
+        assert!(graph.path_overlap(&detour, &[&main]) > 0.7);
+
+        assert_eq!(graph.alternative_paths(1, 2, &AlternativeOptions::default()), [vec![1, 5, 6, 2]]);
+        // Allowed to overlap, it is kept
+        let options = AlternativeOptions { max_overlap: 1.0, ..AlternativeOptions::default() };
+        assert_eq!(graph.alternative_paths(1, 2, &options), [vec![1, 5, 6, 2], vec![1, 5, 7, 6, 2]]);
+    }
+
+    #[test]
+    fn an_alternative_far_slower_than_the_fastest_is_dropped() {
+        // The southern arc dips five times as far as the northern one rises
+        let graph = graph(
+            &[(10, vec![1, 3, 2]), (11, vec![1, 4, 2])],
+            &[(1, (55.0, 12.0)), (2, (55.0, 12.01)), (3, (55.003, 12.005)), (4, (54.985, 12.005))],
+        );
+        assert_eq!(graph.alternative_paths(1, 2, &AlternativeOptions::default()), [vec![1, 3, 2]]);
+
+        let options = AlternativeOptions { max_stretch: 10.0, ..AlternativeOptions::default() };
+        assert_eq!(graph.alternative_paths(1, 2, &options).len(), 2);
+    }
+
+    #[test]
+    fn the_overlap_is_the_shared_share_of_the_length() {
+        let graph = graph(&[(10, vec![1, 5, 2])], &[(1, (55.0, 12.0)), (5, (55.0, 12.003)), (2, (55.0, 12.012))]);
+        let whole = graph.path_edges(&[1, 5, 2]).unwrap();
+        let first = graph.path_edges(&[1, 5]).unwrap();
+        // The first edge is a quarter of the length
+        assert!((graph.path_overlap(&whole, &[&first]) - 0.25).abs() < 1e-3);
+        assert_eq!(graph.path_overlap(&whole, &[&whole]), 1.0);
+        assert_eq!(graph.path_overlap(&whole, &[]), 0.0);
+        assert_eq!(graph.path_overlap(&[], &[&whole]), 0.0);
+        assert!(graph.path_edges(&[1, 2]).is_none());
+    }
+}
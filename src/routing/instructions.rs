@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::geo::{bearing, format_distance};
+
+use super::{Edge, RoutingGraph};
+
+// The largest bearing changes in degrees, to either side, that still count as going
+// straight on, a slight turn, a turn and a sharp turn. Anything larger turns around.
+const CONTINUE_MAX_DEG: f64 = 20.0;
+const SLIGHT_TURN_MAX_DEG: f64 = 45.0;
+const TURN_MAX_DEG: f64 = 120.0;
+const SHARP_TURN_MAX_DEG: f64 = 165.0;
+
+/// What to do at a point of a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Maneuver {
+    Depart,
+    Continue,
+    SlightLeft,
+    TurnLeft,
+    SharpLeft,
+    SlightRight,
+    TurnRight,
+    SharpRight,
+    UTurn,
+    /// Enter a roundabout and leave it at the given exit, counting from one.
+    RoundaboutExit(usize),
+    Arrive,
+}
+
+impl Maneuver {
+    /// Classifies the change of bearing at an intersection.
+    ///
+    /// ## Arguments
+    /// * `delta_deg` - The outgoing minus the incoming bearing in degrees, positive to the
+    ///   right, within `[-180, 180]`.
+    pub fn from_bearing_change(delta_deg: f64) -> Self {
+        let right = delta_deg > 0.0;
+        let angle = delta_deg.abs();
+
+        if angle <= CONTINUE_MAX_DEG {
+            Maneuver::Continue
+        } else if angle <= SLIGHT_TURN_MAX_DEG {
+            if right { Maneuver::SlightRight } else { Maneuver::SlightLeft }
+        } else if angle <= TURN_MAX_DEG {
+            if right { Maneuver::TurnRight } else { Maneuver::TurnLeft }
+        } else if angle <= SHARP_TURN_MAX_DEG {
+            if right { Maneuver::SharpRight } else { Maneuver::SharpLeft }
+        } else {
+            Maneuver::UTurn
+        }
+    }
+
+    /// A short name of the maneuver, e.g. for exports.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Maneuver::Depart => "depart",
+            Maneuver::Continue => "continue",
+            Maneuver::SlightLeft => "slight_left",
+            Maneuver::TurnLeft => "turn_left",
+            Maneuver::SharpLeft => "sharp_left",
+            Maneuver::SlightRight => "slight_right",
+            Maneuver::TurnRight => "turn_right",
+            Maneuver::SharpRight => "sharp_right",
+            Maneuver::UTurn => "u_turn",
+            Maneuver::RoundaboutExit(_) => "roundabout",
+            Maneuver::Arrive => "arrive",
+        }
+    }
+}
+
+/// One step of the turn-by-turn instructions of a route.
+///
+/// # Fields
+/// * `maneuver` - What to do.
+/// * `street` - The name of the street the maneuver leads onto, if it has one.
+/// * `distance_m` - The distance in meters from the maneuver to the next one.
+/// * `path_index` - Where along the path of the route the maneuver happens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub maneuver: Maneuver,
+    pub street: Option<String>,
+    pub distance_m: f64,
+    pub path_index: usize,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.maneuver {
+            Maneuver::Depart => "Start".to_string(),
+            Maneuver::Continue => "Continue".to_string(),
+            Maneuver::SlightLeft => "Turn slightly left".to_string(),
+            Maneuver::TurnLeft => "Turn left".to_string(),
+            Maneuver::SharpLeft => "Turn sharp left".to_string(),
+            Maneuver::SlightRight => "Turn slightly right".to_string(),
+            Maneuver::TurnRight => "Turn right".to_string(),
+            Maneuver::SharpRight => "Turn sharp right".to_string(),
+            Maneuver::UTurn => "Make a U-turn".to_string(),
+            Maneuver::RoundaboutExit(exit) => format!("At the roundabout, take exit {}", exit),
+            Maneuver::Arrive => return write!(f, "Arrive at the destination"),
+        };
+
+        write!(f, "{}", action)?;
+        if let Some(street) = &self.street {
+            let preposition = if self.maneuver == Maneuver::Depart { "on" } else { "onto" };
+            write!(f, " {} {}", preposition, street)?;
+        }
+        write!(f, ", then {}", format_distance(self.distance_m))
+    }
+}
+
+/// The difference between two bearings in degrees, positive to the right, within `[-180, 180)`.
+fn bearing_change(incoming_deg: f64, outgoing_deg: f64) -> f64 {
+    (outgoing_deg - incoming_deg + 180.0).rem_euclid(360.0) - 180.0
+}
+
+impl RoutingGraph {
+    /// Turns a path into turn-by-turn instructions, e.g. one found by `shortest_path`.
+    ///
+    /// Maneuvers are only announced at intersections, nodes where more than two neighbours
+    /// meet, so a road bending between them is followed without a word. Going straight on,
+    /// or slightly bending along the same street, is only announced where the street changes.
+    /// A route entering a way tagged with `junction=roundabout` gets a single instruction
+    /// counting the exits up to the one it leaves by.
+    ///
+    /// ## Returns
+    /// * The instructions from departure to arrival, or `None` if two consecutive nodes of
+    ///   the path are not connected.
+    pub fn generate_instructions(&self, path: &[i64]) -> Option<Vec<Instruction>> {
+        let edges: Vec<&Edge> = path.windows(2)
+            .map(|pair| self.fastest_edge(pair[0], pair[1]))
+            .collect::<Option<_>>()?;
+        let Some(first_edge) = edges.first() else {
+            return Some(vec![Instruction { maneuver: Maneuver::Arrive, street: None, distance_m: 0.0, path_index: 0 }]);
+        };
+
+        // The neighbours and ways of every node of the path, in either direction, as a
+        // one-way street still makes an intersection
+        let on_path: HashSet<i64> = path.iter().copied().collect();
+        let mut neighbours: HashMap<i64, HashSet<i64>> = HashMap::new();
+        let mut node_ways: HashMap<i64, HashSet<i64>> = HashMap::new();
+        for edge in &self.edges {
+            for (node, other) in [(edge.from, edge.to), (edge.to, edge.from)] {
+                if on_path.contains(&node) {
+                    neighbours.entry(node).or_default().insert(other);
+                    node_ways.entry(node).or_default().insert(edge.way_id);
+                }
+            }
+        }
+
+        let street = |edge: &Edge| self.way_names.get(&edge.way_id).cloned();
+        let edge_bearing = |edge: &Edge| bearing(self.coordinates[&edge.from], self.coordinates[&edge.to]);
+        let is_roundabout = |edge: &Edge| self.roundabout_ways.contains(&edge.way_id);
+
+        let mut instructions = vec![Instruction { maneuver: Maneuver::Depart, street: street(first_edge), distance_m: 0.0, path_index: 0 }];
+        let mut index = 0;
+
+        while index < edges.len() {
+            let last = instructions.len() - 1;
+            instructions[last].distance_m += edges[index].distance_m;
+            let Some(&outgoing) = edges.get(index + 1) else {
+                break;
+            };
+            let incoming = edges[index];
+            let node = path[index + 1];
+
+            if is_roundabout(outgoing) && !is_roundabout(incoming) {
+                // Every node of the ring where another way leaves is an exit
+                let mut exit = 0;
+                let mut distance_m = 0.0;
+                let mut ring_end = index + 1;
+                while ring_end < edges.len() && is_roundabout(edges[ring_end]) {
+                    distance_m += edges[ring_end].distance_m;
+                    let ways = &node_ways[&edges[ring_end].to];
+                    if ways.iter().any(|way_id| !self.roundabout_ways.contains(way_id)) {
+                        exit += 1;
+                    }
+                    ring_end += 1;
+                }
+
+                instructions.push(Instruction {
+                    maneuver: Maneuver::RoundaboutExit(exit.max(1)),
+                    street: edges.get(ring_end).and_then(|&edge| street(edge)),
+                    distance_m,
+                    path_index: index + 1,
+                });
+                index = ring_end;
+                continue;
+            }
+
+            let is_intersection = neighbours.get(&node).map_or(0, HashSet::len) > 2;
+            let next_street = street(outgoing);
+            let same_street = next_street.is_some() && next_street == street(incoming);
+            let maneuver = match Maneuver::from_bearing_change(bearing_change(edge_bearing(incoming), edge_bearing(outgoing))) {
+                Maneuver::UTurn => Maneuver::UTurn,
+                // Following a street through a slight bend at an intersection needs no instruction
+                Maneuver::SlightLeft | Maneuver::SlightRight if same_street => Maneuver::Continue,
+                maneuver if is_intersection => maneuver,
+                _ => Maneuver::Continue,
+            };
+
+            if maneuver != Maneuver::Continue || next_street != instructions[last].street {
+                instructions.push(Instruction { maneuver, street: next_street, distance_m: 0.0, path_index: index + 1 });
+            }
+            index += 1;
+        }
+
+        instructions.push(Instruction { maneuver: Maneuver::Arrive, street: None, distance_m: 0.0, path_index: path.len() - 1 });
+        Some(instructions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::{Tag, Way};
+    use crate::routing::RoutingProfile;
+
+    #[test]
+    fn bearing_changes_are_classified_into_maneuvers() {
+        let table = [
+            (0.0, Maneuver::Continue),
+            (-20.0, Maneuver::Continue),
+            (20.5, Maneuver::SlightRight),
+            (-45.0, Maneuver::SlightLeft),
+            (46.0, Maneuver::TurnRight),
+            (-90.0, Maneuver::TurnLeft),
+            (120.0, Maneuver::TurnRight),
+            (121.0, Maneuver::SharpRight),
+            (-165.0, Maneuver::SharpLeft),
+            (166.0, Maneuver::UTurn),
+            (-180.0, Maneuver::UTurn),
+        ];
+        for (delta_deg, maneuver) in table {
+            assert_eq!(Maneuver::from_bearing_change(delta_deg), maneuver, "{}", delta_deg);
+        }
+
+        // Across north the change is the short way round
+        assert_eq!(bearing_change(350.0, 10.0), 20.0);
+        assert_eq!(bearing_change(10.0, 350.0), -20.0);
+        assert_eq!(bearing_change(90.0, 0.0), -90.0);
+    }
+
+    // Main Street runs east from node 1 through node 2 to node 3. The B 12, named by its ref
+    // only, leaves it northwards at node 2 and bends north-east at node 4
+    fn t_junction() -> RoutingGraph {
+        let way = |id: i64, node_ids: Vec<i64>, street: (&str, &str)| {
+            let tags = vec![Tag::new("highway".to_string(), "residential".to_string()), Tag::new(street.0.to_string(), street.1.to_string())];
+            Way::new(id, 1, String::new(), 0, 0, String::new(), node_ids, tags)
+        };
+        let ways = vec![way(1, vec![1, 2, 3], ("name", "Main Street")), way(2, vec![2, 4, 5], ("ref", "B 12"))];
+        let coordinates = HashMap::from([
+            (1, (55.0, 12.0)),
+            (2, (55.0, 12.001)),
+            (3, (55.0, 12.002)),
+            (4, (55.001, 12.001)),
+            (5, (55.0015, 12.0015)),
+        ]);
+        RoutingGraph::from_ways(&ways, coordinates, &[], RoutingProfile::Car)
+    }
+
+    #[test]
+    fn turning_off_at_a_t_junction_is_announced_and_the_bend_after_it_is_not() {
+        let graph = t_junction();
+        let path = graph.shortest_path(1, 5).unwrap();
+        assert_eq!(path, [1, 2, 4, 5]);
+
+        let instructions = graph.generate_instructions(&path).unwrap();
+        let steps: Vec<(Maneuver, Option<&str>, usize)> = instructions.iter()
+            .map(|instruction| (instruction.maneuver, instruction.street.as_deref(), instruction.path_index))
+            .collect();
+        assert_eq!(steps, [
+            (Maneuver::Depart, Some("Main Street"), 0),
+            (Maneuver::TurnLeft, Some("B 12"), 1),
+            (Maneuver::Arrive, None, 3),
+        ]);
+
+        // Every instruction covers the way up to the next
+        let summary = graph.route_summary(&path).unwrap();
+        assert!((instructions[0].distance_m - graph.route_summary(&[1, 2]).unwrap().distance_m).abs() < 1e-9);
+        assert!((instructions.iter().map(|instruction| instruction.distance_m).sum::<f64>() - summary.distance_m).abs() < 1e-9);
+        assert_eq!(instructions[1].to_string(), format!("Turn left onto B 12, then {}", format_distance(instructions[1].distance_m)));
+    }
+
+    #[test]
+    fn going_straight_through_the_junction_needs_no_instruction() {
+        let graph = t_junction();
+        let instructions = graph.generate_instructions(&[3, 2, 1]).unwrap();
+        let maneuvers: Vec<Maneuver> = instructions.iter().map(|instruction| instruction.maneuver).collect();
+        assert_eq!(maneuvers, [Maneuver::Depart, Maneuver::Arrive]);
+
+        // Coming south down the B 12, the east end of Main Street is to the left
+        let instructions = graph.generate_instructions(&[5, 4, 2, 3]).unwrap();
+        assert_eq!((instructions[1].maneuver, instructions[1].path_index), (Maneuver::TurnLeft, 2));
+        assert!(graph.generate_instructions(&[1, 4]).is_none());
+    }
+}
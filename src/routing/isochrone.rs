@@ -0,0 +1,367 @@
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use sqlx::SqlitePool;
+
+use crate::cancel::{CancellationToken, OperationError};
+use crate::geo::{BBox, METERS_PER_DEGREE};
+use crate::metrics;
+
+use super::{load_routing_graph, snap_to_road, QueueEntry, RoutingGraph, RoutingProfile, DEFAULT_SNAP_DISTANCE_M};
+
+/// The size of the grid cells an isochrone is filled in, in meters, unless told otherwise.
+pub const DEFAULT_ISOCHRONE_CELL_M: f64 = 50.0;
+
+impl RoutingGraph {
+    /// Finds the travel time to every node reachable from `start` within a time limit, with
+    /// Dijkstra's algorithm stopped at the limit. Turn restrictions are honored like in
+    /// `shortest_path`.
+    ///
+    /// ## Returns
+    /// * The travel time in seconds of every node reached, `start` included at 0. Empty if
+    ///   `start` is not in the graph.
+    pub fn travel_times_within(&self, start: i64, max_seconds: f64) -> HashMap<i64, f64> {
+        let mut times = HashMap::new();
+        if !self.coordinates.contains_key(&start) {
+            return times;
+        }
+        times.insert(start, 0.0);
+
+        let mut best_cost: HashMap<usize, f64> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        for &edge in self.outgoing_edges(start) {
+            let cost = self.edges[edge].cost;
+            if cost <= max_seconds {
+                best_cost.insert(edge, cost);
+                queue.push(QueueEntry { cost, edge });
+            }
+        }
+
+        while let Some(QueueEntry { cost, edge }) = queue.pop() {
+            if cost > best_cost.get(&edge).copied().unwrap_or(f64::INFINITY) {
+                continue;
+            }
+
+            // Entries are popped cheapest first, so the first time a node is reached is the fastest
+            let incoming = &self.edges[edge];
+            times.entry(incoming.to).or_insert(cost);
+
+            for &next in self.outgoing_edges(incoming.to) {
+                let outgoing = &self.edges[next];
+                if !self.is_turn_allowed(incoming, outgoing) {
+                    continue;
+                }
+
+                let next_cost = cost + outgoing.cost;
+                if next_cost <= max_seconds && next_cost < best_cost.get(&next).copied().unwrap_or(f64::INFINITY) {
+                    best_cost.insert(next, next_cost);
+                    queue.push(QueueEntry { cost: next_cost, edge: next });
+                }
+            }
+        }
+
+        times
+    }
+}
+
+/// The area reachable within a time limit, as the cells of a grid the reached roads pass
+/// through. Enclosed gaps are filled, so the area has no holes.
+///
+/// # Fields
+/// * `origin` - The `(lat, lon)` of the south west corner of the grid.
+/// * `cell` - The height and width of a cell in degrees.
+/// * `columns`, `rows` - The size of the grid. The cells along its edge are never filled,
+///   so every filled cell has a neighbor on every side.
+/// * `filled` - Whether each cell is reached, row by row from the south.
+#[derive(Debug, Clone)]
+pub struct ReachGrid {
+    origin: (f64, f64),
+    cell: (f64, f64),
+    columns: usize,
+    rows: usize,
+    filled: Vec<bool>,
+}
+
+impl ReachGrid {
+    /// Fills the cells of a grid the roads reached within `max_seconds` pass through. An edge
+    /// leaving a reached node is followed as far as the time left allows.
+    ///
+    /// ## Arguments
+    /// * `times` - The travel times to the reached nodes, see `travel_times_within`.
+    /// * `cell_m` - The size of the cells in meters.
+    ///
+    /// ## Returns
+    /// * The grid, or `None` if no node was reached.
+    pub fn from_travel_times(graph: &RoutingGraph, times: &HashMap<i64, f64>, max_seconds: f64, cell_m: f64) -> Option<Self> {
+        // The reached part of every edge leaving a reached node, as its ends
+        let mut segments = Vec::new();
+        for (&node, &time) in times {
+            let &from = graph.coordinates.get(&node)?;
+            segments.push((from, from, 0.0));
+
+            for &edge in graph.outgoing_edges(node) {
+                let edge = &graph.edges[edge];
+                let Some(&to) = graph.coordinates.get(&edge.to) else {
+                    continue;
+                };
+                let fraction = if edge.cost > 0.0 { ((max_seconds - time) / edge.cost).clamp(0.0, 1.0) } else { 1.0 };
+                let end = (from.0 + (to.0 - from.0) * fraction, from.1 + (to.1 - from.1) * fraction);
+                segments.push((from, end, edge.distance_m * fraction));
+            }
+        }
+
+        let (min_lat, min_lon, max_lat, max_lon) = segments.iter()
+            .flat_map(|&(from, to, _)| [from, to])
+            .fold((f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY), |(min_lat, min_lon, max_lat, max_lon), (lat, lon)| {
+                (min_lat.min(lat), min_lon.min(lon), max_lat.max(lat), max_lon.max(lon))
+            });
+        if !min_lat.is_finite() {
+            return None;
+        }
+
+        // Cells are square on the ground, narrower in degrees of longitude away from the equator
+        let cell_m = cell_m.max(1.0);
+        let cell_lat = cell_m / METERS_PER_DEGREE;
+        let cell_lon = cell_m / (METERS_PER_DEGREE * ((min_lat + max_lat) / 2.0).to_radians().cos().max(0.01));
+        let mut grid = ReachGrid {
+            origin: (min_lat - cell_lat, min_lon - cell_lon),
+            cell: (cell_lat, cell_lon),
+            columns: ((max_lon - min_lon) / cell_lon) as usize + 3,
+            rows: ((max_lat - min_lat) / cell_lat) as usize + 3,
+            filled: Vec::new(),
+        };
+        grid.filled = vec![false; grid.columns * grid.rows];
+
+        // Points along a segment half a cell apart, so it fills every cell it passes through
+        for (from, to, length_m) in segments {
+            let steps = (length_m / (cell_m / 2.0)).ceil() as usize;
+            let mut previous: Option<(usize, usize)> = None;
+            for step in 0..=steps {
+                let t = if steps == 0 { 0.0 } else { step as f64 / steps as f64 };
+                let Some((column, row)) = grid.cell_at((from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t)) else {
+                    continue;
+                };
+
+                // A road crossing a corner fills a cell beside the corner too, so the cells
+                // of a diagonal road share sides and are outlined as one
+                if let Some((previous_column, previous_row)) = previous {
+                    if column != previous_column && row != previous_row {
+                        grid.fill(column, previous_row);
+                    }
+                }
+                grid.fill(column, row);
+                previous = Some((column, row));
+            }
+        }
+        grid.fill_holes();
+
+        Some(grid)
+    }
+
+    /// The `(column, row)` of the cell a point lies in, or `None` outside of the grid.
+    fn cell_at(&self, (lat, lon): (f64, f64)) -> Option<(usize, usize)> {
+        let row = ((lat - self.origin.0) / self.cell.0) as usize;
+        let column = ((lon - self.origin.1) / self.cell.1) as usize;
+        (row < self.rows && column < self.columns).then_some((column, row))
+    }
+
+    fn fill(&mut self, column: usize, row: usize) {
+        self.filled[row * self.columns + column] = true;
+    }
+
+    fn is_filled(&self, column: isize, row: isize) -> bool {
+        column >= 0 && row >= 0 && (column as usize) < self.columns && (row as usize) < self.rows
+            && self.filled[row as usize * self.columns + column as usize]
+    }
+
+    /// Fills the cells no path along the sides of cells leads to from the edge of the grid.
+    fn fill_holes(&mut self) {
+        let mut outside = vec![false; self.filled.len()];
+        let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+        queue.push_back((0, 0));
+        outside[0] = true;
+
+        while let Some((column, row)) = queue.pop_front() {
+            let neighbors = [
+                (column.wrapping_sub(1), row), (column + 1, row),
+                (column, row.wrapping_sub(1)), (column, row + 1),
+            ];
+            for (column, row) in neighbors {
+                if column >= self.columns || row >= self.rows {
+                    continue;
+                }
+                let index = row * self.columns + column;
+                if !outside[index] && !self.filled[index] {
+                    outside[index] = true;
+                    queue.push_back((column, row));
+                }
+            }
+        }
+
+        for (filled, outside) in self.filled.iter_mut().zip(outside) {
+            *filled = !outside;
+        }
+    }
+
+    /// The `(lat, lon)` of a corner of the cells.
+    fn corner(&self, (column, row): (isize, isize)) -> (f64, f64) {
+        (self.origin.0 + row as f64 * self.cell.0, self.origin.1 + column as f64 * self.cell.1)
+    }
+
+    /// The outlines of the reached area, one closed ring counter clockwise around every part
+    /// of it. Parts touching at a corner only are outlined separately.
+    pub fn outlines(&self) -> Vec<Vec<(f64, f64)>> {
+        // The sides between a filled and an empty cell, directed so the filled cell is on their left
+        let mut sides: HashMap<(isize, isize), Vec<(isize, isize)>> = HashMap::new();
+        for row in 0..self.rows as isize {
+            for column in 0..self.columns as isize {
+                if !self.is_filled(column, row) {
+                    continue;
+                }
+                let mut add = |from: (isize, isize), to: (isize, isize)| sides.entry(from).or_default().push(to);
+                if !self.is_filled(column, row - 1) {
+                    add((column, row), (column + 1, row));
+                }
+                if !self.is_filled(column + 1, row) {
+                    add((column + 1, row), (column + 1, row + 1));
+                }
+                if !self.is_filled(column, row + 1) {
+                    add((column + 1, row + 1), (column, row + 1));
+                }
+                if !self.is_filled(column - 1, row) {
+                    add((column, row + 1), (column, row));
+                }
+            }
+        }
+
+        let mut starts: Vec<(isize, isize)> = sides.keys().copied().collect();
+        starts.sort_unstable();
+
+        let mut outlines = Vec::new();
+        for start in starts {
+            while let Some(first) = sides.get_mut(&start).and_then(Vec::pop) {
+                let mut corners = vec![start];
+                let (mut at, mut direction) = (first, (first.0 - start.0, first.1 - start.1));
+
+                while at != start {
+                    let Some(next) = take_leftmost_side(&mut sides, at, direction) else {
+                        break;
+                    };
+                    let next_direction = (next.0 - at.0, next.1 - at.1);
+                    // Only the corners where the outline turns are kept
+                    if next_direction != direction {
+                        corners.push(at);
+                    }
+                    (at, direction) = (next, next_direction);
+                }
+
+                let mut ring: Vec<(f64, f64)> = corners.into_iter().map(|corner| self.corner(corner)).collect();
+                ring.push(ring[0]);
+                outlines.push(ring);
+            }
+        }
+
+        outlines
+    }
+
+    /// The reached area as rectangles, a run of filled cells of a row each, e.g. to draw it
+    /// without triangulating the outlines.
+    ///
+    /// ## Returns
+    /// * The box of every rectangle.
+    pub fn filled_runs(&self) -> Vec<BBox> {
+        let mut runs = Vec::new();
+        for row in 0..self.rows as isize {
+            let mut start = None;
+            for column in 0..=self.columns as isize {
+                match (start, self.is_filled(column, row)) {
+                    (None, true) => start = Some(column),
+                    (Some(first), false) => {
+                        let (min_lat, min_lon) = self.corner((first, row));
+                        let (max_lat, max_lon) = self.corner((column, row + 1));
+                        runs.push(BBox { min_lat, max_lat, min_lon, max_lon });
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        runs
+    }
+}
+
+/// Takes the side leaving `at` that turns furthest left from `direction`, so parts touching
+/// at a corner are outlined separately.
+fn take_leftmost_side(sides: &mut HashMap<(isize, isize), Vec<(isize, isize)>>, at: (isize, isize), (dx, dy): (isize, isize)) -> Option<(isize, isize)> {
+    let leaving = sides.get_mut(&at)?;
+    let turns = [(-dy, dx), (dx, dy), (dy, -dx)];
+    let index = turns.iter()
+        .find_map(|&(tx, ty)| leaving.iter().position(|&to| (to.0 - at.0, to.1 - at.1) == (tx, ty)))?;
+    Some(leaving.swap_remove(index))
+}
+
+/// Snaps a coordinate to the nearest road and computes the area reachable from there within
+/// a time limit, starting at the node of the graph closest to the snapped point.
+///
+/// `cancel` is looked at while the graph is loaded and before the search, which runs to
+/// the end once started.
+///
+/// ## Returns
+/// * The reached area, or `None` if the coordinate is further than `DEFAULT_SNAP_DISTANCE_M`
+///   from a road.
+pub async fn isochrone_around(sqlite_pool: &SqlitePool, (lat, lon): (f64, f64), max_seconds: f64, profile: RoutingProfile, cell_m: f64, cancel: &CancellationToken) -> Result<Option<ReachGrid>, OperationError> {
+    let _timer = metrics::ISOCHRONE_SECONDS.start_timer();
+    let Some(snap) = snap_to_road(sqlite_pool, lat, lon, DEFAULT_SNAP_DISTANCE_M).await? else {
+        return Ok(None);
+    };
+
+    let graph = load_routing_graph(sqlite_pool, profile, cancel).await?;
+    cancel.check()?;
+    let Some(start) = graph.nearest_node(snap.lat, snap.lon) else {
+        return Ok(None);
+    };
+
+    let times = graph.travel_times_within(start, max_seconds);
+    Ok(ReachGrid::from_travel_times(&graph, &times, max_seconds, cell_m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::{Tag, Way};
+
+    // A residential street running north from node 1 over nodes 2 and 3, about 100 m apart
+    fn street() -> RoutingGraph {
+        let tags = vec![Tag::new("highway".to_string(), "residential".to_string())];
+        let way = Way::new(1, 1, String::new(), 0, 0, String::new(), vec![1, 2, 3], tags);
+        let coordinates = HashMap::from([(1, (55.0, 12.0)), (2, (55.0009, 12.0)), (3, (55.0018, 12.0))]);
+        RoutingGraph::from_ways(&[way], coordinates, &[], RoutingProfile::Car)
+    }
+
+    #[test]
+    fn travel_times_stop_at_the_limit() {
+        let graph = street();
+        let all = graph.travel_times_within(1, f64::INFINITY);
+        assert_eq!(all[&1], 0.0);
+        assert!(0.0 < all[&2] && all[&2] < all[&3]);
+
+        let some = graph.travel_times_within(1, (all[&2] + all[&3]) / 2.0);
+        assert_eq!(some.len(), 2);
+        assert!(!some.contains_key(&3));
+        assert!(graph.travel_times_within(99, f64::INFINITY).is_empty());
+    }
+
+    #[test]
+    fn the_reached_street_is_outlined_once() {
+        let graph = street();
+        let times = graph.travel_times_within(1, f64::INFINITY);
+        let grid = ReachGrid::from_travel_times(&graph, &times, f64::INFINITY, DEFAULT_ISOCHRONE_CELL_M).unwrap();
+        let outlines = grid.outlines();
+        assert_eq!(outlines.len(), 1);
+
+        // The outline encloses the whole street
+        let outline = &outlines[0];
+        let (min_lat, max_lat) = outline.iter().fold((f64::MAX, f64::MIN), |(min, max), &(lat, _)| (min.min(lat), max.max(lat)));
+        assert!(min_lat <= 55.0 && 55.0018 <= max_lat);
+    }
+}
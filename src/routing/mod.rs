@@ -0,0 +1,724 @@
+pub mod profile;
+pub mod instructions;
+pub mod isochrone;
+pub mod alternatives;
+
+pub use profile::*;
+pub use instructions::*;
+pub use isochrone::*;
+pub use alternatives::*;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+
+use sqlx::SqlitePool;
+
+use crate::{
+    cancel::{CancellationToken, OperationError},
+    database::{fetch_highway_node_coordinates, fetch_highway_shapes_in_bbox, fetch_highway_ways, fetch_restriction_relations},
+    geo::{bbox_around, closest_point_on_polyline, format_distance, haversine_distance},
+    metrics,
+    osm_entities::{Relation, RenderableWay, SimpleNode, Tag, Way},
+    utils::MapsType
+};
+
+/// A directed connection between two consecutive nodes of a way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: i64,
+    pub to: i64,
+    pub way_id: i64,
+    /// Length of the edge in meters.
+    pub distance_m: f64,
+    /// Travel time along the edge in seconds, for the profile the graph was built for.
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestrictionKind {
+    /// `no_*` restrictions forbid turning from the `from` way onto the `to` way.
+    No,
+    /// `only_*` restrictions forbid turning from the `from` way onto anything but the `to` way.
+    Only,
+}
+
+/// A turn restriction at a via node, parsed from a `type=restriction` relation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurnRestriction {
+    pub kind: RestrictionKind,
+    pub from_way: i64,
+    pub via_node: i64,
+    pub to_way: i64,
+}
+
+impl TurnRestriction {
+    /// Parses a restriction relation with `from`, `via` and `to` members.
+    ///
+    /// ## Returns
+    /// * The restriction, or `None` if the relation is not a usable restriction. Restrictions
+    ///   using a way as via member are not supported and are skipped as well.
+    pub fn from_relation(relation: &Relation) -> Option<Self> {
+        let restriction = tag_value(&relation.tags, "restriction")?;
+        let kind = if restriction.starts_with("no_") {
+            RestrictionKind::No
+        } else if restriction.starts_with("only_") {
+            RestrictionKind::Only
+        } else {
+            return None;
+        };
+
+        let member = |role: &str, maps_type: MapsType| {
+            relation.members.iter()
+                .find(|member| member.role == role && member.maps_type == maps_type)
+                .map(|member| member.ref_id)
+        };
+
+        Some(TurnRestriction {
+            kind,
+            from_way: member("from", MapsType::Way)?,
+            via_node: member("via", MapsType::Node)?,
+            to_way: member("to", MapsType::Way)?,
+        })
+    }
+}
+
+fn tag_value<'a>(tags: &'a [Tag], key: &str) -> Option<&'a str> {
+    tags.iter().find(|tag| tag.key == key).map(|tag| tag.value.as_str())
+}
+
+/// Entry in the Dijkstra priority queue, ordered so the cheapest entry is popped first.
+struct QueueEntry {
+    cost: f64,
+    edge: usize,
+}
+
+impl PartialEq for QueueEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for QueueEntry {}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, as BinaryHeap is a max-heap
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+/// A directed graph of the road network, built from the ways tagged with `highway` that the
+/// profile it was built for can use. Edges are weighted by travel time with that profile,
+/// so a graph serves one mode of transport.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingGraph {
+    /// The `(lat, lon)` of every node in the graph.
+    pub coordinates: HashMap<i64, (f64, f64)>,
+    pub edges: Vec<Edge>,
+    /// The indices of the outgoing edges of every node.
+    adjacency: HashMap<i64, Vec<usize>>,
+    /// The turn restrictions keyed by their `(from_way, via_node)`.
+    restrictions: HashMap<(i64, i64), Vec<TurnRestriction>>,
+    /// The number of restriction relations that could not be used, e.g. because of a via way.
+    pub ignored_restrictions: usize,
+    /// The `highway` value of every way in the graph.
+    highway_classes: HashMap<i64, String>,
+    /// The `name` of every way in the graph that has one, or else its `ref`.
+    way_names: HashMap<i64, String>,
+    /// The ways tagged with `junction=roundabout`.
+    roundabout_ways: HashSet<i64>,
+}
+
+impl RoutingGraph {
+    /// Builds the graph from ways and the coordinates of their nodes.
+    ///
+    /// ## Arguments
+    /// * `ways` - The ways tagged with `highway`. The profile decides which of them are used,
+    ///   in which directions and how fast.
+    /// * `coordinates` - The `(lat, lon)` of the nodes. Segments with a node missing here are left out.
+    /// * `restriction_relations` - The `type=restriction` relations to apply while routing.
+    /// * `profile` - The mode of transport to route for.
+    pub fn from_ways(ways: &[Way], coordinates: HashMap<i64, (f64, f64)>, restriction_relations: &[Relation], profile: RoutingProfile) -> Self {
+        let mut graph = RoutingGraph {
+            coordinates,
+            ..Default::default()
+        };
+
+        for way in ways {
+            let Some(speed_kmh) = profile.way_speed_kmh(&way.tags) else {
+                continue;
+            };
+            let (forward, backward) = profile.directions(&way.tags);
+            let meters_per_second = speed_kmh / 3.6;
+
+            if let Some(highway) = tag_value(&way.tags, "highway") {
+                graph.highway_classes.insert(way.id, highway.to_string());
+            }
+            if let Some(name) = tag_value(&way.tags, "name").or_else(|| tag_value(&way.tags, "ref")) {
+                graph.way_names.insert(way.id, name.to_string());
+            }
+            if tag_value(&way.tags, "junction") == Some("roundabout") {
+                graph.roundabout_ways.insert(way.id);
+            }
+
+            for pair in way.node_refs.windows(2) {
+                let (from, to) = (pair[0], pair[1]);
+                let (Some(&a), Some(&b)) = (graph.coordinates.get(&from), graph.coordinates.get(&to)) else {
+                    continue;
+                };
+                let distance_m = haversine_distance(a, b);
+                let cost = distance_m / meters_per_second;
+
+                if forward {
+                    graph.add_edge(Edge { from, to, way_id: way.id, distance_m, cost });
+                }
+                if backward {
+                    graph.add_edge(Edge { from: to, to: from, way_id: way.id, distance_m, cost });
+                }
+            }
+        }
+
+        for relation in restriction_relations {
+            match TurnRestriction::from_relation(relation) {
+                Some(restriction) => graph.restrictions
+                    .entry((restriction.from_way, restriction.via_node))
+                    .or_default()
+                    .push(restriction),
+                None => graph.ignored_restrictions += 1,
+            }
+        }
+
+        graph
+    }
+
+    fn add_edge(&mut self, edge: Edge) {
+        self.adjacency.entry(edge.from).or_default().push(self.edges.len());
+        self.edges.push(edge);
+    }
+
+    /// Returns the indices of the edges leaving `node`.
+    pub fn outgoing_edges(&self, node: i64) -> &[usize] {
+        self.adjacency.get(&node).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Checks whether the turn from `incoming` onto `outgoing` at their shared node is allowed.
+    fn is_turn_allowed(&self, incoming: &Edge, outgoing: &Edge) -> bool {
+        // Turning around on the same way is only allowed at dead ends
+        if outgoing.to == incoming.from && outgoing.way_id == incoming.way_id && self.outgoing_edges(incoming.to).len() > 1 {
+            return false;
+        }
+
+        let Some(restrictions) = self.restrictions.get(&(incoming.way_id, incoming.to)) else {
+            return true;
+        };
+
+        restrictions.iter().all(|restriction| match restriction.kind {
+            RestrictionKind::No => restriction.to_way != outgoing.way_id,
+            RestrictionKind::Only => restriction.to_way == outgoing.way_id,
+        })
+    }
+
+    /// Finds the fastest path between two nodes using Dijkstra's algorithm.
+    ///
+    /// The search state is the edge a node was reached by rather than the node itself,
+    /// so turn restrictions that depend on the incoming way can be honored.
+    ///
+    /// ## Returns
+    /// * The node ids along the path from `start` to `goal`, or `None` if `goal` cannot be reached.
+    pub fn shortest_path(&self, start: i64, goal: i64) -> Option<Vec<i64>> {
+        self.shortest_path_with(start, goal, |edge| self.edges[edge].cost)
+    }
+
+    /// Finds the cheapest path between two nodes like `shortest_path`, with the cost of every
+    /// edge given by `edge_cost`, e.g. to make the edges of a route found before more expensive.
+    fn shortest_path_with(&self, start: i64, goal: i64, edge_cost: impl Fn(usize) -> f64) -> Option<Vec<i64>> {
+        if start == goal {
+            return self.coordinates.contains_key(&start).then(|| vec![start]);
+        }
+
+        let mut best_cost: HashMap<usize, f64> = HashMap::new();
+        let mut previous: HashMap<usize, Option<usize>> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+
+        for &edge in self.outgoing_edges(start) {
+            let cost = edge_cost(edge);
+            best_cost.insert(edge, cost);
+            previous.insert(edge, None);
+            queue.push(QueueEntry { cost, edge });
+        }
+
+        while let Some(QueueEntry { cost, edge }) = queue.pop() {
+            if cost > best_cost.get(&edge).copied().unwrap_or(f64::INFINITY) {
+                continue;
+            }
+
+            let incoming = &self.edges[edge];
+            if incoming.to == goal {
+                return Some(self.reconstruct_path(edge, &previous));
+            }
+
+            for &next in self.outgoing_edges(incoming.to) {
+                let outgoing = &self.edges[next];
+                if !self.is_turn_allowed(incoming, outgoing) {
+                    continue;
+                }
+
+                let next_cost = cost + edge_cost(next);
+                if next_cost < best_cost.get(&next).copied().unwrap_or(f64::INFINITY) {
+                    best_cost.insert(next, next_cost);
+                    previous.insert(next, Some(edge));
+                    queue.push(QueueEntry { cost: next_cost, edge: next });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The fastest edge from one node to another, the one a path between them is taken to use.
+    fn fastest_edge(&self, from: i64, to: i64) -> Option<&Edge> {
+        self.fastest_edge_index(from, to).map(|edge| &self.edges[edge])
+    }
+
+    /// The index of the edge `fastest_edge` returns.
+    fn fastest_edge_index(&self, from: i64, to: i64) -> Option<usize> {
+        self.outgoing_edges(from).iter()
+            .copied()
+            .filter(|&edge| self.edges[edge].to == to)
+            .min_by(|&a, &b| self.edges[a].cost.total_cmp(&self.edges[b].cost))
+    }
+
+    /// Sums up the distance and travel time of a path, e.g. one found by `shortest_path`.
+    ///
+    /// Where two nodes are connected by more than one edge, the fastest one is counted.
+    ///
+    /// ## Returns
+    /// * The summary, or `None` if two consecutive nodes of the path are not connected.
+    pub fn route_summary(&self, path: &[i64]) -> Option<RouteSummary> {
+        let mut summary = RouteSummary::default();
+
+        for pair in path.windows(2) {
+            let edge = self.fastest_edge(pair[0], pair[1])?;
+
+            summary.distance_m += edge.distance_m;
+            summary.time_s += edge.cost;
+
+            let highway = self.highway_classes.get(&edge.way_id).map(String::as_str).unwrap_or("unknown");
+            match summary.by_highway.iter_mut().find(|(class, _, _)| class == highway) {
+                Some((_, distance_m, time_s)) => {
+                    *distance_m += edge.distance_m;
+                    *time_s += edge.cost;
+                }
+                None => summary.by_highway.push((highway.to_string(), edge.distance_m, edge.cost)),
+            }
+        }
+
+        Some(summary)
+    }
+
+    /// The nodes of a path with their coordinates, e.g. to draw or export it.
+    pub fn path_nodes(&self, path: &[i64]) -> Vec<SimpleNode> {
+        path.iter()
+            .filter_map(|&id| self.coordinates.get(&id).map(|&(lat, lon)| SimpleNode { id: Some(id), lat, lon }))
+            .collect()
+    }
+
+    /// Finds the node closest to a coordinate among the nodes a route can start from.
+    pub fn nearest_node(&self, lat: f64, lon: f64) -> Option<i64> {
+        self.coordinates.iter()
+            .filter(|(id, _)| self.adjacency.contains_key(id))
+            .map(|(&id, &coordinate)| (id, haversine_distance((lat, lon), coordinate)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| id)
+    }
+
+    fn reconstruct_path(&self, last_edge: usize, previous: &HashMap<usize, Option<usize>>) -> Vec<i64> {
+        let mut path = vec![self.edges[last_edge].to];
+        let mut current = Some(last_edge);
+
+        while let Some(edge) = current {
+            path.push(self.edges[edge].from);
+            current = previous.get(&edge).copied().flatten();
+        }
+
+        path.reverse();
+        path
+    }
+}
+
+/// The length and travel time of a route.
+///
+/// # Fields
+/// * `distance_m` - The length in meters.
+/// * `time_s` - The estimated travel time in seconds.
+/// * `by_highway` - The length and travel time per `highway` class, in the order the route first uses them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteSummary {
+    pub distance_m: f64,
+    pub time_s: f64,
+    pub by_highway: Vec<(String, f64, f64)>,
+}
+
+impl fmt::Display for RouteSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} in {:.0} min", format_distance(self.distance_m), self.time_s / 60.0)?;
+        for (highway, distance_m, time_s) in &self.by_highway {
+            writeln!(f, "  {}: {} in {:.0} min", highway, format_distance(*distance_m), time_s / 60.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Loads the routing graph of every way tagged with `highway` from the database. Loading
+/// stops early once `cancel` is cancelled.
+pub async fn load_routing_graph(sqlite_pool: &SqlitePool, profile: RoutingProfile, cancel: &CancellationToken) -> Result<RoutingGraph, OperationError> {
+    let ways = fetch_highway_ways(sqlite_pool, cancel).await?;
+    let coordinates = fetch_highway_node_coordinates(sqlite_pool, cancel).await?;
+    let restrictions = fetch_restriction_relations(sqlite_pool, cancel).await?;
+
+    Ok(RoutingGraph::from_ways(&ways, coordinates, &restrictions, profile))
+}
+
+/// How far from a coordinate `snap_to_road` looks for roads unless told otherwise.
+pub const DEFAULT_SNAP_DISTANCE_M: f64 = 50.0;
+
+/// The point of a road closest to a coordinate, found by `snap_to_road`.
+///
+/// # Fields
+/// * `way_id` - The road.
+/// * `segment_index` - The segment of the road the point lies on, from its node at this index to the next one.
+/// * `lat`, `lon` - The point on the road.
+/// * `distance_m` - The distance in meters from the coordinate to the point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapResult {
+    pub way_id: i64,
+    pub segment_index: usize,
+    pub lat: f64,
+    pub lon: f64,
+    pub distance_m: f64,
+}
+
+impl fmt::Display for SnapResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "way {} segment {} at {:.7},{:.7}, {:.1} m away", self.way_id, self.segment_index, self.lat, self.lon, self.distance_m)
+    }
+}
+
+/// Finds the point closest to a coordinate on any of the given ways.
+///
+/// ## Returns
+/// * The closest point within `max_dist_m`, or `None` if no way comes that close. Of equally
+///   close ways the first wins.
+pub fn snap_to_ways(ways: &[RenderableWay], lat: f64, lon: f64, max_dist_m: f64) -> Option<SnapResult> {
+    ways.iter()
+        .filter_map(|way| {
+            let (segment_index, (lat, lon), distance_m) = closest_point_on_polyline((lat, lon), &way.coords)?;
+            Some(SnapResult { way_id: way.id, segment_index, lat, lon, distance_m })
+        })
+        .filter(|snap| snap.distance_m <= max_dist_m)
+        .fold(None, |closest: Option<SnapResult>, candidate| match closest {
+            Some(closest) if closest.distance_m <= candidate.distance_m => Some(closest),
+            _ => Some(candidate),
+        })
+}
+
+/// Snaps a coordinate to the nearest point of the nearest road, e.g. to start a route at a clicked position.
+///
+/// The roads are the ways tagged with `highway` whose bounding box in `way_geom` comes
+/// within `max_dist_m` of the coordinate.
+///
+/// ## Returns
+/// * The point on the road, or `None` if no road is within `max_dist_m`.
+pub async fn snap_to_road(sqlite_pool: &SqlitePool, lat: f64, lon: f64, max_dist_m: f64) -> Result<Option<SnapResult>, sqlx::Error> {
+    let _timer = metrics::SNAP_SECONDS.start_timer();
+    let roads = fetch_highway_shapes_in_bbox(sqlite_pool, &bbox_around((lat, lon), max_dist_m)).await?;
+
+    Ok(snap_to_ways(&roads, lat, lon, max_dist_m))
+}
+
+/// A route found by `route_between`.
+///
+/// # Fields
+/// * `nodes` - The nodes along the route, in travel order.
+/// * `summary` - The length and travel time of the route.
+/// * `instructions` - The turn-by-turn instructions, referring to `nodes` by index.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub nodes: Vec<SimpleNode>,
+    pub summary: RouteSummary,
+    pub instructions: Vec<Instruction>,
+}
+
+/// Snaps two coordinates to the nearest roads and finds the fastest route between them.
+///
+/// The route starts and ends at the nodes of the graph closest to the snapped points.
+///
+/// ## Returns
+/// * The route, or `None` if either coordinate is further than `DEFAULT_SNAP_DISTANCE_M`
+///   from a road, or no route connects them.
+pub async fn route_between(sqlite_pool: &SqlitePool, from: (f64, f64), to: (f64, f64), profile: RoutingProfile) -> Result<Option<Route>, OperationError> {
+    let options = AlternativeOptions { count: 0, ..AlternativeOptions::default() };
+    let routes = route_alternatives_between(sqlite_pool, from, to, profile, &options, &CancellationToken::default()).await?;
+    Ok(routes.into_iter().next())
+}
+
+/// Snaps two coordinates to the nearest roads and finds the fastest route between them and
+/// alternatives to it, see `RoutingGraph::alternative_paths`.
+///
+/// `cancel` is looked at while the graph is loaded and before the search, which runs to
+/// the end once started.
+///
+/// ## Returns
+/// * The fastest route followed by the alternatives, or nothing if either coordinate is
+///   further than `DEFAULT_SNAP_DISTANCE_M` from a road, or no route connects them.
+pub async fn route_alternatives_between(sqlite_pool: &SqlitePool, from: (f64, f64), to: (f64, f64), profile: RoutingProfile, options: &AlternativeOptions, cancel: &CancellationToken) -> Result<Vec<Route>, OperationError> {
+    let _timer = metrics::ROUTE_SECONDS.start_timer();
+    let from = snap_to_road(sqlite_pool, from.0, from.1, DEFAULT_SNAP_DISTANCE_M).await?;
+    let to = snap_to_road(sqlite_pool, to.0, to.1, DEFAULT_SNAP_DISTANCE_M).await?;
+    let (Some(from), Some(to)) = (from, to) else {
+        return Ok(Vec::new());
+    };
+
+    let graph = load_routing_graph(sqlite_pool, profile, cancel).await?;
+    cancel.check()?;
+    let (Some(start), Some(goal)) = (graph.nearest_node(from.lat, from.lon), graph.nearest_node(to.lat, to.lon)) else {
+        return Ok(Vec::new());
+    };
+
+    let routes = graph.alternative_paths(start, goal, options).into_iter()
+        .filter_map(|path| {
+            Some(Route {
+                nodes: graph.path_nodes(&path),
+                summary: graph.route_summary(&path)?,
+                instructions: graph.generate_instructions(&path)?,
+            })
+        })
+        .collect();
+    Ok(routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::Member;
+
+    // A one-way residential street from node 1 to node 2, about 100 m long
+    fn one_way_street() -> (Vec<Way>, HashMap<i64, (f64, f64)>) {
+        let tags = vec![
+            Tag::new("highway".to_string(), "residential".to_string()),
+            Tag::new("oneway".to_string(), "yes".to_string()),
+        ];
+        let way = Way::new(1, 1, String::new(), 0, 0, String::new(), vec![1, 2], tags);
+        (vec![way], HashMap::from([(1, (55.0, 12.0)), (2, (55.0009, 12.0))]))
+    }
+
+    #[test]
+    fn edges_are_costed_for_the_profile_the_graph_is_built_for() {
+        let (ways, coordinates) = one_way_street();
+        let car = RoutingGraph::from_ways(&ways, coordinates.clone(), &[], RoutingProfile::Car);
+        let foot = RoutingGraph::from_ways(&ways, coordinates, &[], RoutingProfile::Foot);
+
+        // Cars follow the oneway, pedestrians walk both ways and take longer
+        assert_eq!(car.edges.len(), 1);
+        assert_eq!(foot.edges.len(), 2);
+        let car_time = car.route_summary(&[1, 2]).unwrap().time_s;
+        let foot_time = foot.route_summary(&[1, 2]).unwrap().time_s;
+        assert!(car_time < foot_time);
+        assert!(car.route_summary(&[2, 1]).is_none());
+        assert!(foot.route_summary(&[2, 1]).is_some());
+    }
+
+    // A crossroads at node 5 whose arms are ways of their own, 2 to the south, 8 to the north,
+    // 4 to the west and 6 to the east, with a block to the north east that is one-way
+    // clockwise from 8 over 9 to 6. The eastern arm goes on to a dead end at 7, far enough
+    // that turning around there is slower than the block
+    fn crossroads() -> (Vec<Way>, HashMap<i64, (f64, f64)>) {
+        let way = |id: i64, node_ids: Vec<i64>, oneway: bool| {
+            let mut tags = vec![Tag::new("highway".to_string(), "residential".to_string())];
+            if oneway {
+                tags.push(Tag::new("oneway".to_string(), "yes".to_string()));
+            }
+            Way::new(id, 1, String::new(), 0, 0, String::new(), node_ids, tags)
+        };
+        let ways = vec![
+            way(10, vec![2, 5], false),
+            way(11, vec![5, 8], false),
+            way(12, vec![4, 5], false),
+            way(13, vec![5, 6, 7], false),
+            way(14, vec![8, 9], true),
+            way(15, vec![9, 6], true),
+        ];
+        let coordinates = HashMap::from([
+            (2, (54.999, 12.0)),
+            (4, (55.0, 11.999)),
+            (5, (55.0, 12.0)),
+            (6, (55.0, 12.001)),
+            (7, (55.0, 12.004)),
+            (8, (55.001, 12.0)),
+            (9, (55.001, 12.001)),
+        ]);
+        (ways, coordinates)
+    }
+
+    fn restriction(id: i64, restriction: &str, members: Vec<Member>) -> Relation {
+        let tags = vec![
+            Tag::new("type".to_string(), "restriction".to_string()),
+            Tag::new("restriction".to_string(), restriction.to_string()),
+        ];
+        Relation::new(id, 1, String::new(), 0, 0, String::new(), members, tags)
+    }
+
+    fn member(ref_id: i64, maps_type: MapsType, role: &str) -> Member {
+        Member::new(ref_id, maps_type, role.to_string())
+    }
+
+    #[test]
+    fn a_forbidden_left_turn_is_driven_around_by_three_right_turns() {
+        let (ways, coordinates) = crossroads();
+        let open = RoutingGraph::from_ways(&ways, coordinates.clone(), &[], RoutingProfile::Car);
+        assert_eq!(open.shortest_path(2, 4), Some(vec![2, 5, 4]));
+
+        let no_left_turn = restriction(1, "no_left_turn", vec![
+            member(10, MapsType::Way, "from"),
+            member(5, MapsType::Node, "via"),
+            member(12, MapsType::Way, "to"),
+        ]);
+        let restricted = RoutingGraph::from_ways(&ways, coordinates, &[no_left_turn], RoutingProfile::Car);
+        assert_eq!(restricted.ignored_restrictions, 0);
+        // Straight on to 8, then right onto the block, right to 6 and right back to the crossroads
+        assert_eq!(restricted.shortest_path(2, 4), Some(vec![2, 5, 8, 9, 6, 5, 4]));
+        // Only the turn from the south is forbidden, the one from the east is not
+        assert_eq!(restricted.shortest_path(6, 4), Some(vec![6, 5, 4]));
+    }
+
+    #[test]
+    fn an_only_straight_on_forbids_every_other_turn() {
+        let (ways, coordinates) = crossroads();
+        let only_straight_on = restriction(1, "only_straight_on", vec![
+            member(10, MapsType::Way, "from"),
+            member(5, MapsType::Node, "via"),
+            member(11, MapsType::Way, "to"),
+        ]);
+        let graph = RoutingGraph::from_ways(&ways, coordinates, &[only_straight_on], RoutingProfile::Car);
+
+        assert_eq!(graph.shortest_path(2, 6), Some(vec![2, 5, 8, 9, 6]));
+        assert_eq!(graph.shortest_path(2, 8), Some(vec![2, 5, 8]));
+    }
+
+    #[test]
+    fn restrictions_over_a_via_way_are_ignored() {
+        let (ways, coordinates) = crossroads();
+        let via_way = restriction(1, "no_u_turn", vec![
+            member(10, MapsType::Way, "from"),
+            member(11, MapsType::Way, "via"),
+            member(10, MapsType::Way, "to"),
+        ]);
+        let not_a_restriction = restriction(2, "give_way", vec![]);
+        let graph = RoutingGraph::from_ways(&ways, coordinates, &[via_way, not_a_restriction], RoutingProfile::Car);
+
+        assert_eq!(graph.ignored_restrictions, 2);
+        assert_eq!(graph.shortest_path(2, 4), Some(vec![2, 5, 4]));
+    }
+
+    // Node 1 to node 3 straight along a residential street through node 4, or round about
+    // 30% further along a motorway through node 2
+    fn grid_with_a_motorway() -> (Vec<Way>, HashMap<i64, (f64, f64)>) {
+        let way = |id: i64, node_ids: Vec<i64>, highway: &str| {
+            Way::new(id, 1, String::new(), 0, 0, String::new(), node_ids, vec![Tag::new("highway".to_string(), highway.to_string())])
+        };
+        let ways = vec![way(1, vec![1, 2, 3], "motorway"), way(2, vec![1, 4, 3], "residential")];
+        let coordinates = HashMap::from([(1, (55.0, 12.0)), (2, (55.005, 12.01)), (3, (55.0, 12.02)), (4, (55.0, 12.01))]);
+        (ways, coordinates)
+    }
+
+    #[test]
+    fn every_profile_takes_its_own_fastest_path_through_the_grid() {
+        let (ways, coordinates) = grid_with_a_motorway();
+        let graph = |profile| RoutingGraph::from_ways(&ways, coordinates.clone(), &[], profile);
+
+        let car = graph(RoutingProfile::Car);
+        assert_eq!(car.shortest_path(1, 3), Some(vec![1, 2, 3]));
+        let summary = car.route_summary(&[1, 2, 3]).unwrap();
+        assert_eq!(summary.by_highway.iter().map(|(class, _, _)| class.as_str()).collect::<Vec<_>>(), ["motorway"]);
+        // The detour is longer but quicker than the street
+        let street = car.route_summary(&[1, 4, 3]).unwrap();
+        assert!(summary.distance_m > street.distance_m && summary.time_s < street.time_s, "{} {}", summary, street);
+
+        for profile in [RoutingProfile::Bicycle, RoutingProfile::Foot] {
+            let graph = graph(profile);
+            assert_eq!(graph.shortest_path(1, 3), Some(vec![1, 4, 3]), "{:?}", profile);
+            assert!(graph.route_summary(&[1, 2, 3]).is_none(), "{:?}", profile);
+        }
+        let walked = graph(RoutingProfile::Foot).route_summary(&[1, 4, 3]).unwrap();
+        assert!((walked.time_s - walked.distance_m / (profile::WALKING_SPEED_KMH / 3.6)).abs() < 1e-6);
+    }
+
+    // A straight road running east from node 1 through node 2 to node 3, about 64 m per segment
+    const STRAIGHT_ROAD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"/>
+ <node id="2" lat="55.0" lon="12.001" version="1"/>
+ <node id="3" lat="55.0" lon="12.002" version="1"/>
+ <node id="4" lat="55.0003" lon="12.0015" version="1"/>
+ <node id="5" lat="55.0003" lon="12.0016" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><tag k="highway" v="residential"/></way>
+ <way id="11" version="1"><nd ref="4"/><nd ref="5"/><tag k="building" v="yes"/></way>
+</osm>
+"#;
+
+    fn straight_road() -> RenderableWay {
+        let nodes = [(1, 12.0), (2, 12.001), (3, 12.002)].map(|(id, lon)| SimpleNode { id: Some(id), lat: 55.0, lon });
+        RenderableWay::from_nodes(10, &nodes, Vec::new(), 0)
+    }
+
+    #[test]
+    fn a_point_beside_the_road_snaps_square_onto_it() {
+        let snap = snap_to_ways(&[straight_road()], 55.0001, 12.0015, DEFAULT_SNAP_DISTANCE_M).unwrap();
+
+        assert_eq!((snap.way_id, snap.segment_index), (10, 1));
+        assert!((snap.lat - 55.0).abs() < 1e-9 && (snap.lon - 12.0015).abs() < 1e-9, "{}", snap);
+        // A ten-thousandth of a degree of latitude
+        assert!((snap.distance_m - 0.0001 * crate::geo::METERS_PER_DEGREE).abs() < 0.01, "{}", snap);
+    }
+
+    #[test]
+    fn a_point_beyond_the_end_snaps_to_the_last_node() {
+        let snap = snap_to_ways(&[straight_road()], 55.0, 12.0025, DEFAULT_SNAP_DISTANCE_M).unwrap();
+
+        assert_eq!(snap.segment_index, 1);
+        assert_eq!((snap.lat, snap.lon), (55.0, 12.002));
+        // Degrees of longitude are shorter by the cosine of the latitude
+        let expected_m = 0.0005 * crate::geo::METERS_PER_DEGREE * 55f64.to_radians().cos();
+        assert!((snap.distance_m - expected_m).abs() < 0.01, "{} {}", snap, expected_m);
+
+        assert_eq!(snap_to_ways(&[straight_road()], 55.0, 12.0025, expected_m - 1.0), None);
+    }
+
+    #[test]
+    fn a_point_on_a_node_snaps_to_it_on_the_segment_ending_there() {
+        let snap = snap_to_ways(&[straight_road()], 55.0, 12.001, DEFAULT_SNAP_DISTANCE_M).unwrap();
+
+        assert_eq!(snap.segment_index, 0);
+        assert_eq!((snap.lat, snap.lon, snap.distance_m), (55.0, 12.001, 0.0));
+    }
+
+    #[tokio::test]
+    async fn only_roads_are_snapped_to() {
+        let pool = crate::test_support::memory_pool("snap_to_road").await;
+        crate::test_support::import_osm_xml(&pool, "straight_road", STRAIGHT_ROAD).await;
+
+        // The building is closer, but the road is the one snapped to
+        let snap = snap_to_road(&pool, 55.00025, 12.0015, DEFAULT_SNAP_DISTANCE_M).await.unwrap().unwrap();
+        assert_eq!((snap.way_id, snap.segment_index), (10, 1));
+        assert!((snap.lon - 12.0015).abs() < 1e-9, "{}", snap);
+
+        assert_eq!(snap_to_road(&pool, 55.01, 12.0015, DEFAULT_SNAP_DISTANCE_M).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,263 @@
+use std::str::FromStr;
+
+use crate::osm_entities::Tag;
+
+use super::tag_value;
+
+/// The walking pace in km/h, also the speed of `maxspeed=walk`.
+pub const WALKING_SPEED_KMH: f64 = 5.0;
+/// The cycling pace in km/h on roads and cycleways.
+pub const CYCLING_SPEED_KMH: f64 = 18.0;
+
+const KMH_PER_MPH: f64 = 1.609_344;
+const KMH_PER_KNOT: f64 = 1.852;
+
+/// The mode of transport a routing graph is built for. It decides which ways can be used,
+/// in which direction, and how fast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RoutingProfile {
+    #[default]
+    Car,
+    Bicycle,
+    Foot,
+}
+
+impl FromStr for RoutingProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "car" => Ok(RoutingProfile::Car),
+            "bike" | "bicycle" => Ok(RoutingProfile::Bicycle),
+            "foot" | "walk" => Ok(RoutingProfile::Foot),
+            other => Err(format!("unknown routing profile '{}', expected 'car', 'bicycle' or 'foot'", other)),
+        }
+    }
+}
+
+impl RoutingProfile {
+    /// The speed in km/h on a highway class the profile may use without an access tag saying so.
+    fn class_speed_kmh(self, highway: &str) -> Option<f64> {
+        let speed = match (self, highway) {
+            (RoutingProfile::Car, "motorway") => 110.0,
+            (RoutingProfile::Car, "trunk") => 90.0,
+            (RoutingProfile::Car, "primary") => 70.0,
+            (RoutingProfile::Car, "motorway_link" | "secondary") => 60.0,
+            (RoutingProfile::Car, "trunk_link" | "primary_link" | "tertiary") => 50.0,
+            (RoutingProfile::Car, "secondary_link" | "unclassified") => 40.0,
+            (RoutingProfile::Car, "tertiary_link" | "residential" | "road") => 30.0,
+            (RoutingProfile::Car, "service") => 20.0,
+            (RoutingProfile::Car, "living_street") => 10.0,
+
+            (RoutingProfile::Bicycle, "motorway" | "motorway_link" | "trunk" | "trunk_link") => return None,
+            (RoutingProfile::Bicycle, "footway" | "pedestrian" | "steps" | "bridleway" | "corridor") => return None,
+            (RoutingProfile::Bicycle, "track" | "path") => 12.0,
+            (RoutingProfile::Bicycle, "living_street") => 10.0,
+            (RoutingProfile::Bicycle, _) if is_road(highway) || highway == "cycleway" => CYCLING_SPEED_KMH,
+
+            (RoutingProfile::Foot, "motorway" | "motorway_link" | "trunk" | "trunk_link") => return None,
+            (RoutingProfile::Foot, "steps") => WALKING_SPEED_KMH / 2.0,
+            (RoutingProfile::Foot, _) if is_road(highway) || is_path(highway) => WALKING_SPEED_KMH,
+
+            _ => return None,
+        };
+
+        Some(speed)
+    }
+
+    /// The speed in km/h on a class the profile may only use where an access tag allows it,
+    /// e.g. a footway tagged with `bicycle=yes`.
+    fn permitted_speed_kmh(self) -> f64 {
+        match self {
+            RoutingProfile::Car => 10.0,
+            RoutingProfile::Bicycle => 10.0,
+            RoutingProfile::Foot => WALKING_SPEED_KMH,
+        }
+    }
+
+    /// The access keys deciding whether the profile may use a way, the most specific first.
+    fn access_keys(self) -> &'static [&'static str] {
+        match self {
+            RoutingProfile::Car => &["motorcar", "motor_vehicle", "vehicle", "access"],
+            RoutingProfile::Bicycle => &["bicycle", "vehicle", "access"],
+            RoutingProfile::Foot => &["foot", "access"],
+        }
+    }
+
+    /// Reads the access tags of a way.
+    ///
+    /// ## Returns
+    /// * Whether the most specific access tag allows the profile, or `None` if no tag decides.
+    fn access(self, tags: &[Tag]) -> Option<bool> {
+        self.access_keys().iter()
+            .filter_map(|key| tag_value(tags, key))
+            .find_map(|value| match value {
+                "yes" | "designated" | "permissive" | "destination" | "customers" | "delivery" => Some(true),
+                "no" | "private" | "agricultural" | "forestry" => Some(false),
+                _ => None,
+            })
+    }
+
+    /// Decides how fast the profile travels along a way.
+    ///
+    /// The highway class gives the speed, unless an access tag forbids the way or allows a
+    /// class the profile would not use otherwise. A parsable `maxspeed` replaces the speed of
+    /// cars, and caps the speed of bicycles and pedestrians.
+    ///
+    /// ## Returns
+    /// * The speed in km/h, or `None` if the profile cannot use the way.
+    pub fn way_speed_kmh(self, tags: &[Tag]) -> Option<f64> {
+        let highway = tag_value(tags, "highway")?;
+
+        let speed = match (self.access(tags), self.class_speed_kmh(highway)) {
+            (Some(false), _) | (None, None) => return None,
+            (Some(true), None) => self.permitted_speed_kmh(),
+            (_, Some(speed)) => speed,
+        };
+
+        let limit = tag_value(tags, "maxspeed").and_then(parse_maxspeed);
+        Some(match (self, limit) {
+            (RoutingProfile::Car, Some(limit)) => limit,
+            (_, Some(limit)) => speed.min(limit),
+            (_, None) => speed,
+        })
+    }
+
+    /// Decides in which directions the profile may travel along a way.
+    ///
+    /// Cars follow `oneway` and roundabouts, bicycles too unless `oneway:bicycle=no`.
+    /// Pedestrians only follow `oneway:foot`.
+    ///
+    /// ## Returns
+    /// * Whether the way can be used `(forward, backward)`, in and against the order of its nodes.
+    pub fn directions(self, tags: &[Tag]) -> (bool, bool) {
+        let oneway = match self {
+            RoutingProfile::Car => tag_value(tags, "oneway"),
+            RoutingProfile::Bicycle => tag_value(tags, "oneway:bicycle").or_else(|| tag_value(tags, "oneway")),
+            RoutingProfile::Foot => tag_value(tags, "oneway:foot"),
+        };
+        let roundabout = self != RoutingProfile::Foot && tag_value(tags, "junction") == Some("roundabout");
+
+        let forward = oneway != Some("-1");
+        let backward = match oneway {
+            Some("yes" | "true" | "1") => false,
+            Some("no" | "-1") => true,
+            _ => !roundabout,
+        };
+        (forward, backward)
+    }
+}
+
+fn is_road(highway: &str) -> bool {
+    matches!(
+        highway,
+        "primary" | "primary_link" | "secondary" | "secondary_link" | "tertiary" | "tertiary_link"
+            | "unclassified" | "residential" | "living_street" | "service" | "road"
+    )
+}
+
+fn is_path(highway: &str) -> bool {
+    matches!(highway, "footway" | "pedestrian" | "path" | "track" | "cycleway" | "bridleway" | "steps" | "corridor")
+}
+
+/// Parses a `maxspeed` value: a number in km/h (`50`, `50 km/h`), in mph (`30 mph`), in knots,
+/// or `walk`.
+///
+/// ## Returns
+/// * The speed in km/h, or `None` for values without a speed, e.g. `none`, `signals` or
+///   zones like `DE:urban`.
+pub fn parse_maxspeed(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("walk") {
+        return Some(WALKING_SPEED_KMH);
+    }
+
+    let number_end = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(number_end);
+    let speed = number.parse::<f64>().ok().filter(|speed| *speed > 0.0 && speed.is_finite())?;
+
+    match unit.trim().to_lowercase().as_str() {
+        "" | "km/h" | "kmh" | "kph" => Some(speed),
+        "mph" => Some(speed * KMH_PER_MPH),
+        "knots" => Some(speed * KMH_PER_KNOT),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<Tag> {
+        pairs.iter().map(|&(key, value)| Tag::new(key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn maxspeed_values_are_read_in_km_h() {
+        let table = [
+            ("50", Some(50.0)),
+            (" 50 km/h", Some(50.0)),
+            ("30 mph", Some(30.0 * KMH_PER_MPH)),
+            ("30mph", Some(30.0 * KMH_PER_MPH)),
+            ("10 knots", Some(10.0 * KMH_PER_KNOT)),
+            ("walk", Some(WALKING_SPEED_KMH)),
+            ("Walk", Some(WALKING_SPEED_KMH)),
+            ("none", None),
+            ("signals", None),
+            ("DE:urban", None),
+            ("0", None),
+            ("50 furlongs", None),
+            ("", None),
+        ];
+
+        for (value, expected) in table {
+            assert_eq!(parse_maxspeed(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn the_speed_of_a_way_depends_on_the_profile_class_access_and_maxspeed() {
+        use RoutingProfile::{Bicycle, Car, Foot};
+
+        // The profile, the tags of the way and the speed in km/h, `None` where the profile cannot use the way
+        type Case<'a> = (RoutingProfile, &'a [(&'a str, &'a str)], Option<f64>);
+        let table: [Case; 14] = [
+            (Car, &[("highway", "motorway")], Some(110.0)),
+            (Car, &[("highway", "residential")], Some(30.0)),
+            (Car, &[("highway", "footway")], None),
+            (Car, &[("building", "yes")], None),
+            // Cars drive the limit, faster or slower than the default
+            (Car, &[("highway", "residential"), ("maxspeed", "50")], Some(50.0)),
+            (Car, &[("highway", "motorway"), ("maxspeed", "30 mph")], Some(30.0 * KMH_PER_MPH)),
+            (Car, &[("highway", "primary"), ("access", "no")], None),
+            (Car, &[("highway", "primary"), ("access", "no"), ("motorcar", "yes")], Some(70.0)),
+            (Bicycle, &[("highway", "motorway")], None),
+            (Bicycle, &[("highway", "footway"), ("bicycle", "yes")], Some(10.0)),
+            (Bicycle, &[("highway", "cycleway"), ("bicycle", "no")], None),
+            // Bicycles and pedestrians are only slowed down by a limit
+            (Bicycle, &[("highway", "residential"), ("maxspeed", "walk")], Some(WALKING_SPEED_KMH)),
+            (Foot, &[("highway", "residential"), ("maxspeed", "50")], Some(WALKING_SPEED_KMH)),
+            (Foot, &[("highway", "motorway"), ("foot", "yes")], Some(WALKING_SPEED_KMH)),
+        ];
+
+        for (profile, pairs, expected) in table {
+            assert_eq!(profile.way_speed_kmh(&tags(pairs)), expected, "{:?} {:?}", profile, pairs);
+        }
+    }
+
+    #[test]
+    fn only_cars_and_bicycles_follow_oneway_and_roundabouts() {
+        let oneway = tags(&[("highway", "residential"), ("oneway", "yes")]);
+        let against = tags(&[("highway", "residential"), ("oneway", "-1")]);
+        let roundabout = tags(&[("highway", "primary"), ("junction", "roundabout")]);
+        let contraflow = tags(&[("highway", "residential"), ("oneway", "yes"), ("oneway:bicycle", "no")]);
+
+        assert_eq!(RoutingProfile::Car.directions(&oneway), (true, false));
+        assert_eq!(RoutingProfile::Car.directions(&against), (false, true));
+        assert_eq!(RoutingProfile::Car.directions(&roundabout), (true, false));
+        assert_eq!(RoutingProfile::Car.directions(&contraflow), (true, false));
+        assert_eq!(RoutingProfile::Bicycle.directions(&contraflow), (true, true));
+        assert_eq!(RoutingProfile::Foot.directions(&oneway), (true, true));
+        assert_eq!(RoutingProfile::Foot.directions(&roundabout), (true, true));
+    }
+}
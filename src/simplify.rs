@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::geo::{douglas_peucker, METERS_PER_DEGREE};
+use crate::osm_entities::{Node, Relation, Way};
+use crate::utils::MapsType;
+
+/// What `simplify_import` left out.
+///
+/// # Fields
+/// * `elided_nodes` - The nodes dropped from the ways.
+/// * `skipped_nodes` - The nodes of those not stored at all, as no way refers to them anymore
+///   and they carry no tags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimplifyStats {
+    pub elided_nodes: usize,
+    pub skipped_nodes: usize,
+}
+
+/// Slims down the elements of an import by dropping the nodes of its ways that are not
+/// needed to keep their shape within a tolerance, with the Douglas–Peucker algorithm.
+///
+/// The topology survives: the ends of a way, the nodes referred to more than once and the nodes
+/// relations refer to are never dropped, nor is a node whose coordinates are not imported.
+/// Ways are simplified between these nodes. Only the ways of the import are known, so a
+/// node shared with a way stored before may be dropped.
+///
+/// A dropped node with tags is still stored, as it is a point of interest of its own.
+///
+/// ## Arguments
+/// * `tolerance_m` - The largest distance in meters a dropped node may lie from its simplified way.
+///
+/// ## Returns
+/// * The nodes left to store, the simplified ways and what was left out.
+pub fn simplify_import(nodes: Vec<Node>, mut ways: Vec<Way>, relations: &[Relation], tolerance_m: f64) -> (Vec<Node>, Vec<Way>, SimplifyStats) {
+    let coordinates: HashMap<i64, (f64, f64)> = nodes.iter().map(|node| (node.id, (node.lat, node.lon))).collect();
+
+    // How often the ways refer to every node. A node referred to twice is a junction, or
+    // where a way crosses itself, so dropping it from one place would leave it in the other
+    let mut reference_counts: HashMap<i64, usize> = HashMap::new();
+    for node_id in ways.iter().flat_map(|way| &way.node_refs) {
+        *reference_counts.entry(*node_id).or_default() += 1;
+    }
+    let relation_members: HashSet<i64> = relations.iter()
+        .flat_map(|relation| &relation.members)
+        .filter(|member| member.maps_type == MapsType::Node)
+        .map(|member| member.ref_id)
+        .collect();
+    let is_pinned = |node_id: i64| reference_counts.get(&node_id).copied().unwrap_or(0) > 1 || relation_members.contains(&node_id) || !coordinates.contains_key(&node_id);
+
+    let mut elided: HashSet<i64> = HashSet::new();
+    for way in &mut ways {
+        let keep = keep_nodes(&way.node_refs, &coordinates, is_pinned, tolerance_m);
+        let node_refs = std::mem::take(&mut way.node_refs);
+        for (node_id, keep) in node_refs.into_iter().zip(keep) {
+            if keep {
+                way.node_refs.push(node_id);
+            } else {
+                elided.insert(node_id);
+            }
+        }
+    }
+
+    let count = nodes.len();
+    let nodes: Vec<Node> = nodes.into_iter()
+        .filter(|node| !elided.contains(&node.id) || !node.tags.is_empty())
+        .collect();
+    let stats = SimplifyStats { elided_nodes: elided.len(), skipped_nodes: count - nodes.len() };
+    (nodes, ways, stats)
+}
+
+/// Marks the nodes of a way to keep, simplifying every stretch between two pinned nodes on its own.
+fn keep_nodes(node_refs: &[i64], coordinates: &HashMap<i64, (f64, f64)>, is_pinned: impl Fn(i64) -> bool, tolerance_m: f64) -> Vec<bool> {
+    let mut keep = vec![false; node_refs.len()];
+    let Some(&first) = node_refs.first() else {
+        return keep;
+    };
+
+    // Degrees are scaled to meters around the start of the way, which is close enough for a tolerance
+    let (first_lat, _) = coordinates.get(&first).copied().unwrap_or_default();
+    let meters_per_degree_lon = METERS_PER_DEGREE * first_lat.to_radians().cos();
+    let to_meters = |node_id: &i64| coordinates.get(node_id).map(|&(lat, lon)| (lat * METERS_PER_DEGREE, lon * meters_per_degree_lon));
+
+    let mut start = 0;
+    for (index, &node_id) in node_refs.iter().enumerate() {
+        let is_end = index == 0 || index == node_refs.len() - 1;
+        if !is_end && !is_pinned(node_id) {
+            continue;
+        }
+
+        // A stretch ending at a node without coordinates is kept whole, as its shape is unknown
+        let points: Option<Vec<(f64, f64)>> = node_refs[start..=index].iter().map(to_meters).collect();
+        let kept = match points {
+            Some(points) => douglas_peucker(&points, tolerance_m),
+            None => vec![true; index + 1 - start],
+        };
+        for (offset, kept) in kept.into_iter().enumerate() {
+            keep[start + offset] |= kept;
+        }
+        keep[index] = true;
+        start = index;
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::{Member, Tag};
+
+    fn node(id: i64, lat: f64, lon: f64, tags: Vec<Tag>) -> Node {
+        Node::new(id, lat, lon, 1, String::new(), 0, 0, String::new(), tags)
+    }
+
+    fn way(id: i64, node_refs: Vec<i64>) -> Way {
+        Way::new(id, 1, String::new(), 0, 0, String::new(), node_refs, vec![Tag::new("highway".to_string(), "track".to_string())])
+    }
+
+    // A way east along 55° N from node 1 to node 9, about 64 m between nodes, wiggling about
+    // 2 m to either side. Node 3 is a bench, and way 20 crosses it north to south at node 5
+    fn wiggly_crossing() -> (Vec<Node>, Vec<Way>) {
+        let mut nodes: Vec<Node> = (1..=9)
+            .map(|id| {
+                let wiggle = match id { 1 | 5 | 9 => 0.0, _ if id % 2 == 0 => 0.00002, _ => -0.00002 };
+                let tags = if id == 3 { vec![Tag::new("amenity".to_string(), "bench".to_string())] } else { Vec::new() };
+                node(id, 55.0 + wiggle, 12.0 + (id - 1) as f64 * 0.001, tags)
+            })
+            .collect();
+        nodes.push(node(30, 55.001, 12.004, Vec::new()));
+        nodes.push(node(31, 54.999, 12.004, Vec::new()));
+        (nodes, vec![way(10, (1..=9).collect()), way(20, vec![30, 5, 31])])
+    }
+
+    fn node_ids(nodes: &[Node]) -> Vec<i64> {
+        nodes.iter().map(|node| node.id).collect()
+    }
+
+    #[test]
+    fn a_wiggly_way_keeps_its_ends_and_the_junction() {
+        let (nodes, ways) = wiggly_crossing();
+        let (nodes, ways, stats) = simplify_import(nodes, ways, &[], 10.0);
+
+        assert_eq!(ways[0].node_refs, [1, 5, 9]);
+        assert_eq!(ways[1].node_refs, [30, 5, 31]);
+        // The bench is dropped from the way but stored as a point of its own
+        assert_eq!(stats, SimplifyStats { elided_nodes: 6, skipped_nodes: 5 });
+        assert_eq!(node_ids(&nodes), [1, 3, 5, 9, 30, 31]);
+    }
+
+    #[test]
+    fn nothing_is_dropped_within_a_small_tolerance() {
+        let (nodes, ways) = wiggly_crossing();
+        let (kept, simplified, stats) = simplify_import(nodes.clone(), ways.clone(), &[], 1.0);
+        assert_eq!(stats, SimplifyStats::default());
+        assert_eq!(node_ids(&kept), node_ids(&nodes));
+        assert_eq!(simplified[0].node_refs, ways[0].node_refs);
+    }
+
+    #[test]
+    fn nodes_of_relations_and_without_coordinates_are_kept() {
+        let (mut nodes, ways) = wiggly_crossing();
+        // Node 7 is a member of a relation, and node 2 was not imported
+        let relation = Relation::new(40, 1, String::new(), 0, 0, String::new(), vec![Member::new(7, MapsType::Node, "stop".to_string())], Vec::new());
+        nodes.retain(|node| node.id != 2);
+
+        let (_, ways, stats) = simplify_import(nodes, ways, &[relation], 10.0);
+        // The stretches from 1 to 2 and 2 to 5 have an unknown shape, so are kept whole
+        assert_eq!(ways[0].node_refs, [1, 2, 3, 4, 5, 7, 9]);
+        assert_eq!(stats.elided_nodes, 2);
+    }
+
+    #[test]
+    fn a_closed_way_keeps_its_ring() {
+        // A square of 100 m with a node 1 m off the middle of its northern side
+        let nodes = vec![
+            node(1, 55.0, 12.0, Vec::new()),
+            node(2, 55.0009, 12.0, Vec::new()),
+            node(3, 55.00091, 12.00078, Vec::new()),
+            node(4, 55.0009, 12.00157, Vec::new()),
+            node(5, 55.0, 12.00157, Vec::new()),
+        ];
+        let (nodes, ways, stats) = simplify_import(nodes, vec![way(10, vec![1, 2, 3, 4, 5, 1])], &[], 10.0);
+        assert_eq!(ways[0].node_refs, [1, 2, 4, 5, 1]);
+        assert_eq!((stats.elided_nodes, node_ids(&nodes)), (1, vec![1, 2, 4, 5]));
+    }
+}
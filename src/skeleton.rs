@@ -0,0 +1,142 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::database::WayBbox;
+
+/// The most placeholders fetched for a viewport. The largest ways are fetched first, as they
+/// give the map its structure, and the outlines stay within the indices of one overlay.
+pub const MAX_PLACEHOLDERS: usize = 1000;
+
+/// Placeholders for the ways that are stored but not drawn yet, shown as the outlines of
+/// their bounding boxes while their shapes are loaded and tessellated.
+///
+/// A placeholder is added once the bounding box of its way is fetched and is replaced once
+/// the way is drawn. Placeholders of ways that will not be drawn, as they are gone from the
+/// loaded ways, are removed.
+///
+/// # Fields
+/// * `placeholders` - The bounding boxes of the ways waiting to be drawn, by way id.
+#[derive(Debug, Default)]
+pub struct Skeleton {
+    placeholders: HashMap<i64, WayBbox>,
+}
+
+impl Skeleton {
+    pub fn new() -> Self {
+        Skeleton::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.placeholders.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.placeholders.len()
+    }
+
+    /// The placeholders waiting for their ways, in no particular order.
+    pub fn placeholders(&self) -> impl Iterator<Item = &WayBbox> {
+        self.placeholders.values()
+    }
+
+    /// Adds placeholders for the ways not drawn yet.
+    ///
+    /// ## Arguments
+    /// * `bboxes` - The bounding boxes fetched, see `fetch_way_bboxes_in_viewport`.
+    /// * `is_drawn` - Whether a way is already drawn, so needs no placeholder.
+    ///
+    /// ## Returns
+    /// * The number of placeholders added.
+    pub fn add(&mut self, bboxes: impl IntoIterator<Item = WayBbox>, is_drawn: impl Fn(i64) -> bool) -> usize {
+        let before = self.placeholders.len();
+        for bbox in bboxes {
+            if !is_drawn(bbox.way_id) {
+                self.placeholders.insert(bbox.way_id, bbox);
+            }
+        }
+        self.placeholders.len() - before
+    }
+
+    /// Replaces the placeholders of the ways now drawn.
+    ///
+    /// ## Returns
+    /// * The number of placeholders replaced.
+    pub fn replace(&mut self, drawn_way_ids: impl IntoIterator<Item = i64>) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+
+        let before = self.placeholders.len();
+        for way_id in drawn_way_ids {
+            self.placeholders.remove(&way_id);
+        }
+        before - self.placeholders.len()
+    }
+
+    /// Removes the placeholders of the ways missing from the loaded ways, e.g. ways deleted
+    /// or merged into others, which will never be drawn.
+    ///
+    /// ## Returns
+    /// * The number of placeholders removed.
+    pub fn remove_unknown(&mut self, known_way_ids: &HashSet<i64>) -> usize {
+        let before = self.placeholders.len();
+        self.placeholders.retain(|way_id, _| known_way_ids.contains(way_id));
+        before - self.placeholders.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::BBox;
+
+    fn bbox_of(way_id: i64) -> WayBbox {
+        let offset = way_id as f64 * 0.01;
+        WayBbox { way_id, bbox: BBox { min_lat: 55.0 + offset, max_lat: 55.005 + offset, min_lon: 12.0, max_lon: 12.005 } }
+    }
+
+    fn way_ids(skeleton: &Skeleton) -> Vec<i64> {
+        let mut ids: Vec<i64> = skeleton.placeholders().map(|placeholder| placeholder.way_id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn placeholders_are_added_for_the_ways_not_drawn_yet() {
+        let mut skeleton = Skeleton::new();
+        assert!(skeleton.is_empty());
+
+        // Way 2 is drawn already
+        assert_eq!(skeleton.add([1, 2, 3].map(bbox_of), |way_id| way_id == 2), 2);
+        assert_eq!(way_ids(&skeleton), [1, 3]);
+        assert_eq!(skeleton.placeholders().find(|placeholder| placeholder.way_id == 3), Some(&bbox_of(3)));
+
+        // Fetched again after a pan, the ways waiting are not counted twice
+        assert_eq!(skeleton.add([3, 4].map(bbox_of), |_| false), 1);
+        assert_eq!(skeleton.len(), 3);
+    }
+
+    #[test]
+    fn placeholders_are_replaced_by_their_drawn_ways() {
+        let mut skeleton = Skeleton::new();
+        skeleton.add([1, 2, 3].map(bbox_of), |_| false);
+
+        // Way 9 never had a placeholder
+        assert_eq!(skeleton.replace([1, 9]), 1);
+        assert_eq!(way_ids(&skeleton), [2, 3]);
+        assert_eq!(skeleton.replace([2, 3]), 2);
+        assert!(skeleton.is_empty());
+        assert_eq!(skeleton.replace([1]), 0);
+    }
+
+    #[test]
+    fn placeholders_of_ways_no_longer_loaded_are_removed() {
+        let mut skeleton = Skeleton::new();
+        skeleton.add([1, 2, 3].map(bbox_of), |_| false);
+
+        // Way 2 was merged into way 1
+        assert_eq!(skeleton.remove_unknown(&HashSet::from([1, 3, 4])), 1);
+        assert_eq!(way_ids(&skeleton), [1, 3]);
+        assert_eq!(skeleton.remove_unknown(&HashSet::new()), 2);
+        assert!(skeleton.is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroI64;
+use std::path::Path;
+
+use sqlx::SqlitePool;
+use tracing::debug;
+
+use crate::database::{fetch_all_renderable_ways, fetch_renderable_ways_in_bbox};
+use crate::geo::BBox;
+use crate::osm_entities::{RenderableWay, Tag};
+
+// A snapshot holds the renderable ways in a compact binary file, so the map can be opened
+// without querying the database. All numbers are little-endian:
+//
+//   magic    8 bytes  "GMCSNAP\0"
+//   version  u32
+//   ways     u64
+//   per way: id i64, tag count u32, per tag: key and value as (length u32, UTF-8 bytes),
+//            node count u32, per node: id i64 (i64::MIN for none), lat f64, lon f64,
+//            missing node count u32
+
+/// Where the app looks for a snapshot before loading the ways from the database.
+pub const SNAPSHOT_PATH: &str = "database/snapshot.bin";
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"GMCSNAP\0";
+const SNAPSHOT_VERSION: u32 = 2;
+// Stands in for the id of points that are no node of the map
+const NO_NODE_ID: i64 = i64::MIN;
+// The fewest bytes a way and a node take, used to reject counts the file cannot hold
+const MIN_WAY_SIZE: usize = 8 + 4 + 4 + 4;
+const NODE_SIZE: usize = 8 + 8 + 8;
+
+/// An error while writing or reading a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The file could not be read or written.
+    Io(io::Error),
+    /// The ways could not be loaded from the database.
+    Database(sqlx::Error),
+    /// The file does not start with the snapshot magic.
+    NotASnapshot,
+    /// The file was written by another version of the format.
+    UnsupportedVersion(u32),
+    /// The file ends in the middle of a way, e.g. because writing it was cut short.
+    Truncated,
+    /// The file holds something the format does not allow.
+    Corrupt(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "Could not access the snapshot: {}", e),
+            SnapshotError::Database(e) => write!(f, "Could not load the ways for the snapshot: {}", e),
+            SnapshotError::NotASnapshot => write!(f, "The file is not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "The snapshot has version {}, only version {} is supported", version, SNAPSHOT_VERSION)
+            }
+            SnapshotError::Truncated => write!(f, "The snapshot ends unexpectedly"),
+            SnapshotError::Corrupt(e) => write!(f, "The snapshot is corrupt: {}", e),
+        }
+    }
+}
+
+impl StdError for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<sqlx::Error> for SnapshotError {
+    fn from(error: sqlx::Error) -> Self {
+        SnapshotError::Database(error)
+    }
+}
+
+/// Writes the renderable ways of the database to a snapshot.
+///
+/// The snapshot is written next to `path` first and then moved over it, so a cut short write
+/// never leaves a broken snapshot behind.
+///
+/// ## Arguments
+/// * `pool` - The database to read the ways from.
+/// * `bbox` - The box to take the ways from, or `None` for all ways.
+/// * `path` - Where to write the snapshot.
+///
+/// ## Returns
+/// * The number of ways written.
+pub async fn save_snapshot(pool: &SqlitePool, bbox: Option<&BBox>, path: impl AsRef<Path>) -> Result<usize, SnapshotError> {
+    let ways = match bbox {
+        Some(bbox) => fetch_renderable_ways_in_bbox(pool, bbox).await?,
+        None => fetch_all_renderable_ways(pool).await?,
+    };
+
+    let path = path.as_ref();
+    let temporary_path = path.with_extension("tmp");
+    {
+        let mut out = BufWriter::new(fs::File::create(&temporary_path)?);
+        write_snapshot(&ways, &mut out)?;
+        out.flush()?;
+    }
+    fs::rename(&temporary_path, path)?;
+
+    debug!(path = %path.display(), count = ways.len(), "saved snapshot");
+    Ok(ways.len())
+}
+
+/// Writes ways in the snapshot format.
+pub fn write_snapshot(ways: &[RenderableWay], out: &mut impl Write) -> io::Result<()> {
+    out.write_all(SNAPSHOT_MAGIC)?;
+    out.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    out.write_all(&(ways.len() as u64).to_le_bytes())?;
+
+    for way in ways {
+        out.write_all(&way.id.to_le_bytes())?;
+
+        out.write_all(&(way.tags.len() as u32).to_le_bytes())?;
+        for tag in &way.tags {
+            write_string(out, &tag.key)?;
+            write_string(out, &tag.value)?;
+        }
+
+        out.write_all(&(way.coords.len() as u32).to_le_bytes())?;
+        for (&(lat, lon), id) in way.coords.iter().zip(&way.node_ids) {
+            out.write_all(&id.map_or(NO_NODE_ID, NonZeroI64::get).to_le_bytes())?;
+            out.write_all(&lat.to_le_bytes())?;
+            out.write_all(&lon.to_le_bytes())?;
+        }
+
+        out.write_all(&way.missing_nodes.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn write_string(out: &mut impl Write, value: &str) -> io::Result<()> {
+    out.write_all(&(value.len() as u32).to_le_bytes())?;
+    out.write_all(value.as_bytes())
+}
+
+/// Reads the ways of a snapshot file.
+///
+/// ## Returns
+/// * The ways in the order they were written, or an error if the file is missing, of
+///   another version, or damaged in any way. A damaged file never yields part of its ways.
+pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Vec<RenderableWay>, SnapshotError> {
+    let bytes = fs::read(path)?;
+    read_snapshot(&bytes)
+}
+
+/// Reads ways in the snapshot format.
+pub fn read_snapshot(bytes: &[u8]) -> Result<Vec<RenderableWay>, SnapshotError> {
+    let mut reader = SnapshotReader { bytes, position: 0 };
+
+    if reader.take(SNAPSHOT_MAGIC.len()).ok() != Some(SNAPSHOT_MAGIC.as_slice()) {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    let version = reader.read_u32()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let way_count = reader.read_u64()?;
+    let way_count = reader.read_count(MIN_WAY_SIZE, way_count)?;
+    let mut ways = Vec::with_capacity(way_count);
+
+    for _ in 0..way_count {
+        let id = reader.read_i64()?;
+
+        let tag_count = reader.read_u32()? as u64;
+        let tag_count = reader.read_count(8, tag_count)?;
+        let mut tags = Vec::with_capacity(tag_count);
+        for _ in 0..tag_count {
+            let key = reader.read_string()?;
+            let value = reader.read_string()?;
+            tags.push(Tag { key, value });
+        }
+
+        let node_count = reader.read_u32()? as u64;
+        let node_count = reader.read_count(NODE_SIZE, node_count)?;
+        let mut coords = Vec::with_capacity(node_count);
+        let mut node_ids = Vec::with_capacity(node_count);
+        for _ in 0..node_count {
+            let node_id = reader.read_i64()?;
+            let lat = reader.read_f64()?;
+            let lon = reader.read_f64()?;
+            if !(lat.is_finite() && lon.is_finite()) {
+                return Err(SnapshotError::Corrupt(format!("way {} has a node at {}, {}", id, lat, lon)));
+            }
+            coords.push((lat, lon));
+            node_ids.push((node_id != NO_NODE_ID).then_some(node_id).and_then(NonZeroI64::new));
+        }
+
+        let missing_nodes = reader.read_u32()?;
+
+        ways.push(RenderableWay { id, coords, node_ids, tags, missing_nodes, inner_rings: Vec::new() });
+    }
+
+    if reader.position != bytes.len() {
+        return Err(SnapshotError::Corrupt(format!("{} bytes after the last way", bytes.len() - reader.position)));
+    }
+
+    Ok(ways)
+}
+
+/// Reads the values of a snapshot one after the other.
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.position.checked_add(length).filter(|&end| end <= self.bytes.len()).ok_or(SnapshotError::Truncated)?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_i64(&mut self) -> Result<i64, SnapshotError> {
+        Ok(i64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, SnapshotError> {
+        Ok(f64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_string(&mut self) -> Result<String, SnapshotError> {
+        let length = self.read_u32()? as usize;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|error| SnapshotError::Corrupt(error.to_string()))
+    }
+
+    /// Checks that `count` items of at least `item_size` bytes fit into the rest of the file,
+    /// so a damaged count cannot make us reserve a huge amount of memory.
+    fn read_count(&self, item_size: usize, count: u64) -> Result<usize, SnapshotError> {
+        let remaining = (self.bytes.len() - self.position) as u64;
+        if count.saturating_mul(item_size as u64) > remaining {
+            return Err(SnapshotError::Truncated);
+        }
+        Ok(count as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{import_osm_xml, memory_pool};
+
+    // A closed building and a street with a name needing more than ASCII
+    const WAYS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+ <node id="1" lat="55.0" lon="12.0" version="1"/>
+ <node id="2" lat="55.0" lon="12.001" version="1"/>
+ <node id="3" lat="55.001" lon="12.001" version="1"/>
+ <node id="4" lat="55.001" lon="12.0" version="1"/>
+ <node id="5" lat="55.002" lon="12.002" version="1"/>
+ <way id="10" version="1"><nd ref="1"/><nd ref="2"/><nd ref="3"/><nd ref="4"/><nd ref="1"/><tag k="building" v="yes"/></way>
+ <way id="11" version="1"><nd ref="3"/><nd ref="5"/><tag k="highway" v="residential"/><tag k="name" v="Østergade"/></way>
+</osm>
+"#;
+
+    // Everything a way is drawn from, to compare ways
+    type WayContents = (i64, Vec<(f64, f64)>, Vec<Option<NonZeroI64>>, Vec<(String, String)>, u32);
+
+    fn contents(ways: &[RenderableWay]) -> Vec<WayContents> {
+        ways.iter()
+            .map(|way| {
+                let tags = way.tags.iter().map(|tag| (tag.key.clone(), tag.value.clone())).collect();
+                (way.id, way.coords.clone(), way.node_ids.clone(), tags, way.missing_nodes)
+            })
+            .collect()
+    }
+
+    fn snapshot_bytes(ways: &[RenderableWay]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_snapshot(ways, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[tokio::test]
+    async fn a_saved_snapshot_loads_the_ways_of_the_database() {
+        let pool = memory_pool("snapshot_round_trip").await;
+        import_osm_xml(&pool, "snapshot", WAYS).await;
+        let path = std::env::temp_dir().join(format!("gmc_snapshot_{}.bin", std::process::id()));
+
+        let saved = save_snapshot(&pool, None, &path).await.unwrap();
+        let loaded = load_snapshot(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let from_database = fetch_all_renderable_ways(&pool).await.unwrap();
+        assert_eq!(saved, 2);
+        assert_eq!(contents(&loaded.unwrap()), contents(&from_database));
+    }
+
+    #[test]
+    fn ways_without_node_ids_and_with_missing_nodes_survive_the_round_trip() {
+        let mut clipped = RenderableWay::from_nodes(7, &[], vec![Tag::new(String::new(), "π".to_string())], 3);
+        clipped.coords = vec![(-89.5, -179.9), (89.5, 179.9)];
+        clipped.node_ids = vec![None, NonZeroI64::new(-4)];
+
+        let ways = vec![clipped, RenderableWay::from_nodes(8, &[], Vec::new(), 0)];
+        assert_eq!(contents(&read_snapshot(&snapshot_bytes(&ways)).unwrap()), contents(&ways));
+    }
+
+    #[test]
+    fn a_damaged_snapshot_yields_no_ways() {
+        let ways = vec![RenderableWay::from_nodes(1, &[crate::osm_entities::SimpleNode { id: Some(1), lat: 55.0, lon: 12.0 }], vec![Tag::new("highway".to_string(), "path".to_string())], 0)];
+        let bytes = snapshot_bytes(&ways);
+
+        // Cut short anywhere
+        for length in 0..bytes.len() {
+            let error = read_snapshot(&bytes[..length]).unwrap_err();
+            assert!(matches!(error, SnapshotError::NotASnapshot | SnapshotError::Truncated), "{} bytes: {:?}", length, error);
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(read_snapshot(&trailing), Err(SnapshotError::Corrupt(_))));
+
+        let mut other_version = bytes.clone();
+        other_version[8..12].copy_from_slice(&1u32.to_le_bytes());
+        assert!(matches!(read_snapshot(&other_version), Err(SnapshotError::UnsupportedVersion(1))));
+
+        // A way count the file cannot hold is rejected before anything is read
+        let mut huge_count = bytes.clone();
+        huge_count[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(read_snapshot(&huge_count).is_err());
+
+        let mut not_a_number = bytes.clone();
+        let lat_at = bytes.len() - 4 - 16;
+        not_a_number[lat_at..lat_at + 8].copy_from_slice(&f64::NAN.to_le_bytes());
+        assert!(matches!(read_snapshot(&not_a_number), Err(SnapshotError::Corrupt(_))));
+
+        let missing = std::env::temp_dir().join(format!("gmc_no_snapshot_{}.bin", std::process::id()));
+        assert!(matches!(load_snapshot(&missing), Err(SnapshotError::Io(error)) if error.kind() == io::ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,149 @@
+use std::ops::RangeInclusive;
+
+use crate::geo::{bbox_around, bbox_of_points, BBox};
+use crate::osm_entities::RenderableWay;
+
+// Picking a way tests the point against every segment of every way, which is too slow for
+// city sized data on every click or mouse move. The grid narrows that down to the ways
+// passing through the cells around the point.
+
+/// The most cells along either side of the grid.
+const MAX_GRID_SIDE: usize = 1024;
+
+/// A flat grid over a box, mapping every cell to the indices of the ways passing through it.
+///
+/// Ways reaching outside the box are kept in the cells along its edge, so queries outside
+/// the box still find them, only less efficiently.
+#[derive(Debug, Clone)]
+pub struct SpatialIndex {
+    min_lat: f64,
+    min_lon: f64,
+    cell_lat: f64,
+    cell_lon: f64,
+    rows: usize,
+    columns: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        SpatialIndex::build(&[], &BBox::default())
+    }
+}
+
+impl SpatialIndex {
+    /// Builds the grid for the ways, with about one cell per way.
+    ///
+    /// ## Arguments
+    /// * `ways` - The ways to index, referred to by their position in the slice.
+    /// * `bbox` - The box the grid covers, usually the extent of the ways.
+    pub fn build(ways: &[RenderableWay], bbox: &BBox) -> Self {
+        let BBox { min_lat, max_lat, min_lon, max_lon } = *bbox;
+        let side = ((ways.len() as f64).sqrt().ceil() as usize).clamp(1, MAX_GRID_SIDE);
+
+        let mut index = SpatialIndex {
+            min_lat,
+            min_lon,
+            cell_lat: ((max_lat - min_lat) / side as f64).max(f64::EPSILON),
+            cell_lon: ((max_lon - min_lon) / side as f64).max(f64::EPSILON),
+            rows: side,
+            columns: side,
+            cells: vec![Vec::new(); side * side],
+        };
+
+        for (way_index, way) in ways.iter().enumerate() {
+            let points = &way.coords;
+
+            // A single node has no segment, but can still be picked
+            let segments: Vec<((f64, f64), (f64, f64))> = match points.len() {
+                0 => Vec::new(),
+                1 => vec![(points[0], points[0])],
+                _ => points.windows(2).map(|segment| (segment[0], segment[1])).collect(),
+            };
+
+            // Every cell the bounding box of a segment touches, so no cell the segment crosses is missed
+            for (a, b) in segments {
+                let (rows, columns) = index.cell_span(&bbox_of_points(&[a, b]).unwrap());
+                for row in rows {
+                    for column in columns.clone() {
+                        let cell = &mut index.cells[row * index.columns + column];
+                        // The ways are added in order, so a repeat can only be the last entry
+                        if cell.last() != Some(&way_index) {
+                            cell.push(way_index);
+                        }
+                    }
+                }
+            }
+        }
+
+        index
+    }
+
+    /// The rows and columns of the cells overlapping a box, clamped to the grid.
+    fn cell_span(&self, &BBox { min_lat, max_lat, min_lon, max_lon }: &BBox) -> (RangeInclusive<usize>, RangeInclusive<usize>) {
+        let row = |lat: f64| (((lat - self.min_lat) / self.cell_lat).floor().max(0.0) as usize).min(self.rows - 1);
+        let column = |lon: f64| (((lon - self.min_lon) / self.cell_lon).floor().max(0.0) as usize).min(self.columns - 1);
+
+        (row(min_lat)..=row(max_lat), column(min_lon)..=column(max_lon))
+    }
+
+    /// Finds the ways that may pass within `radius_m` meters of a point.
+    ///
+    /// ## Returns
+    /// * The indices of the candidate ways in ascending order. Every way within the radius is
+    ///   among them, but so may be ways a little further away, so the caller still measures the distance.
+    pub fn query_point(&self, lat: f64, lon: f64, radius_m: f64) -> Vec<usize> {
+        let (rows, columns) = self.cell_span(&bbox_around((lat, lon), radius_m));
+
+        let mut candidates: Vec<usize> = rows
+            .flat_map(|row| columns.clone().map(move |column| row * self.columns + column))
+            .flat_map(|cell| self.cells[cell].iter().copied())
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::distance_to_polyline;
+    use crate::test_support::{synthetic_renderable_ways, SyntheticRng, SYNTHETIC_BBOX};
+
+    #[test]
+    fn every_way_within_the_radius_is_a_candidate() {
+        let ways = synthetic_renderable_ways(3000);
+        let index = SpatialIndex::build(&ways, &SYNTHETIC_BBOX);
+        let mut rng = SyntheticRng::new(7);
+
+        let mut candidates_total = 0;
+        for query in 0..500 {
+            // Some of the points lie outside the grid, where the cells along its edge are searched
+            let (lat, lon) = rng.next_point();
+            let (lat, lon) = if query % 10 == 0 { (lat + 0.1, lon - 0.1) } else { (lat, lon) };
+            let radius_m = [5.0, 25.0, 200.0][query % 3];
+
+            let candidates = index.query_point(lat, lon, radius_m);
+            assert!(candidates.windows(2).all(|pair| pair[0] < pair[1]));
+            for (way_index, way) in ways.iter().enumerate() {
+                if distance_to_polyline((lat, lon), &way.coords) <= radius_m {
+                    assert!(candidates.binary_search(&way_index).is_ok(), "way {} is missing at {} {}", way_index, lat, lon);
+                }
+            }
+            candidates_total += candidates.len();
+        }
+
+        // The grid narrows the ways down, or it would be of no use
+        assert!(candidates_total < 500 * ways.len() / 10, "{}", candidates_total);
+    }
+
+    #[test]
+    fn single_nodes_and_empty_indexes_are_handled() {
+        let point = RenderableWay::from_nodes(1, &[crate::osm_entities::SimpleNode { id: Some(1), lat: 55.0, lon: 12.0 }], Vec::new(), 0);
+        let index = SpatialIndex::build(&[point], &BBox { min_lat: 54.0, max_lat: 56.0, min_lon: 11.0, max_lon: 13.0 });
+        assert_eq!(index.query_point(55.0, 12.0, 1.0), [0]);
+
+        assert!(SpatialIndex::default().query_point(55.0, 12.0, 100.0).is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cancel::{CancellationToken, OperationError, CANCEL_CHECK_BATCH};
+use crate::geo::{bbox_of_points, clip_polygon_to_bbox, clip_polyline_to_bbox, format_area, format_distance, polygon_area, polyline_length, BBox};
+use crate::layers::MapLayer;
+use crate::osm_entities::RenderableWay;
+
+/// What is on screen, counted and measured within the viewport only, so a building or road
+/// crossing its edge counts with the part in view.
+///
+/// # Fields
+/// * `buildings` - The buildings at least partly in view.
+/// * `building_area_m2` - The footprint of the buildings in view.
+/// * `road_length_m` - The length of the roads in view, by their `highway` value.
+/// * `water_area_m2` - The area of the water in view, e.g. lakes and riverbanks.
+/// * `incomplete_ways` - The ways in view missing some of their nodes, left out of the other
+///   numbers, as their shape and size are unknown.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ViewportStats {
+    pub buildings: usize,
+    pub building_area_m2: f64,
+    pub road_length_m: BTreeMap<String, f64>,
+    pub water_area_m2: f64,
+    pub incomplete_ways: usize,
+}
+
+impl fmt::Display for ViewportStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} buildings covering {}, {} of water", self.buildings, format_area(self.building_area_m2), format_area(self.water_area_m2))?;
+
+        // The longest classes first, they say the most about the area
+        let mut roads: Vec<(&String, &f64)> = self.road_length_m.iter().collect();
+        roads.sort_by(|a, b| b.1.total_cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        if roads.is_empty() {
+            write!(f, ", no roads")?;
+        }
+        for (index, (class, length_m)) in roads.iter().enumerate() {
+            write!(f, "{}{} {}", if index == 0 { ", roads: " } else { ", " }, format_distance(**length_m), class)?;
+        }
+
+        if self.incomplete_ways > 0 {
+            write!(f, ", {} incomplete ways left out", self.incomplete_ways)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a way of the water layer outlines water, e.g. a lake, rather than running along
+/// it like rivers do. A closed way outlines water unless it is a coastline, which outlines
+/// an island.
+fn is_water_area(way: &RenderableWay) -> bool {
+    let is_closed = way.coords.len() > 3 && way.node_ids.first() == way.node_ids.last() && way.node_ids[0].is_some();
+    is_closed && !way.tags.iter().any(|tag| tag.key == "natural" && tag.value == "coastline")
+}
+
+/// Counts and measures the buildings, roads and water of the ways within a viewport.
+///
+/// ## Arguments
+/// * `ways` - The ways to look at. Those outside the viewport are skipped.
+/// * `bbox` - The viewport.
+/// * `cancel` - Looked at every `CANCEL_CHECK_BATCH` ways, e.g. cancelled once the camera
+///   moved on.
+///
+/// ## Returns
+/// * The statistics, or `OperationError::Cancelled` once `cancel` is cancelled.
+pub fn compute_viewport_stats(ways: &[RenderableWay], bbox: &BBox, cancel: &CancellationToken) -> Result<ViewportStats, OperationError> {
+    let mut stats = ViewportStats::default();
+
+    for (index, way) in ways.iter().enumerate() {
+        if index % CANCEL_CHECK_BATCH == 0 {
+            cancel.check()?;
+        }
+        if !bbox_of_points(&way.coords).is_some_and(|way_bbox| way_bbox.intersects(bbox)) {
+            continue;
+        }
+        if !way.is_complete() {
+            stats.incomplete_ways += 1;
+            continue;
+        }
+
+        let area_in_view = || polygon_area(&clip_polygon_to_bbox(&way.coords, bbox));
+        match MapLayer::of_tags(&way.tags) {
+            MapLayer::Buildings => {
+                stats.buildings += 1;
+                stats.building_area_m2 += area_in_view();
+            }
+            MapLayer::Highways => {
+                let Some(class) = way.tags.iter().find(|tag| tag.key == "highway") else {
+                    continue;
+                };
+                let length_m: f64 = clip_polyline_to_bbox(&way.coords, bbox).iter()
+                    .map(|part| polyline_length(part))
+                    .sum();
+                if length_m > 0.0 {
+                    *stats.road_length_m.entry(class.value.clone()).or_default() += length_m;
+                }
+            }
+            MapLayer::Water if is_water_area(way) => {
+                stats.water_area_m2 += area_in_view();
+            }
+            _ => (),
+        }
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::METERS_PER_DEGREE;
+    use crate::osm_entities::{SimpleNode, Tag};
+
+    const VIEW: BBox = BBox { min_lat: 55.0, max_lat: 55.01, min_lon: 12.0, max_lon: 12.01 };
+
+    /// A way through `coords`, closed back to its first node if `closed`, tagged `key=value`.
+    fn way(id: i64, coords: &[(f64, f64)], closed: bool, (key, value): (&str, &str)) -> RenderableWay {
+        let mut nodes: Vec<SimpleNode> = coords.iter().enumerate()
+            .map(|(index, &(lat, lon))| SimpleNode { id: Some(id * 100 + index as i64 + 1), lat, lon })
+            .collect();
+        if closed {
+            nodes.push(nodes[0].clone());
+        }
+        RenderableWay::from_nodes(id, &nodes, vec![Tag::new(key.to_string(), value.to_string())], 0)
+    }
+
+    /// A square of `side_m` meters centered at `(lat, lon)`.
+    fn square(side_m: f64, (lat, lon): (f64, f64)) -> Vec<(f64, f64)> {
+        let half_lat = side_m / METERS_PER_DEGREE / 2.0;
+        let half_lon = side_m / (METERS_PER_DEGREE * lat.to_radians().cos()) / 2.0;
+        vec![(lat - half_lat, lon - half_lon), (lat - half_lat, lon + half_lon), (lat + half_lat, lon + half_lon), (lat + half_lat, lon - half_lon)]
+    }
+
+    fn close_to(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() <= expected * 0.01
+    }
+
+    #[test]
+    fn only_what_is_in_view_is_counted() {
+        let mut incomplete = way(9, &square(50.0, (55.008, 12.008)), true, ("building", "yes"));
+        incomplete.missing_nodes = 1;
+        let ways = [
+            way(1, &square(100.0, (55.003, 12.003)), true, ("building", "house")),
+            // Half of it lies west of the viewport
+            way(2, &square(40.0, (55.007, 12.0)), true, ("building", "yes")),
+            way(3, &square(100.0, (55.1, 12.1)), true, ("building", "yes")),
+            way(4, &[(55.005, 11.99), (55.005, 12.02)], false, ("highway", "primary")),
+            way(5, &[(55.001, 12.001), (55.001, 12.002)], false, ("highway", "residential")),
+            way(6, &[(55.002, 12.001), (55.002, 12.002)], false, ("highway", "residential")),
+            way(7, &square(200.0, (55.005, 12.005)), true, ("natural", "water")),
+            // Rivers and coastlines outline no water of their own
+            way(8, &[(55.001, 12.0), (55.009, 12.009)], false, ("waterway", "river")),
+            way(10, &square(300.0, (55.005, 12.005)), true, ("natural", "coastline")),
+            incomplete,
+        ];
+
+        let stats = compute_viewport_stats(&ways, &VIEW, &CancellationToken::default()).unwrap();
+        assert_eq!((stats.buildings, stats.incomplete_ways), (2, 1));
+        assert!(close_to(stats.building_area_m2, 10_000.0 + 800.0), "{}", stats.building_area_m2);
+        assert!(close_to(stats.water_area_m2, 40_000.0), "{}", stats.water_area_m2);
+
+        let primary = polyline_length(&[(55.005, 12.0), (55.005, 12.01)]);
+        let residential = 2.0 * polyline_length(&[(55.001, 12.001), (55.001, 12.002)]);
+        assert_eq!(stats.road_length_m.keys().collect::<Vec<_>>(), ["primary", "residential"]);
+        assert!(close_to(stats.road_length_m["primary"], primary));
+        assert!(close_to(stats.road_length_m["residential"], residential));
+    }
+
+    #[test]
+    fn the_stats_read_as_a_line() {
+        assert_eq!(ViewportStats::default().to_string(), "0 buildings covering 0 m², 0 m² of water, no roads");
+
+        let stats = ViewportStats {
+            buildings: 12,
+            building_area_m2: 25_000.0,
+            road_length_m: BTreeMap::from([("residential".to_string(), 400.0), ("primary".to_string(), 1500.0)]),
+            water_area_m2: 0.0,
+            incomplete_ways: 3,
+        };
+        assert_eq!(stats.to_string(), "12 buildings covering 2.5 ha, 0 m² of water, roads: 1.50 km primary, 400 m residential, 3 incomplete ways left out");
+    }
+
+    #[test]
+    fn a_cancelled_computation_stops() {
+        let ways = crate::test_support::synthetic_renderable_ways(10);
+        let cancel = CancellationToken::default();
+        cancel.cancel();
+        assert!(matches!(compute_viewport_stats(&ways, &VIEW, &cancel), Err(OperationError::Cancelled)));
+    }
+}
@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+/// How long a message about something that finished is shown.
+pub const INFO_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long an error is shown, longer than other messages so it is not missed.
+pub const ERROR_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How severe a status message is, from the least to the most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatusLevel {
+    /// Something finished, e.g. an import.
+    Info,
+    /// Something is running in the background, e.g. a download.
+    Busy,
+    /// Something failed.
+    Error,
+}
+
+impl StatusLevel {
+    /// How long a message of this level is shown, or `None` if it stays until it is cleared.
+    fn timeout(self) -> Option<Duration> {
+        match self {
+            StatusLevel::Info => Some(INFO_TIMEOUT),
+            StatusLevel::Busy => None,
+            StatusLevel::Error => Some(ERROR_TIMEOUT),
+        }
+    }
+}
+
+/// A message shown in the status line.
+///
+/// # Fields
+/// * `level` - How severe the message is.
+/// * `text` - The message, a single line.
+/// * `expires_at` - When the message is removed, or `None` if it stays until it is cleared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusMessage {
+    pub level: StatusLevel,
+    pub text: String,
+    pub expires_at: Option<Instant>,
+}
+
+/// The status line at the top of the window, showing one message at a time.
+///
+/// A new message replaces the shown one, unless the shown one is more severe and has not
+/// expired yet, so an error is not hidden by the message of whatever happens next.
+#[derive(Debug, Default)]
+pub struct StatusLine {
+    message: Option<StatusMessage>,
+}
+
+impl StatusLine {
+    /// The message shown, if any.
+    pub fn current(&self) -> Option<&StatusMessage> {
+        self.message.as_ref()
+    }
+
+    /// Shows a message, unless a more severe one is shown.
+    ///
+    /// ## Returns
+    /// * Whether the message is shown now.
+    pub fn post(&mut self, level: StatusLevel, text: impl Into<String>, now: Instant) -> bool {
+        self.expire(now);
+        if self.message.as_ref().is_some_and(|message| message.level > level) {
+            return false;
+        }
+
+        self.message = Some(StatusMessage {
+            level,
+            text: text.into(),
+            expires_at: level.timeout().map(|timeout| now + timeout),
+        });
+        true
+    }
+
+    /// Removes the message if it has the given level, e.g. the busy message of a finished download.
+    ///
+    /// ## Returns
+    /// * Whether a message was removed.
+    pub fn clear(&mut self, level: StatusLevel) -> bool {
+        if self.message.as_ref().is_some_and(|message| message.level == level) {
+            self.message = None;
+            return true;
+        }
+        false
+    }
+
+    /// Removes the message once its time is up.
+    ///
+    /// ## Returns
+    /// * Whether a message was removed.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let expired = self.message.as_ref()
+            .and_then(|message| message.expires_at)
+            .is_some_and(|expires_at| now >= expires_at);
+        if expired {
+            self.message = None;
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_expire_after_the_timeout_of_their_level() {
+        let start = Instant::now();
+        let mut status = StatusLine::default();
+
+        assert!(status.post(StatusLevel::Info, "Imported 3 ways", start));
+        assert_eq!(status.current().unwrap().expires_at, Some(start + INFO_TIMEOUT));
+        assert!(!status.expire(start + INFO_TIMEOUT - Duration::from_millis(1)));
+        assert!(status.expire(start + INFO_TIMEOUT));
+        assert_eq!(status.current(), None);
+
+        // A busy message stays until the work is done
+        status.post(StatusLevel::Busy, "Downloading", start);
+        assert!(!status.expire(start + ERROR_TIMEOUT * 10));
+        assert!(!status.clear(StatusLevel::Info));
+        assert!(status.clear(StatusLevel::Busy));
+        assert_eq!(status.current(), None);
+    }
+
+    #[test]
+    fn a_message_only_replaces_one_at_most_as_severe() {
+        let start = Instant::now();
+        let mut status = StatusLine::default();
+
+        status.post(StatusLevel::Busy, "Importing", start);
+        assert!(status.post(StatusLevel::Error, "The import failed", start));
+        assert!(!status.post(StatusLevel::Info, "Loaded the map", start + Duration::from_secs(1)));
+        assert!(!status.post(StatusLevel::Busy, "Downloading", start + Duration::from_secs(1)));
+        assert_eq!(status.current().unwrap().text, "The import failed");
+
+        // A later error replaces the first, and once it expires anything can be shown again
+        assert!(status.post(StatusLevel::Error, "The download failed", start + Duration::from_secs(2)));
+        assert_eq!(status.current().unwrap().expires_at, Some(start + Duration::from_secs(2) + ERROR_TIMEOUT));
+        assert!(status.post(StatusLevel::Info, "Loaded the map", start + Duration::from_secs(2) + ERROR_TIMEOUT));
+        assert_eq!(status.current().unwrap().level, StatusLevel::Info);
+    }
+}
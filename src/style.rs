@@ -0,0 +1,581 @@
+use std::fs;
+
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::osm_entities::Tag;
+
+/// The path of the style sheet loaded at startup and on hot reload.
+pub const STYLE_SHEET_PATH: &str = "utils/style.toml";
+
+/// How a rule matches the value of a tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValuePattern {
+    /// Matches any value, written as `value = "*"` or by leaving the value out.
+    Any,
+    /// Matches exactly one value, e.g. `value = "track"`.
+    Exact(String),
+    /// Matches any of the listed values, e.g. `value = ["primary", "secondary"]`.
+    OneOf(Vec<String>),
+}
+
+impl ValuePattern {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            ValuePattern::Any => true,
+            ValuePattern::Exact(expected) => expected == value,
+            ValuePattern::OneOf(expected) => expected.iter().any(|v| v == value),
+        }
+    }
+
+    /// Higher numbers win when several rules match the same way.
+    fn specificity(&self) -> u8 {
+        match self {
+            ValuePattern::Any => 0,
+            ValuePattern::OneOf(_) => 1,
+            ValuePattern::Exact(_) => 2,
+        }
+    }
+}
+
+/// The way a matched way is drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    /// Linear RGBA color.
+    pub color: [f32; 4],
+    /// Line width in meters, used when the way is not filled.
+    pub width_m: f64,
+    /// Fill the way as a polygon instead of drawing it as a line.
+    pub fill: bool,
+    pub min_zoom: f64,
+    pub max_zoom: f64,
+    /// Ways are drawn in ascending layer order, so higher layers end up on top.
+    pub layer: i32,
+    /// The length of the dashes and of the gaps between them in meters, or `None` for a
+    /// solid line.
+    pub dash: Option<(f64, f64)>,
+    /// Dashes drawn along the middle of the line on top of it, or `None` for none.
+    pub dash_overlay: Option<DashOverlay>,
+}
+
+/// Dashes drawn along the middle of a line on top of it, e.g. the light dashes of a railway.
+///
+/// # Fields
+/// * `color` - Linear RGBA color.
+/// * `width_m` - The width of the dashes in meters, narrower than the line to leave its edges showing.
+/// * `dash` - The length of the dashes and of the gaps between them in meters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashOverlay {
+    pub color: [f32; 4],
+    pub width_m: f64,
+    pub dash: (f64, f64),
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            color: srgb_to_linear([0.6, 0.6, 0.6]),
+            width_m: 2.5,
+            fill: false,
+            min_zoom: 0.0,
+            max_zoom: f64::MAX,
+            layer: 0,
+            dash: None,
+            dash_overlay: None,
+        }
+    }
+}
+
+impl Style {
+    /// Returns true if ways with this style should be drawn at the given zoom level.
+    pub fn visible_at(&self, zoom: f64) -> bool {
+        zoom >= self.min_zoom && zoom <= self.max_zoom
+    }
+}
+
+/// A style applied to ways carrying a tag with `key` and a value matching `value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleRule {
+    pub key: String,
+    pub value: ValuePattern,
+    pub style: Style,
+}
+
+impl StyleRule {
+    fn matches(&self, tags: &[Tag]) -> bool {
+        tags.iter().any(|tag| tag.key == self.key && self.value.matches(&tag.value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleSheet {
+    pub rules: Vec<StyleRule>,
+}
+
+impl Default for StyleSheet {
+    fn default() -> Self {
+        let rule = |key: &str, value: ValuePattern, color: &str, width_m: f64, fill: bool, layer: i32| StyleRule {
+            key: key.to_string(),
+            value,
+            style: Style {
+                color: parse_hex_color(color).unwrap_or(Style::default().color),
+                width_m,
+                fill,
+                layer,
+                ..Style::default()
+            },
+        };
+
+        // Administrative boundaries of an `admin_level`, drawn between the areas and the roads
+        // with dashes ten times and gaps five times as long as they are wide
+        let boundary = |admin_level: &str, color: &str, width_m: f64, min_zoom: f64| StyleRule {
+            key: "admin_level".to_string(),
+            value: ValuePattern::Exact(admin_level.to_string()),
+            style: Style {
+                color: parse_hex_color(color).unwrap_or(Style::default().color),
+                width_m,
+                min_zoom,
+                layer: 1,
+                dash: Some((width_m * 10.0, width_m * 5.0)),
+                ..Style::default()
+            },
+        };
+
+        // Railways are a dark line with light dashes along its middle, drawn above the roads
+        // they cross at level crossings
+        let railway = |value: &str, width_m: f64, min_zoom: f64| StyleRule {
+            key: "railway".to_string(),
+            value: ValuePattern::Exact(value.to_string()),
+            style: Style {
+                color: parse_hex_color("#5c5c5c").unwrap_or(Style::default().color),
+                width_m,
+                min_zoom,
+                layer: 3,
+                dash_overlay: Some(DashOverlay {
+                    color: parse_hex_color("#f4f4f4").unwrap_or(Style::default().color),
+                    width_m: width_m / 2.0,
+                    dash: (width_m * 3.0, width_m * 3.0),
+                }),
+                ..Style::default()
+            },
+        };
+
+        // Ferry routes and aeroways lie below the roads, which cross them on bridges and
+        // in tunnels
+        let below_roads = |key: &str, value: &str, color: &str, width_m: f64, min_zoom: f64, dash: Option<(f64, f64)>| StyleRule {
+            key: key.to_string(),
+            value: ValuePattern::Exact(value.to_string()),
+            style: Style {
+                color: parse_hex_color(color).unwrap_or(Style::default().color),
+                width_m,
+                min_zoom,
+                layer: 1,
+                dash,
+                ..Style::default()
+            },
+        };
+
+        StyleSheet {
+            rules: vec![
+                rule("natural", ValuePattern::Exact("coastline".to_string()), "#2b5f8a", 2.5, false, 1),
+                rule("highway", ValuePattern::Exact("track".to_string()), "#a07850", 6.5, false, 2),
+                rule("highway", ValuePattern::Any, "#ffffff", 5.0, false, 2),
+                rule("building", ValuePattern::Any, "#c9b8a6", 1.0, true, 4),
+                // The parts of a building are filled over it, within the outline of each part
+                rule("building:part", ValuePattern::Any, "#d6c7b5", 1.0, true, 5),
+                railway("rail", 6.0, 10.0),
+                railway("tram", 3.0, 13.0),
+                below_roads("route", "ferry", "#4a7ebb", 4.0, 8.0, Some((40.0, 20.0))),
+                below_roads("aeroway", "runway", "#b4b4bc", 45.0, 10.0, None),
+                below_roads("aeroway", "taxiway", "#c4c4cc", 18.0, 12.0, None),
+                boundary("2", "#8e5ea2", 30.0, 0.0),
+                boundary("4", "#8e5ea2", 20.0, 6.0),
+                boundary("6", "#a57db5", 12.0, 9.0),
+                boundary("8", "#a57db5", 6.0, 11.0),
+            ],
+        }
+    }
+}
+
+impl StyleSheet {
+    /// Finds the style for a way with the given tags.
+    ///
+    /// The most specific matching rule wins (`key = value` beats `key = [values]` beats `key = *`),
+    /// ties are won by the rule listed first.
+    ///
+    /// ## Returns
+    /// * The style of the best matching rule, or `None` if no rule matches.
+    pub fn style_for(&self, tags: &[Tag]) -> Option<&Style> {
+        let mut best: Option<&StyleRule> = None;
+
+        for rule in &self.rules {
+            if !rule.matches(tags) {
+                continue;
+            }
+            match best {
+                Some(current) if current.value.specificity() >= rule.value.specificity() => (),
+                _ => best = Some(rule),
+            }
+        }
+
+        best.map(|rule| &rule.style)
+    }
+
+    /// Parses a style sheet from TOML.
+    pub fn from_toml(source: &str) -> Result<Self, String> {
+        let raw: RawStyleSheet = toml::from_str(source).map_err(|error| error.to_string())?;
+
+        let rules = raw.rules.into_iter()
+            .map(RawStyleRule::into_rule)
+            .collect::<Result<Vec<StyleRule>, String>>()?;
+
+        Ok(StyleSheet { rules })
+    }
+
+    /// Loads the style sheet from `path`, falling back to the built-in default
+    /// when the file is missing or cannot be parsed.
+    pub fn load_or_default(path: &str) -> Self {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                warn!(path, %error, "could not read the style sheet, using the default style");
+                return StyleSheet::default();
+            }
+        };
+
+        match StyleSheet::from_toml(&source) {
+            Ok(style_sheet) => {
+                debug!(path, rules = style_sheet.rules.len(), "loaded style sheet");
+                style_sheet
+            }
+            Err(error) => {
+                warn!(path, %error, "invalid style sheet, using the default style");
+                StyleSheet::default()
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawStyleSheet {
+    #[serde(default)]
+    rules: Vec<RawStyleRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawValuePattern {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStyleRule {
+    key: String,
+    value: Option<RawValuePattern>,
+    color: String,
+    width_m: Option<f64>,
+    fill: Option<bool>,
+    min_zoom: Option<f64>,
+    max_zoom: Option<f64>,
+    layer: Option<i32>,
+    dash_m: Option<f64>,
+    gap_m: Option<f64>,
+    overlay_color: Option<String>,
+    overlay_width_m: Option<f64>,
+    overlay_dash_m: Option<f64>,
+    overlay_gap_m: Option<f64>,
+}
+
+impl RawStyleRule {
+    fn into_rule(self) -> Result<StyleRule, String> {
+        let value = match self.value {
+            None => ValuePattern::Any,
+            Some(RawValuePattern::One(value)) if value == "*" => ValuePattern::Any,
+            Some(RawValuePattern::One(value)) => ValuePattern::Exact(value),
+            Some(RawValuePattern::Many(values)) => ValuePattern::OneOf(values),
+        };
+
+        let color = parse_hex_color(&self.color)
+            .ok_or_else(|| format!("invalid color '{}' in rule for key '{}'", self.color, self.key))?;
+
+        // A dashed line without a gap length has gaps as long as its dashes
+        let dash = match (self.dash_m, self.gap_m) {
+            (None, None) => None,
+            (Some(dash_m), gap_m) if dash_m > 0.0 && gap_m.unwrap_or(dash_m) >= 0.0 => Some((dash_m, gap_m.unwrap_or(dash_m))),
+            (None, Some(_)) => return Err(format!("gap_m without dash_m in rule for key '{}'", self.key)),
+            _ => return Err(format!("invalid dash_m or gap_m in rule for key '{}'", self.key)),
+        };
+
+        let default_style = Style::default();
+        let width_m = self.width_m.unwrap_or(default_style.width_m);
+
+        // The dashes on top are half as wide as the line unless given, with gaps as long as the dashes
+        let dash_overlay = match (self.overlay_color, self.overlay_dash_m) {
+            (None, None) if self.overlay_width_m.is_none() && self.overlay_gap_m.is_none() => None,
+            (Some(overlay_color), Some(dash_m)) => {
+                let color = parse_hex_color(&overlay_color)
+                    .ok_or_else(|| format!("invalid overlay_color '{}' in rule for key '{}'", overlay_color, self.key))?;
+                let overlay_width_m = self.overlay_width_m.unwrap_or(width_m / 2.0);
+                let gap_m = self.overlay_gap_m.unwrap_or(dash_m);
+                if dash_m <= 0.0 || gap_m < 0.0 || overlay_width_m <= 0.0 {
+                    return Err(format!("invalid overlay_dash_m, overlay_gap_m or overlay_width_m in rule for key '{}'", self.key));
+                }
+                Some(DashOverlay { color, width_m: overlay_width_m, dash: (dash_m, gap_m) })
+            }
+            _ => return Err(format!("an overlay needs both overlay_color and overlay_dash_m in rule for key '{}'", self.key)),
+        };
+
+        Ok(StyleRule {
+            key: self.key,
+            value,
+            style: Style {
+                color,
+                width_m,
+                fill: self.fill.unwrap_or(default_style.fill),
+                min_zoom: self.min_zoom.unwrap_or(default_style.min_zoom),
+                max_zoom: self.max_zoom.unwrap_or(default_style.max_zoom),
+                layer: self.layer.unwrap_or(default_style.layer),
+                dash,
+                dash_overlay,
+            },
+        })
+    }
+}
+
+/// The height assumed per level for buildings tagged with `building:levels` but no `height`.
+pub const METERS_PER_LEVEL: f64 = 3.0;
+
+/// Parses a height in meters, as written in `height` tags, e.g. `12`, `12.5` or `12 m`.
+///
+/// ## Returns
+/// * The height in meters, or `None` if the value is not a positive number of meters.
+pub fn parse_height_m(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = value.strip_suffix('m').unwrap_or(value).trim();
+    let height: f64 = number.parse().ok()?;
+
+    (height.is_finite() && height > 0.0).then_some(height)
+}
+
+/// Finds the height of a building from its tags. The `height` tag is used if it can be
+/// parsed, otherwise `building:levels` times `METERS_PER_LEVEL`.
+///
+/// ## Returns
+/// * The height in meters, or `None` if neither tag holds a usable value.
+pub fn building_height_m(tags: &[Tag]) -> Option<f64> {
+    let tag_value = |key: &str| tags.iter().find(|tag| tag.key == key).map(|tag| tag.value.as_str());
+
+    if let Some(height) = tag_value("height").and_then(parse_height_m) {
+        return Some(height);
+    }
+
+    let levels: f64 = tag_value("building:levels")?.trim().parse().ok()?;
+    (levels.is_finite() && levels > 0.0).then_some(levels * METERS_PER_LEVEL)
+}
+
+/// Converts an sRGB color component triple to linear RGBA, as expected by the sRGB surface.
+pub fn srgb_to_linear(rgb: [f32; 3]) -> [f32; 4] {
+    let convert = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    [convert(rgb[0]), convert(rgb[1]), convert(rgb[2]), 1.0]
+}
+
+/// Converts a linear RGBA color back to an sRGB component triple, dropping the alpha.
+pub fn linear_to_srgb(color: [f32; 4]) -> [f32; 3] {
+    let convert = |c: f32| {
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+
+    [convert(color[0]), convert(color[1]), convert(color[2])]
+}
+
+/// Parses an sRGB color written as `#rrggbb`.
+///
+/// ## Returns
+/// * The color as linear RGBA, or `None` if the string is not a valid color.
+pub fn parse_hex_color(color: &str) -> Option<[f32; 4]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(|c| c as f32 / 255.0);
+
+    Some(srgb_to_linear([component(0)?, component(2)?, component(4)?]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> Vec<Tag> {
+        pairs.iter().map(|&(key, value)| Tag::new(key.to_string(), value.to_string())).collect()
+    }
+
+    const RULES: &str = r##"
+        [[rules]]
+        key = "highway"
+        color = "#000001"
+
+        [[rules]]
+        key = "highway"
+        value = ["primary", "secondary"]
+        color = "#000002"
+
+        [[rules]]
+        key = "highway"
+        value = "primary"
+        color = "#000003"
+        width_m = 8.0
+
+        [[rules]]
+        key = "highway"
+        value = "primary"
+        color = "#000004"
+
+        [[rules]]
+        key = "building"
+        value = "*"
+        color = "#000005"
+        fill = true
+        min_zoom = 14.0
+    "##;
+
+    #[test]
+    fn the_most_specific_rule_wins_and_ties_go_to_the_first() {
+        let style_sheet = StyleSheet::from_toml(RULES).unwrap();
+        let color = |pairs: &[(&str, &str)]| style_sheet.style_for(&tags(pairs)).map(|style| style.color);
+
+        assert_eq!(color(&[("highway", "primary")]), parse_hex_color("#000003"));
+        assert_eq!(color(&[("highway", "secondary")]), parse_hex_color("#000002"));
+        assert_eq!(color(&[("highway", "track")]), parse_hex_color("#000001"));
+        assert_eq!(color(&[("name", "Main Street")]), None);
+
+        let building = style_sheet.style_for(&tags(&[("building", "yes")])).unwrap();
+        assert!(building.fill && !building.visible_at(13.0) && building.visible_at(14.0));
+        assert_eq!(style_sheet.style_for(&tags(&[("highway", "primary")])).unwrap().width_m, 8.0);
+    }
+
+    #[test]
+    fn railways_ferries_and_aeroways_have_styles_of_their_own() {
+        let style_sheet = StyleSheet::default();
+        let road = style_sheet.style_for(&tags(&[("highway", "primary")])).unwrap().clone();
+        let style = |key: &str, value: &str| style_sheet.style_for(&tags(&[(key, value)])).unwrap().clone();
+
+        // (key, value, dashed, with dashes on top, above the roads, min zoom)
+        let table = [
+            ("railway", "rail", false, true, true, 10.0),
+            ("railway", "tram", false, true, true, 13.0),
+            ("route", "ferry", true, false, false, 8.0),
+            ("aeroway", "runway", false, false, false, 10.0),
+            ("aeroway", "taxiway", false, false, false, 12.0),
+        ];
+        for (key, value, dashed, dash_overlay, above_roads, min_zoom) in table {
+            let style = style(key, value);
+            assert_ne!(style.color, road.color, "{}={}", key, value);
+            assert_eq!(style.dash.is_some(), dashed, "{}={}", key, value);
+            assert_eq!(style.dash_overlay.is_some(), dash_overlay, "{}={}", key, value);
+            assert_eq!(style.layer > road.layer, above_roads, "{}={}", key, value);
+            assert!(!style.visible_at(min_zoom - 0.5) && style.visible_at(min_zoom), "{}={}", key, value);
+            assert!(!style.fill, "{}={}", key, value);
+        }
+
+        // Runways are strips wider than any road
+        assert!(style("aeroway", "runway").width_m > 4.0 * road.width_m);
+        // Railways without a style of their own, e.g. disused ones, are not drawn
+        assert!(style_sheet.style_for(&tags(&[("railway", "abandoned")])).is_none());
+    }
+
+    #[test]
+    fn boundaries_thin_out_and_appear_later_with_their_admin_level() {
+        let style_sheet = StyleSheet::default();
+        let boundary = |admin_level: &str| style_sheet.style_for(&tags(&[("type", "boundary"), ("boundary", "administrative"), ("admin_level", admin_level)])).unwrap();
+        let (country, municipality) = (boundary("2"), boundary("8"));
+
+        assert!(country.width_m > municipality.width_m);
+        assert!(country.visible_at(5.0) && !municipality.visible_at(5.0) && municipality.visible_at(11.0));
+        assert!(country.dash.is_some() && municipality.dash.is_some());
+
+        // Above the areas and below the roads
+        let road = style_sheet.style_for(&tags(&[("highway", "primary")])).unwrap();
+        assert!(Style::default().layer < country.layer && country.layer < road.layer);
+        assert_eq!(country.layer, municipality.layer);
+    }
+
+    #[test]
+    fn building_parts_are_filled_over_their_building() {
+        let style_sheet = StyleSheet::default();
+        let building = style_sheet.style_for(&tags(&[("building", "yes")])).unwrap();
+        let part = style_sheet.style_for(&tags(&[("building:part", "yes"), ("building:levels", "5")])).unwrap();
+
+        assert!(part.fill && part.layer > building.layer);
+        assert_ne!(part.color, building.color);
+        assert_eq!(crate::layers::MapLayer::of_tags(&tags(&[("building:part", "yes")])), crate::layers::MapLayer::Buildings);
+
+        // The checked in style sheet does the same
+        let checked_in = StyleSheet::load_or_default(STYLE_SHEET_PATH);
+        assert!(checked_in.style_for(&tags(&[("building:part", "yes")])).unwrap().layer > checked_in.style_for(&tags(&[("building", "yes")])).unwrap().layer);
+    }
+
+    #[test]
+    fn a_corrupt_style_sheet_falls_back_to_the_default() {
+        let error = StyleSheet::from_toml("[[rules]]\nkey = \"highway\"\ncolor = \n").unwrap_err();
+        assert!(error.contains("line 3"), "{}", error);
+        assert!(StyleSheet::from_toml("[[rules]]\nkey = \"highway\"\ncolor = \"red\"\n").unwrap_err().contains("invalid color"));
+        assert!(StyleSheet::from_toml("[[rules]]\nkey = \"highway\"\ncolor = \"#ffffff\"\nwidth = 3\n").is_err());
+
+        let path = std::env::temp_dir().join(format!("gmc_corrupt_style_{}.toml", std::process::id()));
+        std::fs::write(&path, "[[rules]\nkey = ").unwrap();
+        assert_eq!(StyleSheet::load_or_default(path.to_str().unwrap()), StyleSheet::default());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(StyleSheet::load_or_default("utils/no_such_style.toml"), StyleSheet::default());
+    }
+
+    #[test]
+    fn the_checked_in_style_sheet_loads() {
+        let source = std::fs::read_to_string(STYLE_SHEET_PATH).unwrap();
+        assert!(!StyleSheet::from_toml(&source).unwrap().rules.is_empty());
+    }
+
+    #[test]
+    fn heights_are_read_in_meters_with_or_without_the_unit() {
+        let table = [
+            ("12", Some(12.0)),
+            ("12.5", Some(12.5)),
+            ("12 m", Some(12.0)),
+            (" 7m ", Some(7.0)),
+            ("0", None),
+            ("-3", None),
+            ("inf", None),
+            ("NaN", None),
+            ("tall", None),
+            ("12 ft", None),
+            ("", None),
+        ];
+        for (value, expected) in table {
+            assert_eq!(parse_height_m(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn a_building_without_a_usable_height_falls_back_to_its_levels() {
+        assert_eq!(building_height_m(&tags(&[("height", "12 m"), ("building:levels", "2")])), Some(12.0));
+        assert_eq!(building_height_m(&tags(&[("height", "tall"), ("building:levels", "2")])), Some(2.0 * METERS_PER_LEVEL));
+        assert_eq!(building_height_m(&tags(&[("building:levels", " 3 ")])), Some(3.0 * METERS_PER_LEVEL));
+        assert_eq!(building_height_m(&tags(&[("height", "tall"), ("building:levels", "two")])), None);
+        assert_eq!(building_height_m(&tags(&[("building:levels", "0")])), None);
+        assert_eq!(building_height_m(&tags(&[("building", "yes")])), None);
+    }
+}
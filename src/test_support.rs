@@ -0,0 +1,164 @@
+use std::fmt::Write;
+
+use crate::geo::BBox;
+use crate::osm_entities::{Node, RenderableWay, SimpleNode, Tag, Way};
+
+/// The box all synthetic data lies within, a few kilometers across.
+pub const SYNTHETIC_BBOX: BBox = BBox { min_lat: 55.65, max_lat: 55.72, min_lon: 12.50, max_lon: 12.62 };
+
+// Every this many nodes gets a tag, about as often as in real extracts
+const TAGGED_NODE_EVERY: usize = 10;
+
+/// A small deterministic random number generator, so synthetic data is the same on every run.
+pub struct SyntheticRng(u64);
+
+impl SyntheticRng {
+    pub fn new(seed: u64) -> Self {
+        SyntheticRng(seed.max(1))
+    }
+
+    /// The next number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A random point within the synthetic box.
+    pub fn next_point(&mut self) -> (f64, f64) {
+        let lat = SYNTHETIC_BBOX.min_lat + self.next_f64() * (SYNTHETIC_BBOX.max_lat - SYNTHETIC_BBOX.min_lat);
+        let lon = SYNTHETIC_BBOX.min_lon + self.next_f64() * (SYNTHETIC_BBOX.max_lon - SYNTHETIC_BBOX.min_lon);
+        (lat, lon)
+    }
+}
+
+fn tag(key: &str, value: &str) -> Tag {
+    Tag::new(key.to_string(), value.to_string())
+}
+
+/// Generates nodes spread over the synthetic box with ids from 1, every tenth of them tagged.
+pub fn synthetic_nodes(count: usize) -> Vec<Node> {
+    let mut rng = SyntheticRng::new(1);
+
+    (0..count)
+        .map(|index| {
+            let (lat, lon) = rng.next_point();
+            let tags = if index % TAGGED_NODE_EVERY == 0 {
+                vec![tag("amenity", "bench"), tag("name", &format!("Bench {}", index))]
+            } else {
+                Vec::new()
+            };
+            Node::new(index as i64 + 1, lat, lon, 1, "2024-01-01T00:00:00Z".to_string(), 1, 1, "synthetic".to_string(), tags)
+        })
+        .collect()
+}
+
+/// Generates ways running through consecutive runs of `nodes`, e.g. those of `synthetic_nodes`.
+///
+/// ## Arguments
+/// * `nodes` - The nodes the ways refer to.
+/// * `nodes_per_way` - How many nodes each way refers to, the last way may get fewer.
+pub fn synthetic_ways(nodes: &[Node], nodes_per_way: usize) -> Vec<Way> {
+    nodes.chunks(nodes_per_way.max(2))
+        .enumerate()
+        .map(|(index, chunk)| {
+            let node_refs = chunk.iter().map(|node| node.id).collect();
+            Way::new(index as i64 + 1, 1, "2024-01-01T00:00:00Z".to_string(), 1, 1, "synthetic".to_string(), node_refs, vec![tag("highway", "residential")])
+        })
+        .collect()
+}
+
+/// Writes nodes as an OSM XML file, as read by `read_nodes_from_bytes`.
+pub fn synthetic_osm_xml(nodes: &[Node]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<osm version=\"0.6\" generator=\"synthetic\">\n");
+
+    for node in nodes {
+        let _ = write!(
+            xml,
+            " <node id=\"{}\" visible=\"true\" version=\"{}\" changeset=\"{}\" timestamp=\"{}\" user=\"{}\" uid=\"{}\" lat=\"{:.7}\" lon=\"{:.7}\"",
+            node.id, node.version, node.changeset, node.timestamp, node.user, node.uid, node.lat, node.lon,
+        );
+        if node.tags.is_empty() {
+            xml.push_str("/>\n");
+            continue;
+        }
+
+        xml.push_str(">\n");
+        for tag in &node.tags {
+            let _ = writeln!(xml, "  <tag k=\"{}\" v=\"{}\"/>", tag.key, tag.value);
+        }
+        xml.push_str(" </node>\n");
+    }
+
+    xml.push_str("</osm>\n");
+    xml
+}
+
+/// Generates ways ready to be drawn, a mix of roads, tracks, buildings with levels,
+/// coastlines and unstyled waterways, so every path through the tessellation is taken.
+pub fn synthetic_renderable_ways(count: usize) -> Vec<RenderableWay> {
+    let mut rng = SyntheticRng::new(2);
+    let mut next_node_id = 1;
+    let mut simple_node = |(lat, lon): (f64, f64)| {
+        next_node_id += 1;
+        SimpleNode { id: Some(next_node_id), lat, lon }
+    };
+
+    (0..count)
+        .map(|index| {
+            let start = rng.next_point();
+            let (nodes, tags) = match index % 5 {
+                // A building is a closed square about 15 meters across
+                0 => {
+                    let size = 0.00015;
+                    let corners: Vec<SimpleNode> = [(0.0, 0.0), (0.0, size), (size, size), (size, 0.0)].iter()
+                        .map(|&(dlat, dlon)| simple_node((start.0 + dlat, start.1 + dlon)))
+                        .collect();
+                    let mut nodes = corners.clone();
+                    nodes.push(corners[0].clone());
+                    (nodes, vec![tag("building", "yes"), tag("building:levels", "3")])
+                }
+                kind => {
+                    // Lines wander in short steps
+                    let mut point = start;
+                    let nodes = (0..8)
+                        .map(|_| {
+                            point = (point.0 + (rng.next_f64() - 0.5) * 0.002, point.1 + (rng.next_f64() - 0.5) * 0.002);
+                            simple_node(point)
+                        })
+                        .collect();
+                    let tags = match kind {
+                        1 => vec![tag("highway", "residential"), tag("name", &format!("Street {}", index))],
+                        2 => vec![tag("highway", "track")],
+                        3 => vec![tag("natural", "coastline")],
+                        _ => vec![tag("waterway", "stream")],
+                    };
+                    (nodes, tags)
+                }
+            };
+
+            RenderableWay::from_nodes(index as i64 + 1, &nodes, tags, 0)
+        })
+        .collect()
+}
+
+/// A database in memory with every table, for the tests of what is stored. Every connection
+/// to the same name shares one database, so every test names its own.
+#[cfg(test)]
+pub async fn memory_pool(name: &str) -> sqlx::SqlitePool {
+    let pool = crate::database::connect_pool(&format!("sqlite://file:{}?mode=memory&cache=shared", name)).await.unwrap();
+    crate::database::create_tables(&pool).await.unwrap();
+    pool
+}
+
+/// Imports OSM XML into the database like a file, for the tests of what is read back.
+#[cfg(test)]
+pub async fn import_osm_xml(pool: &sqlx::SqlitePool, name: &str, xml: &str) -> crate::fetcher::ImportStats {
+    let path = std::env::temp_dir().join(format!("gmc_{}_{}.osm", name, std::process::id()));
+    std::fs::write(&path, xml).unwrap();
+    let stats = crate::fetcher::process_map_file(pool, path.to_str().unwrap(), &crate::fetcher::ImportOptions::default()).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    stats
+}
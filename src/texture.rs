@@ -1,6 +1,14 @@
 use image::GenericImageView;
 use anyhow::*;
 
+/// The icon drawn at points of interest. Everything else is flat colored.
+pub const NODE_TEXTURE: &[u8] = include_bytes!("../utils/textures/node.png");
+
+/// The textures built into the program, as `(file name, bytes)`.
+pub const EMBEDDED_TEXTURES: [(&str, &[u8]); 1] = [
+    ("node.png", NODE_TEXTURE),
+];
+
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
@@ -9,6 +17,54 @@ pub struct Texture {
 }
 
 impl Texture {
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    /// Creates a depth texture matching the size of the surface. It has to be recreated
+    /// whenever the surface is resized.
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+        Self::create_depth_texture_of_size(device, config.width, config.height, label)
+    }
+
+    /// Creates a depth texture of the given size, e.g. for drawing into a texture instead of a surface.
+    pub fn create_depth_texture_of_size(device: &wgpu::Device, width: u32, height: u32, label: &str) -> Self {
+        // A window that is not shown yet has no size, but a texture needs at least one pixel
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            }
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(
+            &wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                compare: Some(wgpu::CompareFunction::LessEqual),
+                lod_min_clamp: 0.0,
+                lod_max_clamp: 100.0,
+                ..Default::default()
+            }
+        );
+
+        Self { texture, view, sampler }
+    }
+
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::warn;
+
+use crate::coastline::Surface;
+use crate::style::{linear_to_srgb, parse_hex_color, srgb_to_linear};
+
+/// The key the selected theme is saved under in the settings table.
+pub const THEME_SETTING: &str = "theme";
+
+/// The number of colors the palette uniform holds, see `shader.wgsl`.
+pub const MAX_PALETTE_COLORS: usize = 256;
+
+// The dark theme dims and dulls the colors of the style sheet, the high contrast theme
+// lifts them away from its black background and saturates them.
+const DARK_LIGHTNESS: f32 = 0.55;
+const DARK_SATURATION: f32 = 0.8;
+const HIGH_CONTRAST_MIN_LIGHTNESS: f32 = 0.4;
+const HIGH_CONTRAST_SATURATION: f32 = 1.5;
+
+// The background of a viewport known to be all land or all water, in the light theme
+const LAND_BACKGROUND_COLOR: &str = "#f2efe9";
+const WATER_BACKGROUND_COLOR: &str = "#aad3df";
+
+/// The colors the map is drawn in. The style sheet gives the colors of the light theme and
+/// the other themes are derived from them, so every style sheet works with every theme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    Light,
+    /// Darker and duller colors, for using the map at night.
+    Dark,
+    /// Brighter and fully saturated colors on a black background.
+    HighContrast,
+}
+
+impl Theme {
+    /// The theme after this one, starting over after the last.
+    pub fn next(self) -> Theme {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark => Theme::HighContrast,
+            Theme::HighContrast => Theme::Light,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Theme::Light => "light",
+            Theme::Dark => "dark",
+            Theme::HighContrast => "high_contrast",
+        }
+    }
+
+    /// The color the window is cleared with before the map is drawn, in linear RGBA.
+    pub fn clear_color(self) -> wgpu::Color {
+        match self {
+            Theme::Light => wgpu::Color { r: 0.1, g: 0.2, b: 0.3, a: 1.0 },
+            Theme::Dark => wgpu::Color { r: 0.01, g: 0.02, b: 0.04, a: 1.0 },
+            Theme::HighContrast => wgpu::Color::BLACK,
+        }
+    }
+
+    /// The color the window is cleared with behind a viewport, see `viewport_surface`. A
+    /// viewport not known to be land or water gets the neutral `clear_color`, and so does
+    /// land in the high contrast theme, which keeps its black background.
+    pub fn background_color(self, surface: Option<Surface>) -> wgpu::Color {
+        let color = match (self, surface) {
+            (_, None) | (Theme::HighContrast, Some(Surface::Land)) => return self.clear_color(),
+            (_, Some(Surface::Land)) => LAND_BACKGROUND_COLOR,
+            (_, Some(Surface::Water)) => WATER_BACKGROUND_COLOR,
+        };
+        let [r, g, b, a] = self.apply(parse_hex_color(color).unwrap_or([0.0, 0.0, 0.0, 1.0]));
+        wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: a as f64 }
+    }
+
+    /// Remaps a color of the style sheet to this theme, keeping its hue.
+    ///
+    /// ## Arguments
+    /// * `color` - The linear RGBA color given by the style sheet.
+    pub fn apply(self, color: [f32; 4]) -> [f32; 4] {
+        let (hue, saturation, lightness) = rgb_to_hsl(linear_to_srgb(color));
+        let (saturation, lightness) = match self {
+            Theme::Light => return color,
+            Theme::Dark => (saturation * DARK_SATURATION, lightness * DARK_LIGHTNESS),
+            Theme::HighContrast => (
+                (saturation * HIGH_CONTRAST_SATURATION).min(1.0),
+                HIGH_CONTRAST_MIN_LIGHTNESS + (1.0 - HIGH_CONTRAST_MIN_LIGHTNESS) * lightness,
+            ),
+        };
+
+        let [r, g, b, _] = srgb_to_linear(hsl_to_rgb(hue, saturation, lightness));
+        [r, g, b, color[3]]
+    }
+}
+
+// Saved by name
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            "high_contrast" => Ok(Theme::HighContrast),
+            other => Err(format!("'{}' is not a theme, expected light, dark or high_contrast", other)),
+        }
+    }
+}
+
+/// Converts an sRGB color to `(hue, saturation, lightness)`, with the hue in `[0, 6)` sextants.
+fn rgb_to_hsl([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+
+    (hue, saturation.min(1.0), lightness)
+}
+
+/// Converts `(hue, saturation, lightness)` as returned by `rgb_to_hsl` back to sRGB.
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = lightness - chroma / 2.0;
+    [r + m, g + m, b + m]
+}
+
+/// The colors vertices refer to by their index, so a new theme only rewrites the palette
+/// uniform instead of tessellating the map again.
+///
+/// # Fields
+/// * `colors` - The linear RGBA base colors, with whether the theme remaps them. The colors
+///   of overlays, like the status bar, keep their meaning in every theme.
+/// * `indices` - The index of every color, looked up by its bits.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    colors: Vec<([f32; 4], bool)>,
+    indices: HashMap<([u32; 4], bool), u32>,
+}
+
+impl Palette {
+    /// Starts a palette with the color used for colors missing from it at index 0.
+    pub fn new(fallback: [f32; 4], themed: bool) -> Self {
+        let mut palette = Palette { colors: Vec::new(), indices: HashMap::new() };
+        palette.add(fallback, themed);
+        palette
+    }
+
+    /// Adds a color, unless it is in the palette already.
+    ///
+    /// ## Arguments
+    /// * `color` - The linear RGBA base color.
+    /// * `themed` - Whether the theme remaps the color, as it does for the colors of the style sheet.
+    ///
+    /// ## Returns
+    /// * The index of the color, or 0 if the palette is full.
+    pub fn add(&mut self, color: [f32; 4], themed: bool) -> u32 {
+        let key = (color.map(f32::to_bits), themed);
+        if let Some(&index) = self.indices.get(&key) {
+            return index;
+        }
+        if self.colors.len() == MAX_PALETTE_COLORS {
+            warn!(?color, max = MAX_PALETTE_COLORS, "the palette is full, using the fallback color");
+            return 0;
+        }
+
+        let index = self.colors.len() as u32;
+        self.colors.push((color, themed));
+        self.indices.insert(key, index);
+        index
+    }
+
+    /// Whether a color was added with `add`.
+    pub fn contains(&self, color: [f32; 4], themed: bool) -> bool {
+        self.indices.contains_key(&(color.map(f32::to_bits), themed))
+    }
+
+    /// The index of a color added with `add`, or 0 for the fallback color if it was not.
+    pub fn index_of(&self, color: [f32; 4], themed: bool) -> u32 {
+        self.indices.get(&(color.map(f32::to_bits), themed)).copied().unwrap_or(0)
+    }
+
+    /// The colors of the palette in a theme, padded to `MAX_PALETTE_COLORS` for the uniform.
+    pub fn resolve(&self, theme: Theme) -> Vec<[f32; 4]> {
+        let mut colors: Vec<[f32; 4]> = self.colors.iter()
+            .map(|&(color, themed)| if themed { theme.apply(color) } else { color })
+            .collect();
+        colors.resize(MAX_PALETTE_COLORS, [0.0; 4]);
+        colors
+    }
+
+    /// The colors of the palette in a theme like `resolve`, with the colors the theme remaps
+    /// mixed `amount` of the way towards `background`, e.g. to push the map back behind a
+    /// highlight. The colors of overlays keep theirs.
+    pub fn resolve_dimmed(&self, theme: Theme, background: [f32; 4], amount: f32) -> Vec<[f32; 4]> {
+        let mut colors = self.resolve(theme);
+        for (color, &(_, themed)) in colors.iter_mut().zip(&self.colors) {
+            if themed {
+                for channel in 0..3 {
+                    color[channel] += (background[channel] - color[channel]) * amount;
+                }
+            }
+        }
+        colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{fetch_setting, save_setting};
+    use crate::test_support::memory_pool;
+
+    const RED: [f32; 4] = [0.8, 0.1, 0.1, 1.0];
+    const BLUE: [f32; 4] = [0.1, 0.2, 0.7, 0.5];
+
+    #[test]
+    fn next_cycles_through_every_theme() {
+        assert_eq!(Theme::Light.next(), Theme::Dark);
+        assert_eq!(Theme::Dark.next(), Theme::HighContrast);
+        assert_eq!(Theme::HighContrast.next(), Theme::Light);
+    }
+
+    #[test]
+    fn themes_are_parsed_from_their_names() {
+        for theme in [Theme::Light, Theme::Dark, Theme::HighContrast] {
+            assert_eq!(theme.to_string().parse::<Theme>(), Ok(theme));
+        }
+        assert_eq!(" dark\n".parse::<Theme>(), Ok(Theme::Dark));
+        assert!("sepia".parse::<Theme>().is_err());
+        assert!("".parse::<Theme>().is_err());
+    }
+
+    #[test]
+    fn the_light_theme_keeps_the_colors_and_the_others_keep_hue_and_alpha() {
+        assert_eq!(Theme::Light.apply(BLUE), BLUE);
+
+        let dark = Theme::Dark.apply(BLUE);
+        let high_contrast = Theme::HighContrast.apply(BLUE);
+        for color in [dark, high_contrast] {
+            assert_eq!(color[3], BLUE[3]);
+            // Blue stays the strongest channel
+            assert!(color[2] > color[0] && color[2] > color[1], "{:?}", color);
+        }
+        let lightness = |color: [f32; 4]| rgb_to_hsl(linear_to_srgb(color)).2;
+        assert!(lightness(dark) < lightness(BLUE));
+        assert!(lightness(high_contrast) > lightness(BLUE));
+    }
+
+    #[test]
+    fn colors_survive_the_trip_through_hsl() {
+        for rgb in [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [0.9, 0.2, 0.4], [0.1, 0.8, 0.3], [0.5, 0.5, 0.5], [0.2, 0.3, 0.95]] {
+            let (hue, saturation, lightness) = rgb_to_hsl(rgb);
+            let back = hsl_to_rgb(hue, saturation, lightness);
+            for channel in 0..3 {
+                assert!((back[channel] - rgb[channel]).abs() < 1e-5, "{:?} became {:?}", rgb, back);
+            }
+        }
+    }
+
+    #[test]
+    fn the_palette_looks_up_colors_by_value_and_theming() {
+        let mut palette = Palette::new([0.0, 0.0, 0.0, 1.0], true);
+        assert_eq!(palette.index_of([0.0, 0.0, 0.0, 1.0], true), 0);
+
+        assert_eq!(palette.add(RED, true), 1);
+        assert_eq!(palette.add(BLUE, true), 2);
+        // The same color is stored once, unless one use of it is themed and the other is not
+        assert_eq!(palette.add(RED, true), 1);
+        assert_eq!(palette.add(RED, false), 3);
+
+        assert!(palette.contains(BLUE, true));
+        assert!(!palette.contains(BLUE, false));
+        assert_eq!(palette.index_of(RED, false), 3);
+        assert_eq!(palette.index_of(BLUE, true), 2);
+        // Colors never added fall back to the first
+        assert_eq!(palette.index_of([0.3, 0.3, 0.3, 1.0], true), 0);
+    }
+
+    #[test]
+    fn a_full_palette_hands_out_the_fallback() {
+        let mut palette = Palette::new([0.0, 0.0, 0.0, 1.0], false);
+        for index in 1..MAX_PALETTE_COLORS {
+            assert_eq!(palette.add([index as f32, 0.0, 0.0, 1.0], false), index as u32);
+        }
+
+        assert_eq!(palette.add([-1.0, 0.0, 0.0, 1.0], false), 0);
+        assert!(!palette.contains([-1.0, 0.0, 0.0, 1.0], false));
+        // Colors already in it are still found
+        assert_eq!(palette.add([7.0, 0.0, 0.0, 1.0], false), 7);
+    }
+
+    #[test]
+    fn resolving_remaps_only_the_themed_colors() {
+        let mut palette = Palette::new([0.0, 0.0, 0.0, 1.0], true);
+        let themed = palette.add(BLUE, true) as usize;
+        let overlay = palette.add(RED, false) as usize;
+
+        let colors = palette.resolve(Theme::Dark);
+        assert_eq!(colors.len(), MAX_PALETTE_COLORS);
+        assert_eq!(colors[themed], Theme::Dark.apply(BLUE));
+        assert_eq!(colors[overlay], RED);
+        assert_eq!(colors[overlay + 1], [0.0; 4]);
+        assert_eq!(palette.resolve(Theme::Light)[themed], BLUE);
+
+        let background = [1.0, 1.0, 1.0, 1.0];
+        let dimmed = palette.resolve_dimmed(Theme::Light, background, 0.5);
+        assert_eq!(dimmed[themed], [0.55, 0.6, 0.85, 0.5]);
+        assert_eq!(dimmed[overlay], RED);
+    }
+
+    #[tokio::test]
+    async fn the_selected_theme_is_saved_by_name() {
+        let pool = memory_pool("theme_setting").await;
+        assert_eq!(fetch_setting(&pool, THEME_SETTING).await.unwrap(), None);
+
+        for theme in [Theme::HighContrast, Theme::Dark] {
+            save_setting(&pool, THEME_SETTING, theme.as_str()).await.unwrap();
+            let saved = fetch_setting(&pool, THEME_SETTING).await.unwrap().unwrap();
+            assert_eq!(saved.parse::<Theme>(), Ok(theme));
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::env;
+use std::future::Future;
+use std::io;
+use std::num::NonZeroUsize;
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use sqlx::SqlitePool;
+use tracing::{info, warn};
+
+use crate::events::{AppEvent, EventSender};
+
+/// The environment variable setting how many threads tessellate the map.
+pub const TESSELLATION_THREADS_ENV: &str = "GMC_TESSELLATION_THREADS";
+
+static TESSELLATION_POOL: OnceLock<Option<ThreadPool>> = OnceLock::new();
+
+/// How many threads tessellate the map by default: one fewer than there are cores, so the
+/// thread drawing the frames is not starved, but at least one.
+pub fn default_tessellation_threads() -> usize {
+    let cores = thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1);
+    cores.saturating_sub(1).max(1)
+}
+
+/// Parses a thread count of at least one.
+pub fn parse_thread_count(value: &str) -> Result<usize, String> {
+    match value.trim().parse::<usize>() {
+        Ok(0) => Err("the thread count must be at least 1".to_string()),
+        Ok(count) => Ok(count),
+        Err(error) => Err(format!("'{}' is not a thread count: {}", value.trim(), error)),
+    }
+}
+
+/// The number of tessellation threads, `default_tessellation_threads` unless overridden by
+/// `GMC_TESSELLATION_THREADS`. A value that cannot be parsed is ignored with a warning.
+pub fn tessellation_threads_from_env() -> usize {
+    match env::var(TESSELLATION_THREADS_ENV) {
+        Ok(value) => parse_thread_count(&value).unwrap_or_else(|error| {
+            warn!(variable = TESSELLATION_THREADS_ENV, %error, "ignoring");
+            default_tessellation_threads()
+        }),
+        Err(_) => default_tessellation_threads(),
+    }
+}
+
+/// Runs `op` on the pool of tessellation threads, so the parallel iterators within it use
+/// those threads. The pool is built on first use. If it cannot be built, `op` runs on the
+/// global pool of rayon instead.
+pub fn install_tessellation<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    let pool = TESSELLATION_POOL.get_or_init(|| {
+        let threads = tessellation_threads_from_env();
+        match ThreadPoolBuilder::new().num_threads(threads).thread_name(|index| format!("tessellate-{}", index)).build() {
+            Ok(pool) => {
+                info!(threads, "started the tessellation threads");
+                Some(pool)
+            }
+            Err(error) => {
+                warn!(threads, %error, "could not start the tessellation threads, using the global pool");
+                None
+            }
+        }
+    });
+
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// Runs the future made by `task` to its end on a thread named `name` with a runtime of its
+/// own, as the event loop of the map holds the main thread.
+///
+/// ## Returns
+/// * The thread, or the error if the runtime or the thread could not be started. The runtime
+///   is built before the thread, so the caller learns of both right away.
+pub fn spawn_runtime_thread<F, Fut>(name: &str, task: F) -> io::Result<JoinHandle<()>>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    thread::Builder::new().name(name.to_string()).spawn(move || runtime.block_on(task()))
+}
+
+/// Runs a task on the database on a thread of its own, see `spawn_runtime_thread`, and sends
+/// the event it ends with to the event loop.
+///
+/// ## Arguments
+/// * `pool` - The database the task works on.
+/// * `task` - Gets the pool and a sender for the events it sends along the way, e.g. an import
+///   reloading the ways.
+/// * `events` - Where the event the task ends with is sent.
+pub fn spawn_db_task<F, Fut>(pool: SqlitePool, task: F, events: EventSender) -> io::Result<JoinHandle<()>>
+where
+    F: FnOnce(SqlitePool, EventSender) -> Fut + Send + 'static,
+    Fut: Future<Output = AppEvent>,
+{
+    spawn_runtime_thread("database-task", move || async move {
+        let event = task(pool, events.clone()).await;
+        events.send(event);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::event_channel;
+    use crate::status::StatusLevel;
+    use crate::test_support::memory_pool;
+
+    #[test]
+    fn thread_counts_are_at_least_one() {
+        assert_eq!(parse_thread_count("4"), Ok(4));
+        assert_eq!(parse_thread_count(" 1\n"), Ok(1));
+        assert!(parse_thread_count("0").is_err());
+        assert!(parse_thread_count("-2").is_err());
+        assert!(parse_thread_count("many").is_err());
+
+        let cores = thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1);
+        assert_eq!(default_tessellation_threads(), cores.saturating_sub(1).max(1));
+    }
+
+    #[test]
+    fn the_tessellation_threads_run_the_parallel_work() {
+        use rayon::prelude::*;
+
+        let names: Vec<Option<String>> = install_tessellation(|| (0..64).into_par_iter().map(|_| thread::current().name().map(str::to_string)).collect());
+        assert!(names.iter().all(|name| name.as_deref().is_some_and(|name| name.starts_with("tessellate-"))), "{:?}", names);
+    }
+
+    #[tokio::test]
+    async fn a_database_task_sends_its_events_in_order_from_its_own_thread() {
+        let pool = memory_pool("spawn_db_task").await;
+        let (events, queue) = event_channel(None);
+
+        let task = spawn_db_task(pool, |pool, events| async move {
+            let name = thread::current().name().map(str::to_string);
+            events.send(AppEvent::Status(StatusLevel::Info, format!("{:?}", name)));
+            let markers: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM marker").fetch_one(&pool).await.unwrap();
+            AppEvent::Status(StatusLevel::Info, format!("{} markers", markers))
+        }, events).unwrap();
+        tokio::task::spawn_blocking(move || task.join()).await.unwrap().unwrap();
+
+        let texts: Vec<String> = queue.drain(usize::MAX).into_iter().map(|event| match event {
+            AppEvent::Status(_, text) => text,
+            event => panic!("unexpected event {:?}", event),
+        }).collect();
+        assert_eq!(texts, ["Some(\"database-task\")", "0 markers"]);
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::num::NonZeroI64;
+use std::time::Instant;
+
+use sqlx::SqlitePool;
+use tracing::{error, warn};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::database::fetch_renderable_ways_in_bbox;
+use crate::events::{AppEvent, EventSender};
+use crate::junctions::merge_ways_if_enabled;
+use crate::metrics;
+use crate::geo::{bbox_of_points, clip_polygon_to_bbox, clip_polyline_to_bbox, zoom_level, BBox};
+use crate::osm_entities::RenderableWay;
+use crate::style::{Style, StyleSheet};
+use crate::threads::spawn_runtime_thread;
+
+/// Tiles reach this fraction of their size into their neighbours, so ways split at a
+/// tile edge still join seamlessly once line widths are applied.
+pub const TILE_OVERLAP: f64 = 0.02;
+
+/// The most detailed tile zoom level, deeper viewports reuse tiles of this level.
+pub const MAX_TILE_ZOOM: u8 = 18;
+
+/// A tile of a grid dividing the world into `2^zoom` equally sized columns and rows of
+/// latitude and longitude. Column 0 starts at longitude -180, row 0 starts at latitude 90.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub zoom: u8,
+    pub x: i64,
+    pub y: i64,
+}
+
+impl TileId {
+    /// Returns the `(lat, lon)` size of the tiles of a zoom level in degrees.
+    pub fn size(zoom: u8) -> (f64, f64) {
+        let tiles = 2f64.powi(zoom as i32);
+        (180.0 / tiles, 360.0 / tiles)
+    }
+
+    /// Returns the tile containing a `(lat, lon)` point.
+    pub fn containing(point: (f64, f64), zoom: u8) -> TileId {
+        let (lat_size, lon_size) = TileId::size(zoom);
+        TileId {
+            zoom,
+            x: ((point.1 + 180.0) / lon_size).floor() as i64,
+            y: ((90.0 - point.0) / lat_size).floor() as i64,
+        }
+    }
+
+    /// Returns the box of the tile.
+    pub fn bbox(&self) -> BBox {
+        let (lat_size, lon_size) = TileId::size(self.zoom);
+        let (max_lat, min_lon) = (90.0 - self.y as f64 * lat_size, -180.0 + self.x as f64 * lon_size);
+        BBox { min_lat: max_lat - lat_size, max_lat, min_lon, max_lon: min_lon + lon_size }
+    }
+
+    /// Returns every tile of a zoom level intersecting a box.
+    pub fn covering(bbox: &BBox, zoom: u8) -> Vec<TileId> {
+        let first = TileId::containing(bbox.top_left(), zoom);
+        let last = TileId::containing(bbox.bottom_right(), zoom);
+
+        (first.y..=last.y)
+            .flat_map(|y| (first.x..=last.x).map(move |x| TileId { zoom, x, y }))
+            .collect()
+    }
+}
+
+/// Picks the tile zoom level for a viewport, so a handful of tiles cover it.
+pub fn tile_zoom_for_viewport(view: &BBox) -> u8 {
+    zoom_level(view).floor().clamp(0.0, MAX_TILE_ZOOM as f64) as u8
+}
+
+/// Picks the tiles to prefetch around a viewport: the ring of tiles around the ones in view,
+/// at the zoom level the viewport is drawn with.
+///
+/// ## Arguments
+/// * `view` - The viewport.
+/// * `heading` - The `(lat, lon)` direction the camera last moved in, `(0.0, 0.0)` if it is not known.
+///
+/// ## Returns
+/// * The tiles, those lying in the direction of `heading` first.
+pub fn tiles_to_prefetch(view: &BBox, heading: (f64, f64)) -> Vec<TileId> {
+    let zoom = tile_zoom_for_viewport(view);
+    let visible = TileId::covering(view, zoom);
+    let (Some(first), Some(last)) = (visible.first(), visible.last()) else {
+        return Vec::new();
+    };
+
+    let center = view.center();
+    let rows = 0..(1i64 << zoom);
+
+    let mut ring: Vec<TileId> = (first.y - 1..=last.y + 1)
+        .flat_map(|y| (first.x - 1..=last.x + 1).map(move |x| TileId { zoom, x, y }))
+        .filter(|id| id.x < first.x || id.x > last.x || id.y < first.y || id.y > last.y)
+        // There are no tiles beyond the poles, but the columns wrap around the antimeridian
+        .filter(|id| rows.contains(&id.y))
+        .map(|id| TileId { x: id.x.rem_euclid(1i64 << zoom), ..id })
+        .collect();
+
+    // The sort is stable, so without a heading the tiles stay in row order. Adding zero turns
+    // the -0 of the tiles behind into 0, which `total_cmp` would order apart
+    let alignment = |id: &TileId| {
+        let tile_center = id.bbox().center();
+        (tile_center.0 - center.0) * heading.0 + (tile_center.1 - center.1) * heading.1 + 0.0
+    };
+    ring.sort_by(|a, b| alignment(b).total_cmp(&alignment(a)));
+    ring.dedup();
+
+    ring
+}
+
+/// Splits a way into the pieces inside a box, for storing it in a tile.
+///
+/// ## Arguments
+/// * `points` - The `(lat, lon)` points of the way.
+/// * `bbox` - The box, e.g. of a tile.
+/// * `is_area` - Whether the way is drawn as a filled polygon rather than a line.
+///
+/// ## Returns
+/// * The pieces of the way inside the box. A line leaving and re-entering the box gives
+///   several pieces, an area gives at most one ring which is still a valid polygon.
+pub fn split_way_at_bbox(points: &[(f64, f64)], bbox: &BBox, is_area: bool) -> Vec<Vec<(f64, f64)>> {
+    if is_area {
+        let ring = clip_polygon_to_bbox(points, bbox);
+        if ring.is_empty() {
+            Vec::new()
+        } else {
+            vec![ring]
+        }
+    } else {
+        // A line merely touching a corner of the box leaves a piece without any length
+        clip_polyline_to_bbox(points, bbox).into_iter()
+            .filter(|piece| piece.windows(2).any(|segment| segment[0] != segment[1]))
+            .collect()
+    }
+}
+
+/// The ways of one tile, clipped to the tile and its overlap margin.
+///
+/// # Fields
+/// * `id` - The tile.
+/// * `ways` - The clipped pieces. Every piece keeps the id and tags of the way it was cut from.
+#[derive(Debug, Clone)]
+pub struct Tile {
+    pub id: TileId,
+    pub ways: Vec<RenderableWay>,
+}
+
+impl Tile {
+    /// Builds a tile from the ways intersecting it.
+    ///
+    /// The style sheet decides which ways are areas, as those are clipped as polygons.
+    pub fn build(id: TileId, renderable_ways: &[RenderableWay], style_sheet: &StyleSheet) -> Tile {
+        let bbox = id.bbox().expand(TILE_OVERLAP);
+        let default_style = Style::default();
+
+        let mut ways = Vec::new();
+
+        for way in renderable_ways {
+            match bbox_of_points(&way.coords) {
+                Some(way_bbox) if way_bbox.intersects(&bbox) => (),
+                _ => continue,
+            }
+
+            // Incomplete ways are drawn as lines, so they are split like lines
+            let is_area = way.is_complete() && style_sheet.style_for(&way.tags).unwrap_or(&default_style).fill;
+            let pieces = split_way_at_bbox(&way.coords, &bbox, is_area);
+            if pieces.is_empty() {
+                continue;
+            }
+
+            // Points kept by the clipping are still nodes of the way, so junctions stay apparent
+            let node_ids: HashMap<(u64, u64), NonZeroI64> = way.coords.iter().zip(&way.node_ids)
+                .filter_map(|(&(lat, lon), &id)| Some(((lat.to_bits(), lon.to_bits()), id?)))
+                .collect();
+
+            ways.extend(pieces.into_iter().map(|piece| RenderableWay {
+                id: way.id,
+                node_ids: piece.iter().map(|&(lat, lon)| node_ids.get(&(lat.to_bits(), lon.to_bits())).copied()).collect(),
+                coords: piece,
+                tags: way.tags.clone(),
+                missing_nodes: way.missing_nodes,
+                inner_rings: Vec::new(),
+            }));
+        }
+
+        Tile { id, ways }
+    }
+}
+
+/// Tiles built so far, so panning back and forth does not clip the same ways again.
+#[derive(Debug, Default)]
+pub struct TileCache {
+    tiles: HashMap<TileId, Tile>,
+}
+
+impl TileCache {
+    pub fn new() -> Self {
+        TileCache::default()
+    }
+
+    /// Drops every tile, e.g. after the ways or the style sheet changed.
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    pub fn contains(&self, id: &TileId) -> bool {
+        self.tiles.contains_key(id)
+    }
+
+    /// Adds a tile built elsewhere, keeping the cached one if the tile is already there.
+    pub fn insert(&mut self, tile: Tile) {
+        self.tiles.entry(tile.id).or_insert(tile);
+    }
+
+    /// Returns the tile, building it first if it is not cached yet.
+    pub fn get_or_build(&mut self, id: TileId, renderable_ways: &[RenderableWay], style_sheet: &StyleSheet) -> &Tile {
+        self.tiles.entry(id).or_insert_with(|| Tile::build(id, renderable_ways, style_sheet))
+    }
+
+    /// Collects the way pieces of every tile covering the viewport.
+    pub fn ways_in_viewport(&mut self, renderable_ways: &[RenderableWay], style_sheet: &StyleSheet, view: &BBox) -> Vec<RenderableWay> {
+        let zoom = tile_zoom_for_viewport(view);
+
+        TileId::covering(view, zoom).into_iter()
+            .flat_map(|id| self.get_or_build(id, renderable_ways, style_sheet).ways.clone())
+            .collect()
+    }
+}
+
+/// A batch of tiles for the prefetch worker to build.
+struct PrefetchRequest {
+    generation: u64,
+    tiles: Vec<TileId>,
+    style_sheet: StyleSheet,
+}
+
+enum PrefetchMessage {
+    Build(PrefetchRequest),
+    Cancel,
+}
+
+/// Builds tiles around the viewport on a background thread, so they are ready before the
+/// camera gets there.
+///
+/// The worker fetches the ways of every requested tile from the database and clips them.
+/// Finished tiles are sent to the event loop as `AppEvent::TileReady`. A new request cancels
+/// the one in progress, and tiles of cancelled requests are dropped with `is_current`.
+///
+/// # Fields
+/// * `requests` - Sends requests to the worker.
+/// * `generation` - Counts the requests, so tiles of stale requests can be told apart.
+pub struct TilePrefetcher {
+    requests: UnboundedSender<PrefetchMessage>,
+    generation: u64,
+}
+
+impl TilePrefetcher {
+    /// Starts the worker thread. It stops once the prefetcher is dropped.
+    ///
+    /// ## Arguments
+    /// * `pool` - The database the ways of the tiles are fetched from.
+    /// * `events` - Where the finished tiles are sent.
+    pub fn new(pool: SqlitePool, events: EventSender) -> Self {
+        let (request_sender, mut request_receiver) = mpsc::unbounded_channel::<PrefetchMessage>();
+
+        let worker = spawn_runtime_thread("tile-prefetcher", move || async move {
+            let mut current: Option<tokio::task::JoinHandle<()>> = None;
+
+            while let Some(message) = request_receiver.recv().await {
+                // Only the latest request matters, the camera has moved on from the others
+                if let Some(task) = current.take() {
+                    task.abort();
+                }
+
+                if let PrefetchMessage::Build(request) = message {
+                    current = Some(tokio::spawn(prefetch(pool.clone(), request, events.clone())));
+                }
+            }
+        });
+        if let Err(error) = worker {
+            error!(%error, "could not start the tile prefetcher");
+        }
+
+        TilePrefetcher {
+            requests: request_sender,
+            generation: 0,
+        }
+    }
+
+    /// Asks the worker to build `tiles`, cancelling the previous request.
+    pub fn request(&mut self, tiles: Vec<TileId>, style_sheet: &StyleSheet) {
+        self.generation += 1;
+        self.send(PrefetchMessage::Build(PrefetchRequest {
+            generation: self.generation,
+            tiles,
+            style_sheet: style_sheet.clone(),
+        }));
+    }
+
+    /// Cancels the request in progress, e.g. because the style sheet changed.
+    pub fn cancel(&mut self) {
+        self.generation += 1;
+        self.send(PrefetchMessage::Cancel);
+    }
+
+    fn send(&self, message: PrefetchMessage) {
+        if self.requests.send(message).is_err() {
+            warn!("the tile prefetcher has stopped");
+        }
+    }
+
+    /// Whether a tile of the given generation belongs to the latest request, so it is still wanted.
+    pub fn is_current(&self, generation: u64) -> bool {
+        generation == self.generation
+    }
+}
+
+/// Builds the tiles of a request one by one and sends each as soon as it is done.
+async fn prefetch(pool: SqlitePool, request: PrefetchRequest, events: EventSender) {
+    for id in request.tiles {
+        let bbox = id.bbox().expand(TILE_OVERLAP);
+
+        // Merged like the ways loaded up front, the ways of the tile are all the merging sees
+        let started = Instant::now();
+        let fetched = fetch_renderable_ways_in_bbox(&pool, &bbox).await;
+        metrics::TILE_FETCH_SECONDS.observe_since(started);
+        let renderable_ways = match fetched {
+            Ok(renderable_ways) => merge_ways_if_enabled(renderable_ways).0,
+            Err(error) => {
+                warn!(tile = ?id, %error, "could not prefetch tile");
+                continue;
+            }
+        };
+
+        let tile = Tile::build(id, &renderable_ways, &request.style_sheet);
+        if !events.send(AppEvent::TileReady { generation: request.generation, tile }) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_entities::Tag;
+
+    fn road(id: i64, coords: Vec<(f64, f64)>) -> RenderableWay {
+        RenderableWay {
+            id,
+            node_ids: (1..=coords.len() as i64).map(|index| NonZeroI64::new(id * 10 + index)).collect(),
+            coords,
+            tags: vec![Tag::new("highway".to_string(), "residential".to_string())],
+            missing_nodes: 0,
+            inner_rings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn a_tile_keeps_the_pieces_of_the_ways_crossing_it() {
+        let id = TileId::containing((55.01, 12.01), 10);
+        let BBox { min_lat: bottom, max_lat: top, min_lon: left, max_lon: right } = id.bbox();
+        let middle = ((top + bottom) / 2.0, (left + right) / 2.0);
+        let crossing = road(1, vec![(middle.0, left - 1.0), middle, (middle.0, right + 1.0)]);
+        let elsewhere = road(2, vec![(middle.0 + 1.0, left), (middle.0 + 1.0, right)]);
+
+        let tile = Tile::build(id, &[crossing, elsewhere], &StyleSheet::default());
+        assert_eq!(tile.ways.len(), 1);
+        let piece = &tile.ways[0];
+        assert_eq!(piece.id, 1);
+        // Only the node inside the tile is still a node, the ends are made by the clipping
+        assert_eq!(piece.node_ids, vec![None, NonZeroI64::new(12), None]);
+    }
+
+    #[test]
+    fn the_cache_keeps_the_tile_it_has() {
+        let id = TileId::containing((55.01, 12.01), 10);
+        let BBox { min_lat: bottom, max_lat: top, min_lon: left, .. } = id.bbox();
+        let ways = [road(1, vec![((top + bottom) / 2.0, left - 1.0), ((top + bottom) / 2.0, left + 1.0)])];
+
+        let mut cache = TileCache::new();
+        assert!(!cache.contains(&id));
+        cache.insert(Tile { id, ways: Vec::new() });
+        cache.insert(Tile::build(id, &ways, &StyleSheet::default()));
+        assert!(cache.get_or_build(id, &ways, &StyleSheet::default()).ways.is_empty());
+
+        cache.clear();
+        assert!(!cache.contains(&id));
+    }
+
+    // A viewport just inside a tile, so that tile alone is in view at its own zoom level
+    fn view_inside(id: TileId) -> BBox {
+        let BBox { min_lat, max_lat, min_lon, max_lon } = id.bbox();
+        let (lat_margin, lon_margin) = ((max_lat - min_lat) / 100.0, (max_lon - min_lon) / 100.0);
+        BBox { min_lat: min_lat + lat_margin, max_lat: max_lat - lat_margin, min_lon: min_lon + lon_margin, max_lon: max_lon - lon_margin }
+    }
+
+    #[test]
+    fn the_ring_around_the_viewport_is_prefetched_ahead_first() {
+        let id = TileId::containing((55.01, 12.01), 10);
+        let view = view_inside(id);
+        assert_eq!(tile_zoom_for_viewport(&view), 10);
+
+        // Without a heading, the eight neighbours in row order
+        let ring = tiles_to_prefetch(&view, (0.0, 0.0));
+        let expected: Vec<TileId> = [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)].iter()
+            .map(|&(dx, dy)| TileId { zoom: 10, x: id.x + dx, y: id.y + dy })
+            .collect();
+        assert_eq!(ring, expected);
+
+        // Heading north puts the row above first and the row below last
+        let north = tiles_to_prefetch(&view, (1.0, 0.0));
+        assert_eq!(north.len(), 8);
+        assert!(north[..3].iter().all(|tile| tile.y == id.y - 1), "{:?}", north);
+        assert!(north[5..].iter().all(|tile| tile.y == id.y + 1), "{:?}", north);
+
+        // Heading east puts the column to the right first
+        let east = tiles_to_prefetch(&view, (0.0, 1.0));
+        assert!(east[..3].iter().all(|tile| tile.x == id.x + 1), "{:?}", east);
+    }
+
+    #[test]
+    fn the_ring_stops_at_the_poles_and_wraps_around_the_antimeridian() {
+        let columns = 1i64 << 4;
+        // The top left tile of the grid, at the north pole and the antimeridian
+        let corner = TileId { zoom: 4, x: 0, y: 0 };
+        let ring = tiles_to_prefetch(&view_inside(corner), (0.0, 0.0));
+
+        assert_eq!(ring, vec![
+            TileId { zoom: 4, x: columns - 1, y: 0 },
+            TileId { zoom: 4, x: 1, y: 0 },
+            TileId { zoom: 4, x: columns - 1, y: 1 },
+            TileId { zoom: 4, x: 0, y: 1 },
+            TileId { zoom: 4, x: 1, y: 1 },
+        ]);
+    }
+}
@@ -5,6 +5,9 @@ use std::str::Utf8Error;
 use std::error::Error as StdError;
 use std::io::{self, Write};
 
+use serde::{Serialize, Serializer};
+
+use crate::geo::{lat_lon_to_mercator, mercator_to_lat_lon, BBox};
 use crate::osm_entities::Tag;
 
 /// Custom error type that can encapsulate different kinds of errors that might occur.
@@ -78,6 +81,13 @@ impl MapsType {
     }
 }
 
+/// Written as its name, `node`, `way` or `relation`, like the `member_type` of the members.
+impl Serialize for MapsType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 impl FromStr for MapsType {
     type Err = ParseError;
 
@@ -121,16 +131,203 @@ impl MapsTag {
     }
 }
 
-pub fn lat_lon_to_screen(lat: f64, lon: f64, top_left: (f64, f64), bottom_right: (f64, f64)) -> (f32, f32) {
-    // Normalize the longitude (x-axis)
-    let normalized_x = (lon - top_left.1) / (bottom_right.1 - top_left.1);
+/// Marks where a text was cut off.
+pub const ELLIPSIS: char = '…';
+
+/// Shortens a text to at most `max_chars` characters, ending in an ellipsis if it was cut off.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+/// The coordinates of nodes are stored as integers of this many units per degree, as OSM
+/// itself does. A unit is about a centimeter, finer than any position OSM hands out.
+pub const COORDINATE_SCALE: f64 = 1e7;
+
+/// Converts degrees to the integer stored in the database, rounded to the nearest unit of
+/// `COORDINATE_SCALE`, halves away from zero.
+pub fn to_e7(degrees: f64) -> i64 {
+    (degrees * COORDINATE_SCALE).round() as i64
+}
+
+/// Converts an integer stored in the database back to degrees. SQL converting it divides by
+/// `1e7` the same way, so both give the same `f64`.
+pub fn from_e7(e7: i64) -> f64 {
+    e7 as f64 / COORDINATE_SCALE
+}
+
+/// Maps `(lat, lon)` to the positions stored in vertex buffers and on to normalized device coordinates.
+///
+/// Positions are Web Mercator meters relative to `origin`. The subtraction happens in f64
+/// before the result is cast to f32, so points close to the origin keep their precision at
+/// any zoom level. The GPU finishes the mapping with the camera uniform.
+///
+/// # Fields
+/// * `origin` - The Mercator `(x, y)` in meters that positions are relative to.
+/// * `scale` - Normalized device units per meter along x and y. North is towards negative y.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+    pub origin: (f64, f64),
+    pub scale: (f64, f64),
+}
+
+impl Projection {
+    /// The projection fitting a box into normalized device coordinates, with its top left
+    /// corner at `(-1, -1)` and its bottom right one at `(1, 1)`.
+    pub fn for_viewport(view: &BBox) -> Self {
+        let (left, top) = lat_lon_to_mercator(view.max_lat, view.min_lon);
+        let (right, bottom) = lat_lon_to_mercator(view.min_lat, view.max_lon);
+
+        Projection {
+            origin: ((left + right) / 2.0, (top + bottom) / 2.0),
+            scale: (2.0 / (right - left), -2.0 / (top - bottom)),
+        }
+    }
+
+    /// Converts `(lat, lon)` to a position relative to the origin.
+    pub fn to_local(self, lat: f64, lon: f64) -> (f32, f32) {
+        let (x, y) = lat_lon_to_mercator(lat, lon);
+        ((x - self.origin.0) as f32, (y - self.origin.1) as f32)
+    }
+
+    /// Converts `(lat, lon)` straight to normalized device coordinates.
+    pub fn to_ndc(self, lat: f64, lon: f64) -> (f32, f32) {
+        let (x, y) = lat_lon_to_mercator(lat, lon);
+        (((x - self.origin.0) * self.scale.0) as f32, ((y - self.origin.1) * self.scale.1) as f32)
+    }
+
+    /// Converts normalized device coordinates back to `(lat, lon)`.
+    pub fn ndc_to_lat_lon(&self, x: f32, y: f32) -> (f64, f64) {
+        mercator_to_lat_lon(self.origin.0 + x as f64 / self.scale.0, self.origin.1 + y as f64 / self.scale.1)
+    }
+
+    /// The box the projection fits into normalized device coordinates, the inverse of
+    /// `for_viewport`.
+    pub fn viewport(&self) -> BBox {
+        let corner = |x: f64, y: f64| mercator_to_lat_lon(self.origin.0 + x / self.scale.0, self.origin.1 + y / self.scale.1);
+        let ((max_lat, min_lon), (min_lat, max_lon)) = (corner(-1.0, -1.0), corner(1.0, 1.0));
+        BBox { min_lat, max_lat, min_lon, max_lon }
+    }
+
+    /// Zooms about a point, so the `(lat, lon)` shown there stays in place.
+    ///
+    /// The zoom happens in Web Mercator like the rendering, so the point also stays in place
+    /// far from the equator, where degrees of latitude take up more and more of the screen.
+    ///
+    /// ## Arguments
+    /// * `anchor` - The point that stays in place, in normalized device coordinates.
+    /// * `factor` - How much larger the map is shown, above one zooms in and below one zooms out.
+    pub fn zoomed_about(&self, anchor: (f64, f64), factor: f64) -> Self {
+        let anchor_x = self.origin.0 + anchor.0 / self.scale.0;
+        let anchor_y = self.origin.1 + anchor.1 / self.scale.1;
+        let scale = (self.scale.0 * factor, self.scale.1 * factor);
+
+        Projection {
+            origin: (anchor_x - anchor.0 / scale.0, anchor_y - anchor.1 / scale.1),
+            scale,
+        }
+    }
+
+    /// Converts an offset in normalized device coordinates to an offset between positions.
+    pub fn ndc_offset_to_local(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        ((x as f64 / self.scale.0) as f32, (y as f64 / self.scale.1) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_half_a_meter_apart_stay_apart_at_deep_zoom() {
+        // A viewport of zoom level 20 near the antimeridian, where Mercator x is largest
+        let lon_span = 360.0 / 2f64.powi(20);
+        let view = BBox { min_lat: 55.0, max_lat: 55.0 + lon_span / 2.0, min_lon: 179.99, max_lon: 179.99 + lon_span };
+        let projection = Projection::for_viewport(&view);
+
+        // Half a meter apart on the ground, east to west
+        let (lat, lon) = view.center();
+        let other_lon = lon + 0.5 / (crate::geo::METERS_PER_DEGREE * lat.to_radians().cos());
+        let expected = lat_lon_to_mercator(lat, other_lon).0 - lat_lon_to_mercator(lat, lon).0;
+
+        let (a, b) = (projection.to_local(lat, lon), projection.to_local(lat, other_lon));
+        assert!(((b.0 - a.0) as f64 - expected).abs() < expected * 1e-3, "{} is not {}", b.0 - a.0, expected);
+        assert_eq!(a.1, b.1);
+        let (a, b) = (projection.to_ndc(lat, lon), projection.to_ndc(lat, other_lon));
+        assert!(b.0 - a.0 > 1e-3, "{:?} and {:?} are too close", a, b);
+
+        // Casting the Mercator meters to f32 before subtracting loses the difference
+        let naive = lat_lon_to_mercator(lat, other_lon).0 as f32 - lat_lon_to_mercator(lat, lon).0 as f32;
+        assert!((naive as f64 - expected).abs() > expected / 2.0, "{} is close to {}", naive, expected);
+    }
 
-    // Normalize the latitude (y-axis), invert to account for the natural increase in latitudes as you move north
-    let normalized_y = (top_left.0 - lat) / (top_left.0 - bottom_right.0);
+    #[test]
+    fn the_point_under_the_cursor_stays_in_place_when_zooming_about_it() {
+        let mut rng = crate::test_support::SyntheticRng::new(11);
+        let views = [
+            BBox { min_lat: 55.67, max_lat: 55.68, min_lon: 12.56, max_lon: 12.58 },
+            BBox { min_lat: -10.0, max_lat: 10.0, min_lon: -20.0, max_lon: 20.0 },
+            BBox { min_lat: 69.0, max_lat: 70.0, min_lon: 18.0, max_lon: 21.0 },
+        ];
 
-    // Map to the range [-1, 1] for NDC
-    let screen_x = normalized_x * 2.0 - 1.0;
-    let screen_y = normalized_y * 2.0 - 1.0;
+        for view in views {
+            let projection = Projection::for_viewport(&view);
+            for _ in 0..100 {
+                let cursor = (rng.next_f64() * 2.0 - 1.0, rng.next_f64() * 2.0 - 1.0);
+                // Between zooming out and in by two levels
+                let factor = (rng.next_f64() * 4.0 - 2.0).exp2();
+                let before = projection.ndc_to_lat_lon(cursor.0 as f32, cursor.1 as f32);
 
-    (screen_x as f32, screen_y as f32)
+                // The app shows the viewport of the zoomed projection, so that is what must keep the point
+                let zoomed = Projection::for_viewport(&projection.zoomed_about(cursor, factor).viewport());
+                let after = zoomed.ndc_to_lat_lon(cursor.0 as f32, cursor.1 as f32);
+
+                let tolerance = (view.max_lat - view.min_lat) * 1e-6;
+                assert!((after.0 - before.0).abs() < tolerance && (after.1 - before.1).abs() < tolerance, "{:?} moved to {:?} zooming {} about {:?}", before, after, factor, cursor);
+                assert!((zoomed.scale.0 / projection.scale.0 - factor).abs() < factor * 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn coordinates_survive_the_integer_round_trip() {
+        let mut rng = crate::test_support::SyntheticRng::new(5);
+        let mut coordinates = vec![0.0, 90.0, -90.0, 180.0, -180.0, 55.6761234, -33.8688197, 1e-8, -4.9e-8];
+        coordinates.extend((0..10_000).map(|_| rng.next_f64() * 360.0 - 180.0));
+
+        for degrees in coordinates {
+            let e7 = to_e7(degrees);
+            assert!((from_e7(e7) - degrees).abs() <= 0.5e-7 + 1e-12, "{} came back as {}", degrees, from_e7(e7));
+            // Converting again changes nothing
+            assert_eq!(to_e7(from_e7(e7)), e7);
+        }
+
+        // Rounded to the nearest unit, halves away from zero
+        assert_eq!(to_e7(1.23456785), 12345679);
+        assert_eq!(to_e7(-1.23456785), -12345679);
+        assert_eq!(to_e7(0.00000004), 0);
+        assert_eq!(to_e7(-180.0), -1_800_000_000);
+        assert_eq!(from_e7(556761234), 55.6761234);
+    }
+
+    #[test]
+    fn positions_read_back_as_the_coordinates_they_were_made_from() {
+        let view = BBox { min_lat: 55.67, max_lat: 55.68, min_lon: 12.56, max_lon: 12.58 };
+        let projection = Projection::for_viewport(&view);
+
+        let (x, y) = projection.to_ndc(view.max_lat, view.min_lon);
+        assert!((x + 1.0).abs() < 1e-5 && (y + 1.0).abs() < 1e-5, "{} {}", x, y);
+        let (lat, lon) = projection.ndc_to_lat_lon(0.25, -0.5);
+        let (x, y) = projection.to_ndc(lat, lon);
+        assert!((x - 0.25).abs() < 1e-5 && (y + 0.5).abs() < 1e-5, "{} {}", x, y);
+
+        let viewport = projection.viewport();
+        for (actual, expected) in [(viewport.min_lat, view.min_lat), (viewport.max_lat, view.max_lat), (viewport.min_lon, view.min_lon), (viewport.max_lon, view.max_lon)] {
+            assert!((actual - expected).abs() < 1e-9, "{:?} is not {:?}", viewport, view);
+        }
+    }
 }
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{debug, info, warn};
+
+use crate::events::{AppEvent, EventSender};
+
+/// How often the watched directory is scanned for changes.
+pub const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long a file has to stay unchanged before it is reported, so a file written over
+/// several scans, e.g. by a script generating an extract, is reported once it is complete.
+pub const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+/// The extensions of the map files reported, compared without case.
+pub const WATCHED_EXTENSIONS: [&str; 2] = ["osm", "json"];
+
+/// What is done with a map file that changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    /// The status line offers to import it.
+    Prompt,
+    /// It is imported right away.
+    Auto,
+}
+
+/// What tells a version of a file from the next: when it was modified and its length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub modified: SystemTime,
+    pub len: u64,
+}
+
+/// Whether a file is a map file the watcher reports, see `WATCHED_EXTENSIONS`.
+pub fn is_watched_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| WATCHED_EXTENSIONS.iter().any(|watched| extension.eq_ignore_ascii_case(watched)))
+}
+
+/// Decides which map files to report from the files seen on every scan, without looking at
+/// the file system or the clock itself.
+///
+/// A file is reported once it appeared or its stamp changed, and then stayed the same for
+/// `WATCH_DEBOUNCE`. The files there when watching starts are not reported.
+///
+/// # Fields
+/// * `known` - The stamp of every file as last reported, or as first seen.
+/// * `pending` - The files changed since, with their latest stamp and when it was first seen.
+#[derive(Debug, Default)]
+pub struct MapFileDebouncer {
+    known: HashMap<PathBuf, FileStamp>,
+    pending: HashMap<PathBuf, (FileStamp, Instant)>,
+}
+
+impl MapFileDebouncer {
+    /// Starts from the files there are, which are taken as imported already.
+    pub fn new(files: impl IntoIterator<Item = (PathBuf, FileStamp)>) -> Self {
+        MapFileDebouncer {
+            known: files.into_iter().filter(|(path, _)| is_watched_file(path)).collect(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Takes in the files seen by a scan of the whole directory. Files no longer there are
+    /// forgotten, so a file deleted and written again is reported as new.
+    ///
+    /// ## Returns
+    /// * The files to report, those that settled by `now`, sorted.
+    pub fn scan(&mut self, files: impl IntoIterator<Item = (PathBuf, FileStamp)>, now: Instant) -> Vec<PathBuf> {
+        let files: HashMap<PathBuf, FileStamp> = files.into_iter().filter(|(path, _)| is_watched_file(path)).collect();
+        self.known.retain(|path, _| files.contains_key(path));
+        self.pending.retain(|path, _| files.contains_key(path));
+
+        for (path, stamp) in files {
+            if self.known.get(&path) == Some(&stamp) {
+                // Changed back before it settled, e.g. a file restored from a backup
+                self.pending.remove(&path);
+                continue;
+            }
+            match self.pending.get(&path) {
+                Some((pending, _)) if *pending == stamp => {}
+                _ => {
+                    self.pending.insert(path, (stamp, now));
+                }
+            }
+        }
+
+        let mut settled: Vec<PathBuf> = self.pending.iter()
+            .filter(|(_, (_, since))| now.duration_since(*since) >= WATCH_DEBOUNCE)
+            .map(|(path, _)| path.clone())
+            .collect();
+        settled.sort();
+        for path in &settled {
+            if let Some((stamp, _)) = self.pending.remove(path) {
+                self.known.insert(path.clone(), stamp);
+            }
+        }
+        settled
+    }
+}
+
+/// Lists the files of a directory with their stamps. Files that vanish while listing are left out.
+pub fn scan_directory(directory: &Path) -> io::Result<Vec<(PathBuf, FileStamp)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        files.push((entry.path(), FileStamp { modified: metadata.modified()?, len: metadata.len() }));
+    }
+    Ok(files)
+}
+
+/// Scans a directory for new and changed map files on a thread of its own, and sends every
+/// one that settled to the event loop as `AppEvent::MapFileChanged`.
+///
+/// The thread stops once the watcher is dropped, which waits for it, or once the event loop is gone.
+///
+/// # Fields
+/// * `stop` - Dropping it wakes the thread to stop, rather than after its next scan.
+/// * `thread` - The scanning thread, joined on drop.
+pub struct MapFileWatcher {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MapFileWatcher {
+    /// Starts watching a directory. The files there already are not reported.
+    pub fn start(directory: &Path, events: EventSender) -> io::Result<Self> {
+        let directory = directory.to_path_buf();
+        let mut debouncer = MapFileDebouncer::new(scan_directory(&directory)?);
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let thread = thread::Builder::new().name("map-file-watcher".to_string()).spawn(move || {
+            info!(directory = %directory.display(), "watching for map files");
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(WATCH_POLL_INTERVAL) {
+                let files = match scan_directory(&directory) {
+                    Ok(files) => files,
+                    Err(error) => {
+                        warn!(directory = %directory.display(), %error, "could not scan for map files");
+                        continue;
+                    }
+                };
+
+                for path in debouncer.scan(files, Instant::now()) {
+                    info!(file = %path.display(), "map file changed");
+                    if !events.send(AppEvent::MapFileChanged(path)) {
+                        return;
+                    }
+                }
+            }
+            debug!("the map file watcher finished");
+        })?;
+
+        Ok(MapFileWatcher { stop: Some(stop), thread: Some(thread) })
+    }
+}
+
+impl Drop for MapFileWatcher {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("the map file watcher panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_files_the_importer_reads_are_watched() {
+        assert!(is_watched_file(Path::new("maps/city.osm")));
+        assert!(is_watched_file(Path::new("maps/city.JSON")));
+        // There is no PBF reader, so an extract in that format would only fail to import.
+        assert!(!is_watched_file(Path::new("maps/city.osm.pbf")));
+        assert!(!is_watched_file(Path::new("maps/notes.txt")));
+        assert!(!is_watched_file(Path::new("maps/osm")));
+    }
+
+    fn stamp(seconds: u64, len: u64) -> FileStamp {
+        FileStamp { modified: SystemTime::UNIX_EPOCH + Duration::from_secs(seconds), len }
+    }
+
+    fn file(name: &str, seconds: u64, len: u64) -> (PathBuf, FileStamp) {
+        (PathBuf::from(name), stamp(seconds, len))
+    }
+
+    #[test]
+    fn a_new_file_is_reported_once_it_stopped_changing() {
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+        let mut debouncer = MapFileDebouncer::new([file("old.osm", 1, 10)]);
+
+        // The files there at the start are not reported
+        assert!(debouncer.scan([file("old.osm", 1, 10)], at(0)).is_empty());
+
+        // A file being written grows on every scan, which restarts the wait
+        assert!(debouncer.scan([file("old.osm", 1, 10), file("new.osm", 2, 100)], at(500)).is_empty());
+        assert!(debouncer.scan([file("old.osm", 1, 10), file("new.osm", 3, 200)], at(1000)).is_empty());
+        assert!(debouncer.scan([file("old.osm", 1, 10), file("new.osm", 3, 300)], at(1500)).is_empty());
+        assert!(debouncer.scan([file("old.osm", 1, 10), file("new.osm", 3, 300)], at(2000)).is_empty());
+        assert_eq!(debouncer.scan([file("old.osm", 1, 10), file("new.osm", 3, 300)], at(1500) + WATCH_DEBOUNCE), [PathBuf::from("new.osm")]);
+
+        // And then not again while it stays the same
+        assert!(debouncer.scan([file("old.osm", 1, 10), file("new.osm", 3, 300)], at(9000)).is_empty());
+    }
+
+    #[test]
+    fn changes_are_reported_unless_undone_before_they_settle() {
+        let start = Instant::now();
+        let later = start + WATCH_DEBOUNCE;
+        let mut debouncer = MapFileDebouncer::new([file("a.osm", 1, 10), file("b.json", 1, 10)]);
+
+        // Both change, and are reported together in order
+        assert!(debouncer.scan([file("b.json", 2, 10), file("a.osm", 1, 20)], start).is_empty());
+        assert_eq!(debouncer.scan([file("b.json", 2, 10), file("a.osm", 1, 20)], later), [PathBuf::from("a.osm"), PathBuf::from("b.json")]);
+
+        // Restored to what was reported before it settled
+        assert!(debouncer.scan([file("b.json", 2, 10), file("a.osm", 5, 50)], later).is_empty());
+        assert!(debouncer.scan([file("b.json", 2, 10), file("a.osm", 1, 20)], later + WATCH_DEBOUNCE).is_empty());
+        assert!(debouncer.scan([file("b.json", 2, 10), file("a.osm", 1, 20)], later + WATCH_DEBOUNCE * 3).is_empty());
+
+        // Deleted and written again as it was is a new file
+        assert!(debouncer.scan([file("b.json", 2, 10)], later).is_empty());
+        assert!(debouncer.scan([file("b.json", 2, 10), file("a.osm", 1, 20)], later).is_empty());
+        assert_eq!(debouncer.scan([file("b.json", 2, 10), file("a.osm", 1, 20)], later + WATCH_DEBOUNCE), [PathBuf::from("a.osm")]);
+
+        // Files of other kinds never are
+        assert!(debouncer.scan([file("notes.txt", 1, 1)], start).is_empty());
+        assert!(debouncer.scan([file("notes.txt", 9, 9)], later + WATCH_DEBOUNCE * 9).is_empty());
+    }
+
+    #[test]
+    fn the_watcher_stops_when_dropped() {
+        let directory = std::env::temp_dir().join(format!("gmc_watch_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("there.osm"), "<osm/>").unwrap();
+        let (events, queue) = crate::events::event_channel(None);
+
+        let watcher = MapFileWatcher::start(&directory, events).unwrap();
+        // Dropping wakes the thread and waits for it, rather than hanging on the next scan
+        drop(watcher);
+        assert!(queue.drain(10).is_empty());
+
+        assert!(MapFileWatcher::start(&directory.join("missing"), crate::events::event_channel(None).0).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
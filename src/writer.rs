@@ -0,0 +1,155 @@
+use std::thread::JoinHandle;
+
+use sqlx::SqlitePool;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::{debug, error, warn};
+
+use crate::database::{delete_marker, insert_marker, save_setting, save_viewport};
+use crate::events::{AppEvent, EventSender};
+use crate::history::Viewport;
+use crate::status::StatusLevel;
+use crate::threads::spawn_runtime_thread;
+
+/// A write to the database the event loop asked for.
+#[derive(Debug)]
+pub enum DatabaseWrite {
+    /// Saves a setting, see `save_setting`.
+    ///
+    /// # Fields
+    /// * `what` - What the setting is, for the message if it cannot be saved, e.g. `the theme`.
+    Setting { key: &'static str, value: String, what: &'static str },
+    /// Saves the viewport shown, see `save_viewport`.
+    Viewport(Viewport),
+    /// Adds a marker, reported with `AppEvent::MarkerAdded`.
+    AddMarker { lat: f64, lon: f64, label: String, color: &'static str },
+    /// Deletes a marker, reported with `AppEvent::MarkerDeleted`.
+    DeleteMarker(i64),
+}
+
+/// Writes to the database on a thread of its own, so the event loop never waits for a write,
+/// e.g. while an import holds the database.
+///
+/// The writes are done one after the other in the order they were asked for, so the setting
+/// saved last is the one kept. Failures are reported to the event loop, as a status message
+/// or as the event of the write.
+///
+/// # Fields
+/// * `writes` - Sends the writes to the thread. Dropping it lets the thread finish.
+/// * `thread` - The writing thread, joined by `finish`.
+pub struct DatabaseWriter {
+    writes: Option<UnboundedSender<DatabaseWrite>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DatabaseWriter {
+    /// Starts the writing thread.
+    ///
+    /// ## Arguments
+    /// * `pool` - The database written to.
+    /// * `events` - Where failures and the outcome of marker writes are sent.
+    pub fn start(pool: SqlitePool, events: EventSender) -> Self {
+        let (write_sender, mut write_receiver) = mpsc::unbounded_channel::<DatabaseWrite>();
+
+        let thread = spawn_runtime_thread("database-writer", move || async move {
+            while let Some(write) = write_receiver.recv().await {
+                apply_write(&pool, write, &events).await;
+            }
+            debug!("the database writer finished");
+        });
+
+        let thread = match thread {
+            Ok(thread) => Some(thread),
+            Err(error) => {
+                error!(%error, "could not start the database writer");
+                None
+            }
+        };
+        DatabaseWriter { writes: Some(write_sender), thread }
+    }
+
+    /// Queues a write behind the ones asked for before.
+    pub fn write(&self, write: DatabaseWrite) {
+        let sent = self.writes.as_ref().is_some_and(|writes| writes.send(write).is_ok());
+        if !sent {
+            warn!("the database writer has stopped");
+        }
+    }
+
+    /// Waits for the writes queued so far and stops the thread, e.g. before the window
+    /// closes so the viewport saved last is not lost. Later writes are dropped.
+    pub fn finish(&mut self) {
+        self.writes.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("the database writer panicked");
+            }
+        }
+    }
+}
+
+impl Drop for DatabaseWriter {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Does one write and reports how it went.
+async fn apply_write(pool: &SqlitePool, write: DatabaseWrite, events: &EventSender) {
+    match write {
+        DatabaseWrite::Setting { key, value, what } => {
+            if let Err(error) = save_setting(pool, key, &value).await {
+                error!(key, %error, "could not save {}", what);
+                events.send(AppEvent::Status(StatusLevel::Error, format!("Could not save {}: {}", what, error)));
+            }
+        }
+        DatabaseWrite::Viewport(viewport) => match save_viewport(pool, &viewport).await {
+            Ok(()) => debug!(?viewport, "saved the viewport"),
+            Err(error) => error!(%error, "could not save the viewport"),
+        },
+        DatabaseWrite::AddMarker { lat, lon, label, color } => {
+            let result = insert_marker(pool, lat, lon, &label, color).await;
+            events.send(AppEvent::MarkerAdded { lat, lon, label, result });
+        }
+        DatabaseWrite::DeleteMarker(id) => {
+            let result = delete_marker(pool, id).await;
+            events.send(AppEvent::MarkerDeleted { id, result });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{fetch_markers, fetch_saved_viewport, fetch_setting};
+    use crate::events::event_channel;
+    use crate::geo::BBox;
+    use crate::test_support::memory_pool;
+
+    #[tokio::test]
+    async fn writes_are_done_in_order_before_finish_returns() {
+        let pool = memory_pool("database_writer").await;
+        let (events, queue) = event_channel(None);
+
+        let mut writer = DatabaseWriter::start(pool.clone(), events);
+        writer.write(DatabaseWrite::Setting { key: "theme", value: "dark".to_string(), what: "the theme" });
+        writer.write(DatabaseWrite::Setting { key: "theme", value: "light".to_string(), what: "the theme" });
+        let viewport = BBox { min_lat: 55.0, max_lat: 55.1, min_lon: 12.0, max_lon: 12.2 };
+        writer.write(DatabaseWrite::Viewport(viewport));
+        writer.write(DatabaseWrite::AddMarker { lat: 55.05, lon: 12.1, label: "here".to_string(), color: "#000000" });
+        writer.finish();
+        // Once finished, writes are dropped rather than queued for a thread that is gone
+        writer.write(DatabaseWrite::DeleteMarker(1));
+
+        assert_eq!(fetch_setting(&pool, "theme").await.unwrap().as_deref(), Some("light"));
+        assert_eq!(fetch_saved_viewport(&pool).await.unwrap(), Some(viewport));
+        let markers = fetch_markers(&pool).await.unwrap();
+        assert_eq!(markers.len(), 1);
+
+        let events = queue.drain(usize::MAX);
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            AppEvent::MarkerAdded { label, result: Ok(id), .. } => assert_eq!((label.as_str(), *id), ("here", markers[0].id)),
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Compares frames of synthetic scenes with the golden images checked in to `utils/goldens`,
+//! run with `cargo test --test golden -- --nocapture` to see how far each frame is off.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::{Rgba, RgbaImage};
+
+use google_maps_clone::app::{render_to_image, OffscreenView};
+use google_maps_clone::geo::BBox;
+use google_maps_clone::gpu::GpuError;
+use google_maps_clone::osm_entities::{RenderableWay, SimpleNode, Tag};
+use google_maps_clone::style::{parse_hex_color, Style, StyleRule, StyleSheet, ValuePattern};
+use google_maps_clone::theme::Theme;
+
+/// Where the golden images are checked in.
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/utils/goldens");
+/// Where the frames and diff images of failed comparisons are written.
+const GOLDEN_DIFF_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/golden-diffs");
+/// Set to `1` to write the frames drawn now as the new golden images.
+const UPDATE_GOLDENS_ENV: &str = "UPDATE_GOLDENS";
+
+const GOLDEN_SIZE: u32 = 512;
+// A pixel differs once any channel is further off than this, which absorbs rounding
+// differences between GPUs. Up to this many pixels may differ, e.g. along edges that
+// are rasterized slightly differently.
+const CHANNEL_TOLERANCE: u8 = 3;
+const MAX_DIFFERENT_PIXELS: usize = 256;
+
+// The viewport of the scene, about 110 by 110 meters
+const SCENE_VIEWPORT: BBox = BBox { min_lat: 55.0000, max_lat: 55.0010, min_lon: 11.0000, max_lon: 11.0017 };
+
+/// A frame of a scene compared with its golden image.
+///
+/// # Fields
+/// * `name` - The file name of the golden image, without `.png`.
+/// * `ways` - The ways of the scene.
+/// * `relation_ways` - What is drawn of the relations of the scene.
+/// * `buildings_3d` - Whether the frame shows the extruded buildings.
+struct KeyFrame {
+    name: &'static str,
+    ways: fn() -> Vec<RenderableWay>,
+    relation_ways: fn() -> Vec<RenderableWay>,
+    buildings_3d: bool,
+}
+
+const KEY_FRAMES: [KeyFrame; 4] = [
+    KeyFrame { name: "scene_flat", ways: scene_ways, relation_ways: scene_relation_ways, buildings_3d: false },
+    KeyFrame { name: "scene_3d", ways: scene_ways, relation_ways: scene_relation_ways, buildings_3d: true },
+    KeyFrame { name: "courtyard_flat", ways: courtyard_ways, relation_ways: courtyard_relation_ways, buildings_3d: false },
+    KeyFrame { name: "courtyard_3d", ways: courtyard_ways, relation_ways: courtyard_relation_ways, buildings_3d: true },
+];
+
+fn way(id: i64, points: &[(f64, f64)], closed: bool, tags: &[(&str, &str)]) -> RenderableWay {
+    let mut nodes: Vec<SimpleNode> = points.iter().enumerate()
+        .map(|(index, &(lat, lon))| SimpleNode { id: Some(id * 100 + index as i64), lat, lon })
+        .collect();
+    if closed {
+        nodes.push(nodes[0].clone());
+    }
+
+    let tags = tags.iter().map(|&(key, value)| Tag::new(key.to_string(), value.to_string())).collect();
+    RenderableWay::from_nodes(id, &nodes, tags, 0)
+}
+
+/// The ways of the scene: a concave building, a bent road, a closed water polygon and a
+/// cafe, whose icon is drawn with the textured pipeline.
+fn scene_ways() -> Vec<RenderableWay> {
+    vec![
+        way(1, &[
+            (55.00085, 11.00020), (55.00085, 11.00070), (55.00070, 11.00070),
+            (55.00070, 11.00040), (55.00055, 11.00040), (55.00055, 11.00020),
+        ], true, &[("building", "yes"), ("building:levels", "4")]),
+        way(2, &[(55.00020, 11.00010), (55.00045, 11.00080), (55.00030, 11.00160)], false, &[("highway", "residential")]),
+        way(3, &[
+            (55.00090, 11.00110), (55.00080, 11.00150), (55.00060, 11.00155),
+            (55.00050, 11.00120), (55.00070, 11.00095),
+        ], true, &[("natural", "water")]),
+        way(5, &[(55.00020, 11.00115), (55.00020, 11.00135), (55.00010, 11.00135), (55.00010, 11.00115)], true, &[("amenity", "cafe")]),
+    ]
+}
+
+/// The lines of the boundaries of the scene: a municipal boundary crossing the water and
+/// the road, drawn dashed between them.
+fn scene_relation_ways() -> Vec<RenderableWay> {
+    vec![
+        way(4, &[(55.00000, 11.00090), (55.00100, 11.00140)], false, &[("type", "boundary"), ("boundary", "administrative"), ("admin_level", "8")]),
+    ]
+}
+
+/// The ways of the courtyard scene: a park, and a taller part of the building standing in it
+/// covering its western wing.
+fn courtyard_ways() -> Vec<RenderableWay> {
+    vec![
+        way(11, &[(55.00095, 11.00010), (55.00095, 11.00160), (55.00005, 11.00160), (55.00005, 11.00010)], true, &[("leisure", "park")]),
+        way(12, &[(55.00080, 11.00030), (55.00080, 11.00050), (55.00020, 11.00050), (55.00020, 11.00030)], true, &[("building:part", "yes"), ("building:levels", "5")]),
+    ]
+}
+
+/// The multipolygon of the courtyard scene: a building around a courtyard, through which
+/// the park shows, as `fetch_multipolygon_areas` assembles it.
+fn courtyard_relation_ways() -> Vec<RenderableWay> {
+    let mut building = way(13, &[(55.00080, 11.00030), (55.00080, 11.00130), (55.00020, 11.00130), (55.00020, 11.00030)], true, &[("type", "multipolygon"), ("building", "yes"), ("building:levels", "3")]);
+    building.inner_rings.push(vec![(55.00065, 11.00060), (55.00065, 11.00105), (55.00035, 11.00105), (55.00035, 11.00060), (55.00065, 11.00060)]);
+    vec![building]
+}
+
+/// The built-in style sheet with rules filling water and parks, so the scenes do not change
+/// along with `utils/style.toml`.
+fn scene_style_sheet() -> StyleSheet {
+    let mut style_sheet = StyleSheet::default();
+    let fill = |key: &str, value: &str, color: &str| StyleRule {
+        key: key.to_string(),
+        value: ValuePattern::Exact(value.to_string()),
+        style: Style {
+            color: parse_hex_color(color).unwrap_or(Style::default().color),
+            fill: true,
+            ..Style::default()
+        },
+    };
+    style_sheet.rules.push(fill("natural", "water", "#aad3df"));
+    style_sheet.rules.push(fill("leisure", "park", "#c8facc"));
+    style_sheet
+}
+
+/// Counts the pixels differing by more than `CHANNEL_TOLERANCE` in any channel.
+///
+/// ## Returns
+/// * The number of differing pixels, and an image of them in red over a faded `actual`.
+fn compare_images(expected: &RgbaImage, actual: &RgbaImage) -> (usize, RgbaImage) {
+    let mut different_pixels = 0;
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let differs = expected.get_pixel_checked(x, y)
+            .is_none_or(|expected| expected.0.iter().zip(pixel.0).any(|(&a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE));
+        if differs {
+            different_pixels += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            let [r, g, b, _] = pixel.0;
+            diff.put_pixel(x, y, Rgba([r / 4, g / 4, b / 4, 255]));
+        }
+    }
+
+    (different_pixels, diff)
+}
+
+/// Draws every key frame of the scenes and compares it with its golden image in `GOLDEN_DIR`.
+///
+/// A frame passes if at most `MAX_DIFFERENT_PIXELS` pixels differ. For a failing frame,
+/// the frame and a diff image are written to `GOLDEN_DIFF_DIR`. With `UPDATE_GOLDENS=1`,
+/// the frames are written as the new golden images instead of being compared.
+///
+/// Without a GPU adapter nothing is drawn and the test passes, so machines without one are
+/// not held up. `GMC_WGPU_BACKEND=gl` picks a software adapter where there is one, e.g. in CI.
+#[tokio::test]
+async fn frames_match_the_golden_images() -> Result<()> {
+    let update = env::var(UPDATE_GOLDENS_ENV).is_ok_and(|value| value == "1");
+    let style_sheet = scene_style_sheet();
+    let mut passed = true;
+
+    for frame in KEY_FRAMES {
+        let renderable_ways = (frame.ways)();
+        let relation_ways = (frame.relation_ways)();
+        let view = OffscreenView {
+            renderable_ways: &renderable_ways,
+            relation_ways: &relation_ways,
+            style_sheet: &style_sheet,
+            theme: Theme::Light,
+            viewport: SCENE_VIEWPORT,
+            buildings_3d: frame.buildings_3d,
+        };
+        let actual = match render_to_image(&view, GOLDEN_SIZE, GOLDEN_SIZE).await {
+            Ok(image) => image,
+            Err(error @ GpuError::NoAdapter { .. }) => {
+                println!("skipped the golden images: {}", error);
+                return Ok(());
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let golden_path = Path::new(GOLDEN_DIR).join(format!("{}.png", frame.name));
+        if update {
+            fs::create_dir_all(GOLDEN_DIR)?;
+            actual.save(&golden_path).with_context(|| format!("could not write {}", golden_path.display()))?;
+            println!("{:<14} updated {}", frame.name, golden_path.display());
+            continue;
+        }
+
+        let expected = match image::open(&golden_path) {
+            Ok(expected) => expected.to_rgba8(),
+            Err(error) => {
+                println!("{:<14} FAILED: could not read {}: {}, run with {}=1 to write it", frame.name, golden_path.display(), error, UPDATE_GOLDENS_ENV);
+                passed = false;
+                continue;
+            }
+        };
+
+        let (different_pixels, diff) = compare_images(&expected, &actual);
+        if different_pixels <= MAX_DIFFERENT_PIXELS {
+            println!("{:<14} ok, {} pixels differ", frame.name, different_pixels);
+            continue;
+        }
+
+        fs::create_dir_all(GOLDEN_DIFF_DIR)?;
+        let actual_path = Path::new(GOLDEN_DIFF_DIR).join(format!("{}.actual.png", frame.name));
+        let diff_path = Path::new(GOLDEN_DIFF_DIR).join(format!("{}.diff.png", frame.name));
+        actual.save(&actual_path)?;
+        diff.save(&diff_path)?;
+        println!(
+            "{:<14} FAILED: {} pixels differ, at most {} may, see {}",
+            frame.name, different_pixels, MAX_DIFFERENT_PIXELS, diff_path.display(),
+        );
+        passed = false;
+    }
+
+    anyhow::ensure!(passed, "frames differ from the golden images, see above");
+    Ok(())
+}
@@ -0,0 +1,225 @@
+//! Replays the checked-in fuzzing corpus through the OSM XML and JSON readers and mutates
+//! seed documents into more hostile input, see `fuzz/fuzz_targets/readers.rs` for fuzzing
+//! with coverage guidance.
+
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use google_maps_clone::open_street_map::{read_nodes_from_bytes, read_osc_from_bytes, read_osm_json, read_relations_from_bytes, read_ways_from_bytes};
+use google_maps_clone::test_support::SyntheticRng;
+
+/// Where hostile inputs the readers must survive are checked in, the corpus of the
+/// `readers` target of `cargo fuzz`.
+const FUZZ_CORPUS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/corpus/readers");
+/// Where the inputs a reader panicked on are written, minimized.
+const FUZZ_CRASH_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/fuzz-crashes");
+/// How many mutated inputs a run tries, and which, unless `GMC_FUZZ_ITERATIONS` and
+/// `GMC_FUZZ_SEED` say otherwise.
+const DEFAULT_FUZZ_ITERATIONS: usize = 500;
+const DEFAULT_FUZZ_SEED: u64 = 1;
+
+// At most this many mutations are applied to a seed document per input
+const MAX_MUTATIONS: usize = 4;
+// The length of the huge attribute values inserted, and how deep garbage elements are nested
+const HUGE_VALUE_LEN: usize = 100_000;
+const GARBAGE_DEPTH: usize = 10_000;
+
+// The documents mutated, covering every element and attribute the readers look at
+const SEED_DOCUMENTS: [&str; 3] = [
+    r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="fuzz">
+ <node id="1" version="2" changeset="3" timestamp="2024-01-01T00:00:00Z" user="a" uid="4" lat="55.0" lon="11.0"/>
+ <node id="2" lat="55.1" lon="11.1">
+  <tag k="amenity" v="cafe"/>
+ </node>
+ <way id="10" version="1">
+  <nd ref="1"/>
+  <nd ref="2"/>
+  <tag k="highway" v="primary"/>
+ </way>
+ <relation id="20" version="1">
+  <member type="way" ref="10" role="outer"/>
+  <member type="node" ref="1" role=""/>
+  <tag k="type" v="multipolygon"/>
+ </relation>
+</osm>
+"#,
+    r#"<?xml version="1.0" encoding="UTF-8"?>
+<osmChange version="0.6" generator="fuzz">
+ <create>
+  <node id="3" lat="55.2" lon="11.2"><tag k="shop" v="bakery"/></node>
+ </create>
+ <modify>
+  <way id="10"><nd ref="1"/><nd ref="3"/></way>
+ </modify>
+ <delete>
+  <node id="2"/>
+  <relation id="20"><member type="relation" ref="21" role="sub"/></relation>
+ </delete>
+</osmChange>
+"#,
+    r#"{"version": 0.6, "generator": "fuzz", "elements": [
+ {"type": "node", "id": 1, "lat": 55.0, "lon": 11.0, "version": 2, "timestamp": "2024-01-01T00:00:00Z", "changeset": 3, "user": "a", "uid": 4},
+ {"type": "node", "id": 2, "lat": 55.1, "lon": 11.1, "tags": {"amenity": "cafe"}},
+ {"type": "way", "id": 10, "nodes": [1, 2], "tags": {"highway": "primary"}},
+ {"type": "relation", "id": 20, "members": [{"type": "way", "ref": 10, "role": "outer"}, {"type": "node", "ref": 1, "role": ""}], "tags": {"type": "multipolygon"}}
+], "remark": "fuzz"}
+"#,
+];
+
+/// Runs every reader on an input.
+///
+/// ## Returns
+/// * The message of the first reader that panicked, or `None` if every reader returned,
+///   with the elements it could read or an error.
+fn panic_in_readers(input: &[u8]) -> Option<String> {
+    let readers = [
+        ("nodes", (|input| { let _ = read_nodes_from_bytes(input); }) as fn(&[u8])),
+        ("ways", |input| { let _ = read_ways_from_bytes(input); }),
+        ("relations", |input| { let _ = read_relations_from_bytes(input); }),
+        ("changes", |input| { let _ = read_osc_from_bytes(input); }),
+        ("json", |input| { let _ = read_osm_json(input); }),
+    ];
+
+    readers.iter().find_map(|(name, read)| {
+        panic::catch_unwind(AssertUnwindSafe(|| read(input))).err().map(|payload| {
+            let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            format!("reading {} panicked: {}", name, message)
+        })
+    })
+}
+
+fn next_index(rng: &mut SyntheticRng, len: usize) -> usize {
+    ((rng.next_f64() * len as f64) as usize).min(len.saturating_sub(1))
+}
+
+/// Inserts `insert` in place of the attribute value the random `position` falls into, or
+/// at the position if it is not within a value.
+fn replace_value_at(input: &mut Vec<u8>, position: usize, insert: &[u8]) {
+    let start = input[..position].iter().rposition(|&byte| byte == b'"').map_or(position, |quote| quote + 1);
+    let end = input[start..].iter().position(|&byte| byte == b'"').map_or(start, |quote| start + quote);
+    input.splice(start..end, insert.iter().copied());
+}
+
+/// Changes an input in one of the ways hostile or broken files differ from well-formed ones.
+fn mutate(input: &mut Vec<u8>, rng: &mut SyntheticRng) {
+    if input.is_empty() {
+        input.push(b'<');
+        return;
+    }
+    let position = next_index(rng, input.len());
+
+    match next_index(rng, 9) {
+        // A file cut off while it was written or downloaded
+        0 => input.truncate(position),
+        // A corrupted byte
+        1 => input[position] = (rng.next_f64() * 256.0) as u8,
+        // An attribute value far longer than any real one
+        2 => replace_value_at(input, position, &vec![b'9'; HUGE_VALUE_LEN]),
+        // Deeply nested elements no reader knows
+        3 => {
+            let garbage = format!("{}{}", "<g>".repeat(GARBAGE_DEPTH), "</g>".repeat(GARBAGE_DEPTH));
+            input.splice(position..position, garbage.bytes());
+        }
+        // A value that is not UTF-8
+        4 => replace_value_at(input, position, &[0xff, 0xfe, 0xc3, 0x28]),
+        // A number beyond any integer type, a number that is not finite and a negative one
+        5 => replace_value_at(input, position, b"99999999999999999999999"),
+        6 => replace_value_at(input, position, [&b"NaN"[..], b"inf", b"-1e400", b"-9223372036854775808"][next_index(rng, 4)]),
+        // A stretch of the file repeated, e.g. many members of unknown types
+        7 => {
+            let end = (position + next_index(rng, 200)).min(input.len());
+            let repeated = input[position..end].repeat(next_index(rng, 50) + 1);
+            input.splice(end..end, repeated);
+        }
+        // Arbitrary bytes
+        _ => {
+            let bytes: Vec<u8> = (0..next_index(rng, 64) + 1).map(|_| (rng.next_f64() * 256.0) as u8).collect();
+            input.splice(position..position, bytes);
+        }
+    }
+}
+
+/// Removes as much of an input as possible while it still makes a reader panic, so the
+/// crash is easy to follow.
+fn minimize(mut input: Vec<u8>) -> Vec<u8> {
+    let mut chunk = input.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < input.len() {
+            let end = (start + chunk).min(input.len());
+            let mut candidate = input.clone();
+            candidate.drain(start..end);
+            if panic_in_readers(&candidate).is_some() {
+                input = candidate;
+            } else {
+                start += chunk;
+            }
+        }
+        chunk /= 2;
+    }
+    input
+}
+
+/// Feeds the OSM XML and JSON readers hostile input and checks that they never panic.
+/// Every file in `FUZZ_CORPUS_DIR` is replayed first, then seed documents are mutated at
+/// random, a run with the same seed trying the same inputs.
+///
+/// A failing input is minimized and written to `FUZZ_CRASH_DIR`. Once fixed, it belongs
+/// in `FUZZ_CORPUS_DIR`, so it stays fixed.
+#[test]
+fn no_reader_panics_on_hostile_input() -> Result<()> {
+    let iterations = env::var("GMC_FUZZ_ITERATIONS").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_FUZZ_ITERATIONS);
+    let seed = env::var("GMC_FUZZ_SEED").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_FUZZ_SEED);
+
+    let mut paths: Vec<_> = fs::read_dir(FUZZ_CORPUS_DIR)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+    paths.sort();
+    let mut inputs: Vec<(String, Vec<u8>)> = Vec::new();
+    for path in paths {
+        let input = fs::read(&path).with_context(|| format!("could not read {}", path.display()))?;
+        inputs.push((path.display().to_string(), input));
+    }
+    let corpus_len = inputs.len();
+    anyhow::ensure!(corpus_len > 0, "{} holds no inputs", FUZZ_CORPUS_DIR);
+
+    let mut rng = SyntheticRng::new(seed);
+    for iteration in 0..iterations {
+        let mut input = SEED_DOCUMENTS[iteration % SEED_DOCUMENTS.len()].as_bytes().to_vec();
+        for _ in 0..next_index(&mut rng, MAX_MUTATIONS) + 1 {
+            mutate(&mut input, &mut rng);
+        }
+        inputs.push((format!("mutation {}", iteration), input));
+    }
+
+    // The panics are reported here, not printed by the default hook for every input tried
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut crashes = Vec::new();
+    for (name, input) in &inputs {
+        if let Some(message) = panic_in_readers(input) {
+            crashes.push((name.clone(), message, minimize(input.clone())));
+        }
+    }
+    panic::set_hook(default_hook);
+
+    for (index, (name, message, minimized)) in crashes.iter().enumerate() {
+        fs::create_dir_all(FUZZ_CRASH_DIR)?;
+        let path = Path::new(FUZZ_CRASH_DIR).join(format!("crash-{}.osm", index));
+        fs::write(&path, minimized)?;
+        println!("FAILED: {}: {}, minimized to {} bytes in {}", name, message, minimized.len(), path.display());
+    }
+    println!(
+        "fuzzed the readers with {} corpus files and {} mutated inputs, {} panicked",
+        corpus_len, iterations, crashes.len(),
+    );
+
+    anyhow::ensure!(crashes.is_empty(), "{} inputs made a reader panic, see above", crashes.len());
+    Ok(())
+}